@@ -0,0 +1,97 @@
+// Timing of the mapping paths under different global allocators.
+//
+// The allocator is chosen at compile time, as in minimappers2:
+//   cargo run --release --example allocators
+//   RUSTFLAGS='--cfg allocator="mimalloc"' cargo run --release --example allocators --features allocator-bench
+//   RUSTFLAGS='--cfg allocator="jemalloc"' cargo run --release --example allocators --features allocator-bench
+//
+// minimap2 itself always allocates with the C allocator; only the Rust side (Mappings, batch
+// buffers, strings) goes through the global allocator.
+
+use minimap2::*;
+
+use std::time::Instant;
+
+#[cfg(all(feature = "allocator-bench", allocator = "mimalloc"))]
+#[global_allocator]
+static ALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[cfg(all(feature = "allocator-bench", allocator = "jemalloc", unix))]
+#[global_allocator]
+static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+fn allocator_name() -> &'static str {
+    if cfg!(all(feature = "allocator-bench", allocator = "mimalloc")) {
+        "mimalloc"
+    } else if cfg!(all(
+        feature = "allocator-bench",
+        allocator = "jemalloc",
+        unix
+    )) {
+        "jemalloc"
+    } else {
+        "system"
+    }
+}
+
+fn main() {
+    let n_reads: usize = std::env::args()
+        .nth(1)
+        .map(|n| n.parse().expect("n_reads must be a number"))
+        .unwrap_or(20_000);
+
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_cigar()
+        .with_index("test_data/MT-human.fa", None)
+        .expect("Unable to build index");
+
+    let fasta = std::fs::read_to_string("test_data/MT-human.fa").expect("Unable to read FASTA");
+    let reference: Vec<u8> = fasta.lines().skip(1).flat_map(|l| l.bytes()).collect();
+
+    // 1kb reads tiled along the reference, with a deletion in the middle
+    let reads: Vec<(Vec<u8>, Vec<u8>)> = (0..n_reads)
+        .map(|i| {
+            let start = (i * 97) % (reference.len() - 1000);
+            let mut seq = reference[start..start + 1000].to_vec();
+            seq.drain(500..505);
+            (format!("read{}", i).into_bytes(), seq)
+        })
+        .collect();
+
+    println!("Allocator: {}", allocator_name());
+
+    let start = Instant::now();
+    let mut n_mappings = 0;
+    for (name, seq) in &reads {
+        n_mappings += aligner
+            .map(seq, true, true, None, None, Some(name))
+            .expect("Error mapping")
+            .len();
+    }
+    println!(
+        "map: {} reads, {} mappings in {:?}",
+        n_reads,
+        n_mappings,
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let mut n_mappings = 0;
+    for chunk in reads.chunks(1000) {
+        let batch = aligner
+            .map_batch(
+                chunk.iter().map(|(name, seq)| (&name[..], &seq[..])),
+                true,
+                true,
+            )
+            .expect("Error mapping");
+        n_mappings += batch.len();
+    }
+    println!(
+        "map_batch: {} reads, {} mappings in {:?}",
+        n_reads,
+        n_mappings,
+        start.elapsed()
+    );
+}
@@ -1,3 +1,6 @@
+//! Mapping on worker threads fed through crossbeam queues, for programs that manage their
+//! own threads. To map a read file straight into PAF or BAM, use `minimap2::pipelines`.
+
 use crossbeam::queue::ArrayQueue;
 use minimap2::*;
 use needletail::{parse_fastx_file, FastxReader};
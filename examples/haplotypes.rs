@@ -0,0 +1,63 @@
+use clap::Parser;
+use minimap2::haplotype::Haplotypes;
+use minimap2::*;
+
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "minimap2-haplotypes-example",
+    about = "Assign reads to the haplotypes of a dual-haplotype reference"
+)]
+struct Cli {
+    /// The reference with both haplotypes (FASTA, FASTQ or mmi)
+    pub target: PathBuf,
+
+    /// The reads to assign (FASTA or FASTQ)
+    pub query: PathBuf,
+
+    /// Contig name suffix of each haplotype
+    #[arg(long, num_args = 2.., default_values = ["_hap1", "_hap2"])]
+    pub suffixes: Vec<String>,
+
+    /// The number of threads to use
+    #[arg(long, default_value_t = 4)]
+    pub threads: usize,
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    let aligner = Aligner::builder()
+        .map_hifi()
+        .with_cigar()
+        .with_index_threads(args.threads)
+        .with_index(&args.target, None)
+        .expect("Unable to build index");
+    let haplotypes = Haplotypes::from_suffixes(&args.suffixes);
+
+    // One line per read: its haplotype, the score delta and the best score on each haplotype
+    println!("read\thaplotype\tdelta\t{}", haplotypes.labels().join("\t"));
+    let mut sink = |read_id: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+        let report = haplotypes.assign(mappings);
+        let scores: Vec<String> = report
+            .best
+            .iter()
+            .map(|hit| hit.map_or("-".to_string(), |hit| hit.score.to_string()))
+            .collect();
+        println!(
+            "{}\t{}\t{}\t{}",
+            String::from_utf8_lossy(read_id),
+            report
+                .assigned
+                .map_or("-", |haplotype| haplotypes.labels()[haplotype].as_str()),
+            report.delta,
+            scores.join("\t")
+        );
+        Ok(())
+    };
+
+    aligner
+        .map_file_to_sink(&args.query, &mut sink, args.threads)
+        .expect("Unable to map");
+}
@@ -0,0 +1,82 @@
+// Timing of index lookups and mapping against an index of many small contigs, as built from
+// amplicon panels. Run with `cargo run --release --example many_contigs [n_contigs]`, and with
+// `--features htslib` to include BAM header population.
+
+use minimap2::*;
+
+use std::time::Instant;
+
+fn main() {
+    let n_contigs: usize = std::env::args()
+        .nth(1)
+        .map(|n| n.parse().expect("n_contigs must be a number"))
+        .unwrap_or(50_000);
+
+    // Random 200bp contigs
+    let mut state: u64 = 1;
+    let mut next_base = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        b"ACGT"[(state >> 62) as usize]
+    };
+    let contigs: Vec<Vec<u8>> = (0..n_contigs)
+        .map(|_| (0..200).map(|_| next_base()).collect())
+        .collect();
+
+    let path = std::env::temp_dir().join("minimap2_many_contigs.fa");
+    let mut fasta = Vec::new();
+    for (i, seq) in contigs.iter().enumerate() {
+        fasta.extend_from_slice(format!(">amplicon_{}\n", i).as_bytes());
+        fasta.extend_from_slice(seq);
+        fasta.push(b'\n');
+    }
+    std::fs::write(&path, fasta).expect("Unable to write contigs");
+
+    let start = Instant::now();
+    let aligner = Aligner::builder()
+        .sr()
+        .with_cigar()
+        .with_index(&path, None)
+        .expect("Unable to build index");
+    std::fs::remove_file(&path).expect("Unable to remove contigs");
+    println!(
+        "Index of {} contigs built in {:?}",
+        n_contigs,
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    for i in 0..n_contigs {
+        aligner.target_id(&format!("amplicon_{}", i));
+    }
+    println!("{} target lookups in {:?}", n_contigs, start.elapsed());
+
+    let start = Instant::now();
+    let mut n_mappings = 0;
+    for seq in &contigs {
+        n_mappings += aligner
+            .map(&seq[25..175], false, false, None, None, None)
+            .expect("Error mapping")
+            .len();
+    }
+    println!(
+        "{} reads mapped ({} mappings) in {:?}",
+        n_contigs,
+        n_mappings,
+        start.elapsed()
+    );
+
+    #[cfg(feature = "htslib")]
+    {
+        let start = Instant::now();
+        let mut header = rust_htslib::bam::Header::new();
+        aligner.populate_header(&mut header);
+        let header_view = rust_htslib::bam::HeaderView::from_header(&header);
+        println!(
+            "Header of {} targets populated in {:?}",
+            header_view.target_count(),
+            start.elapsed()
+        );
+    }
+}
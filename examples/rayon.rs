@@ -1,3 +1,6 @@
+//! Mapping reads in parallel with rayon. For a whole read file written to PAF or BAM,
+//! `minimap2::pipelines` does the same with the crate's own thread pool.
+
 use minimap2::*;
 use needletail::{parse_fastx_file, FastxReader};
 use rayon::prelude::*;
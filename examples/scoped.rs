@@ -0,0 +1,75 @@
+use clap::Parser;
+use minimap2::*;
+use needletail::parse_fastx_file;
+
+use std::path::PathBuf;
+use std::{error::Error, path::Path};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "minimap2-scoped-example",
+    about = "An example of mapping borrowed reads on scoped threads, without Arc or 'static"
+)]
+struct Cli {
+    /// The target file to align to (e.g. a reference genome - can be in FASTA, FASTQ, or mmi format)
+    pub target: PathBuf,
+
+    /// The query file to align (e.g. reads - can be FASTA or FASTQ)
+    pub query: PathBuf,
+
+    /// The number of threads to use
+    pub threads: usize,
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    map(args.target, args.query, args.threads).expect("Unable to map");
+}
+
+fn map(
+    target_file: impl AsRef<Path>,
+    query_file: impl AsRef<Path>,
+    threads: usize,
+) -> Result<(), Box<dyn Error>> {
+    // The aligner lives on this stack frame; no Arc is needed to share it with the workers
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_cigar()
+        .with_index_threads(threads)
+        .with_index(target_file, None)
+        .expect("Unable to build index");
+
+    // Read all queries into one buffer, keeping the span of each
+    let mut reader = parse_fastx_file(query_file)?;
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut spans: Vec<(String, std::ops::Range<usize>)> = Vec::new();
+    while let Some(record) = reader.next() {
+        let record = record?;
+        let start = buffer.len();
+        buffer.extend_from_slice(&record.seq());
+        let name = String::from_utf8_lossy(record.id()).to_string();
+        spans.push((name, start..buffer.len()));
+    }
+
+    let mut n_mapped = 0;
+    let mut n_mappings = 0;
+    std::thread::scope(|scope| {
+        // The queries borrow from the buffer, and the sink from the counters above
+        let queries = spans
+            .iter()
+            .map(|(name, span)| (name.as_str(), &buffer[span.clone()]));
+        let sink = |_: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+            n_mapped += !mappings.is_empty() as usize;
+            n_mappings += mappings.len();
+            Ok(())
+        };
+
+        let handle = aligner.scoped_map(scope, queries, threads, sink);
+        println!("Mapping {} queries", spans.len());
+        handle.join().expect("Mapping thread panicked")
+    })?;
+
+    println!("{n_mapped} queries mapped, {n_mappings} mappings");
+    Ok(())
+}
@@ -2,9 +2,14 @@ use crossbeam::queue::ArrayQueue;
 use minimap2::*;
 use needletail::{parse_fastx_file, FastxReader};
 
-use std::{error::Error, path::Path, sync::Arc, time::Duration};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::state::QuerySequence;
+use minimap2::events::{MappingEvent, QuerySequence, Status};
 
 /// We use a worker queue to pass around work between threads.
 /// We do it this way to be generic over the type.
@@ -28,13 +33,15 @@ pub(crate) fn map_with_channels(
     query_file: impl AsRef<Path>,
     threads: usize,
 
+    // Optional output file
+    output: Option<PathBuf>,
+    format: Option<crate::cli::Format>,
+
     // UI Stuff
     dispatcher_tx: tokio::sync::mpsc::UnboundedSender<crate::state::Action>,
 ) -> Result<(), Box<dyn Error>> {
     dispatcher_tx
-        .send(crate::state::Action::SetStatus(
-            "Indexing Sequence".to_string(),
-        ))
+        .send(MappingEvent::Status(Status::Indexing).into())
         .expect("Unable to send status update");
 
     // Aligner gets created using the build pattern.
@@ -47,12 +54,17 @@ pub(crate) fn map_with_channels(
         .expect("Unable to build index");
 
     dispatcher_tx
-        .send(crate::state::Action::SetStatus(
-            "Indexing Complete".to_string(),
-        ))
+        .send(MappingEvent::Status(Status::IndexingComplete).into())
         .expect("Unable to send status update");
 
     log::info!("Made aligner");
+
+    // Mappings are written as they come in, so in whatever order the threads finish
+    let mut sink = match output {
+        Some(path) => Some(crate::output::open_sink(path, format, &aligner)?),
+        None => None,
+    };
+
     // Create a queue for work and for results
     let work_queue = Arc::new(ArrayQueue::<WorkQueue<WorkUnit>>::new(1024));
     let results_queue = Arc::new(ArrayQueue::<WorkQueue<WorkResult>>::new(1024));
@@ -95,9 +107,7 @@ pub(crate) fn map_with_channels(
                 .unwrap_or_else(|_| panic!("Can't find query FASTA file"));
 
             dispatcher_tx
-                .send(crate::state::Action::SetStatus(
-                    "Mapping Sequences".to_string(),
-                ))
+                .send(MappingEvent::Status(Status::Mapping).into())
                 .expect("Unable to send status update");
 
             // I just do this in the main thread, but you can split threads
@@ -111,17 +121,17 @@ pub(crate) fn map_with_channels(
                 }
 
                 // Oh and add it to the UI (this should be first, but trying to keep multithreading and UI separate)
-                let _ =
-                    dispatcher_tx.send(crate::state::Action::AddQuerySequence(QuerySequence::new(
+                let _ = dispatcher_tx.send(
+                    MappingEvent::QueryAdded(QuerySequence::new(
                         std::str::from_utf8(record.id()).unwrap().to_string(),
                         record.seq().to_vec(),
-                    )));
+                    ))
+                    .into(),
+                );
             }
 
             dispatcher_tx
-                .send(crate::state::Action::SetStatus(
-                    "All Sequences Submitted".to_string(),
-                ))
+                .send(MappingEvent::Status(Status::AllQueriesSubmitted).into())
                 .expect("Unable to send status update");
 
             // Set the shutdown flag
@@ -146,11 +156,18 @@ pub(crate) fn map_with_channels(
                     std::str::from_utf8(&record.0).unwrap()
                 );
 
+                if let Some(sink) = sink.as_mut() {
+                    sink.write(&record.0, &alignments)?;
+                }
+
                 // Add the mappings to the UI
-                let _ = dispatcher_tx.send(crate::state::Action::AddMappings((
-                    std::str::from_utf8(&record.0).unwrap().to_string(),
-                    alignments,
-                )));
+                let _ = dispatcher_tx.send(
+                    MappingEvent::MappingsProduced {
+                        query_id: std::str::from_utf8(&record.0).unwrap().to_string(),
+                        mappings: alignments,
+                    }
+                    .into(),
+                );
             }
             Some(_) => unimplemented!("Unexpected result type"),
             None => {
@@ -170,13 +187,15 @@ pub(crate) fn map_with_channels(
     }
 
     dispatcher_tx
-        .send(crate::state::Action::SetStatus(
-            "Mapping Complete".to_string(),
-        ))
+        .send(MappingEvent::Status(Status::MappingComplete).into())
         .expect("Unable to send status update");
 
     log::info!("Iteration complete, total alignments {}", num_alignments);
 
+    if let Some(mut sink) = sink {
+        sink.finish()?;
+    }
+
     // Join all the threads
     log::trace!("Joining all threads");
     for handle in jh {
@@ -19,6 +19,14 @@ pub(crate) struct Cli {
 
     /// The method to use for multithreading
     pub method: Option<Method>,
+
+    /// Also write the mappings to this file (PAF, or BAM with a .bam extension)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// The format of the output file, instead of guessing it from the extension
+    #[arg(short, long, requires = "output")]
+    pub format: Option<Format>,
 }
 
 #[derive(ValueEnum, Debug, Default, Clone)]
@@ -31,6 +39,15 @@ pub(crate) enum Method {
     Rayon,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub(crate) enum Format {
+    /// Pairwise mapping format, as minimap2 prints by default
+    Paf,
+
+    /// BAM (needs the htslib feature)
+    Bam,
+}
+
 pub(crate) fn parse_args() -> Cli {
     Cli::parse()
 }
@@ -1,20 +1,12 @@
 use crossterm::event::KeyEvent;
+use minimap2::events::MappingEvent;
 use minimap2::Mapping;
 use tokio::sync::Mutex;
 
 use std::sync::Arc;
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct QuerySequence {
-    pub id: String,
-    pub sequence: Vec<u8>,
-}
+pub use minimap2::events::{QuerySequence, Status};
 
-impl QuerySequence {
-    pub fn new(id: String, sequence: Vec<u8>) -> Self {
-        Self { id, sequence }
-    }
-}
 pub enum Action {
     // QueryStore
     AddQuerySequence(QuerySequence),
@@ -66,3 +58,16 @@ impl Action {
         }
     }
 }
+
+// Mapping events from the library feed the stores through the dispatcher
+impl From<MappingEvent> for Action {
+    fn from(event: MappingEvent) -> Self {
+        match event {
+            MappingEvent::QueryAdded(query) => Action::AddQuerySequence(query),
+            MappingEvent::MappingsProduced { query_id, mappings } => {
+                Action::AddMappings((query_id, mappings))
+            }
+            MappingEvent::Status(status) => Action::SetStatus(status.to_string()),
+        }
+    }
+}
@@ -9,6 +9,9 @@ mod cli;
 mod channels; // I prefer using channels over rayon, but rayon is simpler to use
 mod rayon;
 
+// Writing the mappings out
+mod output;
+
 use flexi_logger::{FileSpec, Logger, WriteMode};
 
 // Ignore the tokio stuff, it's just for visualization and interaction!
@@ -36,13 +39,22 @@ async fn main() {
                     args.target,
                     args.query,
                     args.threads,
+                    args.output,
+                    args.format,
                     dispatcher_tx.clone(),
                 )
                 .expect("Error mapping with channels");
             }
             cli::Method::Rayon => {
-                rayon::map(args.target, args.query, args.threads, dispatcher_tx.clone())
-                    .expect("Error mapping with rayon");
+                rayon::map(
+                    args.target,
+                    args.query,
+                    args.threads,
+                    args.output,
+                    args.format,
+                    dispatcher_tx.clone(),
+                )
+                .expect("Error mapping with rayon");
             }
         });
     }
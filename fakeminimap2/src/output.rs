@@ -0,0 +1,38 @@
+use std::{error::Error, path::Path};
+
+use minimap2::sink::{OutputSink, PafSink};
+use minimap2::*;
+
+use crate::cli::Format;
+
+/// Opens the sink the mappings are written to, as they are shown in the UI.
+/// Everything written goes through the library's OutputSink trait, so adding a format is
+/// just another match arm (see minimap2::sink for TSV and Parquet).
+pub(crate) fn open_sink(
+    path: impl AsRef<Path>,
+    format: Option<Format>,
+    aligner: &Aligner<Built>,
+) -> Result<Box<dyn OutputSink>, Box<dyn Error>> {
+    let path = path.as_ref();
+
+    // Guess the format from the extension if it wasn't given
+    let format = format.unwrap_or_else(|| match path.extension() {
+        Some(ext) if ext == "bam" => Format::Bam,
+        _ => Format::Paf,
+    });
+
+    match format {
+        Format::Paf => Ok(Box::new(PafSink::create(path)?)),
+
+        #[cfg(feature = "htslib")]
+        Format::Bam => Ok(Box::new(minimap2::htslib::BamSink::from_path(
+            path, aligner,
+        )?)),
+
+        #[cfg(not(feature = "htslib"))]
+        Format::Bam => {
+            let _ = aligner;
+            Err("BAM output needs fakeminimap2 built with --features htslib".into())
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::{error::Error, path::Path};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
 
 use minimap2::*;
 use needletail::{parse_fastx_file, FastxReader};
@@ -9,6 +12,10 @@ pub(crate) fn map(
     query_file: impl AsRef<Path>,
     threads: usize,
 
+    // Optional output file
+    output: Option<PathBuf>,
+    format: Option<crate::cli::Format>,
+
     // UI Stuff
     dispatcher_tx: tokio::sync::mpsc::UnboundedSender<crate::state::Action>,
 ) -> Result<(), Box<dyn Error>> {
@@ -53,5 +60,14 @@ pub(crate) fn map(
     let total_alignments: usize = results.iter().map(|x| x.len()).sum();
     println!("Iteration complete, total alignments {}", total_alignments);
 
+    // Results are in input order, so write them out in one go
+    if let Some(path) = output {
+        let mut sink = crate::output::open_sink(path, format, &aligner)?;
+        for ((id, _), mappings) in queries.iter().zip(&results) {
+            sink.write(id, mappings)?;
+        }
+        sink.finish()?;
+    }
+
     Ok(())
 }
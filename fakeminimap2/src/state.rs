@@ -2,6 +2,7 @@ use crossterm::event::KeyCode;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio::sync::watch;
 
+use minimap2::coverage::TargetCoverage;
 use minimap2::Mapping;
 
 use std::collections::HashMap;
@@ -16,14 +17,25 @@ pub use mapping_results_store::MappingResultStore;
 pub use query_sequences_store::QuerySequencesStore;
 pub use ui_state::{SelectedPanel, UiState};
 
+/// Bin size of the coverage panel, in target bases
+const COVERAGE_BIN_SIZE: u32 = 500;
+
 pub async fn start_dispatcher(
     dispatcher_tx: UnboundedSender<Action>,
     mut dispatcher_rx: UnboundedReceiver<Action>,
     ui_tx: watch::Sender<Option<UiState>>,
 ) {
+    // Coverage of the targets, accumulated as mappings come in
+    let coverage = Arc::new(tokio::sync::Mutex::new(TargetCoverage::new(
+        COVERAGE_BIN_SIZE,
+    )));
+
     let mut query_store = QuerySequencesStore::new(dispatcher_tx.clone());
-    let mut mapping_store = Arc::new(MappingResultStore::new(dispatcher_tx.clone()));
-    let mut ui_state = UiState::new(dispatcher_tx.clone());
+    let mut mapping_store = Arc::new(MappingResultStore::new(
+        dispatcher_tx.clone(),
+        Arc::clone(&coverage),
+    ));
+    let mut ui_state = UiState::new(dispatcher_tx.clone(), coverage);
 
     // Initial state
     ui_tx
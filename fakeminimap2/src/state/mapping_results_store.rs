@@ -2,6 +2,7 @@ pub use crate::datatypes::*;
 
 use crossterm::event::KeyEvent;
 use dashmap::DashMap;
+use minimap2::coverage::TargetCoverage;
 use minimap2::Mapping;
 use tokio::sync::{mpsc::UnboundedSender, Mutex};
 
@@ -10,13 +11,18 @@ use std::sync::Arc;
 pub struct MappingResultStore {
     dispatcher_tx: UnboundedSender<Action>,
     mappings: DashMap<String, Arc<Mutex<Vec<Mapping>>>>,
+    coverage: Arc<Mutex<TargetCoverage>>,
 }
 
 impl MappingResultStore {
-    pub fn new(dispatcher_tx: UnboundedSender<Action>) -> Self {
+    pub fn new(
+        dispatcher_tx: UnboundedSender<Action>,
+        coverage: Arc<Mutex<TargetCoverage>>,
+    ) -> Self {
         Self {
             dispatcher_tx,
             mappings: DashMap::new(),
+            coverage,
         }
     }
 
@@ -45,6 +51,15 @@ impl MappingResultStore {
 
     pub async fn add_mappings(self: Arc<Self>, id: String, mapping: Vec<Mapping>) {
         log::debug!("Adding mappings for id: {} - {} found", id, mapping.len());
+
+        // Coverage is shared with the UI, which redraws it on every tick
+        {
+            let mut coverage = self.coverage.lock().await;
+            mapping.iter().filter(|m| m.is_primary).for_each(|m| {
+                coverage.add(m);
+            });
+        }
+
         // If the entry already exists, update the mappings
         if self.mappings.get(&id).is_some() {
             log::trace!("Entry exists - Updating mappings for id: {}", id);
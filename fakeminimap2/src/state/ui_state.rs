@@ -1,5 +1,6 @@
 pub use crate::datatypes::*;
 
+use minimap2::coverage::TargetCoverage;
 use minimap2::Mapping;
 use tokio::sync::{mpsc::UnboundedSender, Mutex};
 
@@ -17,6 +18,7 @@ pub struct UiState {
     pub query_sequences_list: Vec<Arc<QuerySequence>>,
     pub selected_query_sequence: Option<Arc<QuerySequence>>,
     pub mappings: Option<Arc<Mutex<Vec<Mapping>>>>,
+    pub coverage: Arc<Mutex<TargetCoverage>>,
     pub selected_panel: SelectedPanel,
     pub shutdown: bool,
     pub status: String,
@@ -25,11 +27,15 @@ pub struct UiState {
 }
 
 impl UiState {
-    pub fn new(dispatcher_tx: UnboundedSender<Action>) -> Self {
+    pub fn new(
+        dispatcher_tx: UnboundedSender<Action>,
+        coverage: Arc<Mutex<TargetCoverage>>,
+    ) -> Self {
         Self {
             query_sequences_list: Vec::new(),
             selected_query_sequence: None,
             mappings: None,
+            coverage,
             shutdown: false,
             selected_panel: SelectedPanel::default(),
             status: "Starting up!".to_string(),
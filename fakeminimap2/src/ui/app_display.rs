@@ -24,6 +24,12 @@ const COLORS: [Color; 6] = [
     Color::Rgb(156, 142, 0)
     ];
 
+/// Targets shown in the coverage panel before it stops growing
+const MAX_COVERAGE_ROWS: usize = 8;
+
+/// Levels of the coverage sparklines, from no coverage to the maximum depth
+const SPARK_LEVELS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
 pub struct AppDisplay {
     state: state::UiState,
     frame_count: usize,
@@ -164,12 +170,24 @@ impl AppDisplay {
             None
         };
 
+        let coverage = self.state.coverage.lock().await;
+
         terminal
             .draw(|frame| {
                 let screen_bg = Color::Rgb(0, 0, 0);
 
-                let vertical = Layout::vertical([Length(1), Min(0), Length(1)]);
-                let [title_area, main_area, status_area] = vertical.areas(frame.area());
+                // Coverage panel grows with the number of targets mapped to, up to a point
+                let coverage_targets = coverage.targets();
+                let coverage_height = if coverage_targets.is_empty() {
+                    0
+                } else {
+                    coverage_targets.len().min(MAX_COVERAGE_ROWS) as u16 + 2
+                };
+
+                let vertical =
+                    Layout::vertical([Length(1), Min(0), Length(coverage_height), Length(1)]);
+                let [title_area, main_area, coverage_area, status_area] =
+                    vertical.areas(frame.area());
                 let horizontal = Layout::horizontal([Fill(1), Fill(2)]);
                 let [left_area, right_area] = horizontal.areas(main_area);
 
@@ -420,6 +438,53 @@ impl AppDisplay {
                     );
                 }
 
+                // Coverage of each target, on a shared depth scale so targets can be compared
+                if !coverage_targets.is_empty() {
+                    let name_width = coverage_targets
+                        .iter()
+                        .map(|(name, _)| name.len())
+                        .max()
+                        .unwrap_or(0)
+                        .min(24) as u16;
+                    let plot_width =
+                        coverage_area.width.saturating_sub(name_width + 10 + 2 + 4) as usize;
+
+                    let bin_depths = coverage_targets
+                        .iter()
+                        .map(|(name, _)| coverage.bin_depths(name).unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    let max_depth = bin_depths.iter().flatten().cloned().fold(0.0, f64::max);
+
+                    let rows = coverage_targets
+                        .iter()
+                        .zip(&bin_depths)
+                        .enumerate()
+                        .map(|(i, ((name, depth), bins))| {
+                            Row::new(vec![
+                                Cell::from(name.to_string()),
+                                Cell::from(format!("{:.1}x", depth.mean_depth())),
+                                Cell::from(sparkline(bins, plot_width, max_depth)),
+                            ])
+                            .style(Style::default().bg(screen_bg).fg(COLORS[i % COLORS.len()]))
+                        })
+                        .collect::<Vec<_>>();
+
+                    let table = Table::new(rows, [Length(name_width), Length(10), Fill(1)])
+                        .column_spacing(2)
+                        .style(Style::default().bg(screen_bg))
+                        .block(
+                            Block::bordered()
+                                .title("Coverage (primary mappings)")
+                                .title_bottom(format!(
+                                    "{} Targets - Max Depth {:.1}x",
+                                    coverage_targets.len(),
+                                    max_depth
+                                )),
+                        );
+
+                    frame.render_widget(table, coverage_area);
+                }
+
                 frame.render_widget(Block::bordered().title("Fakeminimap2"), title_area);
 
                 frame.render_widget(
@@ -433,3 +498,22 @@ impl AppDisplay {
             .expect("Error rendering");
     }
 }
+
+/// Squeeze the bin depths of a target into at most `width` characters, averaging
+/// neighbouring bins, scaled so that `max_depth` is a full block
+fn sparkline(depths: &[f64], width: usize, max_depth: f64) -> String {
+    if depths.is_empty() || width == 0 || max_depth <= 0.0 {
+        return String::new();
+    }
+
+    let width = width.min(depths.len());
+    (0..width)
+        .map(|col| {
+            let start = col * depths.len() / width;
+            let end = ((col + 1) * depths.len() / width).max(start + 1);
+            let mean = depths[start..end].iter().sum::<f64>() / (end - start) as f64;
+            let level = (mean / max_depth * (SPARK_LEVELS.len() - 1) as f64).ceil() as usize;
+            SPARK_LEVELS[level.min(SPARK_LEVELS.len() - 1)]
+        })
+        .collect()
+}
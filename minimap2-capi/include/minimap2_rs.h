@@ -0,0 +1,75 @@
+/* C ABI for the minimap2 Rust bindings (libminimap2_rs)
+ *
+ * Builds an index and maps sequences through the safe Rust wrapper, so that C, C++, R and
+ * Julia users get its memory-safety fixes rather than calling minimap2 directly.
+ *
+ * Functions returning a pointer return NULL on error; mm2rs_last_error() then describes the
+ * error. Everything returned must be freed with the matching mm2rs_*_free function. An
+ * aligner can be shared by threads mapping concurrently.
+ *
+ * Mappings are read one at a time through mm2rs_mappings_get(), never as an array, so that
+ * fields can be added at the end of mm2rs_mapping without breaking compiled callers: do not
+ * copy an mm2rs_mapping or index past one. MM2RS_ABI_VERSION is increased when a struct or
+ * function changes.
+ */
+
+#ifndef MINIMAP2_RS_H
+#define MINIMAP2_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define MM2RS_ABI_VERSION 2
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct mm2rs_aligner mm2rs_aligner;
+typedef struct mm2rs_mappings mm2rs_mappings;
+
+typedef struct {
+	const char *target_name;
+	int32_t target_len, target_start, target_end;
+	int32_t query_len, query_start, query_end;
+	int8_t strand;             /* 1 forward, -1 reverse */
+	uint8_t is_primary, is_supplementary;
+	uint32_t mapq;
+	int32_t match_len, block_len;
+	int32_t nm;                /* -1 without base-level alignment */
+	const char *cigar, *cs, *md; /* NULL if not computed */
+} mm2rs_mapping;
+
+/* ABI version of the library, to compare with MM2RS_ABI_VERSION */
+uint32_t mm2rs_abi_version(void);
+
+/* Last error on this thread, NULL if none; valid until the next call on this thread */
+const char *mm2rs_last_error(void);
+
+/* Build or load the index of a FASTA file or .mmi index with a minimap2 preset such as
+ * "map-ont" or "sr". cigar != 0 computes base-level alignments. */
+mm2rs_aligner *mm2rs_aligner_from_index(const char *preset, const char *path, int threads, int cigar);
+
+/* Build the index from n NUL-terminated sequences and names */
+mm2rs_aligner *mm2rs_aligner_from_seqs(const char *preset, size_t n, const char *const *seqs,
+                                       const char *const *names, int cigar);
+
+void mm2rs_aligner_free(mm2rs_aligner *aligner);
+
+/* Map a sequence of len bases; name may be NULL. cs and md need an aligner built with cigar. */
+mm2rs_mappings *mm2rs_map(const mm2rs_aligner *aligner, const char *seq, size_t len,
+                          const char *name, int cs, int md);
+
+/* Number of mappings */
+size_t mm2rs_mappings_len(const mm2rs_mappings *mappings);
+
+/* Mapping i, NULL if out of range; valid until the mappings are freed */
+const mm2rs_mapping *mm2rs_mappings_get(const mm2rs_mappings *mappings, size_t i);
+
+void mm2rs_mappings_free(mm2rs_mappings *mappings);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
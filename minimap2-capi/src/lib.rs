@@ -0,0 +1,444 @@
+//! C ABI for the minimap2 Rust bindings
+//!
+//! Exposes index building and mapping through `extern "C"` functions with `#[repr(C)]`
+//! results, declared in `include/minimap2_rs.h`, so that C, C++, R and Julia can use the safe
+//! wrapper (and its memory-safety fixes) instead of minimap2 itself.
+//!
+//! Functions never unwind into C: errors and panics return NULL and are described by
+//! [`mm2rs_last_error`]. Memory returned to C is owned by this library and freed with the
+//! matching `mm2rs_*_free` function.
+
+#![allow(non_camel_case_types)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use minimap2::{Aligner, Built, Mapping, Preset, Strand};
+
+/// Increased when a struct or function below changes. Fields are only added at the end of
+/// `mm2rs_mapping`, which C reads through [`mm2rs_mappings_get`] rather than as an array.
+pub const MM2RS_ABI_VERSION: u32 = 2;
+
+const PRESETS: [Preset; 17] = [
+    Preset::LrHqae,
+    Preset::LrHq,
+    Preset::Splice,
+    Preset::SpliceHq,
+    Preset::Asm,
+    Preset::Asm5,
+    Preset::Asm10,
+    Preset::Asm20,
+    Preset::Sr,
+    Preset::MapPb,
+    Preset::MapHifi,
+    Preset::MapOnt,
+    Preset::AvaPb,
+    Preset::AvaOnt,
+    Preset::Short,
+    Preset::Map10k,
+    Preset::Cdna,
+];
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with_borrow_mut(|e| *e = Some(message));
+}
+
+/// Run `f`, turning errors and panics into NULL and the last error
+fn guard<T>(f: impl FnOnce() -> Result<*mut T, &'static str>) -> *mut T {
+    LAST_ERROR.with_borrow_mut(|e| *e = None);
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(ptr)) => ptr,
+        Ok(Err(message)) => {
+            set_error(message);
+            ptr::null_mut()
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Unknown error".to_string());
+            set_error(&message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `s` must be NULL or a NUL-terminated string
+unsafe fn str_arg<'a>(s: *const c_char, what: &'static str) -> Result<&'a str, &'static str> {
+    if s.is_null() {
+        return Err(what);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| "Invalid UTF-8 in argument")
+}
+
+fn preset(name: &str) -> Result<Preset, &'static str> {
+    PRESETS
+        .into_iter()
+        .find(|&preset| {
+            let c: *const c_char = preset.into();
+            unsafe { CStr::from_ptr(c) }.to_bytes() == name.as_bytes()
+        })
+        .ok_or("Unknown preset")
+}
+
+fn builder(
+    preset_name: *const c_char,
+    cigar: c_int,
+) -> Result<Aligner<minimap2::PresetSet>, &'static str> {
+    let preset = preset(unsafe { str_arg(preset_name, "Preset is NULL")? })?;
+    let builder = Aligner::builder().preset(preset);
+    Ok(if cigar != 0 {
+        builder.with_cigar()
+    } else {
+        builder
+    })
+}
+
+/// An aligner with its index, opaque to C
+pub struct mm2rs_aligner {
+    aligner: Aligner<Built>,
+}
+
+#[repr(C)]
+pub struct mm2rs_mapping {
+    pub target_name: *const c_char,
+    pub target_len: i32,
+    pub target_start: i32,
+    pub target_end: i32,
+    pub query_len: i32,
+    pub query_start: i32,
+    pub query_end: i32,
+    /// 1 forward, -1 reverse
+    pub strand: i8,
+    pub is_primary: u8,
+    pub is_supplementary: u8,
+    pub mapq: u32,
+    pub match_len: i32,
+    pub block_len: i32,
+    /// -1 without base-level alignment
+    pub nm: i32,
+    /// NULL if not computed
+    pub cigar: *const c_char,
+    pub cs: *const c_char,
+    pub md: *const c_char,
+}
+
+/// The mappings of a query, opaque to C
+pub struct mm2rs_mappings {
+    mappings: Vec<mm2rs_mapping>,
+}
+
+fn into_c_string(s: Option<&str>) -> *const c_char {
+    s.and_then(|s| CString::new(s).ok())
+        .map_or(ptr::null(), |s| s.into_raw() as *const c_char)
+}
+
+/// # Safety
+/// `s` must come from [`into_c_string`]
+unsafe fn free_c_string(s: *const c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s as *mut c_char));
+    }
+}
+
+impl Drop for mm2rs_mapping {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_string(self.target_name);
+            free_c_string(self.cigar);
+            free_c_string(self.cs);
+            free_c_string(self.md);
+        }
+    }
+}
+
+impl From<&Mapping> for mm2rs_mapping {
+    fn from(m: &Mapping) -> Self {
+        let alignment = m.alignment.as_ref();
+        mm2rs_mapping {
+            target_name: into_c_string(m.target_name.as_deref().map(String::as_str)),
+            target_len: m.target_len,
+            target_start: m.target_start,
+            target_end: m.target_end,
+            query_len: m.query_len.map_or(0, |len| len.get()),
+            query_start: m.query_start,
+            query_end: m.query_end,
+            strand: match m.strand {
+                Strand::Forward => 1,
+                Strand::Reverse => -1,
+            },
+            is_primary: m.is_primary as u8,
+            is_supplementary: m.is_supplementary as u8,
+            mapq: m.mapq,
+            match_len: m.match_len,
+            block_len: m.block_len,
+            nm: alignment.map_or(-1, |a| a.nm),
+            cigar: into_c_string(alignment.and_then(|a| a.cigar_str.as_deref())),
+            cs: into_c_string(alignment.and_then(|a| a.cs.as_deref())),
+            md: into_c_string(alignment.and_then(|a| a.md.as_deref())),
+        }
+    }
+}
+
+/// ABI version of the library, to compare with `MM2RS_ABI_VERSION` in the header
+#[no_mangle]
+pub extern "C" fn mm2rs_abi_version() -> u32 {
+    MM2RS_ABI_VERSION
+}
+
+/// Last error on this thread, NULL if the last call succeeded
+#[no_mangle]
+pub extern "C" fn mm2rs_last_error() -> *const c_char {
+    LAST_ERROR.with_borrow(|e| e.as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Build or load the index of a FASTA file or .mmi index
+///
+/// # Safety
+/// `preset` and `path` must be NUL-terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn mm2rs_aligner_from_index(
+    preset: *const c_char,
+    path: *const c_char,
+    threads: c_int,
+    cigar: c_int,
+) -> *mut mm2rs_aligner {
+    guard(|| {
+        let path = str_arg(path, "Path is NULL")?;
+        let aligner = builder(preset, cigar)?
+            .with_index_threads(threads.max(1) as usize)
+            .with_index(path, None)?;
+        Ok(Box::into_raw(Box::new(mm2rs_aligner { aligner })))
+    })
+}
+
+/// Build the index from `n` sequences and names
+///
+/// # Safety
+/// `seqs` and `names` must point to `n` NUL-terminated strings each
+#[no_mangle]
+pub unsafe extern "C" fn mm2rs_aligner_from_seqs(
+    preset: *const c_char,
+    n: usize,
+    seqs: *const *const c_char,
+    names: *const *const c_char,
+    cigar: c_int,
+) -> *mut mm2rs_aligner {
+    guard(|| {
+        if n == 0 || seqs.is_null() || names.is_null() {
+            return Err("Must have at least one sequence and name");
+        }
+        let seqs = std::slice::from_raw_parts(seqs, n);
+        let names = std::slice::from_raw_parts(names, n);
+        if seqs.iter().chain(names).any(|s| s.is_null()) {
+            return Err("Sequence or name is NULL");
+        }
+        let seqs: Vec<Vec<u8>> = seqs
+            .iter()
+            .map(|&s| CStr::from_ptr(s).to_bytes().to_vec())
+            .collect();
+        let names: Vec<Vec<u8>> = names
+            .iter()
+            .map(|&s| CStr::from_ptr(s).to_bytes().to_vec())
+            .collect();
+
+        let aligner = builder(preset, cigar)?.with_seqs_and_ids(&seqs, &names)?;
+        Ok(Box::into_raw(Box::new(mm2rs_aligner { aligner })))
+    })
+}
+
+/// # Safety
+/// `aligner` must be NULL or come from `mm2rs_aligner_from_*`, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn mm2rs_aligner_free(aligner: *mut mm2rs_aligner) {
+    if !aligner.is_null() {
+        drop(Box::from_raw(aligner));
+    }
+}
+
+/// Map a sequence of `len` bases
+///
+/// # Safety
+/// `aligner` must come from `mm2rs_aligner_from_*`, `seq` point to `len` bytes and `name` be
+/// NULL or a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn mm2rs_map(
+    aligner: *const mm2rs_aligner,
+    seq: *const c_char,
+    len: usize,
+    name: *const c_char,
+    cs: c_int,
+    md: c_int,
+) -> *mut mm2rs_mappings {
+    guard(|| {
+        if aligner.is_null() || seq.is_null() {
+            return Err("Aligner or sequence is NULL");
+        }
+        let seq = std::slice::from_raw_parts(seq as *const u8, len);
+        let name = (!name.is_null()).then(|| CStr::from_ptr(name).to_bytes());
+
+        let mappings = (*aligner)
+            .aligner
+            .map(seq, cs != 0, md != 0, None, None, name)?;
+        let mappings = mappings.iter().map(mm2rs_mapping::from).collect();
+        Ok(Box::into_raw(Box::new(mm2rs_mappings { mappings })))
+    })
+}
+
+/// Number of mappings
+///
+/// # Safety
+/// `mappings` must be NULL or come from `mm2rs_map`
+#[no_mangle]
+pub unsafe extern "C" fn mm2rs_mappings_len(mappings: *const mm2rs_mappings) -> usize {
+    mappings.as_ref().map_or(0, |m| m.mappings.len())
+}
+
+/// Mapping `i`, NULL if out of range
+///
+/// # Safety
+/// `mappings` must be NULL or come from `mm2rs_map`
+#[no_mangle]
+pub unsafe extern "C" fn mm2rs_mappings_get(
+    mappings: *const mm2rs_mappings,
+    i: usize,
+) -> *const mm2rs_mapping {
+    mappings
+        .as_ref()
+        .and_then(|m| m.mappings.get(i))
+        .map_or(ptr::null(), |m| m as *const mm2rs_mapping)
+}
+
+/// # Safety
+/// `mappings` must be NULL or come from `mm2rs_map`, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn mm2rs_mappings_free(mappings: *mut mm2rs_mappings) {
+    if !mappings.is_null() {
+        drop(Box::from_raw(mappings));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    const MT: &CStr = c"../test_data/MT-human.fa";
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_layout() {
+        // Must match include/minimap2_rs.h
+        assert_eq!(size_of::<mm2rs_mapping>(), 80);
+        assert_eq!(offset_of!(mm2rs_mapping, strand), 32);
+        assert_eq!(offset_of!(mm2rs_mapping, mapq), 36);
+        assert_eq!(offset_of!(mm2rs_mapping, nm), 48);
+        assert_eq!(offset_of!(mm2rs_mapping, cigar), 56);
+    }
+
+    #[test]
+    fn test_map() {
+        unsafe {
+            let aligner = mm2rs_aligner_from_index(c"map-ont".as_ptr(), MT.as_ptr(), 1, 1);
+            assert!(!aligner.is_null());
+
+            let seq = std::fs::read_to_string("../test_data/MT-human.fa").unwrap();
+            let seq: String = seq.lines().skip(1).collect();
+            let read = &seq.as_bytes()[1000..3000];
+            let result = mm2rs_map(
+                aligner,
+                read.as_ptr() as *const c_char,
+                read.len(),
+                c"read".as_ptr(),
+                1,
+                1,
+            );
+            assert!(!result.is_null());
+            assert!(mm2rs_last_error().is_null());
+
+            assert!(mm2rs_mappings_len(result) > 0);
+            let m = &*mm2rs_mappings_get(result, 0);
+            assert_eq!(CStr::from_ptr(m.target_name).to_str(), Ok("MT_human"));
+            assert_eq!((m.target_start, m.target_end), (1000, 3000));
+            assert_eq!(m.strand, 1);
+            assert_eq!(m.is_primary, 1);
+            assert_eq!(m.nm, 0);
+            assert_eq!(CStr::from_ptr(m.cigar).to_str(), Ok("2000M"));
+            assert!(!m.cs.is_null() && !m.md.is_null());
+            assert!(mm2rs_mappings_get(result, mm2rs_mappings_len(result)).is_null());
+
+            mm2rs_mappings_free(result);
+            mm2rs_aligner_free(aligner);
+        }
+    }
+
+    #[test]
+    fn test_from_seqs() {
+        unsafe {
+            let seq = c"CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
+            let seqs = [seq.as_ptr()];
+            let names = [c"seq1".as_ptr()];
+            let aligner =
+                mm2rs_aligner_from_seqs(c"map-ont".as_ptr(), 1, seqs.as_ptr(), names.as_ptr(), 0);
+            assert!(!aligner.is_null());
+
+            let read = &seq.to_bytes()[0..60];
+            let result = mm2rs_map(
+                aligner,
+                read.as_ptr() as *const c_char,
+                read.len(),
+                ptr::null(),
+                0,
+                0,
+            );
+            assert_eq!(mm2rs_mappings_len(result), 1);
+            let m = &*mm2rs_mappings_get(result, 0);
+            assert_eq!(CStr::from_ptr(m.target_name).to_str(), Ok("seq1"));
+            assert!(m.cigar.is_null());
+            assert_eq!(m.nm, -1);
+            mm2rs_mappings_free(result);
+            mm2rs_aligner_free(aligner);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let aligner = mm2rs_aligner_from_index(c"no-such-preset".as_ptr(), MT.as_ptr(), 1, 0);
+            assert!(aligner.is_null());
+            assert_eq!(
+                CStr::from_ptr(mm2rs_last_error()).to_str(),
+                Ok("Unknown preset")
+            );
+
+            let aligner = mm2rs_aligner_from_index(c"sr".as_ptr(), c"missing.fa".as_ptr(), 1, 0);
+            assert!(aligner.is_null());
+            assert!(!mm2rs_last_error().is_null());
+
+            let aligner = mm2rs_aligner_from_index(c"sr".as_ptr(), MT.as_ptr(), 1, 0);
+            let result = mm2rs_map(aligner, c"".as_ptr(), 0, ptr::null(), 0, 0);
+            assert!(result.is_null());
+            assert_eq!(
+                CStr::from_ptr(mm2rs_last_error()).to_str(),
+                Ok("Sequence is empty")
+            );
+
+            // Freeing NULL is a no-op
+            mm2rs_mappings_free(ptr::null_mut());
+            mm2rs_aligner_free(aligner);
+            mm2rs_aligner_free(ptr::null_mut());
+        }
+    }
+}
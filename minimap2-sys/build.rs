@@ -48,10 +48,51 @@ fn configure(mut cc: &mut cc::Build) {
 
     cc.file("minimap2/ksw2_ll_sse.c");
 
+    // Struct layout checks, see layout.c
+    println!("cargo:rerun-if-changed=layout.c");
+    cc.include(upstream_bucket_header());
+    cc.file("layout.c");
+
+    // Index construction from caller-supplied minimizers, see idx_minimizers.c
+    println!("cargo:rerun-if-changed=idx_minimizers.c");
+    println!("cargo:rerun-if-changed=idx_bucket.h");
+    cc.file("idx_minimizers.c");
+
     #[cfg(not(feature = "noopt"))]
     target_specific(&mut cc);
 }
 
+// Copy the bucket struct private to minimap2/index.c into OUT_DIR/upstream_bucket.h, renamed,
+// for layout.c to check idx_bucket.h against
+fn upstream_bucket_header() -> PathBuf {
+    const START: &str = "typedef struct mm_idx_bucket_s";
+    const END: &str = "} mm_idx_bucket_t;";
+
+    let index_c = std::fs::read_to_string("minimap2/index.c").unwrap();
+    let start = index_c
+        .find(START)
+        .expect("mm_idx_bucket_t not found in minimap2/index.c");
+    let end = start
+        + index_c[start..]
+            .find(END)
+            .expect("mm_idx_bucket_t not found in minimap2/index.c")
+        + END.len();
+    let bucket = index_c[start..end]
+        .replace("mm_idx_bucket_s", "mm2rs_upstream_bucket_s")
+        .replace("mm_idx_bucket_t", "mm2rs_upstream_bucket_t");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    std::fs::write(
+        out_dir.join("upstream_bucket.h"),
+        format!(
+            "// Generated by build.rs from minimap2/index.c\n#include \"mmpriv.h\"\n{bucket}\n"
+        ),
+    )
+    .unwrap();
+    println!("cargo:rerun-if-changed=minimap2/index.c");
+    out_dir
+}
+
 fn target_specific(cc: &mut cc::Build) {
     // let host = env::var("HOST").unwrap();
     let target = env::var("TARGET").unwrap();
@@ -0,0 +1,18 @@
+// Copy of the index bucket struct, which is private to minimap2's index.c.
+//
+// mm_idx_destroy() frees buckets filled by idx_minimizers.c, so this must match index.c;
+// layout.c checks it against the definition build.rs extracts from index.c.
+
+#ifndef MM2RS_IDX_BUCKET_H
+#define MM2RS_IDX_BUCKET_H
+
+#include "mmpriv.h"
+
+typedef struct mm_idx_bucket_s {
+	mm128_v a;   // (minimizer, position) array
+	int32_t n;   // size of the _p_ array
+	uint64_t *p; // position array for minimizers appearing >1 times
+	void *h;     // hash table indexing _p_ and minimizers appearing once
+} mm_idx_bucket_t;
+
+#endif
@@ -0,0 +1,151 @@
+// Index construction from minimizers computed by the caller.
+//
+// mm_idx_str() sketches its sequences with mm_sketch(); mm2rs_idx_from_minimizers() builds
+// the same index from minimizers supplied by the caller instead, e.g. from another sketcher,
+// so that alternative sketching schemes can be tried with minimap2's chaining and alignment.
+// The functions filling the hash tables are static in index.c and are reproduced here; the
+// bucket struct is in idx_bucket.h, checked against index.c by layout.c.
+
+#include <stdlib.h>
+#include <string.h>
+#include "minimap.h"
+#include "mmpriv.h"
+#include "kalloc.h"
+#include "kvec.h"
+#include "khash.h"
+#include "idx_bucket.h"
+
+#define idx_hash(a) ((a)>>1)
+#define idx_eq(a, b) ((a)>>1 == (b)>>1)
+KHASH_INIT(idx, uint64_t, uint64_t, 1, idx_hash, idx_eq)
+typedef khash_t(idx) idxhash_t;
+
+extern unsigned char seq_nt4_table[256];
+
+static mm_idx_t *mm2rs_idx_init(int w, int k, int b, int flag)
+{
+	mm_idx_t *mi;
+	if (k*2 < b) b = k * 2;
+	if (w < 1) w = 1;
+	mi = (mm_idx_t*)calloc(1, sizeof(mm_idx_t));
+	mi->w = w, mi->k = k, mi->b = b, mi->flag = flag;
+	mi->B = (mm_idx_bucket_t*)calloc(1<<b, sizeof(mm_idx_bucket_t));
+	if (!(mm_dbg_flag & 1)) mi->km = km_init();
+	return mi;
+}
+
+// Returns -1 if the hash table cannot be built; the bucket is left for mm_idx_destroy()
+static int mm2rs_idx_post_bucket(mm_idx_t *mi, mm_idx_bucket_t *b)
+{
+	int n, n_keys;
+	size_t j, start_a, start_p;
+	idxhash_t *h;
+	if (b->a.n == 0) return 0;
+
+	// sort by minimizer
+	radix_sort_128x(b->a.a, b->a.a + b->a.n);
+
+	// count and preallocate
+	for (j = 1, n = 1, n_keys = 0, b->n = 0; j <= b->a.n; ++j) {
+		if (j == b->a.n || b->a.a[j].x>>8 != b->a.a[j-1].x>>8) {
+			++n_keys;
+			if (n > 1) b->n += n;
+			n = 1;
+		} else ++n;
+	}
+	b->h = h = kh_init(idx);
+	kh_resize(idx, h, n_keys);
+	b->p = (uint64_t*)calloc(b->n, 8);
+
+	// create the hash table
+	for (j = 1, n = 1, start_a = start_p = 0; j <= b->a.n; ++j) {
+		if (j == b->a.n || b->a.a[j].x>>8 != b->a.a[j-1].x>>8) {
+			khint_t itr;
+			int absent;
+			mm128_t *p = &b->a.a[j-1];
+			itr = kh_put(idx, h, p->x>>8>>mi->b<<1, &absent);
+			if (!absent || j != start_a + n) return -1;
+			if (n == 1) {
+				kh_key(h, itr) |= 1;
+				kh_val(h, itr) = p->y;
+			} else {
+				int k;
+				for (k = 0; k < n; ++k)
+					b->p[start_p + k] = b->a.a[start_a + k].y;
+				radix_sort_64(&b->p[start_p], &b->p[start_p + n]);
+				kh_val(h, itr) = (uint64_t)start_p<<32 | n;
+				start_p += n;
+			}
+			start_a = j, n = 1;
+		} else ++n;
+	}
+
+	kfree(0, b->a.a);
+	b->a.n = b->a.m = 0, b->a.a = 0;
+	return 0;
+}
+
+// Minimizers are encoded as by mm_sketch(): x = hash<<8 | span, y = rid<<32 | last pos<<1 |
+// strand, in any order. Returns NULL if a minimizer refers to a missing sequence or lies
+// outside its sequence, or if the hash tables cannot be built.
+mm_idx_t *mm2rs_idx_from_minimizers(int w, int k, int is_hpc, int bucket_bits, int n, const char **seq, const char **name, size_t n_mini, const mm128_t *mini)
+{
+	uint64_t sum_len = 0;
+	uint32_t *len;
+	mm_idx_t *mi;
+	int i, flag = 0, mask;
+	size_t j;
+
+	if (n <= 0) return 0;
+	len = (uint32_t*)calloc(n, sizeof(uint32_t));
+	for (i = 0; i < n; ++i) len[i] = strlen(seq[i]);
+	for (j = 0; j < n_mini; ++j) {
+		uint64_t rid = mini[j].y>>32;
+		uint32_t pos = (uint32_t)mini[j].y>>1, span = mini[j].x & 0xff;
+		if (rid >= (uint64_t)n || pos >= len[rid] || span == 0 || pos + 1 < span) {
+			free(len);
+			return 0;
+		}
+	}
+
+	if (is_hpc) flag |= MM_I_HPC;
+	if (name == 0) flag |= MM_I_NO_NAME;
+	if (bucket_bits == 0) bucket_bits = 14;
+	mi = mm2rs_idx_init(w, k, bucket_bits, flag);
+	mi->n_seq = n;
+	mi->seq = (mm_idx_seq_t*)kcalloc(mi->km, n, sizeof(mm_idx_seq_t));
+	for (i = 0; i < n; ++i) sum_len += len[i];
+	mi->S = (uint32_t*)calloc((sum_len + 7) / 8, 4);
+	for (i = 0, sum_len = 0; i < n; ++i) {
+		const char *s = seq[i];
+		mm_idx_seq_t *p = &mi->seq[i];
+		uint32_t l;
+		if (name && name[i]) {
+			p->name = (char*)kmalloc(mi->km, strlen(name[i]) + 1);
+			strcpy(p->name, name[i]);
+		}
+		p->offset = sum_len;
+		p->len = len[i];
+		p->is_alt = 0;
+		for (l = 0; l < p->len; ++l) {
+			int c = seq_nt4_table[(uint8_t)s[l]];
+			uint64_t o = sum_len + l;
+			mi->S[o>>3] |= (uint32_t)c << ((o&7)<<2);
+		}
+		sum_len += p->len;
+	}
+	free(len);
+
+	mask = (1<<mi->b) - 1;
+	for (j = 0; j < n_mini; ++j) {
+		mm128_v *p = &mi->B[mini[j].x>>8&mask].a;
+		kv_push(mm128_t, 0, *p, mini[j]);
+	}
+	for (i = 0; i < 1<<mi->b; ++i) {
+		if (mm2rs_idx_post_bucket(mi, &mi->B[i]) < 0) {
+			mm_idx_destroy(mi);
+			return 0;
+		}
+	}
+	return mi;
+}
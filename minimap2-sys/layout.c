@@ -0,0 +1,95 @@
+// Layout checks for the minimap2 structs the Rust crates read directly.
+//
+// The bindings in src/bindings.rs are generated once and checked in, so a minimap2 update
+// that changes one of these structs would otherwise be read with the old layout. Sizes are
+// checked at compile time on 64-bit targets, and mm2rs_layout() exposes sizes and field
+// offsets as seen by the C compiler so the tests can compare them with the bindings.
+//
+// The index bucket struct is private to index.c, so idx_minimizers.c uses a copy from
+// idx_bucket.h. build.rs extracts the original into upstream_bucket.h, renamed
+// mm2rs_upstream_bucket_t, and the copy is checked against it here on every target.
+
+#include <stddef.h>
+#include <stdint.h>
+#include "minimap.h"
+#include "idx_bucket.h"
+#include "upstream_bucket.h"
+
+#define MM2RS_CHECK_SIZE(t, n) typedef char mm2rs_size_of_##t[(sizeof(t) == (n)) ? 1 : -1]
+#define MM2RS_CHECK_BUCKET(f) typedef char mm2rs_bucket_##f[(offsetof(mm_idx_bucket_t, f) == offsetof(mm2rs_upstream_bucket_t, f)) ? 1 : -1]
+
+typedef char mm2rs_bucket_size[(sizeof(mm_idx_bucket_t) == sizeof(mm2rs_upstream_bucket_t)) ? 1 : -1];
+MM2RS_CHECK_BUCKET(a);
+MM2RS_CHECK_BUCKET(n);
+MM2RS_CHECK_BUCKET(p);
+MM2RS_CHECK_BUCKET(h);
+
+#if UINTPTR_MAX == UINT64_MAX
+MM2RS_CHECK_SIZE(mm_reg1_t, 80);
+MM2RS_CHECK_SIZE(mm_extra_t, 28);
+MM2RS_CHECK_SIZE(mm_idx_t, 80);
+MM2RS_CHECK_SIZE(mm_idx_seq_t, 24);
+MM2RS_CHECK_SIZE(mm_mapopt_t, 256);
+MM2RS_CHECK_SIZE(mm_idxopt_t, 24);
+#endif
+
+// Must list the same entries, in the same order, as the layout test in src/lib.rs
+static const size_t mm2rs_layout_table[] = {
+	sizeof(mm_reg1_t),
+	offsetof(mm_reg1_t, id),
+	offsetof(mm_reg1_t, rid),
+	offsetof(mm_reg1_t, qs),
+	offsetof(mm_reg1_t, qe),
+	offsetof(mm_reg1_t, rs),
+	offsetof(mm_reg1_t, re),
+	offsetof(mm_reg1_t, parent),
+	offsetof(mm_reg1_t, mlen),
+	offsetof(mm_reg1_t, blen),
+	offsetof(mm_reg1_t, score0),
+	offsetof(mm_reg1_t, hash),
+	offsetof(mm_reg1_t, div),
+	offsetof(mm_reg1_t, p),
+
+	sizeof(mm_extra_t),
+	offsetof(mm_extra_t, capacity),
+	offsetof(mm_extra_t, dp_score),
+	offsetof(mm_extra_t, dp_max),
+	offsetof(mm_extra_t, dp_max0),
+	offsetof(mm_extra_t, n_cigar),
+	offsetof(mm_extra_t, cigar),
+
+	sizeof(mm_idx_t),
+	offsetof(mm_idx_t, k),
+	offsetof(mm_idx_t, n_seq),
+	offsetof(mm_idx_t, seq),
+	offsetof(mm_idx_t, S),
+	offsetof(mm_idx_t, km),
+	offsetof(mm_idx_t, h),
+
+	sizeof(mm_idx_seq_t),
+	offsetof(mm_idx_seq_t, name),
+	offsetof(mm_idx_seq_t, offset),
+	offsetof(mm_idx_seq_t, len),
+	offsetof(mm_idx_seq_t, is_alt),
+
+	sizeof(mm_mapopt_t),
+	offsetof(mm_mapopt_t, flag),
+	offsetof(mm_mapopt_t, max_qlen),
+	offsetof(mm_mapopt_t, best_n),
+	offsetof(mm_mapopt_t, pe_ori),
+	offsetof(mm_mapopt_t, mid_occ),
+	offsetof(mm_mapopt_t, max_sw_mat),
+	offsetof(mm_mapopt_t, cap_kalloc),
+	offsetof(mm_mapopt_t, split_prefix),
+
+	sizeof(mm_idxopt_t),
+	offsetof(mm_idxopt_t, k),
+	offsetof(mm_idxopt_t, flag),
+	offsetof(mm_idxopt_t, batch_size),
+};
+
+const size_t *mm2rs_layout(size_t *n)
+{
+	*n = sizeof(mm2rs_layout_table) / sizeof(mm2rs_layout_table[0]);
+	return mm2rs_layout_table;
+}
@@ -0,0 +1,192 @@
+//! Named errors for the bare return codes of minimap2
+//!
+//! minimap2 reports invalid options with a negative code from [`mm_check_opt`](crate::mm_check_opt)
+//! and a message on stderr; several checks share a code. [`check_opt`] calls it and, on
+//! failure, finds which check failed so callers get an [`OptError`] naming it, with the code
+//! kept for context.
+//!
+//! `mm_idx_jjump_read` and `mm_idx_spsc_read` are not part of the minimap2 2.28 API bound by
+//! this crate, so have no translation here.
+
+use std::fmt;
+
+use crate::*;
+
+/// Which option check of `mm_check_opt` failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptErrorKind {
+    /// `-r` (bw) is larger than `--bw-long` (bw_long)
+    Bandwidth,
+    /// `--splice` with `--frag`
+    SpliceWithFrag,
+    /// `-N` (best_n) is negative
+    NegativeBestN,
+    /// `-p` (pri_ratio) is outside [0, 1]
+    PriRatio,
+    /// `--for-only` with `--rev-only`
+    ForAndRevOnly,
+    /// Gap open or extension penalties are not positive, or the dual penalties violate
+    /// E1 > E2 and O1 + E1 < O2 + E2
+    GapPenalties,
+    /// (O1 + E1) + (O2 + E2) is above 127
+    ScoreOverflow,
+    /// `--score-N` (sc_ambi) is outside [0, B)
+    AmbiguousScore,
+    /// Z-drop is smaller than the inversion Z-drop
+    ZDrop,
+    /// `-X`/`-P` (all chains) with `--secondary=no`
+    SecondaryWithAllChains,
+    /// `--qstrand` with SAM output, splicing, fragment mode or homopolymer compression
+    QueryStrand,
+    /// A check this crate does not know about
+    Unknown,
+}
+
+/// Invalid options reported by `mm_check_opt`, see [`check_opt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptError {
+    pub kind: OptErrorKind,
+    /// The code returned by `mm_check_opt`
+    pub code: i32,
+}
+
+impl OptError {
+    /// Description of the failed check, as minimap2 words it
+    pub fn message(&self) -> &'static str {
+        match self.kind {
+            OptErrorKind::Bandwidth => "-r should be no larger than --bw-long",
+            OptErrorKind::SpliceWithFrag => {
+                "--splice and --frag should not be specified at the same time"
+            }
+            OptErrorKind::NegativeBestN => "-N must be no less than 0",
+            OptErrorKind::PriRatio => "-p must be within 0 and 1",
+            OptErrorKind::ForAndRevOnly => {
+                "--for-only and --rev-only can't be applied at the same time"
+            }
+            OptErrorKind::GapPenalties => {
+                "gap penalties must be positive, with dual gap penalties satisfying E1>E2 and O1+E1<O2+E2"
+            }
+            OptErrorKind::ScoreOverflow => {
+                "scoring system violating ({-O}+{-E})+({-O2}+{-E2}) <= 127"
+            }
+            OptErrorKind::AmbiguousScore => "--score-N should be within [0,{-B})",
+            OptErrorKind::ZDrop => "Z-drop should not be less than inversion-Z-drop",
+            OptErrorKind::SecondaryWithAllChains => {
+                "-X/-P and --secondary=no can't be applied at the same time"
+            }
+            OptErrorKind::QueryStrand => "--qstrand doesn't work with -a, -H, --frag or --splice",
+            OptErrorKind::Unknown => "Invalid options",
+        }
+    }
+}
+
+impl fmt::Display for OptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (mm_check_opt returned {})",
+            self.message(),
+            self.code
+        )
+    }
+}
+
+impl std::error::Error for OptError {}
+
+impl From<OptError> for &'static str {
+    fn from(e: OptError) -> Self {
+        e.message()
+    }
+}
+
+/// Check options with `mm_check_opt`, naming the failed check on error
+pub fn check_opt(io: &mm_idxopt_t, mo: &mm_mapopt_t) -> Result<(), OptError> {
+    let code = unsafe { mm_check_opt(io, mo) };
+    if code == 0 {
+        return Ok(());
+    }
+    Err(OptError {
+        kind: diagnose(io, mo),
+        code,
+    })
+}
+
+/// The first of the checks of `mm_check_opt` that the options fail
+fn diagnose(io: &mm_idxopt_t, mo: &mm_mapopt_t) -> OptErrorKind {
+    let flag = |f: i64| mo.flag & f != 0;
+
+    if mo.bw > mo.bw_long {
+        OptErrorKind::Bandwidth
+    } else if flag(MM_F_SPLICE as i64) && flag(MM_F_FRAG_MODE as i64) {
+        OptErrorKind::SpliceWithFrag
+    } else if mo.best_n < 0 {
+        OptErrorKind::NegativeBestN
+    } else if !(0.0..=1.0).contains(&mo.pri_ratio) {
+        OptErrorKind::PriRatio
+    } else if flag(MM_F_FOR_ONLY as i64) && flag(MM_F_REV_ONLY as i64) {
+        OptErrorKind::ForAndRevOnly
+    } else if mo.e <= 0
+        || mo.q <= 0
+        || ((mo.q != mo.q2 || mo.e != mo.e2) && !(mo.e > mo.e2 && mo.q + mo.e < mo.q2 + mo.e2))
+    {
+        OptErrorKind::GapPenalties
+    } else if (mo.q + mo.e) + (mo.q2 + mo.e2) > 127 {
+        OptErrorKind::ScoreOverflow
+    } else if mo.sc_ambi < 0 || mo.sc_ambi >= mo.b {
+        OptErrorKind::AmbiguousScore
+    } else if mo.zdrop < mo.zdrop_inv {
+        OptErrorKind::ZDrop
+    } else if flag(MM_F_NO_PRINT_2ND as i64) && flag(MM_F_ALL_CHAINS as i64) {
+        OptErrorKind::SecondaryWithAllChains
+    } else if flag(MM_F_QSTRAND as i64)
+        && (flag(MM_F_OUT_SAM as i64)
+            || flag(MM_F_SPLICE as i64)
+            || flag(MM_F_FRAG_MODE as i64)
+            || io.flag as u32 & MM_I_HPC != 0)
+    {
+        OptErrorKind::QueryStrand
+    } else {
+        OptErrorKind::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_opt() {
+        let io: mm_idxopt_t = Default::default();
+        let mo: mm_mapopt_t = Default::default();
+        assert_eq!(check_opt(&io, &mo), Ok(()));
+
+        let mut both = mo;
+        both.set_for_only();
+        both.set_rev_only();
+        let err = check_opt(&io, &both).unwrap_err();
+        assert_eq!(err.kind, OptErrorKind::ForAndRevOnly);
+        assert!(err.code < 0);
+        assert!(err.to_string().starts_with("--for-only and --rev-only"));
+
+        let mut zdrop = mo;
+        zdrop.zdrop_inv = zdrop.zdrop + 1;
+        assert_eq!(
+            check_opt(&io, &zdrop).unwrap_err().kind,
+            OptErrorKind::ZDrop
+        );
+
+        let mut splice_frag = mo;
+        splice_frag.flag |= (MM_F_SPLICE | MM_F_FRAG_MODE) as i64;
+        assert_eq!(
+            check_opt(&io, &splice_frag).unwrap_err().kind,
+            OptErrorKind::SpliceWithFrag
+        );
+
+        let mut pri_ratio = mo;
+        pri_ratio.pri_ratio = 2.0;
+        assert_eq!(
+            check_opt(&io, &pri_ratio).unwrap_err().kind,
+            OptErrorKind::PriRatio
+        );
+    }
+}
@@ -16,6 +16,8 @@ unsafe impl Send for mm_mapopt_t {}
 
 use paste::paste;
 
+pub mod error;
+
 impl Drop for mm_idx_t {
     fn drop(&mut self) {
         unsafe { mm_idx_destroy(self) };
@@ -145,6 +147,131 @@ add_flag_methods!(
     (set_no_name, unset_no_name, MM_I_NO_NAME)
 );
 
+/// Reusable output buffer for the cs and MD tags generated by [`mm_gen_cs`] and [`mm_gen_MD`]
+///
+/// minimap2 grows the buffer as needed and allocates it, along with its scratch space, from a
+/// kalloc pool owned by the TagBuffer. Everything is freed when the TagBuffer is dropped.
+pub struct TagBuffer {
+    km: *mut std::os::raw::c_void,
+    buf: *mut std::os::raw::c_char,
+    capacity: std::os::raw::c_int,
+}
+
+// The pool and buffer are only reachable through &mut self
+unsafe impl Send for TagBuffer {}
+
+impl TagBuffer {
+    pub fn new() -> Self {
+        TagBuffer {
+            km: unsafe { km_init() },
+            buf: std::ptr::null_mut(),
+            capacity: 0,
+        }
+    }
+
+    /// Generate the cs tag of a hit. The result is valid until the next call.
+    ///
+    /// `no_iden` writes runs of identical bases as `:n` (short form) rather than `=ACGT`.
+    ///
+    /// # Safety
+    /// `reg` must be a hit returned by minimap2 for `seq` against `idx`, with a CIGAR
+    /// (`reg.p` non-null), and `idx` must contain sequences.
+    pub unsafe fn gen_cs(
+        &mut self,
+        idx: &mm_idx_t,
+        reg: &mm_reg1_t,
+        seq: &[u8],
+        no_iden: bool,
+    ) -> &[u8] {
+        assert!(!reg.p.is_null(), "Hit has no alignment");
+        let len = mm_gen_cs(
+            self.km,
+            &mut self.buf,
+            &mut self.capacity,
+            idx,
+            reg,
+            seq.as_ptr() as *const std::os::raw::c_char,
+            no_iden.into(),
+        );
+        self.output(len)
+    }
+
+    /// Generate the MD tag of a hit. The result is valid until the next call.
+    ///
+    /// # Safety
+    /// As for [`TagBuffer::gen_cs`]
+    pub unsafe fn gen_md(&mut self, idx: &mm_idx_t, reg: &mm_reg1_t, seq: &[u8]) -> &[u8] {
+        assert!(!reg.p.is_null(), "Hit has no alignment");
+        let len = mm_gen_MD(
+            self.km,
+            &mut self.buf,
+            &mut self.capacity,
+            idx,
+            reg,
+            seq.as_ptr() as *const std::os::raw::c_char,
+        );
+        self.output(len)
+    }
+
+    fn output(&self, len: std::os::raw::c_int) -> &[u8] {
+        if self.buf.is_null() || len <= 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.buf as *const u8, len as usize) }
+    }
+}
+
+impl Default for TagBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TagBuffer {
+    fn drop(&mut self) {
+        // The buffer was allocated from km
+        unsafe { km_destroy(self.km) };
+    }
+}
+
+/// Generate the cs tag of a hit, see [`TagBuffer::gen_cs`]
+///
+/// # Safety
+/// As for [`TagBuffer::gen_cs`]
+pub unsafe fn gen_cs(idx: &mm_idx_t, reg: &mm_reg1_t, seq: &[u8], no_iden: bool) -> Vec<u8> {
+    TagBuffer::new().gen_cs(idx, reg, seq, no_iden).to_vec()
+}
+
+/// Generate the MD tag of a hit, see [`TagBuffer::gen_md`]
+///
+/// # Safety
+/// As for [`TagBuffer::gen_cs`]
+pub unsafe fn gen_md(idx: &mm_idx_t, reg: &mm_reg1_t, seq: &[u8]) -> Vec<u8> {
+    TagBuffer::new().gen_md(idx, reg, seq).to_vec()
+}
+
+extern "C" {
+    /// Build an index from minimizers computed by the caller rather than by `mm_sketch`, with
+    /// the same arguments as [`mm_idx_str`] plus the minimizers (see idx_minimizers.c)
+    ///
+    /// Minimizers are encoded as by `mm_sketch`: `x` is the hash shifted left by 8 bits with
+    /// the span in the low byte, `y` the sequence ID shifted left by 32 bits, then the
+    /// position of the last base of the k-mer shifted left by 1 bit, then the strand. They
+    /// can be in any order. Returns NULL if a minimizer refers to a missing sequence or lies
+    /// outside its sequence, or if the hash tables cannot be built.
+    pub fn mm2rs_idx_from_minimizers(
+        w: ::std::os::raw::c_int,
+        k: ::std::os::raw::c_int,
+        is_hpc: ::std::os::raw::c_int,
+        bucket_bits: ::std::os::raw::c_int,
+        n: ::std::os::raw::c_int,
+        seq: *mut *const ::std::os::raw::c_char,
+        name: *mut *const ::std::os::raw::c_char,
+        n_mini: usize,
+        mini: *const mm128_t,
+    ) -> *mut mm_idx_t;
+}
+
 // TODO: Add more tests!
 #[cfg(test)]
 mod tests {
@@ -187,6 +314,50 @@ mod tests {
         assert_eq!(opt.flag & MM_F_NO_QUAL as i64, 0_i64);
     }
 
+    // Compares the bindings with the struct layouts seen by the C compiler (layout.c)
+    #[test]
+    fn test_struct_layouts() {
+        extern "C" {
+            fn mm2rs_layout(n: *mut usize) -> *const usize;
+        }
+
+        macro_rules! layout {
+            ($($ty:ty => [$($field:ident),*]),* $(,)?) => {
+                vec![$(
+                    (concat!("size of ", stringify!($ty)), std::mem::size_of::<$ty>()),
+                    $((
+                        concat!("offset of ", stringify!($ty), ".", stringify!($field)),
+                        std::mem::offset_of!($ty, $field),
+                    ),)*
+                )*]
+            };
+        }
+
+        let rust = layout![
+            mm_reg1_t => [id, rid, qs, qe, rs, re, parent, mlen, blen, score0, hash, div, p],
+            mm_extra_t => [capacity, dp_score, dp_max, dp_max0, n_cigar, cigar],
+            mm_idx_t => [k, n_seq, seq, S, km, h],
+            mm_idx_seq_t => [name, offset, len, is_alt],
+            mm_mapopt_t => [flag, max_qlen, best_n, pe_ori, mid_occ, max_sw_mat, cap_kalloc, split_prefix],
+            mm_idxopt_t => [k, flag, batch_size],
+        ];
+
+        let c = unsafe {
+            let mut n = 0;
+            let table = mm2rs_layout(&mut n);
+            std::slice::from_raw_parts(table, n)
+        };
+
+        assert_eq!(
+            rust.len(),
+            c.len(),
+            "layout.c and this test list different fields"
+        );
+        for ((name, rust), c) in rust.iter().zip(c) {
+            assert_eq!(rust, c, "{name} differs between the bindings and minimap2");
+        }
+    }
+
     #[test]
     fn test_idxopt_flags() {
         let mut opt = mm_idxopt_t::default();
@@ -0,0 +1,6 @@
+// Forward routine registration from C to Rust, so that the linker keeps the static library
+void R_init_minimap2r_extendr(void *dll);
+
+void R_init_minimap2r(void *dll) {
+    R_init_minimap2r_extendr(dll);
+}
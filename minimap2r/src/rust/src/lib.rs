@@ -0,0 +1,190 @@
+//! R bindings for minimap2, returning mappings as data.frame columns
+//!
+//! The R functions in `R/minimap2r.R` wrap [`Aligner`]; its methods return named lists of
+//! columns, one element per mapping, that R turns into a data.frame. Coordinates are 0-based
+//! and end-exclusive, as in PAF.
+
+use extendr_api::prelude::*;
+use minimap2::{Mapping, Preset, Strand};
+
+fn preset(name: &str) -> Result<Preset> {
+    Ok(match name {
+        "lr:hqae" => Preset::LrHqae,
+        "lr:hq" => Preset::LrHq,
+        "splice" => Preset::Splice,
+        "splice:hq" => Preset::SpliceHq,
+        "asm" => Preset::Asm,
+        "asm5" => Preset::Asm5,
+        "asm10" => Preset::Asm10,
+        "asm20" => Preset::Asm20,
+        "sr" => Preset::Sr,
+        "map-pb" => Preset::MapPb,
+        "map-hifi" => Preset::MapHifi,
+        "map-ont" => Preset::MapOnt,
+        "ava-pb" => Preset::AvaPb,
+        "ava-ont" => Preset::AvaOnt,
+        "short" => Preset::Short,
+        "map10k" => Preset::Map10k,
+        "cdna" => Preset::Cdna,
+        _ => return Err(Error::Other(format!("Unknown preset: {name}"))),
+    })
+}
+
+fn r_error(e: &str) -> Error {
+    Error::Other(e.to_string())
+}
+
+/// Mappings collected column by column
+#[derive(Default)]
+struct Columns {
+    query_name: Vec<String>,
+    query_len: Vec<Option<i32>>,
+    query_start: Vec<i32>,
+    query_end: Vec<i32>,
+    strand: Vec<String>,
+    target_name: Vec<Option<String>>,
+    target_len: Vec<i32>,
+    target_start: Vec<i32>,
+    target_end: Vec<i32>,
+    match_len: Vec<i32>,
+    block_len: Vec<i32>,
+    mapq: Vec<i32>,
+    is_primary: Vec<bool>,
+    is_supplementary: Vec<bool>,
+    nm: Vec<Option<i32>>,
+    cigar: Vec<Option<String>>,
+    cs: Vec<Option<String>>,
+    md: Vec<Option<String>>,
+}
+
+impl Columns {
+    fn push(&mut self, query_name: &str, m: &Mapping) {
+        let alignment = m.alignment.as_ref();
+        self.query_name.push(query_name.to_string());
+        self.query_len.push(m.query_len.map(|len| len.get()));
+        self.query_start.push(m.query_start);
+        self.query_end.push(m.query_end);
+        self.strand.push(
+            match m.strand {
+                Strand::Forward => "+",
+                Strand::Reverse => "-",
+            }
+            .to_string(),
+        );
+        self.target_name
+            .push(m.target_name.as_ref().map(|name| name.to_string()));
+        self.target_len.push(m.target_len);
+        self.target_start.push(m.target_start);
+        self.target_end.push(m.target_end);
+        self.match_len.push(m.match_len);
+        self.block_len.push(m.block_len);
+        self.mapq.push(m.mapq as i32);
+        self.is_primary.push(m.is_primary);
+        self.is_supplementary.push(m.is_supplementary);
+        self.nm.push(alignment.map(|a| a.nm));
+        self.cigar.push(alignment.and_then(|a| a.cigar_str.clone()));
+        self.cs.push(alignment.and_then(|a| a.cs.clone()));
+        self.md.push(alignment.and_then(|a| a.md.clone()));
+    }
+
+    fn into_list(self) -> List {
+        list!(
+            query_name = self.query_name,
+            query_len = self.query_len,
+            query_start = self.query_start,
+            query_end = self.query_end,
+            strand = self.strand,
+            target_name = self.target_name,
+            target_len = self.target_len,
+            target_start = self.target_start,
+            target_end = self.target_end,
+            match_len = self.match_len,
+            block_len = self.block_len,
+            mapq = self.mapq,
+            is_primary = self.is_primary,
+            is_supplementary = self.is_supplementary,
+            nm = self.nm,
+            cigar = self.cigar,
+            cs = self.cs,
+            md = self.md
+        )
+    }
+}
+
+/// A minimap2 aligner with its index
+struct Aligner {
+    aligner: minimap2::Aligner<minimap2::Built>,
+    cs: bool,
+    md: bool,
+}
+
+#[extendr]
+impl Aligner {
+    /// Build or load the index of a FASTA file or .mmi index with a minimap2 preset.
+    /// `cigar` computes base-level alignments, and with them the cs and MD tags.
+    fn new(preset_name: &str, index: &str, threads: i32, cigar: bool) -> Result<Self> {
+        let builder = minimap2::Aligner::builder()
+            .preset(preset(preset_name)?)
+            .with_index_threads(threads.max(1) as usize);
+        let builder = if cigar { builder.with_cigar() } else { builder };
+        let aligner = builder.with_index(index, None).map_err(r_error)?;
+        Ok(Aligner {
+            aligner,
+            cs: cigar,
+            md: cigar,
+        })
+    }
+
+    /// Map sequences given with their names, on the calling thread
+    fn map(&self, seqs: Vec<String>, names: Vec<String>) -> Result<List> {
+        if seqs.len() != names.len() {
+            return Err(r_error("seqs and names must have the same length"));
+        }
+        let mut columns = Columns::default();
+        for (seq, name) in seqs.iter().zip(&names) {
+            let mappings = self
+                .aligner
+                .map(
+                    seq.as_bytes(),
+                    self.cs,
+                    self.md,
+                    None,
+                    None,
+                    Some(name.as_bytes()),
+                )
+                .map_err(r_error)?;
+            for m in &mappings {
+                columns.push(name, m);
+            }
+        }
+        Ok(columns.into_list())
+    }
+
+    /// Map a FASTA/FASTQ file (may be gzipped) on `threads` threads, in input order
+    fn map_file(&self, path: &str, threads: i32) -> Result<List> {
+        let mut columns = Columns::default();
+        let mut sink = |id: &[u8], mappings: &[Mapping]| -> std::result::Result<(), &'static str> {
+            // The record ID includes the header comment
+            let id = String::from_utf8_lossy(id);
+            let name = id.split_whitespace().next().unwrap_or("*");
+            for m in mappings {
+                columns.push(name, m);
+            }
+            Ok(())
+        };
+        self.aligner
+            .map_file_to_sink(path, &mut sink, threads.max(1) as usize)
+            .map_err(r_error)?;
+        Ok(columns.into_list())
+    }
+
+    /// Names of the sequences in the index
+    fn target_names(&self) -> Vec<String> {
+        self.aligner.seqs().map(|seq| seq.name).collect()
+    }
+}
+
+extendr_module! {
+    mod minimap2r;
+    impl Aligner;
+}
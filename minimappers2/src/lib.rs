@@ -1,7 +1,6 @@
 use std::num::NonZeroI32;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use crossbeam::queue::ArrayQueue;
 use minimap2::*;
 
 use polars::{df, prelude::*};
@@ -49,10 +48,6 @@ static ALLOC: Jemalloc = Jemalloc;
 static ALLOC: MiMalloc = MiMalloc;
 
 
-mod multithreading;
-
-use multithreading::*;
-
 /// Sequence class for use with minimappers2
 #[pyclass]
 #[derive(Default, Debug, Clone)]
@@ -243,80 +238,67 @@ impl Aligner {
 
     /// Map multiple sequences - Multithreaded
     fn map(&self, py: Python<'_>, seqs: Vec<Sequence>) -> PyResult<PyDataFrame> {
-        // If single threaded, do not open a new thread...
-        if self.aligner.threads == 1 {
-            let mut mappings = Mappings::default();
-
-            for seq in seqs {
-                let results = self
-                    .aligner
-                    .map(&seq.sequence, true, true, None, None, Some(&seq.id.as_bytes()))
-                    .unwrap();
-                results.into_iter().for_each(|r| {
-                    mappings.push(r)
-                });
-            }
-            Ok(PyDataFrame(mappings.to_df().unwrap()))
-        } else {
-            let work_queue = Arc::new(Mutex::new(seqs));
-            let results_queue = Arc::new(ArrayQueue::<WorkQueue<Vec<Mapping>>>::new(128));
-            let mut thread_handles = Vec::new();
-            for i in 0..(self.aligner.threads - 1) {
-                let work_queue = Arc::clone(&work_queue);
-                let results_queue = Arc::clone(&results_queue);
-
-                let aligner = self.aligner.clone();
-
-                let handle = std::thread::spawn(move || loop {
-                    let work = work_queue.lock().unwrap().pop();
-
-                    match work {
-                        Some(sequence) => {
-                            let mut result = aligner
-                                .map(&sequence.sequence, true, true, None, None, Some(&sequence.id.as_bytes()))
-                                .expect("Unable to align");
-
-                            results_queue.push(WorkQueue::Work(result));
-                        }
-                        None => {
-                            // Means the work queue is empty...
-                            results_queue.push(WorkQueue::Done);
-                            break;
-                        }
-                    }
-                });
-                thread_handles.push(handle);
-            }
-
-            let mut mappings = Mappings::default();
-            let mut finished_count = 0;
+        let mappings = map_sequences(&self.aligner, py, seqs)?;
+        Ok(PyDataFrame(mappings.to_df().unwrap()))
+    }
 
-            loop {
-                py.check_signals()?;
-                let result = results_queue.pop();
-                match result {
-                    Some(WorkQueue::Work(result)) => {
-                        result.into_iter().for_each(|r| mappings.push(r));
-                    }
-                    Some(WorkQueue::Done) => {
-                        finished_count += 1;
-                        if finished_count == (self.aligner.threads - 1) {
-                            break;
-                        }
+    /// Map a FASTA/FASTQ file (may be gzipped) on the threads set with set_threads, returning
+    /// every result as one DataFrame in input order. Reads never become Python objects, and
+    /// the GIL is released while mapping.
+    fn map_file(&self, py: Python<'_>, path: &str) -> PyResult<PyDataFrame> {
+        let threads = self.aligner.threads.max(1);
+        let mappings = py
+            .allow_threads(|| {
+                let mut mappings = Mappings::default();
+                let mut sink = |id: &[u8], results: &[Mapping]| -> Result<(), &'static str> {
+                    // The record ID includes the header comment
+                    let id = String::from_utf8_lossy(id);
+                    let name = id.split_whitespace().next().unwrap_or_default();
+                    let name = Arc::new(name.to_string());
+                    for r in results {
+                        mappings.push(Mapping {
+                            query_name: Some(Arc::clone(&name)),
+                            ..r.clone()
+                        });
                     }
-                    None => {
-                        // Probably should be backoff, but let's try this for now...
-                        std::thread::sleep(std::time::Duration::from_millis(100));
-                    }
-                }
-            }
-
-            for handle in thread_handles {
-                handle.join().unwrap();
-            }
+                    Ok(())
+                };
+                self.aligner
+                    .map_file_to_sink(path, &mut sink, threads)
+                    .map(|_| mappings)
+            })
+            .map_err(pyo3::exceptions::PyIOError::new_err)?;
+        Ok(PyDataFrame(mappings.to_df().map_err(PyPolarsErr::from)?))
+    }
 
-            Ok(PyDataFrame(mappings.to_df().unwrap()))
+    /// Map a FASTA/FASTQ file (may be gzipped) in chunks of `chunk_size` reads, yielding one
+    /// DataFrame per chunk so that large files can be mapped without holding every result
+    #[pyo3(signature = (path, chunk_size=10000))]
+    fn map_file_chunks(&self, path: &str, chunk_size: usize) -> PyResult<MappingChunks> {
+        if chunk_size == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "chunk_size must be positive",
+            ));
         }
+        let reader = needletail::parse_fastx_file(path)
+            .map_err(|e| pyo3::exceptions::PyIOError::new_err(e.to_string()))?;
+        Ok(MappingChunks {
+            aligner: self.aligner.clone(),
+            reader,
+            chunk_size,
+        })
+    }
+
+    /// Map a FASTA/FASTQ file (may be gzipped) straight to a Parquet file, with the columns of
+    /// minimap2::sink::ParquetSink. Results are streamed to disk in input order with bounded
+    /// memory, on the threads set with set_threads.
+    fn map_file_to_parquet(&self, py: Python<'_>, path: &str, output: &str) -> PyResult<()> {
+        let threads = self.aligner.threads.max(1);
+        py.allow_threads(|| {
+            let mut sink = minimap2::sink::ParquetSink::create(output)?;
+            self.aligner.map_file_to_sink(path, &mut sink, threads)
+        })
+        .map_err(pyo3::exceptions::PyIOError::new_err)
     }
 
     fn set_threads(&mut self, threads: usize) {
@@ -325,12 +307,79 @@ impl Aligner {
     
 }
 
+/// Iterator over the mappings of a file, one DataFrame per chunk of reads, returned by
+/// Aligner.map_file_chunks
+#[pyclass(unsendable)]
+pub struct MappingChunks {
+    aligner: minimap2::Aligner<Built>,
+    reader: Box<dyn needletail::FastxReader>,
+    chunk_size: usize,
+}
+
+#[pymethods]
+impl MappingChunks {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyDataFrame>> {
+        let mut seqs = Vec::with_capacity(self.chunk_size);
+        while seqs.len() < self.chunk_size {
+            match self.reader.next() {
+                Some(Ok(record)) => {
+                    let id = String::from_utf8_lossy(record.id());
+                    seqs.push(Sequence {
+                        id: id.split_whitespace().next().unwrap_or_default().to_string(),
+                        sequence: record.seq().to_vec(),
+                    });
+                }
+                Some(Err(e)) => return Err(pyo3::exceptions::PyIOError::new_err(e.to_string())),
+                None => break,
+            }
+        }
+
+        if seqs.is_empty() {
+            return Ok(None);
+        }
+        let mappings = map_sequences(&self.aligner, py, seqs)?;
+        Ok(Some(PyDataFrame(mappings.to_df().map_err(PyPolarsErr::from)?)))
+    }
+}
+
+/// Map sequences on the aligner's threads, with the GIL released
+fn map_sequences(
+    aligner: &minimap2::Aligner<Built>,
+    py: Python<'_>,
+    seqs: Vec<Sequence>,
+) -> PyResult<Mappings> {
+    py.allow_threads(|| map_sequences_on_threads(aligner, &seqs))
+        .map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// Map sequences on the aligner's threads, or one thread if none are set
+fn map_sequences_on_threads(
+    aligner: &minimap2::Aligner<Built>,
+    seqs: &[Sequence],
+) -> Result<Mappings, &'static str> {
+    let results = aligner.map_parallel(
+        seqs.iter().map(|seq| (seq.id.as_bytes(), &seq.sequence)),
+        true,
+        true,
+        aligner.threads.max(1),
+    )?;
+
+    let mut mappings = Mappings::default();
+    results.into_iter().flatten().for_each(|r| mappings.push(r));
+    Ok(mappings)
+}
+
 /// This module is implemented in Rust.
 #[pymodule]
 fn minimappers2(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Sequence>()?;
     m.add_class::<Aligner>()?;
     m.add_class::<AlignerBuilder>()?;
+    m.add_class::<MappingChunks>()?;
     Ok(())
 }
 
@@ -406,17 +455,17 @@ impl Mappings {
             })
             .collect();
 
-        let cigar: Vec<Option<Vec<(u32, u8)>>> = self
+        // List of {len: u32, op: str} structs, one per CIGAR operation
+        let cigar: ListChunked = self
             .alignment
             .iter()
-            .map(|x| match x {
-                Some(y) => match &y.cigar {
-                    Some(z) => Some(z.clone()),
-                    None => None,
-                },
-                None => None,
+            .map(|x| {
+                x.as_ref()
+                    .and_then(|y| y.cigar.as_ref())
+                    .map(|ops| cigar_series(ops))
+                    .transpose()
             })
-            .collect();
+            .collect::<Result<_, PolarsError>>()?;
 
         let cigar_str: Vec<Option<String>> = self
             .alignment
@@ -468,7 +517,7 @@ impl Mappings {
         let mapq = Series::new("mapq".into(), self.mapq);
         let is_primary = Series::new("is_primary".into(), self.is_primary);
         let nm = Series::new("nm".into(), nm);
-        // let cigar = Series::new("cigar", cigar);
+        let cigar = cigar.with_name("cigar".into()).into_series();
         let cigar_str = Series::new("cigar_str".into(), cigar_str);
         let md = Series::new("md".into(), md);
         let cs = Series::new("cs".into(), cs);
@@ -488,7 +537,7 @@ impl Mappings {
             mapq.into(),
             is_primary.into(),
             nm.into(),
-            // cigar,
+            cigar.into(),
             cigar_str.into(),
             md.into(),
             cs.into(),
@@ -496,6 +545,24 @@ impl Mappings {
     }
 }
 
+/// CIGAR operations by minimap2 op code
+const CIGAR_OPS: &[u8] = b"MIDNSHP=X";
+
+/// A struct Series of the operations of one CIGAR
+fn cigar_series(ops: &[(u32, u8)]) -> Result<Series, PolarsError> {
+    let len = Series::new(
+        "len".into(),
+        ops.iter().map(|(len, _)| *len).collect::<Vec<u32>>(),
+    );
+    let op = Series::new(
+        "op".into(),
+        ops.iter()
+            .map(|(_, op)| (CIGAR_OPS.get(*op as usize).copied().unwrap_or(b'?') as char).to_string())
+            .collect::<Vec<String>>(),
+    );
+    Ok(StructChunked::from_series("cigar".into(), ops.len(), [len, op].iter())?.into_series())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::*;
@@ -530,4 +597,23 @@ mod tests {
         // Test to df - need to figure out pyo3 prob
         // let df = mappings.to_df().unwrap();
     }
+
+    #[test]
+    fn test_map_sequences_zero_threads() {
+        let mut aligner = minimap2::Aligner::builder()
+            .map_ont()
+            .with_index("../test_data/MT-human.fa", None)
+            .unwrap();
+        aligner.threads = 0;
+        let fasta = std::fs::read_to_string("../test_data/MT-human.fa").unwrap();
+        let mt: String = fasta.lines().skip(1).collect();
+        let seqs = vec![Sequence {
+            id: "read".to_string(),
+            sequence: mt.as_bytes()[1000..1400].to_vec(),
+        }];
+
+        let mappings = map_sequences_on_threads(&aligner, &seqs).unwrap();
+        assert_eq!(mappings.query_name, vec![Some("read".to_string())]);
+        assert_eq!(mappings.target_name, vec![Some("MT_human".to_string())]);
+    }
 }
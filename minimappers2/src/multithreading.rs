@@ -1,6 +0,0 @@
-// Whatever is needed to help with multithreading...
-
-pub enum WorkQueue<T> {
-    Work(T),
-    Done,
-}
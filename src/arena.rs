@@ -0,0 +1,493 @@
+//! Batch mapping with arena-allocated results
+//!
+//! [`Aligner::map`] returns owned [`Mapping`]s, each carrying its own CIGAR, cs and MD
+//! allocations. When mapping millions of reads that is a lot of allocator traffic for data
+//! that usually lives for a single batch. [`Aligner::map_batch`] instead writes the results of
+//! a whole batch into a few growable buffers owned by a [`MappingBatch`] and hands out
+//! [`MappingRef`]s borrowing from it. Target names are borrowed from the index.
+//!
+//! # Lifetimes
+//!
+//! Owned [`Mapping`]s copy everything they need, so they may outlive the [`Aligner`] and its
+//! index. The borrowed types can not: a [`MappingBatch`] borrows the aligner and a
+//! [`MappingRef`] borrows its batch, so using either after what it borrows is dropped is a
+//! compile error rather than a dangling pointer into the index.
+//!
+//! ```compile_fail
+//! # use minimap2::*;
+//! let aligner = Aligner::builder().with_index("test_data/genome.fa", None).unwrap();
+//! let batch = aligner.map_batch([(&b"read"[..], &b"ACGT"[..])], false, false).unwrap();
+//! drop(aligner);
+//! batch.len();
+//! ```
+//!
+//! ```compile_fail
+//! # use minimap2::*;
+//! let aligner = Aligner::builder().with_index("test_data/genome.fa", None).unwrap();
+//! let batch = aligner.map_batch([(&b"read"[..], &b"ACGT"[..])], false, false).unwrap();
+//! let first = batch.iter().next();
+//! drop(batch);
+//! first.map(|m| m.target_name.len());
+//! ```
+//!
+//! Copy a [`MappingRef`] out with [`MappingRef::to_mapping`] to keep it past its batch.
+
+use std::ffi::CString;
+use std::fmt::Write;
+use std::num::NonZeroI32;
+use std::ops::Range;
+
+use libc::c_void;
+use minimap2_sys::*;
+
+use crate::paf::cigar_op_char;
+use crate::{
+    check_hits, free_hits, with_buf, Aligner, Alignment, AlignmentType, Built, DpSkipped, Mapping,
+    Strand,
+};
+
+/// Results of mapping a batch of queries, see [`Aligner::map_batch`]
+pub struct MappingBatch<'a> {
+    aligner: &'a Aligner<Built>,
+    /// Query names, CIGAR strings, cs and MD tags
+    text: String,
+    cigar: Vec<(u32, u8)>,
+    /// Target sequences, if requested with `with_target_seq()`
+    target_seqs: Vec<u8>,
+    queries: Vec<QueryEntry>,
+    records: Vec<Record>,
+}
+
+struct QueryEntry {
+    name: Range<usize>,
+    len: i32,
+    records: Range<usize>,
+}
+
+struct Record {
+    query: usize,
+    rid: usize,
+    target_start: i32,
+    target_end: i32,
+    query_start: i32,
+    query_end: i32,
+    strand: Strand,
+    match_len: i32,
+    block_len: i32,
+    mapq: u32,
+    is_primary: bool,
+    is_supplementary: bool,
+    alignment_type: AlignmentType,
+    alignment: Option<AlignmentRecord>,
+    target_seq: Option<Range<usize>>,
+}
+
+struct AlignmentRecord {
+    nm: i32,
+    alignment_score: i32,
+    dp_skipped: DpSkipped,
+    cigar: Option<Range<usize>>,
+    cigar_str: Option<Range<usize>>,
+    cs: Option<Range<usize>>,
+    md: Option<Range<usize>>,
+}
+
+/// A [`Mapping`] borrowing its strings from a [`MappingBatch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappingRef<'a> {
+    pub query_name: &'a str,
+    pub query_len: Option<NonZeroI32>,
+    pub query_start: i32,
+    pub query_end: i32,
+    pub strand: Strand,
+    pub target_name: &'a str,
+    pub target_len: i32,
+    pub target_start: i32,
+    pub target_end: i32,
+    pub match_len: i32,
+    pub block_len: i32,
+    pub mapq: u32,
+    pub is_primary: bool,
+    pub is_supplementary: bool,
+    pub alignment_type: AlignmentType,
+    pub alignment: Option<AlignmentRef<'a>>,
+    pub target_seq: Option<&'a [u8]>,
+}
+
+/// An [`Alignment`] borrowing its strings from a [`MappingBatch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignmentRef<'a> {
+    pub nm: i32,
+    pub cigar: Option<&'a [(u32, u8)]>,
+    pub cigar_str: Option<&'a str>,
+    pub md: Option<&'a str>,
+    pub cs: Option<&'a str>,
+    pub alignment_score: Option<i32>,
+    pub dp_skipped: DpSkipped,
+}
+
+impl MappingRef<'_> {
+    /// Copy into an owned [`Mapping`]
+    pub fn to_mapping(&self) -> Mapping {
+        Mapping {
+            query_name: Some(std::sync::Arc::new(self.query_name.to_string())),
+            query_len: self.query_len,
+            query_start: self.query_start,
+            query_end: self.query_end,
+            strand: self.strand,
+            target_name: Some(std::sync::Arc::new(self.target_name.to_string())),
+            target_len: self.target_len,
+            target_start: self.target_start,
+            target_end: self.target_end,
+            match_len: self.match_len,
+            block_len: self.block_len,
+            mapq: self.mapq,
+            is_primary: self.is_primary,
+            is_supplementary: self.is_supplementary,
+            alignment_type: self.alignment_type,
+            alignment: self.alignment.as_ref().map(|aln| Alignment {
+                nm: aln.nm,
+                cigar: aln.cigar.map(|x| x.to_vec()),
+                cigar_str: aln.cigar_str.map(|x| x.to_string()),
+                md: aln.md.map(|x| x.to_string()),
+                cs: aln.cs.map(|x| x.to_string()),
+                alignment_score: aln.alignment_score,
+                dp_skipped: aln.dp_skipped,
+            }),
+            target_seq: self.target_seq.map(|x| x.to_vec()),
+        }
+    }
+}
+
+impl MappingBatch<'_> {
+    /// Number of queries in the batch
+    pub fn n_queries(&self) -> usize {
+        self.queries.len()
+    }
+
+    /// Total number of mappings in the batch
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Iterate over all mappings, in query order
+    pub fn iter(&self) -> impl Iterator<Item = MappingRef<'_>> + '_ {
+        self.records.iter().map(move |r| self.get_ref(r))
+    }
+
+    /// Iterate over the mappings of the `i`th query of the batch
+    pub fn query_mappings(&self, i: usize) -> impl Iterator<Item = MappingRef<'_>> + '_ {
+        self.records[self.queries[i].records.clone()]
+            .iter()
+            .map(move |r| self.get_ref(r))
+    }
+
+    fn get_ref(&self, record: &Record) -> MappingRef<'_> {
+        let query = &self.queries[record.query];
+        let text = |range: &Option<Range<usize>>| range.clone().map(|r| &self.text[r]);
+
+        let idx: &mm_idx_t = self.aligner.idx.as_ref().unwrap();
+        let seq = unsafe { &*idx.seq.add(record.rid) };
+        let target_name = self.aligner.target_names().names[record.rid].as_str();
+
+        MappingRef {
+            query_name: &self.text[query.name.clone()],
+            query_len: NonZeroI32::new(query.len),
+            query_start: record.query_start,
+            query_end: record.query_end,
+            strand: record.strand,
+            target_name,
+            target_len: seq.len as i32,
+            target_start: record.target_start,
+            target_end: record.target_end,
+            match_len: record.match_len,
+            block_len: record.block_len,
+            mapq: record.mapq,
+            is_primary: record.is_primary,
+            is_supplementary: record.is_supplementary,
+            alignment_type: record.alignment_type,
+            alignment: record.alignment.as_ref().map(|aln| AlignmentRef {
+                nm: aln.nm,
+                cigar: aln.cigar.clone().map(|r| &self.cigar[r]),
+                cigar_str: text(&aln.cigar_str),
+                md: text(&aln.md),
+                cs: text(&aln.cs),
+                alignment_score: Some(aln.alignment_score),
+                dp_skipped: aln.dp_skipped,
+            }),
+            target_seq: record.target_seq.clone().map(|r| &self.target_seqs[r]),
+        }
+    }
+}
+
+impl Aligner<Built> {
+    /// Map a batch of queries, storing all results in a single [`MappingBatch`]
+    ///
+    /// Gives the same mappings as calling [`Aligner::map`] on each query, but the strings of
+    /// all mappings share a handful of buffers that are dropped with the batch. Queries are
+    /// given as (name, sequence) pairs; empty sequences get no mappings. As with `map`, names
+    /// seed minimap2's tie-breaking and skip self hits when mapping all-vs-all.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .with_cigar()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+    /// let batch = aligner
+    ///     .map_batch([(&b"read1"[..], &seq[..]), (&b"read2"[..], &b""[..])], true, false)
+    ///     .unwrap();
+    /// assert_eq!(batch.n_queries(), 2);
+    /// let hit = batch.query_mappings(0).next().unwrap();
+    /// assert_eq!((hit.query_name, hit.target_name, hit.target_start), ("read1", "chr1", 180));
+    /// assert_eq!(batch.query_mappings(1).count(), 0);
+    /// ```
+    pub fn map_batch<'s, I>(
+        &self,
+        queries: I,
+        cs: bool,
+        md: bool,
+    ) -> Result<MappingBatch<'_>, &'static str>
+    where
+        I: IntoIterator<Item = (&'s [u8], &'s [u8])>,
+    {
+        if !self.has_index() {
+            return Err("No index");
+        }
+
+        let mut batch = MappingBatch {
+            aligner: self,
+            text: String::new(),
+            cigar: Vec::new(),
+            target_seqs: Vec::new(),
+            queries: Vec::new(),
+            records: Vec::new(),
+        };
+
+        let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+
+        // One output buffer for all cs/MD strings of the batch
+        let mut tags = TagBuffer::new();
+
+        with_buf(|buf| -> Result<(), &'static str> {
+            for (name, seq) in queries {
+                let name = name.strip_suffix(b"\0").unwrap_or(name);
+                let name_start = batch.text.len();
+                batch.text.push_str(&String::from_utf8_lossy(name));
+                batch.queries.push(QueryEntry {
+                    name: name_start..batch.text.len(),
+                    len: seq.len() as i32,
+                    records: batch.records.len()..batch.records.len(),
+                });
+
+                if seq.is_empty() {
+                    continue;
+                }
+                self.check_query_len(seq.len())?;
+                let oriented = self.orient_query(seq);
+                let seq = &oriented[..];
+                let qname = CString::new(name).map_err(|_| "Invalid query name")?;
+
+                let mut n_regs: i32 = 0;
+                let regs = unsafe {
+                    mm_map(
+                        idx,
+                        seq.len() as i32,
+                        seq.as_ptr() as *const libc::c_char,
+                        &mut n_regs,
+                        buf.get_buf(),
+                        &self.mapopt,
+                        qname.as_ptr(),
+                    )
+                };
+                if let Err(e) = unsafe { check_hits(idx, regs, n_regs) } {
+                    unsafe { free_hits(regs, n_regs) };
+                    return Err(e);
+                }
+
+                let n_regs = unsafe { self.filter_overlaps(regs, n_regs, seq.len() as i32) };
+                let n_regs = unsafe { self.filter_secondary(regs, n_regs, self.mapopt.flag) };
+                let (n_kept, _) =
+                    unsafe { self.limit_hits(regs, n_regs, Some(&batch.text[name_start..])) };
+                unsafe { self.sort_hits(regs, n_kept) };
+
+                for i in 0..n_kept {
+                    unsafe {
+                        let reg = &*regs.add(i);
+
+                        let alignment = if reg.p.is_null() {
+                            None
+                        } else {
+                            let p = &*reg.p;
+                            let mut aln = AlignmentRecord {
+                                nm: reg.blen - reg.mlen + p.n_ambi() as i32,
+                                alignment_score: p.dp_score,
+                                dp_skipped: DpSkipped::from_split(reg.split()),
+                                cigar: None,
+                                cigar_str: None,
+                                cs: None,
+                                md: None,
+                            };
+
+                            if p.n_cigar > 0 {
+                                self.push_cigar(&mut batch, reg, seq.len() as i32, &mut aln);
+                            }
+
+                            if cs {
+                                let tag = tags.gen_cs(idx, reg, seq, true);
+                                aln.cs = Some(push_tag(&mut batch.text, tag));
+                            }
+
+                            if md {
+                                let tag = tags.gen_md(idx, reg, seq);
+                                aln.md = Some(push_tag(&mut batch.text, tag));
+                            }
+
+                            Some(aln)
+                        };
+
+                        let target_seq = if self.output_target_seq {
+                            push_target_seq(&mut batch.target_seqs, idx, reg)
+                        } else {
+                            None
+                        };
+
+                        batch.records.push(Record {
+                            query: batch.queries.len() - 1,
+                            rid: reg.rid as usize,
+                            target_start: reg.rs,
+                            target_end: reg.re,
+                            query_start: reg.qs,
+                            query_end: reg.qe,
+                            strand: if reg.rev() == 0 {
+                                Strand::Forward
+                            } else {
+                                Strand::Reverse
+                            },
+                            match_len: reg.mlen,
+                            block_len: reg.blen,
+                            mapq: reg.mapq(),
+                            is_primary: reg.parent == reg.id && (reg.sam_pri() > 0),
+                            is_supplementary: (reg.parent == reg.id) && (reg.sam_pri() == 0),
+                            alignment_type: AlignmentType::of_reg(reg),
+                            alignment,
+                            target_seq,
+                        });
+
+                        libc::free(reg.p as *mut c_void);
+                    }
+
+                    // Recalibration works on owned Mappings, so only pay for one if it is used
+                    if let Some(recalibrate) = &self.mapq_recalibration {
+                        let mapq =
+                            recalibrate(&batch.get_ref(batch.records.last().unwrap()).to_mapping());
+                        batch.records.last_mut().unwrap().mapq = mapq;
+                    }
+                    if let Some(max_mapq) = self.max_mapq {
+                        let record = batch.records.last_mut().unwrap();
+                        record.mapq = record.mapq.min(max_mapq);
+                    }
+                }
+
+                unsafe { libc::free(regs as *mut c_void) };
+
+                batch.queries.last_mut().unwrap().records.end = batch.records.len();
+            }
+            Ok(())
+        })?;
+
+        Ok(batch)
+    }
+
+    /// Write the CIGAR of `reg`, with clipping as in [`Aligner::map`], to the batch buffers
+    unsafe fn push_cigar(
+        &self,
+        batch: &mut MappingBatch,
+        reg: &mm_reg1_t,
+        qlen: i32,
+        aln: &mut AlignmentRecord,
+    ) {
+        let p = &*reg.p;
+        let (clip_len0, clip_len1) = if reg.rev() != 0 {
+            (qlen - reg.qe, reg.qs)
+        } else {
+            (reg.qs, qlen - reg.qe)
+        };
+
+        let (clip_char, clip_op) = self.clip_op(reg);
+
+        let cigar_start = batch.cigar.len();
+        if clip_len0 > 0 && self.cigar_clipping {
+            batch.cigar.push((clip_len0 as u32, clip_op));
+        }
+        batch.cigar.extend(
+            p.cigar
+                .as_slice(p.n_cigar as usize)
+                .iter()
+                .map(|c| (c >> 4, (c & 0xf) as u8)),
+        );
+        if clip_len1 > 0 && self.cigar_clipping {
+            batch.cigar.push((clip_len1 as u32, clip_op));
+        }
+        aln.cigar = Some(cigar_start..batch.cigar.len());
+
+        // Writing to a String can not fail
+        let text_start = batch.text.len();
+        if clip_len0 > 0 {
+            write!(batch.text, "{}{}", clip_len0, clip_char).unwrap();
+        }
+        for (len, op) in p
+            .cigar
+            .as_slice(p.n_cigar as usize)
+            .iter()
+            .map(|c| (c >> 4, (c & 0xf) as u8))
+        {
+            write!(batch.text, "{}{}", len, cigar_op_char(op)).unwrap();
+        }
+        if clip_len1 > 0 {
+            write!(batch.text, "{}{}", clip_len1, clip_char).unwrap();
+        }
+        aln.cigar_str = Some(text_start..batch.text.len());
+    }
+}
+
+/// Append a generated tag to `text`, returning its range
+fn push_tag(text: &mut String, tag: &[u8]) -> Range<usize> {
+    let start = text.len();
+    text.push_str(&String::from_utf8_lossy(tag));
+    start..text.len()
+}
+
+/// Append the aligned target sequence of `reg` to `seqs` as uppercase ACGTN, returning its range
+unsafe fn push_target_seq(
+    seqs: &mut Vec<u8>,
+    idx: &mm_idx_t,
+    reg: &mm_reg1_t,
+) -> Option<Range<usize>> {
+    if idx.S.is_null() {
+        return None;
+    }
+
+    let start = seqs.len();
+    seqs.resize(start + (reg.re - reg.rs) as usize, 0);
+    let n = mm_idx_getseq(
+        idx,
+        reg.rid as u32,
+        reg.rs as u32,
+        reg.re as u32,
+        seqs[start..].as_mut_ptr(),
+    );
+    if n < 0 {
+        seqs.truncate(start);
+        return None;
+    }
+    seqs.truncate(start + n as usize);
+
+    crate::seq::decode_in_place(&mut seqs[start..]);
+    Some(start..seqs.len())
+}
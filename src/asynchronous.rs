@@ -0,0 +1,239 @@
+//! Mapping from async code
+//!
+//! minimap2 blocks the calling thread while it maps, so async services should not call
+//! [`Aligner::map`] from a task. An [`AsyncAligner`] owns a pool of worker threads, each
+//! keeping its own minimap2 thread buffer, and [`AsyncAligner::map_async`] hands a query to
+//! them and returns a future of its mappings. The futures only use `std::task`, so they can be
+//! awaited from any executor (tokio, async-std, smol) without a runtime dependency.
+//!
+//! ```no_run
+//! use minimap2::asynchronous::AsyncAligner;
+//! use minimap2::Aligner;
+//!
+//! # async fn handler() -> Result<(), &'static str> {
+//! let aligner = Aligner::builder()
+//!     .map_ont()
+//!     .with_index("reference.fa", None)?;
+//! let aligner = AsyncAligner::new(aligner, 4);
+//! let mappings = aligner
+//!     .map_async(b"ACGT".to_vec(), false, false, None, None, Some(b"read1".to_vec()))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+
+use crate::{Aligner, Built, Mapping};
+
+type Job = Box<dyn FnOnce(&Aligner<Built>) + Send>;
+
+/// An aligner with a pool of worker threads, mapping queries for async callers
+///
+/// Dropping it lets the workers finish the queries already submitted, then joins them.
+pub struct AsyncAligner {
+    aligner: Arc<Aligner<Built>>,
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl AsyncAligner {
+    /// Map with `aligner` on `threads` worker threads
+    pub fn new(aligner: Aligner<Built>, threads: usize) -> Self {
+        Self::from_arc(Arc::new(aligner), threads)
+    }
+
+    /// Map with an aligner shared with other users on `threads` worker threads
+    pub fn from_arc(aligner: Arc<Aligner<Built>>, threads: usize) -> Self {
+        assert!(threads > 0, "threads must be positive");
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|i| {
+                let aligner = Arc::clone(&aligner);
+                let receiver = Arc::clone(&receiver);
+                std::thread::Builder::new()
+                    .name(format!("minimap2-async-{i}"))
+                    .spawn(move || work(&aligner, &receiver))
+                    .expect("Unable to spawn worker thread")
+            })
+            .collect();
+        AsyncAligner {
+            aligner,
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// The aligner the workers map with
+    pub fn aligner(&self) -> &Arc<Aligner<Built>> {
+        &self.aligner
+    }
+
+    /// Number of worker threads
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// [`Aligner::map`] on a worker thread, resolving to its result
+    ///
+    /// Queries are mapped in the order they are submitted, as workers become free. A query
+    /// whose mapping panics resolves to an error rather than taking a worker down.
+    pub fn map_async(
+        &self,
+        seq: Vec<u8>,
+        cs: bool,
+        md: bool,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<Vec<u64>>,
+        query_name: Option<Vec<u8>>,
+    ) -> MapFuture {
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let job_slot = Arc::clone(&slot);
+        let job: Job = Box::new(move |aligner| {
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                aligner.map(
+                    &seq,
+                    cs,
+                    md,
+                    max_frag_len,
+                    extra_flags.as_deref(),
+                    query_name.as_deref(),
+                )
+            }))
+            .unwrap_or(Err("Mapping panicked"));
+            job_slot.lock().unwrap().complete(result);
+        });
+
+        let sent = self.sender.as_ref().is_some_and(|s| s.send(job).is_ok());
+        if !sent {
+            slot.lock().unwrap().complete(Err("No worker threads"));
+        }
+        MapFuture { slot }
+    }
+}
+
+impl Drop for AsyncAligner {
+    fn drop(&mut self) {
+        // Workers stop once the channel is closed and empty
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(aligner: &Aligner<Built>, receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // The lock is only held while waiting, not while mapping
+        let job = receiver.lock().unwrap().recv();
+        match job {
+            Ok(job) => job(aligner),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Where a worker leaves the result of a query for its future
+#[derive(Default)]
+struct Slot {
+    result: Option<Result<Vec<Mapping>, &'static str>>,
+    waker: Option<Waker>,
+}
+
+impl Slot {
+    fn complete(&mut self, result: Result<Vec<Mapping>, &'static str>) {
+        self.result = Some(result);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The mappings of a query submitted with [`AsyncAligner::map_async`]
+///
+/// The query is mapped whether or not the future is polled.
+pub struct MapFuture {
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl Future for MapFuture {
+    type Output = Result<Vec<Mapping>, &'static str>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mt_seq;
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor: poll on the current thread, parking until woken
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_map_async() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let mt = mt_seq();
+        let queries: Vec<Vec<u8>> = (0..8)
+            .map(|i| mt[i * 1000..i * 1000 + 500].to_vec())
+            .collect();
+        let expected: Vec<Vec<Mapping>> = queries
+            .iter()
+            .map(|seq| aligner.map(seq, false, false, None, None, None).unwrap())
+            .collect();
+
+        let aligner = AsyncAligner::new(aligner, 3);
+        assert_eq!(aligner.threads(), 3);
+        let futures: Vec<MapFuture> = queries
+            .into_iter()
+            .map(|seq| aligner.map_async(seq, false, false, None, None, None))
+            .collect();
+        let mappings: Vec<Vec<Mapping>> = futures
+            .into_iter()
+            .map(|future| block_on(future).unwrap())
+            .collect();
+        assert_eq!(mappings, expected);
+
+        let empty = block_on(aligner.map_async(Vec::new(), false, false, None, None, None));
+        assert_eq!(empty, Err("Sequence is empty"));
+    }
+}
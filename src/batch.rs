@@ -0,0 +1,215 @@
+//! Multithreaded batch driver for mapping files and in-memory queries
+//!
+//! Reads queries (from a FASTA/FASTQ file or an iterator) on their own thread, maps batches of
+//! them on a pool of worker threads and hands the results back to the calling thread in input
+//! order. Channels are bounded, so at most a few batches per thread are held in memory at any
+//! time.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "map-file")]
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::Mutex;
+
+#[cfg(feature = "map-file")]
+use needletail::parse_fastx_file;
+
+use crate::{Aligner, Built};
+
+/// Number of reads sent to a worker at a time
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// An owned query, read from an input file or given by the caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryRecord {
+    pub id: Vec<u8>,
+    pub seq: Vec<u8>,
+    pub qual: Option<Vec<u8>>,
+}
+
+/// Check that the input file exists and is not empty
+#[cfg(feature = "map-file")]
+pub(crate) fn check_input<P: AsRef<Path>>(file: P) -> Result<(), &'static str> {
+    let file = file.as_ref();
+    if !file.exists() {
+        return Err("File does not exist");
+    }
+
+    if std::fs::metadata(file).map(|m| m.len()).unwrap_or(0) == 0 {
+        return Err("File is empty");
+    }
+
+    Ok(())
+}
+
+/// Map every record of `file` with `map_fn` on `threads` worker threads and pass the results,
+/// in input order, to `sink` on the calling thread.
+///
+/// If `sink` returns an error the remaining reads are skipped and the error is returned.
+#[cfg(feature = "map-file")]
+pub(crate) fn run<P, T, M, S>(
+    aligner: &Aligner<Built>,
+    file: P,
+    threads: usize,
+    map_fn: M,
+    sink: S,
+) -> Result<(), &'static str>
+where
+    P: AsRef<Path>,
+    T: Send,
+    M: Fn(&Aligner<Built>, &QueryRecord) -> T + Sync,
+    S: FnMut(T) -> Result<(), &'static str>,
+{
+    if !aligner.has_index() {
+        return Err("No index");
+    }
+
+    let before = aligner.filtered_reads();
+    run_records(aligner, read_file(aligner, file)?, threads, map_fn, sink)?;
+    aligner.report_filtered_reads(before);
+    Ok(())
+}
+
+/// The records of a FASTA/FASTQ file, read as they are pulled, without those the read filter
+/// of `aligner` skips
+#[cfg(feature = "map-file")]
+pub(crate) fn read_file<'a, P: AsRef<Path>>(
+    aligner: &'a Aligner<Built>,
+    file: P,
+) -> Result<impl Iterator<Item = Result<QueryRecord, &'static str>> + Send + 'a, &'static str> {
+    check_input(&file)?;
+
+    let mut reader = parse_fastx_file(file.as_ref()).map_err(|_| "Unable to read FASTA/X file")?;
+    Ok(std::iter::from_fn(move || loop {
+        let record = match reader.next()? {
+            Ok(record) => record,
+            Err(_) => {
+                return Some(Err(
+                    "Error reading record in FASTA/X files. Please confirm integrity.",
+                ))
+            }
+        };
+        let seq = record.seq();
+        if aligner.keep_read(record.id(), &seq, record.qual()) {
+            return Some(Ok(QueryRecord {
+                id: record.id().to_vec(),
+                seq: seq.to_vec(),
+                qual: record.qual().map(|q| q.to_vec()),
+            }));
+        }
+    }))
+}
+
+/// Map `records` with `map_fn` on `threads` worker threads and pass the results, in input
+/// order, to `sink` on the calling thread. Records are pulled on a thread of their own; the
+/// first error stops reading and is returned once the records before it are handled.
+/// Records can be owned [`QueryRecord`]s or borrow from the caller.
+///
+/// If `sink` returns an error the remaining records are skipped and the error is returned.
+pub(crate) fn run_records<R, Rec, T, M, S>(
+    aligner: &Aligner<Built>,
+    records: R,
+    threads: usize,
+    map_fn: M,
+    mut sink: S,
+) -> Result<(), &'static str>
+where
+    R: Iterator<Item = Result<Rec, &'static str>> + Send,
+    Rec: Send,
+    T: Send,
+    M: Fn(&Aligner<Built>, &Rec) -> T + Sync,
+    S: FnMut(T) -> Result<(), &'static str>,
+{
+    if !aligner.has_index() {
+        return Err("No index");
+    }
+
+    let threads = threads.max(1);
+
+    let (work_tx, work_rx) = sync_channel::<(usize, Vec<Rec>)>(threads * 2);
+    let (result_tx, result_rx) = sync_channel::<(usize, Vec<T>)>(threads * 2);
+    let work_rx = Mutex::new(work_rx);
+    let abort = AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            let work_rx = &work_rx;
+            let result_tx = result_tx.clone();
+            let map_fn = &map_fn;
+            let abort = &abort;
+
+            s.spawn(move || loop {
+                let work = work_rx.lock().unwrap().recv();
+                let (i, batch) = match work {
+                    Ok(work) => work,
+                    Err(_) => break,
+                };
+
+                // Keep draining the work queue so the reader never blocks
+                if abort.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                let results = batch.iter().map(|rec| map_fn(aligner, rec)).collect();
+                if result_tx.send((i, results)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_tx);
+
+        let abort_ = &abort;
+        let reader = s.spawn(move || -> Result<(), &'static str> {
+            let mut batch = Vec::with_capacity(DEFAULT_BATCH_SIZE);
+            let mut n_batches = 0;
+
+            for record in records {
+                if abort_.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+
+                batch.push(record?);
+
+                if batch.len() == DEFAULT_BATCH_SIZE {
+                    let full =
+                        std::mem::replace(&mut batch, Vec::with_capacity(DEFAULT_BATCH_SIZE));
+                    if work_tx.send((n_batches, full)).is_err() {
+                        return Ok(());
+                    }
+                    n_batches += 1;
+                }
+            }
+
+            if !batch.is_empty() {
+                let _ = work_tx.send((n_batches, batch));
+            }
+
+            Ok(())
+        });
+
+        // Results can arrive out of order, so hold on to them until it is their turn
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let mut result = Ok(());
+
+        for (i, batch) in result_rx.iter() {
+            if result.is_err() {
+                continue;
+            }
+
+            pending.insert(i, batch);
+            while let Some(batch) = pending.remove(&next) {
+                next += 1;
+                if let Err(e) = batch.into_iter().try_for_each(&mut sink) {
+                    abort.store(true, Ordering::Relaxed);
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        let read_result = reader.join().expect("Reader thread panicked");
+        result.and(read_result)
+    })
+}
@@ -0,0 +1,319 @@
+//! Random access to the bgzip-compressed FASTA an index was built from
+//!
+//! minimap2 reads gzip and bgzip FASTA alike, but keeps no record of where each contig lies
+//! in the file. [`BgzfFasta::scan`] records the BGZF virtual offsets of the contigs in one
+//! pass, after which any range can be read back by decompressing only from the nearest
+//! checkpoint. Attached to an aligner with
+//! [`with_sequence_source`](crate::Aligner::with_sequence_source), it serves
+//! `fetch_subseq` and `with_target_seq` for indexes built without sequences (`MM_I_NO_SEQ`),
+//! keeping the reference out of memory.
+//!
+//! ```no_run
+//! use minimap2::bgzf::BgzfFasta;
+//! use minimap2::*;
+//!
+//! let fasta = BgzfFasta::scan("reference.fa.gz").unwrap();
+//! let mut builder = Aligner::builder().map_ont().with_sequence_source(fasta);
+//! builder.idxopt.flag |= ffi::MM_I_NO_SEQ as i16;
+//! let aligner = builder.with_index("reference.fa.gz", None).unwrap();
+//! let seq = aligner.fetch_subseq("chr1", 1000..2000, Strand::Forward).unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use rust_htslib::htslib;
+
+use crate::{FetchError, SequenceSource};
+
+/// Bases between checkpoints, bounding how much is decompressed to reach a position
+const CHECKPOINT_INTERVAL: u64 = 1 << 16;
+
+/// Location of a contig in a bgzip-compressed FASTA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BgzfContig {
+    /// Name up to the first whitespace, as minimap2 names targets
+    pub name: String,
+    pub len: u64,
+    /// Virtual offset of the header line
+    pub header_offset: u64,
+    /// Base positions and virtual offsets of line starts, about every 64 kb of sequence.
+    /// The first is the start of the sequence.
+    pub checkpoints: Vec<(u64, u64)>,
+}
+
+/// Contig locations of a bgzip-compressed FASTA, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct BgzfFasta {
+    path: PathBuf,
+    contigs: Vec<BgzfContig>,
+    ids: HashMap<String, usize>,
+}
+
+// Open BGZF file, closed on drop
+struct Bgzf(*mut htslib::BGZF);
+
+impl Bgzf {
+    fn open(path: &Path) -> Result<Self, &'static str> {
+        let path =
+            CString::new(path.to_str().ok_or("Invalid path")?).map_err(|_| "Invalid path")?;
+        let fp = unsafe { htslib::bgzf_open(path.as_ptr(), c"r".as_ptr()) };
+        if fp.is_null() {
+            return Err("Unable to open BGZF file");
+        }
+        if unsafe { (*fp).is_compressed() } == 0 || unsafe { (*fp).is_gzip() } != 0 {
+            unsafe { htslib::bgzf_close(fp) };
+            return Err("File is not bgzip-compressed");
+        }
+        Ok(Bgzf(fp))
+    }
+
+    // bgzf_tell is a macro
+    fn tell(&self) -> u64 {
+        unsafe { ((*self.0).block_address << 16 | ((*self.0).block_offset as i64 & 0xffff)) as u64 }
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<(), &'static str> {
+        if unsafe { htslib::bgzf_seek(self.0, offset as i64, libc::SEEK_SET) } < 0 {
+            return Err("Unable to seek in BGZF file");
+        }
+        Ok(())
+    }
+
+    /// Read a line without its line ending, returning false at the end of the file
+    fn read_line(&mut self, line: &mut htslib::kstring_t) -> Result<bool, &'static str> {
+        line.l = 0;
+        match unsafe { htslib::bgzf_getline(self.0, b'\n' as i32, line) } {
+            -1 => Ok(false),
+            n if n < -1 => Err("Unable to read BGZF file"),
+            _ => Ok(true),
+        }
+    }
+}
+
+impl Drop for Bgzf {
+    fn drop(&mut self) {
+        unsafe { htslib::bgzf_close(self.0) };
+    }
+}
+
+// Line buffer for bgzf_getline, allocated by htslib
+struct Line(htslib::kstring_t);
+
+impl Line {
+    fn new() -> Self {
+        Line(htslib::kstring_t {
+            l: 0,
+            m: 0,
+            s: std::ptr::null_mut(),
+        })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        if self.0.s.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.0.s as *const u8, self.0.l) }
+    }
+}
+
+impl Drop for Line {
+    fn drop(&mut self) {
+        unsafe { libc::free(self.0.s as *mut libc::c_void) };
+    }
+}
+
+impl BgzfFasta {
+    /// Read through a bgzip-compressed FASTA, recording where each contig lies
+    pub fn scan<P: AsRef<Path>>(path: P) -> Result<Self, &'static str> {
+        let path = path.as_ref().to_path_buf();
+        let mut fp = Bgzf::open(&path)?;
+        let mut line = Line::new();
+        let mut contigs: Vec<BgzfContig> = Vec::new();
+
+        loop {
+            let offset = fp.tell();
+            if !fp.read_line(&mut line.0)? {
+                break;
+            }
+            let bytes = line.as_bytes();
+
+            if let Some(header) = bytes.strip_prefix(b">") {
+                let name = header
+                    .split(|b| b.is_ascii_whitespace())
+                    .next()
+                    .unwrap_or_default();
+                contigs.push(BgzfContig {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    len: 0,
+                    header_offset: offset,
+                    checkpoints: vec![(0, fp.tell())],
+                });
+                continue;
+            }
+
+            let contig = contigs.last_mut().ok_or("Not a FASTA file")?;
+            let last = contig.checkpoints.last().unwrap().0;
+            if contig.len - last >= CHECKPOINT_INTERVAL {
+                contig.checkpoints.push((contig.len, offset));
+            }
+            contig.len += bytes.len() as u64;
+        }
+
+        let ids = contigs
+            .iter()
+            .enumerate()
+            .map(|(i, contig)| (contig.name.clone(), i))
+            .collect();
+        Ok(BgzfFasta { path, contigs, ids })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn contigs(&self) -> &[BgzfContig] {
+        &self.contigs
+    }
+
+    pub fn contig(&self, name: &str) -> Option<&BgzfContig> {
+        self.ids.get(name).map(|&i| &self.contigs[i])
+    }
+
+    /// Read `range` (0-based, end-exclusive) of a contig as written in the file
+    pub fn fetch(&self, name: &str, range: Range<u64>) -> Result<Vec<u8>, FetchError> {
+        let contig = self.contig(name).ok_or(FetchError::TargetNotFound)?;
+        if range.start > range.end || range.end > contig.len {
+            return Err(FetchError::OutOfRange {
+                start: range.start as u32,
+                end: range.end as u32,
+                target_len: contig.len as u32,
+            });
+        }
+
+        let mut seq = Vec::with_capacity((range.end - range.start) as usize);
+        if range.is_empty() {
+            return Ok(seq);
+        }
+
+        let checkpoint = contig.checkpoints[contig
+            .checkpoints
+            .partition_point(|(pos, _)| *pos <= range.start)
+            - 1];
+        let unreadable = |_| FetchError::NoSequence;
+        let mut fp = Bgzf::open(&self.path).map_err(unreadable)?;
+        fp.seek(checkpoint.1).map_err(unreadable)?;
+
+        let mut line = Line::new();
+        let mut pos = checkpoint.0;
+        while pos < range.end {
+            if !fp.read_line(&mut line.0).map_err(unreadable)? {
+                return Err(FetchError::NoSequence);
+            }
+            let bytes = line.as_bytes();
+            let end = pos + bytes.len() as u64;
+            if end > range.start {
+                let from = range.start.saturating_sub(pos) as usize;
+                let to = (range.end.min(end) - pos) as usize;
+                seq.extend_from_slice(&bytes[from..to]);
+            }
+            pos = end;
+        }
+
+        Ok(seq)
+    }
+}
+
+impl SequenceSource for BgzfFasta {
+    fn fetch(&self, name: &str, range: Range<u32>) -> Result<Vec<u8>, FetchError> {
+        BgzfFasta::fetch(self, name, range.start as u64..range.end as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random_seq, Aligner, Strand};
+    use minimap2_sys::MM_I_NO_SEQ;
+    use std::io::Write;
+
+    fn write_bgzf(path: &Path, fasta: &[u8]) {
+        let mut writer = rust_htslib::bgzf::Writer::from_path(path).unwrap();
+        writer.write_all(fasta).unwrap();
+    }
+
+    #[test]
+    fn test_bgzf_fasta() {
+        // Many short lines so that the contigs span several BGZF blocks and checkpoints
+        let mut fasta = Vec::new();
+        let mut contigs = Vec::new();
+        for (seed, (name, len)) in [("ctg1", 150_000), ("ctg2", 1_000), ("ctg3", 70_001)]
+            .into_iter()
+            .enumerate()
+        {
+            let seq = random_seq(len, seed as u64);
+            fasta.extend_from_slice(format!(">{} description\n", name).as_bytes());
+            for line in seq.chunks(60) {
+                fasta.extend_from_slice(line);
+                fasta.push(b'\n');
+            }
+            contigs.push((name, seq));
+        }
+
+        let path = std::env::temp_dir().join("minimap2_test_bgzf_fasta.fa.gz");
+        write_bgzf(&path, &fasta);
+
+        let bgzf = BgzfFasta::scan(&path).unwrap();
+        let lens: Vec<_> = bgzf
+            .contigs()
+            .iter()
+            .map(|c| (c.name.as_str(), c.len))
+            .collect();
+        assert_eq!(
+            lens,
+            vec![("ctg1", 150_000), ("ctg2", 1_000), ("ctg3", 70_001)]
+        );
+        assert_eq!(bgzf.contig("ctg1").unwrap().checkpoints.len(), 3);
+
+        for (name, seq) in &contigs {
+            for range in [
+                0..10,
+                59..61,
+                65_530..65_600,
+                1000..1000,
+                seq.len() - 5..seq.len(),
+            ] {
+                if range.end > seq.len() {
+                    continue;
+                }
+                assert_eq!(
+                    bgzf.fetch(name, range.start as u64..range.end as u64)
+                        .unwrap(),
+                    seq[range]
+                );
+            }
+        }
+        assert_eq!(bgzf.fetch("ctg4", 0..1), Err(FetchError::TargetNotFound));
+        assert!(bgzf.fetch("ctg2", 0..1001).is_err());
+
+        // An index without sequences fetches from the compressed FASTA
+        let mut builder = Aligner::builder().map_ont().with_sequence_source(bgzf);
+        builder.idxopt.flag |= MM_I_NO_SEQ as i16;
+        let aligner = builder.with_index(&path, None).unwrap();
+        assert_eq!(aligner.n_seq(), 3);
+        let (name, seq) = &contigs[2];
+        assert_eq!(
+            aligner
+                .fetch_subseq(name, 1000..2000, Strand::Reverse)
+                .unwrap(),
+            crate::seq::revcomp(&seq[1000..2000])
+        );
+
+        std::fs::remove_file(&path).unwrap();
+
+        // Plain text is rejected
+        assert!(BgzfFasta::scan("test_data/genome.fa").is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Reuse of mapping results across identical queries
+//!
+//! Amplicon panels sequence the same few hundred amplicons over and over, so many reads are
+//! exact copies of each other. minimap2 seeds, chains and aligns every query from scratch
+//! inside `mm_map`, and its API has no way to share seeding between queries that only share a
+//! prefix or suffix (e.g. a primer): the minimizers of a query are sketched and looked up in one
+//! call. A [`QueryCache`] instead remembers the mappings of recent queries, so that a query
+//! identical to one of them is answered by [`Aligner::map_cached`] without calling minimap2.
+//!
+//! A cache belongs to one thread and one aligner: give each worker its own. It is emptied
+//! when used with an aligner whose index or settings differ from the last one. The query name is
+//! not part of the key, so do not use a cache for all-vs-all mapping, where minimap2 skips the
+//! self-hit of a query by name. minimap2 also breaks ties between equally good hits with a hash
+//! of the query name, so a cached read with several equally good hits may get another primary
+//! mapping and MAPQ than `map` would give it; build the aligner with
+//! [`with_name_hashing(false)`](crate::Aligner::with_name_hashing) for the results of `map`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Weak};
+
+use minimap2_sys::MmIdx;
+
+use crate::overlap::AvaFilter;
+use crate::{
+    Aligner, Built, HitOrder, Mapping, MapqRecalibration, QueryOrientation, SequenceSource,
+};
+
+/// Number of queries a [`QueryCache::default`] remembers
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// Mappings of the most recent distinct queries mapped through it
+///
+/// ```
+/// # use minimap2::*;
+/// use minimap2::cache::QueryCache;
+/// let aligner = Aligner::builder()
+///     .map_ont()
+///     .with_index("test_data/MT-human.fa", None)
+///     .unwrap();
+/// let fasta = std::fs::read_to_string("test_data/MT-human.fa").unwrap();
+/// let amplicon: String = fasta.lines().skip(1).collect::<String>()[1000..1400].to_string();
+///
+/// let mut cache = QueryCache::new(1000);
+/// for name in ["read1", "read2", "read3"] {
+///     let seq = amplicon.as_bytes();
+///     let mappings = aligner
+///         .map_cached(&mut cache, seq, false, false, None, None, Some(name.as_bytes()))
+///         .unwrap();
+///     assert_eq!(mappings[0].query_name.as_deref().unwrap(), name);
+/// }
+/// assert_eq!((cache.hits(), cache.misses()), (2, 1));
+/// ```
+#[derive(Debug)]
+pub struct QueryCache {
+    capacity: usize,
+    entries: HashMap<u64, Entry>,
+    // Keys in order of insertion, to evict the oldest
+    order: VecDeque<u64>,
+    // Index and settings of the aligner the entries were mapped with
+    settings: Option<Settings>,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    seq: Vec<u8>,
+    options: Options,
+    mappings: Vec<Mapping>,
+}
+
+/// The arguments of `map` other than the query that change its result
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Options {
+    cs: bool,
+    md: bool,
+    max_frag_len: Option<usize>,
+    extra_flags: Option<Vec<u64>>,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl QueryCache {
+    /// Cache of the mappings of up to `capacity` distinct queries. The oldest query is
+    /// forgotten first.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        QueryCache {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            settings: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Queries answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Queries mapped by minimap2
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Number of queries remembered
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget all queries, keeping the hit and miss counts
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn get(&self, key: u64, seq: &[u8], options: &Options) -> Option<&[Mapping]> {
+        self.entries
+            .get(&key)
+            .filter(|entry| entry.seq == seq && entry.options == *options)
+            .map(|entry| entry.mappings.as_slice())
+    }
+
+    fn insert(&mut self, key: u64, seq: &[u8], options: Options, mappings: Vec<Mapping>) {
+        let entry = Entry {
+            seq: seq.to_vec(),
+            options,
+            mappings,
+        };
+        // A query whose key collides with another replaces it
+        if self.entries.insert(key, entry).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// What, besides the arguments of `map`, decides the mappings of an aligner
+#[derive(PartialEq)]
+struct Settings {
+    // Held weakly so that a new index can not reuse the address of a dropped one
+    index: Option<ById<Weak<MmIdx>>>,
+    // mm_mapopt_t has float fields and no PartialEq, so it is compared in its Debug form
+    mapopt: String,
+    cigar_clipping: bool,
+    hard_clipping: bool,
+    max_mapq: Option<u32>,
+    mapq_recalibration: Option<ById<MapqRecalibration>>,
+    output_target_seq: bool,
+    sequence_source: Option<ById<Arc<dyn SequenceSource>>>,
+    max_hits: Option<usize>,
+    truncate_hits: bool,
+    query_orientation: QueryOrientation,
+    hit_order: HitOrder,
+    ava_filter: Option<AvaFilter>,
+}
+
+/// An index or hook, compared by identity
+struct ById<T>(T);
+
+impl<T: ?Sized> PartialEq for ById<Weak<T>> {
+    fn eq(&self, other: &Self) -> bool {
+        Weak::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: ?Sized> PartialEq for ById<Arc<T>> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Settings").finish_non_exhaustive()
+    }
+}
+
+impl Aligner<Built> {
+    fn cache_settings(&self) -> Settings {
+        // No `..`: a new field does not compile until it is either compared or ignored here
+        let Aligner {
+            idxopt: _,
+            mapopt,
+            threads: _,
+            idx,
+            idx_reader: _,
+            cigar_clipping,
+            hard_clipping,
+            max_mapq,
+            mapq_recalibration,
+            output_target_seq,
+            max_hits,
+            truncate_hits,
+            quality_mismatch: _,
+            comment_passthrough: _,
+            tag_passthrough: _,
+            query_orientation,
+            hit_order,
+            record_tags: _,
+            duplicate_names: _,
+            read_filter: _,
+            read_ids: _,
+            read_filter_counts: _,
+            ava_filter,
+            sequence_source,
+            index_files: _,
+            target_names: _,
+            state: _,
+        } = self;
+        Settings {
+            index: idx.as_ref().map(|idx| ById(Arc::downgrade(idx))),
+            mapopt: format!("{:?}", mapopt),
+            cigar_clipping: *cigar_clipping,
+            hard_clipping: *hard_clipping,
+            max_mapq: *max_mapq,
+            mapq_recalibration: mapq_recalibration.clone().map(ById),
+            output_target_seq: *output_target_seq,
+            sequence_source: sequence_source.clone().map(ById),
+            max_hits: *max_hits,
+            truncate_hits: *truncate_hits,
+            query_orientation: *query_orientation,
+            hit_order: *hit_order,
+            ava_filter: ava_filter.clone(),
+        }
+    }
+}
+
+fn hash_query(seq: &[u8], options: &Options) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seq.hash(&mut hasher);
+    options.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Aligner<Built> {
+    /// Like [`map`](Aligner::map), but returns the mappings of an identical earlier query
+    /// (same sequence and arguments) from `cache` instead of mapping it again, with the query
+    /// name replaced by `query_name`. Unless name hashing is turned off, ties between equally
+    /// good hits may be broken differently than `map` would for this name. See the
+    /// [module documentation](crate::cache).
+    ///
+    /// Errors are not cached. The cache is emptied if it was filled by an aligner with another
+    /// index or other settings, including a clone whose `mapopt` was changed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn map_cached(
+        &self,
+        cache: &mut QueryCache,
+        seq: &[u8],
+        cs: bool,
+        md: bool,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<&[u64]>,
+        query_name: Option<&[u8]>,
+    ) -> Result<Vec<Mapping>, &'static str> {
+        let settings = self.cache_settings();
+        if cache.settings.as_ref() != Some(&settings) {
+            cache.clear();
+            cache.settings = Some(settings);
+        }
+
+        let options = Options {
+            cs,
+            md,
+            max_frag_len,
+            extra_flags: extra_flags.map(|flags| flags.to_vec()),
+        };
+        let key = hash_query(seq, &options);
+        if let Some(mappings) = cache.get(key, seq, &options) {
+            cache.hits += 1;
+            let name = query_name.map(|name| {
+                let name = name.strip_suffix(b"\0").unwrap_or(name);
+                Arc::new(String::from_utf8_lossy(name).into_owned())
+            });
+            return Ok(mappings
+                .iter()
+                .map(|mapping| Mapping {
+                    query_name: name.clone(),
+                    ..mapping.clone()
+                })
+                .collect());
+        }
+
+        let mappings = self.map(seq, cs, md, max_frag_len, extra_flags, query_name)?;
+        cache.misses += 1;
+        cache.insert(key, seq, options, mappings.clone());
+        Ok(mappings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mt_seq;
+
+    fn amplicons() -> Vec<Vec<u8>> {
+        let mt = mt_seq();
+        (0..2)
+            .map(|i| mt[1000 + i * 2000..1400 + i * 2000].to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_map_cached() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let amplicons = amplicons();
+        let mut cache = QueryCache::new(2);
+        let map = |aligner: &Aligner<Built>, cache: &mut QueryCache, i: usize, cs, name: &str| {
+            let name = Some(name.as_bytes());
+            aligner
+                .map_cached(cache, &amplicons[i], cs, false, None, None, name)
+                .unwrap()
+        };
+
+        let mapped = aligner
+            .map(&amplicons[0], true, false, None, None, Some(b"a"))
+            .unwrap();
+        let first = map(&aligner, &mut cache, 0, true, "a");
+        let second = map(&aligner, &mut cache, 0, true, "b\0");
+        assert_eq!(first, mapped);
+        assert_eq!(second[0].query_name.as_deref().unwrap(), "b");
+        assert_eq!(
+            second[0].alignment.as_ref().unwrap().cs,
+            mapped[0].alignment.as_ref().unwrap().cs
+        );
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // Other arguments are another query
+        map(&aligner, &mut cache, 0, false, "c");
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+        // The oldest query is forgotten first
+        map(&aligner, &mut cache, 1, true, "c");
+        assert_eq!(cache.len(), 2);
+        map(&aligner, &mut cache, 0, true, "c");
+        assert_eq!((cache.hits(), cache.misses()), (1, 4));
+
+        // Another aligner starts over
+        let other = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        map(&other, &mut cache, 1, true, "c");
+        assert_eq!((cache.len(), cache.misses()), (1, 5));
+
+        // And so does a clone sharing the index with other settings
+        let mut strict = other.clone();
+        map(&strict, &mut cache, 1, true, "c");
+        assert_eq!(cache.hits(), 2);
+        strict.mapopt.min_dp_max = 100_000;
+        assert!(map(&strict, &mut cache, 1, true, "c").is_empty());
+        assert_eq!((cache.len(), cache.misses()), (1, 6));
+        let capped = other.clone().with_max_mapq(1);
+        assert!(map(&capped, &mut cache, 1, true, "c")
+            .iter()
+            .all(|m| m.mapq <= 1));
+        assert_eq!(cache.misses(), 7);
+    }
+}
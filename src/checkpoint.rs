@@ -0,0 +1,194 @@
+//! Checkpoints for long file mapping runs
+//!
+//! A [`Checkpoint`] names a small progress file that the resumable file drivers
+//! ([`Aligner::map_file_to_paf_resumable`](crate::Aligner::map_file_to_paf_resumable) and
+//! [`Aligner::map_file_to_sink_resumable`](crate::Aligner::map_file_to_sink_resumable)) rewrite
+//! every so many seconds or reads, once the output written so far is flushed. A job that is
+//! killed (e.g. at the end of its slot on a cluster) and started again with the same checkpoint
+//! skips the reads it already wrote instead of mapping the whole file again. The progress file
+//! is removed when the run completes.
+//!
+//! The file holds one `key<TAB>value` line per field of [`Progress`].
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Time between checkpoints of [`Checkpoint::new`]
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Progress of a run, as saved in a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Reads written to the output, in input order
+    pub reads: u64,
+    /// Size of the input file, to catch a checkpoint reused with another input
+    pub input_len: u64,
+    /// Size of the output file when the checkpoint was saved, if the driver writes the file
+    /// itself
+    pub output_len: Option<u64>,
+}
+
+/// Where and how often to save the progress of a run
+///
+/// ```
+/// use minimap2::checkpoint::Checkpoint;
+/// use std::time::Duration;
+///
+/// // Save every 5 minutes, or every million reads if that comes first
+/// let checkpoint = Checkpoint::new("reads.paf.ckpt")
+///     .every(Duration::from_secs(300))
+///     .every_reads(1_000_000);
+/// assert_eq!(checkpoint.load(), Ok(None));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    path: PathBuf,
+    interval: Duration,
+    reads: Option<u64>,
+}
+
+impl Checkpoint {
+    /// Checkpoint to `path` every [`DEFAULT_INTERVAL`]
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Checkpoint {
+            path: path.as_ref().to_path_buf(),
+            interval: DEFAULT_INTERVAL,
+            reads: None,
+        }
+    }
+
+    /// Save the progress once `interval` has passed since the last checkpoint
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Also save the progress every `reads` reads
+    pub fn every_reads(mut self, reads: u64) -> Self {
+        assert!(reads > 0, "reads must be positive");
+        self.reads = Some(reads);
+        self
+    }
+
+    /// The progress file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The progress saved by an interrupted run, or None if there is no checkpoint
+    pub fn load(&self) -> Result<Option<Progress>, &'static str> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(_) => return Err("Unable to read checkpoint"),
+        };
+
+        let (mut reads, mut input_len, mut output_len) = (None, None, None);
+        for line in text.lines() {
+            let (key, value) = line.split_once('\t').ok_or("Invalid checkpoint")?;
+            let value = value.parse::<u64>().map_err(|_| "Invalid checkpoint")?;
+            match key {
+                "reads" => reads = Some(value),
+                "input_len" => input_len = Some(value),
+                "output_len" => output_len = Some(value),
+                _ => return Err("Invalid checkpoint"),
+            }
+        }
+
+        Ok(Some(Progress {
+            reads: reads.ok_or("Invalid checkpoint")?,
+            input_len: input_len.ok_or("Invalid checkpoint")?,
+            output_len,
+        }))
+    }
+
+    /// The progress to start a run over `input` from: that of the checkpoint if there is one,
+    /// otherwise none
+    pub(crate) fn resume(&self, input: &Path) -> Result<Progress, &'static str> {
+        let input_len = std::fs::metadata(input)
+            .map_err(|_| "File does not exist")?
+            .len();
+        match self.load()? {
+            Some(progress) if progress.input_len != input_len => {
+                Err("Checkpoint does not match the input file")
+            }
+            Some(progress) => Ok(progress),
+            None => Ok(Progress {
+                reads: 0,
+                input_len,
+                output_len: None,
+            }),
+        }
+    }
+
+    /// Whether to save again, `reads` reads and `elapsed` time after the last checkpoint
+    pub(crate) fn due(&self, reads: u64, elapsed: Duration) -> bool {
+        elapsed >= self.interval || self.reads.is_some_and(|every| reads >= every)
+    }
+
+    /// Save `progress`, replacing the previous checkpoint only once the new one is written
+    pub(crate) fn save(&self, progress: &Progress) -> Result<(), &'static str> {
+        let mut text = format!(
+            "reads\t{}\ninput_len\t{}\n",
+            progress.reads, progress.input_len
+        );
+        if let Some(output_len) = progress.output_len {
+            text.push_str(&format!("output_len\t{output_len}\n"));
+        }
+
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, text)
+            .and_then(|_| std::fs::rename(&temp, &self.path))
+            .map_err(|_| "Unable to write checkpoint")
+    }
+
+    /// Remove the checkpoint of a completed run
+    pub(crate) fn remove(&self) -> Result<(), &'static str> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err("Unable to remove checkpoint")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint() {
+        let path = std::env::temp_dir().join("minimap2_test_checkpoint.ckpt");
+        let checkpoint = Checkpoint::new(&path).every_reads(10);
+        checkpoint.remove().unwrap();
+        assert_eq!(checkpoint.load(), Ok(None));
+
+        let progress = Progress {
+            reads: 12,
+            input_len: 3000,
+            output_len: Some(456),
+        };
+        checkpoint.save(&progress).unwrap();
+        assert_eq!(checkpoint.load(), Ok(Some(progress)));
+
+        let progress = Progress {
+            output_len: None,
+            ..progress
+        };
+        checkpoint.save(&progress).unwrap();
+        assert_eq!(checkpoint.load(), Ok(Some(progress)));
+
+        // Either trigger is enough
+        assert!(!checkpoint.due(9, Duration::from_secs(1)));
+        assert!(checkpoint.due(10, Duration::from_secs(1)));
+        assert!(checkpoint.due(1, DEFAULT_INTERVAL));
+
+        std::fs::write(&path, "reads\tmany\n").unwrap();
+        assert_eq!(checkpoint.load(), Err("Invalid checkpoint"));
+
+        checkpoint.remove().unwrap();
+        assert_eq!(checkpoint.load(), Ok(None));
+    }
+}
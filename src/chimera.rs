@@ -0,0 +1,218 @@
+//! Detection of chimeric reads
+//!
+//! Reads from two molecules ligated together, typically through an adapter in nanopore
+//! libraries, map as two confident alignments to unrelated loci that abut on the query.
+//! [`find_chimeric_joins`] flags these joins from the primary and supplementary mappings of a
+//! read, telling them apart from split alignments around deletions or other local
+//! rearrangements.
+
+use crate::Mapping;
+
+/// Thresholds for [`find_chimeric_joins`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChimeraParams {
+    /// Minimum MAPQ of both alignments of a join
+    pub min_mapq: u32,
+    /// Minimum aligned query length of both alignments of a join
+    pub min_query_len: i32,
+    /// Maximum unaligned query sequence between the two alignments, e.g. an adapter
+    pub max_query_gap: i32,
+    /// Maximum query sequence shared by the two alignments
+    pub max_query_overlap: i32,
+    /// Minimum distance on the target between alignments on the same target for them to be
+    /// distinct loci; closer pairs are taken for a local rearrangement
+    pub min_target_distance: i32,
+}
+
+impl Default for ChimeraParams {
+    fn default() -> Self {
+        ChimeraParams {
+            min_mapq: 20,
+            min_query_len: 100,
+            max_query_gap: 100,
+            max_query_overlap: 50,
+            min_target_distance: 10_000,
+        }
+    }
+}
+
+/// How the two loci of a chimeric join relate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChimeraKind {
+    /// The alignments are on different targets
+    InterTarget,
+    /// The alignments are on the same target, at least `min_target_distance` apart
+    Distant,
+    /// The alignments overlap on the target on opposite strands: the read continues
+    /// into the reverse complement of itself, as when both strands of a molecule are
+    /// sequenced through an adapter
+    FoldBack,
+}
+
+/// A likely chimeric join between two mappings of a read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChimericJoin {
+    /// Index of the mapping before the join on the query, in the slice given
+    pub first: usize,
+    /// Index of the mapping after the join on the query, in the slice given
+    pub second: usize,
+    /// Query position of the join, in the middle of any gap or overlap
+    pub query_pos: i32,
+    /// Unaligned query bases between the alignments, negative if they overlap
+    pub query_gap: i32,
+    pub kind: ChimeraKind,
+}
+
+/// Find the joins between loci that suggest a read is chimeric
+///
+/// `mappings` are the mappings of one read, as returned by [`Aligner::map`](crate::Aligner::map).
+/// Secondary mappings are ignored. Consecutive alignments on the query that both pass
+/// `params` and abut within `max_query_gap`/`max_query_overlap` are reported if they are on
+/// different targets, far apart on the same target, or fold back onto the same locus.
+///
+/// ```
+/// use minimap2::chimera::{find_chimeric_joins, ChimeraKind, ChimeraParams};
+/// use minimap2::*;
+///
+/// let aligner = Aligner::builder()
+///     .map_ont()
+///     .with_index("test_data/genome.fa", None)
+///     .unwrap();
+/// let mut read = aligner.fetch_subseq("chr1", 200..1000, Strand::Forward).unwrap();
+/// read.extend(aligner.fetch_subseq("chr2", 0..400, Strand::Reverse).unwrap());
+/// let mappings = aligner.map(&read, false, false, None, None, None).unwrap();
+///
+/// let joins = find_chimeric_joins(&mappings, &ChimeraParams::default());
+/// assert_eq!(joins.len(), 1);
+/// assert_eq!(joins[0].kind, ChimeraKind::InterTarget);
+/// assert!((joins[0].query_pos - 800).abs() < 20);
+/// ```
+pub fn find_chimeric_joins(mappings: &[Mapping], params: &ChimeraParams) -> Vec<ChimericJoin> {
+    let mut confident: Vec<usize> = (0..mappings.len())
+        .filter(|&i| {
+            let m = &mappings[i];
+            (m.is_primary || m.is_supplementary)
+                && m.mapq >= params.min_mapq
+                && m.query_end - m.query_start >= params.min_query_len
+        })
+        .collect();
+    confident.sort_by_key(|&i| (mappings[i].query_start, mappings[i].query_end));
+
+    let mut joins = Vec::new();
+    for pair in confident.windows(2) {
+        let (a, b) = (&mappings[pair[0]], &mappings[pair[1]]);
+        let query_gap = b.query_start - a.query_end;
+        if query_gap > params.max_query_gap || -query_gap > params.max_query_overlap {
+            continue;
+        }
+
+        if let Some(kind) = join_kind(a, b, params) {
+            joins.push(ChimericJoin {
+                first: pair[0],
+                second: pair[1],
+                query_pos: a.query_end + query_gap / 2,
+                query_gap,
+                kind,
+            });
+        }
+    }
+    joins
+}
+
+/// Whether any join in the mappings of a read suggests it is chimeric, see
+/// [`find_chimeric_joins`]
+pub fn is_chimeric(mappings: &[Mapping], params: &ChimeraParams) -> bool {
+    !find_chimeric_joins(mappings, params).is_empty()
+}
+
+fn join_kind(a: &Mapping, b: &Mapping, params: &ChimeraParams) -> Option<ChimeraKind> {
+    if a.target_name != b.target_name {
+        return Some(ChimeraKind::InterTarget);
+    }
+
+    // Negative when the alignments overlap on the target
+    let distance = a.target_start.max(b.target_start) - a.target_end.min(b.target_end);
+    if a.strand != b.strand && distance < 0 {
+        return Some(ChimeraKind::FoldBack);
+    }
+    if distance >= params.min_target_distance {
+        return Some(ChimeraKind::Distant);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Strand;
+    use std::sync::Arc;
+
+    fn mapping(
+        target: &str,
+        query: (i32, i32),
+        target_range: (i32, i32),
+        strand: Strand,
+    ) -> Mapping {
+        Mapping {
+            query_start: query.0,
+            query_end: query.1,
+            target_name: Some(Arc::new(target.to_string())),
+            target_start: target_range.0,
+            target_end: target_range.1,
+            strand,
+            mapq: 60,
+            is_supplementary: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_chimeric_joins() {
+        let params = ChimeraParams::default();
+        let mut primary = mapping("chr1", (0, 1000), (5000, 6000), Strand::Forward);
+        primary.is_primary = true;
+        primary.is_supplementary = false;
+
+        // Split around a 2 kb deletion: not chimeric
+        let deletion = mapping("chr1", (1000, 2000), (8000, 9000), Strand::Forward);
+        assert!(!is_chimeric(&[primary.clone(), deletion], &params));
+
+        // Same target, far away
+        let distant = mapping("chr1", (1030, 2000), (50_000, 50_970), Strand::Forward);
+        let joins = find_chimeric_joins(&[distant, primary.clone()], &params);
+        assert_eq!(
+            joins,
+            vec![ChimericJoin {
+                first: 1,
+                second: 0,
+                query_pos: 1015,
+                query_gap: 30,
+                kind: ChimeraKind::Distant,
+            }]
+        );
+
+        // Template and complement of the same molecule
+        let foldback = mapping("chr1", (980, 1900), (5100, 6000), Strand::Reverse);
+        let joins = find_chimeric_joins(&[primary.clone(), foldback.clone()], &params);
+        assert_eq!(joins[0].kind, ChimeraKind::FoldBack);
+        assert_eq!(joins[0].query_gap, -20);
+
+        // Too far apart on the query, or too low MAPQ, or secondary
+        let far = mapping("chr2", (1500, 2500), (0, 1000), Strand::Forward);
+        assert!(!is_chimeric(&[primary.clone(), far], &params));
+        let mut low_mapq = mapping("chr2", (1000, 2000), (0, 1000), Strand::Forward);
+        low_mapq.mapq = 3;
+        assert!(!is_chimeric(&[primary.clone(), low_mapq], &params));
+        let mut secondary = foldback;
+        secondary.is_supplementary = false;
+        assert!(!is_chimeric(&[primary.clone(), secondary], &params));
+
+        // Joins are found between consecutive alignments only
+        let second = mapping("chr2", (1000, 2000), (0, 1000), Strand::Forward);
+        let third = mapping("chr3", (2000, 3000), (0, 1000), Strand::Reverse);
+        let joins = find_chimeric_joins(&[third, primary, second], &params);
+        let pairs: Vec<_> = joins.iter().map(|j| (j.first, j.second)).collect();
+        assert_eq!(pairs, vec![(1, 2), (2, 0)]);
+        assert!(joins.iter().all(|j| j.kind == ChimeraKind::InterTarget));
+    }
+}
@@ -0,0 +1,545 @@
+//! Comparing two sets of mappings of the same reads
+//!
+//! Validating a crate upgrade, a change of options or a move from a minimap2 CLI pipeline
+//! comes down to mapping the same reads twice and looking at what changed. [`compare`] takes
+//! the mappings of both runs (left and right), pairs them up by read name and reports, for
+//! each read, differences in the number of mappings and, for its primary mapping, in position,
+//! CIGAR, MAPQ and the NM, AS, MD and cs tags, along with summary counts.
+//!
+//! [`compare_paf`] and, with the `htslib` feature, [`compare_sam`] read the two sets from
+//! files, so crate output can be checked against `minimap2 -c` or `minimap2 -a` directly.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::paf::{cigar_op_char, read_paf};
+use crate::{Mapping, Strand};
+
+/// Tolerances of [`compare`]; the defaults require exact agreement
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompareOptions {
+    /// Largest difference in target start or end still counted as the same position
+    pub position_tolerance: i32,
+    /// Largest difference in MAPQ still counted as the same
+    pub mapq_tolerance: u32,
+}
+
+/// Where a primary mapping lies on its target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locus {
+    pub target_name: String,
+    pub strand: Strand,
+    pub target_start: i32,
+    pub target_end: i32,
+}
+
+impl Locus {
+    fn of(mapping: &Mapping) -> Self {
+        Locus {
+            target_name: mapping
+                .target_name
+                .as_ref()
+                .map_or("*".to_string(), |name| name.to_string()),
+            strand: mapping.strand,
+            target_start: mapping.target_start,
+            target_end: mapping.target_end,
+        }
+    }
+}
+
+impl fmt::Display for Locus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}({})",
+            self.target_name, self.target_start, self.target_end, self.strand
+        )
+    }
+}
+
+/// A difference between the left and right mappings of a read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Mapped in the left set only
+    OnlyLeft,
+    /// Mapped in the right set only
+    OnlyRight,
+    /// Different numbers of mappings, counting secondary and supplementary ones
+    Count { left: usize, right: usize },
+    /// Primary mappings to a different target or strand, or further apart than the tolerance
+    Position { left: Locus, right: Locus },
+    /// Primary mappings with different CIGARs, compared without clipping when both have one
+    Cigar { left: String, right: String },
+    /// Primary mappings with MAPQs further apart than the tolerance
+    Mapq { left: u32, right: u32 },
+    /// Primary mappings with a different NM, AS, MD or cs tag, compared when both have it
+    Tag {
+        name: &'static str,
+        left: String,
+        right: String,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::OnlyLeft => write!(f, "only mapped in left"),
+            Difference::OnlyRight => write!(f, "only mapped in right"),
+            Difference::Count { left, right } => write!(f, "mappings {} != {}", left, right),
+            Difference::Position { left, right } => write!(f, "position {} != {}", left, right),
+            Difference::Cigar { left, right } => write!(f, "CIGAR {} != {}", left, right),
+            Difference::Mapq { left, right } => write!(f, "MAPQ {} != {}", left, right),
+            Difference::Tag { name, left, right } => write!(f, "{} {} != {}", name, left, right),
+        }
+    }
+}
+
+/// The differences found for one read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadDiff {
+    pub query_name: String,
+    pub differences: Vec<Difference>,
+}
+
+/// Counts over all reads; a read with several kinds of difference is counted under each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompareSummary {
+    /// Reads mapped in either set
+    pub n_reads: usize,
+    /// Reads mapped in both sets
+    pub n_both: usize,
+    /// Reads mapped in both sets without any difference
+    pub n_identical: usize,
+    pub n_only_left: usize,
+    pub n_only_right: usize,
+    pub n_count: usize,
+    pub n_position: usize,
+    pub n_cigar: usize,
+    pub n_mapq: usize,
+    pub n_tag: usize,
+    /// Sum of the absolute MAPQ differences of the primary mappings of reads in both sets
+    pub mapq_diff_sum: u64,
+}
+
+impl CompareSummary {
+    /// Mean absolute MAPQ difference over reads mapped in both sets, or 0 if there are none
+    pub fn mean_mapq_diff(&self) -> f64 {
+        if self.n_both > 0 {
+            self.mapq_diff_sum as f64 / self.n_both as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Result of [`compare`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MappingComparison {
+    /// Reads with at least one difference, sorted by name
+    pub reads: Vec<ReadDiff>,
+    pub summary: CompareSummary,
+}
+
+impl MappingComparison {
+    /// Whether both sets map the same reads the same way
+    pub fn is_identical(&self) -> bool {
+        self.reads.is_empty()
+    }
+}
+
+impl fmt::Display for MappingComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = &self.summary;
+        writeln!(
+            f,
+            "{} reads, {} in both, {} identical, {} only left, {} only right",
+            s.n_reads, s.n_both, s.n_identical, s.n_only_left, s.n_only_right
+        )?;
+        write!(
+            f,
+            "differences: {} count, {} position, {} CIGAR, {} MAPQ (mean {:.2}), {} tag",
+            s.n_count,
+            s.n_position,
+            s.n_cigar,
+            s.n_mapq,
+            s.mean_mapq_diff(),
+            s.n_tag
+        )?;
+        for read in &self.reads {
+            for difference in &read.differences {
+                write!(f, "\n{}: {}", read.query_name, difference)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Group mappings by read name; mappings without a query name are skipped
+fn by_read<I: IntoIterator<Item = Mapping>>(mappings: I) -> HashMap<String, Vec<Mapping>> {
+    let mut reads: HashMap<String, Vec<Mapping>> = HashMap::new();
+    for mapping in mappings {
+        if let Some(name) = &mapping.query_name {
+            reads.entry(name.to_string()).or_default().push(mapping);
+        }
+    }
+    reads
+}
+
+/// The primary mapping of a read, or its first mapping if none is marked primary
+fn primary(mappings: &[Mapping]) -> &Mapping {
+    mappings
+        .iter()
+        .find(|m| m.is_primary && !m.is_supplementary)
+        .unwrap_or(&mappings[0])
+}
+
+fn cigar_string(cigar: &[(u32, u8)]) -> String {
+    cigar
+        .iter()
+        .filter(|(_, op)| *op != 4 && *op != 5)
+        .map(|(len, op)| format!("{}{}", len, cigar_op_char(*op)))
+        .collect()
+}
+
+fn compare_primary(left: &Mapping, right: &Mapping, options: &CompareOptions) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    let (l, r) = (Locus::of(left), Locus::of(right));
+    if l.target_name != r.target_name
+        || l.strand != r.strand
+        || (l.target_start - r.target_start).abs() > options.position_tolerance
+        || (l.target_end - r.target_end).abs() > options.position_tolerance
+    {
+        differences.push(Difference::Position { left: l, right: r });
+    }
+
+    let (l, r) = (left.alignment.as_ref(), right.alignment.as_ref());
+    if let (Some(l), Some(r)) = (
+        l.and_then(|a| a.cigar.as_deref()),
+        r.and_then(|a| a.cigar.as_deref()),
+    ) {
+        let (l, r) = (cigar_string(l), cigar_string(r));
+        if l != r {
+            differences.push(Difference::Cigar { left: l, right: r });
+        }
+    }
+
+    if left.mapq.abs_diff(right.mapq) > options.mapq_tolerance {
+        differences.push(Difference::Mapq {
+            left: left.mapq,
+            right: right.mapq,
+        });
+    }
+
+    if let (Some(l), Some(r)) = (l, r) {
+        let tags = [
+            ("NM", Some(l.nm.to_string()), Some(r.nm.to_string())),
+            (
+                "AS",
+                l.alignment_score.map(|x| x.to_string()),
+                r.alignment_score.map(|x| x.to_string()),
+            ),
+            ("MD", l.md.clone(), r.md.clone()),
+            ("cs", l.cs.clone(), r.cs.clone()),
+        ];
+        for (name, l, r) in tags {
+            if let (Some(l), Some(r)) = (l, r) {
+                if l != r {
+                    differences.push(Difference::Tag {
+                        name,
+                        left: l,
+                        right: r,
+                    });
+                }
+            }
+        }
+    }
+
+    differences
+}
+
+/// Compare the mappings of two runs over the same reads, paired up by query name
+///
+/// Reads only appear in a set if they have at least one mapping there. Mappings without a
+/// query name are ignored.
+///
+/// ```
+/// # use minimap2::*;
+/// use minimap2::compare::{compare, CompareOptions, Difference};
+///
+/// let aligner = Aligner::builder()
+///     .map_ont()
+///     .with_index("test_data/genome.fa", None)
+///     .unwrap();
+/// let read = aligner.target_seq(0).unwrap()[100..400].to_vec();
+/// let left = aligner.map(&read, false, false, None, None, Some(b"read")).unwrap();
+///
+/// let mut right = left.clone();
+/// right[0].mapq = 10;
+/// let comparison = compare(left, right, &CompareOptions::default());
+/// assert_eq!(comparison.summary.n_mapq, 1);
+/// assert!(matches!(comparison.reads[0].differences[0], Difference::Mapq { right: 10, .. }));
+/// println!("{}", comparison);
+/// ```
+pub fn compare<L, R>(left: L, right: R, options: &CompareOptions) -> MappingComparison
+where
+    L: IntoIterator<Item = Mapping>,
+    R: IntoIterator<Item = Mapping>,
+{
+    let left = by_read(left);
+    let mut right = by_read(right);
+
+    let mut comparison = MappingComparison::default();
+    let summary = &mut comparison.summary;
+
+    for (name, left) in left {
+        summary.n_reads += 1;
+        let Some(right) = right.remove(&name) else {
+            summary.n_only_left += 1;
+            comparison.reads.push(ReadDiff {
+                query_name: name,
+                differences: vec![Difference::OnlyLeft],
+            });
+            continue;
+        };
+        summary.n_both += 1;
+
+        let mut differences = Vec::new();
+        if left.len() != right.len() {
+            differences.push(Difference::Count {
+                left: left.len(),
+                right: right.len(),
+            });
+        }
+        let (l, r) = (primary(&left), primary(&right));
+        summary.mapq_diff_sum += l.mapq.abs_diff(r.mapq) as u64;
+        differences.extend(compare_primary(l, r, options));
+
+        if differences.is_empty() {
+            summary.n_identical += 1;
+            continue;
+        }
+        let has = |f: fn(&Difference) -> bool| differences.iter().any(f) as usize;
+        summary.n_count += has(|d| matches!(d, Difference::Count { .. }));
+        summary.n_position += has(|d| matches!(d, Difference::Position { .. }));
+        summary.n_cigar += has(|d| matches!(d, Difference::Cigar { .. }));
+        summary.n_mapq += has(|d| matches!(d, Difference::Mapq { .. }));
+        summary.n_tag += has(|d| matches!(d, Difference::Tag { .. }));
+        comparison.reads.push(ReadDiff {
+            query_name: name,
+            differences,
+        });
+    }
+
+    for name in right.into_keys() {
+        summary.n_reads += 1;
+        summary.n_only_right += 1;
+        comparison.reads.push(ReadDiff {
+            query_name: name,
+            differences: vec![Difference::OnlyRight],
+        });
+    }
+
+    comparison
+        .reads
+        .sort_by(|a, b| a.query_name.cmp(&b.query_name));
+    comparison
+}
+
+fn read_paf_file<P: AsRef<Path>>(path: P) -> Result<Vec<Mapping>, &'static str> {
+    let file = std::fs::File::open(path).map_err(|_| "Unable to open PAF file")?;
+    read_paf(std::io::BufReader::new(file)).collect()
+}
+
+/// [`compare`] the mappings of two PAF files, from this crate or the minimap2 CLI
+///
+/// ```
+/// # use minimap2::*;
+/// use minimap2::compare::{compare_paf, CompareOptions};
+///
+/// let comparison = compare_paf(
+///     "test_data/gDNA_vs_genome.paf",
+///     "test_data/gDNA_vs_genome.paf",
+///     &CompareOptions::default(),
+/// )
+/// .unwrap();
+/// assert!(comparison.is_identical());
+/// assert_eq!(comparison.summary.n_reads, 5);
+/// ```
+pub fn compare_paf<P, Q>(
+    left: P,
+    right: Q,
+    options: &CompareOptions,
+) -> Result<MappingComparison, &'static str>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(compare(
+        read_paf_file(left)?,
+        read_paf_file(right)?,
+        options,
+    ))
+}
+
+#[cfg(feature = "htslib")]
+fn read_sam_file<P: AsRef<Path>>(path: P) -> Result<Vec<Mapping>, &'static str> {
+    use rust_htslib::bam::{self, Read};
+
+    let mut reader = bam::Reader::from_path(path).map_err(|_| "Unable to open SAM/BAM file")?;
+    let header = reader.header().clone();
+    let mut mappings = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|_| "Unable to read SAM/BAM record")?;
+        if record.is_unmapped() {
+            continue;
+        }
+        mappings.push(crate::htslib::record_to_mapping(&record, &header)?);
+    }
+    Ok(mappings)
+}
+
+/// [`compare`] the mappings of two SAM or BAM files, skipping unmapped records
+#[cfg(feature = "htslib")]
+pub fn compare_sam<P, Q>(
+    left: P,
+    right: Q,
+    options: &CompareOptions,
+) -> Result<MappingComparison, &'static str>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(compare(
+        read_sam_file(left)?,
+        read_sam_file(right)?,
+        options,
+    ))
+}
+
+/// [`compare`] the mappings of a PAF file (left) with those of a SAM or BAM file (right)
+#[cfg(feature = "htslib")]
+pub fn compare_paf_sam<P, Q>(
+    paf: P,
+    sam: Q,
+    options: &CompareOptions,
+) -> Result<MappingComparison, &'static str>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(compare(read_paf_file(paf)?, read_sam_file(sam)?, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gdna_paf() -> Vec<Mapping> {
+        read_paf_file("test_data/gDNA_vs_genome.paf").unwrap()
+    }
+
+    #[test]
+    fn test_compare() {
+        let left = gdna_paf();
+        let mut right = gdna_paf();
+
+        // perfect_read.fwd moves, perfect_read.rev loses its MAPQ, imperfect_read.fwd gets
+        // another MD and the secondary mapping of perfect_inv_duplicate goes
+        right[0].target_start += 5;
+        right[0].target_end += 5;
+        right[1].mapq = 50;
+        right[2].alignment.as_mut().unwrap().md = Some("100".to_string());
+        right.remove(4);
+        // split_read is only in the left set, and a new read only in the right one
+        let mut extra = right.pop().unwrap();
+        right.pop();
+        extra.query_name = Some(std::sync::Arc::new("new_read".to_string()));
+        right.push(extra);
+
+        let comparison = compare(left.clone(), right.clone(), &CompareOptions::default());
+        let s = comparison.summary;
+        assert_eq!((s.n_reads, s.n_both, s.n_identical), (6, 4, 0));
+        assert_eq!((s.n_only_left, s.n_only_right), (1, 1));
+        assert_eq!((s.n_count, s.n_position, s.n_mapq, s.n_tag), (1, 1, 1, 1));
+        assert_eq!(s.mapq_diff_sum, 10);
+        assert_eq!(s.mean_mapq_diff(), 2.5);
+
+        let names: Vec<&str> = comparison
+            .reads
+            .iter()
+            .map(|r| r.query_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "imperfect_read.fwd",
+                "new_read",
+                "perfect_inv_duplicate",
+                "perfect_read.fwd",
+                "perfect_read.rev",
+                "split_read"
+            ]
+        );
+        assert_eq!(
+            comparison.reads[0].differences,
+            [Difference::Tag {
+                name: "MD",
+                left: "34G10C6A6G33T6".to_string(),
+                right: "100".to_string(),
+            }]
+        );
+        assert_eq!(
+            comparison.reads[2].differences,
+            [Difference::Count { left: 2, right: 1 }]
+        );
+        assert_eq!(
+            comparison.reads[3].differences[0].to_string(),
+            "position chr1:180-280(+) != chr1:185-285(+)"
+        );
+
+        // Within the tolerances only the count, tag and presence differences remain
+        let options = CompareOptions {
+            position_tolerance: 5,
+            mapq_tolerance: 10,
+        };
+        let comparison = compare(left, right, &options);
+        assert_eq!(comparison.summary.n_identical, 2);
+        assert_eq!(comparison.summary.n_position + comparison.summary.n_mapq, 0);
+    }
+
+    #[test]
+    fn test_compare_cigar() {
+        let line = "read\t100\t0\t100\t+\tchr1\t1720\t180\t280\t100\t100\t60\tNM:i:0\ttp:A:P";
+        let left = Mapping::from_paf(&format!("{line}\tcg:Z:100M")).unwrap();
+        let right = Mapping::from_paf(&format!("{line}\tcg:Z:50M1I49M")).unwrap();
+        let no_cigar = Mapping::from_paf(line).unwrap();
+
+        let comparison = compare([left.clone()], [right], &CompareOptions::default());
+        assert_eq!(
+            comparison.reads[0].differences,
+            [Difference::Cigar {
+                left: "100M".to_string(),
+                right: "50M1I49M".to_string(),
+            }]
+        );
+
+        // A CIGAR on one side only is not a difference
+        assert!(compare([left], [no_cigar], &CompareOptions::default()).is_identical());
+    }
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn test_compare_paf_sam() {
+        // The CLI's PAF and SAM output for the same reads agree
+        let comparison = compare_paf_sam(
+            "test_data/gDNA_vs_genome.paf",
+            "test_data/gDNA_vs_genome.sam",
+            &CompareOptions::default(),
+        )
+        .unwrap();
+        assert!(comparison.is_identical(), "{}", comparison);
+        assert_eq!(comparison.summary.n_both, 5);
+    }
+}
@@ -0,0 +1,293 @@
+//! Checking that two indexes are interchangeable
+//!
+//! Mappings against the same reference made by different processes or nodes can only be merged
+//! if the indexes agree on the targets and on the k-mer parameters. [`Aligner::index_diff`]
+//! compares two loaded indexes and lists every difference.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use minimap2_sys::{mm_idx_t, MM_I_HPC};
+
+use crate::{Aligner, Built};
+
+/// An index parameter that differs between two indexes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamMismatch {
+    /// `k`, `w` or `hpc`
+    pub name: &'static str,
+    pub left: i32,
+    pub right: i32,
+}
+
+/// A target that differs between two indexes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContigMismatch {
+    /// Only in the left index
+    MissingRight { name: String },
+    /// Only in the right index
+    MissingLeft { name: String },
+    /// In both, with different lengths
+    Length { name: String, left: u32, right: u32 },
+    /// In both, with different target ids, so `target_id`s of mappings do not match up
+    Order { name: String, left: u32, right: u32 },
+    /// In both, marked ALT in only one
+    Alt {
+        name: String,
+        left: bool,
+        right: bool,
+    },
+}
+
+/// Differences between two indexes, returned by [`Aligner::index_diff`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexDiff {
+    pub params: Vec<ParamMismatch>,
+    /// In the order of the left index, then targets only in the right index
+    pub contigs: Vec<ContigMismatch>,
+}
+
+impl IndexDiff {
+    /// Whether the indexes have the same targets, in the same order, and parameters, so that
+    /// mappings against them can be merged as they are
+    pub fn is_identical(&self) -> bool {
+        self.params.is_empty() && self.contigs.is_empty()
+    }
+
+    /// Whether mappings against the indexes can be merged by target name: the same targets and
+    /// parameters, possibly in a different order
+    pub fn is_compatible(&self) -> bool {
+        self.params.is_empty()
+            && self
+                .contigs
+                .iter()
+                .all(|c| matches!(c, ContigMismatch::Order { .. }))
+    }
+}
+
+impl fmt::Display for IndexDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_identical() {
+            return write!(f, "indexes are identical");
+        }
+        let mut lines = Vec::new();
+        for p in &self.params {
+            lines.push(format!("{}: {} != {}", p.name, p.left, p.right));
+        }
+        for c in &self.contigs {
+            lines.push(match c {
+                ContigMismatch::MissingRight { name } => format!("{}: only in left index", name),
+                ContigMismatch::MissingLeft { name } => format!("{}: only in right index", name),
+                ContigMismatch::Length { name, left, right } => {
+                    format!("{}: length {} != {}", name, left, right)
+                }
+                ContigMismatch::Order { name, left, right } => {
+                    format!("{}: target id {} != {}", name, left, right)
+                }
+                ContigMismatch::Alt { name, left, right } => {
+                    format!("{}: alt {} != {}", name, left, right)
+                }
+            });
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Aligner<Built> {
+    /// Compare the index of this aligner (left) with that of `other` (right)
+    ///
+    /// Compares k, w and homopolymer compression, and the name, length, ALT flag and target id
+    /// of every target. Mapping options are not compared.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let left = Aligner::builder().map_ont().with_index("test_data/genome.fa", None).unwrap();
+    /// let right = Aligner::builder().map_ont().with_index("test_data/genome.fa", None).unwrap();
+    /// assert!(left.index_diff(&right).unwrap().is_identical());
+    ///
+    /// let hifi = Aligner::builder().map_hifi().with_index("test_data/genome.fa", None).unwrap();
+    /// let diff = left.index_diff(&hifi).unwrap();
+    /// assert!(!diff.is_compatible());
+    /// println!("{}", diff);
+    /// ```
+    pub fn index_diff(&self, other: &Aligner<Built>) -> Result<IndexDiff, &'static str> {
+        let left: &mm_idx_t = self.idx.as_ref().ok_or("No index")?;
+        let right: &mm_idx_t = other.idx.as_ref().ok_or("No index")?;
+
+        let mut diff = IndexDiff::default();
+        let hpc = |idx: &mm_idx_t| (idx.flag as u32 & MM_I_HPC != 0) as i32;
+        for (name, l, r) in [
+            ("k", left.k, right.k),
+            ("w", left.w, right.w),
+            ("hpc", hpc(left), hpc(right)),
+        ] {
+            if l != r {
+                diff.params.push(ParamMismatch {
+                    name,
+                    left: l,
+                    right: r,
+                });
+            }
+        }
+
+        let right_seqs: HashMap<String, (u32, crate::SeqMetaData)> = other
+            .seqs()
+            .enumerate()
+            .map(|(i, seq)| (seq.name.clone(), (i as u32, seq)))
+            .collect();
+        let mut seen = 0;
+        for (i, seq) in self.seqs().enumerate() {
+            let Some((j, other_seq)) = right_seqs.get(&seq.name) else {
+                diff.contigs
+                    .push(ContigMismatch::MissingRight { name: seq.name });
+                continue;
+            };
+            seen += 1;
+            if seq.length != other_seq.length {
+                diff.contigs.push(ContigMismatch::Length {
+                    name: seq.name.clone(),
+                    left: seq.length,
+                    right: other_seq.length,
+                });
+            }
+            if seq.is_alt != other_seq.is_alt {
+                diff.contigs.push(ContigMismatch::Alt {
+                    name: seq.name.clone(),
+                    left: seq.is_alt,
+                    right: other_seq.is_alt,
+                });
+            }
+            if i as u32 != *j {
+                diff.contigs.push(ContigMismatch::Order {
+                    name: seq.name,
+                    left: i as u32,
+                    right: *j,
+                });
+            }
+        }
+
+        if seen < right_seqs.len() {
+            let left_names = &self.target_names().ids;
+            for seq in other.seqs() {
+                if !left_names.contains_key(&seq.name) {
+                    diff.contigs
+                        .push(ContigMismatch::MissingLeft { name: seq.name });
+                }
+            }
+        }
+
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_diff() {
+        let genome = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let same = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let diff = genome.index_diff(&same).unwrap();
+        assert!(diff.is_identical() && diff.is_compatible());
+        assert_eq!(diff.to_string(), "indexes are identical");
+
+        let chr1 = genome.target_seq(0).unwrap();
+        let chr2 = genome.target_seq(1).unwrap();
+        let write_fasta = |name: &str, records: &[(&str, &[u8])]| {
+            let path = std::env::temp_dir().join(name);
+            let mut fasta = Vec::new();
+            for (id, seq) in records {
+                fasta.extend_from_slice(format!(">{}\n", id).as_bytes());
+                fasta.extend_from_slice(seq);
+                fasta.push(b'\n');
+            }
+            std::fs::write(&path, fasta).unwrap();
+            path
+        };
+
+        // Reordered targets can be merged by name
+        let path = write_fasta(
+            "minimap2_test_index_diff_reordered.fa",
+            &[("chr2", &chr2[..]), ("chr1", &chr1[..])],
+        );
+        let reordered = Aligner::builder()
+            .map_ont()
+            .with_index(&path, None)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let diff = genome.index_diff(&reordered).unwrap();
+        assert!(!diff.is_identical() && diff.is_compatible());
+        assert_eq!(
+            diff.contigs,
+            vec![
+                ContigMismatch::Order {
+                    name: "chr1".to_string(),
+                    left: 0,
+                    right: 1
+                },
+                ContigMismatch::Order {
+                    name: "chr2".to_string(),
+                    left: 1,
+                    right: 0
+                },
+            ]
+        );
+
+        // Missing, extra and truncated targets, and other parameters
+        let path = write_fasta(
+            "minimap2_test_index_diff_other.fa",
+            &[("chr2", &chr2[..400]), ("chr3", &chr2[..])],
+        );
+        let other = Aligner::builder()
+            .map_hifi()
+            .with_index(&path, None)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let diff = genome.index_diff(&other).unwrap();
+        assert!(!diff.is_compatible());
+        assert_eq!(
+            diff.params,
+            vec![
+                ParamMismatch {
+                    name: "k",
+                    left: 15,
+                    right: 19
+                },
+                ParamMismatch {
+                    name: "w",
+                    left: 10,
+                    right: 19
+                },
+            ]
+        );
+        assert_eq!(
+            diff.contigs,
+            vec![
+                ContigMismatch::MissingRight {
+                    name: "chr1".to_string()
+                },
+                ContigMismatch::Length {
+                    name: "chr2".to_string(),
+                    left: 460,
+                    right: 400
+                },
+                ContigMismatch::Order {
+                    name: "chr2".to_string(),
+                    left: 1,
+                    right: 0
+                },
+                ContigMismatch::MissingLeft {
+                    name: "chr3".to_string()
+                },
+            ]
+        );
+        assert!(diff.to_string().contains("chr2: length 460 != 400"));
+    }
+}
@@ -0,0 +1,441 @@
+//! Plumbing for overlap-based read correction
+//!
+//! Map a read set against an index of itself with the `ava-ont` or `ava-pb` preset and
+//! [`with_cigar`](crate::Aligner::with_cigar), and add the overlaps to [`OverlapGroups`]. It
+//! groups them by read and lays the aligned segments of the reads overlapping each read out in
+//! that read's coordinates as a [`Pileup`], fetching sequences from the index.
+//! [`OverlapGroups::correct`] hands each pileup to a consensus function, so correction methods
+//! can be prototyped without touching the alignment side. [`majority_consensus`] is a simple
+//! example of one.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::{Aligner, Built, Mapping, Strand};
+
+/// Gap character of [`Segment::bases`]
+pub const GAP: u8 = b'-';
+
+/// The part of an overlapping read aligned to the read being corrected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// Name of the overlapping read
+    pub name: Arc<String>,
+    /// Strand of the overlapping read relative to the read being corrected
+    pub strand: Strand,
+    /// Span on the read being corrected, 0-based and end-exclusive
+    pub start: i32,
+    pub end: i32,
+    /// Base of the overlapping read (reverse complemented on [`Strand::Reverse`]) aligned to
+    /// each position of the span, [`GAP`] where it has a deletion
+    pub bases: Vec<u8>,
+    /// Bases the overlapping read has in addition, by the position of the read being corrected
+    /// they come before
+    pub insertions: Vec<(i32, Vec<u8>)>,
+}
+
+impl Segment {
+    /// Base aligned to `pos` of the read being corrected, if the segment covers it
+    pub fn base_at(&self, pos: i32) -> Option<u8> {
+        if pos >= self.start && pos < self.end {
+            Some(self.bases[(pos - self.start) as usize])
+        } else {
+            None
+        }
+    }
+}
+
+/// A read with the segments of the reads overlapping it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pileup {
+    pub name: Arc<String>,
+    /// Sequence of the read, as stored in the index
+    pub seq: Vec<u8>,
+    pub segments: Vec<Segment>,
+}
+
+impl Pileup {
+    /// Number of segments covering `pos`
+    pub fn depth(&self, pos: i32) -> usize {
+        self.column(pos).count()
+    }
+
+    /// Bases of the segments covering `pos`, [`GAP`] for deletions
+    pub fn column(&self, pos: i32) -> impl Iterator<Item = u8> + '_ {
+        self.segments.iter().filter_map(move |s| s.base_at(pos))
+    }
+}
+
+/// All-vs-all overlaps grouped by read
+///
+/// Each overlap counts for both of its reads. Self overlaps are skipped, and so is the second
+/// direction of a pair of reads mapped both ways (A to B and B to A), so every pair
+/// contributes once. Overlaps should come from an aligner whose index holds the reads
+/// themselves, built with sequences, as the pileups are taken from it.
+#[derive(Debug, Clone, Default)]
+pub struct OverlapGroups {
+    overlaps: Vec<Mapping>,
+    by_read: HashMap<Arc<String>, Vec<usize>>,
+    pairs: HashSet<(Arc<String>, Arc<String>)>,
+}
+
+impl OverlapGroups {
+    pub fn new() -> Self {
+        OverlapGroups::default()
+    }
+
+    /// Add an overlap, returning false if it was skipped: a self or dual overlap, or one
+    /// without read names or CIGAR
+    pub fn add(&mut self, mapping: Mapping) -> bool {
+        let (Some(query), Some(target)) = (&mapping.query_name, &mapping.target_name) else {
+            return false;
+        };
+        if query == target || cigar(&mapping).is_none() {
+            return false;
+        }
+        if self
+            .pairs
+            .contains(&(Arc::clone(target), Arc::clone(query)))
+        {
+            return false;
+        }
+        self.pairs.insert((Arc::clone(query), Arc::clone(target)));
+
+        let i = self.overlaps.len();
+        for read in [query, target] {
+            self.by_read.entry(Arc::clone(read)).or_default().push(i);
+        }
+        self.overlaps.push(mapping);
+        true
+    }
+
+    /// Reads with at least one overlap, sorted by name
+    pub fn reads(&self) -> Vec<&str> {
+        let mut reads: Vec<&str> = self.by_read.keys().map(|name| name.as_str()).collect();
+        reads.sort();
+        reads
+    }
+
+    /// Overlaps of read `name`, as query or as target
+    pub fn overlaps(&self, name: &str) -> impl Iterator<Item = &Mapping> {
+        self.by_read
+            .get(&name.to_string())
+            .into_iter()
+            .flatten()
+            .map(|&i| &self.overlaps[i])
+    }
+
+    /// Build the pileup of read `name`, with sequences from `aligner`
+    pub fn pileup(&self, aligner: &Aligner<Built>, name: &str) -> Result<Pileup, &'static str> {
+        let (name, indices) = self
+            .by_read
+            .get_key_value(&name.to_string())
+            .ok_or("Read has no overlaps")?;
+        let rid = aligner.target_id(name).ok_or("Read not found in index")?;
+        let seq = aligner.target_seq(rid).ok_or("Index has no sequence")?;
+
+        let mut segments = Vec::with_capacity(indices.len());
+        for &i in indices {
+            let m = &self.overlaps[i];
+            let ops = cigar(m).unwrap();
+            let (query, target) = (
+                m.query_name.as_ref().unwrap(),
+                m.target_name.as_ref().unwrap(),
+            );
+            let segment = if target == name {
+                // The query on the target's forward strand, with the CIGAR as it is
+                let other = fetch(aligner, query, m.query_start, m.query_end, m.strand)?;
+                build_segment(query, m.strand, m.target_start, &other, ops.iter().copied())?
+            } else {
+                // The target on the query's forward strand: insertions become deletions and,
+                // on the reverse strand, the alignment runs the other way
+                let other = fetch(aligner, target, m.target_start, m.target_end, m.strand)?;
+                let swapped = ops.iter().map(|&(len, op)| (len, swap_indel(op)));
+                match m.strand {
+                    Strand::Forward => {
+                        build_segment(target, m.strand, m.query_start, &other, swapped)?
+                    }
+                    Strand::Reverse => {
+                        build_segment(target, m.strand, m.query_start, &other, swapped.rev())?
+                    }
+                }
+            };
+            if segment.start < 0 || segment.end as usize > seq.len() {
+                return Err("Overlap out of range of the read");
+            }
+            segments.push(segment);
+        }
+        segments.sort_by_key(|s| (s.start, s.end));
+
+        Ok(Pileup {
+            name: Arc::clone(name),
+            seq,
+            segments,
+        })
+    }
+
+    /// Call `consensus` on the pileup of each read, in name order, and collect the corrected
+    /// sequences it returns; reads for which it returns None are left out
+    pub fn correct<F>(
+        &self,
+        aligner: &Aligner<Built>,
+        mut consensus: F,
+    ) -> Result<Vec<(Arc<String>, Vec<u8>)>, &'static str>
+    where
+        F: FnMut(&Pileup) -> Option<Vec<u8>>,
+    {
+        let mut corrected = Vec::new();
+        for read in self.reads() {
+            let pileup = self.pileup(aligner, read)?;
+            if let Some(seq) = consensus(&pileup) {
+                corrected.push((pileup.name, seq));
+            }
+        }
+        Ok(corrected)
+    }
+}
+
+fn cigar(mapping: &Mapping) -> Option<&Vec<(u32, u8)>> {
+    mapping.alignment.as_ref()?.cigar.as_ref()
+}
+
+fn fetch(
+    aligner: &Aligner<Built>,
+    name: &str,
+    start: i32,
+    end: i32,
+    strand: Strand,
+) -> Result<Vec<u8>, &'static str> {
+    if start < 0 || end < start {
+        return Err("Invalid overlap span");
+    }
+    Ok(aligner.fetch_subseq(name, start as u32..end as u32, strand)?)
+}
+
+/// Exchange the roles of query and target in a CIGAR op
+fn swap_indel(op: u8) -> u8 {
+    match op {
+        1 => 2,
+        2 | 3 => 1,
+        op => op,
+    }
+}
+
+/// Walk `ops`, with the read being corrected as target and `other` as query
+fn build_segment<I>(
+    name: &Arc<String>,
+    strand: Strand,
+    start: i32,
+    other: &[u8],
+    ops: I,
+) -> Result<Segment, &'static str>
+where
+    I: Iterator<Item = (u32, u8)>,
+{
+    let mut bases = Vec::with_capacity(other.len());
+    let mut insertions = Vec::new();
+    let (mut pos, mut i) = (start, 0usize);
+    for (len, op) in ops {
+        let n = len as usize;
+        match op {
+            // M, =, X
+            0 | 7 | 8 => {
+                let aligned = other.get(i..i + n).ok_or("CIGAR longer than the overlap")?;
+                bases.extend_from_slice(aligned);
+                i += n;
+                pos += len as i32;
+            }
+            1 => {
+                let inserted = other.get(i..i + n).ok_or("CIGAR longer than the overlap")?;
+                insertions.push((pos, inserted.to_vec()));
+                i += n;
+            }
+            2 | 3 => {
+                bases.resize(bases.len() + n, GAP);
+                pos += len as i32;
+            }
+            // Clipping and padding
+            _ => {}
+        }
+    }
+    if i != other.len() {
+        return Err("CIGAR does not match the overlap span");
+    }
+
+    Ok(Segment {
+        name: Arc::clone(name),
+        strand,
+        start,
+        end: pos,
+        bases,
+        insertions,
+    })
+}
+
+/// Majority vote of the read and its segments at each position
+///
+/// The read's own base wins ties. A base is dropped where most votes are [`GAP`], and an
+/// insertion is added where most of the segments spanning it, and the read, carry one (the
+/// most common inserted sequence). Returns None for reads without segments.
+pub fn majority_consensus(pileup: &Pileup) -> Option<Vec<u8>> {
+    if pileup.segments.is_empty() {
+        return None;
+    }
+    let len = pileup.seq.len();
+
+    // Votes for A, C, G, T, N and gap at each position
+    let mut votes = vec![[0u32; 6]; len];
+    let mut spanning = vec![0u32; len + 1];
+    let mut inserted: HashMap<i32, HashMap<&[u8], u32>> = HashMap::new();
+    for segment in &pileup.segments {
+        let start = segment.start as usize;
+        for (j, &base) in segment.bases.iter().enumerate() {
+            votes[start + j][vote_index(base)] += 1;
+        }
+        for pos in segment.start + 1..segment.end {
+            spanning[pos as usize] += 1;
+        }
+        for (pos, seq) in &segment.insertions {
+            if *pos > segment.start && *pos < segment.end {
+                *inserted
+                    .entry(*pos)
+                    .or_default()
+                    .entry(seq.as_slice())
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    let mut consensus = Vec::with_capacity(len);
+    for (pos, &own) in pileup.seq.iter().enumerate() {
+        if let Some(candidates) = inserted.get(&(pos as i32)) {
+            let total: u32 = candidates.values().sum();
+            if total > (spanning[pos] + 1) / 2 {
+                let (seq, _) = candidates
+                    .iter()
+                    .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                    .unwrap();
+                consensus.extend_from_slice(seq);
+            }
+        }
+
+        let mut column = votes[pos];
+        column[vote_index(own)] += 1;
+        let own_votes = column[vote_index(own)];
+        let (best, &best_votes) = column
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(&a.0)))
+            .unwrap();
+        if best_votes == own_votes {
+            consensus.push(own);
+        } else if best < 5 {
+            consensus.push(b"ACGTN"[best]);
+        }
+    }
+    Some(consensus)
+}
+
+fn vote_index(base: u8) -> usize {
+    match base {
+        b'A' | b'a' => 0,
+        b'C' | b'c' => 1,
+        b'G' | b'g' => 2,
+        b'T' | b't' => 3,
+        GAP => 5,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mt_seq;
+
+    #[test]
+    fn test_build_segment() {
+        let name = Arc::new("other".to_string());
+        // 2M1I2M2D1M against target positions 10..17
+        let ops = [(2, 0), (1, 1), (2, 0), (2, 2), (1, 0)];
+        let segment =
+            build_segment(&name, Strand::Forward, 10, b"ACGTAC", ops.into_iter()).unwrap();
+        assert_eq!(segment.start, 10);
+        assert_eq!(segment.end, 17);
+        assert_eq!(segment.bases, b"ACTA--C");
+        assert_eq!(segment.insertions, vec![(12, b"G".to_vec())]);
+        assert_eq!(segment.base_at(15), Some(GAP));
+        assert_eq!(segment.base_at(17), None);
+
+        assert!(build_segment(&name, Strand::Forward, 0, b"ACG", [(2, 0)].into_iter()).is_err());
+        assert!(build_segment(&name, Strand::Forward, 0, b"AC", [(3, 0)].into_iter()).is_err());
+    }
+
+    #[test]
+    fn test_correct() {
+        let mt = mt_seq();
+
+        // r2 has a substitution, a deleted base and an inserted base; r1, r3 and r4 cover
+        // all three, r4 on the reverse strand
+        let truth = mt[1000..4000].to_vec();
+        let mut r2 = truth.clone();
+        r2[500] = if r2[500] == b'A' { b'C' } else { b'A' };
+        r2.remove(1000);
+        r2.insert(1500, if r2[1500] == b'G' { b'T' } else { b'G' });
+        let reads = vec![
+            mt[0..3000].to_vec(),
+            r2,
+            mt[500..3500].to_vec(),
+            crate::seq::revcomp(&mt[1200..3800]),
+        ];
+        let names: Vec<Vec<u8>> = ["r1", "r2", "r3", "r4"]
+            .iter()
+            .map(|name| name.as_bytes().to_vec())
+            .collect();
+
+        let aligner = Aligner::builder()
+            .ava_ont()
+            .with_cigar()
+            .with_seqs_and_ids(&reads, &names)
+            .unwrap();
+        let mut groups = OverlapGroups::new();
+        for (read, name) in reads.iter().zip(&names) {
+            for m in aligner
+                .map(read, false, false, None, None, Some(name))
+                .unwrap()
+            {
+                groups.add(m);
+            }
+        }
+        assert_eq!(groups.reads(), ["r1", "r2", "r3", "r4"]);
+        // Each pair counts once, whichever way it was mapped
+        assert_eq!(groups.overlaps("r2").count(), 3);
+
+        let pileup = groups.pileup(&aligner, "r2").unwrap();
+        assert_eq!(pileup.seq, reads[1]);
+        assert_eq!(pileup.segments.len(), 3);
+        assert_eq!(pileup.depth(600), 3);
+        for segment in &pileup.segments {
+            let strand = match segment.name.as_str() {
+                "r4" => Strand::Reverse,
+                _ => Strand::Forward,
+            };
+            assert_eq!(segment.strand, strand);
+        }
+
+        let corrected = groups.correct(&aligner, majority_consensus).unwrap();
+        let r2 = corrected
+            .iter()
+            .find(|(name, _)| name.as_str() == "r2")
+            .unwrap();
+        assert_eq!(r2.1, truth);
+
+        // A consensus function can skip reads
+        let only_r2 = groups
+            .correct(&aligner, |p| {
+                (p.name.as_str() == "r2").then(|| p.seq.clone())
+            })
+            .unwrap();
+        assert_eq!(only_r2.len(), 1);
+        assert!(groups.pileup(&aligner, "r5").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Streaming per-target coverage of mappings
+//!
+//! [`TargetCoverage`] adds up the aligned target bases of each [`Mapping`] in fixed-size bins
+//! as results come in, so that depth along each target can be shown or summarised while
+//! mapping is still running. Accumulators built on separate threads are combined with
+//! [`TargetCoverage::merge`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::Mapping;
+
+/// Bin size of [`TargetCoverage::default`]
+pub const DEFAULT_BIN_SIZE: u32 = 1000;
+
+/// Coverage of one target
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Depth {
+    pub len: i32,
+    pub n_mappings: u64,
+    /// Target bases covered by mappings, over the whole target
+    pub aligned_bases: u64,
+    /// Target bases covered by mappings in each bin
+    pub bins: Vec<u64>,
+}
+
+impl Depth {
+    /// Aligned bases over the target length, or 0 for an empty target
+    pub fn mean_depth(&self) -> f64 {
+        if self.len > 0 {
+            self.aligned_bases as f64 / self.len as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Per-target coverage, binned along each target
+///
+/// Mappings are counted over their target span, so deletions count as covered and
+/// insertions do not. Secondary mappings are counted too; skip them before calling
+/// [`add`](TargetCoverage::add) for primary-only depth.
+///
+/// ```
+/// use minimap2::coverage::TargetCoverage;
+/// use minimap2::Mapping;
+/// use std::sync::Arc;
+///
+/// let mut coverage = TargetCoverage::new(100);
+/// coverage.add(&Mapping {
+///     target_name: Some(Arc::new("chr1".to_string())),
+///     target_len: 250,
+///     target_start: 50,
+///     target_end: 250,
+///     ..Default::default()
+/// });
+/// let depth = coverage.get("chr1").unwrap();
+/// assert_eq!(depth.bins, vec![50, 100, 50]);
+/// assert_eq!(depth.mean_depth(), 0.8);
+/// assert_eq!(coverage.bin_depths("chr1").unwrap(), vec![0.5, 1.0, 1.0]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TargetCoverage {
+    bin_size: u32,
+    targets: HashMap<Arc<String>, Depth>,
+}
+
+impl Default for TargetCoverage {
+    fn default() -> Self {
+        TargetCoverage::new(DEFAULT_BIN_SIZE)
+    }
+}
+
+impl TargetCoverage {
+    /// Panics if `bin_size` is 0
+    pub fn new(bin_size: u32) -> Self {
+        assert!(bin_size > 0, "bin_size must be positive");
+        TargetCoverage {
+            bin_size,
+            targets: HashMap::new(),
+        }
+    }
+
+    pub fn bin_size(&self) -> u32 {
+        self.bin_size
+    }
+
+    /// Add a mapping, returning false if it has no target name or an invalid span
+    pub fn add(&mut self, mapping: &Mapping) -> bool {
+        let Some(target) = mapping.target_name.as_ref() else {
+            return false;
+        };
+        let (start, end) = (mapping.target_start, mapping.target_end);
+        if start < 0 || end <= start || end > mapping.target_len {
+            return false;
+        }
+
+        let bin_size = self.bin_size as i64;
+        let depth = self.targets.entry(Arc::clone(target)).or_default();
+        if depth.bins.is_empty() {
+            depth.len = mapping.target_len;
+            let n_bins = (mapping.target_len as i64 + bin_size - 1) / bin_size;
+            depth.bins = vec![0; n_bins as usize];
+        }
+        depth.n_mappings += 1;
+        depth.aligned_bases += (end - start) as u64;
+
+        let (start, end) = (start as i64, end as i64);
+        for bin in start / bin_size..=(end - 1) / bin_size {
+            let bin_start = bin * bin_size;
+            let overlap = end.min(bin_start + bin_size) - start.max(bin_start);
+            depth.bins[bin as usize] += overlap as u64;
+        }
+        true
+    }
+
+    /// Add the counts of `other`, which must use the same bin size
+    pub fn merge(&mut self, other: &TargetCoverage) {
+        assert_eq!(self.bin_size, other.bin_size, "bin sizes differ");
+        for (name, theirs) in &other.targets {
+            let ours = self.targets.entry(Arc::clone(name)).or_default();
+            if ours.bins.is_empty() {
+                ours.len = theirs.len;
+                ours.bins = vec![0; theirs.bins.len()];
+            }
+            ours.n_mappings += theirs.n_mappings;
+            ours.aligned_bases += theirs.aligned_bases;
+            for (a, b) in ours.bins.iter_mut().zip(&theirs.bins) {
+                *a += b;
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Depth> {
+        self.targets.get(&name.to_string())
+    }
+
+    /// Coverage of all targets with at least one mapping, sorted by name
+    pub fn targets(&self) -> Vec<(&str, &Depth)> {
+        let mut targets: Vec<_> = self
+            .targets
+            .iter()
+            .map(|(name, depth)| (name.as_str(), depth))
+            .collect();
+        targets.sort_by(|a, b| a.0.cmp(b.0));
+        targets
+    }
+
+    /// Mean depth of each bin of a target; the last bin may be shorter than the bin size
+    pub fn bin_depths(&self, name: &str) -> Option<Vec<f64>> {
+        let depth = self.get(name)?;
+        let bin_size = self.bin_size as i64;
+        Some(
+            depth
+                .bins
+                .iter()
+                .enumerate()
+                .map(|(i, &bases)| {
+                    let bin_start = i as i64 * bin_size;
+                    let bin_len = (depth.len as i64 - bin_start).min(bin_size);
+                    bases as f64 / bin_len as f64
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(target: &str, len: i32, start: i32, end: i32) -> Mapping {
+        Mapping {
+            target_name: Some(Arc::new(target.to_string())),
+            target_len: len,
+            target_start: start,
+            target_end: end,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_target_coverage() {
+        let mut coverage = TargetCoverage::new(10);
+        assert!(coverage.add(&mapping("b", 25, 0, 25)));
+        assert!(coverage.add(&mapping("b", 25, 5, 12)));
+        assert!(coverage.add(&mapping("a", 10, 9, 10)));
+        assert!(!coverage.add(&mapping("a", 10, 5, 11)));
+        assert!(!coverage.add(&mapping("a", 10, 5, 5)));
+        assert!(!coverage.add(&Mapping::default()));
+
+        let b = coverage.get("b").unwrap();
+        assert_eq!(b.n_mappings, 2);
+        assert_eq!(b.aligned_bases, 32);
+        assert_eq!(b.bins, vec![15, 12, 5]);
+        assert_eq!(coverage.bin_depths("b").unwrap(), vec![1.5, 1.2, 1.0]);
+        assert_eq!(coverage.bin_depths("c"), None);
+
+        let names: Vec<&str> = coverage.targets().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["a", "b"]);
+
+        // Merging accumulators built separately gives the same counts as one accumulator
+        let mut other = TargetCoverage::new(10);
+        other.add(&mapping("b", 25, 20, 25));
+        other.add(&mapping("c", 5, 0, 5));
+        let mut merged = coverage.clone();
+        merged.merge(&other);
+        coverage.add(&mapping("b", 25, 20, 25));
+        coverage.add(&mapping("c", 5, 0, 5));
+        assert_eq!(merged.targets(), coverage.targets());
+    }
+}
@@ -0,0 +1,158 @@
+//! Duplicate read detection for the file drivers
+//!
+//! Merged or concatenated FASTQs often hold the same reads more than once. With a
+//! [`ReadIds`] set on the builder ([`with_dedup`](crate::Aligner::with_dedup)), the file
+//! drivers skip every read whose ID was seen before, counting it in
+//! [`FilteredReads::duplicates`](crate::FilteredReads::duplicates). IDs are compared up to the
+//! first whitespace of the header, as minimap2 names reads.
+//!
+//! [`HashReadIds`] remembers every ID exactly. [`BloomReadIds`] takes a fixed amount of memory
+//! however many reads there are, at the cost of skipping a small fraction of unique reads as
+//! false positives. Either can be replaced by an implementation of [`ReadIds`], e.g. one
+//! backed by an on-disk key-value store.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::Hasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// A set of read IDs seen so far, shared by the runs of an aligner and its clones
+pub trait ReadIds: Send + Sync {
+    /// Add `id`, returning whether it is new. A probabilistic set may also return false for
+    /// some IDs it has not seen.
+    fn insert(&self, id: &[u8]) -> bool;
+}
+
+/// Exact set of read IDs
+#[derive(Debug, Default)]
+pub struct HashReadIds {
+    ids: Mutex<HashSet<Vec<u8>>>,
+}
+
+impl HashReadIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct IDs seen
+    pub fn len(&self) -> usize {
+        self.ids.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ReadIds for HashReadIds {
+    fn insert(&self, id: &[u8]) -> bool {
+        self.ids.lock().unwrap().insert(id.to_vec())
+    }
+}
+
+/// Bloom filter of read IDs, of fixed size
+///
+/// ```
+/// use minimap2::dedup::{BloomReadIds, ReadIds};
+///
+/// // About 1.2 MB for a million reads at a false positive rate of 1%
+/// let ids = BloomReadIds::new(1_000_000, 0.01);
+/// assert!(ids.insert(b"read1"));
+/// assert!(!ids.insert(b"read1"));
+/// ```
+#[derive(Debug)]
+pub struct BloomReadIds {
+    bits: Vec<AtomicU64>,
+    n_hashes: u32,
+}
+
+impl BloomReadIds {
+    /// Filter sized for `expected_reads` distinct reads with a rate of false positives of
+    /// `false_positive_rate`. Past that many reads the rate rises.
+    pub fn new(expected_reads: usize, false_positive_rate: f64) -> Self {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "false_positive_rate must be between 0 and 1"
+        );
+        let n = expected_reads.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let n_bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0);
+        let n_hashes = (n_bits / n * ln2).round().max(1.0) as u32;
+        BloomReadIds {
+            bits: (0..(n_bits as usize).div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            n_hashes,
+        }
+    }
+
+    /// Size of the filter in bytes
+    pub fn size(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+impl ReadIds for BloomReadIds {
+    fn insert(&self, id: &[u8]) -> bool {
+        // Double hashing: the i-th bit is h1 + i * h2
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            hasher.write_u64(seed);
+            hasher.write(id);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        let n_bits = self.bits.len() as u64 * 64;
+
+        let mut new = false;
+        for i in 0..self.n_hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % n_bits;
+            let mask = 1 << (bit % 64);
+            let word = self.bits[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            new |= word & mask == 0;
+        }
+        new
+    }
+}
+
+/// The ID of a read from its FASTA/FASTQ header: the header up to the first whitespace
+pub(crate) fn read_id(header: &[u8]) -> &[u8] {
+    let end = header
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .unwrap_or(header.len());
+    &header[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_ids() {
+        let ids = HashReadIds::new();
+        assert!(ids.insert(b"read1"));
+        assert!(ids.insert(b"read2"));
+        assert!(!ids.insert(b"read1"));
+        assert_eq!(ids.len(), 2);
+
+        assert_eq!(read_id(b"read1 runid=abc ch=7"), b"read1");
+        assert_eq!(read_id(b"read1\tBC:Z:ACGT"), b"read1");
+        assert_eq!(read_id(b"read1"), b"read1");
+    }
+
+    #[test]
+    fn test_bloom_read_ids() {
+        let ids = BloomReadIds::new(10_000, 0.01);
+        assert!(ids.size() >= 10_000 * 9 / 8);
+        let new = (0..10_000)
+            .filter(|i| ids.insert(format!("read{i}").as_bytes()))
+            .count();
+        // Every ID is new, but a few are taken for duplicates
+        assert!(new > 9_800, "{new}");
+        assert!((0..10_000).all(|i| !ids.insert(format!("read{i}").as_bytes())));
+    }
+}
@@ -0,0 +1,383 @@
+//! Mapping one read set under several presets or parameter sets
+//!
+//! Tuning studies map the same reads again and again with different options and compare the
+//! results. [`evaluate`] does the orchestration: it builds an aligner per parameter set,
+//! sharing the index between those whose index options agree (see
+//! [`with_shared_index`](Aligner::with_shared_index)), maps the reads with each on a pool of
+//! threads and returns the mappings of every read under every set along with
+//! [`RunStats`]. Any two runs can then be compared read by read with
+//! [`Evaluation::compare`].
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use minimap2_sys::{mm_mapopt_update, MM_F_OUT_CS, MM_F_OUT_MD, MM_I_HPC};
+
+use crate::compare::{compare, CompareOptions, MappingComparison};
+use crate::{AcceptsParams, Aligner, BuilderState, Built, Mapping, PresetSet};
+
+/// MAPQ from which [`RunStats::n_high_mapq`] counts a read
+pub const HIGH_MAPQ: u32 = 30;
+
+/// Summary of the mappings of one run, counted over primary mappings unless noted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    pub n_reads: usize,
+    /// Reads with at least one mapping
+    pub n_mapped: usize,
+    /// All mappings, including secondary and supplementary ones
+    pub n_mappings: usize,
+    pub n_supplementary: usize,
+    /// Mapped reads with a MAPQ of at least [`HIGH_MAPQ`]
+    pub n_high_mapq: usize,
+    pub mapq_sum: u64,
+    /// Query bases covered by primary mappings
+    pub aligned_bases: u64,
+    pub match_len: u64,
+    pub block_len: u64,
+    /// Time spent building or loading the index, zero if it was shared
+    pub index_time: Duration,
+    pub map_time: Duration,
+}
+
+impl RunStats {
+    fn from_mappings(mappings: &[Vec<Mapping>]) -> Self {
+        let mut stats = RunStats {
+            n_reads: mappings.len(),
+            ..Default::default()
+        };
+        for read in mappings {
+            stats.n_mappings += read.len();
+            stats.n_supplementary += read.iter().filter(|m| m.is_supplementary).count();
+            let Some(primary) = read
+                .iter()
+                .find(|m| m.is_primary && !m.is_supplementary)
+                .or(read.first())
+            else {
+                continue;
+            };
+            stats.n_mapped += 1;
+            stats.n_high_mapq += (primary.mapq >= HIGH_MAPQ) as usize;
+            stats.mapq_sum += primary.mapq as u64;
+            stats.aligned_bases += (primary.query_end - primary.query_start).max(0) as u64;
+            stats.match_len += primary.match_len.max(0) as u64;
+            stats.block_len += primary.block_len.max(0) as u64;
+        }
+        stats
+    }
+
+    /// Fraction of reads with at least one mapping
+    pub fn mapped_fraction(&self) -> f64 {
+        if self.n_reads > 0 {
+            self.n_mapped as f64 / self.n_reads as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Mean MAPQ of mapped reads
+    pub fn mean_mapq(&self) -> f64 {
+        if self.n_mapped > 0 {
+            self.mapq_sum as f64 / self.n_mapped as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Matching bases over alignment block length, as the PAF columns 10 and 11
+    pub fn identity(&self) -> f64 {
+        if self.block_len > 0 {
+            self.match_len as f64 / self.block_len as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The mappings and statistics of one parameter set
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub name: String,
+    /// Whether the index was taken from an earlier run rather than built
+    pub index_shared: bool,
+    /// Mappings of each read, in the order of the reads
+    pub mappings: Vec<Vec<Mapping>>,
+    pub stats: RunStats,
+}
+
+/// Result of [`evaluate`], with the runs in the order of the parameter sets
+#[derive(Debug, Clone, Default)]
+pub struct Evaluation {
+    pub runs: Vec<Run>,
+}
+
+impl Evaluation {
+    pub fn get(&self, name: &str) -> Option<&Run> {
+        self.runs.iter().find(|run| run.name == name)
+    }
+
+    /// Compare the mappings of two runs read by read, None if either run is missing
+    pub fn compare(
+        &self,
+        left: &str,
+        right: &str,
+        options: &CompareOptions,
+    ) -> Option<MappingComparison> {
+        let (left, right) = (self.get(left)?, self.get(right)?);
+        Some(compare(
+            left.mappings.iter().flatten().cloned(),
+            right.mappings.iter().flatten().cloned(),
+            options,
+        ))
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "name\treads\tmapped\tmappings\tsupplementary\tmapq>={}\tmean_mapq\tidentity\tindex_s\tmap_s",
+            HIGH_MAPQ
+        )?;
+        for run in &self.runs {
+            let s = &run.stats;
+            write!(
+                f,
+                "\n{}\t{}\t{:.4}\t{}\t{}\t{}\t{:.2}\t{:.4}\t{:.3}\t{:.3}",
+                run.name,
+                s.n_reads,
+                s.mapped_fraction(),
+                s.n_mappings,
+                s.n_supplementary,
+                s.n_high_mapq,
+                s.mean_mapq(),
+                s.identity(),
+                s.index_time.as_secs_f64(),
+                s.map_time.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> Aligner<S>
+where
+    S: BuilderState + AcceptsParams,
+{
+    /// Build with the index of `other` rather than building or loading another copy
+    ///
+    /// The index is shared, not copied. The mapping options of this builder are used, updated
+    /// for the index as by [`with_index`](Aligner::with_index). Fails if the k-mer size,
+    /// window size or homopolymer compression set on this builder differ from those of the
+    /// index, as minimap2 would then find different seeds.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let ont = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let mut builder = Aligner::builder().map_ont();
+    /// builder.mapopt.bw = 100;
+    /// let narrow = builder.with_shared_index(&ont).unwrap();
+    /// assert_eq!(narrow.n_seq(), ont.n_seq());
+    ///
+    /// assert!(Aligner::builder().sr().with_shared_index(&ont).is_err());
+    /// ```
+    pub fn with_shared_index(
+        mut self,
+        other: &Aligner<Built>,
+    ) -> Result<Aligner<Built>, &'static str> {
+        let idx = other.idx.as_ref().ok_or("No index")?;
+
+        let hpc = |flag: i32| flag & MM_I_HPC as i32 != 0;
+        if idx.k != self.idxopt.k as i32
+            || idx.w != self.idxopt.w as i32
+            || hpc(idx.flag) != hpc(self.idxopt.flag as i32)
+        {
+            return Err("Index options differ");
+        }
+
+        unsafe { mm_mapopt_update(&mut self.mapopt, idx.idx) };
+        self.idx = Some(Arc::clone(idx));
+        self.index_files = other.index_files.clone();
+        Ok(self.into_built())
+    }
+}
+
+/// Map `reads`, given as `(name, sequence)` pairs, against the index at `index` with each of
+/// `param_sets`, on `threads` worker threads
+///
+/// Each parameter set is a named builder, typically a preset with some options changed.
+/// Builders whose index options match those of an earlier one reuse its index. Mappings are
+/// computed with cs and MD as set on each builder. Names must be unique.
+///
+/// ```
+/// # use minimap2::*;
+/// use minimap2::eval::evaluate;
+///
+/// let reference = Aligner::builder()
+///     .with_index("test_data/genome.fa", None)
+///     .unwrap();
+/// let chr1 = reference.target_seq(0).unwrap();
+/// let reads = [("read1", chr1[100..600].to_vec()), ("read2", chr1[900..1400].to_vec())];
+///
+/// let evaluation = evaluate(
+///     "test_data/genome.fa",
+///     [
+///         ("map-ont", Aligner::builder().map_ont()),
+///         ("map-hifi", Aligner::builder().map_hifi()),
+///     ],
+///     &reads,
+///     2,
+/// )
+/// .unwrap();
+/// assert_eq!(evaluation.get("map-ont").unwrap().stats.n_mapped, 2);
+/// println!("{}", evaluation);
+/// ```
+pub fn evaluate<P, C, N, R, Q>(
+    index: P,
+    param_sets: C,
+    reads: &[(R, Q)],
+    threads: usize,
+) -> Result<Evaluation, &'static str>
+where
+    P: AsRef<Path>,
+    C: IntoIterator<Item = (N, Aligner<PresetSet>)>,
+    N: Into<String>,
+    R: AsRef<[u8]> + Sync,
+    Q: AsRef<[u8]> + Sync,
+{
+    let mut evaluation = Evaluation::default();
+    let mut aligners: Vec<Aligner<Built>> = Vec::new();
+
+    for (name, builder) in param_sets {
+        let name = name.into();
+        if evaluation.get(&name).is_some() {
+            return Err("Parameter set names must be unique");
+        }
+
+        let start = Instant::now();
+        let shared = aligners
+            .iter()
+            .find_map(|aligner| builder.clone().with_shared_index(aligner).ok());
+        let index_shared = shared.is_some();
+        let aligner = match shared {
+            Some(aligner) => aligner,
+            None => builder.with_index(&index, None)?,
+        };
+        let index_time = if index_shared {
+            Duration::ZERO
+        } else {
+            start.elapsed()
+        };
+
+        let cs = aligner.mapopt.flag & MM_F_OUT_CS as i64 != 0;
+        let md = aligner.mapopt.flag & MM_F_OUT_MD as i64 != 0;
+        let start = Instant::now();
+        let mappings = aligner.map_parallel(
+            reads
+                .iter()
+                .map(|(name, seq)| (name.as_ref(), seq.as_ref())),
+            cs,
+            md,
+            threads,
+        )?;
+        let map_time = start.elapsed();
+
+        let stats = RunStats {
+            index_time,
+            map_time,
+            ..RunStats::from_mappings(&mappings)
+        };
+        evaluation.runs.push(Run {
+            name,
+            index_shared,
+            mappings,
+            stats,
+        });
+        aligners.push(aligner);
+    }
+
+    if evaluation.runs.is_empty() {
+        return Err("No parameter sets");
+    }
+    Ok(evaluation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seq::revcomp;
+
+    #[test]
+    fn test_evaluate() {
+        let reference = Aligner::builder()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let chr1 = reference.target_seq(0).unwrap();
+        let reads = vec![
+            ("fwd".to_string(), chr1[10..600].to_vec()),
+            ("rev".to_string(), revcomp(&chr1[700..1300])),
+            ("ns".to_string(), b"N".repeat(500)),
+        ];
+
+        let mut narrow = Aligner::builder().map_ont().with_cigar();
+        narrow.mapopt.bw = 100;
+        let evaluation = evaluate(
+            "test_data/genome.fa",
+            [
+                ("map-ont", Aligner::builder().map_ont().with_cigar()),
+                ("sr", Aligner::builder().sr()),
+                ("map-ont-narrow", narrow),
+            ],
+            &reads,
+            2,
+        )
+        .unwrap();
+
+        let shared: Vec<bool> = evaluation.runs.iter().map(|r| r.index_shared).collect();
+        assert_eq!(shared, [false, false, true]);
+        assert_eq!(evaluation.runs[2].stats.index_time, Duration::ZERO);
+
+        let ont = evaluation.get("map-ont").unwrap();
+        assert_eq!(ont.mappings.len(), 3);
+        assert_eq!(ont.stats.n_reads, 3);
+        assert_eq!(ont.stats.n_mapped, 2);
+        assert!(ont.mappings[2].is_empty());
+        assert_eq!(ont.stats.identity(), 1.0);
+        assert!((ont.stats.mapped_fraction() - 2.0 / 3.0).abs() < 1e-9);
+
+        // Same reads, same seeds and a wide enough band: the same mappings
+        let comparison = evaluation
+            .compare("map-ont", "map-ont-narrow", &CompareOptions::default())
+            .unwrap();
+        assert!(comparison.is_identical(), "{}", comparison);
+        assert!(evaluation
+            .compare("map-ont", "asm5", &CompareOptions::default())
+            .is_none());
+
+        assert_eq!(evaluation.to_string().lines().count(), 4);
+
+        assert_eq!(
+            evaluate(
+                "test_data/genome.fa",
+                [
+                    ("a", Aligner::builder().map_ont()),
+                    ("a", Aligner::builder().map_ont())
+                ],
+                &reads,
+                1
+            )
+            .unwrap_err(),
+            "Parameter set names must be unique"
+        );
+        let none: [(&str, Aligner<PresetSet>); 0] = [];
+        assert_eq!(
+            evaluate("test_data/genome.fa", none, &reads, 1).unwrap_err(),
+            "No parameter sets"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Progress events of a mapping run, independent of any UI
+//!
+//! These are the events fakeminimap2's terminal UI is driven by: queries read, mappings
+//! produced and status changes. Frontends (dashboards, loggers) implement [`EventListener`]
+//! or receive the events over a channel, and can tally them with [`Progress`].
+//!
+//! ```
+//! use minimap2::events::{EventListener, MappingEvent, Progress, Status};
+//! use std::sync::mpsc;
+//!
+//! let (tx, rx) = mpsc::channel();
+//! tx.send_event(MappingEvent::Status(Status::Mapping));
+//! tx.send_event(MappingEvent::MappingsProduced {
+//!     query_id: "read1".to_string(),
+//!     mappings: Vec::new(),
+//! });
+//! drop(tx);
+//!
+//! let mut progress = Progress::default();
+//! for event in rx {
+//!     progress.update(&event);
+//! }
+//! assert_eq!(progress.queries_mapped, 1);
+//! assert_eq!(progress.status, Some(Status::Mapping));
+//! ```
+
+use crate::Mapping;
+
+/// A query sequence submitted for mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuerySequence {
+    pub id: String,
+    pub sequence: Vec<u8>,
+}
+
+impl QuerySequence {
+    pub fn new(id: String, sequence: Vec<u8>) -> Self {
+        Self { id, sequence }
+    }
+}
+
+/// Stage of a mapping run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Indexing,
+    IndexingComplete,
+    Mapping,
+    AllQueriesSubmitted,
+    MappingComplete,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Indexing => write!(f, "Indexing Sequence"),
+            Status::IndexingComplete => write!(f, "Indexing Complete"),
+            Status::Mapping => write!(f, "Mapping Sequences"),
+            Status::AllQueriesSubmitted => write!(f, "All Sequences Submitted"),
+            Status::MappingComplete => write!(f, "Mapping Complete"),
+        }
+    }
+}
+
+/// An event of a mapping run
+#[derive(Debug, Clone, PartialEq)]
+pub enum MappingEvent {
+    /// A query was read and submitted for mapping
+    QueryAdded(QuerySequence),
+    /// A query was mapped
+    MappingsProduced {
+        query_id: String,
+        mappings: Vec<Mapping>,
+    },
+    Status(Status),
+}
+
+/// Receiver of [`MappingEvent`]s
+///
+/// Implemented for closures and for the sending half of std channels. Sending never fails:
+/// events sent after a listener went away are dropped, as progress reporting should not stop
+/// the mapping.
+pub trait EventListener: Send + Sync {
+    fn send_event(&self, event: MappingEvent);
+}
+
+impl<F> EventListener for F
+where
+    F: Fn(MappingEvent) + Send + Sync,
+{
+    fn send_event(&self, event: MappingEvent) {
+        self(event)
+    }
+}
+
+impl EventListener for std::sync::mpsc::Sender<MappingEvent> {
+    fn send_event(&self, event: MappingEvent) {
+        let _ = self.send(event);
+    }
+}
+
+impl EventListener for std::sync::mpsc::SyncSender<MappingEvent> {
+    fn send_event(&self, event: MappingEvent) {
+        let _ = self.send(event);
+    }
+}
+
+/// Running totals of a mapping run, updated from its events
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub queries_submitted: usize,
+    pub queries_mapped: usize,
+    /// Queries with at least one mapping
+    pub queries_with_mappings: usize,
+    pub mappings: usize,
+    /// Latest status, None before the first status event
+    pub status: Option<Status>,
+}
+
+impl Progress {
+    pub fn update(&mut self, event: &MappingEvent) {
+        match event {
+            MappingEvent::QueryAdded(_) => self.queries_submitted += 1,
+            MappingEvent::MappingsProduced { mappings, .. } => {
+                self.queries_mapped += 1;
+                self.mappings += mappings.len();
+                if !mappings.is_empty() {
+                    self.queries_with_mappings += 1;
+                }
+            }
+            MappingEvent::Status(status) => self.status = Some(*status),
+        }
+    }
+
+    /// Whether all submitted queries have been mapped
+    pub fn is_complete(&self) -> bool {
+        self.status == Some(Status::MappingComplete)
+            || (self.status == Some(Status::AllQueriesSubmitted)
+                && self.queries_mapped >= self.queries_submitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_progress() {
+        let events = Mutex::new(Vec::new());
+        let listener = |event: MappingEvent| events.lock().unwrap().push(event);
+
+        listener.send_event(MappingEvent::Status(Status::Indexing));
+        for id in ["read1", "read2"] {
+            listener.send_event(MappingEvent::QueryAdded(QuerySequence::new(
+                id.to_string(),
+                b"ACGT".to_vec(),
+            )));
+        }
+        listener.send_event(MappingEvent::Status(Status::AllQueriesSubmitted));
+        listener.send_event(MappingEvent::MappingsProduced {
+            query_id: "read1".to_string(),
+            mappings: vec![Mapping::default(), Mapping::default()],
+        });
+
+        let mut progress = Progress::default();
+        for event in events.lock().unwrap().iter() {
+            progress.update(event);
+        }
+        assert_eq!(
+            progress,
+            Progress {
+                queries_submitted: 2,
+                queries_mapped: 1,
+                queries_with_mappings: 1,
+                mappings: 2,
+                status: Some(Status::AllQueriesSubmitted),
+            }
+        );
+        assert!(!progress.is_complete());
+
+        progress.update(&MappingEvent::MappingsProduced {
+            query_id: "read2".to_string(),
+            mappings: Vec::new(),
+        });
+        assert!(progress.is_complete());
+        assert_eq!(progress.queries_with_mappings, 1);
+
+        assert_eq!(Status::Indexing.to_string(), "Indexing Sequence");
+    }
+}
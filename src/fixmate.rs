@@ -0,0 +1,411 @@
+//! Mate information and name sorting for paired BAM records, as samtools fixmate and sort -n
+//!
+//! Duplicate marking tools such as samtools markdup expect name-grouped pairs with mate
+//! information filled in: mate position and strand, the MC (mate CIGAR), MQ (mate MAPQ) and
+//! ms (mate score) tags, template length and the proper-pair flag. [`pair_records`] turns the
+//! records of two mates mapped with [`map_to_sam`](crate::Aligner::map_to_sam) into such a
+//! pair, [`fixmate`] fills in the mate information of an existing template and
+//! [`sort_by_name`] with [`name_sorted_header`] gives name-sorted output.
+//!
+//! ```
+//! use minimap2::fixmate::{name_sorted_header, pair_records, DEFAULT_MAX_INSERT};
+//! use minimap2::*;
+//! use rust_htslib::bam::HeaderView;
+//!
+//! let aligner = Aligner::builder()
+//!     .sr()
+//!     .with_cigar()
+//!     .with_index("test_data/genome.fa", None)
+//!     .unwrap();
+//! let header = HeaderView::from_header(&name_sorted_header(&aligner));
+//!
+//! let chr1 = aligner.target_seq(0).unwrap();
+//! let read1 = &chr1[100..250];
+//! let read2 = minimap2::seq::revcomp(&chr1[400..550]);
+//! let first = aligner.map_to_sam(read1, None, Some(b"pair"), &header, None, None).unwrap();
+//! let second = aligner.map_to_sam(&read2, None, Some(b"pair"), &header, None, None).unwrap();
+//!
+//! let pair = pair_records(first, second, DEFAULT_MAX_INSERT).unwrap();
+//! assert!(pair.iter().all(|r| r.is_proper_pair()));
+//! ```
+
+use std::cmp::Ordering;
+
+use rust_htslib::bam::header::HeaderRecord;
+use rust_htslib::bam::record::{Aux, Record};
+use rust_htslib::bam::Header;
+
+use crate::{Aligner, Built};
+
+/// Template length above which mates are not properly paired
+pub const DEFAULT_MAX_INSERT: i64 = 1000;
+
+/// Qualities counted by the ms tag, as samtools fixmate
+const MATE_SCORE_MIN_QUALITY: u8 = 15;
+
+fn is_primary(record: &Record) -> bool {
+    !record.is_secondary() && !record.is_supplementary()
+}
+
+/// What a record tells its mate
+struct MateInfo {
+    tid: i32,
+    pos: i64,
+    end: i64,
+    reverse: bool,
+    unmapped: bool,
+    cigar: String,
+    mapq: u8,
+    score: i32,
+}
+
+impl MateInfo {
+    fn new(record: &Record) -> Self {
+        let qual = record.qual();
+        // Missing qualities are stored as 0xff
+        let score = if qual.first() == Some(&255) {
+            0
+        } else {
+            qual.iter()
+                .filter(|&&q| q >= MATE_SCORE_MIN_QUALITY)
+                .map(|&q| q as i32)
+                .sum()
+        };
+        MateInfo {
+            tid: record.tid(),
+            pos: record.pos(),
+            end: record.cigar().end_pos(),
+            reverse: record.is_reverse(),
+            unmapped: record.is_unmapped(),
+            cigar: record.cigar().to_string(),
+            mapq: record.mapq(),
+            score,
+        }
+    }
+}
+
+fn set_aux(record: &mut Record, tag: &[u8], value: Aux) -> Result<(), &'static str> {
+    // Absent tags are fine
+    let _ = record.remove_aux(tag);
+    record
+        .push_aux(tag, value)
+        .map_err(|_| "Unable to set BAM tag")
+}
+
+/// Fill in the mate information of the primary records of a template: the records of one
+/// read pair, with the first and last segment flags set
+///
+/// Sets the mate position, strand and unmapped flags, the MC, MQ and ms tags, the template
+/// length (positive for the leftmost mate) and the proper-pair flag, which requires both
+/// mates on the same target, facing each other (forward-reverse) and a template length up to
+/// `max_insert`. An unmapped mate is placed at its partner's position. minimap2's own ms tag
+/// (DP score) is replaced by the mate score markdup expects. Secondary and supplementary
+/// records are left unchanged.
+pub fn fixmate(records: &mut [Record], max_insert: i64) -> Result<(), &'static str> {
+    let find = |first: bool| {
+        records.iter().position(|r| {
+            is_primary(r)
+                && if first {
+                    r.is_first_in_template()
+                } else {
+                    r.is_last_in_template()
+                }
+        })
+    };
+    let (i, j) = match (find(true), find(false)) {
+        (Some(i), Some(j)) if i != j => (i, j),
+        _ => return Err("Template has no primary record for both mates"),
+    };
+
+    let (r1, r2) = if i < j {
+        let (left, right) = records.split_at_mut(j);
+        (&mut left[i], &mut right[0])
+    } else {
+        let (left, right) = records.split_at_mut(i);
+        (&mut right[0], &mut left[j])
+    };
+
+    // Place an unmapped mate at its partner
+    if r1.is_unmapped() && !r2.is_unmapped() {
+        r1.set_tid(r2.tid());
+        r1.set_pos(r2.pos());
+    } else if r2.is_unmapped() && !r1.is_unmapped() {
+        r2.set_tid(r1.tid());
+        r2.set_pos(r1.pos());
+    }
+
+    let info1 = MateInfo::new(r1);
+    let info2 = MateInfo::new(r2);
+
+    let paired = !info1.unmapped && !info2.unmapped && info1.tid == info2.tid;
+    let (tlen, proper) = if paired {
+        let tlen = info1.end.max(info2.end) - info1.pos.min(info2.pos);
+        // The forward mate must start no later than the reverse one
+        let facing = match (info1.reverse, info2.reverse) {
+            (false, true) => info1.pos <= info2.pos,
+            (true, false) => info2.pos <= info1.pos,
+            _ => false,
+        };
+        (tlen, facing && tlen <= max_insert)
+    } else {
+        (0, false)
+    };
+    // Ties go to the first mate
+    let r1_leftmost = info1.pos <= info2.pos;
+
+    for (record, mate, leftmost) in [(r1, &info2, r1_leftmost), (r2, &info1, !r1_leftmost)] {
+        record.set_paired();
+        record.set_mtid(mate.tid);
+        record.set_mpos(mate.pos);
+        if mate.reverse {
+            record.set_mate_reverse();
+        } else {
+            record.unset_mate_reverse();
+        }
+        if mate.unmapped {
+            record.set_mate_unmapped();
+        } else {
+            record.unset_mate_unmapped();
+        }
+        if proper {
+            record.set_proper_pair();
+        } else {
+            record.unset_proper_pair();
+        }
+        record.set_insert_size(if leftmost { tlen } else { -tlen });
+
+        if !mate.unmapped {
+            set_aux(record, b"MC", Aux::String(&mate.cigar))?;
+            set_aux(record, b"MQ", Aux::I32(mate.mapq as i32))?;
+        }
+        set_aux(record, b"ms", Aux::I32(mate.score))?;
+    }
+
+    Ok(())
+}
+
+/// Make a template of the records of two mates, e.g. from
+/// [`map_to_sam`](crate::Aligner::map_to_sam), setting the paired and segment flags and
+/// filling in the mate information with [`fixmate`]
+///
+/// Each mate needs a primary record, mapped or unmapped, so don't use `with_sam_hit_only`.
+pub fn pair_records(
+    first: Vec<Record>,
+    second: Vec<Record>,
+    max_insert: i64,
+) -> Result<Vec<Record>, &'static str> {
+    let mut records = Vec::with_capacity(first.len() + second.len());
+    for mut record in first {
+        record.set_paired();
+        record.set_first_in_template();
+        record.unset_last_in_template();
+        records.push(record);
+    }
+    for mut record in second {
+        record.set_paired();
+        record.set_last_in_template();
+        record.unset_first_in_template();
+        records.push(record);
+    }
+    fixmate(&mut records, max_insert)?;
+    Ok(records)
+}
+
+fn name_order(a: &Record, b: &Record) -> Ordering {
+    let segment = |r: &Record| match (r.is_first_in_template(), r.is_last_in_template()) {
+        (true, false) => 0,
+        (false, true) => 1,
+        _ => 2,
+    };
+    a.qname()
+        .cmp(b.qname())
+        .then_with(|| segment(a).cmp(&segment(b)))
+        .then_with(|| is_primary(b).cmp(&is_primary(a)))
+}
+
+/// Sort records by name, the first mate before the second and primary records first
+///
+/// Names are compared bytewise, not with the natural order of samtools sort -n. The sort is
+/// stable, so the records of a read keep minimap2's order otherwise.
+pub fn sort_by_name(records: &mut [Record]) {
+    records.sort_by(name_order);
+}
+
+/// Sort records by name and run [`fixmate`] on each template
+///
+/// Reads without a primary record for both mates (single-end reads) are left unchanged.
+pub fn sort_and_fixmate(records: &mut [Record], max_insert: i64) -> Result<(), &'static str> {
+    sort_by_name(records);
+    let mut start = 0;
+    while start < records.len() {
+        let end = start
+            + records[start..]
+                .iter()
+                .take_while(|r| r.qname() == records[start].qname())
+                .count();
+        let template = &mut records[start..end];
+        if template.iter().any(|r| r.is_paired()) {
+            fixmate(template, max_insert)?;
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+/// A header for name-sorted output (`@HD SO:queryname`) with the targets of the index
+pub fn name_sorted_header(aligner: &Aligner<Built>) -> Header {
+    let mut header = Header::new();
+    header.push_record(
+        HeaderRecord::new(b"HD")
+            .push_tag(b"VN", "1.6")
+            .push_tag(b"SO", "queryname"),
+    );
+    aligner.populate_header(&mut header);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seq::revcomp;
+    use rust_htslib::bam::HeaderView;
+
+    fn map_pair(
+        aligner: &Aligner<Built>,
+        header: &HeaderView,
+        name: &[u8],
+        read1: &[u8],
+        read2: &[u8],
+    ) -> (Vec<Record>, Vec<Record>) {
+        let qual = |seq: &[u8]| vec![b'5'; seq.len()];
+        (
+            aligner
+                .map_to_sam(read1, Some(&qual(read1)), Some(name), header, None, None)
+                .unwrap(),
+            aligner
+                .map_to_sam(read2, Some(&qual(read2)), Some(name), header, None, None)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_pair_records() {
+        let aligner = Aligner::builder()
+            .sr()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let header = name_sorted_header(&aligner);
+        let hd = header.to_hashmap();
+        assert_eq!(hd.get("HD").unwrap()[0].get("SO").unwrap(), "queryname");
+        let header = HeaderView::from_header(&header);
+
+        let chr1 = aligner.target_seq(0).unwrap();
+        let (first, second) = map_pair(
+            &aligner,
+            &header,
+            b"pair",
+            &chr1[100..250],
+            &revcomp(&chr1[400..550]),
+        );
+        let pair = pair_records(first, second, DEFAULT_MAX_INSERT).unwrap();
+        let r1 = pair.iter().find(|r| r.is_first_in_template()).unwrap();
+        let r2 = pair.iter().find(|r| r.is_last_in_template()).unwrap();
+
+        for (record, mate) in [(r1, r2), (r2, r1)] {
+            assert!(record.is_paired() && record.is_proper_pair());
+            assert_eq!((record.mtid(), record.mpos()), (mate.tid(), mate.pos()));
+            assert_eq!(record.is_mate_reverse(), mate.is_reverse());
+            assert_eq!(record.aux(b"MC").unwrap(), Aux::String("150M"));
+            assert_eq!(record.aux(b"MQ").unwrap(), Aux::I32(mate.mapq() as i32));
+            // '5' is quality 20, counted by the mate score
+            assert_eq!(record.aux(b"ms").unwrap(), Aux::I32(150 * 20));
+        }
+        assert_eq!((r1.insert_size(), r2.insert_size()), (450, -450));
+
+        // Too far apart for a proper pair
+        let (first, second) = map_pair(
+            &aligner,
+            &header,
+            b"far",
+            &chr1[100..250],
+            &revcomp(&chr1[400..550]),
+        );
+        let pair = pair_records(first, second, 300).unwrap();
+        assert!(pair.iter().all(|r| !r.is_proper_pair()));
+
+        // Same strand
+        let (first, second) =
+            map_pair(&aligner, &header, b"same", &chr1[100..250], &chr1[400..550]);
+        let pair = pair_records(first, second, DEFAULT_MAX_INSERT).unwrap();
+        assert!(pair.iter().all(|r| !r.is_proper_pair()));
+
+        // An unmapped mate is placed at its partner
+        let (first, second) = map_pair(
+            &aligner,
+            &header,
+            b"unmapped",
+            &chr1[100..250],
+            b"ACGTACGTACGTACGTACGTACGTACGTACGTACGT",
+        );
+        let pair = pair_records(first, second, DEFAULT_MAX_INSERT).unwrap();
+        let r1 = pair.iter().find(|r| r.is_first_in_template()).unwrap();
+        let r2 = pair.iter().find(|r| r.is_last_in_template()).unwrap();
+        assert!(r2.is_unmapped() && r1.is_mate_unmapped());
+        assert_eq!((r2.tid(), r2.pos()), (r1.tid(), r1.pos()));
+        assert!(r1.aux(b"MC").is_err());
+        assert_eq!(r2.aux(b"MC").unwrap(), Aux::String("150M"));
+    }
+
+    #[test]
+    fn test_sort_and_fixmate() {
+        let aligner = Aligner::builder()
+            .sr()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let header = HeaderView::from_header(&name_sorted_header(&aligner));
+        let chr1 = aligner.target_seq(0).unwrap();
+
+        let mut records = Vec::new();
+        for name in [&b"b"[..], b"a"] {
+            let (first, second) = map_pair(
+                &aligner,
+                &header,
+                name,
+                &chr1[100..250],
+                &revcomp(&chr1[400..550]),
+            );
+            for (mut record, first) in first
+                .into_iter()
+                .map(|r| (r, true))
+                .chain(second.into_iter().map(|r| (r, false)))
+            {
+                record.set_paired();
+                if first {
+                    record.set_first_in_template();
+                } else {
+                    record.set_last_in_template();
+                }
+                records.push(record);
+            }
+        }
+        records.reverse();
+
+        sort_and_fixmate(&mut records, DEFAULT_MAX_INSERT).unwrap();
+        let order: Vec<_> = records
+            .iter()
+            .map(|r| (r.qname().to_vec(), r.is_first_in_template()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (b"a".to_vec(), true),
+                (b"a".to_vec(), false),
+                (b"b".to_vec(), true),
+                (b"b".to_vec(), false),
+            ]
+        );
+        assert!(records.iter().all(|r| r.is_proper_pair()));
+    }
+}
@@ -0,0 +1,239 @@
+//! Haplotype-aware reporting against diploid or dual-haplotype references
+//!
+//! With both haplotypes of a phased or trio-binned assembly in one index, a read maps to its
+//! haplotype of origin and, usually as a secondary hit with a lower score, to the other one.
+//! [`Haplotypes`] assigns the targets of the index to haplotypes, by name suffix or from a
+//! table, and [`Haplotypes::assign`] reports the best hit of a read on each haplotype and by
+//! how much the best haplotype wins.
+//!
+//! Keep the secondary mappings (minimap2 keeps up to 5 by default, see `mapopt.best_n`) so
+//! that the hit on the other haplotype is seen, and map with alignment (`with_cigar`) to score
+//! hits by their alignment score rather than by their matching bases.
+
+use std::collections::HashMap;
+
+use crate::Mapping;
+
+/// Haplotype of each target of an index
+///
+/// ```
+/// use minimap2::haplotype::Haplotypes;
+///
+/// let haplotypes = Haplotypes::from_suffixes(&["_pat", "_mat"]);
+/// assert_eq!(haplotypes.haplotype("chr1_mat"), Some(1));
+/// assert_eq!(haplotypes.haplotype("chrM"), None);
+/// assert_eq!(haplotypes.labels(), ["_pat", "_mat"]);
+///
+/// let haplotypes = Haplotypes::from_map([("h1tg000001l", "hap1"), ("h2tg000007l", "hap2")]);
+/// assert_eq!(haplotypes.haplotype("h2tg000007l"), Some(1));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Haplotypes {
+    labels: Vec<String>,
+    suffixes: Vec<String>,
+    targets: HashMap<String, usize>,
+}
+
+impl Haplotypes {
+    /// Haplotypes of targets named with one of `suffixes`, e.g. `["_hap1", "_hap2"]`, labelled
+    /// by their suffix. The longest matching suffix wins.
+    pub fn from_suffixes<S: AsRef<str>>(suffixes: &[S]) -> Self {
+        let suffixes: Vec<String> = suffixes.iter().map(|s| s.as_ref().to_string()).collect();
+        Haplotypes {
+            labels: suffixes.clone(),
+            suffixes,
+            targets: HashMap::new(),
+        }
+    }
+
+    /// Haplotypes given as `(target, label)` pairs, numbered in order of first appearance of
+    /// their label
+    pub fn from_map<I, T, L>(targets: I) -> Self
+    where
+        I: IntoIterator<Item = (T, L)>,
+        T: AsRef<str>,
+        L: AsRef<str>,
+    {
+        let mut haplotypes = Haplotypes::default();
+        for (target, label) in targets {
+            let label = label.as_ref();
+            let i = match haplotypes.labels.iter().position(|l| l == label) {
+                Some(i) => i,
+                None => {
+                    haplotypes.labels.push(label.to_string());
+                    haplotypes.labels.len() - 1
+                }
+            };
+            haplotypes.targets.insert(target.as_ref().to_string(), i);
+        }
+        haplotypes
+    }
+
+    /// Labels of the haplotypes, indexed by haplotype
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Haplotype of `target`, or None if it is in no haplotype (e.g. an unphased contig)
+    pub fn haplotype(&self, target: &str) -> Option<usize> {
+        if let Some(&i) = self.targets.get(target) {
+            return Some(i);
+        }
+        self.suffixes
+            .iter()
+            .enumerate()
+            .filter(|(_, suffix)| target.ends_with(suffix.as_str()))
+            .max_by_key(|(_, suffix)| suffix.len())
+            .map(|(i, _)| i)
+    }
+
+    /// Best hit of a read on each haplotype, from its `mappings` as returned by
+    /// [`Aligner::map`](crate::Aligner::map)
+    ///
+    /// Hits are scored by their alignment score, or by their matching bases for mappings
+    /// without alignment. Mappings to targets in no haplotype are ignored.
+    pub fn assign(&self, mappings: &[Mapping]) -> HaplotypeReport {
+        let mut best: Vec<Option<HaplotypeHit>> = vec![None; self.labels.len()];
+        for (i, mapping) in mappings.iter().enumerate() {
+            let Some(haplotype) = mapping
+                .target_name
+                .as_deref()
+                .and_then(|name| self.haplotype(name))
+            else {
+                continue;
+            };
+            let score = score(mapping);
+            if !best[haplotype].is_some_and(|hit| hit.score >= score) {
+                best[haplotype] = Some(HaplotypeHit { mapping: i, score });
+            }
+        }
+
+        // Best and runner-up haplotype
+        let mut ranked: Vec<(usize, i32)> = best
+            .iter()
+            .enumerate()
+            .filter_map(|(haplotype, hit)| hit.map(|hit| (haplotype, hit.score)))
+            .collect();
+        ranked.sort_by_key(|&(haplotype, score)| (std::cmp::Reverse(score), haplotype));
+        let (assigned, delta) = match ranked[..] {
+            [] => (None, 0),
+            [(haplotype, score)] => (Some(haplotype), score),
+            [(first, score), (_, second), ..] => {
+                ((score > second).then_some(first), score - second)
+            }
+        };
+
+        HaplotypeReport {
+            best,
+            assigned,
+            delta,
+        }
+    }
+}
+
+/// The best hit of a read on a haplotype
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HaplotypeHit {
+    /// Index of the mapping in the slice given to [`Haplotypes::assign`]
+    pub mapping: usize,
+    pub score: i32,
+}
+
+/// Hits of a read on each haplotype, see [`Haplotypes::assign`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HaplotypeReport {
+    /// Best hit on each haplotype, indexed like [`Haplotypes::labels`]; None where the read
+    /// has no hit
+    pub best: Vec<Option<HaplotypeHit>>,
+    /// Haplotype with the best hit, None if the read hits no haplotype or its best hits on
+    /// two haplotypes score the same
+    pub assigned: Option<usize>,
+    /// Score of the best hit over that of the best hit on another haplotype, or the whole
+    /// score if no other haplotype is hit
+    pub delta: i32,
+}
+
+/// Score of a hit: its alignment score, or its matching bases without alignment
+fn score(mapping: &Mapping) -> i32 {
+    mapping
+        .alignment
+        .as_ref()
+        .and_then(|aln| aln.alignment_score)
+        .unwrap_or(mapping.match_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mt_seq, Aligner};
+
+    #[test]
+    fn test_assign() {
+        let mt = mt_seq();
+        let hap1 = mt[0..6000].to_vec();
+        // hap2 differs from hap1 by a SNP every 500 bp
+        let mut hap2 = hap1.clone();
+        for i in (250..hap2.len()).step_by(500) {
+            hap2[i] = if hap2[i] == b'A' { b'C' } else { b'A' };
+        }
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_seqs_and_ids(
+                &[hap1, hap2.clone(), mt[8000..12000].to_vec()],
+                &[
+                    b"ctg_hap1".to_vec(),
+                    b"ctg_hap2".to_vec(),
+                    b"ctg_unphased".to_vec(),
+                ],
+            )
+            .unwrap();
+        let haplotypes = Haplotypes::from_suffixes(&["_hap1", "_hap2"]);
+
+        let mappings = aligner
+            .map(&hap2[1000..4000], false, false, None, None, None)
+            .unwrap();
+        let report = haplotypes.assign(&mappings);
+        assert_eq!(report.assigned, Some(1));
+        let (hit1, hit2) = (report.best[0].unwrap(), report.best[1].unwrap());
+        assert_eq!(
+            mappings[hit2.mapping].target_name.as_deref().unwrap(),
+            "ctg_hap2"
+        );
+        assert_eq!(report.delta, hit2.score - hit1.score);
+        assert!(report.delta > 0);
+
+        // Reads on unphased targets are in no haplotype
+        let mappings = aligner
+            .map(&mt[9000..11000], false, false, None, None, None)
+            .unwrap();
+        assert!(!mappings.is_empty());
+        let report = haplotypes.assign(&mappings);
+        assert_eq!(report.best, [None, None]);
+        assert_eq!((report.assigned, report.delta), (None, 0));
+    }
+
+    #[test]
+    fn test_ties_and_single_hits() {
+        let hit = |target: &str, match_len| Mapping {
+            target_name: Some(std::sync::Arc::new(target.to_string())),
+            match_len,
+            ..Default::default()
+        };
+        let haplotypes = Haplotypes::from_map([("a", "pat"), ("b", "mat"), ("c", "mat")]);
+        assert_eq!(haplotypes.labels(), ["pat", "mat"]);
+
+        let report = haplotypes.assign(&[hit("a", 100), hit("b", 90), hit("c", 100)]);
+        assert_eq!(
+            report.best[1],
+            Some(HaplotypeHit {
+                mapping: 2,
+                score: 100
+            })
+        );
+        assert_eq!((report.assigned, report.delta), (None, 0));
+
+        let report = haplotypes.assign(&[hit("b", 90)]);
+        assert_eq!((report.assigned, report.delta), (Some(1), 90));
+    }
+}
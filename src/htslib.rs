@@ -43,39 +43,127 @@
 //! ```
 
 use super::ffi as mm_ffi;
-use crate::{Aligner, Built, Mapping, Strand, BUF};
+use crate::paf::cigar_op_char;
+use crate::{
+    seq_meta_data, with_buf, Aligner, Alignment, AlignmentType, Built, CommentPassthrough,
+    DpSkipped, Mapping, QualityMismatch, QueryOrientation, RecordTags, Strand, TagValue,
+};
 use rust_htslib::bam::header::HeaderRecord;
-use rust_htslib::bam::record::{Cigar, CigarString};
-use rust_htslib::bam::{Header, HeaderView, Record};
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+use rust_htslib::bam::{self, Header, HeaderView, Record};
+use std::borrow::Cow;
 use std::ffi::{CStr, CString};
-use std::mem::MaybeUninit;
+use std::num::NonZeroI32;
+use std::path::Path;
 use std::ptr;
 use std::sync::Arc;
 
 /// A wrapper around mm_bseq1_t
+///
+/// The strings of `inner` are owned by the query and freed when it is dropped, so it is only
+/// exposed read-only.
 #[derive(Debug)]
 pub struct Query {
-    pub inner: mm_ffi::mm_bseq1_t,
+    inner: mm_ffi::mm_bseq1_t,
+}
+
+/// Error creating a [`Query`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryError {
+    EmptySequence,
+    /// The quality string and sequence differ in length, with [`QualityMismatch::Error`]
+    QualityLengthMismatch {
+        seq_len: usize,
+        qual_len: usize,
+    },
+    /// The sequence, quality string or name contains a NUL byte (a name may end with one)
+    InteriorNul,
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::EmptySequence => write!(f, "Empty sequence supplied"),
+            QueryError::QualityLengthMismatch { seq_len, qual_len } => write!(
+                f,
+                "Quality string of length {qual_len} for sequence of length {seq_len}"
+            ),
+            QueryError::InteriorNul => write!(f, "NUL byte in sequence, quality or name"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl From<QueryError> for &'static str {
+    fn from(e: QueryError) -> Self {
+        match e {
+            QueryError::EmptySequence => "Sequence is empty",
+            QueryError::QualityLengthMismatch { .. } => {
+                "Sequence and quality strings are different lengths"
+            }
+            QueryError::InteriorNul => "NUL byte in sequence, quality or name",
+        }
+    }
 }
 
 impl Query {
+    /// Panics on an empty sequence, a quality string of the wrong length or a NUL byte, see
+    /// [`try_new`](Query::try_new)
     pub fn new(seq: &[u8], qual: Option<&[u8]>, name: Option<&[u8]>) -> Self {
-        let l_seq = seq.len();
-        assert!(l_seq > 0, "Empty sequence supplied");
-        // clone into a CString
-        let seq = CString::new(seq).unwrap().into_raw();
-        let qual = match qual {
-            Some(qual) => {
-                assert_eq!(
-                    l_seq,
-                    qual.len(),
-                    "Sequence and quality strings are different lenght"
-                );
-                CString::new(qual).unwrap().into_raw()
-            }
-            None => ptr::null_mut(),
+        Query::try_new(seq, qual, name, QualityMismatch::Error).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a query, handling a quality string whose length differs from the sequence
+    /// according to `policy`
+    pub fn try_new(
+        seq: &[u8],
+        qual: Option<&[u8]>,
+        name: Option<&[u8]>,
+        policy: QualityMismatch,
+    ) -> Result<Self, QueryError> {
+        if seq.is_empty() {
+            return Err(QueryError::EmptySequence);
+        }
+
+        let mut seq = seq;
+        let qual: Option<std::borrow::Cow<[u8]>> = match qual {
+            Some(qual) if qual.len() != seq.len() => match policy {
+                QualityMismatch::Error => {
+                    return Err(QueryError::QualityLengthMismatch {
+                        seq_len: seq.len(),
+                        qual_len: qual.len(),
+                    })
+                }
+                QualityMismatch::Trim => {
+                    let len = seq.len().min(qual.len());
+                    if len == 0 {
+                        return Err(QueryError::EmptySequence);
+                    }
+                    seq = &seq[..len];
+                    Some(qual[..len].into())
+                }
+                QualityMismatch::Pad => {
+                    let mut padded = qual[..qual.len().min(seq.len())].to_vec();
+                    padded.resize(seq.len(), b'!');
+                    Some(padded.into())
+                }
+            },
+            qual => qual.map(|qual| qual.into()),
         };
-        let name = CString::new(name.unwrap_or(b"query")).unwrap();
+
+        let l_seq = seq.len();
+        // clone into CStrings, all checked before any is leaked into `inner`
+        let seq = CString::new(seq).map_err(|_| QueryError::InteriorNul)?;
+        let qual = qual
+            .map(|qual| CString::new(qual.into_owned()))
+            .transpose()
+            .map_err(|_| QueryError::InteriorNul)?;
+        let name = name.unwrap_or(b"query");
+        let name = CString::new(name.strip_suffix(b"\0").unwrap_or(name))
+            .map_err(|_| QueryError::InteriorNul)?;
+        let seq = seq.into_raw();
+        let qual = qual.map_or(ptr::null_mut(), CString::into_raw);
 
         let inner = mm_ffi::mm_bseq1_t {
             l_seq: l_seq as i32,
@@ -83,23 +171,41 @@ impl Query {
             name: name.into_raw(),
             seq,
             qual,
-            comment: ptr::null_mut(), // TODO: pass SAM flags in comment
+            comment: ptr::null_mut(), // Comments are added to the records, see add_comment
         };
-        Query { inner }
+        Ok(Query { inner })
+    }
+
+    /// The minimap2 sequence record, valid as long as the query
+    pub fn inner(&self) -> &mm_ffi::mm_bseq1_t {
+        &self.inner
+    }
+
+    pub fn name(&self) -> &[u8] {
+        unsafe { CStr::from_ptr(self.inner.name).to_bytes() }
+    }
+
+    pub fn seq(&self) -> &[u8] {
+        unsafe { CStr::from_ptr(self.inner.seq).to_bytes() }
+    }
+
+    /// The quality string, if one was given
+    pub fn qual(&self) -> Option<&[u8]> {
+        if self.inner.qual.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(self.inner.qual).to_bytes() })
+        }
     }
 
     pub fn as_unmapped_record(&self) -> Record {
         let mut rec = Record::new();
 
-        let qname = unsafe { CStr::from_ptr(self.inner.name).to_bytes() };
-        let seq = unsafe { CStr::from_ptr(self.inner.seq).to_bytes() };
-        let qual = if self.inner.qual.is_null() {
-            rec.set(qname, None, seq, &vec![255u8; seq.len()]);
-        } else {
-            rec.set(qname, None, seq, unsafe {
-                CStr::from_ptr(self.inner.qual).to_bytes()
-            });
-        };
+        let (qname, seq) = (self.name(), self.seq());
+        match self.qual() {
+            Some(qual) => rec.set(qname, None, seq, qual),
+            None => rec.set(qname, None, seq, &vec![255u8; seq.len()]),
+        }
         rec.set_unmapped();
         rec.set_tid(-1);
         rec.set_pos(-1);
@@ -111,14 +217,31 @@ impl Query {
     }
 }
 
+// The strings were allocated by Rust (CString::into_raw), so they must be freed by Rust and
+// never by minimap2, whatever the global allocator
+impl Drop for Query {
+    fn drop(&mut self) {
+        unsafe {
+            for ptr in [self.inner.seq, self.inner.qual, self.inner.name] {
+                if !ptr.is_null() {
+                    drop(CString::from_raw(ptr));
+                }
+            }
+        }
+    }
+}
+
 impl Aligner<Built> {
+    /// Add an @SQ line for each target of the index to `header`
     pub fn populate_header(&self, header: &mut Header) {
-        let mm_idx = MMIndex::from(self);
-        for seq in mm_idx.seqs() {
+        let names = &self.target_names().names;
+        let idx: &mm_ffi::mm_idx_t = self.idx.as_ref().unwrap();
+        for (i, name) in names.iter().enumerate() {
+            let length = unsafe { (*idx.seq.add(i)).len };
             header.push_record(
                 HeaderRecord::new(b"SQ")
-                    .push_tag(b"SN", &seq.name)
-                    .push_tag(b"LN", &seq.length),
+                    .push_tag(b"SN", name.as_str())
+                    .push_tag(b"LN", &length),
             );
         }
     }
@@ -137,89 +260,519 @@ impl Aligner<Built> {
             return Err("No index");
         }
 
-        let query = Query::new(seq, qual, name);
+        let reverse = self.query_orientation == QueryOrientation::ReverseComplement;
+        let seq = self.orient_query(seq);
+        let qual: Option<Cow<[u8]>> = qual.map(|qual| {
+            if reverse {
+                Cow::Owned(qual.iter().rev().copied().collect())
+            } else {
+                Cow::Borrowed(qual)
+            }
+        });
+        let query = Query::try_new(&seq, qual.as_deref(), name, self.quality_mismatch)?;
+        self.check_query_len(seq.len())?;
         // Number of results
         let mut n_regs: i32 = 0;
-        let mut map_opt = self.mapopt.clone();
-
         // TODO: other flags to consider:
-        // MM_F_NO_PRINT_2ND
         // MM_F_SAM_HIT_ONLY (though this seems to be the default?)
         //map_opt.flag |= mm_ffi::MM_F_OUT_SAM as i64;
         //map_opt.flag |= mm_ffi::MM_F_CIGAR as i64;
+        let map_opt = self.call_mapopt(max_frag_len, extra_flags.as_deref(), name);
 
-        // if max_frag_len is not None: map_opt.max_frag_len = max_frag_len
-        if let Some(max_frag_len) = max_frag_len {
-            map_opt.max_frag_len = max_frag_len as i32;
-        }
-
-        // if extra_flags is not None: map_opt.flag |= extra_flags
-        if let Some(extra_flags) = extra_flags {
-            for flag in extra_flags {
-                map_opt.flag |= flag as i64;
-            }
-        }
-
-        let mappings = BUF.with(|buf| {
-            //let km = unsafe { mm_ffi::mm_tbuf_get_km(buf.borrow_mut().buf) };
+        // For the mappings handed to `record_tags`
+        let query_seq = unsafe {
+            std::slice::from_raw_parts(query.inner.seq as *const u8, query.inner.l_seq as usize)
+        };
+        let query_name = name.map(|name| Arc::new(String::from_utf8_lossy(name).into_owned()));
 
-            let mm_reg = MaybeUninit::new(unsafe {
+        let mut mappings = with_buf(|buf| -> Result<Vec<Record>, &'static str> {
+            let regs = unsafe {
                 mm_ffi::mm_map(
                     &**self.idx.as_ref().unwrap().as_ref() as *const mm_ffi::mm_idx_t,
                     query.inner.l_seq,
                     query.inner.seq as *const libc::c_char,
                     &mut n_regs,
-                    buf.borrow_mut().buf,
+                    buf.get_buf(),
                     &map_opt,
                     query.inner.name,
                 )
-            });
+            };
             // FIXFIX: mm_map should return unmapped SAM records but it
             //  currently doesn't seem to work. To work around this we create the
             // record manually
             if (n_regs == 0) & ((map_opt.flag & mm_ffi::MM_F_SAM_HIT_ONLY as i64) == 0) {
-                return vec![query.as_unmapped_record()];
+                unsafe { libc::free(regs as *mut libc::c_void) };
+                return Ok(vec![query.as_unmapped_record()]);
             }
 
-            let mut mappings = Vec::with_capacity(n_regs as usize);
-
-            for i in 0..n_regs {
-                let sam_str = unsafe {
-                    let mut result: MaybeUninit<mm_ffi::kstring_t> = MaybeUninit::zeroed();
-                    let reg_ptr = (*mm_reg.as_ptr()).offset(i as isize);
-                    //    // println!("{:#?}", *reg_ptr);
-                    let const_ptr = reg_ptr as *const mm_ffi::mm_reg1_t;
-                    // TODO: use mm_write_sam3 t do the writing so that we can pass the map_opt flags
-                    mm_ffi::mm_write_sam(
-                        result.as_mut_ptr(),
-                        &**self.idx.as_ref().unwrap().as_ref() as *const mm_ffi::mm_idx_t,
-                        &query.inner as *const mm_ffi::mm_bseq1_t,
-                        const_ptr,
-                        n_regs,
-                        *mm_reg.as_ptr() as *const mm_ffi::mm_reg1_t,
+            n_regs = unsafe { self.filter_secondary(regs, n_regs, map_opt.flag) };
+            let idx: &mm_ffi::mm_idx_t = self.idx.as_ref().unwrap();
+            let (km, rep_len) = unsafe {
+                (
+                    mm_ffi::mm_tbuf_get_km(buf.get_buf()),
+                    (*buf.get_buf()).rep_len,
+                )
+            };
+
+            // Written with the flags of map_opt, so that cs, MD, =/X operations and soft
+            // clipping are as set on the aligner. All records are written before any hit is
+            // freed, as the SA tag of each reads the others.
+            let mut sam: mm_ffi::kstring_t = unsafe { std::mem::zeroed() };
+            let records = (0..n_regs)
+                .map(|i| unsafe {
+                    sam.l = 0;
+                    mm_ffi::mm_write_sam3(
+                        &mut sam,
+                        idx,
+                        &query.inner,
+                        0,
+                        i,
+                        1,
+                        &n_regs,
+                        &(regs as *const mm_ffi::mm_reg1_t),
+                        km,
+                        map_opt.flag,
+                        rep_len,
                     );
-                    //mm_ffi::mm_write_sam3(
-                    //    result.as_mut_ptr(),
-                    //    self.idx.as_ref().unwrap() as *const mm_ffi::mm_idx_t,
-                    //    &read  as *const mm_ffi::mm_bseq1_t,
-                    //    0, // seg_idx doesn't apply here (think it's a batch index)
-                    //    i,
-                    //    1, // only 1 segment
-                    //    n_regs as *const i32,
-                    //    &const_ptr,
-                    //    km,
-                    //    map_opt.flag,
-                    //    0
-                    //);
-                    CStr::from_ptr((*result.as_ptr()).s)
-                };
-                let record = Record::from_sam(header, sam_str.to_bytes()).unwrap();
-                mappings.push(record);
+                    Record::from_sam(header, CStr::from_ptr(sam.s).to_bytes())
+                        .map_err(|_| "Invalid SAM record")
+                })
+                .collect::<Result<Vec<Record>, &'static str>>();
+            unsafe { libc::free(sam.s as *mut libc::c_void) };
+
+            let mut records = match records {
+                Ok(records) => records,
+                Err(e) => {
+                    unsafe { crate::free_hits(regs, n_regs) };
+                    return Err(e);
+                }
+            };
+            match &self.record_tags {
+                Some(record_tags) => {
+                    let mappings: Vec<Mapping> = (0..n_regs as usize)
+                        .map(|i| unsafe {
+                            self.reg_to_mapping(regs.add(i), query_seq, false, false, &query_name)
+                        })
+                        .collect();
+                    unsafe { libc::free(regs as *mut libc::c_void) };
+                    for (record, mapping) in records.iter_mut().zip(&mappings) {
+                        add_record_tags(record, name.unwrap_or_default(), mapping, record_tags)?;
+                    }
+                }
+                None => unsafe { crate::free_hits(regs, n_regs) },
             }
-            mappings
-        });
+            Ok(records)
+        })?;
+        // SEQ and QUAL hold the reverse complement of the read
+        if reverse {
+            for record in mappings.iter_mut() {
+                record
+                    .push_aux(b"rc", Aux::I32(1))
+                    .map_err(|_| "Unable to set BAM tag")?;
+            }
+        }
         Ok(mappings)
     }
+
+    /// [`map_to_sam`](Aligner::map_to_sam) for a read with a FASTA/Q header comment, which
+    /// is added to each record as set by
+    /// [`with_comment_passthrough`](Aligner::with_comment_passthrough)
+    ///
+    /// Tags the records already have, such as minimap2's NM, are not overwritten.
+    #[allow(clippy::too_many_arguments)]
+    pub fn map_to_sam_with_comment(
+        &self,
+        seq: &[u8],
+        qual: Option<&[u8]>,
+        name: Option<&[u8]>,
+        comment: Option<&[u8]>,
+        header: &HeaderView,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<Vec<u64>>,
+    ) -> Result<Vec<Record>, &'static str> {
+        let mut records = self.map_to_sam(seq, qual, name, header, max_frag_len, extra_flags)?;
+        if let Some(comment) = comment {
+            for record in records.iter_mut() {
+                add_comment(record, comment, self.comment_passthrough)?;
+            }
+        }
+        Ok(records)
+    }
+
+    /// Map an entire file and write the results to `output` as BAM
+    ///
+    /// The header is populated from the index. Reads are mapped in batches on `threads` worker
+    /// threads and written in input order, with only a few batches held in memory at a time.
+    /// Header comments are kept as set by
+    /// [`with_comment_passthrough`](Aligner::with_comment_passthrough).
+    #[cfg(feature = "map-file")]
+    pub fn map_file_to_bam<P, Q>(
+        &self,
+        input: P,
+        output: Q,
+        threads: usize,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        crate::batch::check_input(&input)?;
+
+        let mut header = Header::new();
+        self.populate_header(&mut header);
+
+        let header_view = HeaderView::from_header(&header);
+
+        let mut writer = bam::Writer::from_path(output, &header, bam::Format::Bam)
+            .map_err(|_| "Unable to create output file")?;
+
+        crate::batch::run(
+            self,
+            input,
+            threads,
+            |aligner, rec| {
+                let (name, comment) = split_read_id(&rec.id);
+                aligner.map_to_sam_with_comment(
+                    &rec.seq,
+                    rec.qual.as_deref(),
+                    Some(name),
+                    comment,
+                    &header_view,
+                    None,
+                    None,
+                )
+            },
+            |records| {
+                for record in records? {
+                    writer.write(&record).map_err(|_| "Unable to write BAM")?;
+                }
+                Ok(())
+            },
+        )
+    }
+}
+
+/// Split a FASTA/Q header into the read name and the comment after it, if any
+pub(crate) fn split_read_id(id: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match id.iter().position(|b| b.is_ascii_whitespace()) {
+        Some(i) => {
+            let comment = id[i..].trim_ascii();
+            (&id[..i], (!comment.is_empty()).then_some(comment))
+        }
+        None => (id, None),
+    }
+}
+
+/// Add a FASTA/Q header comment to `record` as `mode` says, keeping tags it already has
+pub(crate) fn add_comment(
+    record: &mut Record,
+    comment: &[u8],
+    mode: CommentPassthrough,
+) -> Result<(), &'static str> {
+    match mode {
+        CommentPassthrough::Drop => Ok(()),
+        CommentPassthrough::Tag(tag) => {
+            if record.aux(&tag).is_ok() {
+                return Ok(());
+            }
+            let comment = std::str::from_utf8(comment).map_err(|_| "Comment is not UTF-8")?;
+            record
+                .push_aux(&tag, Aux::String(comment))
+                .map_err(|_| "Unable to set BAM tag")
+        }
+        CommentPassthrough::SamTags => {
+            let separator = if comment.contains(&b'\t') {
+                b'\t'
+            } else {
+                b' '
+            };
+            for field in comment.split(|&b| b == separator) {
+                let Some((tag, value)) = parse_sam_tag(field) else {
+                    continue;
+                };
+                if record.aux(&tag).is_ok() {
+                    continue;
+                }
+                record
+                    .push_aux(&tag, value)
+                    .map_err(|_| "Unable to set BAM tag")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Add the tags `record_tags` gives for `mapping` of read `name` to `record`, keeping tags it
+/// already has
+pub(crate) fn add_record_tags(
+    record: &mut Record,
+    name: &[u8],
+    mapping: &Mapping,
+    record_tags: &RecordTags,
+) -> Result<(), &'static str> {
+    for (tag, value) in record_tags(name, mapping) {
+        if record.aux(&tag).is_ok() {
+            continue;
+        }
+        let value = match &value {
+            TagValue::Char(c) => Aux::Char(*c),
+            TagValue::Int(i) => Aux::I32(*i),
+            TagValue::Float(f) => Aux::Float(*f),
+            TagValue::String(s) => Aux::String(s),
+        };
+        record
+            .push_aux(&tag, value)
+            .map_err(|_| "Unable to set BAM tag")?;
+    }
+    Ok(())
+}
+
+/// Parse a `TAG:TYPE:VALUE` field of type A, i, f or Z
+fn parse_sam_tag(field: &[u8]) -> Option<([u8; 2], Aux<'_>)> {
+    let field = std::str::from_utf8(field).ok()?.trim();
+    let mut parts = field.splitn(3, ':');
+    let tag = parts.next()?.as_bytes();
+    let (kind, value) = (parts.next()?, parts.next()?);
+    if tag.len() != 2 || !tag[0].is_ascii_alphabetic() || !tag[1].is_ascii_alphanumeric() {
+        return None;
+    }
+    let value = match kind {
+        "A" if value.len() == 1 => Aux::Char(value.as_bytes()[0]),
+        "i" => Aux::I32(value.parse().ok()?),
+        "f" => Aux::Float(value.parse().ok()?),
+        "Z" => Aux::String(value),
+        _ => return None,
+    };
+    Some(([tag[0], tag[1]], value))
+}
+
+/// Tags minimap2 sets on its records, which are not copied from unmapped BAM input
+const MAPPING_TAGS: [&[u8]; 17] = [
+    b"NM", b"ms", b"AS", b"nn", b"tp", b"cm", b"s1", b"s2", b"de", b"dv", b"rl", b"cg", b"cs",
+    b"MD", b"SA", b"ts", b"zd",
+];
+
+/// Base modification tags, which index into the full read sequence
+const BASE_MOD_TAGS: [&[u8]; 5] = [b"MM", b"ML", b"Mm", b"Ml", b"MN"];
+
+impl Aligner<Built> {
+    /// Map a record of an unmapped BAM (uBAM), copying its tags to the output records
+    ///
+    /// The read is mapped in its original orientation, or as set by
+    /// [`with_query_orientation`](Aligner::with_query_orientation), with its qualities, as
+    /// with [`map_to_sam`](Aligner::map_to_sam). Tags of the input (RG, MM/ML, basecaller tags)
+    /// are added to each output record unless minimap2 sets the same tag, or only some of them
+    /// as set with [`with_tag_passthrough`](Aligner::with_tag_passthrough). Base modification
+    /// tags are only copied to records holding the full read sequence: secondary records have
+    /// no SEQ and supplementary records are hard clipped unless `mapopt.set_softclip()` is used.
+    /// Secondary and supplementary input records are skipped.
+    pub fn map_bam_record(
+        &self,
+        record: &Record,
+        header: &HeaderView,
+    ) -> Result<Vec<Record>, &'static str> {
+        if record.is_secondary() || record.is_supplementary() {
+            return Ok(Vec::new());
+        }
+
+        let mut seq = record.seq().as_bytes();
+        let mut qual = record.qual().to_vec();
+        if record.is_reverse() {
+            seq = crate::seq::revcomp(&seq);
+            qual.reverse();
+        }
+        // Missing qualities are stored as 0xff
+        let qual: Option<Vec<u8>> = match qual.first() {
+            None | Some(255) => None,
+            Some(_) => Some(qual.iter().map(|q| q.saturating_add(33)).collect()),
+        };
+
+        let mut records = self.map_to_sam(
+            &seq,
+            qual.as_deref(),
+            Some(record.qname()),
+            header,
+            None,
+            None,
+        )?;
+
+        // Base modification tags are relative to the read as given
+        let same_orientation = self.query_orientation == QueryOrientation::AsIs;
+        for output in records.iter_mut() {
+            let full_seq = same_orientation && output.seq_len() == seq.len();
+            for aux in record.aux_iter() {
+                let (tag, value) = aux.map_err(|_| "Unable to read BAM tag")?;
+                if !self.tag_passthrough.copies(tag)
+                    || MAPPING_TAGS.contains(&tag)
+                    || (!full_seq && BASE_MOD_TAGS.contains(&tag))
+                    || output.aux(tag).is_ok()
+                {
+                    continue;
+                }
+                output
+                    .push_aux(tag, value)
+                    .map_err(|_| "Unable to set BAM tag")?;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Map every record of an unmapped BAM, in order, with
+    /// [`map_bam_record`](Aligner::map_bam_record)
+    ///
+    /// `header` must hold the targets of the index, e.g. from
+    /// [`populate_header`](Aligner::populate_header).
+    pub fn map_unmapped_bam<'a, R: bam::Read>(
+        &'a self,
+        reader: &'a mut R,
+        header: &'a HeaderView,
+    ) -> impl Iterator<Item = Result<Vec<Record>, &'static str>> + 'a {
+        reader.records().map(move |record| {
+            let record = record.map_err(|_| "Unable to read BAM record")?;
+            self.map_bam_record(&record, header)
+        })
+    }
+
+    /// Map an unmapped BAM, as delivered by ONT and PacBio basecallers, and write the results
+    /// to `output` as BAM, keeping the tags of the reads (see
+    /// [`map_bam_record`](Aligner::map_bam_record))
+    ///
+    /// The header of the input, with its @RG and @PG lines, is kept and the targets of the
+    /// index are added to it.
+    pub fn map_unmapped_bam_to_bam<P, Q>(&self, input: P, output: Q) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        if !self.has_index() {
+            return Err("No index");
+        }
+
+        let mut reader = bam::Reader::from_path(input).map_err(|_| "Unable to open BAM file")?;
+        let mut header = Header::from_template(bam::Read::header(&reader));
+        self.populate_header(&mut header);
+        let header_view = HeaderView::from_header(&header);
+
+        let mut writer = bam::Writer::from_path(output, &header, bam::Format::Bam)
+            .map_err(|_| "Unable to create output file")?;
+
+        for records in self.map_unmapped_bam(&mut reader, &header_view) {
+            for record in records? {
+                writer.write(&record).map_err(|_| "Unable to write BAM")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [`OutputSink`](crate::sink::OutputSink) writing BAM
+///
+/// Mappings carry no query sequence, so records are written without SEQ and QUAL and the
+/// unaligned ends of the query are hard clipped. NM and AS tags are set when the mapping has
+/// an alignment. Reads without mappings are written as unmapped records. Use
+/// [`Aligner::map_file_to_bam`] to keep the sequence. The read name is the read id up to the
+/// first whitespace, and the rest is kept as set by the aligner's
+/// [`with_comment_passthrough`](Aligner::with_comment_passthrough). Tags set with
+/// [`with_record_tags`](Aligner::with_record_tags) are added to mapped records.
+pub struct BamSink {
+    writer: bam::Writer,
+    header: HeaderView,
+    comments: CommentPassthrough,
+    record_tags: Option<RecordTags>,
+}
+
+impl BamSink {
+    /// Create a BAM file with the targets of `aligner` in its header
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        aligner: &Aligner<Built>,
+    ) -> Result<Self, &'static str> {
+        if !aligner.has_index() {
+            return Err("No index");
+        }
+        let mut header = Header::new();
+        aligner.populate_header(&mut header);
+        let writer = bam::Writer::from_path(path, &header, bam::Format::Bam)
+            .map_err(|_| "Unable to create output file")?;
+        Ok(BamSink {
+            writer,
+            header: HeaderView::from_header(&header),
+            comments: aligner.comment_passthrough,
+            record_tags: aligner.record_tags.clone(),
+        })
+    }
+
+    fn record(&self, read_id: &[u8], mapping: &Mapping) -> Result<Record, &'static str> {
+        let mut rec = mapping_to_record(Some(mapping), &[], Header::new(), None, Some(read_id));
+
+        let tid = mapping
+            .target_name
+            .as_deref()
+            .and_then(|name| self.header.tid(name.as_bytes()))
+            .ok_or("Mapping target not in header")?;
+        rec.set_tid(tid as i32);
+
+        if let Some(cigar) = mapping
+            .alignment
+            .as_ref()
+            .and_then(|aln| aln.cigar.as_ref())
+        {
+            let query_len = mapping.query_len.map_or(mapping.query_end, |len| len.get());
+            let (mut left, mut right) = (mapping.query_start, query_len - mapping.query_end);
+            if mapping.strand == Strand::Reverse {
+                std::mem::swap(&mut left, &mut right);
+            }
+            let mut ops = Vec::with_capacity(cigar.len() + 2);
+            if left > 0 {
+                ops.push(Cigar::HardClip(left as u32));
+            }
+            ops.extend(cigar_to_cigarstr(cigar).0);
+            if right > 0 {
+                ops.push(Cigar::HardClip(right as u32));
+            }
+            rec.set(read_id, Some(&CigarString(ops)), &[], &[]);
+        }
+
+        if let Some(aln) = mapping.alignment.as_ref() {
+            rec.push_aux(b"NM", Aux::I32(aln.nm))
+                .map_err(|_| "Unable to set NM tag")?;
+            if let Some(score) = aln.alignment_score {
+                rec.push_aux(b"AS", Aux::I32(score))
+                    .map_err(|_| "Unable to set AS tag")?;
+            }
+        }
+        if let Some(record_tags) = &self.record_tags {
+            add_record_tags(&mut rec, read_id, mapping, record_tags)?;
+        }
+        Ok(rec)
+    }
+}
+
+impl crate::sink::OutputSink for BamSink {
+    fn write(&mut self, read_id: &[u8], mappings: &[Mapping]) -> Result<(), &'static str> {
+        let (name, comment) = split_read_id(read_id);
+        let mut records = Vec::with_capacity(mappings.len().max(1));
+        if mappings.is_empty() {
+            records.push(mapping_to_record(
+                None,
+                &[],
+                Header::new(),
+                None,
+                Some(name),
+            ));
+        }
+        for mapping in mappings {
+            records.push(self.record(name, mapping)?);
+        }
+        for rec in records.iter_mut() {
+            if let Some(comment) = comment {
+                add_comment(rec, comment, self.comments)?;
+            }
+            self.writer.write(rec).map_err(|_| "Unable to write BAM")?;
+        }
+        Ok(())
+    }
 }
 
 pub fn mapping_to_record(
@@ -231,6 +784,20 @@ pub fn mapping_to_record(
 ) -> Record {
     let mut rec = Record::new();
     let qname = query_name.unwrap_or(b"query");
+    // Hard clipped bases are not part of SEQ, see `with_hard_clipping`
+    let (clip0, clip1) = mapping
+        .and_then(|m| m.alignment.as_ref()?.cigar.as_ref())
+        .map_or((0, 0), |cigar| {
+            let hard = |op: Option<&(u32, u8)>| match op {
+                Some(&(len, 5)) => len as usize,
+                _ => 0,
+            };
+            (hard(cigar.first()), hard(cigar.last()))
+        });
+    let seq = seq
+        .get(clip0..seq.len().saturating_sub(clip1))
+        .unwrap_or(seq);
+    let qual = qual.map(|q| q.get(clip0..q.len().saturating_sub(clip1)).unwrap_or(q));
     // FIXFIX: there's probably a better way of setting a default value
     // for the quality string
     let qual = qual.map_or_else(|| vec![255u8; seq.len()], Vec::from);
@@ -296,13 +863,154 @@ fn cigar_to_cigarstr(cigar: &Vec<(u32, u8)>) -> CigarString {
     CigarString(op_vec)
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct SeqMetaData {
-    pub name: String,
-    pub length: u32,
-    pub is_alt: bool,
+/// Convert a mapped SAM/BAM record into a [`Mapping`], with target names and lengths from
+/// `header`
+///
+/// Query coordinates are on the forward strand of the query and the query length includes
+/// hard clips, as in PAF. The CIGAR is kept without clipping, as returned by `map()`. The
+/// match length is derived from the NM tag (the alignment length less NM), so it is
+/// underestimated for alignments over ambiguous bases, and is 0 without NM. The NM, AS, MD
+/// and cs tags fill the [`Alignment`].
+///
+/// ```
+/// use minimap2::htslib::record_to_mapping;
+/// use rust_htslib::bam::{Read, Reader};
+///
+/// let mut reader = Reader::from_path("test_data/gDNA_vs_genome.sam").unwrap();
+/// let header = reader.header().clone();
+/// let record = reader.records().next().unwrap().unwrap();
+/// let mapping = record_to_mapping(&record, &header).unwrap();
+/// assert_eq!(mapping.target_name.as_deref().unwrap(), "chr1");
+/// assert_eq!((mapping.target_start, mapping.target_end), (180, 280));
+/// ```
+pub fn record_to_mapping(record: &Record, header: &HeaderView) -> Result<Mapping, &'static str> {
+    if record.is_unmapped() || record.tid() < 0 {
+        return Err("Record is unmapped");
+    }
+    let tid = record.tid() as u32;
+    if tid >= header.target_count() {
+        return Err("Record target not in header");
+    }
+
+    let ops: Vec<(u32, u8)> = record
+        .raw_cigar()
+        .iter()
+        .map(|op| (op >> 4, (op & 0xf) as u8))
+        .collect();
+    let is_clip = |op: u8| op == 4 || op == 5;
+    let leading_clip: u32 = ops
+        .iter()
+        .take_while(|(_, op)| is_clip(*op))
+        .map(|x| x.0)
+        .sum();
+    let trailing_clip: u32 = ops
+        .iter()
+        .rev()
+        .take_while(|(_, op)| is_clip(*op))
+        .map(|x| x.0)
+        .sum();
+    // M, I, S, H, =, X consume the query
+    let query_len: u32 = ops
+        .iter()
+        .filter(|(_, op)| matches!(op, 0 | 1 | 4 | 5 | 7 | 8))
+        .map(|x| x.0)
+        .sum();
+    // M, I, D, =, X make up the alignment block, as in minimap2
+    let block_len: u32 = ops
+        .iter()
+        .filter(|(_, op)| matches!(op, 0 | 1 | 2 | 7 | 8))
+        .map(|x| x.0)
+        .sum();
+
+    let strand = if record.is_reverse() {
+        Strand::Reverse
+    } else {
+        Strand::Forward
+    };
+    let (query_start, query_end) = match strand {
+        Strand::Forward => (leading_clip, query_len - trailing_clip),
+        Strand::Reverse => (trailing_clip, query_len - leading_clip),
+    };
+
+    let nm = aux_integer(record, b"NM");
+    let cigar: Vec<(u32, u8)> = ops.into_iter().filter(|(_, op)| !is_clip(*op)).collect();
+    let cigar_str = cigar
+        .iter()
+        .map(|(len, op)| format!("{}{}", len, cigar_op_char(*op)))
+        .collect::<String>();
+
+    let alignment = Alignment {
+        nm: nm.unwrap_or(0),
+        cigar: Some(cigar),
+        cigar_str: Some(cigar_str),
+        md: aux_string(record, b"MD"),
+        cs: aux_string(record, b"cs"),
+        alignment_score: aux_integer(record, b"AS"),
+        dp_skipped: DpSkipped::None,
+    };
+
+    let target_name = String::from_utf8_lossy(header.tid2name(tid)).into_owned();
+
+    Ok(Mapping {
+        query_name: Some(Arc::new(
+            String::from_utf8_lossy(record.qname()).into_owned(),
+        )),
+        query_len: NonZeroI32::new(query_len as i32),
+        query_start: query_start as i32,
+        query_end: query_end as i32,
+        strand,
+        target_name: Some(Arc::new(target_name)),
+        target_len: header.target_len(tid).unwrap_or(0) as i32,
+        target_start: record.pos() as i32,
+        target_end: record.cigar().end_pos() as i32,
+        match_len: nm.map_or(0, |nm| (block_len as i32 - nm).max(0)),
+        block_len: block_len as i32,
+        mapq: record.mapq() as u32,
+        is_primary: !record.is_secondary() && !record.is_supplementary(),
+        is_supplementary: record.is_supplementary(),
+        alignment_type: if record.is_secondary() {
+            AlignmentType::Secondary
+        } else if record.is_supplementary() {
+            AlignmentType::Supplementary
+        } else {
+            AlignmentType::Primary
+        },
+        alignment: Some(alignment),
+        target_seq: None,
+    })
+}
+
+/// Convert a record read from a SAM/BAM file, using the header it was read with
+impl TryFrom<&Record> for Mapping {
+    type Error = &'static str;
+
+    fn try_from(record: &Record) -> Result<Self, Self::Error> {
+        let header = record.header().ok_or("Record has no header")?;
+        record_to_mapping(record, header)
+    }
+}
+
+fn aux_integer(record: &Record, tag: &[u8]) -> Option<i32> {
+    match record.aux(tag).ok()? {
+        Aux::I8(x) => Some(x as i32),
+        Aux::U8(x) => Some(x as i32),
+        Aux::I16(x) => Some(x as i32),
+        Aux::U16(x) => Some(x as i32),
+        Aux::I32(x) => Some(x),
+        Aux::U32(x) => i32::try_from(x).ok(),
+        _ => None,
+    }
+}
+
+fn aux_string(record: &Record, tag: &[u8]) -> Option<String> {
+    match record.aux(tag).ok()? {
+        Aux::String(x) => Some(x.to_string()),
+        _ => None,
+    }
 }
 
+pub use crate::SeqMetaData;
+
 pub struct MMIndex {
     pub inner: Arc<super::MmIdx>,
 }
@@ -313,19 +1021,9 @@ impl MMIndex {
     }
 
     pub fn seqs(&self) -> Vec<SeqMetaData> {
-        let mut seqs: Vec<SeqMetaData> = Vec::with_capacity(self.n_seq() as usize);
-        for i in 0..self.n_seq() {
-            let _seq = unsafe { *(**self.inner).seq.offset(i as isize) };
-            // let c_str = unsafe { ffi::CStr::from_ptr(_seq.name) };
-            let c_str = unsafe { CStr::from_ptr(_seq.name) };
-            let rust_str = c_str.to_str().unwrap().to_string();
-            seqs.push(SeqMetaData {
-                name: rust_str,
-                length: _seq.len,
-                is_alt: _seq.is_alt != 0,
-            });
-        }
-        seqs
+        (0..self.n_seq() as usize)
+            .map(|i| unsafe { seq_meta_data(&self.inner, i) })
+            .collect()
     }
 
     pub fn get_header(&self) -> Header {
@@ -353,7 +1051,7 @@ impl From<&Aligner<Built>> for MMIndex {
 #[cfg(feature = "htslib")]
 mod tests {
     use super::*;
-    use crate::Aligner;
+    use crate::{Aligner, TagPassthrough};
     use rust_htslib::bam::ext::BamRecordExtensions;
     use rust_htslib::bam::{header::Header, record::Aux, Read, Reader, Record};
 
@@ -372,12 +1070,14 @@ mod tests {
                 SeqMetaData {
                     name: "chr1".to_string(),
                     length: 1720u32,
-                    is_alt: false
+                    is_alt: false,
+                    offset: 0
                 },
                 SeqMetaData {
                     name: "chr2".to_string(),
                     length: 460u32,
-                    is_alt: false
+                    is_alt: false,
+                    offset: 1720
                 },
             ]
         );
@@ -414,17 +1114,7 @@ mod tests {
                 let mut seq = r.seq().as_bytes();
                 let mut qual = r.qual().to_vec();
                 if r.is_reverse() {
-                    seq = seq
-                        .iter()
-                        .rev()
-                        .map(|b| match b {
-                            b'A' => b'T',
-                            b'T' => b'A',
-                            b'G' => b'C',
-                            b'C' => b'G',
-                            _ => panic!("Invalid base"),
-                        })
-                        .collect();
+                    seq = crate::seq::revcomp(&seq);
                     qual = qual.into_iter().rev().collect();
                 };
                 (seq, qual)
@@ -683,4 +1373,485 @@ mod tests {
         let record = records.first().unwrap();
         assert_eq!((record.tid(), record.pos(), record.mapq()), (0, 180, 13));
     }
+
+    #[test]
+    fn test_mapopt_flags() {
+        let aligner = Aligner::builder()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let mut header = Header::new();
+        aligner.populate_header(&mut header);
+        let header_view = HeaderView::from_header(&header);
+        let has_op = |record: &Record, kind: fn(&Cigar) -> bool| record.cigar().iter().any(kind);
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+
+        let records = aligner
+            .map_to_sam(seq, None, Some(b"read1"), &header_view, None, None)
+            .unwrap();
+        let record = &records[0];
+        assert!(record.aux(b"AS").is_ok());
+        assert!(record.aux(b"NM").is_ok());
+        assert!(record.aux(b"cs").is_err());
+        assert!(record.aux(b"MD").is_err());
+        assert!(has_op(record, |op| matches!(op, Cigar::Match(_))));
+
+        let flags = [mm_ffi::MM_F_OUT_CS, mm_ffi::MM_F_OUT_MD, mm_ffi::MM_F_EQX];
+        let records = aligner
+            .map_to_sam(
+                seq,
+                None,
+                Some(b"read1"),
+                &header_view,
+                None,
+                Some(flags.iter().map(|&flag| flag as u64).collect()),
+            )
+            .unwrap();
+        let record = &records[0];
+        let mappings = aligner
+            .map(seq, true, true, None, None, Some(b"read1"))
+            .unwrap();
+        let aln = mappings[0].alignment.as_ref().unwrap();
+        assert_eq!(
+            record.aux(b"cs").unwrap(),
+            Aux::String(aln.cs.as_deref().unwrap())
+        );
+        assert_eq!(
+            record.aux(b"MD").unwrap(),
+            Aux::String(aln.md.as_deref().unwrap())
+        );
+        assert!(!has_op(record, |op| matches!(op, Cigar::Match(_))));
+        assert!(has_op(record, |op| matches!(op, Cigar::Equal(_))));
+    }
+
+    #[test]
+    fn test_query_orientation_sam() {
+        let builder = Aligner::builder().with_cigar();
+        let reverse = builder
+            .clone()
+            .with_query_orientation(QueryOrientation::ReverseComplement)
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let aligner = builder.with_index("test_data/genome.fa", None).unwrap();
+        let mut header = Header::new();
+        aligner.populate_header(&mut header);
+        let header_view = HeaderView::from_header(&header);
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+        let qual: Vec<u8> = (0..seq.len()).map(|i| b'!' + (i % 40) as u8).collect();
+        let map = |aligner: &Aligner<Built>| {
+            aligner
+                .map_to_sam(seq, Some(&qual), Some(b"read1"), &header_view, None, None)
+                .unwrap()
+                .remove(0)
+        };
+
+        let expected = map(&aligner);
+        let record = map(&reverse);
+        assert_eq!(record.aux(b"rc").unwrap(), Aux::I32(1));
+        assert!(expected.aux(b"rc").is_err());
+        assert_eq!(record.is_reverse(), !expected.is_reverse());
+        // Both records hold the read as it maps on the forward strand of the target
+        assert_eq!(
+            (record.tid(), record.pos()),
+            (expected.tid(), expected.pos())
+        );
+        assert_eq!(record.seq().as_bytes(), expected.seq().as_bytes());
+        assert_eq!(record.qual(), expected.qual());
+    }
+
+    #[test]
+    fn test_record_to_mapping() {
+        let mut reader = Reader::from_path("test_data/gDNA_vs_genome.sam").unwrap();
+        let records: Vec<Record> = reader.records().map(|r| r.unwrap()).collect();
+
+        let unmapped = records.iter().find(|r| r.is_unmapped()).unwrap();
+        assert_eq!(Mapping::try_from(unmapped), Err("Record is unmapped"));
+
+        // The minimap2 CLI's PAF output for the same reads
+        let columns = |line: &str| line.split('\t').take(12).collect::<Vec<_>>().join("\t");
+        let mut observed: Vec<String> = records
+            .iter()
+            .filter(|r| !r.is_unmapped())
+            .map(|r| columns(&Mapping::try_from(r).unwrap().to_paf()))
+            .collect();
+        let expected = std::fs::read_to_string("test_data/gDNA_vs_genome.paf").unwrap();
+        let mut expected: Vec<String> = expected.lines().map(columns).collect();
+        observed.sort();
+        expected.sort();
+        assert_eq!(observed, expected);
+
+        let split = records
+            .iter()
+            .find(|r| r.qname() == b"split_read" && r.is_supplementary())
+            .unwrap();
+        let mapping = Mapping::try_from(split).unwrap();
+        assert!(!mapping.is_primary && mapping.is_supplementary);
+        let aln = mapping.alignment.unwrap();
+        assert_eq!(aln.cigar, Some(vec![(80, 0)]));
+        assert_eq!(aln.cigar_str.as_deref(), Some("80M"));
+
+        let imperfect = records
+            .iter()
+            .find(|r| r.qname() == b"imperfect_read.fwd")
+            .unwrap();
+        let aln = Mapping::try_from(imperfect).unwrap().alignment.unwrap();
+        assert_eq!(aln.nm, 5);
+        assert_eq!(aln.alignment_score, Some(170));
+        assert_eq!(aln.md.as_deref(), Some("34G10C6A6G33T6"));
+
+        // Records built without a reader carry no header
+        let mut record = Record::new();
+        record.set_tid(0);
+        assert_eq!(Mapping::try_from(&record), Err("Record has no header"));
+        let header = reader.header().clone();
+        assert!(record_to_mapping(&record, &header).is_ok());
+    }
+
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_bam_sink() {
+        use crate::sink::OutputSink;
+
+        let aligner = Aligner::builder()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let output = std::env::temp_dir().join("minimap2_test_bam_sink.bam");
+        let mut sink = BamSink::from_path(&output, &aligner).unwrap();
+        aligner
+            .map_file_to_sink("test_data/gDNA_reads.fq", &mut sink, 2)
+            .unwrap();
+        sink.write(b"unmapped", &[]).unwrap();
+        drop(sink);
+
+        let mut reader = Reader::from_path(&output).unwrap();
+        let records: Vec<Record> = reader.records().map(|r| r.unwrap()).collect();
+        assert!(records.last().unwrap().is_unmapped());
+
+        // Without SEQ, the query length is kept in hard clips
+        let expected = std::fs::read_to_string("test_data/gDNA_vs_genome.paf").unwrap();
+        let columns = |line: &str| line.split('\t').take(12).collect::<Vec<_>>().join("\t");
+        let mut observed: Vec<String> = records
+            .iter()
+            .filter(|r| !r.is_unmapped())
+            .map(|r| {
+                assert!(r.seq().is_empty());
+                columns(&Mapping::try_from(r).unwrap().to_paf())
+            })
+            .collect();
+        let mut expected: Vec<String> = expected.lines().map(columns).collect();
+        observed.sort();
+        expected.sort();
+        assert_eq!(observed, expected);
+
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_comment_passthrough() {
+        use crate::sink::OutputSink;
+
+        assert_eq!(split_read_id(b"read1"), (&b"read1"[..], None));
+        assert_eq!(
+            split_read_id(b"read1 runid=abc ch=12 "),
+            (&b"read1"[..], Some(&b"runid=abc ch=12"[..]))
+        );
+        assert_eq!(split_read_id(b"read1\t"), (&b"read1"[..], None));
+
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+        let aligner = |mode| {
+            Aligner::builder()
+                .with_cigar()
+                .with_comment_passthrough(mode)
+                .with_index("test_data/genome.fa", None)
+                .unwrap()
+        };
+        let map = |aligner: &Aligner<Built>, comment: &[u8]| {
+            let mut header = Header::new();
+            aligner.populate_header(&mut header);
+            let header_view = HeaderView::from_header(&header);
+            let records = aligner
+                .map_to_sam_with_comment(
+                    seq,
+                    None,
+                    Some(b"read1"),
+                    Some(comment),
+                    &header_view,
+                    None,
+                    None,
+                )
+                .unwrap();
+            assert_eq!(records.len(), 1);
+            records.into_iter().next().unwrap()
+        };
+
+        let ont = b"runid=abc ch=12";
+        let record = map(&aligner(CommentPassthrough::Drop), ont);
+        assert!(record.aux(b"CO").is_err());
+
+        let record = map(&aligner(CommentPassthrough::Tag(*b"CO")), ont);
+        assert_eq!(record.aux(b"CO").unwrap(), Aux::String("runid=abc ch=12"));
+
+        // Fields that are not SAM tags are skipped, and minimap2's own tags win
+        let tags = b"RG:Z:group 1\tch:i:12\tNM:i:99\trunid=abc";
+        let record = map(&aligner(CommentPassthrough::SamTags), tags);
+        assert_eq!(record.aux(b"RG").unwrap(), Aux::String("group 1"));
+        assert_eq!(record.aux(b"ch").unwrap(), Aux::I32(12));
+        assert_eq!(record.aux(b"NM").unwrap(), Aux::U8(5));
+        let record = map(&aligner(CommentPassthrough::SamTags), b"BC:Z:ACGT xx:A:y");
+        assert_eq!(record.aux(b"BC").unwrap(), Aux::String("ACGT"));
+        assert_eq!(record.aux(b"xx").unwrap(), Aux::Char(b'y'));
+
+        // BamSink writes the name without the comment
+        let aligner = aligner(CommentPassthrough::Tag(*b"CO"));
+        let output = std::env::temp_dir().join("minimap2_test_comment_passthrough.bam");
+        let mut sink = BamSink::from_path(&output, &aligner).unwrap();
+        let mappings = aligner.map(seq, false, false, None, None, None).unwrap();
+        sink.write(b"read1 runid=abc ch=12", &mappings).unwrap();
+        sink.write(b"read2 runid=abc ch=13", &[]).unwrap();
+        drop(sink);
+
+        let mut reader = Reader::from_path(&output).unwrap();
+        let records: Vec<Record> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].qname(), b"read1");
+        assert_eq!(
+            records[0].aux(b"CO").unwrap(),
+            Aux::String("runid=abc ch=12")
+        );
+        assert_eq!(records[1].qname(), b"read2");
+        assert_eq!(
+            records[1].aux(b"CO").unwrap(),
+            Aux::String("runid=abc ch=13")
+        );
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_record_tags() {
+        use crate::sink::OutputSink;
+
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+        let aligner = Aligner::builder()
+            .with_cigar()
+            .with_record_tags(|name: &[u8], mapping: &Mapping| {
+                let cell = name.split(|&b| b == b':').next().unwrap();
+                vec![
+                    (
+                        *b"CB",
+                        TagValue::String(String::from_utf8_lossy(cell).into_owned()),
+                    ),
+                    (*b"XS", TagValue::Int(mapping.target_start)),
+                    (*b"XC", TagValue::Char(b'+')),
+                    (*b"XF", TagValue::Float(0.5)),
+                    // minimap2's NM is kept
+                    (*b"NM", TagValue::Int(99)),
+                ]
+            })
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let mut header = Header::new();
+        aligner.populate_header(&mut header);
+        let header_view = HeaderView::from_header(&header);
+
+        let records = aligner
+            .map_to_sam(seq, None, Some(b"AAAC:read1"), &header_view, None, None)
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.aux(b"CB").unwrap(), Aux::String("AAAC"));
+        assert_eq!(record.aux(b"XS").unwrap(), Aux::I32(180));
+        assert_eq!(record.aux(b"XC").unwrap(), Aux::Char(b'+'));
+        assert_eq!(record.aux(b"XF").unwrap(), Aux::Float(0.5));
+        assert_eq!(record.aux(b"NM").unwrap(), Aux::U8(5));
+
+        // Unmapped reads get no tags
+        let records = aligner
+            .map_to_sam(
+                b"ACGTACGTACGTACGTACGT",
+                None,
+                Some(b"AAAC:read2"),
+                &header_view,
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(records[0].is_unmapped());
+        assert!(records[0].aux(b"CB").is_err());
+
+        let output = std::env::temp_dir().join("minimap2_test_record_tags.bam");
+        let mut sink = BamSink::from_path(&output, &aligner).unwrap();
+        let mappings = aligner.map(seq, false, false, None, None, None).unwrap();
+        sink.write(b"GGTT:read1 runid=abc", &mappings).unwrap();
+        drop(sink);
+
+        let mut reader = Reader::from_path(&output).unwrap();
+        let records: Vec<Record> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].aux(b"CB").unwrap(), Aux::String("GGTT"));
+        assert_eq!(records[0].aux(b"XS").unwrap(), Aux::I32(180));
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_quality_mismatch() {
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+        let qual = vec![b'5'; seq.len() - 1];
+
+        assert_eq!(
+            Query::try_new(seq, Some(&qual), None, QualityMismatch::Error).unwrap_err(),
+            QueryError::QualityLengthMismatch {
+                seq_len: 100,
+                qual_len: 99
+            }
+        );
+        assert_eq!(
+            Query::try_new(b"", None, None, QualityMismatch::Pad).unwrap_err(),
+            QueryError::EmptySequence
+        );
+
+        let query =
+            Query::try_new(seq, Some(&qual), Some(b"read1\0"), QualityMismatch::Trim).unwrap();
+        assert_eq!(query.name(), b"read1");
+        assert_eq!(query.seq(), &seq[..99]);
+        assert_eq!(query.qual(), Some(&qual[..]));
+        assert_eq!(query.inner().l_seq, 99);
+        for (seq, name) in [(&b"AC\0GT"[..], &b"read1"[..]), (b"ACGT", b"re\0ad1")] {
+            assert_eq!(
+                Query::try_new(seq, None, Some(name), QualityMismatch::Error).unwrap_err(),
+                QueryError::InteriorNul
+            );
+        }
+
+        let map = |policy| {
+            let aligner = Aligner::builder()
+                .with_cigar()
+                .with_quality_mismatch(policy)
+                .with_index("test_data/genome.fa", None)
+                .unwrap();
+            let mut header = Header::new();
+            aligner.populate_header(&mut header);
+            let header_view = HeaderView::from_header(&header);
+            aligner.map_to_sam(seq, Some(&qual), Some(b"read1"), &header_view, None, None)
+        };
+
+        assert_eq!(
+            map(QualityMismatch::Error).unwrap_err(),
+            "Sequence and quality strings are different lengths"
+        );
+
+        let records = map(QualityMismatch::Trim).unwrap();
+        assert_eq!(records[0].seq_len(), 99);
+        assert_eq!(records[0].qual().len(), 99);
+
+        let records = map(QualityMismatch::Pad).unwrap();
+        assert_eq!(records[0].seq_len(), 100);
+        let qual = records[0].qual();
+        assert_eq!((qual[0], qual[99]), (20, 0));
+    }
+
+    #[test]
+    fn test_map_unmapped_bam() {
+        let aligner = Aligner::builder()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+
+        let input = std::env::temp_dir().join("minimap2_test_ubam.bam");
+        let output = std::env::temp_dir().join("minimap2_test_ubam_mapped.bam");
+
+        let mut header = Header::new();
+        header.push_record(HeaderRecord::new(b"RG").push_tag(b"ID", "run1"));
+        {
+            let mut writer = bam::Writer::from_path(&input, &header, bam::Format::Bam).unwrap();
+            let reads: [(&[u8], &[u8]); 2] =
+                [(b"read1", seq), (b"unmapped", b"ACGTACGTACGTACGTACGT")];
+            for (name, seq) in reads {
+                let mut record = Record::new();
+                record.set(name, None, seq, &vec![20; seq.len()]);
+                record.set_unmapped();
+                record.set_tid(-1);
+                record.set_pos(-1);
+                record.set_mtid(-1);
+                record.set_mpos(-1);
+                record.push_aux(b"RG", Aux::String("run1")).unwrap();
+                record.push_aux(b"MM", Aux::String("C+m?,0;")).unwrap();
+                record.push_aux(b"NM", Aux::I32(99)).unwrap();
+                writer.write(&record).unwrap();
+            }
+        }
+
+        aligner.map_unmapped_bam_to_bam(&input, &output).unwrap();
+
+        let mut reader = Reader::from_path(&output).unwrap();
+        let header = reader.header().clone();
+        assert_eq!(header.target_count(), 2);
+        let records: Vec<Record> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+
+        let mapped = &records[0];
+        assert_eq!((mapped.tid(), mapped.pos(), mapped.mapq()), (0, 180, 13));
+        assert_eq!(mapped.qual()[0], 20);
+        assert_eq!(mapped.aux(b"RG").unwrap(), Aux::String("run1"));
+        assert_eq!(mapped.aux(b"MM").unwrap(), Aux::String("C+m?,0;"));
+        // minimap2's NM wins over the input's
+        assert_eq!(mapped.aux(b"NM").unwrap(), Aux::U8(5));
+
+        let unmapped = &records[1];
+        assert!(unmapped.is_unmapped());
+        assert_eq!(unmapped.aux(b"RG").unwrap(), Aux::String("run1"));
+        assert!(unmapped.aux(b"NM").is_err());
+
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_tag_passthrough() {
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+        let mut record = Record::new();
+        record.set(b"read1", None, seq, &vec![20; seq.len()]);
+        record.set_unmapped();
+        record.set_tid(-1);
+        record.set_pos(-1);
+        record.set_mtid(-1);
+        record.set_mpos(-1);
+        record.push_aux(b"HP", Aux::I32(2)).unwrap();
+        record.push_aux(b"PS", Aux::I32(12345)).unwrap();
+        record.push_aux(b"RG", Aux::String("run1")).unwrap();
+        record
+            .push_aux(b"SA", Aux::String("chr2,1,+,10M,60,0;"))
+            .unwrap();
+
+        let map = |mode: TagPassthrough| {
+            let aligner = Aligner::builder()
+                .with_cigar()
+                .with_tag_passthrough(mode)
+                .with_index("test_data/genome.fa", None)
+                .unwrap();
+            let mut header = Header::new();
+            aligner.populate_header(&mut header);
+            let header_view = HeaderView::from_header(&header);
+            let mut records = aligner.map_bam_record(&record, &header_view).unwrap();
+            assert_eq!(records.len(), 1);
+            records.remove(0)
+        };
+
+        let all = map(TagPassthrough::All);
+        assert_eq!(all.aux(b"HP").unwrap(), Aux::I32(2));
+        assert_eq!(all.aux(b"RG").unwrap(), Aux::String("run1"));
+        // Tags of the old alignment are never copied
+        assert!(all.aux(b"SA").is_err());
+
+        let phased = map(TagPassthrough::Only(vec![*b"HP", *b"PS"]));
+        assert_eq!(phased.aux(b"HP").unwrap(), Aux::I32(2));
+        assert_eq!(phased.aux(b"PS").unwrap(), Aux::I32(12345));
+        assert!(phased.aux(b"RG").is_err());
+        assert!(phased.aux(b"NM").is_ok());
+
+        let none = map(TagPassthrough::None);
+        assert!(none.aux(b"HP").is_err());
+        assert!(none.aux(b"RG").is_err());
+    }
 }
@@ -0,0 +1,196 @@
+//! Conversions between [`Mapping`] and rust-bio ecosystem types
+//!
+//! Enabled by the `bio-types` and `bio` features.
+
+#[cfg(feature = "bio")]
+use bio::alignment::{Alignment as BioAlignment, AlignmentMode, AlignmentOperation};
+#[cfg(feature = "bio-types")]
+use bio_types::genome::{AbstractInterval, Interval, Position};
+#[cfg(feature = "bio-types")]
+use bio_types::strand::ReqStrand;
+
+use crate::{Mapping, Strand};
+
+#[cfg(feature = "bio-types")]
+impl From<Strand> for ReqStrand {
+    fn from(strand: Strand) -> Self {
+        match strand {
+            Strand::Forward => ReqStrand::Forward,
+            Strand::Reverse => ReqStrand::Reverse,
+        }
+    }
+}
+
+#[cfg(feature = "bio-types")]
+impl From<ReqStrand> for Strand {
+    fn from(strand: ReqStrand) -> Self {
+        match strand {
+            ReqStrand::Forward => Strand::Forward,
+            ReqStrand::Reverse => Strand::Reverse,
+        }
+    }
+}
+
+#[cfg(feature = "bio-types")]
+impl From<Strand> for bio_types::strand::Strand {
+    fn from(strand: Strand) -> Self {
+        ReqStrand::from(strand).into()
+    }
+}
+
+/// The target interval of the mapping. Unmapped mappings have contig "*" and an empty range.
+#[cfg(feature = "bio-types")]
+impl AbstractInterval for Mapping {
+    fn contig(&self) -> &str {
+        self.target_name.as_ref().map_or("*", |x| x.as_str())
+    }
+
+    fn range(&self) -> std::ops::Range<Position> {
+        self.target_start as Position..self.target_end as Position
+    }
+}
+
+#[cfg(feature = "bio-types")]
+impl TryFrom<&Mapping> for Interval {
+    type Error = &'static str;
+
+    fn try_from(mapping: &Mapping) -> Result<Self, Self::Error> {
+        match &mapping.target_name {
+            Some(name) => Ok(Interval::new(name.to_string(), mapping.range())),
+            None => Err("Mapping has no target"),
+        }
+    }
+}
+
+/// Convert to a rust-bio pairwise alignment, with the query as x and the target as y
+///
+/// Needs the cs tag or a CIGAR with =/X operations to tell matches from substitutions. Like
+/// the cs tag, the alignment is on the target's forward strand: for reverse strand mappings
+/// the x coordinates refer to the reverse complement of the query. Introns become deletions.
+#[cfg(feature = "bio")]
+impl TryFrom<&Mapping> for BioAlignment {
+    type Error = &'static str;
+
+    fn try_from(mapping: &Mapping) -> Result<Self, Self::Error> {
+        let aln = mapping
+            .alignment
+            .as_ref()
+            .ok_or("Mapping has no alignment")?;
+
+        let operations = match (&aln.cs, &aln.cigar) {
+            (Some(cs), _) => cs_operations(cs)?,
+            (None, Some(cigar)) => cigar_operations(cigar)?,
+            (None, None) => return Err("Mapping has no cs tag or CIGAR"),
+        };
+
+        let xlen = mapping.query_len.map_or(0, |x| x.get()) as usize;
+        let (xstart, xend) = match mapping.strand {
+            Strand::Forward => (mapping.query_start as usize, mapping.query_end as usize),
+            Strand::Reverse => (
+                xlen - mapping.query_end as usize,
+                xlen - mapping.query_start as usize,
+            ),
+        };
+
+        Ok(BioAlignment {
+            score: aln.alignment_score.unwrap_or(0),
+            xstart,
+            ystart: mapping.target_start as usize,
+            xend,
+            yend: mapping.target_end as usize,
+            xlen,
+            ylen: mapping.target_len as usize,
+            operations,
+            mode: AlignmentMode::Local,
+        })
+    }
+}
+
+#[cfg(feature = "bio")]
+fn cigar_operations(cigar: &[(u32, u8)]) -> Result<Vec<AlignmentOperation>, &'static str> {
+    let mut ops = Vec::new();
+    for &(len, op) in cigar {
+        let op = match op {
+            7 => AlignmentOperation::Match,
+            8 => AlignmentOperation::Subst,
+            1 => AlignmentOperation::Ins,
+            2 | 3 => AlignmentOperation::Del,
+            // Clipping is covered by the start and end coordinates
+            4 | 5 => continue,
+            _ => return Err("CIGAR has M operations, map with cs or eqx to convert"),
+        };
+        push_n(&mut ops, op, len as usize);
+    }
+    Ok(ops)
+}
+
+#[cfg(feature = "bio")]
+fn push_n(ops: &mut Vec<AlignmentOperation>, op: AlignmentOperation, n: usize) {
+    ops.resize(ops.len() + n, op);
+}
+
+#[cfg(feature = "bio")]
+fn cs_operations(cs: &str) -> Result<Vec<AlignmentOperation>, &'static str> {
+    const INVALID: &str = "Invalid cs tag";
+
+    let cs = cs.as_bytes();
+    let mut ops = Vec::new();
+    let mut i = 0;
+
+    while i < cs.len() {
+        let op = cs[i];
+        let start = i + 1;
+        let mut end = start;
+        while end < cs.len() && !b":=*+-~".contains(&cs[end]) {
+            end += 1;
+        }
+        let value = &cs[start..end];
+
+        match op {
+            b':' => {
+                let n: usize = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+                    .ok_or(INVALID)?;
+                push_n(&mut ops, AlignmentOperation::Match, n);
+            }
+            b'=' => push_n(&mut ops, AlignmentOperation::Match, value.len()),
+            b'*' if value.len() == 2 => ops.push(AlignmentOperation::Subst),
+            b'+' => push_n(&mut ops, AlignmentOperation::Ins, value.len()),
+            b'-' => push_n(&mut ops, AlignmentOperation::Del, value.len()),
+            // Introns are written as ~gt123ag
+            b'~' if value.len() > 4 => {
+                let n: usize = std::str::from_utf8(&value[2..value.len() - 2])
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+                    .ok_or(INVALID)?;
+                push_n(&mut ops, AlignmentOperation::Del, n);
+            }
+            _ => return Err(INVALID),
+        }
+
+        i = end;
+    }
+
+    Ok(ops)
+}
+
+#[cfg(all(test, feature = "bio"))]
+mod tests {
+    use super::*;
+    use AlignmentOperation::*;
+
+    #[test]
+    fn test_cs_operations() {
+        assert_eq!(
+            cs_operations(":2*ag=CA+t-cc~gt3ag:1").unwrap(),
+            vec![Match, Match, Subst, Match, Match, Ins, Del, Del, Del, Del, Del, Match]
+        );
+        assert_eq!(cs_operations(":x"), Err("Invalid cs tag"));
+        assert_eq!(
+            cigar_operations(&[(2, 4), (2, 7), (1, 8), (1, 2)]).unwrap(),
+            vec![Match, Match, Subst, Del]
+        );
+        assert!(cigar_operations(&[(3, 0)]).is_err());
+    }
+}
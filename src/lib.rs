@@ -9,6 +9,31 @@
 //! Setting threads with the builder pattern applies only to building the index, not the mapping.
 //! For an example of using multiple threads with mapping, see: [fakeminimap2](https://github.com/jguhlin/minimap2-rs/blob/main/fakeminimap2/src/main.rs)
 //!
+//! # Thread safety
+//! A built `Aligner` is `Send` and `Sync`, and this is part of the API: any number of threads
+//! may call the mapping methods (`map`, `map_with_summary`, `map_to_sam`, `map_batch`,
+//! `score_junctions`, ...) at the same time on one aligner, shared by reference, in an `Arc`,
+//! or as clones. What makes this safe:
+//! * The index is read-only once built. Clones share it through an `Arc` and it is freed
+//!   when the last clone is dropped, whichever thread drops it.
+//! * minimap2's per-query scratch memory (`mm_tbuf_t`) is thread local, so threads never
+//!   share one. A mapping call nested in another on the same thread (e.g. from a
+//!   `with_mapq_recalibration` hook) gets a temporary buffer of its own.
+//! * Options (`mapopt`, `idxopt` and the `with_*` settings) can only be changed through
+//!   `&mut` or by value, so never while another thread maps with them. Change options on a
+//!   clone to map with other settings alongside the original.
+//! * Hooks set on the builder must be `Send + Sync`: they are called from every mapping
+//!   thread at once.
+//!
+//! Everything that changes the index happens before the aligner is built. minimap2's own
+//! loaders for junction annotations (`--junc-bed`) and splice scores write into the index
+//! and are not exposed for that reason; splice scores read with
+//! [`splice::read_splice_scores`] are plain Rust data, and can be loaded at any time and
+//! shared like any other `Sync` value. Calling into [`ffi`] with the index of a built aligner
+//! is outside this guarantee.
+//!
+//! `tests/thread_safety.rs` hammers these cases on every CI run.
+//!
 //! # Crate Features
 //! This crate has multiple create features available.
 //! * map-file - Enables the ability to map a file directly to a reference. Enabled by deafult
@@ -60,15 +85,16 @@
 //! assert_eq!(hits.unwrap().len(), 1);
 //! ```
 
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 
 use std::ffi::{CStr, CString};
 use std::mem::MaybeUninit;
 use std::num::NonZeroI32;
 use std::path::Path;
-use std::sync::Arc;
-
-use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 
 use libc::c_void;
 use minimap2_sys::*;
@@ -78,9 +104,58 @@ pub use minimap2_sys as ffi;
 #[cfg(feature = "map-file")]
 use needletail::parse_fastx_file;
 
+use seq::revcomp;
+
+#[cfg(feature = "htslib")]
+pub mod bgzf;
+
+#[cfg(feature = "htslib")]
+pub mod fixmate;
+
 #[cfg(feature = "htslib")]
 pub mod htslib;
 
+mod batch;
+
+#[cfg(feature = "map-file")]
+pub mod checkpoint;
+
+#[cfg(feature = "map-file")]
+pub mod pipelines;
+
+#[cfg(any(feature = "bio-types", feature = "bio"))]
+mod interop;
+
+#[cfg(feature = "events")]
+pub mod events;
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+
+pub mod arena;
+pub mod cache;
+pub mod chimera;
+pub mod compare;
+pub mod compat;
+pub mod correct;
+pub mod coverage;
+pub mod dedup;
+pub mod eval;
+pub mod haplotype;
+pub mod merge;
+pub mod overlap;
+pub mod paf;
+pub mod pool;
+pub mod seq;
+pub mod sink;
+pub mod sketch;
+pub mod splice;
+pub mod split;
+pub mod store;
+pub mod suggest;
+pub mod sv;
+pub mod wire;
+
 /// Alias for mm_mapop_t
 pub type MapOpt = mm_mapopt_t;
 
@@ -110,7 +185,7 @@ static MAP10K: &CStr = c"map10k";
 static CDNA: &CStr = c"cdna";
 
 /// Strand enum
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Hash)]
 pub enum Strand {
     #[default]
     Forward,
@@ -127,7 +202,7 @@ impl std::fmt::Display for Strand {
 }
 
 /// Preset's for minimap2 config
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Preset {
     LrHqae,
     LrHq,
@@ -173,14 +248,48 @@ impl From<Preset> for *const libc::c_char {
     }
 }
 
-/// Alignment type
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Alignment type, as in the `tp` tag of minimap2 PAF output
+///
+/// The default is `Secondary`, to agree with the `is_primary` and `is_supplementary` flags of
+/// `Mapping::default()`, which are both false.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum AlignmentType {
     Primary,
+    #[default]
     Secondary,
+    /// Part of a chimeric or split alignment other than the primary one (SAM flag 0x800)
+    Supplementary,
+    /// Alignment of a short inversion between two parts of a chain
     Inversion,
 }
 
+impl AlignmentType {
+    /// Type of a hit returned by minimap2
+    pub(crate) fn of_reg(reg: &mm_reg1_t) -> Self {
+        if reg.inv() != 0 {
+            AlignmentType::Inversion
+        } else if reg.id != reg.parent {
+            AlignmentType::Secondary
+        } else if reg.sam_pri() == 0 {
+            AlignmentType::Supplementary
+        } else {
+            AlignmentType::Primary
+        }
+    }
+
+    /// Type given by the primary and supplementary flags of a mapping, which can not tell
+    /// inversions apart
+    pub(crate) fn from_flags(is_primary: bool, is_supplementary: bool) -> Self {
+        if is_primary {
+            AlignmentType::Primary
+        } else if is_supplementary {
+            AlignmentType::Supplementary
+        } else {
+            AlignmentType::Secondary
+        }
+    }
+}
+
 /// Alignment struct when alignment flag is set
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Alignment {
@@ -191,1640 +300,7713 @@ pub struct Alignment {
     pub md: Option<String>,
     pub cs: Option<String>,
     pub alignment_score: Option<i32>,
+    /// Ends at which minimap2 stopped base-level alignment before the end of the chain.
+    /// PAF and SAM do not record it, so it is `None` for mappings read from them.
+    pub dp_skipped: DpSkipped,
 }
 
-/// Mapping result
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub struct Mapping {
-    // The query sequence name.
-    pub query_name: Option<Arc<String>>,
-    pub query_len: Option<NonZeroI32>,
-    pub query_start: i32,
-    pub query_end: i32,
-    pub strand: Strand,
-    pub target_name: Option<Arc<String>>,
-    pub target_len: i32,
-    pub target_start: i32,
-    pub target_end: i32,
-    pub match_len: i32,
-    pub block_len: i32,
-    pub mapq: u32,
-    pub is_primary: bool,
-    pub is_supplementary: bool,
-    pub alignment: Option<Alignment>,
+impl Alignment {
+    /// BLAST identity: matching bases over alignment columns, gaps included
+    ///
+    /// Matches are the columns less the edit distance `nm`, so ambiguous bases count as
+    /// mismatches as in minimap2. Introns (N) are not columns. None without a CIGAR.
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_cigar()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+    /// let mappings = aligner.map(seq, false, false, None, None, None).unwrap();
+    /// let aln = mappings[0].alignment.as_ref().unwrap();
+    /// assert!(aln.blast_identity().unwrap() <= aln.gap_compressed_identity().unwrap());
+    /// ```
+    pub fn blast_identity(&self) -> Option<f64> {
+        let counts = self.cigar_counts()?;
+        Some(counts.matches as f64 / counts.columns as f64)
+    }
+
+    /// Gap-compressed identity: matching bases over matches, mismatches and gaps, each run of
+    /// inserted or deleted bases counting as one difference whatever its length
+    ///
+    /// This is the identity of minimap2's `de` tag (which holds 1 minus it), as defined in
+    /// <https://lh3.github.io/2018/11/25/on-the-definition-of-sequence-identity>. None without a
+    /// CIGAR.
+    pub fn gap_compressed_identity(&self) -> Option<f64> {
+        let counts = self.cigar_counts()?;
+        Some(counts.matches as f64 / (counts.aligned + counts.gap_opens) as f64)
+    }
+
+    /// Column counts of the CIGAR, or None without one or for an empty alignment
+    fn cigar_counts(&self) -> Option<CigarCounts> {
+        let mut counts = CigarCounts::default();
+        for &(len, op) in self.cigar.as_ref()? {
+            let len = len as u64;
+            match op {
+                // M, = and X
+                0 | 7 | 8 => counts.aligned += len,
+                // I and D
+                1 | 2 => {
+                    counts.gap_bases += len;
+                    counts.gap_opens += 1;
+                }
+                _ => {}
+            }
+        }
+        counts.columns = counts.aligned + counts.gap_bases;
+        if counts.columns == 0 {
+            return None;
+        }
+        counts.matches = counts.columns.saturating_sub(self.nm.max(0) as u64);
+        Some(counts)
+    }
 }
 
-// Thread local buffer (memory management) for minimap2
-thread_local! {
-    static BUF: RefCell<ThreadLocalBuffer> = RefCell::new(ThreadLocalBuffer::new());
+/// Alignment columns of a CIGAR, see [`Alignment::blast_identity`]
+#[derive(Debug, Default)]
+struct CigarCounts {
+    /// Columns with a base on both sequences
+    aligned: u64,
+    gap_bases: u64,
+    gap_opens: u64,
+    columns: u64,
+    matches: u64,
 }
 
-/// ThreadLocalBuffer for minimap2 memory management
-#[derive(Debug)]
-struct ThreadLocalBuffer {
-    buf: *mut mm_tbuf_t,
-    // max_uses: usize,
-    // uses: usize,
+/// What a CIGAR is checked against by [`Mapping::check_cigar`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CigarCheck {
+    /// Query bases of the aligned part (M, I, = and X) against `query_end - query_start`
+    QuerySpan,
+    /// Target bases (M, D, N, = and X) against `target_end - target_start`
+    TargetSpan,
+    /// Leading clip (S or H) against the query bases before the alignment, in target
+    /// orientation: `query_start`, or `query_len - query_end` on the reverse strand
+    LeadingClip,
+    /// Trailing clip against the query bases after the alignment, in target orientation
+    TrailingClip,
 }
 
-impl ThreadLocalBuffer {
-    pub fn new() -> Self {
-        let buf = unsafe { mm_tbuf_init() };
-        Self {
-            buf,
-            // max_uses: 15,
-            // uses: 0,
-        }
-    }
-    /// Return the buffer, checking how many times it has been borrowed.
-    /// Free the memory of the old buffer and reinitialise a new one If
-    /// num_uses exceeds max_uses.
-    pub fn get_buf(&mut self) -> *mut mm_tbuf_t {
-        /* if self.uses > self.max_uses {
-            // println!("renewing threadbuffer");
-            self.free_buffer();
-            let buf = unsafe { mm_tbuf_init() };
-            self.buf = buf;
-            self.uses = 0;
-        }
-        self.uses += 1; */
-        self.buf
-    }
+/// A length of a mapping that its CIGAR disagrees with, see [`Mapping::check_cigar`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CigarMismatch {
+    pub check: CigarCheck,
+    /// Whether the mismatch is in `cigar_str` rather than in `cigar`
+    pub in_cigar_str: bool,
+    /// The length given by the CIGAR
+    pub cigar_len: i64,
+    /// The length given by the coordinates of the mapping
+    pub expected: i64,
+}
 
-    fn free_buffer(&mut self) {
-        unsafe { mm_tbuf_destroy(self.buf) };
+impl std::fmt::Display for CigarMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} of {} in {}, expected {}",
+            self.check,
+            self.cigar_len,
+            if self.in_cigar_str {
+                "cigar_str"
+            } else {
+                "cigar"
+            },
+            self.expected
+        )
     }
 }
 
-/// Handle destruction of thread local buffer properly.
-impl Drop for ThreadLocalBuffer {
-    fn drop(&mut self) {
-        // unsafe { mm_tbuf_destroy(self.buf) };
-        self.free_buffer();
+impl Mapping {
+    /// The cs tag of the alignment in short form, as with `cs` set in [`Aligner::map`], or
+    /// generated now from the CIGAR if the mapping was made without it
+    ///
+    /// `map` with `cs` generates the tag for every hit, secondary ones included. Pipelines
+    /// that only look at a few hits, e.g. the primary one, can map without it and call this
+    /// for those. `aligner` must be the aligner that made the mapping, with a CIGAR, and
+    /// `query` the mapped sequence.
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_cigar()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+    /// let mappings = aligner.map(seq, false, false, None, None, None).unwrap();
+    /// let primary = mappings.iter().find(|m| m.is_primary).unwrap();
+    /// let cs = primary.cs(&aligner, seq).unwrap();
+    ///
+    /// let mappings = aligner.map(seq, true, false, None, None, None).unwrap();
+    /// let primary = mappings.iter().find(|m| m.is_primary).unwrap();
+    /// assert_eq!(primary.alignment.as_ref().unwrap().cs.as_ref(), Some(&cs));
+    /// ```
+    pub fn cs(&self, aligner: &Aligner<Built>, query: &[u8]) -> Result<String, &'static str> {
+        match self.alignment.as_ref().and_then(|aln| aln.cs.as_ref()) {
+            Some(cs) => Ok(cs.clone()),
+            None => aligner.gen_tag(self, query, false),
+        }
     }
-}
 
-impl Default for ThreadLocalBuffer {
-    fn default() -> Self {
-        Self::new()
+    /// The MD tag of the alignment, as with `md` set in [`Aligner::map`], or generated now
+    /// from the CIGAR. See [`Mapping::cs`].
+    pub fn md(&self, aligner: &Aligner<Built>, query: &[u8]) -> Result<String, &'static str> {
+        match self.alignment.as_ref().and_then(|aln| aln.md.as_ref()) {
+            Some(md) => Ok(md.clone()),
+            None => aligner.gen_tag(self, query, true),
+        }
     }
-}
 
-#[derive(Default, Clone, Copy)]
-pub struct Unset;
+    /// Check that the CIGAR of the alignment agrees with the coordinates of the mapping, in
+    /// both `cigar` and `cigar_str`, returning every disagreement
+    ///
+    /// The aligned part must consume exactly the query and target spans. Clips are optional,
+    /// as in PAF and in `cigar` without [`with_cigar_clipping`](Aligner::with_cigar_clipping),
+    /// but a CIGAR with any clip must clip both ends to the query length. Mappings without a
+    /// CIGAR pass, and a `cigar_str` that does not parse is not checked.
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_cigar()
+    ///     .with_cigar_clipping()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+    /// let mut mapping = aligner.map(seq, false, false, None, None, None).unwrap().remove(0);
+    /// assert_eq!(mapping.check_cigar(), Ok(()));
+    ///
+    /// mapping.target_end += 1;
+    /// let mismatches = mapping.check_cigar().unwrap_err();
+    /// assert_eq!(mismatches[0].check, CigarCheck::TargetSpan);
+    /// ```
+    pub fn check_cigar(&self) -> Result<(), Vec<CigarMismatch>> {
+        let Some(alignment) = &self.alignment else {
+            return Ok(());
+        };
 
-#[derive(Default, Clone, Copy)]
-pub struct PresetSet;
+        let mut mismatches = Vec::new();
+        if let Some(cigar) = &alignment.cigar {
+            self.check_cigar_ops(cigar, false, &mut mismatches);
+        }
+        if let Some(Ok(cigar)) = alignment.cigar_str.as_deref().map(paf::parse_cigar) {
+            self.check_cigar_ops(&cigar, true, &mut mismatches);
+        }
 
-#[derive(Default, Clone, Copy)]
-pub struct Built;
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
 
-pub trait BuilderState {}
-impl BuilderState for Unset {}
-impl BuilderState for PresetSet {}
-impl BuilderState for Built {}
-impl BuilderState for () {}
+    fn check_cigar_ops(
+        &self,
+        cigar: &[(u32, u8)],
+        in_cigar_str: bool,
+        mismatches: &mut Vec<CigarMismatch>,
+    ) {
+        let mut check = |kind, cigar_len: i64, expected: i64| {
+            if cigar_len != expected {
+                mismatches.push(CigarMismatch {
+                    check: kind,
+                    in_cigar_str,
+                    cigar_len,
+                    expected,
+                });
+            }
+        };
 
-pub trait AcceptsParams {}
-impl AcceptsParams for PresetSet {}
-impl AcceptsParams for Unset {}
+        let is_clip = |&&(_, op): &&(u32, u8)| op == 4 || op == 5;
+        let leading: i64 = cigar
+            .iter()
+            .take_while(is_clip)
+            .map(|&(len, _)| len as i64)
+            .sum();
+        let trailing: i64 = if leading as usize == cigar.len() {
+            0
+        } else {
+            cigar
+                .iter()
+                .rev()
+                .take_while(is_clip)
+                .map(|&(len, _)| len as i64)
+                .sum()
+        };
 
-/// Aligner struct, mimicking minimap2's python interface
-///
-/// ```
-/// # use minimap2::*;
-/// Aligner::builder();
-/// ```
+        let (mut query, mut target) = (0, 0);
+        for &(len, op) in cigar {
+            match op {
+                // M, = and X
+                0 | 7 | 8 => {
+                    query += len as i64;
+                    target += len as i64;
+                }
+                // I
+                1 => query += len as i64,
+                // D and N
+                2 | 3 => target += len as i64,
+                _ => {}
+            }
+        }
+        check(
+            CigarCheck::QuerySpan,
+            query,
+            (self.query_end - self.query_start) as i64,
+        );
+        check(
+            CigarCheck::TargetSpan,
+            target,
+            (self.target_end - self.target_start) as i64,
+        );
 
-#[derive(Clone)]
-pub struct Aligner<S: BuilderState> {
-    /// Index options passed to minimap2 (mm_idxopt_t)
-    pub idxopt: IdxOpt,
+        if cigar.iter().any(|op| is_clip(&op)) {
+            // The query bases after the alignment are only known with the query length
+            let before = Some(self.query_start as i64);
+            let after = self
+                .query_len
+                .map(|len| len.get() as i64 - self.query_end as i64);
+            let (before, after) = match self.strand {
+                Strand::Forward => (before, after),
+                Strand::Reverse => (after, before),
+            };
+            if let Some(expected) = before {
+                check(CigarCheck::LeadingClip, leading, expected);
+            }
+            if let Some(expected) = after {
+                check(CigarCheck::TrailingClip, trailing, expected);
+            }
+        }
+    }
+}
 
-    /// Mapping options passed to minimap2 (mm_mapopt_t)
-    pub mapopt: MapOpt,
+/// Ends of an alignment at which minimap2 gave up on base-level alignment, splitting the
+/// chain into several mappings
+///
+/// minimap2 does not fill a DP matrix between two seeds when it would exceed `max_sw_mat`
+/// cells (see `with_max_sw_mat`), and stops when the score drops by more than z-drop. In both
+/// cases the rest of the chain is aligned as a separate mapping, so a read spanning a
+/// multi-kb insertion or deletion gives two mappings, marked at the ends that face the gap.
+/// Ends are in target order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DpSkipped {
+    /// Aligned through to both ends of the chain
+    #[default]
+    None,
+    /// The chain continues in another mapping before the start of this one
+    Start,
+    /// The chain continues in another mapping after the end of this one
+    End,
+    /// The chain continues in other mappings on both sides
+    Both,
+}
 
-    /// Number of threads to create the index with
-    pub threads: usize,
+impl DpSkipped {
+    /// From the `split` bits of a minimap2 hit
+    pub(crate) fn from_split(split: u32) -> Self {
+        match split & 3 {
+            0 => DpSkipped::None,
+            1 => DpSkipped::End,
+            2 => DpSkipped::Start,
+            _ => DpSkipped::Both,
+        }
+    }
+}
 
-    /// Index created by minimap2
-    pub idx: Option<Arc<MmIdx>>,
+/// Metadata of a sequence (contig) stored in the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqMetaData {
+    pub name: String,
+    pub length: u32,
+    pub is_alt: bool,
+    /// Offset of the sequence in the index's packed sequence store
+    pub offset: u64,
+}
 
-    /// Index reader created by minimap2
-    pub idx_reader: Option<Arc<mm_idx_reader_t>>,
+/// A record of a FASTA/Q file that could not be read or mapped, reported by
+/// [`Aligner::map_file_tolerant`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordError {
+    /// Index of the record in the file (0-based)
+    pub index: usize,
+    /// Name of the record, if it could be read
+    pub name: Option<String>,
+    pub message: String,
+}
 
-    /// Whether to add soft clipping to CIGAR result
-    pub cigar_clipping: bool,
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "record {} ({}): {}",
+            self.index,
+            self.name.as_deref().unwrap_or("unnamed"),
+            self.message
+        )
+    }
+}
 
-    // State of the builder
-    state: S,
+/// Error returned by [`Aligner::fetch_subseq`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// No target with this name or id in the index
+    TargetNotFound,
+    /// The index was built without sequences (MM_I_NO_SEQ)
+    NoSequence,
+    /// The range is reversed or extends past the end of the target
+    OutOfRange {
+        start: u32,
+        end: u32,
+        target_len: u32,
+    },
 }
 
-/// Create a default aligner
-impl Default for Aligner<Unset> {
-    fn default() -> Self {
-        Self {
-            idxopt: Default::default(),
-            mapopt: Default::default(),
-            threads: 1,
-            idx: None,
-            idx_reader: None,
-            cigar_clipping: false,
-            state: Unset,
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::TargetNotFound => write!(f, "Target not found in index"),
+            FetchError::NoSequence => write!(f, "Index has no sequence"),
+            FetchError::OutOfRange {
+                start,
+                end,
+                target_len,
+            } => write!(
+                f,
+                "Range {start}..{end} out of bounds for target of length {target_len}"
+            ),
         }
     }
 }
 
-impl Aligner<()> {
-    /// Create a new aligner with default options
-    pub fn builder() -> Aligner<Unset> {
-        let mut aligner = Aligner {
-            mapopt: MapOpt {
-                seed: 11,
-                best_n: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
+impl std::error::Error for FetchError {}
 
-        unsafe {
-            minimap2_sys::mm_set_opt(&0, &mut aligner.idxopt, &mut aligner.mapopt);
+impl From<FetchError> for &'static str {
+    fn from(e: FetchError) -> Self {
+        match e {
+            FetchError::TargetNotFound => "Target not found in index",
+            FetchError::NoSequence => "Index has no sequence",
+            FetchError::OutOfRange { .. } => "Range out of bounds",
         }
-
-        aligner
     }
 }
 
-impl Aligner<Unset> {
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to lr:hq.
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    pub fn lrhq(self) -> Aligner<PresetSet> {
-        self.preset(Preset::LrHq)
-    }
+/// Target sequences kept outside the index, e.g. [`bgzf::BgzfFasta`] with the htslib
+/// feature, see [`with_sequence_source`](Aligner::with_sequence_source)
+pub trait SequenceSource: Send + Sync {
+    /// Read `range` (0-based, end-exclusive) of target `name` on the forward strand
+    fn fetch(&self, name: &str, range: std::ops::Range<u32>) -> Result<Vec<u8>, FetchError>;
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to splice
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().splice();
-    /// ```
-    pub fn splice(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Splice)
+/// The reference files an index was built from, see
+/// [`with_index_files`](Aligner::with_index_files)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexFiles {
+    /// The files, in the order given
+    pub files: Vec<std::path::PathBuf>,
+    /// For each target of the index, in index order, the position of its file in `files`
+    pub target_files: Vec<usize>,
+}
+
+impl IndexFiles {
+    /// The file target `target_id` came from
+    pub fn file_of(&self, target_id: usize) -> Option<&Path> {
+        self.target_files
+            .get(target_id)
+            .map(|&i| self.files[i].as_path())
     }
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to splice:hq
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().splice_hq();
-    /// ```
-    pub fn splice_hq(self) -> Aligner<PresetSet> {
-        self.preset(Preset::SpliceHq)
+/// Temporary file that indexes are built from or written to, removed when dropped
+struct TempFile(std::path::PathBuf);
+
+impl TempFile {
+    fn new(extension: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+        TempFile(std::env::temp_dir().join(format!(
+            "minimap2-rs-index-{}-{}.{extension}",
+            std::process::id(),
+            TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+        )))
     }
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().asm();
-    /// ```
-    pub fn asm(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Asm)
-    }
+#[cfg(feature = "map-file")]
+impl TempFile {
+    /// Copy the records of `paths` whose names `keep` accepts, in order, recording the file
+    /// of each. Names must be unique.
+    fn write_records<P, K>(
+        &self,
+        paths: &[P],
+        duplicate_names: DuplicateNames,
+        mut keep: K,
+    ) -> Result<IndexFiles, &'static str>
+    where
+        P: AsRef<Path>,
+        K: FnMut(&[u8]) -> bool,
+    {
+        use std::io::Write;
+        const WRITE_ERROR: &str = "Unable to write temporary index file";
+
+        let temp =
+            std::fs::File::create(&self.0).map_err(|_| "Unable to create temporary index file")?;
+        let mut temp = std::io::BufWriter::new(temp);
+        let mut index_files = IndexFiles::default();
+        let mut names = HashSet::new();
+
+        for (i, path) in paths.iter().enumerate() {
+            batch::check_input(path)?;
+            let mut reader = parse_fastx_file(path).map_err(|_| "Unable to read FASTA/X file")?;
+            while let Some(record) = reader.next() {
+                let record = record.map_err(|_| {
+                    "Error reading record in FASTA/X files. Please confirm integrity."
+                })?;
+                // minimap2 names sequences up to the first whitespace
+                let name = record
+                    .id()
+                    .split(|c| c.is_ascii_whitespace())
+                    .next()
+                    .unwrap_or_default();
+                if !keep(name) {
+                    continue;
+                }
+                let name = duplicate_names
+                    .rename(name, &mut names)
+                    .map_err(|_| "Duplicate sequence name across index files")?;
+
+                temp.write_all(b">")
+                    .and_then(|_| temp.write_all(&name))
+                    .and_then(|_| temp.write_all(b"\n"))
+                    .and_then(|_| temp.write_all(&record.seq()))
+                    .and_then(|_| temp.write_all(b"\n"))
+                    .map_err(|_| WRITE_ERROR)?;
+                index_files.target_files.push(i);
+            }
+            index_files.files.push(path.as_ref().to_path_buf());
+        }
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm5
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().asm5();
-    /// ```
-    pub fn asm5(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Asm5)
-    }
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm10
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().asm10();
-    /// ```
-    pub fn asm10(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Asm10)
-    }
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm20
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().asm20();
-    /// ```
-    pub fn asm20(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Asm20)
+        temp.flush().map_err(|_| WRITE_ERROR)?;
+        Ok(index_files)
     }
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to sr
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().sr();
-    /// ```
-    pub fn sr(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Sr)
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Best effort: the index no longer needs the file
+        let _ = std::fs::remove_file(&self.0);
     }
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to MapPb
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().map_pb();
-    /// ```
-    pub fn map_pb(self) -> Aligner<PresetSet> {
-        self.preset(Preset::MapPb)
-    }
+/// What to do when a quality string and its sequence differ in length, see
+/// [`with_quality_mismatch`](Aligner::with_quality_mismatch)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualityMismatch {
+    /// Fail the read (`QueryError::QualityLengthMismatch` with the htslib feature)
+    #[default]
+    Error,
+    /// Truncate the longer of the sequence and quality string to the length of the other
+    Trim,
+    /// Pad a short quality string with `!` (quality 0), or truncate a long one, to the length
+    /// of the sequence
+    Pad,
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to MapHifi
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().map_hifi();
-    /// ```
-    pub fn map_hifi(self) -> Aligner<PresetSet> {
-        self.preset(Preset::MapHifi)
-    }
+/// How SAM/BAM output carries the comment of a FASTA/Q header (everything after the read
+/// name), see [`with_comment_passthrough`](Aligner::with_comment_passthrough)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommentPassthrough {
+    /// Drop the comment, as minimap2 does without `-y`
+    #[default]
+    Drop,
+    /// Add the fields of the comment that are SAM tags (`TAG:TYPE:VALUE` of type A, i, f or
+    /// Z, as written by `samtools fastq -T`) to each record, as minimap2 `-y` does. Other
+    /// fields are skipped. Fields are separated by tabs if there are any, otherwise by spaces.
+    SamTags,
+    /// Store the whole comment as a string in this tag, e.g. `CO` as `samtools import` does,
+    /// so free-form metadata such as ONT's `runid=... ch=...` is kept
+    Tag([u8; 2]),
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to MapOnt.
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().map_ont();
-    /// ```
-    pub fn map_ont(self) -> Aligner<PresetSet> {
-        self.preset(Preset::MapOnt)
-    }
+/// Which tags of an input BAM record `map_bam_record` (htslib feature) copies to the new
+/// alignments of the read, see [`with_tag_passthrough`](Aligner::with_tag_passthrough)
+///
+/// Tags minimap2 sets itself (NM, AS, SA, cs, ...) are never copied.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TagPassthrough {
+    /// Every tag, as for the reads of an unmapped BAM
+    #[default]
+    All,
+    /// Only these tags, e.g. HP and PS to keep the phase of haplotagged reads when
+    /// realigning them without the stale tags of the old alignment
+    Only(Vec<[u8; 2]>),
+    /// No tag
+    None,
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to AvaPb
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().ava_pb();
-    /// ```
-    pub fn ava_pb(self) -> Aligner<PresetSet> {
-        self.preset(Preset::AvaPb)
+impl TagPassthrough {
+    /// Whether to copy `tag`
+    pub(crate) fn copies(&self, tag: &[u8]) -> bool {
+        match self {
+            TagPassthrough::All => true,
+            TagPassthrough::Only(tags) => tags.iter().any(|t| t == tag),
+            TagPassthrough::None => false,
+        }
     }
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to AvaOnt.
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().ava_ont();
-    /// ```
-    pub fn ava_ont(self) -> Aligner<PresetSet> {
-        self.preset(Preset::AvaOnt)
-    }
+/// Strands on which the splice presets look for splice signals, see
+/// [`with_splice_strand`](Aligner::with_splice_strand)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpliceStrand {
+    /// Both strands, keeping the better alignment, as the splice presets do (minimap2 -ub)
+    #[default]
+    Both,
+    /// Only the transcript strand being the strand of the query (-uf)
+    Forward,
+    /// Only the transcript strand being opposite to the query (-ur)
+    Reverse,
+    /// Ignore splice signals (-un)
+    None,
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to Short
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().short();
-    /// ```
-    pub fn short(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Short)
-    }
+/// Orientation in which queries are mapped, see
+/// [`with_query_orientation`](Aligner::with_query_orientation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueryOrientation {
+    /// As given
+    #[default]
+    AsIs,
+    /// Reverse complemented before mapping, for reads antisense to the molecule they were
+    /// sequenced from
+    ReverseComplement,
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to Map10k
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().map10k();
-    /// ```
-    pub fn map10k(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Map10k)
-    }
+/// How [`Aligner::align_region`] treats the ends of the query and of the target region
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RegionAlignmentMode {
+    /// Start at the first base of both and stop where the score peaks, soft clipping the rest
+    /// of the query, as minimap2 extends the ends of a chain
+    #[default]
+    Extension,
+    /// Align the whole query to the whole region
+    Global,
+}
 
-    /// Ergonomic function for Aligner. Sets the minimap2 preset to cdna
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().cdna();
-    /// ```
-    pub fn cdna(self) -> Aligner<PresetSet> {
-        self.preset(Preset::Cdna)
-    }
+/// Order of the hits of a read, see [`with_hit_order`](Aligner::with_hit_order)
+///
+/// All orders are stable: hits that compare equal stay in minimap2's order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HitOrder {
+    /// minimap2's own order, which usually but not always starts with the primary hit
+    #[default]
+    Minimap2,
+    /// Primary hits, then supplementary hits, then secondary hits
+    PrimaryFirst,
+    /// By alignment score, or by matching bases for hits without alignment, best first. Ties
+    /// put primary hits first.
+    Score,
+    /// By target (in the order of the index), start and end on the target
+    TargetCoordinate,
+}
 
-    /// Create an aligner using a preset.
+impl HitOrder {
+    /// Sort the hits of one read
     ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    pub fn preset(mut self, preset: Preset) -> Aligner<PresetSet> {
-        unsafe {
-            mm_set_opt(&0, &mut self.idxopt, &mut self.mapopt);
-            mm_set_opt(preset.into(), &mut self.idxopt, &mut self.mapopt)
+    /// # Safety
+    /// `regs` must be hits returned by minimap2, with their extra data
+    pub(crate) unsafe fn sort(self, regs: &mut [mm_reg1_t]) {
+        // Primary, supplementary, secondary
+        let rank = |reg: &mm_reg1_t| match (reg.parent == reg.id, reg.sam_pri() > 0) {
+            (true, true) => 0u8,
+            (true, false) => 1,
+            (false, _) => 2,
         };
-
-        Aligner {
-            idxopt: self.idxopt,
-            mapopt: self.mapopt,
-            threads: self.threads,
-            idx: self.idx,
-            idx_reader: self.idx_reader,
-            cigar_clipping: self.cigar_clipping,
-            state: PresetSet,
+        // As in Mapping: the alignment score, or the matching bases without alignment
+        let score = |reg: &mm_reg1_t| {
+            if reg.p.is_null() {
+                reg.mlen
+            } else {
+                (*reg.p).dp_score
+            }
+        };
+        match self {
+            HitOrder::Minimap2 => {}
+            HitOrder::PrimaryFirst => regs.sort_by_key(rank),
+            HitOrder::Score => regs.sort_by_key(|reg| (std::cmp::Reverse(score(reg)), rank(reg))),
+            HitOrder::TargetCoordinate => {
+                regs.sort_by_key(|reg| (reg.rid, reg.rs, reg.re, rank(reg)))
+            }
         }
     }
-
-    // These next few are valid for both Unset and PresetSet
-    // If you make a change copy it below!
 }
 
-impl<S> Aligner<S>
-where
-    S: BuilderState + AcceptsParams,
-{
-    /// Set Alignment mode / cigar mode in minimap2
-    /// ```
-    /// # use minimap2::*;
-    /// Aligner::builder().map_ont().with_cigar();
-    /// ```
-    ///
-    pub fn with_cigar(mut self) -> Self {
-        // Make sure MM_F_CIGAR flag isn't already set
-        assert!((self.mapopt.flag & MM_F_CIGAR as i64) == 0);
+/// What to do when two targets of an index get the same name, see
+/// [`with_duplicate_names`](Aligner::with_duplicate_names)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateNames {
+    /// Fail with [`DuplicateNameError`]
+    #[default]
+    Error,
+    /// Rename the repeats by appending `.2`, `.3`, ... to the name
+    Suffix,
+    /// Keep the names as they are. SAM headers of the index are then invalid.
+    Allow,
+}
 
-        self.mapopt.flag |= MM_F_CIGAR as i64 | MM_F_OUT_CS as i64;
-        self
+impl DuplicateNames {
+    /// The name to give a target called `name`, `seen` holding the names given so far
+    pub(crate) fn rename(
+        self,
+        name: &[u8],
+        seen: &mut HashSet<Vec<u8>>,
+    ) -> Result<Vec<u8>, DuplicateNameError> {
+        if seen.insert(name.to_vec()) {
+            return Ok(name.to_vec());
+        }
+        match self {
+            DuplicateNames::Error => Err(DuplicateNameError {
+                name: String::from_utf8_lossy(name).into_owned(),
+            }),
+            DuplicateNames::Suffix => {
+                // The renamed target must not clash with a name given earlier either
+                let renamed = (2..)
+                    .map(|n| [name, format!(".{n}").as_bytes()].concat())
+                    .find(|renamed| !seen.contains(renamed))
+                    .unwrap();
+                seen.insert(renamed.clone());
+                Ok(renamed)
+            }
+            DuplicateNames::Allow => Ok(name.to_vec()),
+        }
     }
+}
 
-    pub fn with_cigar_clipping(mut self) -> Self {
-        self.cigar_clipping = true;
-        self
+/// Two targets of an index have the same name, with [`DuplicateNames::Error`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateNameError {
+    /// The repeated name
+    pub name: String,
+}
+
+impl std::fmt::Display for DuplicateNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Duplicate sequence name {}", self.name)
     }
+}
 
-    pub fn with_sam_out(mut self) -> Self {
-        // Make sure MM_F_CIGAR flag isn't already set
-        assert!((self.mapopt.flag & MM_F_OUT_SAM as i64) == 0);
+impl std::error::Error for DuplicateNameError {}
 
-        self.mapopt.flag |= MM_F_OUT_SAM as i64;
-        self
+impl From<DuplicateNameError> for &'static str {
+    fn from(_: DuplicateNameError) -> Self {
+        "Duplicate sequence name"
     }
+}
 
-    pub fn with_sam_hit_only(mut self) -> Self {
-        // Make sure MM_F_CIGAR flag isn't already set
-        assert!((self.mapopt.flag & MM_F_SAM_HIT_ONLY as i64) == 0);
+/// Reads the file drivers (`map_file`, `map_file_to_paf`, `map_file_to_sink`, ...) skip before
+/// mapping, see [`with_read_filter`](Aligner::with_read_filter)
+///
+/// ```
+/// # use minimap2::*;
+/// let filter = ReadFilter {
+///     min_len: 1000,
+///     min_mean_qual: Some(10.0),
+///     ..Default::default()
+/// };
+/// assert!(filter.keep(&[b'A'; 5000], Some(&[b'5'; 5000])));
+/// assert!(!filter.keep(&[b'A'; 500], None));
+/// // One base at Q0 drags the mean of four bases at Q40 below Q10
+/// assert!(!filter.keep(b"ACGTA", Some(b"IIII!")));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ReadFilter {
+    /// Skip reads shorter than this
+    pub min_len: usize,
+    /// Skip reads longer than this
+    pub max_len: Option<usize>,
+    /// Skip reads whose mean quality is below this. The mean is taken over error
+    /// probabilities, as by NanoPlot or chopper, rather than over Phred scores. Reads without
+    /// qualities (FASTA) are kept.
+    pub min_mean_qual: Option<f64>,
+}
 
-        self.mapopt.flag |= MM_F_SAM_HIT_ONLY as i64;
-        self
+impl ReadFilter {
+    /// Whether a read passes the filter
+    pub fn keep(&self, seq: &[u8], qual: Option<&[u8]>) -> bool {
+        self.check(seq, qual).is_none()
     }
 
-    /// Sets the gap open penalty for minimap2.
-    ///
-    /// minimap2 -O 4 sets both the short and long gap open penalty to 4.
-    /// [minimap2 code](https://github.com/lh3/minimap2/blob/618d33515e5853c4576d5a3d126fdcda28f0e8a4/main.c#L315)
-    ///
-    /// To set the long gap open penalty, simply provide a value for `penalty_long`.
-    pub fn with_gap_open_penalty(mut self, penalty: i32, penalty_long: Option<i32>) -> Self {
-        self.mapopt.q = penalty;
-        if let Some(penalty_long) = penalty_long {
-            self.mapopt.q2 = penalty_long;
+    /// Why a read fails the filter, if it does
+    fn check(&self, seq: &[u8], qual: Option<&[u8]>) -> Option<FilterReason> {
+        if seq.len() < self.min_len {
+            Some(FilterReason::TooShort)
+        } else if self.max_len.is_some_and(|max_len| seq.len() > max_len) {
+            Some(FilterReason::TooLong)
+        } else if let (Some(min_mean_qual), Some(qual)) = (self.min_mean_qual, qual) {
+            (mean_qual(qual) < min_mean_qual).then_some(FilterReason::LowQuality)
         } else {
-            self.mapopt.q2 = penalty;
+            None
         }
-        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterReason {
+    TooShort,
+    TooLong,
+    LowQuality,
+    Duplicate,
+}
+
+/// Mean quality of a Phred+33 quality string, from the mean of its error probabilities.
+/// NaN if `qual` is empty.
+fn mean_qual(qual: &[u8]) -> f64 {
+    let error: f64 = qual
+        .iter()
+        .map(|&q| 10f64.powf(-(q.saturating_sub(33) as f64) / 10.0))
+        .sum();
+    -10.0 * (error / qual.len() as f64).log10()
+}
+
+/// Reads skipped by a [`ReadFilter`] or as duplicates, see
+/// [`filtered_reads`](Aligner::filtered_reads)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FilteredReads {
+    pub too_short: u64,
+    pub too_long: u64,
+    pub low_quality: u64,
+    /// Reads whose ID was seen before, see [`with_dedup`](Aligner::with_dedup)
+    pub duplicates: u64,
+}
+
+impl FilteredReads {
+    /// All reads skipped
+    pub fn total(&self) -> u64 {
+        self.too_short + self.too_long + self.low_quality + self.duplicates
+    }
+}
+
+/// Counts of [`FilteredReads`], shared by the threads reading input files
+#[derive(Debug, Default)]
+pub(crate) struct ReadFilterCounts {
+    too_short: AtomicU64,
+    too_long: AtomicU64,
+    low_quality: AtomicU64,
+    duplicates: AtomicU64,
+}
+
+impl ReadFilterCounts {
+    fn get(&self) -> FilteredReads {
+        FilteredReads {
+            too_short: self.too_short.load(Ordering::Relaxed),
+            too_long: self.too_long.load(Ordering::Relaxed),
+            low_quality: self.low_quality.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+        }
+    }
+
+    #[cfg(feature = "map-file")]
+    fn add(&self, reason: FilterReason) {
+        let count = match reason {
+            FilterReason::TooShort => &self.too_short,
+            FilterReason::TooLong => &self.too_long,
+            FilterReason::LowQuality => &self.low_quality,
+            FilterReason::Duplicate => &self.duplicates,
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A query is longer than `max_qlen` (see [`with_max_qlen`](Aligner::with_max_qlen)), or
+/// than minimap2 can take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryTooLong {
+    /// Length of the query, or of both mates of a pair
+    pub len: usize,
+    /// The longest query allowed
+    pub max_len: usize,
+}
+
+impl std::fmt::Display for QueryTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Query of length {} is longer than {}",
+            self.len, self.max_len
+        )
+    }
+}
+
+impl std::error::Error for QueryTooLong {}
+
+impl From<QueryTooLong> for &'static str {
+    fn from(_: QueryTooLong) -> Self {
+        "Query is longer than max_qlen"
+    }
+}
+
+/// Names of the targets in an index, with a lookup from name to id
+///
+/// Built once per index so that mappings share the name allocations, which matters for
+/// indexes with many small contigs such as amplicon panels.
+#[derive(Debug, Default)]
+pub(crate) struct TargetNames {
+    pub(crate) names: Vec<Arc<String>>,
+    pub(crate) ids: HashMap<String, u32>,
+}
+
+impl TargetNames {
+    fn new(idx: &mm_idx_t) -> Self {
+        let names: Vec<Arc<String>> = (0..idx.n_seq as usize)
+            .map(|i| Arc::new(unsafe { seq_meta_data(idx, i) }.name))
+            .collect();
+        let ids = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), i as u32))
+            .collect();
+        TargetNames { names, ids }
+    }
+}
+
+/// Read the metadata of the i-th sequence in the index
+///
+/// Caller must ensure i < idx.n_seq
+pub(crate) unsafe fn seq_meta_data(idx: &mm_idx_t, i: usize) -> SeqMetaData {
+    let seq = &*idx.seq.add(i);
+    let name = if seq.name.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(seq.name).to_string_lossy().into_owned()
+    };
+    SeqMetaData {
+        name,
+        length: seq.len,
+        is_alt: seq.is_alt != 0,
+        offset: seq.offset,
+    }
+}
+
+/// ksw2's dual affine gap alignment, as called by minimap2's align.c, with `gaps` being
+/// `[q, e, q2, e2]`. minimap2-sys only builds ksw2 for x86_64 and ARM.
+///
+/// # Safety
+/// `qs` and `ts` must be encoded as 0-4; `ez.cigar` is allocated and must be freed
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm"))]
+#[allow(clippy::too_many_arguments)]
+unsafe fn ksw_extd2(
+    qs: &[u8],
+    ts: &[u8],
+    mat: &[i8; 25],
+    gaps: [i8; 4],
+    bw: i32,
+    zdrop: i32,
+    end_bonus: i32,
+    flag: i32,
+    ez: &mut ksw_extz_t,
+) -> Result<(), &'static str> {
+    let [q, e, q2, e2] = gaps;
+    ksw_extd2_sse(
+        std::ptr::null_mut(),
+        qs.len() as i32,
+        qs.as_ptr(),
+        ts.len() as i32,
+        ts.as_ptr(),
+        5,
+        mat.as_ptr(),
+        q,
+        e,
+        q2,
+        e2,
+        bw,
+        zdrop,
+        end_bonus,
+        flag,
+        ez,
+    );
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+#[allow(clippy::too_many_arguments)]
+unsafe fn ksw_extd2(
+    _qs: &[u8],
+    _ts: &[u8],
+    _mat: &[i8; 25],
+    _gaps: [i8; 4],
+    _bw: i32,
+    _zdrop: i32,
+    _end_bonus: i32,
+    _flag: i32,
+    _ez: &mut ksw_extz_t,
+) -> Result<(), &'static str> {
+    Err("ksw2 is not built for this target")
+}
+
+/// Check that the hits minimap2 returned lie within the targets of `idx`
+///
+/// A corrupted index or an upstream bug could otherwise have a target id index past the end
+/// of the sequence table. On error the hits should be discarded with [`free_hits`].
+///
+/// # Safety
+/// `regs` must point to `n_regs` hits, or be null if there are none
+pub(crate) unsafe fn check_hits(
+    idx: &mm_idx_t,
+    regs: *const mm_reg1_t,
+    n_regs: i32,
+) -> Result<(), &'static str> {
+    for i in 0..n_regs.max(0) as usize {
+        let reg = &*regs.add(i);
+        if reg.rid < 0 || reg.rid as u32 >= idx.n_seq {
+            return Err("Hit on a target outside the index");
+        }
+        let target_len = (*idx.seq.add(reg.rid as usize)).len as i32;
+        if reg.rs < 0 || reg.re < reg.rs || reg.re > target_len {
+            return Err("Hit outside its target");
+        }
+    }
+    Ok(())
+}
+
+/// Free hits returned by minimap2, with their extra data
+///
+/// # Safety
+/// `regs` must be a malloc'ed array of `n_regs` hits, or null
+pub(crate) unsafe fn free_hits(regs: *mut mm_reg1_t, n_regs: i32) {
+    if regs.is_null() {
+        return;
+    }
+    for i in 0..n_regs.max(0) as usize {
+        libc::free((*regs.add(i)).p as *mut c_void);
+    }
+    libc::free(regs as *mut c_void);
+}
+
+/// Hit counts of a query, returned by [`Aligner::map_with_summary`]
+///
+/// Counts cover every hit found by minimap2, including those dropped by `with_max_hits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MappingSummary {
+    pub n_primary: usize,
+    pub n_secondary: usize,
+    pub n_supplementary: usize,
+    /// Whether hits past `max_hits` were dropped
+    pub truncated: bool,
+    /// Whether the query was reverse complemented before mapping, see
+    /// [`with_query_orientation`](Aligner::with_query_orientation)
+    pub reverse_complemented: bool,
+}
+
+impl MappingSummary {
+    /// Number of hits found by minimap2
+    pub fn n_hits(&self) -> usize {
+        self.n_primary + self.n_secondary + self.n_supplementary
+    }
+}
+
+/// `best_n` of the second mapping of [`Aligner::map_with_diagnostics`]
+pub const DIAGNOSTIC_BEST_N: i32 = 10_000;
+
+/// Why hits of a query were reported or not, returned by [`Aligner::map_with_diagnostics`]
+///
+/// minimap2 keeps a secondary hit if its score is at least `pri_ratio` times that of its
+/// primary (or within 2k of it), and only the best `best_n` such hits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MapDiagnostics {
+    /// Hit counts of the query, as returned by `map_with_summary`
+    pub summary: MappingSummary,
+    /// Hits found with `pri_ratio` and `best_n` lifted
+    pub n_candidates: usize,
+    /// Secondary hits dropped because their score is below `pri_ratio` of their primary's
+    pub below_pri_ratio: usize,
+    /// Secondary hits dropped because `best_n` better ones were kept
+    pub past_best_n: usize,
+    /// Score of the best dropped hit
+    pub best_dropped_score: Option<i32>,
+}
+
+/// Classify the secondary hits of a mapping with `pri_ratio` and `best_n` lifted as minimap2's
+/// `mm_select_sub` does with those of `map_opt`, for an index of k-mer size `k`
+///
+/// # Safety
+/// `regs` must point to `n_regs` hits returned by minimap2, sorted by score as it returns them
+unsafe fn diagnose_hits(
+    regs: *const mm_reg1_t,
+    n_regs: i32,
+    map_opt: &mm_mapopt_t,
+    k: i32,
+) -> MapDiagnostics {
+    let n_regs = n_regs.max(0) as usize;
+    let mut diagnostics = MapDiagnostics {
+        n_candidates: n_regs,
+        ..Default::default()
+    };
+    let mut n_secondary = 0;
+    for i in 0..n_regs {
+        let reg = &*regs.add(i);
+        if reg.parent == reg.id || reg.inv() != 0 || reg.parent < 0 || reg.parent as usize >= n_regs
+        {
+            continue;
+        }
+        let parent = &*regs.add(reg.parent as usize);
+        let close = reg.score as f32 >= parent.score as f32 * map_opt.pri_ratio
+            || reg.score + k * 2 >= parent.score;
+        if close && n_secondary < map_opt.best_n {
+            n_secondary += 1;
+            continue;
+        }
+        if close {
+            diagnostics.past_best_n += 1;
+        } else {
+            diagnostics.below_pri_ratio += 1;
+        }
+        diagnostics.best_dropped_score = diagnostics.best_dropped_score.max(Some(reg.score));
+    }
+    diagnostics
+}
+
+/// Mapping result
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Mapping {
+    // The query sequence name.
+    pub query_name: Option<Arc<String>>,
+    pub query_len: Option<NonZeroI32>,
+    pub query_start: i32,
+    pub query_end: i32,
+    pub strand: Strand,
+    pub target_name: Option<Arc<String>>,
+    pub target_len: i32,
+    pub target_start: i32,
+    pub target_end: i32,
+    pub match_len: i32,
+    pub block_len: i32,
+    pub mapq: u32,
+    pub is_primary: bool,
+    pub is_supplementary: bool,
+    /// Primary, secondary, supplementary or inversion, to filter on rather than the two flags
+    /// above
+    pub alignment_type: AlignmentType,
+    pub alignment: Option<Alignment>,
+    /// The aligned target sequence, if requested with `with_target_seq()`
+    pub target_seq: Option<Vec<u8>>,
+}
+
+// Thread local buffer (memory management) for minimap2
+thread_local! {
+    static BUF: RefCell<ThreadLocalBuffer> = RefCell::new(ThreadLocalBuffer::new());
+}
+
+/// Run `f` with this thread's minimap2 buffer
+///
+/// Mapping calls may nest on one thread: a `MapqRecalibration` hook or a `SequenceSource`
+/// can itself call `map()`, on this or another aligner. The nested call then finds the thread
+/// local buffer in use and maps with a fresh buffer, freed when it returns, instead of
+/// panicking on the RefCell. Never hold on to the buffer outside of `f`.
+pub(crate) fn with_buf<T>(f: impl FnOnce(&mut ThreadLocalBuffer) -> T) -> T {
+    BUF.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut buf) => f(&mut buf),
+        Err(_) => f(&mut ThreadLocalBuffer::new()),
+    })
+}
+
+/// ThreadLocalBuffer for minimap2 memory management
+#[derive(Debug)]
+struct ThreadLocalBuffer {
+    buf: *mut mm_tbuf_t,
+    // max_uses: usize,
+    // uses: usize,
+}
+
+impl ThreadLocalBuffer {
+    pub fn new() -> Self {
+        let buf = unsafe { mm_tbuf_init() };
+        Self {
+            buf,
+            // max_uses: 15,
+            // uses: 0,
+        }
+    }
+    /// Return the buffer, checking how many times it has been borrowed.
+    /// Free the memory of the old buffer and reinitialise a new one If
+    /// num_uses exceeds max_uses.
+    pub fn get_buf(&mut self) -> *mut mm_tbuf_t {
+        /* if self.uses > self.max_uses {
+            // println!("renewing threadbuffer");
+            self.free_buffer();
+            let buf = unsafe { mm_tbuf_init() };
+            self.buf = buf;
+            self.uses = 0;
+        }
+        self.uses += 1; */
+        self.buf
+    }
+
+    fn free_buffer(&mut self) {
+        unsafe { mm_tbuf_destroy(self.buf) };
+    }
+}
+
+/// Handle destruction of thread local buffer properly.
+impl Drop for ThreadLocalBuffer {
+    fn drop(&mut self) {
+        // unsafe { mm_tbuf_destroy(self.buf) };
+        self.free_buffer();
+    }
+}
+
+impl Default for ThreadLocalBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Verbosity from which per-call option overrides are printed to stderr
+pub const DEBUG_VERBOSITY: i32 = 4;
+
+/// Set the verbosity of minimap2's C library (`mm_verbose`)
+///
+/// 0 silences all output, 1 prints errors, 2 adds warnings (such as k/w being overridden by a
+/// loaded index) and 3 adds informational messages. minimap2 defaults to 1. From
+/// [`DEBUG_VERBOSITY`], mapping calls whose `max_frag_len` or `extra_flags` change the options
+/// set on the builder also print the options they use, see
+/// [`effective_mapopt`](Aligner::effective_mapopt).
+///
+/// This is a process-wide setting and applies to every Aligner.
+///
+/// # Safety
+/// minimap2 reads `mm_verbose` without synchronization, as does [`verbosity`]. No other thread
+/// may build an index, map reads or call [`verbosity`] or `set_verbosity` while this runs, so
+/// call it before any such threads are started.
+pub unsafe fn set_verbosity(level: i32) {
+    mm_verbose = level;
+}
+
+/// Get the current verbosity of minimap2's C library (`mm_verbose`)
+pub fn verbosity() -> i32 {
+    unsafe { mm_verbose }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Unset;
+
+#[derive(Default, Clone, Copy)]
+pub struct PresetSet;
+
+#[derive(Default, Clone, Copy)]
+pub struct Built;
+
+pub trait BuilderState {}
+impl BuilderState for Unset {}
+impl BuilderState for PresetSet {}
+impl BuilderState for Built {}
+impl BuilderState for () {}
+
+pub trait AcceptsParams {}
+impl AcceptsParams for PresetSet {}
+impl AcceptsParams for Unset {}
+
+/// Function applied to each Mapping to compute its reported MAPQ, see `with_mapq_recalibration`
+pub type MapqRecalibration = Arc<dyn Fn(&Mapping) -> u32 + Send + Sync>;
+
+/// Value of a SAM tag added with `with_record_tags`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagValue {
+    /// Type A
+    Char(u8),
+    /// Type i
+    Int(i32),
+    /// Type f
+    Float(f32),
+    /// Type Z
+    String(String),
+}
+
+/// Function giving the extra SAM tags of the record of each mapping, from the read name and
+/// the mapping, see `with_record_tags`
+pub type RecordTags = Arc<dyn Fn(&[u8], &Mapping) -> Vec<([u8; 2], TagValue)> + Send + Sync>;
+
+/// Aligner struct, mimicking minimap2's python interface
+///
+/// ```
+/// # use minimap2::*;
+/// Aligner::builder();
+/// ```
+
+#[derive(Clone)]
+pub struct Aligner<S: BuilderState> {
+    /// Index options passed to minimap2 (mm_idxopt_t)
+    pub idxopt: IdxOpt,
+
+    /// Mapping options passed to minimap2 (mm_mapopt_t)
+    pub mapopt: MapOpt,
+
+    /// Number of threads to create the index with
+    pub threads: usize,
+
+    /// Index created by minimap2
+    pub idx: Option<Arc<MmIdx>>,
+
+    /// Unused, the index reader is closed once the index is read. See `index_parts` to read
+    /// the parts of an index on demand.
+    pub idx_reader: Option<Arc<mm_idx_reader_t>>,
+
+    /// Whether to add soft clipping to CIGAR result
+    pub cigar_clipping: bool,
+
+    /// Whether to clip supplementary alignments with `H` as minimap2 does, see
+    /// `with_hard_clipping`
+    pub hard_clipping: bool,
+
+    /// Maximum MAPQ reported for a mapping
+    pub max_mapq: Option<u32>,
+
+    /// Recalibrates the MAPQ of each mapping before it is returned
+    pub mapq_recalibration: Option<MapqRecalibration>,
+
+    /// Whether to return the aligned target sequence with each mapping
+    pub output_target_seq: bool,
+
+    /// Number of hits of a query above which to warn, see `with_max_hits`
+    pub max_hits: Option<usize>,
+
+    /// Whether to drop the hits of a query past `max_hits` rather than only warn
+    pub truncate_hits: bool,
+
+    /// What `map_to_sam` does with quality strings of the wrong length
+    pub quality_mismatch: QualityMismatch,
+
+    /// How SAM/BAM output carries FASTA/Q header comments
+    pub comment_passthrough: CommentPassthrough,
+
+    /// Which tags of input BAM records `map_bam_record` copies
+    pub tag_passthrough: TagPassthrough,
+
+    /// Orientation in which queries are mapped
+    pub query_orientation: QueryOrientation,
+
+    /// Order of the mappings returned for a read
+    pub hit_order: HitOrder,
+
+    /// Extra tags added to each SAM/BAM record of a mapping
+    pub record_tags: Option<RecordTags>,
+
+    /// What building an index does with repeated target names
+    pub duplicate_names: DuplicateNames,
+
+    /// Reads the file drivers skip before mapping, see `with_read_filter`
+    pub read_filter: Option<ReadFilter>,
+
+    /// IDs of the reads seen by the file drivers, to skip duplicates, see `with_dedup`
+    pub read_ids: Option<Arc<dyn dedup::ReadIds>>,
+
+    /// Reads skipped by `read_filter` or as duplicates, shared with clones of the aligner
+    read_filter_counts: Arc<ReadFilterCounts>,
+
+    /// Filter for all-vs-all overlaps applied before hits become mappings, see
+    /// `with_ava_filter`
+    pub ava_filter: Option<overlap::AvaFilter>,
+
+    /// Where to read target sequences from when the index has none, see
+    /// `with_sequence_source`
+    pub sequence_source: Option<Arc<dyn SequenceSource>>,
+
+    /// The reference files the index was built from, when built by `with_index_files`
+    pub index_files: Option<Arc<IndexFiles>>,
+
+    /// Target names of the index, shared by all mappings. Built on first use.
+    target_names: OnceLock<Arc<TargetNames>>,
+
+    // State of the builder
+    state: S,
+}
+
+/// Create a default aligner
+impl Default for Aligner<Unset> {
+    fn default() -> Self {
+        Self {
+            idxopt: Default::default(),
+            mapopt: Default::default(),
+            threads: 1,
+            idx: None,
+            idx_reader: None,
+            cigar_clipping: false,
+            hard_clipping: false,
+            max_mapq: None,
+            mapq_recalibration: None,
+            output_target_seq: false,
+            target_names: OnceLock::new(),
+            max_hits: None,
+            truncate_hits: false,
+            quality_mismatch: QualityMismatch::Error,
+            comment_passthrough: CommentPassthrough::Drop,
+            query_orientation: QueryOrientation::AsIs,
+            hit_order: HitOrder::Minimap2,
+            tag_passthrough: TagPassthrough::All,
+            read_filter: None,
+            read_ids: None,
+            read_filter_counts: Default::default(),
+            record_tags: None,
+            duplicate_names: DuplicateNames::Error,
+            ava_filter: None,
+            sequence_source: None,
+            index_files: None,
+            state: Unset,
+        }
+    }
+}
+
+impl Aligner<()> {
+    /// Create a new aligner with default options
+    pub fn builder() -> Aligner<Unset> {
+        let mut aligner = Aligner {
+            mapopt: MapOpt {
+                seed: 11,
+                best_n: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            minimap2_sys::mm_set_opt(&0, &mut aligner.idxopt, &mut aligner.mapopt);
+        }
+
+        aligner
+    }
+}
+
+impl Aligner<Unset> {
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to lr:hqae.
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().lrhqae();
+    /// ```
+    pub fn lrhqae(self) -> Aligner<PresetSet> {
+        self.preset(Preset::LrHqae)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to lr:hq.
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().lrhq();
+    /// ```
+    pub fn lrhq(self) -> Aligner<PresetSet> {
+        self.preset(Preset::LrHq)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to splice
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().splice();
+    /// ```
+    pub fn splice(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Splice)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to splice:hq
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().splice_hq();
+    /// ```
+    pub fn splice_hq(self) -> Aligner<PresetSet> {
+        self.preset(Preset::SpliceHq)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().asm();
+    /// ```
+    pub fn asm(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Asm)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm5
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().asm5();
+    /// ```
+    pub fn asm5(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Asm5)
+    }
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm10
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().asm10();
+    /// ```
+    pub fn asm10(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Asm10)
+    }
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to Asm20
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().asm20();
+    /// ```
+    pub fn asm20(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Asm20)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to sr
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().sr();
+    /// ```
+    pub fn sr(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Sr)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to MapPb
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_pb();
+    /// ```
+    pub fn map_pb(self) -> Aligner<PresetSet> {
+        self.preset(Preset::MapPb)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to MapHifi
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_hifi();
+    /// ```
+    pub fn map_hifi(self) -> Aligner<PresetSet> {
+        self.preset(Preset::MapHifi)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to MapOnt.
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont();
+    /// ```
+    pub fn map_ont(self) -> Aligner<PresetSet> {
+        self.preset(Preset::MapOnt)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to AvaPb
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().ava_pb();
+    /// ```
+    pub fn ava_pb(self) -> Aligner<PresetSet> {
+        self.preset(Preset::AvaPb)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to AvaOnt.
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().ava_ont();
+    /// ```
+    pub fn ava_ont(self) -> Aligner<PresetSet> {
+        self.preset(Preset::AvaOnt)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to Short
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().short();
+    /// ```
+    pub fn short(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Short)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to Map10k
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map10k();
+    /// ```
+    pub fn map10k(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Map10k)
+    }
+
+    /// Ergonomic function for Aligner. Sets the minimap2 preset to cdna
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().cdna();
+    /// ```
+    pub fn cdna(self) -> Aligner<PresetSet> {
+        self.preset(Preset::Cdna)
+    }
+
+    /// Create an aligner using a preset.
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    pub fn preset(mut self, preset: Preset) -> Aligner<PresetSet> {
+        unsafe {
+            mm_set_opt(&0, &mut self.idxopt, &mut self.mapopt);
+            mm_set_opt(preset.into(), &mut self.idxopt, &mut self.mapopt)
+        };
+
+        self.with_state(PresetSet)
+    }
+
+    // These next few are valid for both Unset and PresetSet
+    // If you make a change copy it below!
+}
+
+impl<S> Aligner<S>
+where
+    S: BuilderState + AcceptsParams,
+{
+    /// Set Alignment mode / cigar mode in minimap2
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_cigar();
+    /// ```
+    ///
+    pub fn with_cigar(mut self) -> Self {
+        // Make sure MM_F_CIGAR flag isn't already set
+        assert!((self.mapopt.flag & MM_F_CIGAR as i64) == 0);
+
+        self.mapopt.flag |= MM_F_CIGAR as i64 | MM_F_OUT_CS as i64;
+        self
+    }
+
+    pub fn with_cigar_clipping(mut self) -> Self {
+        self.cigar_clipping = true;
+        self
+    }
+
+    /// Clip the CIGAR of supplementary alignments with `H` rather than `S`, in both
+    /// `cigar_str` and `cigar` (with [`with_cigar_clipping`](Aligner::with_cigar_clipping)),
+    /// as minimap2 does in SAM output. Secondary alignments are hard clipped too when
+    /// `MM_F_SECONDARY_SEQ` is set, and nothing is after `mapopt.set_softclip()` (minimap2 -Y).
+    pub fn with_hard_clipping(mut self) -> Self {
+        self.hard_clipping = true;
+        self
+    }
+
+    /// Returns the aligned part of the target (`target_start..target_end`, forward strand of
+    /// the target) in `Mapping::target_seq`, fetched from the index as uppercase ACGTN
+    ///
+    /// The index must keep its sequences, which is the default.
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .with_target_seq()
+    ///     .with_index("test_data/MT-human.fa", None)
+    ///     .unwrap();
+    /// let query = b"CCAGTTGACACAAAATAGACTACGAAAGTGGCTTTAACATATCTGAACACACAATAGCTAAGACCCAAACTGGGATTAGATACCCCACTATGCTTAGCCC";
+    /// let hit = &aligner.map(query, false, false, None, None, None).unwrap()[0];
+    /// assert_eq!(hit.target_seq.as_deref(), Some(&query[..]));
+    /// ```
+    pub fn with_target_seq(mut self) -> Self {
+        self.output_target_seq = true;
+        self
+    }
+
+    /// Caps the MAPQ of each mapping at `max_mapq` (minimap2 itself caps at 60)
+    ///
+    /// Applied after any recalibration set with `with_mapq_recalibration`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_mapq(30);
+    /// ```
+    pub fn with_max_mapq(mut self, max_mapq: u32) -> Self {
+        self.max_mapq = Some(max_mapq);
+        self
+    }
+
+    /// Recalibrates the MAPQ of each mapping with the given function before it is returned,
+    /// avoiding a second pass over the results (e.g. to implement a custom MAPQ floor).
+    /// ```
+    /// # use minimap2::*;
+    /// // Zero out the MAPQ of short alignments
+    /// Aligner::builder()
+    ///     .map_ont()
+    ///     .with_mapq_recalibration(|m: &Mapping| {
+    ///         if m.block_len < 100 {
+    ///             0
+    ///         } else {
+    ///             m.mapq
+    ///         }
+    ///     });
+    /// ```
+    pub fn with_mapq_recalibration<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Mapping) -> u32 + Send + Sync + 'static,
+    {
+        self.mapq_recalibration = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the largest DP matrix, in cells (query gap × target gap), minimap2 fills to align
+    /// between two seeds (`mapopt.max_sw_mat`, 100M by default). Gaps needing more are not
+    /// aligned and the chain is split around them, see [`DpSkipped`]. Raise it to align
+    /// reads through multi-kb insertions or deletions, at a time and memory cost that grows
+    /// with the product of the gap lengths.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_cigar().with_max_sw_mat(1_000_000_000);
+    /// ```
+    pub fn with_max_sw_mat(mut self, cells: i64) -> Self {
+        assert!(cells > 0, "max_sw_mat must be positive");
+        self.mapopt.max_sw_mat = cells;
+        self
+    }
+
+    /// Sets whether chaining joins chains separated by long gaps (on by default). Equivalent
+    /// to minimap2 --no-long-join when false.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_long_join(false);
+    /// ```
+    pub fn with_long_join(mut self, long_join: bool) -> Self {
+        if long_join {
+            self.mapopt.flag &= !(MM_F_NO_LJOIN as i64);
+        } else {
+            self.mapopt.flag |= MM_F_NO_LJOIN as i64;
+        }
+        self
+    }
+
+    /// Sets whether the query name seeds the tie-breaking of equally scored hits (on by
+    /// default). Equivalent to minimap2 --no-hash-name when false.
+    ///
+    /// minimap2 breaks ties between equally good hits, such as the copies of a repeat, with a
+    /// hash of the query name, its length and `mapopt.seed`, so the primary hit and MAPQ of a
+    /// multi-mapping read depend on its name. Keep this on, with the CLI's seed, to reproduce
+    /// minimap2 output; turn it off to make reads with the same sequence and length map
+    /// identically whatever their names (or when mapping without names).
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_name_hashing(false);
+    /// ```
+    pub fn with_name_hashing(mut self, hash_name: bool) -> Self {
+        if hash_name {
+            self.mapopt.flag &= !(MM_F_NO_HASH_NAME as i64);
+        } else {
+            self.mapopt.flag |= MM_F_NO_HASH_NAME as i64;
+        }
+        self
+    }
+
+    /// Sets the seed of the tie-breaking hash (`mapopt.seed`, 11 by default). Equivalent to
+    /// minimap2 --seed.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_seed(42);
+    /// ```
+    pub fn with_seed(mut self, seed: i32) -> Self {
+        self.mapopt.seed = seed;
+        self
+    }
+
+    /// Sets the maximum number of secondary alignments kept per query (`mapopt.best_n`).
+    /// Equivalent to minimap2 -N; 0 reports no secondary alignments.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_secondary(0);
+    /// ```
+    pub fn with_max_secondary(mut self, max_secondary: u32) -> Self {
+        assert!(
+            max_secondary <= i32::MAX as u32,
+            "max_secondary is too large"
+        );
+        self.mapopt.best_n = max_secondary as i32;
+        self
+    }
+
+    /// Same as [`with_max_secondary`](Aligner::with_max_secondary), named after
+    /// `mapopt.best_n`
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder().map_ont().with_best_n(1);
+    /// assert_eq!(aligner.mapopt.best_n, 1);
+    /// ```
+    pub fn with_best_n(self, best_n: u32) -> Self {
+        self.with_max_secondary(best_n)
+    }
+
+    /// Whether to report secondary alignments (the default). Without them,
+    /// `MM_F_NO_PRINT_2ND` is set, as by minimap2 --secondary=no: minimap2 still finds
+    /// secondary alignments, so MAPQ is unchanged, but all mapping functions drop them.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_secondary(false);
+    /// ```
+    pub fn with_secondary(mut self, secondary: bool) -> Self {
+        if secondary {
+            self.mapopt.flag &= !(MM_F_NO_PRINT_2ND as i64);
+        } else {
+            self.mapopt.flag |= MM_F_NO_PRINT_2ND as i64;
+        }
+        self
+    }
+
+    /// Sets the longest query mapped (`mapopt.max_qlen`, unlimited by default), for both mates
+    /// together when mapping pairs. minimap2 silently returns no hits for longer queries; here
+    /// mapping them fails with [`QueryTooLong`] instead, so they are not mistaken for unmapped
+    /// reads. Queries longer than `i32::MAX` always fail.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_qlen(1_000_000);
+    /// ```
+    pub fn with_max_qlen(mut self, max_qlen: usize) -> Self {
+        assert!(
+            max_qlen > 0 && max_qlen <= i32::MAX as usize,
+            "max_qlen must be between 1 and i32::MAX"
+        );
+        self.mapopt.max_qlen = max_qlen as i32;
+        self
+    }
+
+    /// Guards against queries with an extreme number of hits, such as reads from repeats
+    /// mapped to a repetitive reference.
+    ///
+    /// When a query has more than `max_hits` hits (primary, secondary and supplementary), a
+    /// warning is printed to stderr unless the verbosity is 0. With `truncate`, only the
+    /// first `max_hits` hits are returned, minimap2 having sorted them best first. The full
+    /// counts are available from `map_with_summary`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_hits(100, true);
+    /// ```
+    pub fn with_max_hits(mut self, max_hits: usize, truncate: bool) -> Self {
+        assert!(max_hits > 0, "max_hits must be positive");
+        self.max_hits = Some(max_hits);
+        self.truncate_hits = truncate;
+        self
+    }
+
+    /// Filters all-vs-all overlaps (`ava-ont`/`ava-pb` presets) by length and overhang inside
+    /// the mapping call, freeing the hits that fail before they become Mappings, and sets
+    /// whether each pair of reads is reported once (`suppress_dual`, minimap2 --dual=no).
+    ///
+    /// Applies to `map`, `map_with_summary`, `map_batch` and the file drivers built on them.
+    /// Hits dropped here are not counted by `map_with_summary`.
+    /// ```
+    /// # use minimap2::*;
+    /// use minimap2::overlap::AvaFilter;
+    /// Aligner::builder().ava_ont().with_ava_filter(AvaFilter {
+    ///     min_overlap_len: 2000,
+    ///     max_overhang: Some(1000),
+    ///     suppress_dual: true,
+    /// });
+    /// ```
+    pub fn with_ava_filter(mut self, filter: overlap::AvaFilter) -> Self {
+        if filter.suppress_dual {
+            self.mapopt.flag |= MM_F_NO_DUAL as i64;
+        } else {
+            self.mapopt.flag &= !(MM_F_NO_DUAL as i64);
+        }
+        self.ava_filter = Some(filter);
+        self
+    }
+
+    /// Sets how `map_to_sam` (htslib feature) handles quality strings whose length differs
+    /// from the sequence, e.g. off-by-one output of trimming tools. Defaults to returning an
+    /// error for the read.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_quality_mismatch(QualityMismatch::Trim);
+    /// ```
+    pub fn with_quality_mismatch(mut self, policy: QualityMismatch) -> Self {
+        self.quality_mismatch = policy;
+        self
+    }
+
+    /// Sets how SAM/BAM output (htslib feature) carries FASTA/Q header comments: dropped
+    /// (the default), parsed as SAM tags as with minimap2 `-y`, or kept whole in one tag.
+    /// Applies to `map_to_sam_with_comment`, `map_file_to_bam` and `BamSink`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder()
+    ///     .map_ont()
+    ///     .with_comment_passthrough(CommentPassthrough::Tag(*b"CO"));
+    /// ```
+    pub fn with_comment_passthrough(mut self, mode: CommentPassthrough) -> Self {
+        self.comment_passthrough = mode;
+        self
+    }
+
+    /// Sets which tags of the input record `map_bam_record` (htslib feature) copies to the new
+    /// alignments: all of them (the default), an allowlist, or none. Use an allowlist when
+    /// realigning an aligned BAM, e.g. to carry the HP/PS phase tags of a haplotagged BAM
+    /// over without the tags of the old alignment. Applies to `map_unmapped_bam` and
+    /// `map_unmapped_bam_to_bam` too.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder()
+    ///     .map_hifi()
+    ///     .with_tag_passthrough(TagPassthrough::Only(vec![*b"HP", *b"PS"]));
+    /// ```
+    pub fn with_tag_passthrough(mut self, mode: TagPassthrough) -> Self {
+        self.tag_passthrough = mode;
+        self
+    }
+
+    /// Sets the orientation in which queries are mapped. With
+    /// [`QueryOrientation::ReverseComplement`], each query is reverse complemented (with its
+    /// quality string) before mapping, so mappings, and the SEQ and QUAL of SAM/BAM records,
+    /// describe the reverse complement, and [`MappingSummary::reverse_complemented`] is set.
+    /// SAM/BAM records get an `rc:i:1` tag to record it. Combine with
+    /// [`with_splice_strand`](Aligner::with_splice_strand) to call junctions on the strand of
+    /// the transcript for reads antisense to it, without rewriting the reads first.
+    ///
+    /// Applies to every mapping function, including `map_pair` (each mate is reverse
+    /// complemented, and they keep their order) and `map_batch`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder()
+    ///     .splice()
+    ///     .with_splice_strand(SpliceStrand::Forward)
+    ///     .with_query_orientation(QueryOrientation::ReverseComplement);
+    /// ```
+    pub fn with_query_orientation(mut self, orientation: QueryOrientation) -> Self {
+        self.query_orientation = orientation;
+        self
+    }
+
+    /// Returns the mappings of a read in `order` rather than in minimap2's order, e.g.
+    /// [`HitOrder::PrimaryFirst`] to guarantee that the first mapping is the primary hit.
+    /// Applies to `map`, `map_pair` (to each mate), `map_batch` and the file drivers.
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_hit_order(HitOrder::TargetCoordinate)
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// ```
+    pub fn with_hit_order(mut self, order: HitOrder) -> Self {
+        self.hit_order = order;
+        self
+    }
+
+    /// Adds the tags returned by `f`, called with the read name and the mapping, to each
+    /// SAM/BAM record of a mapping (htslib feature), e.g. a barcode, a cell ID or the pipeline
+    /// version, instead of rewriting the BAM afterwards. Applies to `map_to_sam` and the
+    /// functions built on it (`map_file_to_bam`, `map_bam_record`, ...) and to `BamSink`.
+    /// Unmapped records get no tags, and tags the record already has (minimap2's, or from a
+    /// header comment) are kept.
+    /// ```
+    /// # use minimap2::*;
+    /// // Reads named `cell:read` get their cell barcode in CB
+    /// Aligner::builder()
+    ///     .map_ont()
+    ///     .with_record_tags(|read_name: &[u8], _: &Mapping| {
+    ///         let cell = read_name.split(|&b| b == b':').next().unwrap_or_default();
+    ///         vec![
+    ///             (*b"CB", TagValue::String(String::from_utf8_lossy(cell).into_owned())),
+    ///             (*b"XV", TagValue::String("pipeline-1.2".to_string())),
+    ///         ]
+    ///     });
+    /// ```
+    pub fn with_record_tags<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&[u8], &Mapping) -> Vec<([u8; 2], TagValue)> + Send + Sync + 'static,
+    {
+        self.record_tags = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets what building an index from in-memory sequences (`with_seqs_and_ids`) or from
+    /// several FASTA files (`with_index_files`, `with_index_subset`) does when two targets have
+    /// the same name: fail (the default), rename the repeats, or keep them, which makes the SAM
+    /// header of the index invalid. FASTA names are compared up to the first whitespace of the
+    /// header, as minimap2 names sequences.
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_duplicate_names(DuplicateNames::Suffix)
+    ///     .with_seqs_and_ids(
+    ///         &[b"ACGTACGTACGTACGTACGTACGT".to_vec(), b"TTGCAAGCTTAGCTTAGGCTAGGA".to_vec()],
+    ///         &[b"contig".to_vec(), b"contig".to_vec()],
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!(aligner.seq(1).unwrap().name, "contig.2");
+    /// ```
+    pub fn with_duplicate_names(mut self, policy: DuplicateNames) -> Self {
+        self.duplicate_names = policy;
+        self
+    }
+
+    /// Skips reads that are too short, too long or of too low mean quality while reading input
+    /// files, before they are mapped. Applies to the file drivers (`map_file`,
+    /// `map_file_tolerant`, `map_file_to_paf`, `map_file_to_sink`, `map_file_to_bam`, ...), not
+    /// to sequences passed to `map`. Skipped reads are written nowhere; their counts are
+    /// returned by `filtered_reads` and printed at the end of each file from verbosity 3.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_read_filter(ReadFilter {
+    ///     min_len: 500,
+    ///     max_len: Some(100_000),
+    ///     min_mean_qual: Some(9.0),
+    /// });
+    /// ```
+    pub fn with_read_filter(mut self, filter: ReadFilter) -> Self {
+        self.read_filter = Some(filter);
+        self
+    }
+
+    /// Skips reads whose ID (the header up to the first whitespace) is in `ids`, adding the
+    /// IDs of the others, in the same file drivers as `with_read_filter`. The set is shared by
+    /// all files mapped with the aligner and its clones, so reads repeated across files are
+    /// skipped too. Skipped reads are counted in `filtered_reads().duplicates`.
+    /// ```
+    /// # use minimap2::*;
+    /// use minimap2::dedup::BloomReadIds;
+    /// Aligner::builder()
+    ///     .map_ont()
+    ///     .with_dedup(BloomReadIds::new(10_000_000, 0.001));
+    /// ```
+    pub fn with_dedup<T: dedup::ReadIds + 'static>(mut self, ids: T) -> Self {
+        self.read_ids = Some(Arc::new(ids));
+        self
+    }
+
+    /// Reads target sequences from `source` when the index was built without them
+    /// (MM_I_NO_SEQ), for `fetch_subseq` and `with_target_seq`. Sequences are fetched by
+    /// target name, so the source must use the names of the index.
+    /// ```no_run
+    /// # use minimap2::*;
+    /// # #[cfg(feature = "htslib")]
+    /// let fasta = minimap2::bgzf::BgzfFasta::scan("reference.fa.gz").unwrap();
+    /// # #[cfg(feature = "htslib")]
+    /// Aligner::builder().map_ont().with_sequence_source(fasta);
+    /// ```
+    pub fn with_sequence_source<T: SequenceSource + 'static>(mut self, source: T) -> Self {
+        self.sequence_source = Some(Arc::new(source));
+        self
+    }
+
+    pub fn with_sam_out(mut self) -> Self {
+        // Make sure MM_F_CIGAR flag isn't already set
+        assert!((self.mapopt.flag & MM_F_OUT_SAM as i64) == 0);
+
+        self.mapopt.flag |= MM_F_OUT_SAM as i64;
+        self
+    }
+
+    pub fn with_sam_hit_only(mut self) -> Self {
+        // Make sure MM_F_CIGAR flag isn't already set
+        assert!((self.mapopt.flag & MM_F_SAM_HIT_ONLY as i64) == 0);
+
+        self.mapopt.flag |= MM_F_SAM_HIT_ONLY as i64;
+        self
+    }
+
+    /// Sets the gap open penalty for minimap2.
+    ///
+    /// minimap2 -O 4 sets both the short and long gap open penalty to 4.
+    /// [minimap2 code](https://github.com/lh3/minimap2/blob/618d33515e5853c4576d5a3d126fdcda28f0e8a4/main.c#L315)
+    ///
+    /// To set the long gap open penalty, simply provide a value for `penalty_long`.
+    pub fn with_gap_open_penalty(mut self, penalty: i32, penalty_long: Option<i32>) -> Self {
+        self.mapopt.q = penalty;
+        if let Some(penalty_long) = penalty_long {
+            self.mapopt.q2 = penalty_long;
+        } else {
+            self.mapopt.q2 = penalty;
+        }
+        self
+    }
+
+    /// Sets the gap extension penalty (`mapopt.e`), and the long one (`mapopt.e2`) to
+    /// `penalty_long` or else to `penalty`. Equivalent to minimap2 -E INT[,INT].
+    ///
+    /// With two penalties, minimap2 requires E1 > E2 and O1 + E1 < O2 + E2; see
+    /// [`check_opts`](Aligner::check_opts).
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_gap_extension_penalty(2, Some(1));
+    /// ```
+    pub fn with_gap_extension_penalty(mut self, penalty: i32, penalty_long: Option<i32>) -> Self {
+        self.mapopt.e = penalty;
+        self.mapopt.e2 = penalty_long.unwrap_or(penalty);
+        self
+    }
+
+    /// Sets the score of a matching base (`mapopt.a`). Equivalent to minimap2 -A.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_match_score(2);
+    /// ```
+    pub fn with_match_score(mut self, score: i32) -> Self {
+        self.mapopt.a = score;
+        self
+    }
+
+    /// Sets the penalty of a mismatching base (`mapopt.b`). Equivalent to minimap2 -B.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_mismatch_penalty(4);
+    /// ```
+    pub fn with_mismatch_penalty(mut self, penalty: i32) -> Self {
+        self.mapopt.b = penalty;
+        self
+    }
+
+    /// Sets the Z-drop score (`mapopt.zdrop`) at which extension stops, and the one for
+    /// inversions (`mapopt.zdrop_inv`) to `zdrop_inv` or else to `zdrop`. Equivalent to
+    /// minimap2 -z INT[,INT]; `zdrop_inv` must not be above `zdrop`, see
+    /// [`check_opts`](Aligner::check_opts).
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_zdrop(400, Some(200));
+    /// ```
+    pub fn with_zdrop(mut self, zdrop: i32, zdrop_inv: Option<i32>) -> Self {
+        self.mapopt.zdrop = zdrop;
+        self.mapopt.zdrop_inv = zdrop_inv.unwrap_or(zdrop);
+        self
+    }
+
+    /// Sets the bandwidth of chaining and alignment (`mapopt.bw`), and the one for long joins
+    /// (`mapopt.bw_long`) if `bw_long` is given. Equivalent to minimap2 -r INT[,INT].
+    ///
+    /// `bw` must not be above `bw_long`, including one left by the preset; see
+    /// [`check_opts`](Aligner::check_opts). Values above `i32::MAX` are capped.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_bandwidth(500, Some(20000));
+    /// ```
+    pub fn with_bandwidth(mut self, bw: u32, bw_long: Option<u32>) -> Self {
+        self.mapopt.bw = i32::try_from(bw).unwrap_or(i32::MAX);
+        if let Some(bw_long) = bw_long {
+            self.mapopt.bw_long = i32::try_from(bw_long).unwrap_or(i32::MAX);
+        }
+        self
+    }
+
+    /// Sets the minimal ratio of the score of a secondary to that of the primary alignment for
+    /// it to be kept (`mapopt.pri_ratio`). Equivalent to minimap2 -p; must be within 0 and 1, see
+    /// [`check_opts`](Aligner::check_opts).
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_pri_ratio(0.5);
+    /// ```
+    pub fn with_pri_ratio(mut self, ratio: f32) -> Self {
+        self.mapopt.pri_ratio = ratio;
+        self
+    }
+
+    /// Sets the minimal chaining score of a hit (`mapopt.min_chain_score`). Equivalent to
+    /// minimap2 -m.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_min_chain_score(100);
+    /// ```
+    pub fn with_min_chain_score(mut self, score: u32) -> Self {
+        self.mapopt.min_chain_score = i32::try_from(score).unwrap_or(i32::MAX);
+        self
+    }
+
+    /// Sets the minimal number of minimizers in a chain (`mapopt.min_cnt`). Equivalent to
+    /// minimap2 -n.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_min_chain_count(5);
+    /// ```
+    pub fn with_min_chain_count(mut self, count: u32) -> Self {
+        self.mapopt.min_cnt = i32::try_from(count).unwrap_or(i32::MAX);
+        self
+    }
+
+    /// Sets the minimal peak alignment score of a hit (`mapopt.min_dp_max`). Equivalent to
+    /// minimap2 -s.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_min_dp_score(100);
+    /// ```
+    pub fn with_min_dp_score(mut self, score: u32) -> Self {
+        self.mapopt.min_dp_max = i32::try_from(score).unwrap_or(i32::MAX);
+        self
+    }
+
+    /// Maps the reads of a fragment (e.g. the mates of a pair) jointly or not
+    /// (`MM_F_FRAG_MODE`). Equivalent to minimap2 --frag=yes/no; the short-read preset turns
+    /// it on. Cannot be combined with splicing; see [`check_opts`](Aligner::check_opts).
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().sr().with_frag_mode(false);
+    /// ```
+    pub fn with_frag_mode(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.mapopt.flag |= MM_F_FRAG_MODE as i64;
+        } else {
+            self.mapopt.flag &= !(MM_F_FRAG_MODE as i64);
+        }
+        self
+    }
+
+    /// Sets the verbosity of minimap2's C library, see [`set_verbosity`]
+    ///
+    /// This is a process-wide setting and applies to every Aligner, not only this one.
+    /// ```
+    /// # use minimap2::*;
+    /// // Silence warnings printed to stderr by minimap2, before any mapping threads start
+    /// unsafe { Aligner::builder().map_ont().with_verbosity(0) };
+    /// ```
+    ///
+    /// # Safety
+    /// As for [`set_verbosity`]: no other thread may build an index or map reads meanwhile.
+    pub unsafe fn with_verbosity(self, level: i32) -> Self {
+        set_verbosity(level);
+        self
+    }
+
+    /// Sets the maximum gap on the query (and on the reference unless `with_max_gap_ref` is
+    /// also used) when chaining. Equivalent to minimap2 -g.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_gap(10000);
+    /// ```
+    pub fn with_max_gap(mut self, max_gap: u32) -> Self {
+        assert!(max_gap > 0, "max_gap must be positive");
+        assert!(max_gap <= i32::MAX as u32, "max_gap is too large");
+        self.mapopt.max_gap = max_gap as i32;
+        self
+    }
+
+    /// Sets the maximum gap on the reference when chaining, independently of `with_max_gap`.
+    /// For the splice presets this is the maximum intron length, prefer `with_max_intron_len`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_gap_ref(10000);
+    /// ```
+    pub fn with_max_gap_ref(mut self, max_gap_ref: u32) -> Self {
+        assert!(max_gap_ref > 0, "max_gap_ref must be positive");
+        assert!(max_gap_ref <= i32::MAX as u32, "max_gap_ref is too large");
+        self.mapopt.max_gap_ref = max_gap_ref as i32;
+        self
+    }
+
+    /// Sets the maximum intron length for the splice presets. Equivalent to minimap2 -G.
+    ///
+    /// This also sets the chaining bandwidths, as minimap2 does. Must be called after a splice
+    /// preset (splice, splice_hq, cdna), panics otherwise.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().splice().with_max_intron_len(50000);
+    /// ```
+    pub fn with_max_intron_len(mut self, max_intron_len: u32) -> Self {
+        assert!(
+            (self.mapopt.flag & MM_F_SPLICE as i64) != 0,
+            "Max intron length only applies to splice presets"
+        );
+        assert!(max_intron_len > 0, "max_intron_len must be positive");
+        assert!(
+            max_intron_len <= i32::MAX as u32,
+            "max_intron_len is too large"
+        );
+        unsafe { mm_mapopt_max_intron_len(&mut self.mapopt, max_intron_len as i32) };
+        self
+    }
+
+    /// Sets the strands on which splice signals are looked for, relative to the query.
+    /// Equivalent to minimap2 -u. Use [`SpliceStrand::Forward`] for reads in the orientation
+    /// of their transcript, such as stranded cDNA.
+    ///
+    /// Must be called after a splice preset (splice, splice_hq, cdna), panics otherwise.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().splice().with_splice_strand(SpliceStrand::Forward);
+    /// ```
+    pub fn with_splice_strand(mut self, strand: SpliceStrand) -> Self {
+        assert!(
+            (self.mapopt.flag & MM_F_SPLICE as i64) != 0,
+            "Splice strand only applies to splice presets"
+        );
+        let bits = match strand {
+            SpliceStrand::Both => MM_F_SPLICE_FOR | MM_F_SPLICE_REV,
+            SpliceStrand::Forward => MM_F_SPLICE_FOR,
+            SpliceStrand::Reverse => MM_F_SPLICE_REV,
+            SpliceStrand::None => 0,
+        };
+        self.mapopt.flag &= !((MM_F_SPLICE_FOR | MM_F_SPLICE_REV) as i64);
+        self.mapopt.flag |= bits as i64;
+        self
+    }
+
+    /// Sets a fixed occurrence threshold for minimizers: minimizers occurring more than
+    /// `mid_occ` times in the index are not used as seeds. Equivalent to minimap2 -f INT.
+    ///
+    /// By default the threshold is computed when the index is loaded so that the top
+    /// fraction of most frequent minimizers is dropped, see `with_mid_occ_frac`. Three
+    /// options then bring some of the dropped minimizers back:
+    /// - `with_occ_dist` keeps minimizers occurring up to `max_max_occ` times if they are far
+    ///   enough apart on the query, so repeats are not left without any seed
+    /// - `with_max_occ` reseeds reads without a good chain with a higher threshold
+    /// - `with_mid_occ_range` bounds the computed threshold
+    ///
+    /// In repeat-rich genomes a higher threshold finds more repeat copies at the cost of time.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_mid_occ(500);
+    /// ```
+    pub fn with_mid_occ(mut self, mid_occ: u32) -> Self {
+        assert!(mid_occ > 0, "mid_occ must be positive");
+        assert!(mid_occ <= i32::MAX as u32, "mid_occ is too large");
+        self.mapopt.mid_occ = mid_occ as i32;
+        self
+    }
+
+    /// Drops the top `frac` fraction of most frequent minimizers as seeds (2e-4 by default),
+    /// computing the occurrence threshold when the index is loaded. Equivalent to minimap2
+    /// -f FLOAT. Replaces any fixed threshold from `with_mid_occ`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_mid_occ_frac(0.001);
+    /// ```
+    pub fn with_mid_occ_frac(mut self, frac: f32) -> Self {
+        assert!(
+            frac > 0.0 && frac < 1.0,
+            "mid_occ_frac must be between 0 and 1"
+        );
+        self.mapopt.mid_occ_frac = frac;
+        self.mapopt.mid_occ = 0;
+        self
+    }
+
+    /// Bounds the occurrence threshold computed from `mid_occ_frac` (10 and 1,000,000 by
+    /// default). Equivalent to minimap2 -U INT1,INT2. Has no effect on a fixed threshold set
+    /// with `with_mid_occ`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_mid_occ_range(50, 5000);
+    /// ```
+    pub fn with_mid_occ_range(mut self, min: u32, max: u32) -> Self {
+        assert!(min > 0, "min mid_occ must be positive");
+        assert!(min <= max, "min mid_occ must not be above max");
+        assert!(max <= i32::MAX as u32, "max mid_occ is too large");
+        self.mapopt.min_mid_occ = min as i32;
+        self.mapopt.max_mid_occ = max as i32;
+        self
+    }
+
+    /// Sets the occurrence threshold of the second round of seeding, run with a fixed
+    /// `mid_occ` for reads whose best chain does not cover them well. Equivalent to the INT2 of
+    /// minimap2 -f INT1,INT2; only has an effect above `mid_occ`.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_mid_occ(200).with_max_occ(2000);
+    /// ```
+    pub fn with_max_occ(mut self, max_occ: u32) -> Self {
+        assert!(max_occ > 0, "max_occ must be positive");
+        assert!(max_occ <= i32::MAX as u32, "max_occ is too large");
+        self.mapopt.max_occ = max_occ as i32;
+        self
+    }
+
+    /// Keeps minimizers occurring more than `mid_occ` but at most `max_max_occ` times as seeds
+    /// if they are at least `occ_dist` bases apart on the query (500 and 4095 by default for
+    /// long reads), so that reads in repeats keep some seeds. Equivalent to minimap2
+    /// --occ-dist and --max-max-occ; an `occ_dist` of 0 turns it off.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_occ_dist(1000, 10000);
+    /// ```
+    pub fn with_occ_dist(mut self, occ_dist: u32, max_max_occ: u32) -> Self {
+        assert!(occ_dist <= i32::MAX as u32, "occ_dist is too large");
+        assert!(max_max_occ <= i32::MAX as u32, "max_max_occ is too large");
+        self.mapopt.occ_dist = occ_dist as i32;
+        self.mapopt.max_max_occ = max_max_occ as i32;
+        self
+    }
+
+    // Expert options: chaining
+    //
+    // These change how seeds are chained, below the level the presets are tuned at. They are
+    // experimental: minimap2 may change their meaning or defaults between versions, and
+    // `check_opts` does not validate them.
+
+    /// Scales the gap cost of chaining (`mapopt.chain_gap_scale`, 1.0 by default). Equivalent
+    /// to minimap2 --chain-gap-scale; above 1 chains break more readily at indels.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_chain_gap_scale(1.5);
+    /// ```
+    pub fn with_chain_gap_scale(mut self, scale: f32) -> Self {
+        assert!(scale > 0.0, "chain_gap_scale must be positive");
+        self.mapopt.chain_gap_scale = scale;
+        self
+    }
+
+    /// Scales the penalty of seeds skipped while chaining (`mapopt.chain_skip_scale`, 0 or off
+    /// by default). Equivalent to minimap2 --chain-skip-scale.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_chain_skip_scale(0.3);
+    /// ```
+    pub fn with_chain_skip_scale(mut self, scale: f32) -> Self {
+        assert!(scale >= 0.0, "chain_skip_scale must not be negative");
+        self.mapopt.chain_skip_scale = scale;
+        self
+    }
+
+    /// Sets how many predecessors of a seed are tried when chaining (`mapopt.max_chain_iter`,
+    /// 5000 by default). Equivalent to minimap2 --max-chain-iter; lower is faster and may
+    /// miss chains across dense repeats.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_chain_iter(1000);
+    /// ```
+    pub fn with_max_chain_iter(mut self, iterations: u32) -> Self {
+        assert!(iterations > 0, "max_chain_iter must be positive");
+        assert!(iterations <= i32::MAX as u32, "max_chain_iter is too large");
+        self.mapopt.max_chain_iter = iterations as i32;
+        self
+    }
+
+    /// Stops looking for a better predecessor of a seed after `skips` tries without
+    /// improvement (`mapopt.max_chain_skip`, 25 by default). Equivalent to minimap2
+    /// --max-chain-skip.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_max_chain_skip(50);
+    /// ```
+    pub fn with_max_chain_skip(mut self, skips: u32) -> Self {
+        assert!(skips <= i32::MAX as u32, "max_chain_skip is too large");
+        self.mapopt.max_chain_skip = skips as i32;
+        self
+    }
+
+    /// Sets the k-mer size of the index (`idxopt.k`), equivalent to minimap2 -k
+    ///
+    /// Must be between 1 and 28, the largest k minimap2's 56-bit k-mer encoding holds; panics
+    /// otherwise rather than truncating into the C field. Only applies to indexes built from
+    /// FASTA/FASTQ: prebuilt `.mmi` indexes keep the k they were built with.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_kmer(17);
+    /// ```
+    pub fn with_kmer(mut self, k: usize) -> Self {
+        assert!((1..=28).contains(&k), "k must be between 1 and 28");
+        self.idxopt.k = k as i16;
+        self
+    }
+
+    /// Sets the minimizer window size of the index (`idxopt.w`), equivalent to minimap2 -w
+    ///
+    /// Must be between 1 and 255; panics otherwise. Only applies to indexes built from
+    /// FASTA/FASTQ.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_kmer(17).with_window(11);
+    /// ```
+    pub fn with_window(mut self, w: usize) -> Self {
+        assert!((1..=255).contains(&w), "w must be between 1 and 255");
+        self.idxopt.w = w as i16;
+        self
+    }
+
+    /// Sets the number of bits of the minimizer hash used to bucket the index
+    /// (`idxopt.bucket_bits`, 14 by default), trading memory for lookup speed. minimap2 uses
+    /// at most 2k bits.
+    ///
+    /// Must be between 1 and 32; panics otherwise. Only applies to indexes built from
+    /// FASTA/FASTQ.
+    /// ```
+    /// # use minimap2::*;
+    /// Aligner::builder().map_ont().with_bucket_bits(16);
+    /// ```
+    pub fn with_bucket_bits(mut self, bits: usize) -> Self {
+        assert!(
+            (1..=32).contains(&bits),
+            "bucket_bits must be between 1 and 32"
+        );
+        self.idxopt.bucket_bits = bits as i16;
+        self
     }
 
     /// Sets the number of threads minimap2 will use for building the index
     /// ```
     /// # use minimap2::*;
-    /// Aligner::builder().with_index_threads(10);
+    /// Aligner::builder().with_index_threads(10);
+    /// ```
+    ///
+    /// Set the number of threads (prefer to use the struct config)
+    pub fn with_index_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    #[deprecated(since = "0.1.17", note = "Please use `with_index_threads` instead")]
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    // Check options
+    /// Check if the options are valid - Maps to mm_check_opt in minimap2
+    ///
+    /// The error names the failed check; use [`ffi::error::check_opt`] for the typed [`ffi::error::OptError`].
+    /// Building an index runs this check and fails with the same error.
+    pub fn check_opts(&self) -> Result<(), &'static str> {
+        minimap2_sys::error::check_opt(&self.idxopt, &self.mapopt).map_err(Into::into)
+    }
+
+    /// Set index parameters for minimap2 using builder pattern
+    /// Creates the index as well with the given number of threads (set at struct creation).
+    /// You must set the number of threads before calling this function.
+    ///
+    /// Parameters:
+    /// path: Location of pre-built index or FASTA/FASTQ file (may be gzipped or plaintext)
+    /// Output: Option (None) or a filename
+    ///
+    /// Returns the aligner with the index set
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// // Do not save the index file
+    /// Aligner::builder().map_ont().with_index("test_data/test_data.fasta", None);
+    ///
+    /// // Save the index file as my_index.mmi
+    /// Aligner::builder().map_ont().with_index("test_data/test_data.fasta", Some("my_index.mmi"));
+    ///
+    /// // Use the previously built index
+    /// Aligner::builder().map_ont().with_index("my_index.mmi", None);
+    /// ```
+    pub fn with_index<P>(
+        self,
+        path: P,
+        output: Option<&str>,
+    ) -> Result<Aligner<Built>, &'static str>
+    where
+        P: AsRef<Path>,
+    {
+        match self.set_index(path, output) {
+            Ok(aln) => Ok(aln),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sets the index, uses the builder pattern. Returns Aligner<Built> if successful.
+    ///
+    /// Only the first part of a multi-part index is read, see `index_parts` to read the
+    /// others.
+    pub fn set_index<P>(self, path: P, output: Option<&str>) -> Result<Aligner<Built>, &'static str>
+    where
+        P: AsRef<Path>,
+    {
+        self.index_parts(path, output)?
+            .next()
+            .ok_or("Index is empty")
+    }
+
+    /// Build with a prebuilt index read from `reader`, as written by
+    /// [`write_index`](Aligner::write_index) or minimap2 `-d`, e.g. from memory, a network
+    /// stream or an object store
+    ///
+    /// minimap2 only reads indexes from files, so the index is copied to a temporary file and
+    /// loaded as by [`with_index`](Aligner::with_index), then the file is removed. Only the
+    /// first part of a multi-part index is read.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let mut index = Vec::new();
+    /// aligner.write_index(&mut index).unwrap();
+    ///
+    /// let loaded = Aligner::builder().map_ont().load_index(&index[..]).unwrap();
+    /// assert_eq!(loaded.n_seq(), aligner.n_seq());
+    /// ```
+    pub fn load_index<R: std::io::Read>(
+        self,
+        mut reader: R,
+    ) -> Result<Aligner<Built>, &'static str> {
+        use std::io::Write;
+
+        // Anything else would be indexed as a FASTA
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| "Not a minimap2 index")?;
+        if magic != MM_IDX_MAGIC.to_bytes() {
+            return Err("Not a minimap2 index");
+        }
+
+        let temp = TempFile::new("mmi");
+        let mut file =
+            std::fs::File::create(&temp.0).map_err(|_| "Unable to create temporary index file")?;
+        file.write_all(&magic)
+            .and_then(|_| std::io::copy(&mut reader, &mut file))
+            .and_then(|_| file.flush())
+            .map_err(|_| "Unable to write temporary index file")?;
+        drop(file);
+        self.with_index(&temp.0, None)
+    }
+
+    /// Build a single index from several reference FASTA/FASTQ files (optionally gzipped),
+    /// e.g. the assemblies of a pan-reference panel
+    ///
+    /// The sequences are copied, in the order given, into a temporary FASTA which is indexed
+    /// on `with_index_threads` threads as by [`with_index`](Aligner::with_index), and removed
+    /// afterwards. The file each target came from is kept in
+    /// [`index_files`](Aligner::index_files), see [`source_file`](Aligner::source_file); it is
+    /// not stored in `output`, if given. Repeated sequence names are handled as set with
+    /// [`with_duplicate_names`](Aligner::with_duplicate_names), and the references must fit in
+    /// one index part (`idxopt.batch_size`).
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index_files(&["test_data/genome.fa", "test_data/MT-human.fa"], None)
+    ///     .unwrap();
+    /// assert_eq!(aligner.n_seq(), 3);
+    /// assert_eq!(
+    ///     aligner.source_file("MT_human"),
+    ///     Some(std::path::Path::new("test_data/MT-human.fa"))
+    /// );
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn with_index_files<P>(
+        mut self,
+        paths: &[P],
+        output: Option<&str>,
+    ) -> Result<Aligner<Built>, &'static str>
+    where
+        P: AsRef<Path>,
+    {
+        if paths.is_empty() {
+            return Err("No index files");
+        }
+
+        let temp = TempFile::new("fa");
+        let index_files = temp.write_records(paths, self.duplicate_names, |_| true)?;
+        self.index_files = Some(Arc::new(index_files));
+        self.with_single_part_index(&temp.0, output)
+    }
+
+    /// Build an index of only the named contigs of a reference FASTA/FASTQ file (optionally
+    /// gzipped), to avoid indexing a whole genome when only a few targets are relevant
+    ///
+    /// The file is streamed and the named contigs copied to a temporary FASTA, which is
+    /// indexed as by [`with_index`](Aligner::with_index) and removed afterwards. Targets keep
+    /// the order of the file. Every name must be found in the file; names are matched up to the
+    /// first whitespace of the header, as minimap2 names sequences. Prebuilt `.mmi` indexes can
+    /// not be subset.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index_subset("test_data/genome.fa", &["chr2"], None)
+    ///     .unwrap();
+    /// assert_eq!(aligner.n_seq(), 1);
+    /// assert_eq!(aligner.seqs().next().unwrap().name, "chr2");
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn with_index_subset<P, S>(
+        self,
+        path: P,
+        contig_names: &[S],
+        output: Option<&str>,
+    ) -> Result<Aligner<Built>, &'static str>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str>,
+    {
+        if contig_names.is_empty() {
+            return Err("No contigs to index");
+        }
+
+        let wanted: std::collections::HashSet<&[u8]> = contig_names
+            .iter()
+            .map(|name| name.as_ref().as_bytes())
+            .collect();
+        let mut found = std::collections::HashSet::new();
+
+        let temp = TempFile::new("fa");
+        temp.write_records(&[path], self.duplicate_names, |name| {
+            let keep = wanted.contains(name);
+            if keep {
+                found.insert(name.to_vec());
+            }
+            keep
+        })?;
+        if found.len() < wanted.len() {
+            return Err("Contig not found in index file");
+        }
+
+        self.with_single_part_index(&temp.0, output)
+    }
+
+    /// Read the index at `path`, failing if it has more than one part
+    #[cfg(feature = "map-file")]
+    fn with_single_part_index(
+        self,
+        path: &Path,
+        output: Option<&str>,
+    ) -> Result<Aligner<Built>, &'static str> {
+        let mut parts = self.index_parts(path, output)?;
+        let aligner = parts.next().ok_or("Index is empty")?;
+        if parts.next().is_some() {
+            return Err("References do not fit in one index part, raise idxopt.batch_size");
+        }
+        Ok(aligner)
+    }
+
+    /// Move the settings of this builder into a built aligner, with the index in `idx`
+    pub(crate) fn into_built(self) -> Aligner<Built> {
+        Aligner {
+            idx_reader: None,
+            ..self.with_state(Built)
+        }
+    }
+
+    /// Move all settings of this aligner into one in builder state `state`
+    pub(crate) fn with_state<T: BuilderState>(self, state: T) -> Aligner<T> {
+        Aligner {
+            idxopt: self.idxopt,
+            mapopt: self.mapopt,
+            threads: self.threads,
+            idx: self.idx,
+            idx_reader: self.idx_reader,
+            cigar_clipping: self.cigar_clipping,
+            hard_clipping: self.hard_clipping,
+            max_mapq: self.max_mapq,
+            mapq_recalibration: self.mapq_recalibration,
+            output_target_seq: self.output_target_seq,
+            target_names: OnceLock::new(),
+            max_hits: self.max_hits,
+            truncate_hits: self.truncate_hits,
+            quality_mismatch: self.quality_mismatch,
+            comment_passthrough: self.comment_passthrough,
+            query_orientation: self.query_orientation,
+            hit_order: self.hit_order,
+            tag_passthrough: self.tag_passthrough,
+            read_filter: self.read_filter,
+            read_ids: self.read_ids,
+            read_filter_counts: self.read_filter_counts,
+            record_tags: self.record_tags,
+            duplicate_names: self.duplicate_names,
+            ava_filter: self.ava_filter,
+            sequence_source: self.sequence_source,
+            index_files: self.index_files,
+            state,
+        }
+    }
+
+    /// Use a single sequence as the index. Sets the sequence ID to "N/A".
+    /// Can not be combined with `with_index` or `set_index`.
+    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
+    /// ```
+    /// # use minimap2::*;
+    /// # let seq = "CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
+    /// let aligner = Aligner::builder().map_ont().with_seq(seq.as_bytes()).expect("Unable to build index");
+    /// let query = b"CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCG";
+    /// let hits = aligner.map(query, false, false, None, None, Some(b"Query Name"));
+    /// assert_eq!(hits.unwrap().len(), 1);
+    /// ```
+    pub fn with_seq(self, seq: &[u8]) -> Result<Aligner<Built>, &'static str>
+// where T: AsRef<[u8]> + std::ops::Deref<Target = str>,
+    {
+        let default_id = "N/A";
+        self.with_seq_and_id(seq, default_id.as_bytes())
+    }
+
+    /// Use a single sequence as the index. Sets the sequence ID to "N/A".
+    /// Can not be combined with `with_index` or `set_index`.
+    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
+    /// ```
+    /// # use minimap2::*;
+    /// # let seq = "CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
+    /// # let id = "seq1";
+    /// let aligner = Aligner::builder().map_ont().with_seq_and_id(seq.as_bytes(), id.as_bytes()).expect("Unable to build index");
+    /// let query = b"CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCG";
+    /// let hits = aligner.map(query, false, false, None, None, Some(b"Sample Query"));
+    /// assert_eq!(hits.as_ref().unwrap().len(), 1);
+    /// assert_eq!(hits.as_ref().unwrap()[0].target_name.as_ref().unwrap().as_str(), id);
+    /// ```
+    pub fn with_seq_and_id(self, seq: &[u8], id: &[u8]) -> Result<Aligner<Built>, &'static str>
+// where T: AsRef<[u8]> + std::ops::Deref<Target = str>,
+    {
+        assert!(
+            self.idx.is_none(),
+            "Index already set. Can not set sequence as index."
+        );
+        assert!(!seq.is_empty(), "Sequence is empty");
+        assert!(!id.is_empty(), "ID is empty");
+
+        self.with_seqs_and_ids(&[seq.to_vec()], &[id.to_vec()])
+    }
+
+    /// Pass multiple sequences to build an index functionally.
+    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
+    /// Can not be combined with `with_index` or `set_index`.
+    /// Sets the sequence IDs to "Unnamed Sequence n" where n is the sequence number.
+    pub fn with_seqs(self, seqs: &[Vec<u8>]) -> Result<Aligner<Built>, &'static str> {
+        assert!(
+            self.idx.is_none(),
+            "Index already set. Can not set sequence as index."
+        );
+        assert!(!seqs.is_empty(), "Must have at least one sequence");
+
+        let mut ids: Vec<Vec<u8>> = Vec::new();
+        for i in 0..seqs.len() {
+            ids.push(format!("Unnamed Sequence {}", i).into_bytes());
+        }
+
+        self.with_seqs_and_ids(seqs, &ids)
+    }
+
+    /// Pass multiple sequences and corresponding IDs to build an index functionally.
+    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
+    /// Repeated IDs are handled as set with `with_duplicate_names`.
+    // https://github.com/lh3/minimap2/blob/c2f07ff2ac8bdc5c6768e63191e614ea9012bd5d/index.c#L408
+    pub fn with_seqs_and_ids(
+        self,
+        seqs: &[Vec<u8>],
+        ids: &[Vec<u8>],
+    ) -> Result<Aligner<Built>, &'static str> {
+        self.check_opts()?;
+        self.try_with_seqs_and_ids(seqs, ids).map_err(Into::into)
+    }
+
+    /// Same as [`with_seqs_and_ids`](Aligner::with_seqs_and_ids), the error naming the repeated
+    /// ID with [`DuplicateNames::Error`]
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let seq = b"ACGTACGTACGTACGTACGTACGT".to_vec();
+    /// let err = Aligner::builder()
+    ///     .map_ont()
+    ///     .try_with_seqs_and_ids(&[seq.clone(), seq], &[b"a".to_vec(), b"a".to_vec()])
+    ///     .err()
+    ///     .unwrap();
+    /// assert_eq!(err.name, "a");
+    /// ```
+    pub fn try_with_seqs_and_ids(
+        self,
+        seqs: &[Vec<u8>],
+        ids: &[Vec<u8>],
+    ) -> Result<Aligner<Built>, DuplicateNameError> {
+        assert!(
+            seqs.len() == ids.len(),
+            "Number of sequences and IDs must be equal"
+        );
+        assert!(!seqs.is_empty(), "Must have at least one sequence and ID");
+
+        let seqs: Vec<std::ffi::CString> = seqs
+            .iter()
+            .map(|s| std::ffi::CString::new(s.clone()).expect("Invalid Sequence"))
+            .collect();
+        let mut seen = HashSet::new();
+        let ids: Vec<std::ffi::CString> = ids
+            .iter()
+            .map(|id| self.duplicate_names.rename(id, &mut seen))
+            .map(|id| id.map(|id| std::ffi::CString::new(id).expect("Invalid ID")))
+            .collect::<Result<_, _>>()?;
+        // mm_idx_str takes arrays of C string pointers
+        let seq_ptrs: Vec<*const libc::c_char> = seqs.iter().map(|s| s.as_ptr()).collect();
+        let id_ptrs: Vec<*const libc::c_char> = ids.iter().map(|s| s.as_ptr()).collect();
+
+        let idx = MaybeUninit::new(unsafe {
+            mm_idx_str(
+                self.idxopt.w as i32,
+                self.idxopt.k as i32,
+                (self.idxopt.flag & 1) as i32,
+                self.idxopt.bucket_bits as i32,
+                seqs.len() as i32,
+                seq_ptrs.as_ptr() as *mut *const libc::c_char,
+                id_ptrs.as_ptr() as *mut *const libc::c_char,
+            )
+        });
+
+        let mm_idx = unsafe { idx.assume_init() };
+        Ok(self.with_string_index(mm_idx))
+    }
+
+    /// Finish building with an index built from sequences in memory. Following the mappy
+    /// implementation, this also sets mapopt.mid_occ to 1000.
+    pub(crate) fn with_string_index(mut self, mm_idx: *mut mm_idx_t) -> Aligner<Built> {
+        self.idx = Some(Arc::new(mm_idx.into()));
+
+        self.mapopt.mid_occ = 1000;
+        self.into_built()
+    }
+
+    /// Applies an additional preset to the aligner
+    /// WARNING: This overwrites multiple other parameters. Make sure you know what you are doing
+    ///
+    /// Presets should be called before any other options are set, as they change multiple
+    /// options at once.
+    pub fn additional_preset(mut self, preset: Preset) -> Self {
+        unsafe { mm_set_opt(preset.into(), &mut self.idxopt, &mut self.mapopt) };
+
+        self
+    }
+}
+
+impl Aligner<Built> {
+    /// Check that a query (or pair of mates) of `len` bases can be mapped: that it is no
+    /// longer than `max_qlen`, see [`with_max_qlen`](Aligner::with_max_qlen)
+    pub fn check_query_len(&self, len: usize) -> Result<(), QueryTooLong> {
+        let max_len = match self.mapopt.max_qlen {
+            max_qlen if max_qlen > 0 => max_qlen as usize,
+            _ => i32::MAX as usize,
+        };
+        if len > max_len {
+            return Err(QueryTooLong { len, max_len });
+        }
+        Ok(())
+    }
+
+    /// Reads skipped by the read filter (see [`with_read_filter`](Aligner::with_read_filter))
+    /// in all files mapped so far by this aligner and its clones
+    pub fn filtered_reads(&self) -> FilteredReads {
+        self.read_filter_counts.get()
+    }
+
+    /// Whether to map a read of an input file with header `header`, counting it if the read
+    /// filter or the duplicate check skips it
+    #[cfg(feature = "map-file")]
+    pub(crate) fn keep_read(&self, header: &[u8], seq: &[u8], qual: Option<&[u8]>) -> bool {
+        let reason = self
+            .read_filter
+            .and_then(|filter| filter.check(seq, qual))
+            .or_else(|| {
+                let ids = self.read_ids.as_ref()?;
+                (!ids.insert(dedup::read_id(header))).then_some(FilterReason::Duplicate)
+            });
+        let Some(reason) = reason else {
+            return true;
+        };
+        self.read_filter_counts.add(reason);
+        false
+    }
+
+    /// Print the reads skipped by the read filter or as duplicates since `before` was taken
+    /// from `filtered_reads`, at the end of a file
+    #[cfg(feature = "map-file")]
+    pub(crate) fn report_filtered_reads(&self, before: FilteredReads) {
+        let after = self.filtered_reads();
+        let total = after.total() - before.total();
+        if total > 0 && verbosity() >= 3 {
+            eprintln!(
+                "[M::read_filter] skipped {} reads: {} too short, {} too long, {} low quality, {} \
+                 duplicates",
+                total,
+                after.too_short - before.too_short,
+                after.too_long - before.too_long,
+                after.low_quality - before.low_quality,
+                after.duplicates - before.duplicates,
+            );
+        }
+    }
+
+    /// Returns the number of sequences in the index
+    pub fn n_seq(&self) -> u32 {
+        unsafe {
+            // let idx = Arc::as_ptr(self.idx.as_ref().unwrap());
+            let idx: *const mm_idx_t = &(***self.idx.as_ref().unwrap());
+            (*idx).n_seq as u32
+        }
+    }
+
+    /// Target names of the index, see [`TargetNames`]
+    pub(crate) fn target_names(&self) -> &Arc<TargetNames> {
+        self.target_names
+            .get_or_init(|| Arc::new(TargetNames::new(self.idx.as_ref().unwrap())))
+    }
+
+    /// The reference file target `name` was read from, for indexes built by
+    /// [`with_index_files`](Aligner::with_index_files)
+    pub fn source_file(&self, name: &str) -> Option<&Path> {
+        let index_files = self.index_files.as_ref()?;
+        let id = *self.target_names().ids.get(name)?;
+        index_files.file_of(id as usize)
+    }
+
+    /// Rough estimate of the memory used by the index, in bytes
+    ///
+    /// Counts the packed sequences (4 bits per base), about 16 bytes per minimizer (2 per
+    /// w + 1 bases) and the sequence names. Meant for budgeting, e.g. by
+    /// [`pool::AlignerPool`], not as an exact figure.
+    pub fn index_size_estimate(&self) -> usize {
+        let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+        let w = idx.w.max(1) as usize;
+
+        let mut bases = 0;
+        let mut names = 0;
+        for seq in self.seqs() {
+            bases += seq.length as usize;
+            names += seq.name.len() + 1 + std::mem::size_of::<mm_idx_seq_t>();
+        }
+
+        let packed = if idx.S.is_null() { 0 } else { bases / 2 };
+        let minimizers = 2 * bases / (w + 1);
+        packed + 16 * minimizers + names
+    }
+
+    /// Get sequences direct from the index
+    ///
+    /// Returns a reference to the sequence at the given index, or None if `i` is out of
+    /// range. Remains valid as long as the aligner is valid. [`seq`](Aligner::seq) returns an
+    /// owned copy of the metadata instead.
+    pub fn get_seq<'aln>(&'aln self, i: usize) -> Option<&'aln mm_idx_seq_t> {
+        if i >= self.n_seq() as usize {
+            return None;
+        }
+        let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+        Some(unsafe { &*idx.seq.add(i) })
+    }
+
+    /// Metadata (name, length, is_alt, offset) of the `i`-th sequence in the index, or None if
+    /// `i` is out of range
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder().with_index("test_data/genome.fa", None).unwrap();
+    /// assert_eq!(aligner.seq(1).unwrap().name, "chr2");
+    /// assert_eq!(aligner.seq(2), None);
+    /// ```
+    pub fn seq(&self, i: usize) -> Option<SeqMetaData> {
+        if i >= self.n_seq() as usize {
+            return None;
+        }
+        let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+        Some(unsafe { seq_meta_data(idx, i) })
+    }
+
+    /// Iterate over the metadata (name, length, is_alt, offset) of the sequences in the index
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder().with_index("test_data/genome.fa", None).unwrap();
+    /// let names: Vec<String> = aligner.seqs().map(|s| s.name).collect();
+    /// assert_eq!(names, vec!["chr1", "chr2"]);
+    /// ```
+    pub fn seqs(&self) -> impl Iterator<Item = SeqMetaData> + '_ {
+        let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+        (0..self.n_seq() as usize).map(move |i| unsafe { seq_meta_data(idx, i) })
+    }
+
+    /// Sort key ordering mappings as in a coordinate-sorted BAM: by target in index order,
+    /// then start and end. Mappings without a known target sort last.
+    ///
+    /// Pass to [`merge::merge_sorted_by_key`] to merge per-worker sorted results.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder().with_index("test_data/genome.fa", None).unwrap();
+    /// let key = aligner.coordinate_key();
+    /// let mut mappings = vec![Mapping::default(), Mapping::default()];
+    /// mappings[1].target_name = Some(std::sync::Arc::new("chr2".to_string()));
+    /// mappings.sort_by_key(&key);
+    /// assert_eq!(key(&mappings[0]), (1, 0, 0));
+    /// ```
+    pub fn coordinate_key(&self) -> impl Fn(&Mapping) -> (u32, i32, i32) + Send + Sync {
+        let targets = Arc::clone(self.target_names());
+
+        move |mapping| {
+            let tid = mapping
+                .target_name
+                .as_ref()
+                .and_then(|name| targets.ids.get(name.as_str()).copied())
+                .unwrap_or(u32::MAX);
+            (tid, mapping.target_start, mapping.target_end)
+        }
+    }
+
+    /// Get the full sequence of target `rid` from the index, as uppercase ACGTN
+    ///
+    /// Returns None if rid is out of range or the index was built without sequences (MM_I_NO_SEQ)
+    pub(crate) fn target_seq(&self, rid: u32) -> Option<Vec<u8>> {
+        let len = self.seq(rid as usize)?.length;
+        self.fetch_subseq_by_id(rid, 0..len, Strand::Forward).ok()
+    }
+
+    /// Id of the target called `name` in the index
+    pub fn target_id(&self, name: &str) -> Option<u32> {
+        self.target_names().ids.get(name).copied()
+    }
+
+    /// Generate the cs tag (short form), or the MD tag if `md`, of `mapping`, a mapping of
+    /// `query` by this aligner, from its CIGAR
+    fn gen_tag(&self, mapping: &Mapping, query: &[u8], md: bool) -> Result<String, &'static str> {
+        let cigar = mapping
+            .alignment
+            .as_ref()
+            .and_then(|aln| aln.cigar.as_ref())
+            .ok_or("Mapping has no CIGAR")?;
+        let idx: &mm_idx_t = self.idx.as_ref().ok_or("No index")?;
+        if idx.S.is_null() {
+            return Err("Index has no sequence");
+        }
+        let rid = mapping
+            .target_name
+            .as_deref()
+            .and_then(|name| self.target_id(name))
+            .ok_or("Target not in index")?;
+
+        // minimap2 reads the spans of the hit from the query and the index, so they must be
+        // within both and agree with the CIGAR
+        let target_len = unsafe { (*idx.seq.add(rid as usize)).len } as i32;
+        if mapping.query_len.map(|len| len.get() as usize) != Some(query.len())
+            || mapping.query_start < 0
+            || mapping.query_end as usize > query.len()
+            || mapping.target_start < 0
+            || mapping.target_end > target_len
+            || mapping.check_cigar().is_err()
+        {
+            return Err("Mapping does not match the query");
+        }
+
+        // The CIGAR without clipping, as minimap2 stores it
+        let ops: Vec<u32> = cigar
+            .iter()
+            .filter(|&&(_, op)| op != 4 && op != 5)
+            .map(|&(len, op)| len << 4 | op as u32)
+            .collect();
+
+        unsafe {
+            let size = std::mem::size_of::<mm_extra_t>() + ops.len() * std::mem::size_of::<u32>();
+            let p = libc::calloc(1, size) as *mut mm_extra_t;
+            if p.is_null() {
+                return Err("Unable to allocate the CIGAR");
+            }
+            (*p).capacity = ops.len() as u32;
+            (*p).n_cigar = ops.len() as u32;
+            (*p).cigar.as_mut_slice(ops.len()).copy_from_slice(&ops);
+
+            let mut reg: mm_reg1_t = std::mem::zeroed();
+            reg.rid = rid as i32;
+            reg.rs = mapping.target_start;
+            reg.re = mapping.target_end;
+            reg.qs = mapping.query_start;
+            reg.qe = mapping.query_end;
+            reg.set_rev((mapping.strand == Strand::Reverse) as u32);
+            reg.p = p;
+
+            let mut tags = TagBuffer::new();
+            let tag = if md {
+                tags.gen_md(idx, &reg, query)
+            } else {
+                tags.gen_cs(idx, &reg, query, true)
+            };
+            let tag = String::from_utf8_lossy(tag).into_owned();
+            libc::free(p as *mut c_void);
+            Ok(tag)
+        }
+    }
+
+    /// Align `query` to `range` (0-based, end-exclusive) of target `target` with ksw2, as
+    /// minimap2 aligns between and beyond anchors, and with the aligner's scoring (`-A`, `-B`,
+    /// `-O`, `-E`, `-z`, `-r`, `--end-bonus`)
+    ///
+    /// For realigning e.g. the clipped tail of a mapping in a polishing or variant calling
+    /// workflow. With [`RegionAlignmentMode::Extension`] the rest of the query after the best
+    /// scoring end is soft clipped at the end of the CIGAR; the target span is given by the
+    /// CIGAR in either mode. cs and MD are only generated when the index holds its sequences.
+    /// Splice scoring is not applied. ksw2 takes scores and gap penalties of at most 127, and
+    /// is only built on x86_64 and ARM; elsewhere this returns an error.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("test_data/MT-human.fa", None)
+    ///     .unwrap();
+    /// let target = aligner.fetch_subseq("MT_human", 100..200, Strand::Forward).unwrap();
+    /// // A tail whose last 20 bases were not called
+    /// let mut tail = target[..80].to_vec();
+    /// tail.extend_from_slice(&[b'N'; 20]);
+    /// let aln = aligner
+    ///     .align_region(&tail, "MT_human", 100..200, RegionAlignmentMode::Extension)
+    ///     .unwrap();
+    /// assert_eq!(aln.cigar_str.as_deref(), Some("80M20S"));
+    /// ```
+    pub fn align_region(
+        &self,
+        query: &[u8],
+        target: &str,
+        range: std::ops::Range<u32>,
+        mode: RegionAlignmentMode,
+    ) -> Result<Alignment, &'static str> {
+        if query.is_empty() {
+            return Err("Sequence is empty");
+        }
+        if range.is_empty() {
+            return Err("Region is empty");
+        }
+        let target_seq = self.fetch_subseq(target, range.clone(), Strand::Forward)?;
+        let encode = |seq: &[u8]| seq.iter().map(|&b| seq::SEQ_NT4[b as usize]).collect();
+        let (qs, ts): (Vec<u8>, Vec<u8>) = (encode(query), encode(&target_seq));
+        let (qlen, tlen) = (qs.len() as i32, ts.len() as i32);
+
+        // ksw_gen_simple_mat: ambiguous bases score -sc_ambi against anything
+        let opt = &self.mapopt;
+        let to_i8 = |x: i32| i8::try_from(x).map_err(|_| "Score out of range for ksw2");
+        let mut mat = [0i8; 25];
+        for i in 0..5 {
+            for j in 0..5 {
+                let sc = if i == 4 || j == 4 {
+                    -opt.sc_ambi.abs()
+                } else if i == j {
+                    opt.a
+                } else {
+                    -opt.b.abs()
+                };
+                mat[i * 5 + j] = to_i8(sc)?;
+            }
+        }
+        let gaps = [to_i8(opt.q)?, to_i8(opt.e)?, to_i8(opt.q2)?, to_i8(opt.e2)?];
+        let (flag, zdrop, end_bonus) = match mode {
+            RegionAlignmentMode::Extension => (KSW_EZ_EXTZ_ONLY as i32, opt.zdrop, opt.end_bonus),
+            RegionAlignmentMode::Global => (0, -1, 0),
+        };
+        // The band must hold the length difference for the ends to meet
+        let bw = opt.bw.max((qlen - tlen).abs());
+
+        let mut ez: ksw_extz_t = unsafe { std::mem::zeroed() };
+        let ops: Vec<(u32, u8)> = unsafe {
+            ksw_extd2(&qs, &ts, &mat, gaps, bw, zdrop, end_bonus, flag, &mut ez)?;
+            let ops = (0..ez.n_cigar as usize)
+                .map(|i| {
+                    let op = *ez.cigar.add(i);
+                    (op >> 4, (op & 0xf) as u8)
+                })
+                .collect();
+            libc::free(ez.cigar as *mut c_void);
+            ops
+        };
+        let score = match mode {
+            RegionAlignmentMode::Global => ez.score,
+            _ if ez.reach_end != 0 => ez.mqe,
+            _ => ez.max() as i32,
+        };
+
+        // NM as minimap2 counts it: mismatches, ambiguous bases and gaps
+        let (mut nm, mut q, mut t) = (0, 0, 0);
+        for &(len, op) in &ops {
+            let len = len as usize;
+            match op {
+                0 => {
+                    nm += (0..len)
+                        .filter(|&i| qs[q + i] != ts[t + i] || qs[q + i] > 3)
+                        .count() as i32;
+                    q += len;
+                    t += len;
+                }
+                1 => {
+                    nm += len as i32;
+                    q += len;
+                }
+                2 => {
+                    nm += len as i32;
+                    t += len;
+                }
+                _ => {}
+            }
+        }
+        let mut cigar = ops;
+        if q < query.len() {
+            cigar.push(((query.len() - q) as u32, 4));
+        }
+        let cigar_str: String = cigar
+            .iter()
+            .map(|&(len, op)| format!("{len}{}", paf::cigar_op_char(op)))
+            .collect();
+
+        let mut mapping = Mapping {
+            query_len: NonZeroI32::new(qlen),
+            query_start: 0,
+            query_end: q as i32,
+            strand: Strand::Forward,
+            target_name: Some(Arc::new(target.to_string())),
+            target_start: range.start as i32,
+            target_end: (range.start as usize + t) as i32,
+            alignment: Some(Alignment {
+                nm,
+                cigar: Some(cigar),
+                cigar_str: Some(cigar_str),
+                md: None,
+                cs: None,
+                alignment_score: Some(score),
+                dp_skipped: DpSkipped::None,
+            }),
+            ..Default::default()
+        };
+        let tags = if t > 0 {
+            (
+                self.gen_tag(&mapping, query, false).ok(),
+                self.gen_tag(&mapping, query, true).ok(),
+            )
+        } else {
+            (None, None)
+        };
+        let mut alignment = mapping.alignment.take().unwrap();
+        (alignment.cs, alignment.md) = tags;
+        Ok(alignment)
+    }
+
+    /// Fetch `range` (0-based, end-exclusive) of target `target` from the index as uppercase
+    /// ACGTN, reverse complemented for [`Strand::Reverse`]
+    ///
+    /// The range must lie within the target; an empty range gives an empty sequence.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder().with_index("test_data/genome.fa", None).unwrap();
+    /// let fwd = aligner.fetch_subseq("chr2", 0..10, Strand::Forward).unwrap();
+    /// let rev = aligner.fetch_subseq("chr2", 0..10, Strand::Reverse).unwrap();
+    /// assert_eq!(rev, minimap2::seq::revcomp(&fwd));
+    /// assert_eq!(
+    ///     aligner.fetch_subseq("chr2", 450..470, Strand::Forward),
+    ///     Err(FetchError::OutOfRange { start: 450, end: 470, target_len: 460 })
+    /// );
+    /// ```
+    pub fn fetch_subseq(
+        &self,
+        target: &str,
+        range: std::ops::Range<u32>,
+        strand: Strand,
+    ) -> Result<Vec<u8>, FetchError> {
+        let rid = self.target_id(target).ok_or(FetchError::TargetNotFound)?;
+        self.fetch_subseq_by_id(rid, range, strand)
+    }
+
+    /// [`Aligner::fetch_subseq`] by target id
+    pub fn fetch_subseq_by_id(
+        &self,
+        rid: u32,
+        range: std::ops::Range<u32>,
+        strand: Strand,
+    ) -> Result<Vec<u8>, FetchError> {
+        let idx: &mm_idx_t = self.idx.as_ref().ok_or(FetchError::TargetNotFound)?;
+        if rid >= idx.n_seq {
+            return Err(FetchError::TargetNotFound);
+        }
+        if idx.S.is_null() && self.sequence_source.is_none() {
+            return Err(FetchError::NoSequence);
+        }
+
+        let target_len = unsafe { (*idx.seq.add(rid as usize)).len };
+        if range.start > range.end || range.end > target_len {
+            return Err(FetchError::OutOfRange {
+                start: range.start,
+                end: range.end,
+                target_len,
+            });
+        }
+
+        let mut seq = if let (true, Some(source)) = (idx.S.is_null(), &self.sequence_source) {
+            let name = &self.target_names().names[rid as usize];
+            let mut seq = source.fetch(name, range.clone())?;
+            if seq.len() != range.len() {
+                return Err(FetchError::NoSequence);
+            }
+            // Normalise as minimap2 stores sequences
+            seq.iter_mut().for_each(|b| *b = seq::SEQ_NT4[*b as usize]);
+            seq
+        } else {
+            let mut seq = vec![0u8; range.len()];
+            if !seq.is_empty() {
+                let n =
+                    unsafe { mm_idx_getseq(idx, rid, range.start, range.end, seq.as_mut_ptr()) };
+                debug_assert_eq!(n as usize, seq.len());
+            }
+            seq
+        };
+        seq::decode_in_place(&mut seq);
+
+        if strand == Strand::Reverse {
+            seq::revcomp_in_place(&mut seq);
+        }
+        Ok(seq)
+    }
+
+    /// Write the sequences in the index to a FASTA file, along with a matching samtools-style
+    /// FASTA index (`path` + ".fai").
+    ///
+    /// Useful when the index was built from in-memory sequences (`with_seqs_and_ids`) and
+    /// downstream tools (IGV, variant callers) need the reference on disk. Names and lengths are
+    /// taken from the index, so they match the SAM header generated by this crate.
+    ///
+    /// Fails if the index was built without sequences (MM_I_NO_SEQ).
+    /// ```no_run
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_seq_and_id(b"ACGTACGTACGTACGTACGTACGTACGT", b"contig1")
+    ///     .unwrap();
+    /// aligner.write_fasta("reference.fa").unwrap(); // Also writes reference.fa.fai
+    /// ```
+    pub fn write_fasta<P: AsRef<Path>>(&self, path: P) -> Result<(), &'static str> {
+        use std::io::Write;
+
+        const LINE_WIDTH: usize = 60;
+
+        if self.n_seq() > 0 && self.target_seq(0).is_none() {
+            return Err("Index does not contain sequences");
+        }
+
+        let path = path.as_ref();
+        let mut fai_path = path.as_os_str().to_owned();
+        fai_path.push(".fai");
+
+        let fasta = std::fs::File::create(path).map_err(|_| "Unable to create FASTA file")?;
+        let fai = std::fs::File::create(fai_path).map_err(|_| "Unable to create FASTA index")?;
+        let mut fasta = std::io::BufWriter::new(fasta);
+        let mut fai = std::io::BufWriter::new(fai);
+
+        let mut offset: usize = 0;
+        for (rid, meta) in self.seqs().enumerate() {
+            let seq = self
+                .target_seq(rid as u32)
+                .ok_or("Index does not contain sequences")?;
+
+            let header = format!(">{}\n", meta.name);
+            offset += header.len();
+
+            writeln!(
+                fai,
+                "{}\t{}\t{}\t{}\t{}",
+                meta.name,
+                seq.len(),
+                offset,
+                LINE_WIDTH,
+                LINE_WIDTH + 1
+            )
+            .map_err(|_| "Unable to write FASTA index")?;
+
+            fasta
+                .write_all(header.as_bytes())
+                .map_err(|_| "Unable to write FASTA file")?;
+            for line in seq.chunks(LINE_WIDTH) {
+                fasta
+                    .write_all(line)
+                    .and_then(|_| fasta.write_all(b"\n"))
+                    .map_err(|_| "Unable to write FASTA file")?;
+                offset += line.len() + 1;
+            }
+        }
+
+        fasta.flush().map_err(|_| "Unable to write FASTA file")?;
+        fai.flush().map_err(|_| "Unable to write FASTA index")
+    }
+
+    // https://github.com/lh3/minimap2/blob/master/python/mappy.pyx#L164
+    // TODO: I doubt extra_flags is working properly...
+    // TODO: Python allows for paired-end mapping with seq2: Option<&[u8]>, but more work to implement
+    /// Aligns a given sequence (as bytes) to the index associated with this aligner
+    ///
+    /// Parameters:
+    /// seq: Sequence to align
+    /// cs: Whether to output CIGAR string
+    /// MD: Whether to output MD tag
+    /// max_frag_len: Maximum fragment length
+    /// extra_flags: Extra flags to pass to minimap2 as `Vec<u64>`
+    /// query_name: Name of the query sequence
+    ///
+    /// Each thread maps with its own minimap2 buffer. A map() called from within another on the
+    /// same thread, e.g. from a `with_mapq_recalibration` hook, allocates a temporary buffer for
+    /// itself rather than reusing (or panicking on) the one in use; this is safe but slower, so
+    /// avoid mapping from hooks on hot paths.
+    pub fn map(
+        &self,
+        seq: &[u8],
+        cs: bool,
+        md: bool,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<&[u64]>,
+        query_name: Option<&[u8]>,
+    ) -> Result<Vec<Mapping>, &'static str> {
+        self.map_with_summary(seq, cs, md, max_frag_len, extra_flags, query_name)
+            .map(|(mappings, _)| mappings)
+    }
+
+    /// Same as [`map`](Aligner::map), also returning the hit counts of the query
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_max_hits(1, true)
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let seq = b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTTTCGACGGGTAAAAGTACAGGAAGCGACCCTTCGGTATGTCCAGCCAAAGACTGAGAATCTGTAATTTACTAGTTTATCTCTCATGAATAGCTCATTCGTTTCCAAATAATAAACTTTTCTTCTTAAAAGAGACCCGCCTATCAGAAAACCCATTTTGAGGAAAGGTATGCACACAAGAAGCTTACAACGAAGCTTTGCGTATCGACGGTGCTATACACG";
+    /// let (mappings, summary) = aligner
+    ///     .map_with_summary(seq, false, false, None, None, None)
+    ///     .unwrap();
+    /// assert_eq!(summary.n_primary, 1);
+    /// assert!(mappings.len() <= 1);
+    /// ```
+    pub fn map_with_summary(
+        &self,
+        seq: &[u8],
+        cs: bool,
+        md: bool,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<&[u64]>,
+        query_name: Option<&[u8]>,
+    ) -> Result<(Vec<Mapping>, MappingSummary), &'static str> {
+        // Make sure index is set
+        if !self.has_index() {
+            return Err("No index");
+        }
+
+        // Make sure sequence is not empty
+        if seq.is_empty() {
+            return Err("Sequence is empty");
+        }
+        self.check_query_len(seq.len())?;
+        let oriented = self.orient_query(seq);
+        let seq = &oriented[..];
+
+        let qname_cstring = query_name
+            .map(|qname| CString::new(qname.strip_suffix(b"\0").unwrap_or(qname)))
+            .transpose()
+            .map_err(|_| "Invalid query name")?;
+        let query_name_cstr: Option<&CStr> = qname_cstring.as_deref();
+
+        let mut mm_reg: MaybeUninit<*mut mm_reg1_t> = MaybeUninit::uninit();
+
+        // Number of results
+        let mut n_regs: i32 = 0;
+        let map_opt = self.call_mapopt(max_frag_len, extra_flags, query_name);
+
+        let query_name_arc = query_name_cstr.map(|x| Arc::new(x.to_string_lossy().into_owned()));
+
+        let qname = match query_name_cstr {
+            None => std::ptr::null(),
+            Some(qname) => qname.as_ref().as_ptr() as *const ::std::os::raw::c_char,
+        };
+
+        let result = with_buf(|buf| {
+            let km: *mut c_void = unsafe { mm_tbuf_get_km(buf.get_buf()) };
+
+            mm_reg = MaybeUninit::new(unsafe {
+                mm_map(
+                    &**self.idx.as_ref().unwrap().as_ref() as *const mm_idx_t,
+                    seq.len() as i32,
+                    seq.as_ptr() as *const ::std::os::raw::c_char,
+                    &mut n_regs,
+                    buf.get_buf(),
+                    &map_opt,
+                    qname,
+                )
+            });
+
+            let regs = unsafe { *mm_reg.as_ptr() };
+            let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+            unsafe { check_hits(idx, regs, n_regs) }?;
+            n_regs = unsafe { self.filter_overlaps(regs, n_regs, seq.len() as i32) };
+            n_regs = unsafe { self.filter_secondary(regs, n_regs, map_opt.flag) };
+            let (n_kept, mut summary) = unsafe {
+                self.limit_hits(
+                    regs,
+                    n_regs,
+                    query_name_arc.as_ref().map(|name| name.as_str()),
+                )
+            };
+            summary.reverse_complemented =
+                self.query_orientation == QueryOrientation::ReverseComplement;
+            unsafe { self.sort_hits(regs, n_kept) };
+            let mut mappings = Vec::with_capacity(n_kept);
+
+            for i in 0..n_kept {
+                unsafe {
+                    let reg_ptr = regs.add(i);
+                    mappings.push(self.reg_to_mapping(reg_ptr, seq, cs, md, &query_name_arc));
+                }
+            }
+            Ok((mappings, summary))
+        });
+        // free some stuff here
+        unsafe {
+            if result.is_err() {
+                // None of the hits were converted, so their extra data is still allocated
+                free_hits(mm_reg.assume_init(), n_regs);
+            } else {
+                // Free mm_regs
+                let ptr: *mut mm_reg1_t = mm_reg.assume_init();
+                let c_void_ptr: *mut c_void = ptr as *mut c_void;
+                libc::free(c_void_ptr);
+            }
+        }
+        result
+    }
+
+    /// Same as [`map_with_summary`](Aligner::map_with_summary), also returning why hits of the
+    /// query were dropped, to understand why an expected hit is missing when tuning
+    /// sensitivity
+    ///
+    /// minimap2 does not report what it drops, so the query is mapped a second time with
+    /// `pri_ratio` 0 and `best_n` [`DIAGNOSTIC_BEST_N`], and the extra hits are classified as
+    /// minimap2 would. Mapping is about twice as slow as with `map`. Hits dropped by the
+    /// secondary-to-primary check on the opposite strand (minimap2 keeps some of them) and by
+    /// `with_ava_filter` are not told apart.
+    /// ```
+    /// # use minimap2::*;
+    /// let mut aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// aligner.mapopt.best_n = 1;
+    /// let seq = b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTTTCGACGGGTAAAAGTACAGGAAGCGACCCTTCGGTATGTCCAGCCAAAGACTGAGAATCTGTAATTTACTAGTTTATCTCTCATGAATAGCTCATTCGTTTCCAAATAATAAACTTTTCTTCTTAAAAGAGACCCGCCTATCAGAAAACCCATTTTGAGGAAAGGTATGCACACAAGAAGCTTACAACGAAGCTTTGCGTATCGACGGTGCTATACACG";
+    /// let (mappings, diagnostics) = aligner
+    ///     .map_with_diagnostics(seq, false, false, None, None, None)
+    ///     .unwrap();
+    /// assert_eq!(diagnostics.summary.n_hits(), mappings.len());
+    /// assert!(diagnostics.n_candidates >= mappings.len());
+    /// ```
+    pub fn map_with_diagnostics(
+        &self,
+        seq: &[u8],
+        cs: bool,
+        md: bool,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<&[u64]>,
+        query_name: Option<&[u8]>,
+    ) -> Result<(Vec<Mapping>, MapDiagnostics), &'static str> {
+        let (mappings, summary) =
+            self.map_with_summary(seq, cs, md, max_frag_len, extra_flags, query_name)?;
+        let oriented = self.orient_query(seq);
+        let seq = &oriented[..];
+
+        let map_opt = self.effective_mapopt(max_frag_len, extra_flags);
+        let mut relaxed = map_opt;
+        relaxed.pri_ratio = 0.0;
+        relaxed.best_n = DIAGNOSTIC_BEST_N;
+        let qname = query_name
+            .map(|name| CString::new(name.strip_suffix(b"\0").unwrap_or(name)))
+            .transpose()
+            .map_err(|_| "Invalid query name")?;
+
+        let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+        let mut n_regs: i32 = 0;
+        let regs = with_buf(|buf| unsafe {
+            mm_map(
+                idx,
+                seq.len() as i32,
+                seq.as_ptr() as *const ::std::os::raw::c_char,
+                &mut n_regs,
+                buf.get_buf(),
+                &relaxed,
+                qname
+                    .as_ref()
+                    .map_or(std::ptr::null(), |name| name.as_ptr()),
+            )
+        });
+        let diagnostics = unsafe {
+            let diagnostics = diagnose_hits(regs, n_regs, &map_opt, idx.k);
+            free_hits(regs, n_regs);
+            diagnostics
+        };
+
+        Ok((
+            mappings,
+            MapDiagnostics {
+                summary,
+                ..diagnostics
+            },
+        ))
+    }
+
+    /// The options a mapping call uses: those set on the builder with the per-call
+    /// `max_frag_len` and `extra_flags` of [`map`](Aligner::map) applied, as `map`,
+    /// `map_with_summary` and `map_to_sam` (htslib feature) do
+    ///
+    /// Useful to check how per-call arguments interact with the builder settings. With the
+    /// verbosity at [`DEBUG_VERBOSITY`] or above, mapping calls print the options that differ.
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .sr()
+    ///     .with_index("test_data/MT-human.fa", None)
+    ///     .unwrap();
+    /// let no_2nd = ffi::MM_F_NO_PRINT_2ND as u64;
+    /// let opt = aligner.effective_mapopt(Some(800), Some(&[no_2nd]));
+    /// assert_eq!(opt.max_frag_len, 800);
+    /// assert_ne!(opt.flag & no_2nd as i64, 0);
+    /// assert_eq!(opt.min_cnt, aligner.mapopt.min_cnt);
+    /// ```
+    pub fn effective_mapopt(
+        &self,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<&[u64]>,
+    ) -> mm_mapopt_t {
+        let mut map_opt = self.mapopt;
+
+        // if max_frag_len is not None: map_opt.max_frag_len = max_frag_len
+        if let Some(max_frag_len) = max_frag_len {
+            map_opt.max_frag_len = max_frag_len as i32;
+        }
+
+        // if extra_flags is not None: map_opt.flag |= extra_flags
+        if let Some(extra_flags) = extra_flags {
+            for flag in extra_flags {
+                map_opt.flag |= *flag as i64;
+            }
+        }
+
+        map_opt
+    }
+
+    /// `effective_mapopt`, printing the overrides at [`DEBUG_VERBOSITY`]
+    pub(crate) fn call_mapopt(
+        &self,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<&[u64]>,
+        query_name: Option<&[u8]>,
+    ) -> mm_mapopt_t {
+        let map_opt = self.effective_mapopt(max_frag_len, extra_flags);
+        let changed =
+            map_opt.max_frag_len != self.mapopt.max_frag_len || map_opt.flag != self.mapopt.flag;
+        if changed && verbosity() >= DEBUG_VERBOSITY {
+            eprintln!(
+                "[DEBUG] query {}: max_frag_len {} (builder {}), flag {:#x} (builder {:#x})",
+                query_name.map_or("*".into(), |name| {
+                    String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(name))
+                }),
+                map_opt.max_frag_len,
+                self.mapopt.max_frag_len,
+                map_opt.flag,
+                self.mapopt.flag,
+            );
+        }
+        map_opt
+    }
+
+    /// Drop the hits failing `ava_filter`, freeing their extra data, and move the others to
+    /// the front of `regs`. Returns the number of hits kept.
+    ///
+    /// # Safety
+    /// `regs` must point to `n_regs` hits returned by minimap2 for a query of `query_len`
+    /// against this index
+    pub(crate) unsafe fn filter_overlaps(
+        &self,
+        regs: *mut mm_reg1_t,
+        n_regs: i32,
+        query_len: i32,
+    ) -> i32 {
+        let Some(filter) = &self.ava_filter else {
+            return n_regs;
+        };
+        let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+
+        let mut n_kept = 0;
+        for i in 0..n_regs.max(0) as usize {
+            let reg = &*regs.add(i);
+            let target_len = (*idx.seq.add(reg.rid as usize)).len as i32;
+            let strand = if reg.rev() == 0 {
+                Strand::Forward
+            } else {
+                Strand::Reverse
+            };
+            if filter.accepts_coords(
+                query_len,
+                (reg.qs, reg.qe),
+                target_len,
+                (reg.rs, reg.re),
+                strand,
+            ) {
+                if i != n_kept {
+                    std::ptr::copy_nonoverlapping(regs.add(i), regs.add(n_kept), 1);
+                }
+                n_kept += 1;
+            } else {
+                libc::free(reg.p as *mut c_void);
+            }
+        }
+        n_kept as i32
+    }
+
+    /// Drop the secondary hits if `flag` has `MM_F_NO_PRINT_2ND`, freeing their extra data, and
+    /// move the others to the front of `regs`. Returns the number of hits kept.
+    ///
+    /// # Safety
+    /// `regs` must point to `n_regs` hits returned by minimap2
+    pub(crate) unsafe fn filter_secondary(
+        &self,
+        regs: *mut mm_reg1_t,
+        n_regs: i32,
+        flag: i64,
+    ) -> i32 {
+        if flag & MM_F_NO_PRINT_2ND as i64 == 0 {
+            return n_regs;
+        }
+
+        let mut n_kept = 0;
+        for i in 0..n_regs.max(0) as usize {
+            let reg = &*regs.add(i);
+            if reg.id == reg.parent {
+                if i != n_kept {
+                    std::ptr::copy_nonoverlapping(regs.add(i), regs.add(n_kept), 1);
+                }
+                n_kept += 1;
+            } else {
+                libc::free(reg.p as *mut c_void);
+            }
+        }
+        n_kept as i32
+    }
+
+    /// Count the hits of a query and apply `max_hits`, returning the number of hits to keep.
+    /// Hits that are dropped have their extra data freed.
+    ///
+    /// # Safety
+    /// `regs` must point to `n_regs` hits returned by minimap2
+    pub(crate) unsafe fn limit_hits(
+        &self,
+        regs: *mut mm_reg1_t,
+        n_regs: i32,
+        query_name: Option<&str>,
+    ) -> (usize, MappingSummary) {
+        let n_regs = n_regs.max(0) as usize;
+        let mut summary = MappingSummary::default();
+        for i in 0..n_regs {
+            let reg = &*regs.add(i);
+            if reg.parent != reg.id {
+                summary.n_secondary += 1;
+            } else if reg.sam_pri() > 0 {
+                summary.n_primary += 1;
+            } else {
+                summary.n_supplementary += 1;
+            }
+        }
+
+        let max_hits = match self.max_hits {
+            Some(max_hits) if n_regs > max_hits => max_hits,
+            _ => return (n_regs, summary),
+        };
+
+        if verbosity() > 0 {
+            eprintln!(
+                "[WARNING] query {} has {} hits (max_hits {}){}",
+                query_name.unwrap_or("*"),
+                n_regs,
+                max_hits,
+                if self.truncate_hits {
+                    ", truncated"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        if !self.truncate_hits {
+            return (n_regs, summary);
+        }
+
+        for i in max_hits..n_regs {
+            libc::free((*regs.add(i)).p as *mut c_void);
+        }
+        summary.truncated = true;
+        (max_hits, summary)
+    }
+
+    /// `seq` in the orientation set by `with_query_orientation`
+    pub(crate) fn orient_query<'a>(&self, seq: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.query_orientation {
+            QueryOrientation::AsIs => Cow::Borrowed(seq),
+            QueryOrientation::ReverseComplement => Cow::Owned(revcomp(seq)),
+        }
+    }
+
+    /// Sort the first `n_hits` hits of a query by [`hit_order`](Aligner::with_hit_order)
+    ///
+    /// # Safety
+    /// `regs` must point to at least `n_hits` hits returned by minimap2
+    pub(crate) unsafe fn sort_hits(&self, regs: *mut mm_reg1_t, n_hits: usize) {
+        if n_hits > 1 {
+            self.hit_order
+                .sort(std::slice::from_raw_parts_mut(regs, n_hits));
+        }
+    }
+
+    /// Map a read pair jointly, as minimap2 does for paired-end short reads
+    ///
+    /// Both mates are chained together, so a mate with few seeds of its own can be placed by
+    /// its partner, and MAPQ is adjusted for properly paired hits. minimap2 does not realign
+    /// a missing mate within the insert window, so neither does this. Use with the `sr`
+    /// preset and `with_cigar()`; mates are given in their sequenced orientation, with the
+    /// expected orientation taken from `mapopt.pe_ori` (forward-reverse for `sr`).
+    ///
+    /// Returns the mappings of the first and second mate. Query coordinates and strands are
+    /// relative to each mate as given.
+    ///
+    /// Parameters:
+    /// seq1: First mate
+    /// seq2: Second mate
+    /// cs: Whether to output CIGAR string
+    /// MD: Whether to output MD tag
+    /// query_name: Name of the read pair
+    pub fn map_pair(
+        &self,
+        seq1: &[u8],
+        seq2: &[u8],
+        cs: bool,
+        md: bool,
+        query_name: Option<&[u8]>,
+    ) -> Result<(Vec<Mapping>, Vec<Mapping>), &'static str> {
+        if !self.has_index() {
+            return Err("No index");
+        }
+
+        if seq1.is_empty() || seq2.is_empty() {
+            return Err("Sequence is empty");
+        }
+        self.check_query_len(seq1.len() + seq2.len())?;
+        let (oriented1, oriented2) = (self.orient_query(seq1), self.orient_query(seq2));
+        let (seq1, seq2) = (&oriented1[..], &oriented2[..]);
+
+        let query_name = match query_name {
+            None => None,
+            Some(qname) => {
+                let qname = qname.strip_suffix(b"\0").unwrap_or(qname);
+                Some(CString::new(qname).map_err(|_| "Invalid query name")?)
+            }
+        };
+        let query_name_arc = query_name
+            .as_ref()
+            .map(|x| Arc::new(x.to_string_lossy().into_owned()));
+        let qname = query_name.as_ref().map_or(std::ptr::null(), |x| x.as_ptr());
+
+        // minimap2 maps pairs as if both mates were on the same strand, so flip a mate
+        // according to the expected orientation and flip its hits back afterwards
+        let pe_ori = self.mapopt.pe_ori;
+        let flip = [pe_ori >> 1 & 1 != 0, pe_ori & 1 != 0];
+        let seqs = [seq1, seq2];
+        let oriented: Vec<Vec<u8>> = seqs
+            .iter()
+            .zip(flip)
+            .map(|(seq, flip)| if flip { revcomp(seq) } else { seq.to_vec() })
+            .collect();
+
+        let qlens = [seq1.len() as i32, seq2.len() as i32];
+        let mut seq_ptrs = [
+            oriented[0].as_ptr() as *const ::std::os::raw::c_char,
+            oriented[1].as_ptr() as *const ::std::os::raw::c_char,
+        ];
+        let mut n_regs = [0i32; 2];
+        let mut regs: [*mut mm_reg1_t; 2] = [std::ptr::null_mut(); 2];
+
+        let mappings = with_buf(|buf| unsafe {
+            mm_map_frag(
+                &**self.idx.as_ref().unwrap().as_ref() as *const mm_idx_t,
+                2,
+                qlens.as_ptr(),
+                seq_ptrs.as_mut_ptr(),
+                n_regs.as_mut_ptr(),
+                regs.as_mut_ptr(),
+                buf.get_buf(),
+                &self.mapopt,
+                qname,
+            );
+
+            let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+            if let Err(e) = (0..2).try_for_each(|j| check_hits(idx, regs[j], n_regs[j])) {
+                for (&mate_regs, &mate_n_regs) in regs.iter().zip(&n_regs) {
+                    free_hits(mate_regs, mate_n_regs);
+                }
+                return Err(e);
+            }
+
+            let mut mappings = [Vec::new(), Vec::new()];
+            for (j, mate_mappings) in mappings.iter_mut().enumerate() {
+                let n_regs = self.filter_secondary(regs[j], n_regs[j], self.mapopt.flag);
+                let (n_kept, _) = self.limit_hits(
+                    regs[j],
+                    n_regs,
+                    query_name_arc.as_ref().map(|name| name.as_str()),
+                );
+                self.sort_hits(regs[j], n_kept);
+                mate_mappings.reserve(n_kept);
+                for i in 0..n_kept {
+                    let reg_ptr = regs[j].add(i);
+                    if flip[j] {
+                        let reg = &mut *reg_ptr;
+                        let qs = reg.qs;
+                        reg.qs = qlens[j] - reg.qe;
+                        reg.qe = qlens[j] - qs;
+                        reg.set_rev((reg.rev() == 0) as u32);
+                    }
+                    mate_mappings.push(self.reg_to_mapping(
+                        reg_ptr,
+                        seqs[j],
+                        cs,
+                        md,
+                        &query_name_arc,
+                    ));
+                }
+                libc::free(regs[j] as *mut c_void);
+            }
+            Ok(mappings)
+        })?;
+
+        let [mappings1, mappings2] = mappings;
+        Ok((mappings1, mappings2))
+    }
+
+    /// Map read pairs jointly with [`map_pair`](Aligner::map_pair), lazily and in order
+    ///
+    /// Each item of `pairs` is the pair name and the sequences of its two mates. The mappings of
+    /// both mates come back together as a [`paf::PairedMappings`], ready to be written with
+    /// [`paf::PairedMappings::write_paf`].
+    pub fn map_pairs<'a, I, N, S>(
+        &'a self,
+        pairs: I,
+        cs: bool,
+        md: bool,
+    ) -> impl Iterator<Item = Result<paf::PairedMappings, &'static str>> + 'a
+    where
+        I: IntoIterator<Item = (N, S, S)>,
+        I::IntoIter: 'a,
+        N: AsRef<[u8]>,
+        S: AsRef<[u8]>,
+    {
+        pairs.into_iter().map(move |(name, seq1, seq2)| {
+            let name = name.as_ref();
+            let (first, second) =
+                self.map_pair(seq1.as_ref(), seq2.as_ref(), cs, md, Some(name))?;
+            let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(name));
+            // Share the name with the mappings, as map_pair does between mates
+            let name = first
+                .iter()
+                .chain(second.iter())
+                .find_map(|m| m.query_name.clone())
+                .unwrap_or_else(|| Arc::new(name.into_owned()));
+            Ok(paf::PairedMappings {
+                name: Some(name),
+                first,
+                second,
+            })
+        })
+    }
+
+    /// CIGAR character and op clipping the query of `reg`, following `mm_write_sam3`
+    pub(crate) fn clip_op(&self, reg: &mm_reg1_t) -> (char, u8) {
+        let flag = self.mapopt.flag;
+        let secondary = reg.id != reg.parent;
+        let supplementary = !secondary && reg.sam_pri() == 0;
+        let hard = self.hard_clipping
+            && (supplementary || (secondary && flag & MM_F_SECONDARY_SEQ as i64 != 0))
+            && flag & MM_F_SOFTCLIP as i64 == 0;
+        if hard {
+            ('H', 5)
+        } else {
+            ('S', 4)
+        }
+    }
+
+    /// Convert a hit returned by minimap2 into a [`Mapping`], freeing its extra data
+    ///
+    /// # Safety
+    /// `reg_ptr` must point to a hit returned by minimap2 for `seq` against this index
+    unsafe fn reg_to_mapping(
+        &self,
+        reg_ptr: *mut mm_reg1_t,
+        seq: &[u8],
+        cs: bool,
+        md: bool,
+        query_name_arc: &Option<Arc<String>>,
+    ) -> Mapping {
+        let reg: mm_reg1_t = *reg_ptr;
+
+        let idx = Arc::as_ptr(self.idx.as_ref().unwrap());
+        debug_assert!(
+            reg.rid >= 0 && (reg.rid as u32) < (**idx).n_seq,
+            "hit on target {} of an index of {} targets",
+            reg.rid,
+            (**idx).n_seq
+        );
+
+        let is_primary = reg.parent == reg.id && (reg.sam_pri() > 0);
+        let is_supplementary = (reg.parent == reg.id) && (reg.sam_pri() == 0);
+
+        // todo holy heck this code is ugly
+        let alignment = if !reg.p.is_null() {
+            let p = &*reg.p;
+
+            // calculate the edit distance
+            let nm = reg.blen - reg.mlen + p.n_ambi() as i32;
+            let n_cigar = p.n_cigar;
+
+            // Create a vector of the cigar blocks
+            let (cigar, cigar_str) = if n_cigar > 0 {
+                let mut cigar = p
+                    .cigar
+                    .as_slice(n_cigar as usize)
+                    .to_vec()
+                    .iter()
+                    .map(|c| ((c >> 4), (c & 0xf) as u8)) // unpack the length and op code
+                    .collect::<Vec<(u32, u8)>>();
+
+                // Fix for adding in soft clipping cigar strings
+                // Taken from minimap2 write_sam_cigar function
+                // clip_len[0] = r->rev? qlen - r->qe : r->qs;
+                // clip_len[1] = r->rev? r->qs : qlen - r->qe;
+
+                let clip_len0 = if reg.rev() != 0 {
+                    seq.len() as i32 - reg.qe
+                } else {
+                    reg.qs
+                };
+
+                let clip_len1 = if reg.rev() != 0 {
+                    reg.qs
+                } else {
+                    seq.len() as i32 - reg.qe
+                };
+
+                let mut cigar_str = cigar
+                    .iter()
+                    .map(|(len, code)| {
+                        let cigar_char = match code {
+                            0 => "M",
+                            1 => "I",
+                            2 => "D",
+                            3 => "N",
+                            4 => "S",
+                            5 => "H",
+                            6 => "P",
+                            7 => "=",
+                            8 => "X",
+                            _ => panic!("Invalid CIGAR code {code}"),
+                        };
+                        format!("{len}{cigar_char}")
+                    })
+                    .collect::<Vec<String>>()
+                    .join("");
+
+                let (clip_char, clip_op) = self.clip_op(&reg);
+
+                // Pre and append clip identifiers to start and end
+                if clip_len0 > 0 {
+                    cigar_str = format!("{}{}{}", clip_len0, clip_char, cigar_str);
+                    if self.cigar_clipping {
+                        cigar.insert(0, (clip_len0 as u32, clip_op));
+                    }
+                }
+
+                if clip_len1 > 0 {
+                    cigar_str = format!("{}{}{}", cigar_str, clip_len1, clip_char);
+                    if self.cigar_clipping {
+                        cigar.push((clip_len1 as u32, clip_op));
+                    }
+                }
+
+                (Some(cigar), Some(cigar_str))
+            } else {
+                (None, None)
+            };
+
+            let (cs_str, md_str) = if cs || md {
+                let idx: &mm_idx_t = self.idx.as_ref().unwrap();
+                let mut tags = TagBuffer::new();
+
+                let cs_str = if cs {
+                    let cs = tags.gen_cs(idx, &reg, seq, true);
+                    Some(String::from_utf8_lossy(cs).into_owned())
+                } else {
+                    None
+                };
+
+                let md_str = if md {
+                    let md = tags.gen_md(idx, &reg, seq);
+                    Some(String::from_utf8_lossy(md).into_owned())
+                } else {
+                    None
+                };
+
+                (cs_str, md_str)
+            } else {
+                (None, None)
+            };
+
+            Some(Alignment {
+                nm,
+                cigar,
+                cigar_str,
+                md: md_str,
+                cs: cs_str,
+                alignment_score: Some(p.dp_score as i32),
+                dp_skipped: DpSkipped::from_split(reg.split()),
+            })
+        } else {
+            None
+        };
+
+        let target_len = (*(**idx).seq.offset(reg.rid as isize)).len as i32;
+
+        let mut mapping = Mapping {
+            target_name: Some(Arc::clone(&self.target_names().names[reg.rid as usize])),
+            target_len,
+            target_start: reg.rs,
+            target_end: reg.re,
+            query_name: query_name_arc.clone(),
+            query_len: NonZeroI32::new(seq.len() as i32),
+            query_start: reg.qs,
+            query_end: reg.qe,
+            strand: if reg.rev() == 0 {
+                Strand::Forward
+            } else {
+                Strand::Reverse
+            },
+            match_len: reg.mlen,
+            block_len: reg.blen,
+            mapq: reg.mapq(),
+            is_primary,
+            is_supplementary,
+            alignment_type: AlignmentType::of_reg(&reg),
+            alignment,
+            target_seq: None,
+        };
+
+        if self.output_target_seq {
+            mapping.target_seq = self
+                .fetch_subseq_by_id(
+                    reg.rid as u32,
+                    reg.rs as u32..reg.re as u32,
+                    Strand::Forward,
+                )
+                .ok();
+        }
+
+        if let Some(recalibrate) = &self.mapq_recalibration {
+            mapping.mapq = recalibrate(&mapping);
+        }
+        if let Some(max_mapq) = self.max_mapq {
+            mapping.mapq = mapping.mapq.min(max_mapq);
+        }
+
+        libc::free(reg.p as *mut c_void);
+        mapping
+    }
+
+    /// Map entire file
+    /// Detects if file is gzip or not and if it's fastq/fasta or not
+    /// Best for smaller files (all results are stored in an accumulated Vec!)
+    /// What you probably want is to loop through the file yourself and use the map() function
+    ///
+    /// TODO: Remove cs and md and make them options on the struct
+    ///
+    #[cfg(feature = "map-file")]
+    pub fn map_file(&self, file: &str, cs: bool, md: bool) -> Result<Vec<Mapping>, &'static str> {
+        // Make sure index is set
+        if self.idx.is_none() {
+            return Err("No index");
+        }
+
+        // Check that file exists
+        if !Path::new(file).exists() {
+            return Err("File does not exist");
+        }
+
+        // Check that file isn't empty...
+        let metadata = std::fs::metadata(file).unwrap();
+        if metadata.len() == 0 {
+            return Err("File is empty");
+        }
+
+        let mut reader = parse_fastx_file(file).expect("Unable to read FASTA/X file");
+
+        // The output vec
+        let mut mappings = Vec::new();
+        let before = self.filtered_reads();
+
+        // Iterate over the sequences
+        while let Some(record) = reader.next() {
+            let record = match record {
+                Ok(record) => record,
+                Err(_) => {
+                    return Err("Error reading record in FASTA/X files. Please confirm integrity.")
+                }
+            };
+
+            if !self.keep_read(record.id(), &record.seq(), record.qual()) {
+                continue;
+            }
+
+            let query_name = record.id().to_vec();
+            let mut seq_mappings =
+                self.map(&record.seq(), cs, md, None, None, Some(&query_name))?;
+
+            for mapping in seq_mappings.iter_mut() {
+                let id = record.id();
+                if id.is_empty() {
+                    mapping.query_name = Some(Arc::new(
+                        format!("Unnamed Seq with Length: {}", record.seq().len()).to_string(),
+                    ));
+                }
+            }
+
+            mappings.extend(seq_mappings);
+        }
+        self.report_filtered_reads(before);
+
+        Ok(mappings)
+    }
+
+    /// Map an entire file like [`map_file`](Aligner::map_file), but carry on past records that
+    /// can not be read or mapped (truncated or empty records), reporting them instead
+    ///
+    /// Returns the mappings of the good records and an error per bad record. Errors opening
+    /// the file still fail the whole call. If the parser can not get past a bad record, the
+    /// rest of the file is skipped after reporting it.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("test_data/MT-human.fa", None)
+    ///     .unwrap();
+    /// let (mappings, errors) = aligner
+    ///     .map_file_tolerant("test_data/query.fa", false, false)
+    ///     .unwrap();
+    /// assert!(errors.is_empty());
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn map_file_tolerant(
+        &self,
+        file: &str,
+        cs: bool,
+        md: bool,
+    ) -> Result<(Vec<Mapping>, Vec<RecordError>), &'static str> {
+        if self.idx.is_none() {
+            return Err("No index");
+        }
+
+        if !Path::new(file).exists() {
+            return Err("File does not exist");
+        }
+
+        let metadata = std::fs::metadata(file).map_err(|_| "Unable to read FASTA/X file")?;
+        if metadata.len() == 0 {
+            return Err("File is empty");
+        }
+
+        let mut reader = parse_fastx_file(file).map_err(|_| "Unable to read FASTA/X file")?;
+
+        let mut mappings = Vec::new();
+        let mut errors = Vec::new();
+        let mut index = 0;
+        let mut last_error_byte = None;
+        let before = self.filtered_reads();
+
+        while let Some(record) = reader.next() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    errors.push(RecordError {
+                        index,
+                        name: None,
+                        message: e.to_string(),
+                    });
+                    index += 1;
+
+                    // Stop if the parser is stuck on the same bad record
+                    let byte = reader.position().byte();
+                    if last_error_byte == Some(byte) {
+                        break;
+                    }
+                    last_error_byte = Some(byte);
+                    continue;
+                }
+            };
+
+            if !self.keep_read(record.id(), &record.seq(), record.qual()) {
+                index += 1;
+                continue;
+            }
+
+            let name = String::from_utf8_lossy(record.id()).into_owned();
+            match self.map(&record.seq(), cs, md, None, None, Some(record.id())) {
+                Ok(seq_mappings) => mappings.extend(seq_mappings),
+                Err(e) => errors.push(RecordError {
+                    index,
+                    name: Some(name),
+                    message: e.to_string(),
+                }),
+            }
+            index += 1;
+        }
+        self.report_filtered_reads(before);
+
+        Ok((mappings, errors))
+    }
+
+    /// Map an entire file and write the results to `output` as PAF
+    ///
+    /// Reads are mapped in batches on `threads` worker threads and written in input order, with
+    /// only a few batches held in memory at a time, so this is suitable for large files.
+    /// cs and MD tags are written when enabled in mapopt (`with_cigar` enables cs).
+    ///
+    /// ```no_run
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_cigar()
+    ///     .with_index("ReferenceFile.fasta", None)
+    ///     .expect("Unable to build index");
+    /// aligner.map_file_to_paf("reads.fastq", "reads.paf", 4).expect("Unable to map");
     /// ```
+    #[cfg(feature = "map-file")]
+    pub fn map_file_to_paf<P, Q>(
+        &self,
+        input: P,
+        output: Q,
+        threads: usize,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        batch::check_input(&input)?;
+        let mut sink = sink::PafSink::create(output)?;
+        self.map_file_to_sink(input, &mut sink, threads)
+    }
+
+    /// Map an entire file and hand the mappings of each read, in input order, to `sink`
     ///
-    /// Set the number of threads (prefer to use the struct config)
-    pub fn with_index_threads(mut self, threads: usize) -> Self {
-        self.threads = threads;
-        self
+    /// Reads are mapped in batches on `threads` worker threads as in
+    /// [`map_file_to_paf`](Aligner::map_file_to_paf), which is this with a
+    /// [`PafSink`](sink::PafSink). cs and MD are computed when enabled in mapopt.
+    /// [`OutputSink::finish`](sink::OutputSink::finish) is called after the last read.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let mut n_mapped = 0;
+    /// let mut sink = |_: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+    ///     n_mapped += !mappings.is_empty() as usize;
+    ///     Ok(())
+    /// };
+    /// aligner
+    ///     .map_file_to_sink("test_data/gDNA_reads.fq", &mut sink, 2)
+    ///     .unwrap();
+    /// assert!(n_mapped > 0);
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn map_file_to_sink<P, S>(
+        &self,
+        input: P,
+        sink: &mut S,
+        threads: usize,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        S: sink::OutputSink + ?Sized,
+    {
+        let cs = self.mapopt.flag & MM_F_OUT_CS as i64 != 0;
+        let md = self.mapopt.flag & MM_F_OUT_MD as i64 != 0;
+
+        batch::run(
+            self,
+            input,
+            threads,
+            |aligner, rec| {
+                aligner
+                    .map(&rec.seq, cs, md, None, None, Some(&rec.id))
+                    .map(|mappings| (rec.id.clone(), mappings))
+            },
+            |result| {
+                let (id, mappings) = result?;
+                sink.write(&id, &mappings)
+            },
+        )?;
+
+        sink.finish()
+    }
+
+    /// Map an entire file on `threads` worker threads, handing the reads to `callback` in
+    /// batches of up to 256, in input order
+    ///
+    /// Each read of a batch comes with its ID and its mappings, empty if it did not map. The
+    /// work queue, worker threads and shutdown are those of
+    /// [`map_file_to_sink`](Aligner::map_file_to_sink): the first mapping error, or an error
+    /// returned by `callback`, stops the run and is returned. cs and MD are computed when
+    /// enabled in mapopt.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let mut n_reads = 0;
+    /// aligner
+    ///     .map_file_parallel("test_data/gDNA_reads.fq", 2, |batch| {
+    ///         n_reads += batch.len();
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// assert!(n_reads > 0);
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn map_file_parallel<P, F>(
+        &self,
+        input: P,
+        threads: usize,
+        mut callback: F,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&[(Vec<u8>, Vec<Mapping>)]) -> Result<(), &'static str>,
+    {
+        let mut batch = Vec::with_capacity(batch::DEFAULT_BATCH_SIZE);
+        let mut sink = |id: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+            batch.push((id.to_vec(), mappings.to_vec()));
+            if batch.len() == batch::DEFAULT_BATCH_SIZE {
+                callback(&batch)?;
+                batch.clear();
+            }
+            Ok(())
+        };
+        self.map_file_to_sink(input, &mut sink, threads)?;
+        if !batch.is_empty() {
+            callback(&batch)?;
+        }
+        Ok(())
+    }
+
+    /// Same as [`map_file_to_paf`](Aligner::map_file_to_paf), saving its progress to
+    /// `checkpoint` and resuming after the last checkpoint of an interrupted run
+    ///
+    /// Run it again with the same arguments after the job is killed: the output is cut back to
+    /// its size at the last checkpoint, the reads written by then are skipped and the others
+    /// appended, so the output is as if the run had not been interrupted. The checkpoint is
+    /// removed once every read is written.
+    ///
+    /// ```no_run
+    /// # use minimap2::*;
+    /// use minimap2::checkpoint::Checkpoint;
+    ///
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("reference.fasta", None)
+    ///     .expect("Unable to build index");
+    /// let checkpoint = Checkpoint::new("reads.paf.ckpt");
+    /// aligner
+    ///     .map_file_to_paf_resumable("reads.fastq", "reads.paf", 4, &checkpoint)
+    ///     .expect("Unable to map");
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn map_file_to_paf_resumable<P, Q>(
+        &self,
+        input: P,
+        output: Q,
+        threads: usize,
+        checkpoint: &checkpoint::Checkpoint,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        batch::check_input(&input)?;
+        let progress = checkpoint.resume(input.as_ref())?;
+        let output = output.as_ref();
+
+        let file = match progress.output_len {
+            // Drop what was written after the last checkpoint
+            Some(len) => {
+                let file = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(output)
+                    .map_err(|_| "Unable to open output file")?;
+                if file.metadata().map_or(0, |m| m.len()) < len {
+                    return Err("Output file is shorter than its checkpoint");
+                }
+                file.set_len(len)
+                    .map_err(|_| "Unable to open output file")?;
+                file
+            }
+            None if progress.reads > 0 => return Err("Checkpoint has no output length"),
+            None => std::fs::File::create(output).map_err(|_| "Unable to create output file")?,
+        };
+        let mut sink = sink::PafSink::new(std::io::BufWriter::new(file));
+
+        self.map_file_resumable(input, &mut sink, threads, checkpoint, progress, || {
+            std::fs::metadata(output).ok().map(|m| m.len())
+        })
+    }
+
+    /// Same as [`map_file_to_sink`](Aligner::map_file_to_sink), saving its progress to
+    /// `checkpoint` and resuming after the last checkpoint of an interrupted run
+    ///
+    /// [`OutputSink::flush`](sink::OutputSink::flush) is called before each checkpoint is
+    /// saved. When resuming, `sink` must add to the output of the interrupted run; reads it
+    /// wrote after its last checkpoint are written again, use
+    /// [`map_file_to_paf_resumable`](Aligner::map_file_to_paf_resumable) when they must not
+    /// be. The checkpoint is removed once every read is written.
+    #[cfg(feature = "map-file")]
+    pub fn map_file_to_sink_resumable<P, S>(
+        &self,
+        input: P,
+        sink: &mut S,
+        threads: usize,
+        checkpoint: &checkpoint::Checkpoint,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        S: sink::OutputSink + ?Sized,
+    {
+        batch::check_input(&input)?;
+        let progress = checkpoint.resume(input.as_ref())?;
+        self.map_file_resumable(input, sink, threads, checkpoint, progress, || None)
+    }
+
+    /// Map `input` to `sink` from the read after `progress`, saving the progress as set by
+    /// `checkpoint`; `output_len` gives the size of the output once flushed, if known
+    #[cfg(feature = "map-file")]
+    fn map_file_resumable<P, S, L>(
+        &self,
+        input: P,
+        sink: &mut S,
+        threads: usize,
+        checkpoint: &checkpoint::Checkpoint,
+        mut progress: checkpoint::Progress,
+        mut output_len: L,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        S: sink::OutputSink + ?Sized,
+        L: FnMut() -> Option<u64>,
+    {
+        let cs = self.mapopt.flag & MM_F_OUT_CS as i64 != 0;
+        let md = self.mapopt.flag & MM_F_OUT_MD as i64 != 0;
+
+        let before = self.filtered_reads();
+        let records = batch::read_file(self, input)?.skip(progress.reads as usize);
+        let mut saved = (progress.reads, std::time::Instant::now());
+
+        batch::run_records(
+            self,
+            records,
+            threads,
+            |aligner, rec| {
+                aligner
+                    .map(&rec.seq, cs, md, None, None, Some(&rec.id))
+                    .map(|mappings| (rec.id.clone(), mappings))
+            },
+            |result| {
+                let (id, mappings) = result?;
+                sink.write(&id, &mappings)?;
+                progress.reads += 1;
+
+                if checkpoint.due(progress.reads - saved.0, saved.1.elapsed()) {
+                    sink.flush()?;
+                    progress.output_len = output_len();
+                    checkpoint.save(&progress)?;
+                    saved = (progress.reads, std::time::Instant::now());
+                }
+                Ok(())
+            },
+        )?;
+        self.report_filtered_reads(before);
+
+        sink.finish()?;
+        checkpoint.remove()
+    }
+
+    /// Map an entire file, handing reads that map and pass `keep` to `sink` and writing the
+    /// others back out to `rejected`, in one pass
+    ///
+    /// Reads are mapped on `threads` worker threads as in
+    /// [`map_file_to_sink`](Aligner::map_file_to_sink). Unmapped reads are always rejected;
+    /// `keep` is called with the mappings of each mapped read. Rejected reads keep their
+    /// qualities and header comments, ready for re-analysis.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// use minimap2::sink::FastqWriter;
+    ///
+    /// let aligner = Aligner::builder()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let mut kept = 0;
+    /// let mut sink = |_: &[u8], _: &[Mapping]| -> Result<(), &'static str> {
+    ///     kept += 1;
+    ///     Ok(())
+    /// };
+    /// let mut rejected = FastqWriter::new(Vec::new());
+    /// aligner
+    ///     .map_file_split(
+    ///         "test_data/gDNA_reads.fq",
+    ///         &mut sink,
+    ///         &mut rejected,
+    ///         |mappings| mappings.iter().any(|m| m.mapq >= 30),
+    ///         2,
+    ///     )
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn map_file_split<P, S, W, F>(
+        &self,
+        input: P,
+        sink: &mut S,
+        rejected: &mut sink::FastqWriter<W>,
+        keep: F,
+        threads: usize,
+    ) -> Result<(), &'static str>
+    where
+        P: AsRef<Path>,
+        S: sink::OutputSink + ?Sized,
+        W: std::io::Write,
+        F: Fn(&[Mapping]) -> bool + Sync,
+    {
+        let cs = self.mapopt.flag & MM_F_OUT_CS as i64 != 0;
+        let md = self.mapopt.flag & MM_F_OUT_MD as i64 != 0;
+
+        batch::run(
+            self,
+            input,
+            threads,
+            |aligner, rec| {
+                aligner
+                    .map(&rec.seq, cs, md, None, None, Some(&rec.id))
+                    .map(|mappings| {
+                        if !mappings.is_empty() && keep(&mappings) {
+                            Ok((rec.id.clone(), mappings))
+                        } else {
+                            Err(rec.clone())
+                        }
+                    })
+            },
+            |result| match result? {
+                Ok((id, mappings)) => sink.write(&id, &mappings),
+                Err(rec) => rejected.write_read(&rec.id, &rec.seq, rec.qual.as_deref()),
+            },
+        )?;
+
+        rejected.finish()?;
+        sink.finish()
+    }
+
+    /// Map in-memory queries, given as `(name, sequence)` pairs, on `threads` worker threads
+    ///
+    /// Queries are mapped in batches as in [`map_file_to_sink`](Aligner::map_file_to_sink),
+    /// without touching the filesystem; the mappings of each query are returned in input order.
+    /// The calling thread only collects results, so bindings can release their interpreter
+    /// lock around this call. The first mapping error is returned.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .with_index("test_data/MT-human.fa", None)
+    ///     .unwrap();
+    /// let query = "CCAGTTGACACAAAATAGACTACGAAAGTGGCTTTAACATATCTGAACACACAATAGCTAAGACCCAAACTGGGATTAGATACCCCACTATGCTTAGCCC";
+    /// let mappings = aligner
+    ///     .map_parallel([("q1", query), ("q2", query)], false, false, 2)
+    ///     .unwrap();
+    /// assert_eq!(mappings.len(), 2);
+    /// assert_eq!(mappings[0], mappings[1]);
+    /// ```
+    pub fn map_parallel<I, N, Q>(
+        &self,
+        queries: I,
+        cs: bool,
+        md: bool,
+        threads: usize,
+    ) -> Result<Vec<Vec<Mapping>>, &'static str>
+    where
+        I: IntoIterator<Item = (N, Q)>,
+        I::IntoIter: Send,
+        N: AsRef<[u8]>,
+        Q: AsRef<[u8]>,
+    {
+        let records = queries.into_iter().map(|(name, seq)| {
+            Ok(batch::QueryRecord {
+                id: name.as_ref().to_vec(),
+                seq: seq.as_ref().to_vec(),
+                qual: None,
+            })
+        });
+
+        let mut results = Vec::new();
+        batch::run_records(
+            self,
+            records,
+            threads,
+            |aligner, rec| aligner.map(&rec.seq, cs, md, None, None, Some(&rec.id)),
+            |mappings| {
+                results.push(mappings?);
+                Ok(())
+            },
+        )?;
+
+        Ok(results)
+    }
+
+    /// Map borrowed queries on worker threads spawned in `scope`, handing the mappings of each
+    /// query, in input order, to `sink`
+    ///
+    /// Unlike sharing an `Arc<Aligner>` with spawned threads, neither the aligner, the query
+    /// buffers nor the sink need to be `'static`: they only have to outlive the
+    /// [`std::thread::scope`], which joins every thread before it returns. Mapping runs in the
+    /// background, so the calling thread can carry on in the scope; join the returned handle
+    /// for the outcome. Queries are `(name, sequence)` pairs and are not copied. cs and MD are
+    /// computed when enabled in mapopt, and
+    /// [`OutputSink::finish`](sink::OutputSink::finish) is called after the last query.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .with_index("test_data/MT-human.fa", None)
+    ///     .unwrap();
+    /// let query = b"CCAGTTGACACAAAATAGACTACGAAAGTGGCTTTAACATATCTGAACACACAATAGCTAAGACCCAAACTGGGATTAGATACCCCACTATGCTTAGCCC".to_vec();
+    /// let reads = vec![("q1", &query[..]), ("q2", &query[..50])];
+    ///
+    /// let mut names = Vec::new();
+    /// std::thread::scope(|scope| {
+    ///     let sink = |name: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+    ///         names.push((name.to_vec(), mappings.len()));
+    ///         Ok(())
+    ///     };
+    ///     let handle = aligner.scoped_map(scope, reads.iter().copied(), 2, sink);
+    ///     handle.join().unwrap().unwrap();
+    /// });
+    /// assert_eq!(names[0], (b"q1".to_vec(), 1));
+    /// assert_eq!(names[1].0, b"q2");
+    /// ```
+    pub fn scoped_map<'scope, 'env, I, N, Q, S>(
+        &'env self,
+        scope: &'scope std::thread::Scope<'scope, 'env>,
+        queries: I,
+        threads: usize,
+        mut sink: S,
+    ) -> std::thread::ScopedJoinHandle<'scope, Result<(), &'static str>>
+    where
+        I: IntoIterator<Item = (N, Q)>,
+        I::IntoIter: Send + 'scope,
+        N: AsRef<[u8]> + Send + 'scope,
+        Q: AsRef<[u8]> + Send + 'scope,
+        S: sink::OutputSink + Send + 'scope,
+    {
+        let cs = self.mapopt.flag & MM_F_OUT_CS as i64 != 0;
+        let md = self.mapopt.flag & MM_F_OUT_MD as i64 != 0;
+        let queries = queries.into_iter().map(Ok);
+
+        scope.spawn(move || {
+            batch::run_records(
+                self,
+                queries,
+                threads,
+                |aligner, (name, seq): &(N, Q)| {
+                    let name = name.as_ref();
+                    aligner
+                        .map(seq.as_ref(), cs, md, None, None, Some(name))
+                        .map(|mappings| (name.to_vec(), mappings))
+                },
+                |result| {
+                    let (name, mappings) = result?;
+                    sink.write(&name, &mappings)
+                },
+            )?;
+            sink.finish()
+        })
+    }
+
+    /// Write the index to `writer` in minimap2's `.mmi` format, as `with_index` does to its
+    /// `output`, to keep it in memory, send it over the network or put it in an object store.
+    /// Load it back with [`load_index`](Aligner::load_index).
+    ///
+    /// minimap2 only writes indexes to files, so the index is written to a temporary file and
+    /// copied from there. Only the part of a multi-part index this aligner holds is written.
+    pub fn write_index<W: std::io::Write>(&self, mut writer: W) -> Result<(), &'static str> {
+        let idx: &mm_idx_t = self.idx.as_ref().ok_or("No index")?;
+        let temp = TempFile::new("mmi");
+        let path = temp
+            .0
+            .to_str()
+            .and_then(|path| CString::new(path).ok())
+            .ok_or("Invalid temporary index path")?;
+
+        unsafe {
+            let fp = libc::fopen(path.as_ptr(), b"wb\0".as_ptr() as *const libc::c_char);
+            if fp.is_null() {
+                return Err("Unable to create temporary index file");
+            }
+            mm_idx_dump(fp as *mut FILE, idx);
+            if libc::fclose(fp) != 0 {
+                return Err("Unable to write temporary index file");
+            }
+        }
+
+        let mut file =
+            std::fs::File::open(&temp.0).map_err(|_| "Unable to read temporary index file")?;
+        std::io::copy(&mut file, &mut writer).map_err(|_| "Unable to write index")?;
+        writer.flush().map_err(|_| "Unable to write index")
+    }
+
+    // This is in the python module, so copied here...
+    pub fn has_index(&self) -> bool {
+        self.idx.is_some()
+    }
+}
+
+mod send {
+    use super::{Aligner, Built, PresetSet, Unset};
+
+    unsafe impl Sync for Aligner<Unset> {}
+    unsafe impl Send for Aligner<Unset> {}
+    unsafe impl Sync for Aligner<Built> {}
+    unsafe impl Send for Aligner<Built> {}
+    unsafe impl Sync for Aligner<PresetSet> {}
+    unsafe impl Send for Aligner<PresetSet> {}
+}
+
+#[derive(PartialEq, Eq)]
+pub enum FileFormat {
+    FASTA,
+    FASTQ,
+}
+
+/// Pseudo-random sequence of `len` bases, the same for the same `seed`, for tests
+#[cfg(test)]
+pub(crate) fn random_seq(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            b"ACGT"[(state >> 62) as usize]
+        })
+        .collect()
+}
+
+/// The sequence of test_data/MT-human.fa
+#[cfg(test)]
+pub(crate) fn mt_seq() -> Vec<u8> {
+    let fasta = std::fs::read_to_string("test_data/MT-human.fa").unwrap();
+    fasta.lines().skip(1).flat_map(|l| l.bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligner_between_threads() {
+        // Because I'm not sure how this will work with FFI + Threads, want a sanity check
+        use std::thread;
+
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(2)
+            .with_index("yeast_ref.mmi", None)
+            .unwrap();
+
+        aligner
+            .map(
+                "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(),
+                false,
+                false,
+                None,
+                None,
+                Some(b"Sample Query")
+            )
+            .unwrap();
+        let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+        assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+
+        let jh = thread::spawn(move || {
+            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+            aligner
+        });
+
+        let aligner = jh.join().unwrap();
+
+        let jh = thread::spawn(move || {
+            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+            aligner
+        });
+
+        let _aligner = jh.join().unwrap();
+    }
+
+    #[test]
+    fn shared_aligner() {
+        // Because I'm not sure how this will work with FFI + Threads, want a sanity check
+        use std::sync::Arc;
+        use std::thread;
+
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(2)
+            .with_index("yeast_ref.mmi", None)
+            .unwrap();
+
+        let aligner = Arc::new(aligner);
+
+        aligner
+            .map(
+                "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(),
+                false,
+                false,
+                None,
+                None,
+                Some(b"Sample Query")
+            )
+            .unwrap();
+        let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+        assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+
+        let aligner_handle = Arc::clone(&aligner);
+        let jh0 = thread::spawn(move || {
+            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+        });
+
+        jh0.join().unwrap();
+
+        let aligner_handle = Arc::clone(&aligner);
+        let jh1 = thread::spawn(move || {
+            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+        });
+
+        jh1.join().unwrap();
+    }
+
+    #[test]
+    fn rayon() {
+        // Because I'm not sure how this will work with FFI + Threads, want a sanity check
+        use rayon::prelude::*;
+
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(2)
+            .with_cigar()
+            .with_index("yeast_ref.mmi", None)
+            .unwrap();
+
+        let sequences = vec![
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
+            "GTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGG",
+            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAG",
+        ];
+
+        let _results = sequences
+            .par_iter()
+            .map(|seq| {
+                aligner
+                    .map(
+                        seq.as_bytes(),
+                        false,
+                        false,
+                        None,
+                        None,
+                        Some(b"Sample Query"),
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+    }
+
+    #[test]
+    fn does_it_work() {
+        let mut mm_idxopt = MaybeUninit::uninit();
+        let mut mm_mapopt = MaybeUninit::uninit();
+
+        unsafe { mm_set_opt(&0, mm_idxopt.as_mut_ptr(), mm_mapopt.as_mut_ptr()) };
+    }
+
+    #[test]
+    fn idxopt() {
+        let _x: IdxOpt = Default::default();
+    }
+
+    #[test]
+    fn mapopt() {
+        let _x: mm_mapopt_t = Default::default();
+        let _y: MapOpt = Default::default();
+    }
+
+    #[test]
+    fn aligner_build_manually() {
+        let idxopt: IdxOpt = Default::default();
+
+        let mapopt: MapOpt = Default::default();
+
+        let threads = 1;
+        let idx = None;
+        let idx_reader = None;
+
+        let _aligner = Aligner {
+            idxopt,
+            mapopt,
+            threads,
+            idx,
+            idx_reader,
+            cigar_clipping: false,
+            hard_clipping: false,
+            max_mapq: None,
+            mapq_recalibration: None,
+            output_target_seq: false,
+            target_names: OnceLock::new(),
+            max_hits: None,
+            truncate_hits: false,
+            quality_mismatch: QualityMismatch::Error,
+            comment_passthrough: CommentPassthrough::Drop,
+            query_orientation: QueryOrientation::AsIs,
+            hit_order: HitOrder::Minimap2,
+            tag_passthrough: TagPassthrough::All,
+            read_filter: None,
+            read_ids: None,
+            read_filter_counts: Default::default(),
+            record_tags: None,
+            duplicate_names: DuplicateNames::Error,
+            ava_filter: None,
+            sequence_source: None,
+            index_files: None,
+            state: Unset,
+        };
+    }
+
+    #[test]
+    fn test_mapopt_flags_in_aligner() {
+        let mut aligner = Aligner::builder();
+        aligner.mapopt.set_no_qual();
+        assert_eq!(
+            aligner.mapopt.flag & MM_F_NO_QUAL as i64,
+            MM_F_NO_QUAL as i64
+        );
+        aligner.mapopt.unset_no_qual();
+        assert_eq!(aligner.mapopt.flag & MM_F_NO_QUAL as i64, 0_i64);
+    }
+
+    #[test]
+    fn test_chaining_options() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_chain_gap_scale(1.5)
+            .with_chain_skip_scale(0.3)
+            .with_max_chain_iter(1000)
+            .with_max_chain_skip(50);
+        assert_eq!(aligner.mapopt.chain_gap_scale, 1.5);
+        assert_eq!(aligner.mapopt.chain_skip_scale, 0.3);
+        assert_eq!(aligner.mapopt.max_chain_iter, 1000);
+        assert_eq!(aligner.mapopt.max_chain_skip, 50);
+        assert!(aligner.check_opts().is_ok());
+
+        let aligner = aligner.with_index("test_data/MT-human.fa", None).unwrap();
+        let seq = mt_seq();
+        let mappings = aligner
+            .map(&seq[2000..4000], false, false, None, None, None)
+            .unwrap();
+        assert_eq!(
+            (mappings[0].target_start, mappings[0].target_end),
+            (2000, 4000)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "max_chain_iter must be positive")]
+    fn test_max_chain_iter_positive() {
+        let _aligner = Aligner::builder().map_ont().with_max_chain_iter(0);
+    }
+
+    #[test]
+    fn test_ava_filter() {
+        let mt = mt_seq();
+        // r1 and r2 overlap by 1 kb, r2 and r3 by 500 bp
+        let reads = [
+            mt[0..3000].to_vec(),
+            mt[2000..5000].to_vec(),
+            mt[4500..7500].to_vec(),
+        ];
+        let names = [b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()];
+
+        let overlaps = |filter: Option<overlap::AvaFilter>| {
+            let mut builder = Aligner::builder().ava_ont();
+            if let Some(filter) = filter {
+                builder = builder.with_ava_filter(filter);
+            }
+            let aligner = builder.with_seqs_and_ids(&reads, &names).unwrap();
+            let mut pairs = Vec::new();
+            for (read, name) in reads.iter().zip(&names) {
+                for m in aligner
+                    .map(read, false, false, None, None, Some(name))
+                    .unwrap()
+                {
+                    let target = m.target_name.unwrap().to_string();
+                    let query = m.query_name.unwrap().to_string();
+                    if query != target {
+                        pairs.push((query, target));
+                    }
+                }
+            }
+            pairs.sort();
+            pairs.dedup();
+            pairs
+        };
+        let pair = |q: &str, t: &str| (q.to_string(), t.to_string());
+
+        let all = overlaps(None);
+        assert!(all.contains(&pair("r1", "r2")));
+        assert!(all.contains(&pair("r2", "r3")));
+
+        let long = overlaps(Some(overlap::AvaFilter {
+            min_overlap_len: 800,
+            ..Default::default()
+        }));
+        assert_eq!(long, vec![pair("r1", "r2")]);
+
+        let dual = overlaps(Some(overlap::AvaFilter {
+            min_overlap_len: 800,
+            max_overhang: None,
+            suppress_dual: false,
+        }));
+        assert_eq!(dual, vec![pair("r1", "r2"), pair("r2", "r1")]);
+    }
+
+    #[test]
+    fn test_name_hashing() {
+        let aligner = Aligner::builder().map_ont();
+        assert_eq!(aligner.mapopt.flag & MM_F_NO_HASH_NAME as i64, 0);
+
+        let aligner = aligner.with_name_hashing(false).with_seed(42);
+        assert_ne!(aligner.mapopt.flag & MM_F_NO_HASH_NAME as i64, 0);
+        assert_eq!(aligner.mapopt.seed, 42);
+        let aligner = aligner.with_name_hashing(true);
+        assert_eq!(aligner.mapopt.flag & MM_F_NO_HASH_NAME as i64, 0);
+
+        // Without name hashing, a read in a repeat maps the same under any name
+        let unit = b"ACGTTGCATCCGATAGCTAGGCTTACGATCGGATCTTAGCCATGCAGTCGATCCGTAGCTAGTCGATCGTACG";
+        let mut reference = Vec::new();
+        for _ in 0..2 {
+            reference.extend_from_slice(b"TTTTTTTTTTGGGGGGGGGG");
+            for _ in 0..8 {
+                reference.extend_from_slice(unit);
+            }
+        }
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_name_hashing(false)
+            .with_seq(&reference)
+            .unwrap();
+        let query = &reference[20..20 + unit.len() * 4];
+        let mapped = |name: &[u8]| {
+            aligner
+                .map(query, false, false, None, None, Some(name))
+                .unwrap()
+                .into_iter()
+                .map(|m| (m.target_start, m.strand, m.mapq, m.is_primary))
+                .collect::<Vec<_>>()
+        };
+        let first = mapped(b"read1");
+        for name in [b"read2", b"other", b"xyzzy"] {
+            assert_eq!(mapped(name), first);
+        }
+    }
+
+    #[test]
+    fn test_idxopt_flags_in_aligner() {
+        let mut aligner = Aligner::builder();
+        aligner.idxopt.set_hpc();
+        assert_eq!(aligner.idxopt.flag & MM_I_HPC as i16, MM_I_HPC as i16);
+        aligner.idxopt.unset_hpc();
+        assert_eq!(aligner.idxopt.flag & MM_I_HPC as i16, 0_i16);
+    }
+
+    #[test]
+    fn aligner_builder() {
+        let _result = Aligner::builder();
+    }
+
+    #[test]
+    fn aligner_builder_preset() {
+        let _result = Aligner::builder().preset(Preset::LrHq);
+    }
+
+    #[test]
+    fn aligner_builder_preset_with_threads() {
+        let _result = Aligner::builder()
+            .preset(Preset::LrHq)
+            .with_index_threads(1);
+    }
+
+    #[test]
+    fn create_index_file_missing() {
+        let result = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(1)
+            .with_index(
+                "test_data/test.fa_FILE_NOT_FOUND",
+                Some("test_FILE_NOT_FOUND.mmi"),
+            );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn create_index() {
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(1);
+
+        println!("{}", aligner.idxopt.w);
+
+        assert!(aligner.idxopt.w == 10);
+
+        aligner
+            .with_index("test_data/test_data.fasta", Some("test.mmi"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_builder() {
+        let _aligner = Aligner::builder().preset(Preset::MapOnt);
+    }
+
+    #[test]
+    fn test_mapping() {
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(2)
+            .with_index("yeast_ref.mmi", None)
+            .unwrap();
+
+        aligner
+            .map(
+                "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(),
+                false,
+                false,
+                None,
+                None,
+                Some(b"Sample Query")
+            )
+            .unwrap();
+        let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+        println!("{:#?}", mappings);
+
+        // This should be reverse strand
+        let mappings = aligner.map("TTTTGCATCGCTGAAAACCCCAAAGTATATTTTAGAACTCGTCTATAGGTTCTACGATTTAACATCCACAGCCTTCTGGTGTCGCTGGTGTTTCAAACACCTCGATATATCACTCCTTCTGAATAACATCCATGAAAGAAGAGCCCAATCCATACTACTAAAGCTATCGTCATATGCACCATGGTCTTTTGAGAAAATTTTGCCCTCTTTAATTGACTCTAAGCTAAAAAAGAAAATTTTAATCAGTCCTCAAATTACTTACGTAGTCTTCAAATCAATAAACTATATGATAACCACGAATGACGATAAAATACACAAGTCCGCTATTCCTTCTTCTTCCTCTCTACCGT".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+        println!("Reverse Strand\n{:#?}", mappings);
+        assert!(mappings[0].strand == Strand::Reverse);
+
+        // Assert the Display impl for strand works
+        println!("{}", mappings[0].strand);
+
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(2)
+            .with_cigar()
+            .with_index("yeast_ref.mmi", None)
+            .unwrap();
+
+        aligner
+            .map(
+                "ATGAGCAAAATATTCTAAAGTGGAAACGGCACTAAGGTGAACTAAGCAACTTAGTGCAAAAc".as_bytes(),
+                true,
+                false,
+                None,
+                None,
+                Some(b"Sample Query"),
+            )
+            .unwrap();
+
+        let mappings = aligner.map("atCCTACACTGCATAAACTATTTTGcaccataaaaaaaagttatgtgtgGGTCTAAAATAATTTGCTGAGCAATTAATGATTTCTAAATGATGCTAAAGTGAACCATTGTAatgttatatgaaaaataaatacacaattaagATCAACACAGTGAAATAACATTGATTGGGTGATTTCAAATGGGGTCTATctgaataatgttttatttaacagtaatttttatttctatcaatttttagtaatatctacaaatattttgttttaggcTGCCAGAAGATCGGCGGTGCAAGGTCAGAGGTGAGATGTTAGGTGGTTCCACCAACTGCACGGAAGAGCTGCCCTCTGTCATTCAAAATTTGACAGGTACAAACAGactatattaaataagaaaaacaaactttttaaaggCTTGACCATTAGTGAATAGGTTATATGCTTATTATTTCCATTTAGCTTTTTGAGACTAGTATGATTAGACAAATCTGCTTAGttcattttcatataatattgaGGAACAAAATTTGTGAGATTTTGCTAAAATAACTTGCTTTGCTTGTTTATAGAGGCacagtaaatcttttttattattattataattttagattttttaatttttaaat".as_bytes(), true, false, None, None, Some(b"Sample Query")).unwrap();
+        println!("{:#?}", mappings);
+    }
+
+    #[test]
+    fn test_cli_options() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_match_score(1)
+            .with_mismatch_penalty(3)
+            .with_gap_open_penalty(3, Some(24))
+            .with_gap_extension_penalty(2, Some(1))
+            .with_zdrop(200, None)
+            .with_bandwidth(100, Some(1000))
+            .with_pri_ratio(0.5)
+            .with_min_chain_score(20)
+            .with_min_chain_count(2)
+            .with_min_dp_score(30)
+            .with_frag_mode(true);
+        let mapopt = &aligner.mapopt;
+        assert_eq!((mapopt.a, mapopt.b), (1, 3));
+        assert_eq!((mapopt.q, mapopt.e, mapopt.q2, mapopt.e2), (3, 2, 24, 1));
+        assert_eq!((mapopt.zdrop, mapopt.zdrop_inv), (200, 200));
+        assert_eq!((mapopt.bw, mapopt.bw_long), (100, 1000));
+        assert_eq!(mapopt.pri_ratio, 0.5);
+        assert_eq!(
+            (mapopt.min_chain_score, mapopt.min_cnt, mapopt.min_dp_max),
+            (20, 2, 30)
+        );
+        assert_ne!(mapopt.flag & MM_F_FRAG_MODE as i64, 0);
+        assert_eq!(aligner.check_opts(), Ok(()));
+        let aligner = aligner.with_frag_mode(false);
+        assert_eq!(aligner.mapopt.flag & MM_F_FRAG_MODE as i64, 0);
+
+        // Combinations are left to mm_check_opt
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_gap_extension_penalty(1, Some(2));
+        assert_eq!(
+            aligner.check_opts(),
+            Err("gap penalties must be positive, with dual gap penalties satisfying E1>E2 and O1+E1<O2+E2")
+        );
+        let aligner = Aligner::builder().map_ont().with_bandwidth(1_000_000, None);
+        assert_eq!(
+            aligner.check_opts(),
+            Err("-r should be no larger than --bw-long")
+        );
+
+        // and checked again when the index is built
+        let invalid = || Aligner::builder().map_ont().with_zdrop(100, Some(200));
+        let zdrop_error = Err("Z-drop should not be less than inversion-Z-drop");
+        assert_eq!(invalid().check_opts(), zdrop_error);
+        assert_eq!(
+            invalid().with_index("test_data/genome.fa", None).err(),
+            zdrop_error.err()
+        );
+        assert_eq!(
+            invalid().with_seq(b"ACGTACGTACGTACGTACGTACGT").err(),
+            zdrop_error.err()
+        );
+        assert_eq!(
+            invalid()
+                .with_index_parts("test_data/genome.fa", None)
+                .err(),
+            zdrop_error.err()
+        );
+
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_match_score(1)
+            .with_mismatch_penalty(3)
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+        let mappings = aligner.map(seq, false, false, None, None, None).unwrap();
+        assert!(!mappings.is_empty());
+    }
+
+    #[test]
+    fn test_query_orientation() {
+        let strands = |strand| {
+            let flag = Aligner::builder()
+                .splice()
+                .with_splice_strand(strand)
+                .mapopt
+                .flag;
+            (
+                flag & MM_F_SPLICE_FOR as i64 != 0,
+                flag & MM_F_SPLICE_REV as i64 != 0,
+            )
+        };
+        assert_eq!(strands(SpliceStrand::Both), (true, true));
+        assert_eq!(strands(SpliceStrand::Forward), (true, false));
+        assert_eq!(strands(SpliceStrand::Reverse), (false, true));
+        assert_eq!(strands(SpliceStrand::None), (false, false));
+
+        let builder = Aligner::builder()
+            .splice()
+            .with_cigar()
+            .with_splice_strand(SpliceStrand::Forward);
+        let reverse = builder
+            .clone()
+            .with_query_orientation(QueryOrientation::ReverseComplement)
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let aligner = builder.with_index("test_data/genome.fa", None).unwrap();
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
+
+        let (mappings, summary) = reverse
+            .map_with_summary(seq, true, false, None, None, Some(b"read1"))
+            .unwrap();
+        let (expected, expected_summary) = aligner
+            .map_with_summary(&revcomp(seq), true, false, None, None, Some(b"read1"))
+            .unwrap();
+        assert!(!mappings.is_empty());
+        assert_eq!(mappings, expected);
+        assert!(summary.reverse_complemented);
+        assert!(!expected_summary.reverse_complemented);
+        assert_eq!(
+            MappingSummary {
+                reverse_complemented: false,
+                ..summary
+            },
+            expected_summary
+        );
+
+        // Batches and pairs are reverse complemented too
+        let batch = reverse
+            .map_batch([(&b"read1"[..], &seq[..])], false, false)
+            .unwrap();
+        let batched: Vec<Mapping> = batch.query_mappings(0).map(|m| m.to_mapping()).collect();
+        let rc = revcomp(seq);
+        let expected = aligner
+            .map(&rc, false, false, None, None, Some(b"read1"))
+            .unwrap();
+        assert_eq!(batched, expected);
+        let (mate1, mate2) = (&seq[..50], &seq[50..]);
+        assert_eq!(
+            reverse.map_pair(mate1, mate2, false, false, None),
+            aligner.map_pair(&revcomp(mate1), &revcomp(mate2), false, false, None)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Splice strand only applies to splice presets")]
+    fn test_splice_strand_without_splice_preset() {
+        Aligner::builder()
+            .map_ont()
+            .with_splice_strand(SpliceStrand::Forward);
+    }
+
+    #[test]
+    fn test_check_opts_names_failed_check() {
+        let mut aligner = Aligner::builder();
+        assert!(aligner.check_opts().is_ok());
+
+        aligner.mapopt.set_for_only();
+        aligner.mapopt.set_rev_only();
+        assert_eq!(
+            aligner.check_opts(),
+            Err("--for-only and --rev-only can't be applied at the same time")
+        );
+    }
+
+    #[test]
+    fn test_alignment_score() {
+        let aligner = Aligner::builder()
+            .preset(Preset::Splice)
+            .with_index_threads(1);
+
+        aligner.check_opts().expect("Opts are invalid");
+
+        let aligner = aligner.with_index("test_data/genome.fa", None).unwrap();
+
+        let output = aligner.map(
+            b"GAAATACGGGTCTCTGGTTTGACATAAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGCCCAGACTTAAATCGCACATACTGCGTCGTGCAATGCCGGGCGCTAACGGCTCAATATCACGCTGCGTCACTATGGCTACCCCAAAGCGGGGGGGGCATCGACGGGCTGTTTGATTTGAGCTCCATTACCCTACAATTAGAACACTGGCAACATTTGGGCGTTGAGCGGTCTTCCGTGTCGCTCGATCCGCTGGAACTTGGCAACCACACTCTAAACTACATGTGGTATGGCTCATAAGATCATGCGGATCGTGGCACTGCTTTCGGCCACGTTAGAGCCGCTGTGCTCGAAGATTGGGACCTACCAAC",
+            false, false, None, None, Some(b"Sample Query")).unwrap();
+
+        println!("{:#?}", aligner.mapopt);
+        println!("{:#?}", aligner.idxopt);
+        println!("{:#?}", output);
+    }
+
+    #[test]
+    fn test_aligner_config_and_mapping() {
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(2);
+        let aligner = aligner
+            .with_cigar()
+            .with_index("test_data/test_data.fasta", Some("test.mmi"))
+            .unwrap();
+
+        aligner
+            .map(
+                "ATGAGCAAAATATTCTAAAGTGGAAACGGCACTAAGGTGAACTAAGCAACTTAGTGCAAAAc".as_bytes(),
+                true,
+                true,
+                None,
+                None,
+                Some(b"Sample Query"),
+            )
+            .unwrap();
+        let mappings = aligner.map("atCCTACACTGCATAAACTATTTTGcaccataaaaaaaagGGACatgtgtgGGTCTAAAATAATTTGCTGAGCAATTAATGATTTCTAAATGATGCTAAAGTGAACCATTGTAatgttatatgaaaaataaatacacaattaagATCAACACAGTGAAATAACATTGATTGGGTGATTTCAAATGGGGTCTATctgaataatgttttatttaacagtaatttttatttctatcaatttttagtaatatctacaaatattttgttttaggcTGCCAGAAGATCGGCGGTGCAAGGTCAGAGGTGAGATGTTAGGTGGTTCCACCAACTGCACGGAAGAGCTGCCCTCTGTCATTCAAAATTTGACAGGTACAAACAGactatattaaataagaaaaacaaactttttaaaggCTTGACCATTAGTGAATAGGTTATATGCTTATTATTTCCATTTAGCTTTTTGAGACTAGTATGATTAGACAAATCTGCTTAGttcattttcatataatattgaGGAACAAAATTTGTGAGATTTTGCTAAAATAACTTGCTTTGCTTGTTTATAGAGGCacagtaaatcttttttattattattataattttagattttttaatttttaaat".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
+        println!("{:#?}", mappings);
     }
 
-    #[deprecated(since = "0.1.17", note = "Please use `with_index_threads` instead")]
-    pub fn with_threads(mut self, threads: usize) -> Self {
-        self.threads = threads;
-        self
+    #[test]
+    fn test_mappy_output() {
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(1)
+            .with_cigar()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+
+        let mut mappings = aligner.map(
+    b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG",
+            true, true, None, None, Some(b"Sample Query")).unwrap();
+        assert_eq!(mappings.len(), 1);
+
+        let observed = mappings.pop().unwrap();
+
+        assert_eq!(
+            observed.target_name,
+            Some(Arc::new(String::from("MT_human")))
+        );
+        assert_eq!(observed.target_start, 576);
+        assert_eq!(observed.target_end, 768);
+        assert_eq!(observed.query_start, 0);
+        assert_eq!(observed.query_end, 191);
+        assert_eq!(observed.mapq, 29);
+        assert_eq!(observed.match_len, 168);
+        assert_eq!(observed.block_len, 195);
+        assert_eq!(observed.strand, Strand::Forward);
+        assert_eq!(observed.is_primary, true);
+
+        let align = observed.alignment.as_ref().unwrap();
+        assert_eq!(align.nm, 27);
+        assert_eq!(
+            align.cigar,
+            Some(vec![
+                (14, 0),
+                (2, 2),
+                (4, 0),
+                (3, 1),
+                (37, 0),
+                (1, 2),
+                (85, 0),
+                (1, 2),
+                (48, 0)
+            ])
+        );
+        assert_eq!(
+            align.cigar_str,
+            Some(String::from("14M2D4M3I37M1D85M1D48M9S"))
+        );
+        assert_eq!(
+            align.md,
+            Some(String::from(
+                "14^CC1C11A12T1A7T4^T1A48A2A21T0T8^T2A5T2A4C0A0C2T0C2A4A17"
+            ))
+        );
+        assert_eq!(align.cs, Some(String::from(":14-cc:1*ct:2+atc:9*ag:12*tc:1*ac:7*tc:4-t:1*ag:48*ag:2*ag:21*tc*tc:8-t:2*ag:5*tc:2*ag:4*ct*ac*ct:2*tc*ct:2*ag:4*ag:17")));
+
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(1)
+            .with_cigar()
+            .with_cigar_clipping()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+
+        let mut mappings = aligner.map(
+            b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG",
+                    true, true, None, None, Some(b"Sample Query")).unwrap();
+        assert_eq!(mappings.len(), 1);
+
+        let observed = mappings.pop().unwrap();
+
+        assert_eq!(
+            observed.target_name,
+            Some(Arc::new(String::from("MT_human")))
+        );
+        assert_eq!(observed.target_start, 576);
+        assert_eq!(observed.target_end, 768);
+        assert_eq!(observed.query_start, 0);
+        assert_eq!(observed.query_end, 191);
+        assert_eq!(observed.mapq, 29);
+        assert_eq!(observed.match_len, 168);
+        assert_eq!(observed.block_len, 195);
+        assert_eq!(observed.strand, Strand::Forward);
+        assert_eq!(observed.is_primary, true);
+
+        let align = observed.alignment.as_ref().unwrap();
+        assert_eq!(align.nm, 27);
+        assert_eq!(
+            align.cigar,
+            Some(vec![
+                (14, 0),
+                (2, 2),
+                (4, 0),
+                (3, 1),
+                (37, 0),
+                (1, 2),
+                (85, 0),
+                (1, 2),
+                (48, 0),
+                (9, 4)
+            ])
+        );
+        assert_eq!(
+            align.cigar_str,
+            Some(String::from("14M2D4M3I37M1D85M1D48M9S"))
+        );
+
+        let mut mappings = aligner.map(
+                    b"TTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG",
+                            true, true, None, None, Some(b"Sample Query")).unwrap();
+        assert_eq!(mappings.len(), 1);
+
+        let _observed = mappings.pop().unwrap();
+
+        assert_eq!(
+            align.cigar,
+            Some(vec![
+                (14, 0),
+                (2, 2),
+                (4, 0),
+                (3, 1),
+                (37, 0),
+                (1, 2),
+                (85, 0),
+                (1, 2),
+                (48, 0),
+                (9, 4)
+            ])
+        );
     }
 
-    // Check options
-    /// Check if the options are valid - Maps to mm_check_opt in minimap2
-    pub fn check_opts(&self) -> Result<(), &'static str> {
-        let result = unsafe { mm_check_opt(&self.idxopt, &self.mapopt) };
+    #[test]
+    fn test_mappy_output_no_md() {
+        let aligner = Aligner::builder()
+            .preset(Preset::MapOnt)
+            .with_index_threads(1)
+            .with_cigar()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let query =  b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG";
 
-        if result == 0 {
-            Ok(())
-        } else {
-            Err("Invalid options")
+        for (md, cs) in vec![(true, true), (false, false), (true, false), (false, true)].iter() {
+            let mapping = aligner
+                .map(query, *cs, *md, None, None, Some(b"Sample Query"))
+                .unwrap()
+                .pop()
+                .unwrap();
+            let align = mapping.alignment.as_ref().unwrap();
+            assert_eq!(align.cigar_str.is_some(), true);
+            assert_eq!(align.md.is_some(), *md);
+            assert_eq!(align.cs.is_some(), *cs);
         }
     }
 
-    /// Set index parameters for minimap2 using builder pattern
-    /// Creates the index as well with the given number of threads (set at struct creation).
-    /// You must set the number of threads before calling this function.
-    ///
-    /// Parameters:
-    /// path: Location of pre-built index or FASTA/FASTQ file (may be gzipped or plaintext)
-    /// Output: Option (None) or a filename
-    ///
-    /// Returns the aligner with the index set
-    ///
-    /// ```
-    /// # use minimap2::*;
-    /// // Do not save the index file
-    /// Aligner::builder().map_ont().with_index("test_data/test_data.fasta", None);
-    ///
-    /// // Save the index file as my_index.mmi
-    /// Aligner::builder().map_ont().with_index("test_data/test_data.fasta", Some("my_index.mmi"));
-    ///
-    /// // Use the previously built index
-    /// Aligner::builder().map_ont().with_index("my_index.mmi", None);
-    /// ```
-    pub fn with_index<P>(
-        self,
-        path: P,
-        output: Option<&str>,
-    ) -> Result<Aligner<Built>, &'static str>
-    where
-        P: AsRef<Path>,
-    {
-        match self.set_index(path, output) {
-            Ok(aln) => Ok(aln),
-            Err(e) => Err(e),
+    #[test]
+    fn test_seqs() {
+        let aligner = Aligner::builder()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+
+        let seqs: Vec<SeqMetaData> = aligner.seqs().collect();
+        assert_eq!(seqs.len(), aligner.n_seq() as usize);
+        assert_eq!(
+            seqs,
+            vec![
+                SeqMetaData {
+                    name: "chr1".to_string(),
+                    length: 1720,
+                    is_alt: false,
+                    offset: 0
+                },
+                SeqMetaData {
+                    name: "chr2".to_string(),
+                    length: 460,
+                    is_alt: false,
+                    offset: 1720
+                },
+            ]
+        );
+
+        // Indexed access agrees with the iterator and stops at the last sequence
+        for (i, seq) in seqs.iter().enumerate() {
+            assert_eq!(aligner.seq(i).as_ref(), Some(seq));
+            assert_eq!(aligner.get_seq(i).unwrap().len, seq.length);
         }
+        assert_eq!(aligner.seq(seqs.len()), None);
+        assert!(aligner.get_seq(seqs.len()).is_none());
     }
 
-    /// Sets the index, uses the builder pattern. Returns Aligner<Built> if successful.
-    pub fn set_index<P>(
-        mut self,
-        path: P,
-        output: Option<&str>,
-    ) -> Result<Aligner<Built>, &'static str>
-    where
-        P: AsRef<Path>,
-    {
-        let path_str = match std::ffi::CString::new(path.as_ref().as_os_str().as_bytes()) {
-            Ok(path) => path,
-            Err(_) => {
-                return Err("Invalid Path for Index");
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_map_file_to_paf() {
+        let aligner = Aligner::builder()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+
+        let output = std::env::temp_dir().join("minimap2_test_map_file_to_paf.paf");
+        aligner
+            .map_file_to_paf("test_data/gDNA_reads.fq", &output, 2)
+            .unwrap();
+
+        // Compare the 12 mandatory columns against minimap2 CLI output
+        let columns = |line: &str| line.split('\t').take(12).collect::<Vec<_>>().join("\t");
+        let observed = std::fs::read_to_string(&output).unwrap();
+        let expected = std::fs::read_to_string("test_data/gDNA_vs_genome.paf").unwrap();
+        let mut observed: Vec<String> = observed.lines().map(columns).collect();
+        let mut expected: Vec<String> = expected.lines().map(columns).collect();
+        observed.sort();
+        expected.sort();
+        assert_eq!(observed, expected);
+
+        std::fs::remove_file(&output).unwrap();
+
+        assert_eq!(
+            aligner.map_file_to_paf("test_data/file-does-not-exist", &output, 2),
+            Err("File does not exist")
+        );
+    }
+
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_map_file_resumable() {
+        let aligner = Aligner::builder()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let input = "test_data/gDNA_reads.fq";
+
+        // The id and PAF lines of each read, in input order
+        let mut reads: Vec<(Vec<u8>, String)> = Vec::new();
+        let mut sink = |id: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+            let paf = mappings.iter().map(|m| m.to_paf() + "\n").collect();
+            reads.push((id.to_vec(), paf));
+            Ok(())
+        };
+        aligner.map_file_to_sink(input, &mut sink, 2).unwrap();
+        let ids: Vec<Vec<u8>> = reads.iter().map(|(id, _)| id.clone()).collect();
+        assert_eq!(ids.len(), 6);
+
+        // A run interrupted after 5 reads, with a checkpoint every 2
+        let path = std::env::temp_dir().join("minimap2_test_map_file_resumable.ckpt");
+        let checkpoint = checkpoint::Checkpoint::new(&path).every_reads(2);
+        checkpoint.remove().unwrap();
+        let mut written = Vec::new();
+        let mut sink = |id: &[u8], _: &[Mapping]| -> Result<(), &'static str> {
+            if written.len() == 5 {
+                return Err("Interrupted");
             }
+            written.push(id.to_vec());
+            Ok(())
         };
+        assert_eq!(
+            aligner.map_file_to_sink_resumable(input, &mut sink, 2, &checkpoint),
+            Err("Interrupted")
+        );
+        assert_eq!(checkpoint.load().unwrap().unwrap().reads, 4);
 
-        // Confirm file exists
-        if !path.as_ref().exists() {
-            return Err("Index File does not exist");
-        }
+        // The next run starts after the checkpoint, so the fifth read is written again
+        let mut sink = |id: &[u8], _: &[Mapping]| -> Result<(), &'static str> {
+            written.push(id.to_vec());
+            Ok(())
+        };
+        aligner
+            .map_file_to_sink_resumable(input, &mut sink, 2, &checkpoint)
+            .unwrap();
+        assert_eq!(written[..5], ids[..5]);
+        assert_eq!(written[5..], ids[4..]);
+        assert_eq!(checkpoint.load(), Ok(None));
+
+        // A PAF run killed after its checkpoint at 3 reads, halfway through a line
+        let output = std::env::temp_dir().join("minimap2_test_map_file_resumable.paf");
+        let expected: String = reads.iter().map(|(_, paf)| paf.as_str()).collect();
+        let prefix: String = reads[..3].iter().map(|(_, paf)| paf.as_str()).collect();
+        std::fs::write(&output, prefix.clone() + "read4\t100").unwrap();
+        let input_len = std::fs::metadata(input).unwrap().len();
+        checkpoint
+            .save(&checkpoint::Progress {
+                reads: 3,
+                input_len,
+                output_len: Some(prefix.len() as u64),
+            })
+            .unwrap();
+        aligner
+            .map_file_to_paf_resumable(input, &output, 2, &checkpoint)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), expected);
+        assert_eq!(checkpoint.load(), Ok(None));
 
-        // Confirm file is not empty
-        if path.as_ref().metadata().unwrap().len() == 0 {
-            return Err("Index File is empty");
-        }
+        // Without a checkpoint the run starts over
+        aligner
+            .map_file_to_paf_resumable(input, &output, 2, &checkpoint)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), expected);
 
-        let output = match output {
-            Some(output) => match std::ffi::CString::new(output) {
-                Ok(output) => output,
-                Err(_) => return Err("Invalid Output for Index"),
-            },
-            None => std::ffi::CString::new(Vec::new()).unwrap(),
+        checkpoint
+            .save(&checkpoint::Progress {
+                reads: 3,
+                input_len: input_len + 1,
+                output_len: Some(0),
+            })
+            .unwrap();
+        assert_eq!(
+            aligner.map_file_to_paf_resumable(input, &output, 2, &checkpoint),
+            Err("Checkpoint does not match the input file")
+        );
+
+        checkpoint.remove().unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_with_index_files() {
+        let files = ["test_data/MT-human.fa", "test_data/genome.fa"];
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index_threads(2)
+            .with_index_files(&files, None)
+            .unwrap();
+        assert_eq!(aligner.n_seq(), 3);
+
+        let index_files = aligner.index_files.as_ref().unwrap();
+        assert_eq!(index_files.target_files, vec![0, 1, 1]);
+        assert_eq!(aligner.source_file("chr2"), Some(Path::new(files[1])));
+        assert_eq!(aligner.source_file("chr3"), None);
+
+        // Reads map to targets from either file
+        let read = aligner
+            .fetch_subseq("chr1", 100..600, Strand::Forward)
+            .unwrap();
+        let mappings = aligner.map(&read, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_name.as_deref().unwrap(), "chr1");
+        let read = aligner
+            .fetch_subseq("MT_human", 1000..1500, Strand::Forward)
+            .unwrap();
+        let mappings = aligner.map(&read, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_name.as_deref().unwrap(), "MT_human");
+
+        // Indexes from a single file do not track it
+        let aligner = Aligner::builder().with_index(files[1], None).unwrap();
+        assert_eq!(aligner.source_file("chr1"), None);
+
+        assert_eq!(
+            Aligner::builder()
+                .with_index_files(&["test_data/genome.fa", "test_data/genome.fa"], None)
+                .err(),
+            Some("Duplicate sequence name across index files")
+        );
+        assert_eq!(
+            Aligner::builder()
+                .with_index_files(
+                    &["test_data/genome.fa", "test_data/file-does-not-exist"],
+                    None
+                )
+                .err(),
+            Some("File does not exist")
+        );
+        assert_eq!(
+            Aligner::builder().with_index_files::<&str>(&[], None).err(),
+            Some("No index files")
+        );
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let mt = mt_seq();
+        let seqs = [
+            mt[0..3000].to_vec(),
+            mt[5000..8000].to_vec(),
+            mt[10000..13000].to_vec(),
+        ];
+        let ids = [b"r".to_vec(), b"r".to_vec(), b"r.2".to_vec()];
+        let names = |aligner: &Aligner<Built>| -> Vec<String> {
+            aligner.seqs().map(|seq| seq.name).collect()
         };
 
-        let idx_reader = MaybeUninit::new(unsafe {
-            mm_idx_reader_open(path_str.as_ptr(), &self.idxopt, output.as_ptr())
-        });
+        let err = Aligner::builder()
+            .map_ont()
+            .try_with_seqs_and_ids(&seqs, &ids)
+            .err()
+            .unwrap();
+        assert_eq!(err.name, "r");
+        assert_eq!(err.to_string(), "Duplicate sequence name r");
+        assert_eq!(
+            Aligner::builder()
+                .map_ont()
+                .with_seqs_and_ids(&seqs, &ids)
+                .err(),
+            Some("Duplicate sequence name")
+        );
 
-        let idx;
+        // Renamed repeats do not clash with later names
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_duplicate_names(DuplicateNames::Suffix)
+            .with_seqs_and_ids(&seqs, &ids)
+            .unwrap();
+        assert_eq!(names(&aligner), ["r", "r.2", "r.2.2"]);
 
-        let idx_reader = unsafe { idx_reader.assume_init() };
+        // Reads map to the sequence they came from
+        let mappings = aligner
+            .map(&mt[5500..6500], false, false, None, None, None)
+            .unwrap();
+        assert_eq!(mappings[0].target_name.as_deref().unwrap(), "r.2");
+        assert_eq!(mappings[0].target_start, 500);
 
-        unsafe {
-            // Just a test read? Just following: https://github.com/lh3/minimap2/blob/master/python/mappy.pyx#L147
-            idx = MaybeUninit::new(mm_idx_reader_read(
-                // self.idx_reader.as_mut().unwrap() as *mut mm_idx_reader_t,
-                &mut *idx_reader as *mut mm_idx_reader_t,
-                self.threads as libc::c_int,
-            ));
-            // Close the reader
-            mm_idx_reader_close(idx_reader);
-            // Set index opts
-            mm_mapopt_update(&mut self.mapopt, *idx.as_ptr());
-            // Idx index name
-            mm_idx_index_name(idx.assume_init());
-        }
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_duplicate_names(DuplicateNames::Allow)
+            .with_seqs_and_ids(&seqs, &ids)
+            .unwrap();
+        assert_eq!(names(&aligner), ["r", "r", "r.2"]);
+    }
 
-        let mm_idx = unsafe { idx.assume_init() };
-        self.idx = Some(Arc::new(mm_idx.into()));
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_duplicate_names_in_files() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_duplicate_names(DuplicateNames::Suffix)
+            .with_index_files(&["test_data/genome.fa", "test_data/genome.fa"], None)
+            .unwrap();
+        let names: Vec<String> = aligner.seqs().map(|seq| seq.name).collect();
+        assert_eq!(names, ["chr1", "chr2", "chr1.2", "chr2.2"]);
+        assert_eq!(
+            aligner.source_file("chr2.2"),
+            Some(Path::new("test_data/genome.fa"))
+        );
+        assert_eq!(
+            aligner.index_files.as_ref().unwrap().target_files,
+            [0, 0, 1, 1]
+        );
+    }
 
-        Ok(Aligner {
-            idxopt: self.idxopt,
-            mapopt: self.mapopt,
-            threads: self.threads,
-            idx: self.idx,
-            idx_reader: Some(Arc::new(unsafe { *idx_reader })),
-            cigar_clipping: self.cigar_clipping,
-            state: Built,
-        })
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_with_index_subset() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index_subset("test_data/genome.fa", &["chr1"], None)
+            .unwrap();
+        assert_eq!(aligner.n_seq(), 1);
+        assert_eq!(aligner.source_file("chr1"), None);
+
+        // Reads from the other contigs no longer map
+        let full = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let read = full.fetch_subseq("chr2", 0..400, Strand::Forward).unwrap();
+        assert!(aligner
+            .map(&read, false, false, None, None, None)
+            .unwrap()
+            .is_empty());
+        let read = full
+            .fetch_subseq("chr1", 100..600, Strand::Forward)
+            .unwrap();
+        let mappings = aligner.map(&read, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_name.as_deref().unwrap(), "chr1");
+        assert_eq!(mappings[0].target_start, 100);
+
+        // Both contigs, in file order
+        let aligner = Aligner::builder()
+            .with_index_subset("test_data/genome.fa", &["chr2", "chr1"], None)
+            .unwrap();
+        let names: Vec<String> = aligner.seqs().map(|s| s.name).collect();
+        assert_eq!(names, vec!["chr1", "chr2"]);
+
+        assert_eq!(
+            Aligner::builder()
+                .with_index_subset("test_data/genome.fa", &["chr1", "chr3"], None)
+                .err(),
+            Some("Contig not found in index file")
+        );
+        assert_eq!(
+            Aligner::builder()
+                .with_index_subset::<_, &str>("test_data/genome.fa", &[], None)
+                .err(),
+            Some("No contigs to index")
+        );
     }
 
-    /// Use a single sequence as the index. Sets the sequence ID to "N/A".
-    /// Can not be combined with `with_index` or `set_index`.
-    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
-    /// ```
-    /// # use minimap2::*;
-    /// # let seq = "CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
-    /// let aligner = Aligner::builder().map_ont().with_seq(seq.as_bytes()).expect("Unable to build index");
-    /// let query = b"CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCG";
-    /// let hits = aligner.map(query, false, false, None, None, Some(b"Query Name"));
-    /// assert_eq!(hits.unwrap().len(), 1);
-    /// ```
-    pub fn with_seq(self, seq: &[u8]) -> Result<Aligner<Built>, &'static str>
-// where T: AsRef<[u8]> + std::ops::Deref<Target = str>,
-    {
-        let default_id = "N/A";
-        self.with_seq_and_id(seq, default_id.as_bytes())
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_map_file_split() {
+        let aligner = Aligner::builder()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+
+        // Map a copy of the reads with an unmappable read and a comment added
+        let path = std::env::temp_dir().join("minimap2_test_map_file_split.fq");
+        let mut fastq = std::fs::read_to_string("test_data/gDNA_reads.fq").unwrap();
+        fastq.push_str("@junk comment=1\nACGTACGTACGTACGTACGTACGTACGTAC\n+\n");
+        fastq.push_str(&"5".repeat(30));
+        fastq.push('\n');
+        std::fs::write(&path, &fastq).unwrap();
+
+        let mut kept = Vec::new();
+        let mut sink = |id: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+            assert!(mappings.iter().any(|m| m.mapq >= 30));
+            kept.push(id.to_vec());
+            Ok(())
+        };
+        let mut rejected = sink::FastqWriter::new(Vec::new());
+        aligner
+            .map_file_split(
+                &path,
+                &mut sink,
+                &mut rejected,
+                |mappings| mappings.iter().any(|m| m.mapq >= 30),
+                2,
+            )
+            .unwrap();
+
+        let rejected = String::from_utf8(rejected.into_inner()).unwrap();
+        let n_reads = fastq.lines().filter(|l| l.starts_with('@')).count();
+        let n_rejected = rejected.lines().filter(|l| l.starts_with('@')).count();
+        assert_eq!(kept.len() + n_rejected, n_reads);
+        assert!(rejected.contains(&format!(
+            "@junk comment=1\nACGTACGTACGTACGTACGTACGTACGTAC\n+\n{}\n",
+            "5".repeat(30)
+        )));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_map_file_to_sink() {
+        let aligner = Aligner::builder()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+
+        let mut reads = Vec::new();
+        let mut n_mappings = 0;
+        let mut sink = |id: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+            reads.push(id.to_vec());
+            n_mappings += mappings.len();
+            Ok(())
+        };
+        aligner
+            .map_file_to_sink("test_data/gDNA_reads.fq", &mut sink, 3)
+            .unwrap();
+
+        // Every read is passed in input order, mapped or not
+        let fastq = std::fs::read_to_string("test_data/gDNA_reads.fq").unwrap();
+        let ids: Vec<Vec<u8>> = fastq
+            .lines()
+            .step_by(4)
+            .map(|line| {
+                line[1..]
+                    .split_whitespace()
+                    .next()
+                    .unwrap()
+                    .as_bytes()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(reads, ids);
+        let expected = std::fs::read_to_string("test_data/gDNA_vs_genome.paf").unwrap();
+        assert_eq!(n_mappings, expected.lines().count());
+
+        // Errors from the sink stop mapping
+        let mut failing =
+            |_: &[u8], _: &[Mapping]| -> Result<(), &'static str> { Err("Sink failed") };
+        assert_eq!(
+            aligner.map_file_to_sink("test_data/gDNA_reads.fq", &mut failing, 2),
+            Err("Sink failed")
+        );
     }
 
-    /// Use a single sequence as the index. Sets the sequence ID to "N/A".
-    /// Can not be combined with `with_index` or `set_index`.
-    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
-    /// ```
-    /// # use minimap2::*;
-    /// # let seq = "CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
-    /// # let id = "seq1";
-    /// let aligner = Aligner::builder().map_ont().with_seq_and_id(seq.as_bytes(), id.as_bytes()).expect("Unable to build index");
-    /// let query = b"CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCG";
-    /// let hits = aligner.map(query, false, false, None, None, Some(b"Sample Query"));
-    /// assert_eq!(hits.as_ref().unwrap().len(), 1);
-    /// assert_eq!(hits.as_ref().unwrap()[0].target_name.as_ref().unwrap().as_str(), id);
-    /// ```
-    pub fn with_seq_and_id(self, seq: &[u8], id: &[u8]) -> Result<Aligner<Built>, &'static str>
-// where T: AsRef<[u8]> + std::ops::Deref<Target = str>,
-    {
-        assert!(
-            self.idx.is_none(),
-            "Index already set. Can not set sequence as index."
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_map_file_parallel() {
+        let aligner = Aligner::builder()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+
+        let mut expected = Vec::new();
+        let mut sink = |id: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+            expected.push((id.to_vec(), mappings.to_vec()));
+            Ok(())
+        };
+        aligner
+            .map_file_to_sink("test_data/gDNA_reads.fq", &mut sink, 1)
+            .unwrap();
+
+        let mut reads = Vec::new();
+        let mut n_batches = 0;
+        aligner
+            .map_file_parallel("test_data/gDNA_reads.fq", 3, |batch| {
+                assert!(!batch.is_empty() && batch.len() <= batch::DEFAULT_BATCH_SIZE);
+                reads.extend_from_slice(batch);
+                n_batches += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(reads, expected);
+        assert_eq!(
+            n_batches,
+            expected.len().div_ceil(batch::DEFAULT_BATCH_SIZE)
         );
-        assert!(!seq.is_empty(), "Sequence is empty");
-        assert!(!id.is_empty(), "ID is empty");
 
-        self.with_seqs_and_ids(&[seq.to_vec()], &[id.to_vec()])
+        assert_eq!(
+            aligner.map_file_parallel("test_data/gDNA_reads.fq", 2, |_| Err("Callback failed")),
+            Err("Callback failed")
+        );
+        assert_eq!(
+            aligner.map_file_parallel("test_data/missing.fq", 2, |_| Ok(())),
+            Err("File does not exist")
+        );
     }
 
-    /// TODO: Does not work for more than 1 seq currently!
-    /// Pass multiple sequences to build an index functionally.
-    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
-    /// Can not be combined with `with_index` or `set_index`.
-    /// Sets the sequence IDs to "Unnamed Sequence n" where n is the sequence number.
-    pub fn with_seqs(self, seqs: &[Vec<u8>]) -> Result<Aligner<Built>, &'static str> {
-        assert!(
-            self.idx.is_none(),
-            "Index already set. Can not set sequence as index."
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_map_file_tolerant() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+
+        let reference = String::from_utf8(mt_seq()).unwrap();
+        let read = |start: usize| &reference[start..start + 500];
+        let qual = "I".repeat(500);
+
+        // A good read, an empty read, a good read, a read whose header has a NUL byte and a
+        // record truncated by the end of file
+        let fastq = format!(
+            "@good1\n{}\n+\n{}\n@empty\n\n+\n\n@good2\n{}\n+\n{}\n@bad\0name\n{}\n+\n{}\n@truncated\n{}\n+\n{}",
+            read(1000),
+            qual,
+            read(5000),
+            qual,
+            read(7000),
+            qual,
+            read(9000),
+            &qual[..100]
         );
-        assert!(!seqs.is_empty(), "Must have at least one sequence");
+        let path = std::env::temp_dir().join("minimap2_test_map_file_tolerant.fq");
+        std::fs::write(&path, fastq).unwrap();
 
-        let mut ids: Vec<Vec<u8>> = Vec::new();
-        for i in 0..seqs.len() {
-            ids.push(format!("Unnamed Sequence {}", i).into_bytes());
-        }
+        let (mappings, errors) = aligner
+            .map_file_tolerant(path.to_str().unwrap(), false, false)
+            .unwrap();
+        let mut names: Vec<&str> = mappings
+            .iter()
+            .filter(|m| m.is_primary)
+            .map(|m| m.query_name.as_ref().unwrap().as_str())
+            .collect();
+        names.dedup();
+        assert_eq!(names, vec!["good1", "good2"]);
 
-        self.with_seqs_and_ids(seqs, &ids)
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0],
+            RecordError {
+                index: 1,
+                name: Some("empty".to_string()),
+                message: "Sequence is empty".to_string()
+            }
+        );
+        assert_eq!(
+            errors[1],
+            RecordError {
+                index: 3,
+                name: Some("bad\0name".to_string()),
+                message: "Invalid query name".to_string()
+            }
+        );
+        assert_eq!((errors[2].index, errors[2].name.as_ref()), (4, None));
+
+        // map_file gives up on the first bad record
+        assert!(aligner
+            .map_file(path.to_str().unwrap(), false, false)
+            .is_err());
+
+        std::fs::remove_file(&path).unwrap();
     }
 
-    /// TODO: Does not work for more than 1 seq currently!
-    /// Pass multiple sequences and corresponding IDs to build an index functionally.
-    /// Following the mappy implementation, this also sets mapopt.mid_occ to 1000.
-    // This works for a single sequence, but not for multiple sequences.
-    // Maybe convert the underlying function itself?
-    // https://github.com/lh3/minimap2/blob/c2f07ff2ac8bdc5c6768e63191e614ea9012bd5d/index.c#L408
-    pub fn with_seqs_and_ids(
-        mut self,
-        seqs: &[Vec<u8>],
-        ids: &[Vec<u8>],
-    ) -> Result<Aligner<Built>, &'static str> {
-        assert!(
-            seqs.len() == ids.len(),
-            "Number of sequences and IDs must be equal"
+    #[test]
+    fn test_read_filter() {
+        let reference = String::from_utf8(mt_seq()).unwrap();
+        let read = |start: usize, len: usize| &reference[start..start + len];
+
+        // A good read, a short read, a long read and a read of mean quality 2
+        let fastq = format!(
+            "@good\n{}\n+\n{}\n@short\n{}\n+\n{}\n@long\n{}\n+\n{}\n@low_quality\n{}\n+\n{}\n",
+            read(1000, 500),
+            "I".repeat(500),
+            read(3000, 100),
+            "I".repeat(100),
+            read(5000, 3000),
+            "I".repeat(3000),
+            read(9000, 500),
+            "#".repeat(500),
         );
-        assert!(!seqs.is_empty(), "Must have at least one sequence and ID");
+        let path = std::env::temp_dir().join("minimap2_test_read_filter.fq");
+        std::fs::write(&path, fastq).unwrap();
 
-        let seqs: Vec<std::ffi::CString> = seqs
-            .iter()
-            .map(|s| std::ffi::CString::new(s.clone()).expect("Invalid Sequence"))
-            .collect();
-        let ids: Vec<std::ffi::CString> = ids
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_read_filter(ReadFilter {
+                min_len: 200,
+                max_len: Some(2000),
+                min_mean_qual: Some(7.0),
+            })
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+
+        let mappings = aligner
+            .map_file(path.to_str().unwrap(), false, false)
+            .unwrap();
+        assert!(!mappings.is_empty());
+        assert!(mappings
             .iter()
-            .map(|s| std::ffi::CString::new(s.clone()).expect("Invalid ID"))
-            .collect();
+            .all(|m| m.query_name.as_deref().unwrap() == "good"));
+        let filtered = FilteredReads {
+            too_short: 1,
+            too_long: 1,
+            low_quality: 1,
+            duplicates: 0,
+        };
+        assert_eq!(aligner.filtered_reads(), filtered);
 
-        let idx = MaybeUninit::new(unsafe {
-            mm_idx_str(
-                self.idxopt.w as i32,
-                self.idxopt.k as i32,
-                (self.idxopt.flag & 1) as i32,
-                self.idxopt.bucket_bits as i32,
-                seqs.len() as i32,
-                seqs.as_ptr() as *mut *const libc::c_char,
-                ids.as_ptr() as *mut *const libc::c_char,
-            )
-        });
+        // The batch drivers skip the same reads, and the counts add up over files
+        let mut names = Vec::new();
+        let mut sink = |id: &[u8], _: &[Mapping]| -> Result<(), &'static str> {
+            names.push(id.to_vec());
+            Ok(())
+        };
+        aligner.map_file_to_sink(&path, &mut sink, 2).unwrap();
+        assert_eq!(names, [b"good".to_vec()]);
+        assert_eq!(aligner.filtered_reads().total(), 6);
 
-        let mm_idx = unsafe { idx.assume_init() };
-        self.idx = Some(Arc::new(mm_idx.into()));
+        std::fs::remove_file(&path).unwrap();
+    }
 
-        self.mapopt.mid_occ = 1000;
+    #[test]
+    fn test_dedup() {
+        let reference = String::from_utf8(mt_seq()).unwrap();
+        let read = |start: usize| &reference[start..start + 500];
+
+        // read1 is repeated with another comment, as in a FASTQ concatenated twice
+        let fastq = format!(
+            "@read1 runid=a\n{}\n+\n{}\n@read2\n{}\n+\n{}\n@read1 runid=b\n{}\n+\n{}\n",
+            read(1000),
+            "I".repeat(500),
+            read(5000),
+            "I".repeat(500),
+            read(1000),
+            "I".repeat(500),
+        );
+        let path = std::env::temp_dir().join("minimap2_test_dedup.fq");
+        std::fs::write(&path, fastq).unwrap();
 
-        let aln = Aligner {
-            idxopt: self.idxopt,
-            mapopt: self.mapopt,
-            threads: self.threads,
-            idx: self.idx,
-            idx_reader: None,
-            cigar_clipping: self.cigar_clipping,
-            state: Built,
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_dedup(dedup::HashReadIds::new())
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+
+        let mut names = Vec::new();
+        let mut sink = |id: &[u8], _: &[Mapping]| -> Result<(), &'static str> {
+            names.push(id.to_vec());
+            Ok(())
         };
+        aligner.map_file_to_sink(&path, &mut sink, 2).unwrap();
+        assert_eq!(names, [b"read1 runid=a".to_vec(), b"read2".to_vec()]);
+        assert_eq!(aligner.filtered_reads().duplicates, 1);
+
+        // The IDs seen are kept, so mapping the file again skips every read
+        let mappings = aligner
+            .map_file(path.to_str().unwrap(), false, false)
+            .unwrap();
+        assert!(mappings.is_empty());
+        assert_eq!(aligner.filtered_reads().duplicates, 4);
 
-        Ok(aln)
+        std::fs::remove_file(&path).unwrap();
     }
 
-    /// Applies an additional preset to the aligner
-    /// WARNING: This overwrites multiple other parameters. Make sure you know what you are doing
-    ///
-    /// Presets should be called before any other options are set, as they change multiple
-    /// options at once.
-    pub fn additional_preset(mut self, preset: Preset) -> Self {
-        unsafe { mm_set_opt(preset.into(), &mut self.idxopt, &mut self.mapopt) };
+    #[test]
+    fn test_effective_mapopt() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
 
-        self
+        let opt = aligner.effective_mapopt(None, None);
+        assert_eq!(opt.flag, aligner.mapopt.flag);
+        assert_eq!(opt.max_frag_len, aligner.mapopt.max_frag_len);
+
+        let opt = aligner.effective_mapopt(
+            Some(500),
+            Some(&[MM_F_NO_PRINT_2ND as u64, MM_F_FOR_ONLY as u64]),
+        );
+        assert_eq!(opt.max_frag_len, 500);
+        assert_eq!(
+            opt.flag,
+            aligner.mapopt.flag | MM_F_NO_PRINT_2ND as i64 | MM_F_FOR_ONLY as i64
+        );
+        assert_eq!(opt.bw, aligner.mapopt.bw);
+
+        // Per-call overrides do not change the builder settings
+        assert_eq!(aligner.mapopt.flag & MM_F_FOR_ONLY as i64, 0);
     }
-}
 
-impl Aligner<Built> {
-    /// Returns the number of sequences in the index
-    pub fn n_seq(&self) -> u32 {
-        unsafe {
-            // let idx = Arc::as_ptr(self.idx.as_ref().unwrap());
-            let idx: *const mm_idx_t = &(***self.idx.as_ref().unwrap());
-            (*idx).n_seq as u32
-        }
+    #[test]
+    fn test_mapq_cap_and_recalibration() {
+        let query = b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG";
+
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_max_mapq(10)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let mappings = aligner.map(query, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].mapq, 10);
+
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_mapq_recalibration(|m: &Mapping| m.mapq + 1)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let mappings = aligner.map(query, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].mapq, 30);
+
+        // The cap is applied after recalibration
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_mapq_recalibration(|_: &Mapping| 100)
+            .with_max_mapq(60)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let mappings = aligner.map(query, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].mapq, 60);
     }
 
-    /// Get sequences direct from the index
-    ///
-    /// Returns a reference to the sequence at the given index
-    /// Remainds valid as long as the aligner is valid
-    pub fn get_seq<'aln>(&'aln self, i: usize) -> Option<&'aln mm_idx_seq_t> {
-        unsafe {
-            // let idx = Arc::as_ptr(self.idx.as_ref().unwrap());
-            // let idx: *const mm_idx_t = *idx;
-            let idx: *const mm_idx_t = &(***self.idx.as_ref().unwrap());
-            // todo, should this be > or >=
-            if i > self.n_seq() as usize {
-                return None;
-            }
-            let seq = (*idx).seq;
-            let seq = seq.offset(i as isize);
-            let seq = &*seq;
-            Some(seq)
-        }
+    #[test]
+    fn test_nested_map() {
+        let query: &'static [u8] = b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG";
+
+        // The hook maps the query again, on the same thread and while the outer map() holds
+        // the thread local buffer
+        let inner = Arc::new(
+            Aligner::builder()
+                .map_ont()
+                .with_index("test_data/MT-human.fa", None)
+                .unwrap(),
+        );
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_mapq_recalibration(move |m: &Mapping| {
+                let nested = inner.map(query, false, false, None, None, None).unwrap();
+                assert_eq!(nested[0].target_start, m.target_start);
+                nested[0].mapq + 1
+            })
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let mappings = aligner.map(query, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].mapq, 30);
     }
 
-    // https://github.com/lh3/minimap2/blob/master/python/mappy.pyx#L164
-    // TODO: I doubt extra_flags is working properly...
-    // TODO: Python allows for paired-end mapping with seq2: Option<&[u8]>, but more work to implement
-    /// Aligns a given sequence (as bytes) to the index associated with this aligner
-    ///
-    /// Parameters:
-    /// seq: Sequence to align
-    /// cs: Whether to output CIGAR string
-    /// MD: Whether to output MD tag
-    /// max_frag_len: Maximum fragment length
-    /// extra_flags: Extra flags to pass to minimap2 as `Vec<u64>`
-    /// query_name: Name of the query sequence
-    pub fn map(
-        &self,
-        seq: &[u8],
-        cs: bool,
-        md: bool,
-        max_frag_len: Option<usize>,
-        extra_flags: Option<&[u64]>,
-        query_name: Option<&[u8]>,
-    ) -> Result<Vec<Mapping>, &'static str> {
-        // Make sure index is set
-        if !self.has_index() {
-            return Err("No index");
-        }
+    #[test]
+    fn test_write_fasta() {
+        let seq = "CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
+        let aligner = Aligner::builder()
+            .short()
+            .with_seq_and_id(seq.as_bytes(), b"contig1")
+            .unwrap();
 
-        // Make sure sequence is not empty
-        if seq.is_empty() {
-            return Err("Sequence is empty");
-        }
+        let path = std::env::temp_dir().join("minimap2_test_write_fasta.fa");
+        let fai_path = std::env::temp_dir().join("minimap2_test_write_fasta.fa.fai");
+        aligner.write_fasta(&path).unwrap();
 
-        let qname_cstring;
+        let fasta = std::fs::read_to_string(&path).unwrap();
+        let mut lines = fasta.lines();
+        assert_eq!(lines.next(), Some(">contig1"));
+        assert_eq!(lines.next().unwrap().len(), 60);
+        assert_eq!(lines.collect::<String>().len() + 60, seq.len());
+        assert_eq!(fasta.replace('\n', "")[8..], *seq);
 
-        let query_name_cstr: Option<&CStr> = match query_name {
-            None => None,
-            Some(qname_slice) => {
-                if qname_slice.last() != Some(&b'\0') {
-                    qname_cstring = Some(CString::new(qname_slice).expect("Invalid query name"));
-                    Some(qname_cstring.as_ref().unwrap().as_c_str())
-                } else {
-                    Some(
-                        CStr::from_bytes_with_nul(query_name.as_ref().unwrap().as_ref())
-                            .expect("Invalid query name"),
-                    )
-                }
-            }
-        };
+        let fai = std::fs::read_to_string(&fai_path).unwrap();
+        assert_eq!(fai, format!("contig1\t{}\t9\t60\t61\n", seq.len()));
 
-        let mut mm_reg: MaybeUninit<*mut mm_reg1_t> = MaybeUninit::uninit();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&fai_path).unwrap();
+    }
 
-        // Number of results
-        let mut n_regs: i32 = 0;
-        let mut map_opt = self.mapopt.clone();
+    #[test]
+    fn test_max_gap_and_intron_len() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_max_gap(1234)
+            .with_max_gap_ref(5678);
+        assert_eq!(aligner.mapopt.max_gap, 1234);
+        assert_eq!(aligner.mapopt.max_gap_ref, 5678);
+
+        let aligner = Aligner::builder().splice().with_max_intron_len(1000);
+        assert_eq!(aligner.mapopt.max_gap_ref, 1000);
+        assert_eq!(aligner.mapopt.bw, 1000);
+        assert_eq!(aligner.mapopt.bw_long, 1000);
+        aligner.check_opts().expect("Opts are invalid");
+    }
 
-        // if max_frag_len is not None: map_opt.max_frag_len = max_frag_len
-        if let Some(max_frag_len) = max_frag_len {
-            map_opt.max_frag_len = max_frag_len as i32;
-        }
+    #[test]
+    #[should_panic(expected = "Max intron length only applies to splice presets")]
+    fn test_max_intron_len_requires_splice() {
+        let _aligner = Aligner::builder().map_ont().with_max_intron_len(1000);
+    }
 
-        // if extra_flags is not None: map_opt.flag |= extra_flags
-        if let Some(extra_flags) = extra_flags {
-            for flag in extra_flags {
-                map_opt.flag |= *flag as i64;
-            }
-        }
+    #[test]
+    fn test_map_pair() {
+        let aligner = Aligner::builder()
+            .sr()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
 
-        let query_name_arc = query_name_cstr.map(|x| Arc::new(x.to_owned().into_string().unwrap()));
+        let chr1 = aligner.target_seq(0).unwrap();
+        let read1 = chr1[100..250].to_vec();
+        let read2 = revcomp(&chr1[400..550]);
 
-        let qname = match query_name_cstr {
-            None => std::ptr::null(),
-            Some(qname) => qname.as_ref().as_ptr() as *const ::std::os::raw::c_char,
-        };
+        let (mappings1, mappings2) = aligner
+            .map_pair(&read1, &read2, true, false, Some(b"pair"))
+            .unwrap();
 
-        let mappings = BUF.with_borrow_mut(|buf| {
-            let km: *mut c_void = unsafe { mm_tbuf_get_km(buf.get_buf()) };
+        let mate1 = mappings1.iter().find(|m| m.is_primary).unwrap();
+        let mate2 = mappings2.iter().find(|m| m.is_primary).unwrap();
 
-            mm_reg = MaybeUninit::new(unsafe {
-                mm_map(
-                    &**self.idx.as_ref().unwrap().as_ref() as *const mm_idx_t,
-                    seq.len() as i32,
-                    seq.as_ptr() as *const ::std::os::raw::c_char,
-                    &mut n_regs,
-                    buf.get_buf(),
-                    &map_opt,
-                    qname,
-                )
-            });
+        assert_eq!(mate1.target_name.as_deref().unwrap(), "chr1");
+        assert_eq!(mate1.strand, Strand::Forward);
+        assert_eq!((mate1.target_start, mate1.target_end), (100, 250));
+        assert_eq!((mate1.query_start, mate1.query_end), (0, 150));
+        assert_eq!(mate1.query_name.as_deref().unwrap(), "pair");
+        assert_eq!(
+            mate1.alignment.as_ref().unwrap().cs.as_deref(),
+            Some(":150")
+        );
 
-            let mut mappings = Vec::with_capacity(n_regs as usize);
+        assert_eq!(mate2.target_name.as_deref().unwrap(), "chr1");
+        assert_eq!(mate2.strand, Strand::Reverse);
+        assert_eq!((mate2.target_start, mate2.target_end), (400, 550));
+        assert_eq!((mate2.query_start, mate2.query_end), (0, 150));
+        assert_eq!(
+            mate2.alignment.as_ref().unwrap().cs.as_deref(),
+            Some(":150")
+        );
 
-            for i in 0..n_regs {
-                unsafe {
-                    let mm_reg1_mut_ptr = (*mm_reg.as_ptr()).offset(i as isize);
-                    let mm_reg1_const_ptr = mm_reg1_mut_ptr as *const mm_reg1_t;
-                    let reg: mm_reg1_t = *mm_reg1_mut_ptr;
-
-                    let idx = Arc::as_ptr(self.idx.as_ref().unwrap());
-                    let contig =
-                        std::ffi::CStr::from_ptr((*(**idx).seq.offset(reg.rid as isize)).name);
-
-                    let is_primary = reg.parent == reg.id && (reg.sam_pri() > 0);
-                    let is_supplementary = (reg.parent == reg.id) && (reg.sam_pri() == 0);
-
-                    // todo holy heck this code is ugly
-                    let alignment = if !reg.p.is_null() {
-                        let p = &*reg.p;
-
-                        // calculate the edit distance
-                        let nm = reg.blen - reg.mlen + p.n_ambi() as i32;
-                        let n_cigar = p.n_cigar;
-
-                        // Create a vector of the cigar blocks
-                        let (cigar, cigar_str) = if n_cigar > 0 {
-                            let mut cigar = p
-                                .cigar
-                                .as_slice(n_cigar as usize)
-                                .to_vec()
-                                .iter()
-                                .map(|c| ((c >> 4), (c & 0xf) as u8)) // unpack the length and op code
-                                .collect::<Vec<(u32, u8)>>();
-
-                            // Fix for adding in soft clipping cigar strings
-                            // Taken from minimap2 write_sam_cigar function
-                            // clip_len[0] = r->rev? qlen - r->qe : r->qs;
-                            // clip_len[1] = r->rev? r->qs : qlen - r->qe;
-
-                            let clip_len0 = if reg.rev() != 0 {
-                                seq.len() as i32 - reg.qe
-                            } else {
-                                reg.qs
-                            };
-
-                            let clip_len1 = if reg.rev() != 0 {
-                                reg.qs
-                            } else {
-                                seq.len() as i32 - reg.qe
-                            };
-
-                            let mut cigar_str = cigar
-                                .iter()
-                                .map(|(len, code)| {
-                                    let cigar_char = match code {
-                                        0 => "M",
-                                        1 => "I",
-                                        2 => "D",
-                                        3 => "N",
-                                        4 => "S",
-                                        5 => "H",
-                                        6 => "P",
-                                        7 => "=",
-                                        8 => "X",
-                                        _ => panic!("Invalid CIGAR code {code}"),
-                                    };
-                                    format!("{len}{cigar_char}")
-                                })
-                                .collect::<Vec<String>>()
-                                .join("");
-
-                            // int clip_char = (((sam_flag&0x800) || ((sam_flag&0x100) && (opt_flag&MM_F_SECONDARY_SEQ))) &&
-                            // !(opt_flag&MM_F_SOFTCLIP)) ? 'H' : 'S';
-
-                            // let clip_char = if (reg.flag & 0x800 != 0) || ((reg.flag & 0x100 != 0) && (map_opt.flag & 0x100 != 0)) && (map_opt.flag & 0x4 == 0) {
-                            // 'H'
-                            // } else {
-                            // 'S'
-                            // };
-
-                            // TODO: Support hard clipping
-                            let clip_char = 'S';
-
-                            // Pre and append soft clip identifiers to start and end
-                            if clip_len0 > 0 {
-                                cigar_str = format!("{}{}{}", clip_len0, clip_char, cigar_str);
-                                if self.cigar_clipping {
-                                    cigar.insert(0, (clip_len0 as u32, 4_u8));
-                                }
-                            }
-
-                            if clip_len1 > 0 {
-                                cigar_str = format!("{}{}{}", cigar_str, clip_len1, clip_char);
-                                if self.cigar_clipping {
-                                    cigar.push((clip_len1 as u32, 4_u8));
-                                }
-                            }
-
-                            (Some(cigar), Some(cigar_str))
-                        } else {
-                            (None, None)
-                        };
+        // Same results as mapping the mates one at a time, with pairing on top
+        let single = aligner.map(&read2, false, false, None, None, None).unwrap();
+        assert_eq!(single[0].strand, Strand::Reverse);
+        assert!(mate2.mapq >= single[0].mapq);
 
-                        let (cs_str, md_str) = if cs || md {
-                            // let idx: *const mm_idx_t = *Arc::as_ptr(self.idx.as_ref().unwrap());
-                            let idx: *const mm_idx_t = &(***self.idx.as_ref().unwrap());
-
-                            let cs_str = if cs {
-                                let mut cs_string: *mut libc::c_char = std::ptr::null_mut();
-                                let mut m_cs_string: libc::c_int = 0i32;
-
-                                // This solves a weird segfault...
-                                let km = km_init();
-
-                                let _cs_len = mm_gen_cs(
-                                    km,
-                                    &mut cs_string,
-                                    &mut m_cs_string,
-                                    idx,
-                                    mm_reg1_const_ptr,
-                                    seq.as_ptr() as *const libc::c_char,
-                                    true.into(),
-                                );
-
-                                let _cs_string = std::ffi::CStr::from_ptr(cs_string)
-                                    .to_str()
-                                    .unwrap()
-                                    .to_string();
-
-                                libc::free(cs_string as *mut c_void);
-                                km_destroy(km);
-                                Some(_cs_string)
-                            } else {
-                                None
-                            };
-
-                            let md_str = if md {
-                                let mut cs_string: *mut libc::c_char = std::ptr::null_mut();
-                                let mut m_cs_string: libc::c_int = 0i32;
-
-                                // This solves a weird segfault...
-                                let km = km_init();
-
-                                let _md_len = mm_gen_MD(
-                                    km,
-                                    &mut cs_string,
-                                    &mut m_cs_string,
-                                    idx,
-                                    mm_reg1_const_ptr,
-                                    seq.as_ptr() as *const libc::c_char,
-                                );
-                                let _md_string = std::ffi::CStr::from_ptr(cs_string)
-                                    .to_str()
-                                    .unwrap()
-                                    .to_string();
-
-                                libc::free(cs_string as *mut c_void);
-                                km_destroy(km);
-                                Some(_md_string)
-                            } else {
-                                None
-                            };
-
-                            (cs_str, md_str)
-                        } else {
-                            (None, None)
-                        };
+        assert_eq!(
+            aligner.map_pair(b"", &read2, false, false, None),
+            Err("Sequence is empty")
+        );
 
-                        Some(Alignment {
-                            nm,
-                            cigar,
-                            cigar_str,
-                            md: md_str,
-                            cs: cs_str,
-                            alignment_score: Some(p.dp_score as i32),
-                        })
-                    } else {
-                        None
-                    };
-
-                    let target_name_arc = Arc::new(
-                        std::ffi::CStr::from_ptr(contig.as_ptr())
-                            .to_str()
-                            .unwrap()
-                            .to_string(),
-                    );
-
-                    let target_len = (*(**idx).seq.offset(reg.rid as isize)).len as i32;
-
-                    mappings.push(Mapping {
-                        target_name: Some(Arc::clone(&target_name_arc)),
-                        target_len,
-                        target_start: reg.rs,
-                        target_end: reg.re,
-                        query_name: query_name_arc.clone(),
-                        query_len: NonZeroI32::new(seq.len() as i32),
-                        query_start: reg.qs,
-                        query_end: reg.qe,
-                        strand: if reg.rev() == 0 {
-                            Strand::Forward
-                        } else {
-                            Strand::Reverse
-                        },
-                        match_len: reg.mlen,
-                        block_len: reg.blen,
-                        mapq: reg.mapq(),
-                        is_primary,
-                        is_supplementary,
-                        alignment,
-                    });
-                    libc::free(reg.p as *mut c_void);
-                }
-            }
-            mappings
-        });
-        // free some stuff here
-        unsafe {
-            // Free mm_regs
-            let ptr: *mut mm_reg1_t = mm_reg.assume_init();
-            let c_void_ptr: *mut c_void = ptr as *mut c_void;
-            libc::free(c_void_ptr);
+        // Names that are not UTF-8 are converted lossily, names with a NUL byte are rejected
+        let (mappings1, mappings2) = aligner
+            .map_pair(&read1, &read2, false, false, Some(b"pair\xff"))
+            .unwrap();
+        for mapping in mappings1.iter().chain(&mappings2) {
+            assert_eq!(mapping.query_name.as_deref().unwrap(), "pair\u{fffd}");
         }
-        Ok(mappings)
+        let pairs = aligner
+            .map_pairs([(&b"pair\xff"[..], &read1[..], &read2[..])], false, false)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(pairs[0].name.as_deref().unwrap(), "pair\u{fffd}");
+        assert_eq!(
+            aligner.map_pair(&read1, &read2, false, false, Some(b"pa\0ir")),
+            Err("Invalid query name")
+        );
     }
 
-    /// Map entire file
-    /// Detects if file is gzip or not and if it's fastq/fasta or not
-    /// Best for smaller files (all results are stored in an accumulated Vec!)
-    /// What you probably want is to loop through the file yourself and use the map() function
-    ///
-    /// TODO: Remove cs and md and make them options on the struct
-    ///
-    #[cfg(feature = "map-file")]
-    pub fn map_file(&self, file: &str, cs: bool, md: bool) -> Result<Vec<Mapping>, &'static str> {
-        // Make sure index is set
-        if self.idx.is_none() {
-            return Err("No index");
-        }
+    #[test]
+    fn test_map_pair_cli() {
+        // The pairs of test_data/pairs_{1,2}.fq, to compare with
+        // `minimap2 -ax sr test_data/genome.fa test_data/pairs_1.fq test_data/pairs_2.fq`.
+        // The second mate of "repeat" lies in a 65 bp inverted repeat of chr1 (566..631 and
+        // 729..794), so only the orientation of its partner decides where it goes.
+        let aligner = Aligner::builder()
+            .sr()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
 
-        // Check that file exists
-        if !Path::new(file).exists() {
-            return Err("File does not exist");
+        let read = |path: &str| {
+            let mut reader = needletail::parse_fastx_file(path).unwrap();
+            let mut reads = Vec::new();
+            while let Some(record) = reader.next() {
+                let record = record.unwrap();
+                reads.push((record.id().to_vec(), record.seq().into_owned()));
+            }
+            reads
+        };
+        let mates1 = read("test_data/pairs_1.fq");
+        let mates2 = read("test_data/pairs_2.fq");
+
+        // Per mate: 0-based position (SAM POS - 1), strand (flag 16) and MAPQ range
+        let expected = [
+            (
+                &b"unique"[..],
+                (100, Strand::Forward, 60..=60),
+                (400, Strand::Reverse, 60..=60),
+            ),
+            (
+                b"repeat",
+                (300, Strand::Forward, 60..=60),
+                (566, Strand::Reverse, 1..=60),
+            ),
+        ];
+        assert_eq!(mates1.len(), expected.len());
+        for (((name, seq1), (name2, seq2)), (expected_name, expected1, expected2)) in
+            mates1.iter().zip(&mates2).zip(expected)
+        {
+            assert_eq!((&name[..], &name2[..]), (expected_name, expected_name));
+            let (mappings1, mappings2) = aligner
+                .map_pair(seq1, seq2, false, false, Some(&name[..]))
+                .unwrap();
+            for (mappings, (start, strand, mapq)) in
+                [(mappings1, expected1), (mappings2, expected2)]
+            {
+                // Primary (no flag 256) and not supplementary (no flag 2048)
+                let primary: Vec<_> = mappings.iter().filter(|m| m.is_primary).collect();
+                assert_eq!(primary.len(), 1);
+                let mapping = primary[0];
+                assert!(!mapping.is_supplementary);
+                assert_eq!(mapping.target_name.as_deref().unwrap(), "chr1");
+                assert_eq!((mapping.target_start, mapping.strand), (start, strand));
+                assert!(mapq.contains(&mapping.mapq), "MAPQ {}", mapping.mapq);
+            }
         }
 
-        // Check that file isn't empty...
-        let metadata = std::fs::metadata(file).unwrap();
-        if metadata.len() == 0 {
-            return Err("File is empty");
-        }
+        // Alone, the repeat mate maps equally well to both copies
+        let single = aligner
+            .map(&mates2[1].1, false, false, None, None, None)
+            .unwrap();
+        assert_eq!(single.iter().find(|m| m.is_primary).unwrap().mapq, 0);
+    }
 
-        let mut reader = parse_fastx_file(file).expect("Unable to read FASTA/X file");
+    #[test]
+    fn test_map_batch_matches_map() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
 
-        // The output vec
-        let mut mappings = Vec::new();
+        let chr1 = aligner.target_seq(0).unwrap();
+        let reads = [
+            (b"fwd".to_vec(), chr1[10..400].to_vec()),
+            (b"empty".to_vec(), Vec::new()),
+            (b"rev".to_vec(), revcomp(&chr1[600..1100])),
+        ];
 
-        // Iterate over the sequences
-        while let Some(record) = reader.next() {
-            let record = match record {
-                Ok(record) => record,
-                Err(_) => {
-                    return Err("Error reading record in FASTA/X files. Please confirm integrity.")
-                }
-            };
+        let batch = aligner
+            .map_batch(reads.iter().map(|(n, s)| (&n[..], &s[..])), true, true)
+            .unwrap();
+        assert_eq!(batch.n_queries(), 3);
+        assert_eq!(batch.query_mappings(1).count(), 0);
 
-            let query_name = record.id().to_vec();
-            let mut seq_mappings = self
-                .map(&record.seq(), cs, md, None, None, Some(&query_name))
+        for (i, (name, seq)) in reads.iter().enumerate() {
+            if seq.is_empty() {
+                continue;
+            }
+            let expected = aligner
+                .map(seq, true, true, None, None, Some(name))
                 .unwrap();
+            let mappings: Vec<Mapping> = batch.query_mappings(i).map(|m| m.to_mapping()).collect();
+            assert!(!mappings.is_empty());
+            assert_eq!(mappings, expected);
+        }
+        assert_eq!(batch.len(), batch.iter().count());
+    }
 
-            for mapping in seq_mappings.iter_mut() {
-                let id = record.id();
-                if id.is_empty() {
-                    mapping.query_name = Some(Arc::new(
-                        format!("Unnamed Seq with Length: {}", record.seq().len()).to_string(),
-                    ));
-                }
-            }
+    #[test]
+    fn test_map_batch_names() {
+        // All-vs-all, where the name of a query skips its hit to itself
+        let aligner = Aligner::builder()
+            .ava_ont()
+            .with_index("test_data/gDNA_reads.fq", None)
+            .unwrap();
+        let fastq = std::fs::read_to_string("test_data/gDNA_reads.fq").unwrap();
+        let lines: Vec<&str> = fastq.lines().collect();
+        let reads: Vec<(&[u8], &[u8])> = lines
+            .chunks(4)
+            .map(|read| (read[0][1..].as_bytes(), read[1].as_bytes()))
+            .collect();
 
-            mappings.extend(seq_mappings);
+        let batch = aligner
+            .map_batch(reads.iter().copied(), false, false)
+            .unwrap();
+        let mut self_hits = 0;
+        for (i, &(name, seq)) in reads.iter().enumerate() {
+            let expected = aligner
+                .map(seq, false, false, None, None, Some(name))
+                .unwrap();
+            let mappings: Vec<Mapping> = batch.query_mappings(i).map(|m| m.to_mapping()).collect();
+            assert_eq!(mappings, expected);
+            let is_self = |m: &Mapping| m.target_name.as_deref() == m.query_name.as_deref();
+            assert!(!mappings.iter().any(is_self));
+            let unnamed = aligner.map(seq, false, false, None, None, None).unwrap();
+            self_hits += unnamed
+                .iter()
+                .filter(|m| m.target_name.as_deref().map(|t| t.as_bytes()) == Some(name))
+                .count();
         }
-
-        Ok(mappings)
+        assert!(self_hits > 0);
     }
 
-    // This is in the python module, so copied here...
-    pub fn has_index(&self) -> bool {
-        self.idx.is_some()
+    #[test]
+    fn test_map_batch_unnamed_targets() {
+        // Targets of an index built without names are named "", as by map
+        let mut builder = Aligner::builder().map_ont();
+        builder.idxopt.flag |= MM_I_NO_NAME as i16;
+        let aligner = builder.with_index("test_data/genome.fa", None).unwrap();
+
+        let chr1 = aligner.target_seq(0).unwrap();
+        let seq = &chr1[100..600];
+        let batch = aligner
+            .map_batch([(&b"read"[..], seq)], false, false)
+            .unwrap();
+        let expected = aligner
+            .map(seq, false, false, None, None, Some(b"read"))
+            .unwrap();
+        assert!(!expected.is_empty());
+        assert_eq!(expected[0].target_name.as_deref().unwrap(), "");
+        let mappings: Vec<Mapping> = batch.query_mappings(0).map(|m| m.to_mapping()).collect();
+        assert_eq!(mappings, expected);
+        assert_eq!(batch.query_mappings(0).next().unwrap().target_name, "");
     }
-}
 
-mod send {
-    use super::{Aligner, Built, PresetSet, Unset};
+    #[test]
+    fn test_map_parallel() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
 
-    unsafe impl Sync for Aligner<Unset> {}
-    unsafe impl Send for Aligner<Unset> {}
-    unsafe impl Sync for Aligner<Built> {}
-    unsafe impl Send for Aligner<Built> {}
-    unsafe impl Sync for Aligner<PresetSet> {}
-    unsafe impl Send for Aligner<PresetSet> {}
-}
+        let chr1 = aligner.target_seq(0).unwrap();
+        // More than a batch, so results come back from several workers
+        let reads: Vec<(String, Vec<u8>)> = (0..batch::DEFAULT_BATCH_SIZE + 10)
+            .map(|i| {
+                let start = (i * 7) % (chr1.len() - 400);
+                let seq = chr1[start..start + 300].to_vec();
+                let seq = if i % 2 == 0 { seq } else { revcomp(&seq) };
+                (format!("read{i}"), seq)
+            })
+            .collect();
 
-#[derive(PartialEq, Eq)]
-pub enum FileFormat {
-    FASTA,
-    FASTQ,
-}
+        let mappings = aligner
+            .map_parallel(reads.iter().map(|(n, s)| (n, s)), true, false, 4)
+            .unwrap();
+        assert_eq!(mappings.len(), reads.len());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Same results, in input order, as mapping on the calling thread
+        for ((name, seq), mappings) in reads.iter().zip(&mappings).step_by(97) {
+            let expected = aligner
+                .map(seq, true, false, None, None, Some(name.as_bytes()))
+                .unwrap();
+            assert!(!mappings.is_empty());
+            assert_eq!(mappings, &expected);
+            assert_eq!(
+                mappings[0].query_name.as_deref().unwrap().as_bytes(),
+                name.as_bytes()
+            );
+        }
 
-    #[test]
-    fn aligner_between_threads() {
-        // Because I'm not sure how this will work with FFI + Threads, want a sanity check
-        use std::thread;
+        assert_eq!(
+            aligner.map_parallel([("empty", "")], false, false, 2),
+            Err("Sequence is empty")
+        );
+    }
 
+    #[test]
+    fn test_hits_outside_index() {
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(2)
-            .with_index("yeast_ref.mmi", None)
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
             .unwrap();
+        let read = aligner
+            .fetch_subseq("chr2", 100..400, Strand::Forward)
+            .unwrap();
+        assert!(!aligner
+            .map(&read, false, false, None, None, None)
+            .unwrap()
+            .is_empty());
+
+        // Hide chr2 from the sequence table, as a corrupted index might, so its hits point
+        // past the end
+        let idx = aligner.idx.as_ref().unwrap().idx;
+        unsafe { (*idx).n_seq = 1 };
+        let result = aligner.map(&read, false, false, None, None, None);
+        let batch = aligner
+            .map_batch([(&b"read"[..], &read[..])], false, false)
+            .err();
+        unsafe { (*idx).n_seq = 2 };
+
+        assert_eq!(result, Err("Hit on a target outside the index"));
+        assert_eq!(batch, Some("Hit on a target outside the index"));
+    }
 
-        aligner
-            .map(
-                "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(),
-                false,
-                false,
-                None,
-                None,
-                Some(b"Sample Query")
-            )
+    #[test]
+    fn test_scoped_map() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
             .unwrap();
-        let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-        assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
 
-        let jh = thread::spawn(move || {
-            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
-            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
-            aligner
+        // Reads borrowed from one buffer
+        let chr1 = aligner.target_seq(0).unwrap();
+        let names: Vec<String> = (0..batch::DEFAULT_BATCH_SIZE + 10)
+            .map(|i| format!("read{i}"))
+            .collect();
+        let reads: Vec<(&str, &[u8])> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let start = (i * 7) % (chr1.len() - 400);
+                (name.as_str(), &chr1[start..start + 300])
+            })
+            .collect();
+
+        let mut results = Vec::new();
+        std::thread::scope(|scope| {
+            let sink = |name: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+                results.push((name.to_vec(), mappings.to_vec()));
+                Ok(())
+            };
+            let handle = aligner.scoped_map(scope, reads.iter().copied(), 4, sink);
+            handle.join().unwrap().unwrap();
         });
 
-        let aligner = jh.join().unwrap();
+        let expected = aligner
+            .map_parallel(reads.iter().copied(), false, false, 2)
+            .unwrap();
+        assert_eq!(results.len(), reads.len());
+        for (((name, _), (id, mappings)), expected) in reads.iter().zip(&results).zip(&expected) {
+            assert_eq!(id, name.as_bytes());
+            assert_eq!(mappings, expected);
+        }
 
-        let jh = thread::spawn(move || {
-            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
-            let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
+        // A sink error stops mapping and is returned by the handle
+        let result = std::thread::scope(|scope| {
+            let sink = |_: &[u8], _: &[Mapping]| -> Result<(), &'static str> { Err("Sink full") };
             aligner
+                .scoped_map(scope, reads.iter().copied(), 2, sink)
+                .join()
+                .unwrap()
         });
-
-        let _aligner = jh.join().unwrap();
+        assert_eq!(result, Err("Sink full"));
     }
 
     #[test]
-    fn shared_aligner() {
-        // Because I'm not sure how this will work with FFI + Threads, want a sanity check
-        use std::sync::Arc;
-        use std::thread;
+    fn test_mappings_outlive_aligner() {
+        fn assert_owned<T: Send + Sync + 'static>(_: &T) {}
 
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(2)
-            .with_index("yeast_ref.mmi", None)
+            .map_ont()
+            .with_cigar()
+            .with_target_seq()
+            .with_index("test_data/genome.fa", None)
             .unwrap();
+        let read = aligner
+            .fetch_subseq("chr1", 100..600, Strand::Reverse)
+            .unwrap();
+        let clone = aligner.clone();
 
-        let aligner = Arc::new(aligner);
+        let mappings = aligner.map(&read, true, true, None, None, None).unwrap();
+        let batch = clone
+            .map_batch([(&b"read"[..], &read[..])], true, true)
+            .unwrap();
+        let copied: Vec<Mapping> = batch.iter().map(|m| m.to_mapping()).collect();
+        assert_owned(&mappings);
 
-        aligner
-            .map(
-                "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(),
-                false,
-                false,
-                None,
-                None,
-                Some(b"Sample Query")
-            )
+        // Drop the batch, then each aligner sharing the index, then use the results
+        drop(batch);
+        drop(aligner);
+        let later = clone.map(&read, true, true, None, None, None).unwrap();
+        drop(clone);
+
+        for mappings in [&mappings, &copied, &later] {
+            let hit = &mappings[0];
+            assert_eq!(hit.target_name.as_deref().unwrap(), "chr1");
+            assert_eq!((hit.target_start, hit.target_end), (100, 600));
+            let aln = hit.alignment.as_ref().unwrap();
+            assert_eq!(aln.cigar_str.as_deref(), Some("500M"));
+            assert_eq!(aln.md.as_deref(), Some("500"));
+            assert_eq!(hit.target_seq.as_ref().unwrap().len(), 500);
+        }
+        assert_eq!(mappings, later);
+    }
+
+    #[test]
+    fn test_target_seq() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_target_seq()
+            .with_index("test_data/genome.fa", None)
             .unwrap();
-        let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-        assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
 
-        let aligner_handle = Arc::clone(&aligner);
-        let jh0 = thread::spawn(move || {
-            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
-            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
-        });
+        let chr1 = aligner.target_seq(0).unwrap();
+        let query = revcomp(&chr1[600..1100]);
 
-        jh0.join().unwrap();
+        let mappings = aligner.map(&query, false, false, None, None, None).unwrap();
+        let hit = &mappings[0];
+        assert_eq!(hit.strand, Strand::Reverse);
+        assert_eq!(
+            hit.target_seq.as_deref(),
+            Some(&chr1[hit.target_start as usize..hit.target_end as usize])
+        );
 
-        let aligner_handle = Arc::clone(&aligner);
-        let jh1 = thread::spawn(move || {
-            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
-            let mappings = aligner_handle.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-            assert!(mappings[0].query_len == Some(NonZeroI32::new(350).unwrap()));
-        });
+        let batch = aligner
+            .map_batch([(&b"q"[..], &query[..])], false, false)
+            .unwrap();
+        assert_eq!(
+            batch.iter().next().unwrap().target_seq,
+            hit.target_seq.as_deref()
+        );
 
-        jh1.join().unwrap();
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let mappings = aligner.map(&query, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_seq, None);
     }
 
     #[test]
-    fn rayon() {
-        // Because I'm not sure how this will work with FFI + Threads, want a sanity check
-        use rayon::prelude::*;
-
+    fn test_fetch_subseq() {
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(2)
-            .with_cigar()
-            .with_index("yeast_ref.mmi", None)
+            .with_index("test_data/genome.fa", None)
             .unwrap();
+        let chr2 = aligner.target_seq(1).unwrap();
+        assert_eq!(chr2.len(), 460);
 
-        let sequences = vec![
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA",
-            "GTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGG",
-            "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAG",
-        ];
+        // Boundaries are end-exclusive
+        assert_eq!(
+            aligner
+                .fetch_subseq("chr2", 0..460, Strand::Forward)
+                .unwrap(),
+            chr2
+        );
+        assert_eq!(
+            aligner
+                .fetch_subseq("chr2", 459..460, Strand::Forward)
+                .unwrap(),
+            &chr2[459..]
+        );
+        assert_eq!(
+            aligner
+                .fetch_subseq("chr2", 460..460, Strand::Reverse)
+                .unwrap(),
+            b""
+        );
+        assert_eq!(
+            aligner
+                .fetch_subseq_by_id(1, 10..20, Strand::Reverse)
+                .unwrap(),
+            seq::revcomp(&chr2[10..20])
+        );
 
-        let _results = sequences
-            .par_iter()
-            .map(|seq| {
-                aligner
-                    .map(
-                        seq.as_bytes(),
-                        false,
-                        false,
-                        None,
-                        None,
-                        Some(b"Sample Query"),
-                    )
-                    .unwrap()
+        assert_eq!(
+            aligner.fetch_subseq("chr2", 455..461, Strand::Forward),
+            Err(FetchError::OutOfRange {
+                start: 455,
+                end: 461,
+                target_len: 460
             })
-            .collect::<Vec<_>>();
+        );
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 20..10;
+        assert!(matches!(
+            aligner.fetch_subseq("chr2", reversed, Strand::Forward),
+            Err(FetchError::OutOfRange { .. })
+        ));
+        assert_eq!(
+            aligner.fetch_subseq("chr3", 0..10, Strand::Forward),
+            Err(FetchError::TargetNotFound)
+        );
+        assert_eq!(
+            aligner.fetch_subseq_by_id(2, 0..10, Strand::Forward),
+            Err(FetchError::TargetNotFound)
+        );
+        assert_eq!(aligner.target_id("chr2"), Some(1));
     }
 
     #[test]
-    fn does_it_work() {
-        let mut mm_idxopt = MaybeUninit::uninit();
-        let mut mm_mapopt = MaybeUninit::uninit();
+    fn test_map_with_diagnostics() {
+        let mt = mt_seq();
+        let base = &mt[0..3000];
+
+        // Five copies of the target with a SNP every 200 bp, at different offsets
+        let mut seqs = vec![base.to_vec()];
+        let mut ids = vec![b"t0".to_vec()];
+        for copy in 1..6 {
+            let mut seq = base.to_vec();
+            for i in (copy * 30..seq.len()).step_by(200) {
+                seq[i] = if seq[i] == b'A' { b'C' } else { b'A' };
+            }
+            seqs.push(seq);
+            ids.push(format!("t{copy}").into_bytes());
+        }
+        let mut aligner = Aligner::builder()
+            .map_ont()
+            .with_seqs_and_ids(&seqs, &ids)
+            .unwrap();
+        let query = &base[500..2500];
 
-        unsafe { mm_set_opt(&0, mm_idxopt.as_mut_ptr(), mm_mapopt.as_mut_ptr()) };
+        // The copies are close enough for pri_ratio, but only two are kept
+        aligner.mapopt.best_n = 2;
+        aligner.mapopt.pri_ratio = 0.5;
+        let (mappings, diagnostics) = aligner
+            .map_with_diagnostics(query, false, false, None, None, None)
+            .unwrap();
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(diagnostics.summary.n_secondary, 2);
+        assert_eq!(diagnostics.n_candidates, 6);
+        assert_eq!(
+            (diagnostics.below_pri_ratio, diagnostics.past_best_n),
+            (0, 3)
+        );
+        assert!(diagnostics.best_dropped_score.is_some());
+
+        // With pri_ratio 1, only copies scoring within 2k of the primary are kept
+        aligner.mapopt.best_n = 5;
+        aligner.mapopt.pri_ratio = 1.0;
+        let (_, diagnostics) = aligner
+            .map_with_diagnostics(query, false, false, None, None, None)
+            .unwrap();
+        assert_eq!(diagnostics.past_best_n, 0);
+        assert!(diagnostics.below_pri_ratio > 0);
+        assert_eq!(
+            diagnostics.summary.n_secondary + diagnostics.below_pri_ratio,
+            5
+        );
     }
 
     #[test]
-    fn idxopt() {
-        let _x: IdxOpt = Default::default();
+    fn test_max_qlen() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_max_qlen(1000)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let mt = mt_seq();
+        let seq = &mt[2000..3500];
+
+        assert_eq!(
+            aligner.check_query_len(seq.len()),
+            Err(QueryTooLong {
+                len: 1500,
+                max_len: 1000
+            })
+        );
+        assert_eq!(
+            aligner.map(seq, false, false, None, None, None),
+            Err("Query is longer than max_qlen")
+        );
+        assert!(!aligner
+            .map(&seq[..1000], false, false, None, None, None)
+            .unwrap()
+            .is_empty());
+
+        // Pairs are checked on the length of both mates
+        assert_eq!(
+            aligner.map_pair(&seq[..600], &seq[900..], false, false, None),
+            Err("Query is longer than max_qlen")
+        );
+
+        let batch = aligner.map_batch([(&b"read"[..], seq)], false, false);
+        assert_eq!(batch.err(), Some("Query is longer than max_qlen"));
     }
 
     #[test]
-    fn mapopt() {
-        let _x: mm_mapopt_t = Default::default();
-        let _y: MapOpt = Default::default();
+    fn test_hit_order() {
+        let mt = mt_seq();
+        let seq = mt[3000..5000].to_vec();
+        // Three copies of the query, the first and last with a few SNPs
+        let mutate = |n: usize| {
+            let mut copy = seq.clone();
+            for i in (100..copy.len()).step_by(2000 / n) {
+                copy[i] = if copy[i] == b'A' { b'C' } else { b'A' };
+            }
+            copy
+        };
+        let targets = [mutate(4), seq.clone(), mutate(8)];
+        let names = [b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        let map = |order| {
+            Aligner::builder()
+                .map_ont()
+                .with_cigar()
+                .with_hit_order(order)
+                .with_seqs_and_ids(&targets, &names)
+                .unwrap()
+                .map(&seq, false, false, None, None, None)
+                .unwrap()
+        };
+        let target_names = |mappings: &[Mapping]| -> Vec<String> {
+            mappings
+                .iter()
+                .map(|m| m.target_name.as_deref().unwrap().clone())
+                .collect()
+        };
+
+        let mappings = map(HitOrder::Minimap2);
+        assert_eq!(mappings.len(), 3);
+
+        let by_target = map(HitOrder::TargetCoordinate);
+        assert_eq!(target_names(&by_target), ["a", "b", "c"]);
+
+        let by_score = map(HitOrder::Score);
+        assert_eq!(target_names(&by_score), ["b", "c", "a"]);
+        assert!(by_score[0].is_primary);
+
+        let primary_first = map(HitOrder::PrimaryFirst);
+        assert!(primary_first[0].is_primary);
+        assert!(primary_first[1..].iter().all(|m| !m.is_primary));
+
+        // Each order holds the same mappings
+        for mut other in [mappings, by_score, primary_first] {
+            other.sort_by_key(|m| m.target_name.clone());
+            assert_eq!(other, by_target);
+        }
     }
 
     #[test]
-    fn aligner_build_manually() {
-        let idxopt: IdxOpt = Default::default();
+    fn test_max_hits() {
+        // Five copies of a repeat separated by unique spacers
+        let repeat = random_seq(500, 7);
+        let mut reference = random_seq(1000, 8);
+        for seed in 9..14 {
+            reference.extend_from_slice(&repeat);
+            reference.extend(random_seq(1000, seed));
+        }
+        let ids = [b"repeats".to_vec()];
 
-        let mapopt: MapOpt = Default::default();
+        let map = |aligner: Aligner<Built>| {
+            aligner
+                .map_with_summary(&repeat, false, false, None, None, Some(b"read"))
+                .unwrap()
+        };
 
-        let threads = 1;
-        let idx = None;
-        let idx_reader = None;
+        let (mappings, summary) = map(Aligner::builder()
+            .map_ont()
+            .with_seqs_and_ids(&[reference.clone()], &ids)
+            .unwrap());
+        assert_eq!(mappings.len(), 5);
+        assert_eq!((summary.n_primary, summary.n_secondary), (1, 4));
+        assert_eq!(summary.n_hits(), 5);
+        assert!(!summary.truncated);
+
+        let (mappings, summary) = map(Aligner::builder()
+            .map_ont()
+            .with_max_secondary(2)
+            .with_seqs_and_ids(&[reference.clone()], &ids)
+            .unwrap());
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(summary.n_secondary, 2);
+
+        // Warn only
+        let (mappings, summary) = map(Aligner::builder()
+            .map_ont()
+            .with_max_hits(2, false)
+            .with_seqs_and_ids(&[reference.clone()], &ids)
+            .unwrap());
+        assert_eq!(mappings.len(), 5);
+        assert!(!summary.truncated);
 
-        let _aligner = Aligner {
-            idxopt,
-            mapopt,
-            threads,
-            idx,
-            idx_reader,
-            cigar_clipping: false,
-            state: Unset,
-        };
+        let (mappings, summary) = map(Aligner::builder()
+            .map_ont()
+            .with_max_hits(2, true)
+            .with_seqs_and_ids(&[reference], &ids)
+            .unwrap());
+        assert_eq!(mappings.len(), 2);
+        assert!(mappings[0].is_primary);
+        assert_eq!(summary.n_hits(), 5);
+        assert!(summary.truncated);
     }
 
     #[test]
-    fn test_mapopt_flags_in_aligner() {
-        let mut aligner = Aligner::builder();
-        aligner.mapopt.set_no_qual();
+    fn test_index_params() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_kmer(17)
+            .with_window(11)
+            .with_bucket_bits(16)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
         assert_eq!(
-            aligner.mapopt.flag & MM_F_NO_QUAL as i64,
-            MM_F_NO_QUAL as i64
+            (
+                aligner.idxopt.k,
+                aligner.idxopt.w,
+                aligner.idxopt.bucket_bits
+            ),
+            (17, 11, 16)
         );
-        aligner.mapopt.unset_no_qual();
-        assert_eq!(aligner.mapopt.flag & MM_F_NO_QUAL as i64, 0_i64);
-    }
-
-    #[test]
-    fn test_idxopt_flags_in_aligner() {
-        let mut aligner = Aligner::builder();
-        aligner.idxopt.set_hpc();
-        assert_eq!(aligner.idxopt.flag & MM_I_HPC as i16, MM_I_HPC as i16);
-        aligner.idxopt.unset_hpc();
-        assert_eq!(aligner.idxopt.flag & MM_I_HPC as i16, 0_i16);
-    }
+        let idx: &mm_idx_t = aligner.idx.as_ref().unwrap();
+        assert_eq!((idx.k, idx.w), (17, 11));
 
-    #[test]
-    fn aligner_builder() {
-        let _result = Aligner::builder();
+        let read = aligner
+            .fetch_subseq("MT_human", 2000..3000, Strand::Forward)
+            .unwrap();
+        let mappings = aligner.map(&read, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_start, 2000);
     }
 
     #[test]
-    fn aligner_builder_preset() {
-        let _result = Aligner::builder().preset(Preset::LrHq);
+    #[should_panic(expected = "k must be between 1 and 28")]
+    fn test_kmer_too_large() {
+        Aligner::builder().map_ont().with_kmer(32);
     }
 
     #[test]
-    fn aligner_builder_preset_with_threads() {
-        let _result = Aligner::builder()
-            .preset(Preset::LrHq)
-            .with_index_threads(1);
+    #[should_panic(expected = "w must be between 1 and 255")]
+    fn test_window_too_large() {
+        Aligner::builder().map_ont().with_window(256);
     }
 
     #[test]
-    fn create_index_file_missing() {
-        let result = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(1)
-            .with_index(
-                "test_data/test.fa_FILE_NOT_FOUND",
-                Some("test_FILE_NOT_FOUND.mmi"),
-            );
-        assert!(result.is_err());
+    #[should_panic(expected = "bucket_bits must be between 1 and 32")]
+    fn test_bucket_bits_zero() {
+        Aligner::builder().map_ont().with_bucket_bits(0);
     }
 
     #[test]
-    fn create_index() {
+    fn test_occurrence_filters() {
+        // A fixed threshold is kept when the index is loaded
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(1);
-
-        println!("{}", aligner.idxopt.w);
+            .map_ont()
+            .with_mid_occ(500)
+            .with_max_occ(5000)
+            .with_occ_dist(1000, 10000)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        assert_eq!(aligner.mapopt.mid_occ, 500);
+        assert_eq!(aligner.mapopt.max_occ, 5000);
+        assert_eq!(
+            (aligner.mapopt.occ_dist, aligner.mapopt.max_max_occ),
+            (1000, 10000)
+        );
 
-        assert!(aligner.idxopt.w == 10);
+        // A computed threshold is clamped to the range
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_mid_occ(500)
+            .with_mid_occ_frac(0.001)
+            .with_mid_occ_range(50, 60)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        assert_eq!(aligner.mapopt.mid_occ_frac, 0.001);
+        assert!((50..=60).contains(&aligner.mapopt.mid_occ));
 
-        aligner
-            .with_index("test_data/test_data.fasta", Some("test.mmi"))
+        let read = aligner
+            .fetch_subseq("MT_human", 5000..6000, Strand::Forward)
             .unwrap();
+        let mappings = aligner.map(&read, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_start, 5000);
     }
 
     #[test]
-    fn test_builder() {
-        let _aligner = Aligner::builder().preset(Preset::MapOnt);
+    #[should_panic(expected = "mid_occ_frac must be between 0 and 1")]
+    fn test_mid_occ_frac_out_of_range() {
+        Aligner::builder().map_ont().with_mid_occ_frac(1.5);
     }
 
     #[test]
-    fn test_mapping() {
+    fn test_max_sw_mat() {
+        let reference = mt_seq();
+        // 300 bp deletion in the middle of the read
+        let mut query = reference[1000..4000].to_vec();
+        query.drain(1500..1800);
+
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(2)
-            .with_index("yeast_ref.mmi", None)
+            .map_ont()
+            .with_cigar()
+            .with_index("test_data/MT-human.fa", None)
             .unwrap();
+        let mappings = aligner.map(&query, false, false, None, None, None).unwrap();
+        assert_eq!(mappings.len(), 1);
+        let aln = mappings[0].alignment.as_ref().unwrap();
+        assert_eq!(aln.dp_skipped, DpSkipped::None);
+        assert!(aln.cigar.as_ref().unwrap().contains(&(300, 2)));
 
-        aligner
-            .map(
-                "ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(),
-                false,
-                false,
-                None,
-                None,
-                Some(b"Sample Query")
-            )
+        // The gap no longer fits in the DP matrix: the read is split around the deletion
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_max_sw_mat(5_000)
+            .with_index("test_data/MT-human.fa", None)
             .unwrap();
-        let mappings = aligner.map("ACGGTAGAGAGGAAGAAGAAGGAATAGCGGACTTGTGTATTTTATCGTCATTCGTGGTTATCATATAGTTTATTGATTTGAAGACTACGTAAGTAATTTGAGGACTGATTAAAATTTTCTTTTTTAGCTTAGAGTCAATTAAAGAGGGCAAAATTTTCTCAAAAGACCATGGTGCATATGACGATAGCTTTAGTAGTATGGATTGGGCTCTTCTTTCATGGATGTTATTCAGAAGGAGTGATATATCGAGGTGTTTGAAACACCAGCGACACCAGAAGGCTGTGGATGTTAAATCGTAGAACCTATAGACGAGTTCTAAAATATACTTTGGGGTTTTCAGCGATGCAAAA".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-        println!("{:#?}", mappings);
+        assert_eq!(aligner.mapopt.max_sw_mat, 5_000);
+        let mappings = aligner.map(&query, false, false, None, None, None).unwrap();
+        let mut skipped: Vec<(i32, DpSkipped)> = mappings
+            .iter()
+            .map(|m| (m.target_start, m.alignment.as_ref().unwrap().dp_skipped))
+            .collect();
+        skipped.sort_by_key(|(start, _)| *start);
+        assert!(skipped.len() >= 2);
+        assert_eq!(skipped.first().unwrap().1, DpSkipped::End);
+        assert_eq!(skipped.last().unwrap().1, DpSkipped::Start);
+        assert!(mappings.iter().all(|m| !m
+            .alignment
+            .as_ref()
+            .unwrap()
+            .cigar
+            .as_ref()
+            .unwrap()
+            .contains(&(300, 2))));
+
+        let aligner = Aligner::builder().map_ont().with_long_join(false);
+        assert_ne!(aligner.mapopt.flag & MM_F_NO_LJOIN as i64, 0);
+        let aligner = aligner.with_long_join(true);
+        assert_eq!(aligner.mapopt.flag & MM_F_NO_LJOIN as i64, 0);
+    }
 
-        // This should be reverse strand
-        let mappings = aligner.map("TTTTGCATCGCTGAAAACCCCAAAGTATATTTTAGAACTCGTCTATAGGTTCTACGATTTAACATCCACAGCCTTCTGGTGTCGCTGGTGTTTCAAACACCTCGATATATCACTCCTTCTGAATAACATCCATGAAAGAAGAGCCCAATCCATACTACTAAAGCTATCGTCATATGCACCATGGTCTTTTGAGAAAATTTTGCCCTCTTTAATTGACTCTAAGCTAAAAAAGAAAATTTTAATCAGTCCTCAAATTACTTACGTAGTCTTCAAATCAATAAACTATATGATAACCACGAATGACGATAAAATACACAAGTCCGCTATTCCTTCTTCTTCCTCTCTACCGT".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-        println!("Reverse Strand\n{:#?}", mappings);
-        assert!(mappings[0].strand == Strand::Reverse);
+    #[test]
+    fn test_many_contigs() {
+        // Amplicon-panel-like index: many short contigs
+        let mut fasta = String::new();
+        let mut contigs = Vec::new();
+        for i in 0..5000 {
+            let seq = String::from_utf8(random_seq(150, 42 + i)).unwrap();
+            fasta.push_str(&format!(">amp{}\n{}\n", i, seq));
+            contigs.push(seq);
+        }
 
-        // Assert the Display impl for strand works
-        println!("{}", mappings[0].strand);
+        let path = std::env::temp_dir().join("minimap2_test_many_contigs.fa");
+        std::fs::write(&path, fasta).unwrap();
+        let aligner = Aligner::builder().sr().with_index(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
 
-        let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(2)
-            .with_cigar()
-            .with_index("yeast_ref.mmi", None)
-            .unwrap();
+        assert_eq!(aligner.n_seq(), 5000);
+        assert_eq!(aligner.target_id("amp4999"), Some(4999));
+        assert_eq!(aligner.target_id("amp5000"), None);
 
-        aligner
-            .map(
-                "ATGAGCAAAATATTCTAAAGTGGAAACGGCACTAAGGTGAACTAAGCAACTTAGTGCAAAAc".as_bytes(),
-                true,
-                false,
-                None,
-                None,
-                Some(b"Sample Query"),
-            )
-            .unwrap();
+        let query = &contigs[4321].as_bytes()[20..120];
+        let first = aligner.map(query, false, false, None, None, None).unwrap();
+        let second = aligner.map(query, false, false, None, None, None).unwrap();
+        assert_eq!(first[0].target_name.as_deref().unwrap(), "amp4321");
+        assert_eq!(first[0].target_len, 150);
 
-        let mappings = aligner.map("atCCTACACTGCATAAACTATTTTGcaccataaaaaaaagttatgtgtgGGTCTAAAATAATTTGCTGAGCAATTAATGATTTCTAAATGATGCTAAAGTGAACCATTGTAatgttatatgaaaaataaatacacaattaagATCAACACAGTGAAATAACATTGATTGGGTGATTTCAAATGGGGTCTATctgaataatgttttatttaacagtaatttttatttctatcaatttttagtaatatctacaaatattttgttttaggcTGCCAGAAGATCGGCGGTGCAAGGTCAGAGGTGAGATGTTAGGTGGTTCCACCAACTGCACGGAAGAGCTGCCCTCTGTCATTCAAAATTTGACAGGTACAAACAGactatattaaataagaaaaacaaactttttaaaggCTTGACCATTAGTGAATAGGTTATATGCTTATTATTTCCATTTAGCTTTTTGAGACTAGTATGATTAGACAAATCTGCTTAGttcattttcatataatattgaGGAACAAAATTTGTGAGATTTTGCTAAAATAACTTGCTTTGCTTGTTTATAGAGGCacagtaaatcttttttattattattataattttagattttttaatttttaaat".as_bytes(), true, false, None, None, Some(b"Sample Query")).unwrap();
-        println!("{:#?}", mappings);
+        // Target names are shared, not allocated per mapping
+        assert!(Arc::ptr_eq(
+            first[0].target_name.as_ref().unwrap(),
+            second[0].target_name.as_ref().unwrap()
+        ));
+
+        let key = aligner.coordinate_key();
+        assert_eq!(
+            key(&first[0]),
+            (4321, first[0].target_start, first[0].target_end)
+        );
+    }
+
+    #[test]
+    fn test_strand_struct() {
+        let strand = Strand::default();
+        assert_eq!(strand, Strand::Forward);
+        println!("{}", strand);
+        let strand = Strand::Reverse;
+        println!("{}", strand);
     }
 
     #[test]
-    fn test_alignment_score() {
-        let aligner = Aligner::builder()
-            .preset(Preset::Splice)
-            .with_index_threads(1);
-
-        aligner.check_opts().expect("Opts are invalid");
-
-        let aligner = aligner.with_index("test_data/genome.fa", None).unwrap();
-
-        let output = aligner.map(
-            b"GAAATACGGGTCTCTGGTTTGACATAAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGCCCAGACTTAAATCGCACATACTGCGTCGTGCAATGCCGGGCGCTAACGGCTCAATATCACGCTGCGTCACTATGGCTACCCCAAAGCGGGGGGGGCATCGACGGGCTGTTTGATTTGAGCTCCATTACCCTACAATTAGAACACTGGCAACATTTGGGCGTTGAGCGGTCTTCCGTGTCGCTCGATCCGCTGGAACTTGGCAACCACACTCTAAACTACATGTGGTATGGCTCATAAGATCATGCGGATCGTGGCACTGCTTTCGGCCACGTTAGAGCCGCTGTGCTCGAAGATTGGGACCTACCAAC",
-            false, false, None, None, Some(b"Sample Query")).unwrap();
+    fn test_identity() {
+        // 1 mismatch, a 2 bp insertion and a 3 bp deletion over 30 columns
+        let aln = Alignment {
+            nm: 6,
+            cigar: Some(vec![(10, 0), (2, 1), (5, 0), (3, 2), (10, 0)]),
+            cigar_str: None,
+            md: None,
+            cs: None,
+            alignment_score: None,
+            dp_skipped: DpSkipped::None,
+        };
+        assert_eq!(aln.blast_identity(), Some(24.0 / 30.0));
+        assert_eq!(aln.gap_compressed_identity(), Some(24.0 / 27.0));
+
+        // Introns and clips are not columns
+        let spliced = Alignment {
+            nm: 0,
+            cigar: Some(vec![(5, 4), (10, 7), (1000, 3), (10, 7)]),
+            ..aln.clone()
+        };
+        assert_eq!(spliced.blast_identity(), Some(1.0));
+        assert_eq!(spliced.gap_compressed_identity(), Some(1.0));
 
-        println!("{:#?}", aligner.mapopt);
-        println!("{:#?}", aligner.idxopt);
-        println!("{:#?}", output);
+        let no_cigar = Alignment { cigar: None, ..aln };
+        assert_eq!(no_cigar.blast_identity(), None);
+        assert_eq!(no_cigar.gap_compressed_identity(), None);
     }
 
     #[test]
-    fn test_aligner_config_and_mapping() {
+    fn test_lazy_cs_md() {
+        let mt = mt_seq();
+        let mut seq = mt[6000..8000].to_vec();
+        // A mismatch, an insertion and a deletion
+        seq[300] = if seq[300] == b'A' { b'C' } else { b'A' };
+        seq.insert(900, b'G');
+        seq.remove(1500);
+
+        for clipping in [false, true] {
+            let mut builder = Aligner::builder().map_ont().with_cigar();
+            if clipping {
+                builder = builder.with_cigar_clipping();
+            }
+            let aligner = builder.with_index("test_data/MT-human.fa", None).unwrap();
+            for query in [seq.clone(), revcomp(&seq)] {
+                let eager = aligner.map(&query, true, true, None, None, None).unwrap();
+                let lazy = aligner.map(&query, false, false, None, None, None).unwrap();
+                assert!(!lazy.is_empty());
+                for (eager, lazy) in eager.iter().zip(&lazy) {
+                    let aln = eager.alignment.as_ref().unwrap();
+                    assert_eq!(lazy.cs(&aligner, &query).ok(), aln.cs.clone());
+                    assert_eq!(lazy.md(&aligner, &query).ok(), aln.md.clone());
+                    assert_eq!(eager.cs(&aligner, &query).ok(), aln.cs.clone());
+                }
+            }
+        }
+
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(2);
-        let aligner = aligner
-            .with_cigar()
-            .with_index("test_data/test_data.fasta", Some("test.mmi"))
+            .map_ont()
+            .with_index("test_data/MT-human.fa", None)
             .unwrap();
+        let mapping = aligner
+            .map(&seq, false, false, None, None, None)
+            .unwrap()
+            .remove(0);
+        assert_eq!(mapping.cs(&aligner, &seq), Err("Mapping has no CIGAR"));
 
-        aligner
-            .map(
-                "ATGAGCAAAATATTCTAAAGTGGAAACGGCACTAAGGTGAACTAAGCAACTTAGTGCAAAAc".as_bytes(),
-                true,
-                true,
-                None,
-                None,
-                Some(b"Sample Query"),
-            )
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_index("test_data/MT-human.fa", None)
             .unwrap();
-        let mappings = aligner.map("atCCTACACTGCATAAACTATTTTGcaccataaaaaaaagGGACatgtgtgGGTCTAAAATAATTTGCTGAGCAATTAATGATTTCTAAATGATGCTAAAGTGAACCATTGTAatgttatatgaaaaataaatacacaattaagATCAACACAGTGAAATAACATTGATTGGGTGATTTCAAATGGGGTCTATctgaataatgttttatttaacagtaatttttatttctatcaatttttagtaatatctacaaatattttgttttaggcTGCCAGAAGATCGGCGGTGCAAGGTCAGAGGTGAGATGTTAGGTGGTTCCACCAACTGCACGGAAGAGCTGCCCTCTGTCATTCAAAATTTGACAGGTACAAACAGactatattaaataagaaaaacaaactttttaaaggCTTGACCATTAGTGAATAGGTTATATGCTTATTATTTCCATTTAGCTTTTTGAGACTAGTATGATTAGACAAATCTGCTTAGttcattttcatataatattgaGGAACAAAATTTGTGAGATTTTGCTAAAATAACTTGCTTTGCTTGTTTATAGAGGCacagtaaatcttttttattattattataattttagattttttaatttttaaat".as_bytes(), false, false, None, None, Some(b"Sample Query")).unwrap();
-        println!("{:#?}", mappings);
+        let mapping = aligner
+            .map(&seq, false, false, None, None, None)
+            .unwrap()
+            .remove(0);
+        assert_eq!(
+            mapping.md(&aligner, &seq[..1000]),
+            Err("Mapping does not match the query")
+        );
     }
 
     #[test]
-    fn test_mappy_output() {
+    fn test_align_region() {
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(1)
-            .with_cigar()
+            .map_ont()
             .with_index("test_data/MT-human.fa", None)
             .unwrap();
+        let mt = aligner.target_seq(0).unwrap();
+
+        // A 10 bp deletion, then a tail that is not in the reference
+        let mut query = mt[1000..1200].to_vec();
+        query.extend_from_slice(&mt[1210..1400]);
+        query.extend_from_slice(&b"ACGT".repeat(10));
+        let aln = aligner
+            .align_region(
+                &query,
+                "MT_human",
+                1000..1400,
+                RegionAlignmentMode::Extension,
+            )
+            .unwrap();
+        let cigar = aln.cigar.unwrap();
+        let ops: Vec<u8> = cigar.iter().map(|&(_, op)| op).collect();
+        assert_eq!(ops, [0, 2, 0, 4]);
+        assert_eq!((cigar[1].0, cigar[3].0), (10, 40));
+        assert_eq!(aln.nm, 10);
+        // 390 matches at 2, a 10 bp gap at min(4 + 10 * 2, 24 + 10 * 1)
+        assert_eq!(aln.alignment_score, Some(756));
+        assert!(aln.cs.unwrap().contains('-'));
+        assert!(aln.md.unwrap().contains('^'));
+
+        // One mismatch, end to end
+        let mut query = mt[2000..2300].to_vec();
+        query[150] = if query[150] == b'A' { b'C' } else { b'A' };
+        let aln = aligner
+            .align_region(&query, "MT_human", 2000..2300, RegionAlignmentMode::Global)
+            .unwrap();
+        assert_eq!(aln.cigar_str.as_deref(), Some("300M"));
+        assert_eq!(aln.nm, 1);
+        assert_eq!(aln.alignment_score, Some(299 * 2 - 4));
+        assert!(aln.cs.unwrap().contains('*'));
 
-        let mut mappings = aligner.map(
-    b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG",
-            true, true, None, None, Some(b"Sample Query")).unwrap();
-        assert_eq!(mappings.len(), 1);
+        assert_eq!(
+            aligner.align_region(&query, "chr9", 0..100, RegionAlignmentMode::Global),
+            Err("Target not found in index")
+        );
+        assert_eq!(
+            aligner.align_region(b"", "MT_human", 0..100, RegionAlignmentMode::Global),
+            Err("Sequence is empty")
+        );
 
-        let observed = mappings.pop().unwrap();
+        // Scores that do not fit ksw2's 8-bit scoring are rejected rather than wrapped
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_match_score(200)
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        assert_eq!(
+            aligner.align_region(&query, "MT_human", 1000..1400, RegionAlignmentMode::Global),
+            Err("Score out of range for ksw2")
+        );
+    }
+
+    #[test]
+    fn test_index_io() {
+        let path = std::env::temp_dir().join("minimap2_test_index_io.mmi");
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", path.to_str())
+            .unwrap();
+        let mut index = Vec::new();
+        aligner.write_index(&mut index).unwrap();
+        // The same bytes minimap2 writes to a file
+        assert_eq!(index, std::fs::read(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
 
+        let loaded = Aligner::builder()
+            .map_ont()
+            .load_index(std::io::Cursor::new(&index))
+            .unwrap();
+        let seq = b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA";
         assert_eq!(
-            observed.target_name,
-            Some(Arc::new(String::from("MT_human")))
+            loaded.map(seq, false, false, None, None, None),
+            aligner.map(seq, false, false, None, None, None)
         );
-        assert_eq!(observed.target_start, 576);
-        assert_eq!(observed.target_end, 768);
-        assert_eq!(observed.query_start, 0);
-        assert_eq!(observed.query_end, 191);
-        assert_eq!(observed.mapq, 29);
-        assert_eq!(observed.match_len, 168);
-        assert_eq!(observed.block_len, 195);
-        assert_eq!(observed.strand, Strand::Forward);
-        assert_eq!(observed.is_primary, true);
 
-        let align = observed.alignment.as_ref().unwrap();
-        assert_eq!(align.nm, 27);
+        let fasta = std::fs::read("test_data/genome.fa").unwrap();
         assert_eq!(
-            align.cigar,
-            Some(vec![
-                (14, 0),
-                (2, 2),
-                (4, 0),
-                (3, 1),
-                (37, 0),
-                (1, 2),
-                (85, 0),
-                (1, 2),
-                (48, 0)
-            ])
+            Aligner::builder().load_index(&fasta[..]).err(),
+            Some("Not a minimap2 index")
         );
         assert_eq!(
-            align.cigar_str,
-            Some(String::from("14M2D4M3I37M1D85M1D48M9S"))
+            Aligner::builder().load_index(&index[..2]).err(),
+            Some("Not a minimap2 index")
         );
         assert_eq!(
-            align.md,
-            Some(String::from(
-                "14^CC1C11A12T1A7T4^T1A48A2A21T0T8^T2A5T2A4C0A0C2T0C2A4A17"
-            ))
+            Aligner::builder().load_index(&index[..100]).err(),
+            Some("Index file is truncated")
         );
-        assert_eq!(align.cs, Some(String::from(":14-cc:1*ct:2+atc:9*ag:12*tc:1*ac:7*tc:4-t:1*ag:48*ag:2*ag:21*tc*tc:8-t:2*ag:5*tc:2*ag:4*ct*ac*ct:2*tc*ct:2*ag:4*ag:17")));
+    }
 
-        let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(1)
+    #[test]
+    fn test_hard_clipping() {
+        let mt = mt_seq();
+        // A chimeric read: a primary alignment and a supplementary one
+        let seq = [&mt[1000..1600], &mt[9000..9400]].concat();
+        let builder = Aligner::builder()
+            .map_ont()
             .with_cigar()
-            .with_cigar_clipping()
+            .with_cigar_clipping();
+        let cigars = |aligner: &Aligner<Built>| {
+            let mut mappings = aligner.map(&seq, false, false, None, None, None).unwrap();
+            mappings.sort_by_key(|m| m.is_supplementary);
+            let cigars: Vec<_> = mappings
+                .iter()
+                .map(|m| {
+                    let aln = m.alignment.as_ref().unwrap();
+                    (aln.cigar_str.clone().unwrap(), aln.cigar.clone().unwrap())
+                })
+                .collect();
+            let batch = aligner
+                .map_batch([(&b"q"[..], &seq[..])], false, false)
+                .unwrap();
+            let mut batched: Vec<_> = batch.query_mappings(0).map(|m| m.to_mapping()).collect();
+            batched.sort_by_key(|m| m.is_supplementary);
+            for (m, b) in mappings.iter().zip(&batched) {
+                let (m, b) = (m.alignment.as_ref().unwrap(), b.alignment.as_ref().unwrap());
+                assert_eq!((&m.cigar_str, &m.cigar), (&b.cigar_str, &b.cigar));
+            }
+            cigars
+        };
+
+        let hard = builder
+            .clone()
+            .with_hard_clipping()
             .with_index("test_data/MT-human.fa", None)
             .unwrap();
+        let cigars_hard = cigars(&hard);
+        assert_eq!(cigars_hard.len(), 2);
+        let (primary, supplementary) = (&cigars_hard[0], &cigars_hard[1]);
+        assert!(primary.0.ends_with('S') && !primary.0.contains('H'));
+        assert_eq!(primary.1.last().unwrap().1, 4);
+        assert!(supplementary.0.contains('H') && !supplementary.0.contains('S'));
+        assert_eq!(supplementary.1.first().unwrap().1, 5);
+
+        // Soft clips only without the option, or with MM_F_SOFTCLIP
+        let soft = builder.with_index("test_data/MT-human.fa", None).unwrap();
+        let mut softclip = hard.clone();
+        softclip.mapopt.flag |= MM_F_SOFTCLIP as i64;
+        for aligner in [&soft, &softclip] {
+            let supplementary = &cigars(aligner)[1];
+            assert!(supplementary.0.contains('S') && !supplementary.0.contains('H'));
+            assert_eq!(supplementary.1.first().unwrap().1, 4);
+        }
+    }
 
-        let mut mappings = aligner.map(
-            b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG",
-                    true, true, None, None, Some(b"Sample Query")).unwrap();
-        assert_eq!(mappings.len(), 1);
-
-        let observed = mappings.pop().unwrap();
+    #[test]
+    fn test_secondary() {
+        let mt = mt_seq();
+        // Two copies of the same sequence: each query has a primary and a secondary hit
+        let copy = mt[1000..3000].to_vec();
+        let seq = &mt[1200..1800];
+        let types = |builder: Aligner<PresetSet>| {
+            let aligner = builder.with_seqs(&[copy.clone(), copy.clone()]).unwrap();
+            let mappings = aligner.map(seq, false, false, None, None, None).unwrap();
+            let batch = aligner.map_batch([(&b"q"[..], seq)], false, false).unwrap();
+            let batched: Vec<_> = batch.query_mappings(0).map(|m| m.alignment_type).collect();
+            let types: Vec<_> = mappings.iter().map(|m| m.alignment_type).collect();
+            assert_eq!(types, batched);
+            for m in &mappings {
+                assert_eq!(
+                    m.alignment_type,
+                    AlignmentType::from_flags(m.is_primary, m.is_supplementary)
+                );
+            }
+            types
+        };
 
         assert_eq!(
-            observed.target_name,
-            Some(Arc::new(String::from("MT_human")))
+            types(Aligner::builder().map_ont()),
+            [AlignmentType::Primary, AlignmentType::Secondary]
         );
-        assert_eq!(observed.target_start, 576);
-        assert_eq!(observed.target_end, 768);
-        assert_eq!(observed.query_start, 0);
-        assert_eq!(observed.query_end, 191);
-        assert_eq!(observed.mapq, 29);
-        assert_eq!(observed.match_len, 168);
-        assert_eq!(observed.block_len, 195);
-        assert_eq!(observed.strand, Strand::Forward);
-        assert_eq!(observed.is_primary, true);
-
-        let align = observed.alignment.as_ref().unwrap();
-        assert_eq!(align.nm, 27);
         assert_eq!(
-            align.cigar,
-            Some(vec![
-                (14, 0),
-                (2, 2),
-                (4, 0),
-                (3, 1),
-                (37, 0),
-                (1, 2),
-                (85, 0),
-                (1, 2),
-                (48, 0),
-                (9, 4)
-            ])
+            types(Aligner::builder().map_ont().with_secondary(false)),
+            [AlignmentType::Primary]
         );
         assert_eq!(
-            align.cigar_str,
-            Some(String::from("14M2D4M3I37M1D85M1D48M9S"))
+            types(Aligner::builder().map_ont().with_max_secondary(0)),
+            [AlignmentType::Primary]
+        );
+        assert_eq!(
+            types(Aligner::builder().map_ont().with_best_n(0)),
+            [AlignmentType::Primary]
+        );
+        let builder = Aligner::builder()
+            .map_ont()
+            .with_secondary(false)
+            .with_secondary(true);
+        assert_eq!(builder.mapopt.flag & MM_F_NO_PRINT_2ND as i64, 0);
+        assert_eq!(
+            types(builder),
+            [AlignmentType::Primary, AlignmentType::Secondary]
         );
 
-        let mut mappings = aligner.map(
-                    b"TTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG",
-                            true, true, None, None, Some(b"Sample Query")).unwrap();
-        assert_eq!(mappings.len(), 1);
-
-        let _observed = mappings.pop().unwrap();
-
+        // Pairs too
+        let pair_types = |secondary| {
+            let aligner = Aligner::builder()
+                .sr()
+                .with_cigar()
+                .with_secondary(secondary)
+                .with_seqs(&[copy.clone(), copy.clone()])
+                .unwrap();
+            let mate2 = revcomp(&copy[500..650]);
+            let (mate1, mate2) = aligner
+                .map_pair(&copy[200..350], &mate2, false, false, None)
+                .unwrap();
+            [mate1, mate2].map(|mate| mate.iter().map(|m| m.alignment_type).collect::<Vec<_>>())
+        };
+        assert!(pair_types(true)
+            .iter()
+            .all(|mate| mate.contains(&AlignmentType::Secondary)));
         assert_eq!(
-            align.cigar,
-            Some(vec![
-                (14, 0),
-                (2, 2),
-                (4, 0),
-                (3, 1),
-                (37, 0),
-                (1, 2),
-                (85, 0),
-                (1, 2),
-                (48, 0),
-                (9, 4)
-            ])
+            pair_types(false),
+            [[AlignmentType::Primary], [AlignmentType::Primary]].map(Vec::from)
         );
     }
 
     #[test]
-    fn test_mappy_output_no_md() {
+    fn test_check_cigar() {
         let aligner = Aligner::builder()
-            .preset(Preset::MapOnt)
-            .with_index_threads(1)
+            .map_ont()
             .with_cigar()
+            .with_cigar_clipping()
             .with_index("test_data/MT-human.fa", None)
             .unwrap();
-        let query =  b"GTTTATGTAGCTTATTCTATCCAAAGCAATGCACTGAAAATGTCTCGACGGGCCCACACGCCCCATAAACAAATAGGTTTGGTCCTAGCCTTTCTATTAGCTCTTAGTGAGGTTACACATGCAAGCATCCCCGCCCCAGTGAGTCGCCCTCCAAGTCACTCTGACTAAGAGGAGCAAGCATCAAGCACGCAACAGCGCAG";
-
-        for (md, cs) in vec![(true, true), (false, false), (true, false), (false, true)].iter() {
-            let mapping = aligner
-                .map(query, *cs, *md, None, None, Some(b"Sample Query"))
-                .unwrap()
-                .pop()
-                .unwrap();
-            let align = mapping.alignment.as_ref().unwrap();
-            assert_eq!(align.cigar_str.is_some(), true);
-            assert_eq!(align.md.is_some(), *md);
-            assert_eq!(align.cs.is_some(), *cs);
+        let mt = mt_seq();
+
+        // A read with unaligned ends (reversed, not reverse complemented, sequence)
+        let junk: Vec<u8> = mt[8000..8060].iter().rev().copied().collect();
+        let read = [&junk[..30], &mt[3000..4000], &junk[30..]].concat();
+        for seq in [read.clone(), revcomp(&read)] {
+            let mappings = aligner.map(&seq, false, false, None, None, None).unwrap();
+            assert_eq!(mappings[0].check_cigar(), Ok(()));
         }
-    }
 
-    #[test]
-    fn test_strand_struct() {
-        let strand = Strand::default();
-        assert_eq!(strand, Strand::Forward);
-        println!("{}", strand);
-        let strand = Strand::Reverse;
-        println!("{}", strand);
+        let mut mapping = aligner
+            .map(&read, false, false, None, None, None)
+            .unwrap()
+            .remove(0);
+        let expected = read.len() as i64 - mapping.query_end as i64;
+        let alignment = mapping.alignment.as_mut().unwrap();
+        alignment.cigar.as_mut().unwrap().push((5, 4));
+        alignment.cigar_str = Some("10M".to_string());
+        assert_eq!(
+            mapping.check_cigar(),
+            Err(vec![
+                CigarMismatch {
+                    check: CigarCheck::TrailingClip,
+                    in_cigar_str: false,
+                    cigar_len: expected + 5,
+                    expected,
+                },
+                CigarMismatch {
+                    check: CigarCheck::QuerySpan,
+                    in_cigar_str: true,
+                    cigar_len: 10,
+                    expected: (mapping.query_end - mapping.query_start) as i64,
+                },
+                CigarMismatch {
+                    check: CigarCheck::TargetSpan,
+                    in_cigar_str: true,
+                    cigar_len: 10,
+                    expected: (mapping.target_end - mapping.target_start) as i64,
+                },
+            ])
+        );
     }
 
     #[test]
@@ -1833,6 +8015,17 @@ mod tests {
         drop(tlb);
     }
 
+    #[test]
+    fn test_nested_buf() {
+        with_buf(|outer| {
+            with_buf(|inner| assert_ne!(outer.get_buf(), inner.get_buf()));
+        });
+
+        // The thread local buffer is free again once the outer call returns
+        let first = with_buf(|buf| buf.get_buf());
+        assert_eq!(with_buf(|buf| buf.get_buf()), first);
+    }
+
     #[test]
     fn test_with_seq() {
         let seq = "CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
@@ -1975,6 +8168,8 @@ mod tests {
         let aligner = Aligner::default();
         drop(aligner);
 
+        let _aligner = Aligner::builder().lrhqae();
+        let _aligner = Aligner::builder().lrhq();
         let _aligner = Aligner::builder().map_ont();
         let _aligner = Aligner::builder().ava_ont();
         let _aligner = Aligner::builder().map10k();
@@ -2025,6 +8220,113 @@ mod tests {
         }
     }
 
+    // Every preset needs an ergonomic builder function. The match is exhaustive, so adding a
+    // variant to Preset fails to compile until it is added here (and to PRESETS).
+    fn preset_builder(preset: &Preset) -> Aligner<PresetSet> {
+        match preset {
+            Preset::LrHqae => Aligner::builder().lrhqae(),
+            Preset::LrHq => Aligner::builder().lrhq(),
+            Preset::Splice => Aligner::builder().splice(),
+            Preset::SpliceHq => Aligner::builder().splice_hq(),
+            Preset::Asm => Aligner::builder().asm(),
+            Preset::Asm5 => Aligner::builder().asm5(),
+            Preset::Asm10 => Aligner::builder().asm10(),
+            Preset::Asm20 => Aligner::builder().asm20(),
+            Preset::Sr => Aligner::builder().sr(),
+            Preset::MapPb => Aligner::builder().map_pb(),
+            Preset::MapHifi => Aligner::builder().map_hifi(),
+            Preset::MapOnt => Aligner::builder().map_ont(),
+            Preset::AvaPb => Aligner::builder().ava_pb(),
+            Preset::AvaOnt => Aligner::builder().ava_ont(),
+            Preset::Short => Aligner::builder().short(),
+            Preset::Map10k => Aligner::builder().map10k(),
+            Preset::Cdna => Aligner::builder().cdna(),
+        }
+    }
+
+    const PRESETS: [Preset; 17] = [
+        Preset::LrHqae,
+        Preset::LrHq,
+        Preset::Splice,
+        Preset::SpliceHq,
+        Preset::Asm,
+        Preset::Asm5,
+        Preset::Asm10,
+        Preset::Asm20,
+        Preset::Sr,
+        Preset::MapPb,
+        Preset::MapHifi,
+        Preset::MapOnt,
+        Preset::AvaPb,
+        Preset::AvaOnt,
+        Preset::Short,
+        Preset::Map10k,
+        Preset::Cdna,
+    ];
+
+    #[test]
+    fn test_preset_builder_parity() {
+        for preset in PRESETS.iter() {
+            let ergonomic = preset_builder(preset);
+            let explicit = Aligner::builder().preset(preset.clone());
+            assert_eq!(
+                format!("{:?}", ergonomic.idxopt),
+                format!("{:?}", explicit.idxopt),
+                "idxopt differs for {:?}",
+                preset
+            );
+            assert_eq!(
+                format!("{:?}", ergonomic.mapopt),
+                format!("{:?}", explicit.mapopt),
+                "mapopt differs for {:?}",
+                preset
+            );
+        }
+    }
+
+    // Upstream minimap2 bumps can change preset parameters silently. Every preset's options
+    // are compared against test_data/preset_snapshots.txt; after checking a change, and noting
+    // it in CHANGELOG.md, rewrite the file with MINIMAP2_UPDATE_SNAPSHOTS=1. A missing file
+    // is recorded on the first run.
+    #[test]
+    fn test_preset_snapshots() {
+        use std::fmt::Write;
+
+        let path = "test_data/preset_snapshots.txt";
+        let mut snapshot = String::new();
+        for preset in PRESETS.iter() {
+            let aligner = preset_builder(preset);
+            writeln!(
+                snapshot,
+                "== {:?}\n{:#?}\n{:#?}",
+                preset, aligner.idxopt, aligner.mapopt
+            )
+            .unwrap();
+        }
+
+        if std::env::var_os("MINIMAP2_UPDATE_SNAPSHOTS").is_some() || !Path::new(path).exists() {
+            std::fs::write(path, &snapshot).unwrap();
+            return;
+        }
+
+        let expected = std::fs::read_to_string(path).unwrap();
+        let mut preset = "";
+        for (line, expected_line) in snapshot.lines().zip(expected.lines()) {
+            if let Some(name) = line.strip_prefix("== ") {
+                preset = name;
+            }
+            assert_eq!(
+                line, expected_line,
+                "preset {preset} changed, see the comment on test_preset_snapshots"
+            );
+        }
+        assert_eq!(
+            snapshot.lines().count(),
+            expected.lines().count(),
+            "preset options changed, see the comment on test_preset_snapshots"
+        );
+    }
+
     #[test]
     fn test_send() {
         let seq = "CGGCACCAGGTTAAAATCTGAGTGCTGCAATAGGCGATTACAGTACAGCACCCAGCCTCCGAAATTCTTTAACGGTCGTCGTCTCGATACTGCCACTATGCCTTTATATTATTGTCTTCAGGTGATGCTGCAGATCGTGCAGACGGGTGGCTTTAGTGTTGTGGGATGCATAGCTATTGACGGATCTTTGTCAATTGACAGAAATACGGGTCTCTGGTTTGACATGAAGGTCCAACTGTAATAACTGATTTTATCTGTGGGTGATGCGTTTCTCGGACAACCACGACCGCGACCAGACTTAAGTCTGGGCGCGGTCGTGGTTGTCCGAGAAACGCATCACCCACAGATAAAATCAGTTATTACAGTTGGACCTTTATGTCAAACCAGAGACCCGTATTTC";
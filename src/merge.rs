@@ -0,0 +1,131 @@
+//! k-way merge of sorted result streams
+//!
+//! Workers that sort their own output (for example one sorted run per thread or per chunk of
+//! reads) can be combined into a single sorted stream while holding only one item per input
+//! stream in memory. Combined with [`Aligner::coordinate_key`](crate::Aligner::coordinate_key)
+//! this gives coordinate-sorted output suitable for writing a sorted BAM.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Iterator over the merged items of several sorted streams, see [`merge_sorted_by_key`]
+pub struct SortedMerge<I: Iterator, K, F> {
+    streams: Vec<I>,
+    heap: BinaryHeap<Reverse<HeapItem<K, I::Item>>>,
+    key: F,
+}
+
+struct HeapItem<K, T> {
+    key: K,
+    stream: usize,
+    item: T,
+}
+
+// Only the key and stream take part in the ordering, so items need not be comparable. Ties
+// are broken by stream index, which keeps the merge stable.
+impl<K: Ord, T> Ord for HeapItem<K, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then(self.stream.cmp(&other.stream))
+    }
+}
+
+impl<K: Ord, T> PartialOrd for HeapItem<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> PartialEq for HeapItem<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord, T> Eq for HeapItem<K, T> {}
+
+/// Merge streams that are each sorted by `key` into one stream sorted by `key`
+///
+/// Only the head of each stream is buffered. Items with equal keys are returned in stream
+/// order. If an input stream is not sorted the output is not either, but no items are lost.
+///
+/// ```
+/// use minimap2::merge::merge_sorted_by_key;
+///
+/// let merged: Vec<u32> =
+///     merge_sorted_by_key(vec![vec![1, 4, 9], vec![2, 3, 10], vec![]], |x| *x).collect();
+/// assert_eq!(merged, vec![1, 2, 3, 4, 9, 10]);
+/// ```
+pub fn merge_sorted_by_key<S, I, K, F>(streams: S, key: F) -> SortedMerge<I, K, F>
+where
+    S: IntoIterator,
+    S::Item: IntoIterator<IntoIter = I>,
+    I: Iterator,
+    K: Ord,
+    F: FnMut(&I::Item) -> K,
+{
+    let mut merge = SortedMerge {
+        streams: streams.into_iter().map(|s| s.into_iter()).collect(),
+        heap: BinaryHeap::new(),
+        key,
+    };
+
+    for stream in 0..merge.streams.len() {
+        merge.refill(stream);
+    }
+
+    merge
+}
+
+impl<I: Iterator, K: Ord, F: FnMut(&I::Item) -> K> SortedMerge<I, K, F> {
+    fn refill(&mut self, stream: usize) {
+        if let Some(item) = self.streams[stream].next() {
+            let key = (self.key)(&item);
+            self.heap.push(Reverse(HeapItem { key, stream, item }));
+        }
+    }
+}
+
+impl<I: Iterator, K: Ord, F: FnMut(&I::Item) -> K> Iterator for SortedMerge<I, K, F> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse(head) = self.heap.pop()?;
+        self.refill(head.stream);
+        Some(head.item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.streams.iter().map(|s| s.size_hint()).fold(
+            (self.heap.len(), Some(self.heap.len())),
+            |acc, x| {
+                (
+                    acc.0.saturating_add(x.0),
+                    acc.1.zip(x.1).and_then(|(a, b)| a.checked_add(b)),
+                )
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_sorted_by_key() {
+        let streams = vec![
+            vec![(0, 5, 'a'), (0, 20, 'b'), (1, 3, 'c')],
+            vec![(0, 5, 'd'), (2, 0, 'e')],
+            vec![],
+            vec![(0, 1, 'f'), (1, 3, 'g'), (1, 4, 'h')],
+        ];
+
+        let merge = merge_sorted_by_key(streams, |x| (x.0, x.1));
+        assert_eq!(merge.size_hint(), (8, Some(8)));
+
+        let merged: String = merge.map(|x| x.2).collect();
+        assert_eq!(merged, "fadbcghe");
+    }
+}
@@ -0,0 +1,524 @@
+//! Classification and coverage of all-vs-all read overlaps
+//!
+//! With the `ava-ont` and `ava-pb` presets each [`Mapping`] is an overlap between two reads.
+//! [`classify_overlap`] sorts overlaps into containments, dovetails and internal matches as
+//! assemblers (miniasm and others) do, and [`OverlapCoverage`] tallies the overlaps of each
+//! read to estimate its depth and flag contained reads and likely repeats. [`AvaFilter`] drops
+//! short and internal overlaps inside the mapping call, before they become [`Mapping`]s.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{Mapping, Strand};
+
+/// Thresholds for [`classify_overlap`], with the defaults of miniasm
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlapParams {
+    /// Maximum overhang: unaligned sequence at the ends of an overlap that could have been
+    /// aligned, had the reads overlapped end to end
+    pub max_hang: i32,
+    /// Maximum overhang as a fraction of the overlap length
+    pub int_frac: f32,
+}
+
+impl Default for OverlapParams {
+    fn default() -> Self {
+        OverlapParams {
+            max_hang: 1000,
+            int_frac: 0.8,
+        }
+    }
+}
+
+/// How two reads overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapKind {
+    /// The reads share a region but diverge on both sides of it, as at a repeat
+    Internal,
+    /// The query lies entirely within the target
+    QueryContained,
+    /// The target lies entirely within the query
+    TargetContained,
+    /// The end of the query overlaps the start of the target (of its reverse complement for
+    /// overlaps on the reverse strand)
+    QueryToTarget,
+    /// The end of the target (or of its reverse complement) overlaps the start of the query
+    TargetToQuery,
+}
+
+/// Classify an overlap between two reads
+///
+/// Returns None if the query length is unknown.
+///
+/// ```
+/// use minimap2::overlap::{classify_overlap, OverlapKind, OverlapParams};
+/// use minimap2::Mapping;
+/// use std::num::NonZeroI32;
+///
+/// // The last 3 kb of a 10 kb read overlap the first 3 kb of another
+/// let overlap = Mapping {
+///     query_len: NonZeroI32::new(10_000),
+///     query_start: 7_000,
+///     query_end: 10_000,
+///     target_len: 8_000,
+///     target_start: 0,
+///     target_end: 3_000,
+///     ..Default::default()
+/// };
+/// assert_eq!(
+///     classify_overlap(&overlap, &OverlapParams::default()),
+///     Some(OverlapKind::QueryToTarget)
+/// );
+/// ```
+pub fn classify_overlap(mapping: &Mapping, params: &OverlapParams) -> Option<OverlapKind> {
+    let query_len = mapping.query_len?.get();
+    let (qs, qe) = (mapping.query_start, mapping.query_end);
+    let tl = mapping.target_len;
+    let (ts, te) = oriented_target(tl, mapping.target_start, mapping.target_end, mapping.strand);
+    let (left_hang, right_hang, overlap_len) = hangs(query_len, qs, qe, tl, ts, te);
+    let max_hang = (params.max_hang as f32).min(overlap_len as f32 * params.int_frac);
+
+    Some(if (left_hang + right_hang) as f32 > max_hang {
+        OverlapKind::Internal
+    } else if qs <= ts && query_len - qe <= tl - te {
+        OverlapKind::QueryContained
+    } else if qs >= ts && query_len - qe >= tl - te {
+        OverlapKind::TargetContained
+    } else if qs > ts {
+        OverlapKind::QueryToTarget
+    } else {
+        OverlapKind::TargetToQuery
+    })
+}
+
+/// Target coordinates in the orientation of the query
+fn oriented_target(target_len: i32, start: i32, end: i32, strand: Strand) -> (i32, i32) {
+    match strand {
+        Strand::Forward => (start, end),
+        Strand::Reverse => (target_len - end, target_len - start),
+    }
+}
+
+/// Left and right overhangs and length of an overlap, target coordinates oriented
+fn hangs(query_len: i32, qs: i32, qe: i32, tl: i32, ts: i32, te: i32) -> (i32, i32, i32) {
+    let left_hang = qs.min(ts);
+    let right_hang = (query_len - qe).min(tl - te);
+    let overlap_len = (qe - qs).max(te - ts);
+    (left_hang, right_hang, overlap_len)
+}
+
+/// Filters applied to all-vs-all overlaps inside the mapping call, so that overlaps that
+/// would be thrown away never become [`Mapping`]s. See
+/// [`with_ava_filter`](crate::Aligner::with_ava_filter).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvaFilter {
+    /// Minimum overlap length, the longer of the query and target spans
+    pub min_overlap_len: i32,
+    /// Maximum total overhang (left plus right, as in [`classify_overlap`]); None for no limit
+    pub max_overhang: Option<i32>,
+    /// Report each pair of reads once, from the read with the smaller name (minimap2
+    /// --dual=no, set by the ava presets). Applied by minimap2 before chaining.
+    pub suppress_dual: bool,
+}
+
+impl Default for AvaFilter {
+    fn default() -> Self {
+        AvaFilter {
+            min_overlap_len: 0,
+            max_overhang: None,
+            suppress_dual: true,
+        }
+    }
+}
+
+impl AvaFilter {
+    /// Whether an overlap passes the length and overhang filters. Overlaps without query
+    /// length only have their length checked.
+    pub fn accepts(&self, mapping: &Mapping) -> bool {
+        match mapping.query_len {
+            Some(query_len) => self.accepts_coords(
+                query_len.get(),
+                (mapping.query_start, mapping.query_end),
+                mapping.target_len,
+                (mapping.target_start, mapping.target_end),
+                mapping.strand,
+            ),
+            None => {
+                (mapping.query_end - mapping.query_start)
+                    .max(mapping.target_end - mapping.target_start)
+                    >= self.min_overlap_len
+            }
+        }
+    }
+
+    pub(crate) fn accepts_coords(
+        &self,
+        query_len: i32,
+        (qs, qe): (i32, i32),
+        target_len: i32,
+        (start, end): (i32, i32),
+        strand: Strand,
+    ) -> bool {
+        let (ts, te) = oriented_target(target_len, start, end, strand);
+        let (left_hang, right_hang, overlap_len) = hangs(query_len, qs, qe, target_len, ts, te);
+        overlap_len >= self.min_overlap_len
+            && self
+                .max_overhang
+                .map_or(true, |max| left_hang + right_hang <= max)
+    }
+}
+
+/// Overlaps of one read, tallied by [`OverlapCoverage`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadOverlaps {
+    pub len: i32,
+    pub n_overlaps: usize,
+    pub n_internal: usize,
+    pub n_dovetail: usize,
+    /// Overlaps in which this read lies within the other
+    pub n_contained: usize,
+    /// Overlaps in which the other read lies within this one
+    pub n_containing: usize,
+    /// Bases of this read covered by overlaps, summed over overlaps
+    pub aligned_bases: u64,
+}
+
+impl ReadOverlaps {
+    /// Mean depth of overlaps along the read
+    pub fn mean_depth(&self) -> f64 {
+        if self.len > 0 {
+            self.aligned_bases as f64 / self.len as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Classification of a read from its overlaps, see [`OverlapCoverage::read_class`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadClass {
+    /// Lies within another read, so adds nothing to an assembly
+    Contained,
+    /// Overlap depth well above the median: likely a repeat or duplicated sequence
+    Repeat,
+    Unique,
+}
+
+/// Per-read overlap tallies of an all-vs-all mapping run
+///
+/// Each overlap is counted for both its reads. minimap2's ava presets report each pair of
+/// reads once, so every overlap should be added once.
+///
+/// ```
+/// use minimap2::overlap::{OverlapCoverage, OverlapKind, OverlapParams, ReadClass};
+/// use minimap2::Mapping;
+/// use std::num::NonZeroI32;
+/// use std::sync::Arc;
+///
+/// let mut coverage = OverlapCoverage::new(OverlapParams::default());
+/// let kind = coverage.add(&Mapping {
+///     query_name: Some(Arc::new("short".to_string())),
+///     query_len: NonZeroI32::new(2_000),
+///     query_start: 0,
+///     query_end: 2_000,
+///     target_name: Some(Arc::new("long".to_string())),
+///     target_len: 10_000,
+///     target_start: 4_000,
+///     target_end: 6_000,
+///     ..Default::default()
+/// });
+/// assert_eq!(kind, Some(OverlapKind::QueryContained));
+/// assert_eq!(coverage.get("short").unwrap().mean_depth(), 1.0);
+/// assert_eq!(coverage.read_class("short", 3.0), Some(ReadClass::Contained));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OverlapCoverage {
+    params: OverlapParams,
+    reads: HashMap<Arc<String>, ReadOverlaps>,
+}
+
+impl OverlapCoverage {
+    pub fn new(params: OverlapParams) -> Self {
+        OverlapCoverage {
+            params,
+            reads: HashMap::new(),
+        }
+    }
+
+    /// Add an overlap, returning its kind. Overlaps of a read with itself and overlaps
+    /// without read names or query length are skipped and give None.
+    pub fn add(&mut self, mapping: &Mapping) -> Option<OverlapKind> {
+        let query = mapping.query_name.as_ref()?;
+        let target = mapping.target_name.as_ref()?;
+        if query == target {
+            return None;
+        }
+        let kind = classify_overlap(mapping, &self.params)?;
+
+        let (query_role, target_role) = match kind {
+            OverlapKind::Internal => (Role::Internal, Role::Internal),
+            OverlapKind::QueryContained => (Role::Contained, Role::Containing),
+            OverlapKind::TargetContained => (Role::Containing, Role::Contained),
+            OverlapKind::QueryToTarget | OverlapKind::TargetToQuery => {
+                (Role::Dovetail, Role::Dovetail)
+            }
+        };
+
+        let sides = [
+            (
+                query,
+                mapping.query_len?.get(),
+                mapping.query_end - mapping.query_start,
+                query_role,
+            ),
+            (
+                target,
+                mapping.target_len,
+                mapping.target_end - mapping.target_start,
+                target_role,
+            ),
+        ];
+        for (name, len, aligned, role) in sides {
+            let read = self.reads.entry(Arc::clone(name)).or_default();
+            read.len = len;
+            read.n_overlaps += 1;
+            read.aligned_bases += aligned.max(0) as u64;
+            match role {
+                Role::Internal => read.n_internal += 1,
+                Role::Dovetail => read.n_dovetail += 1,
+                Role::Contained => read.n_contained += 1,
+                Role::Containing => read.n_containing += 1,
+            }
+        }
+
+        Some(kind)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ReadOverlaps> {
+        self.reads.get(&name.to_string())
+    }
+
+    /// Tallies of all reads with at least one overlap
+    pub fn reads(&self) -> impl Iterator<Item = (&str, &ReadOverlaps)> {
+        self.reads.iter().map(|(name, read)| (name.as_str(), read))
+    }
+
+    /// Median of the mean depths of all reads, or 0 if there are none
+    pub fn median_depth(&self) -> f64 {
+        let mut depths: Vec<f64> = self.reads.values().map(|r| r.mean_depth()).collect();
+        if depths.is_empty() {
+            return 0.0;
+        }
+        depths.sort_by(|a, b| a.total_cmp(b));
+        depths[depths.len() / 2]
+    }
+
+    /// Mean depth of a read relative to the median depth of all reads
+    pub fn normalized_depth(&self, name: &str) -> Option<f64> {
+        let depth = self.get(name)?.mean_depth();
+        let median = self.median_depth();
+        Some(if median > 0.0 { depth / median } else { 0.0 })
+    }
+
+    /// Classify a read as contained, repeat (normalized depth of at least `repeat_factor`)
+    /// or unique. None for reads without overlaps.
+    pub fn read_class(&self, name: &str, repeat_factor: f64) -> Option<ReadClass> {
+        let read = self.get(name)?;
+        Some(if read.n_contained > 0 {
+            ReadClass::Contained
+        } else if self.normalized_depth(name)? >= repeat_factor {
+            ReadClass::Repeat
+        } else {
+            ReadClass::Unique
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Role {
+    Internal,
+    Dovetail,
+    Contained,
+    Containing,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroI32;
+
+    fn overlap(
+        query: (&str, i32, i32, i32),
+        target: (&str, i32, i32, i32),
+        strand: Strand,
+    ) -> Mapping {
+        Mapping {
+            query_name: Some(Arc::new(query.0.to_string())),
+            query_len: NonZeroI32::new(query.1),
+            query_start: query.2,
+            query_end: query.3,
+            target_name: Some(Arc::new(target.0.to_string())),
+            target_len: target.1,
+            target_start: target.2,
+            target_end: target.3,
+            strand,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ava_filter() {
+        let filter = AvaFilter {
+            min_overlap_len: 1000,
+            max_overhang: Some(100),
+            suppress_dual: true,
+        };
+        // Dovetail of 3 kb
+        let dovetail = overlap(
+            ("a", 10_000, 7_000, 10_000),
+            ("b", 8_000, 0, 3_000),
+            Strand::Forward,
+        );
+        assert!(filter.accepts(&dovetail));
+        // Too short
+        let short = overlap(
+            ("a", 10_000, 9_500, 10_000),
+            ("b", 8_000, 0, 500),
+            Strand::Forward,
+        );
+        assert!(!filter.accepts(&short));
+        // Internal: 2 kb unaligned on both reads at each end
+        let internal = overlap(
+            ("a", 10_000, 2_000, 8_000),
+            ("b", 10_000, 2_000, 8_000),
+            Strand::Forward,
+        );
+        assert!(!filter.accepts(&internal));
+        assert!(AvaFilter::default().accepts(&internal));
+        // On the reverse strand the end of the target is next to the end of the query
+        let reverse = overlap(
+            ("a", 10_000, 7_000, 10_000),
+            ("b", 8_000, 5_000, 8_000),
+            Strand::Reverse,
+        );
+        assert!(filter.accepts(&reverse));
+    }
+
+    #[test]
+    fn test_classify_overlap() {
+        let params = OverlapParams::default();
+        let kind = |q, t, s| classify_overlap(&overlap(q, t, s), &params).unwrap();
+
+        assert_eq!(
+            kind(
+                ("a", 10_000, 0, 3_000),
+                ("b", 8_000, 5_000, 8_000),
+                Strand::Forward
+            ),
+            OverlapKind::TargetToQuery
+        );
+        // On the reverse strand the start of the target's reverse complement is its end
+        assert_eq!(
+            kind(
+                ("a", 10_000, 7_000, 10_000),
+                ("b", 8_000, 5_000, 8_000),
+                Strand::Reverse
+            ),
+            OverlapKind::QueryToTarget
+        );
+        assert_eq!(
+            kind(
+                ("a", 10_000, 2_000, 5_000),
+                ("b", 3_000, 0, 3_000),
+                Strand::Forward
+            ),
+            OverlapKind::TargetContained
+        );
+        // Small overhangs are tolerated
+        assert_eq!(
+            kind(
+                ("a", 3_000, 50, 3_000),
+                ("b", 10_000, 2_100, 5_000),
+                Strand::Forward
+            ),
+            OverlapKind::QueryContained
+        );
+        // A shared 2 kb region in the middle of both reads
+        assert_eq!(
+            kind(
+                ("a", 10_000, 4_000, 6_000),
+                ("b", 10_000, 3_000, 5_000),
+                Strand::Forward
+            ),
+            OverlapKind::Internal
+        );
+
+        let mut unknown = overlap(("a", 1, 0, 1), ("b", 1, 0, 1), Strand::Forward);
+        unknown.query_len = None;
+        assert_eq!(classify_overlap(&unknown, &params), None);
+    }
+
+    #[test]
+    fn test_overlap_coverage() {
+        let mut coverage = OverlapCoverage::new(OverlapParams::default());
+
+        // A chain of dovetailing reads, a read contained in one of them, and a short read
+        // found in several, as a repeat would be
+        let overlaps = [
+            overlap(
+                ("r1", 10_000, 6_000, 10_000),
+                ("r2", 10_000, 0, 4_000),
+                Strand::Forward,
+            ),
+            overlap(
+                ("r2", 10_000, 6_000, 10_000),
+                ("r3", 10_000, 0, 4_000),
+                Strand::Forward,
+            ),
+            overlap(
+                ("r4", 2_000, 0, 2_000),
+                ("r2", 10_000, 3_000, 5_000),
+                Strand::Forward,
+            ),
+            overlap(
+                ("rep", 2_000, 0, 2_000),
+                ("r1", 10_000, 1_000, 3_000),
+                Strand::Forward,
+            ),
+            overlap(
+                ("rep", 2_000, 0, 2_000),
+                ("r3", 10_000, 5_000, 7_000),
+                Strand::Forward,
+            ),
+            overlap(
+                ("rep", 2_000, 0, 2_000),
+                ("x", 10_000, 5_000, 7_000),
+                Strand::Forward,
+            ),
+            overlap(
+                ("r1", 10_000, 0, 10_000),
+                ("r1", 10_000, 0, 10_000),
+                Strand::Forward,
+            ),
+        ];
+        let kinds: Vec<_> = overlaps.iter().map(|o| coverage.add(o)).collect();
+        assert_eq!(kinds[0], Some(OverlapKind::QueryToTarget));
+        assert_eq!(kinds[2], Some(OverlapKind::QueryContained));
+        assert_eq!(kinds[6], None);
+
+        let r2 = coverage.get("r2").unwrap();
+        assert_eq!(r2.n_overlaps, 3);
+        assert_eq!(r2.n_dovetail, 2);
+        assert_eq!(r2.n_containing, 1);
+        assert_eq!(r2.aligned_bases, 10_000);
+        assert_eq!(r2.mean_depth(), 1.0);
+
+        assert_eq!(coverage.reads().count(), 6);
+        assert_eq!(coverage.read_class("r4", 2.0), Some(ReadClass::Contained));
+        assert_eq!(coverage.read_class("rep", 2.0), Some(ReadClass::Contained));
+        assert_eq!(coverage.median_depth(), 1.0);
+        assert_eq!(coverage.normalized_depth("rep"), Some(3.0));
+        assert_eq!(coverage.read_class("r2", 2.0), Some(ReadClass::Unique));
+        assert_eq!(coverage.read_class("missing", 2.0), None);
+    }
+}
@@ -0,0 +1,532 @@
+//! PAF (Pairwise mApping Format) support for [`Mapping`]s
+//!
+//! Follows the column layout and tag order of minimap2's `mm_write_paf`. PAF files, from this
+//! crate or the minimap2 CLI, can be read back into [`Mapping`]s with [`Mapping::from_paf`] or
+//! [`read_paf`].
+//!
+//! The mappings of both mates of a read pair travel together as [`PairedMappings`], written
+//! with the mate naming of [`MateNaming`].
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{BufRead, Write as IoWrite};
+use std::num::NonZeroI32;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::{Alignment, AlignmentType, DpSkipped, Mapping, Strand};
+
+/// Convert a CIGAR op code to its character
+pub(crate) fn cigar_op_char(op: u8) -> char {
+    match op {
+        0 => 'M',
+        1 => 'I',
+        2 => 'D',
+        3 => 'N',
+        4 => 'S',
+        5 => 'H',
+        6 => 'P',
+        7 => '=',
+        8 => 'X',
+        _ => panic!("Invalid CIGAR code {op}"),
+    }
+}
+
+/// Convert a CIGAR op character to its code
+pub(crate) fn cigar_op_code(op: u8) -> Option<u8> {
+    match op {
+        b'M' => Some(0),
+        b'I' => Some(1),
+        b'D' => Some(2),
+        b'N' => Some(3),
+        b'S' => Some(4),
+        b'H' => Some(5),
+        b'P' => Some(6),
+        b'=' => Some(7),
+        b'X' => Some(8),
+        _ => None,
+    }
+}
+
+/// Parse a CIGAR string such as `10M2I5M`
+pub(crate) fn parse_cigar(cigar: &str) -> Result<Vec<(u32, u8)>, &'static str> {
+    let mut ops = Vec::new();
+    let mut len: Option<u32> = None;
+    for c in cigar.bytes() {
+        if c.is_ascii_digit() {
+            let digit = (c - b'0') as u32;
+            len = Some(
+                len.unwrap_or(0)
+                    .checked_mul(10)
+                    .and_then(|x| x.checked_add(digit))
+                    .ok_or("Invalid CIGAR")?,
+            );
+        } else {
+            let op = cigar_op_code(c).ok_or("Invalid CIGAR")?;
+            ops.push((len.take().ok_or("Invalid CIGAR")?, op));
+        }
+    }
+    if len.is_some() {
+        return Err("Invalid CIGAR");
+    }
+    Ok(ops)
+}
+
+fn parse_field<T: FromStr>(field: Option<&str>) -> Result<T, &'static str> {
+    field
+        .ok_or("Missing PAF column")?
+        .parse()
+        .map_err(|_| "Invalid PAF column")
+}
+
+fn parse_name(field: &str) -> Option<String> {
+    if field == "*" {
+        None
+    } else {
+        Some(field.to_string())
+    }
+}
+
+impl Mapping {
+    /// Parse a PAF line, as written by [`to_paf`](Mapping::to_paf) or the minimap2 CLI
+    ///
+    /// The NM, AS, tp, cg, cs and MD tags are read and other tags ignored. PAF does not tell
+    /// primary and supplementary alignments apart, so `tp:A:P` sets `is_primary` and
+    /// `is_supplementary` is always false; lines without a `tp` tag are taken as primary.
+    /// `tp:A:I` and `tp:A:i` are inversions, as primary and secondary alignments respectively.
+    /// Query and target names are `*` for None.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let line = "read1\t100\t0\t100\t-\tchr1\t1720\t180\t280\t95\t100\t13\tNM:i:5\ttp:A:P\tcg:Z:100M";
+    /// let mapping = Mapping::from_paf(line).unwrap();
+    /// assert_eq!(mapping.strand, Strand::Reverse);
+    /// assert_eq!(mapping.alignment.as_ref().unwrap().cigar, Some(vec![(100, 0)]));
+    /// assert_eq!(mapping.to_paf(), line);
+    /// ```
+    pub fn from_paf(line: &str) -> Result<Mapping, &'static str> {
+        let mut fields = line.trim_end_matches(['\n', '\r']).split('\t');
+
+        let query_name = parse_name(fields.next().ok_or("Missing PAF column")?).map(Arc::new);
+        let query_len: i32 = parse_field(fields.next())?;
+        let query_start = parse_field(fields.next())?;
+        let query_end = parse_field(fields.next())?;
+        let strand = match fields.next() {
+            Some("+") => Strand::Forward,
+            Some("-") => Strand::Reverse,
+            Some(_) => return Err("Invalid PAF strand"),
+            None => return Err("Missing PAF column"),
+        };
+        let target_name = parse_name(fields.next().ok_or("Missing PAF column")?).map(Arc::new);
+        let target_len = parse_field(fields.next())?;
+        let target_start = parse_field(fields.next())?;
+        let target_end = parse_field(fields.next())?;
+        let match_len = parse_field(fields.next())?;
+        let block_len = parse_field(fields.next())?;
+        let mapq = parse_field(fields.next())?;
+
+        let mut is_primary = true;
+        let mut alignment_type = AlignmentType::Primary;
+        let mut alignment: Option<Alignment> = None;
+        for tag in fields {
+            let (name, value) = match (tag.get(..5), tag.get(5..)) {
+                (Some(name), Some(value)) => (name, value),
+                _ => return Err("Invalid PAF tag"),
+            };
+            let aln = || Alignment {
+                nm: 0,
+                cigar: None,
+                cigar_str: None,
+                md: None,
+                cs: None,
+                alignment_score: None,
+                dp_skipped: DpSkipped::None,
+            };
+            match name {
+                "NM:i:" => {
+                    alignment.get_or_insert_with(aln).nm =
+                        value.parse().map_err(|_| "Invalid PAF tag")?
+                }
+                "AS:i:" => {
+                    alignment.get_or_insert_with(aln).alignment_score =
+                        Some(value.parse().map_err(|_| "Invalid PAF tag")?)
+                }
+                "tp:A:" => {
+                    is_primary = matches!(value, "P" | "I");
+                    alignment_type = match value {
+                        "P" => AlignmentType::Primary,
+                        "I" | "i" => AlignmentType::Inversion,
+                        _ => AlignmentType::Secondary,
+                    };
+                }
+                "cg:Z:" => {
+                    let aln = alignment.get_or_insert_with(aln);
+                    aln.cigar = Some(parse_cigar(value)?);
+                    aln.cigar_str = Some(value.to_string());
+                }
+                "cs:Z:" => alignment.get_or_insert_with(aln).cs = Some(value.to_string()),
+                "MD:Z:" => alignment.get_or_insert_with(aln).md = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(Mapping {
+            query_name,
+            query_len: NonZeroI32::new(query_len),
+            query_start,
+            query_end,
+            strand,
+            target_name,
+            target_len,
+            target_start,
+            target_end,
+            match_len,
+            block_len,
+            mapq,
+            is_primary,
+            is_supplementary: false,
+            alignment_type,
+            alignment,
+            target_seq: None,
+        })
+    }
+
+    /// Format the mapping as a single PAF line (without a trailing newline)
+    ///
+    /// The CIGAR is written to the `cg` tag without clipping, as minimap2 does.
+    pub fn to_paf(&self) -> String {
+        let mut line = String::with_capacity(256);
+
+        // Writing to a String can not fail
+        write!(
+            line,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.query_name.as_ref().map_or("*", |x| x.as_str()),
+            self.query_len.map_or(0, |x| x.get()),
+            self.query_start,
+            self.query_end,
+            self.strand,
+            self.target_name.as_ref().map_or("*", |x| x.as_str()),
+            self.target_len,
+            self.target_start,
+            self.target_end,
+            self.match_len,
+            self.block_len,
+            self.mapq,
+        )
+        .unwrap();
+
+        if let Some(aln) = &self.alignment {
+            write!(line, "\tNM:i:{}", aln.nm).unwrap();
+            if let Some(score) = aln.alignment_score {
+                write!(line, "\tAS:i:{}", score).unwrap();
+            }
+        }
+
+        // PAF marks supplementary alignments as primary
+        let primary = self.is_primary || self.is_supplementary;
+        let tp = match (self.alignment_type, primary) {
+            (AlignmentType::Inversion, true) => 'I',
+            (AlignmentType::Inversion, false) => 'i',
+            (_, true) => 'P',
+            (_, false) => 'S',
+        };
+        write!(line, "\ttp:A:{}", tp).unwrap();
+
+        if let Some(aln) = &self.alignment {
+            if let Some(cigar) = &aln.cigar {
+                line.push_str("\tcg:Z:");
+                for (len, op) in cigar.iter().filter(|(_, op)| *op != 4 && *op != 5) {
+                    write!(line, "{}{}", len, cigar_op_char(*op)).unwrap();
+                }
+            }
+            if let Some(cs) = &aln.cs {
+                write!(line, "\tcs:Z:{}", cs).unwrap();
+            }
+            if let Some(md) = &aln.md {
+                write!(line, "\tMD:Z:{}", md).unwrap();
+            }
+        }
+
+        line
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = &'static str;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        Mapping::from_paf(line)
+    }
+}
+
+/// Read the mappings of a PAF file, skipping empty lines
+///
+/// Consecutive mappings of a query share its name, and mappings to the same target share
+/// the target name, as for mappings returned by an [`Aligner`](crate::Aligner).
+///
+/// ```
+/// # use minimap2::*;
+/// let file = std::fs::File::open("test_data/gDNA_vs_genome.paf").unwrap();
+/// let mappings = paf::read_paf(std::io::BufReader::new(file))
+///     .collect::<Result<Vec<Mapping>, _>>()
+///     .unwrap();
+/// assert_eq!(mappings.len(), 7);
+/// ```
+pub fn read_paf<R: BufRead>(reader: R) -> impl Iterator<Item = Result<Mapping, &'static str>> {
+    let mut query_name: Option<Arc<String>> = None;
+    let mut target_names: HashMap<String, Arc<String>> = HashMap::new();
+
+    reader.lines().filter_map(move |line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return Some(Err("Unable to read PAF file")),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        let mut mapping = match Mapping::from_paf(&line) {
+            Ok(mapping) => mapping,
+            Err(e) => return Some(Err(e)),
+        };
+
+        match (&mapping.query_name, &query_name) {
+            (Some(name), Some(previous)) if name == previous => {
+                mapping.query_name = Some(Arc::clone(previous))
+            }
+            _ => query_name = mapping.query_name.clone(),
+        }
+        if let Some(name) = &mapping.target_name {
+            let shared = target_names
+                .entry(name.to_string())
+                .or_insert_with(|| Arc::clone(name));
+            mapping.target_name = Some(Arc::clone(shared));
+        }
+
+        Some(Ok(mapping))
+    })
+}
+
+/// Mate of a read pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mate {
+    First,
+    Second,
+}
+
+impl Mate {
+    /// The `/1` or `/2` read name suffix of the mate
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Mate::First => "/1",
+            Mate::Second => "/2",
+        }
+    }
+}
+
+/// How the mates of a pair are named in PAF
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MateNaming {
+    /// Both mates under the pair name, as the minimap2 CLI writes them
+    #[default]
+    PairName,
+    /// The pair name with `/1` or `/2` appended, so the mates can be told apart
+    Suffix,
+}
+
+/// The mappings of both mates of a read pair, as returned by
+/// [`Aligner::map_pairs`](crate::Aligner::map_pairs)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PairedMappings {
+    pub name: Option<Arc<String>>,
+    pub first: Vec<Mapping>,
+    pub second: Vec<Mapping>,
+}
+
+impl PairedMappings {
+    /// Mappings of a mate
+    pub fn mate(&self, mate: Mate) -> &[Mapping] {
+        match mate {
+            Mate::First => &self.first,
+            Mate::Second => &self.second,
+        }
+    }
+
+    /// Mappings of the first mate then of the second, with the mate of each
+    pub fn iter(&self) -> impl Iterator<Item = (Mate, &Mapping)> {
+        self.first
+            .iter()
+            .map(|m| (Mate::First, m))
+            .chain(self.second.iter().map(|m| (Mate::Second, m)))
+    }
+
+    /// PAF lines of both mates, first mate first (without trailing newlines)
+    pub fn to_paf(&self, naming: MateNaming) -> Vec<String> {
+        self.iter()
+            .map(|(mate, mapping)| {
+                let mut line = mapping.to_paf();
+                if naming == MateNaming::Suffix {
+                    // The query name is the first column
+                    let name_end = line.find('\t').unwrap_or(line.len());
+                    line.insert_str(name_end, mate.suffix());
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// Write the PAF lines of both mates to `writer`
+    pub fn write_paf<W: IoWrite>(
+        &self,
+        writer: &mut W,
+        naming: MateNaming,
+    ) -> Result<(), &'static str> {
+        for line in self.to_paf(naming) {
+            writeln!(writer, "{}", line).map_err(|_| "Unable to write PAF")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mt_seq, Aligner};
+
+    #[test]
+    fn test_read_paf_cli_output() {
+        for path in [
+            "test_data/gDNA_vs_genome.paf",
+            "test_data/query_vs_test_data.paf",
+        ] {
+            let file = std::fs::File::open(path).unwrap();
+            let mappings: Vec<Mapping> = read_paf(std::io::BufReader::new(file))
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let expected = std::fs::read_to_string(path).unwrap();
+            assert_eq!(mappings.len(), expected.lines().count());
+
+            for (mapping, line) in mappings.iter().zip(expected.lines()) {
+                // The 12 columns are written back unchanged, as are the tags kept
+                let paf = mapping.to_paf();
+                let columns = |line: &str| line.split('\t').take(12).collect::<Vec<_>>();
+                assert_eq!(columns(&paf), columns(line));
+                for tag in paf.split('\t').skip(12) {
+                    assert!(line.split('\t').any(|t| t == tag), "{tag} not in {line}");
+                }
+                assert_eq!(Mapping::from_paf(&paf).unwrap(), *mapping);
+            }
+        }
+
+        let file = std::fs::File::open("test_data/gDNA_vs_genome.paf").unwrap();
+        let mappings: Vec<Mapping> = read_paf(std::io::BufReader::new(file))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(Arc::ptr_eq(
+            mappings[0].target_name.as_ref().unwrap(),
+            mappings[1].target_name.as_ref().unwrap()
+        ));
+        assert_eq!(mappings.iter().filter(|m| !m.is_primary).count(), 1);
+    }
+
+    #[test]
+    fn test_paf_round_trip() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let seq = mt_seq();
+        let mut query = seq[1000..2000].to_vec();
+        query.drain(500..510);
+
+        let mappings = aligner
+            .map(&query, true, true, None, None, Some(b"read"))
+            .unwrap();
+        assert!(!mappings.is_empty());
+        for mapping in mappings {
+            let parsed: Mapping = mapping.to_paf().parse().unwrap();
+            let mut expected = mapping.clone();
+            let aln = expected.alignment.as_mut().unwrap();
+            aln.cigar_str = parsed.alignment.as_ref().unwrap().cigar_str.clone();
+            // PAF marks supplementary alignments as primary
+            expected.is_primary |= expected.is_supplementary;
+            expected.is_supplementary = false;
+            if expected.alignment_type == AlignmentType::Supplementary {
+                expected.alignment_type = AlignmentType::Primary;
+            }
+            assert_eq!(parsed, expected);
+        }
+    }
+
+    #[test]
+    fn test_from_paf_errors() {
+        assert_eq!(Mapping::from_paf(""), Err("Missing PAF column"));
+        assert_eq!(
+            Mapping::from_paf("q\t100\t0\t100\t+\tt\t100\t0"),
+            Err("Missing PAF column")
+        );
+        assert_eq!(
+            Mapping::from_paf("q\t100\t0\t100\t.\tt\t100\t0\t100\t100\t100\t60"),
+            Err("Invalid PAF strand")
+        );
+        assert_eq!(
+            Mapping::from_paf("q\t100\t0\t100\t+\tt\t100\t0\t100\t100\t100\t60\tcg:Z:10Q"),
+            Err("Invalid CIGAR")
+        );
+        assert_eq!(
+            parse_cigar("5S10M2I3D"),
+            Ok(vec![(5, 4), (10, 0), (2, 1), (3, 2)])
+        );
+        assert!(parse_cigar("M").is_err());
+        assert!(parse_cigar("10").is_err());
+    }
+
+    #[test]
+    fn test_paired_paf() {
+        let aligner = Aligner::builder()
+            .sr()
+            .with_cigar()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+
+        let chr1 = aligner.target_seq(0).unwrap();
+        let read1 = chr1[100..250].to_vec();
+        let read2 = crate::seq::revcomp(&chr1[400..550]);
+        let pairs = vec![(b"pair".to_vec(), read1, read2)];
+
+        let paired: Vec<PairedMappings> = aligner
+            .map_pairs(pairs, false, false)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(paired.len(), 1);
+        let pair = &paired[0];
+        assert_eq!(pair.name.as_deref().unwrap(), "pair");
+        assert!(!pair.mate(Mate::First).is_empty());
+        assert!(!pair.mate(Mate::Second).is_empty());
+
+        let names = |naming| {
+            pair.to_paf(naming)
+                .iter()
+                .map(|line| line.split('\t').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let expected: Vec<_> = pair
+            .iter()
+            .map(|(mate, _)| format!("pair{}", mate.suffix()))
+            .collect();
+        assert_eq!(names(MateNaming::Suffix), expected);
+        assert!(names(MateNaming::PairName).iter().all(|n| n == "pair"));
+
+        // Apart from the name the lines are those of the mappings
+        let lines = pair.to_paf(MateNaming::Suffix);
+        let first = Mapping::from_paf(&lines[0]).unwrap();
+        assert_eq!(first.query_name.as_deref().unwrap(), "pair/1");
+        assert_eq!(first.target_start, pair.first[0].target_start);
+
+        let mut out = Vec::new();
+        pair.write_paf(&mut out, MateNaming::PairName).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), lines.len());
+    }
+}
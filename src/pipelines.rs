@@ -0,0 +1,147 @@
+//! Ready-made pipelines from a read file to an alignment file
+//!
+//! The `channels` and `rayon` examples show how to drive an [`Aligner`] from threads of your
+//! own. For the common case of mapping a whole FASTA/FASTQ file against a reference, the
+//! functions here build the aligner from [`PipelineOptions`] and run the crate's file drivers
+//! ([`Aligner::map_file_to_paf`], `Aligner::map_file_to_bam`), which map on a pool of worker
+//! threads and write in input order. Fixes to the drivers reach every pipeline, where a copy of
+//! an example would not get them.
+//!
+//! ```no_run
+//! use minimap2::pipelines::{fastq_to_paf, PipelineOptions};
+//! use minimap2::Preset;
+//!
+//! let options = PipelineOptions::new(Preset::MapOnt, "reference.fa", "reads.fastq", "reads.paf")
+//!     .threads(8);
+//! fastq_to_paf(&options).expect("Unable to map");
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use crate::{Aligner, Built, Preset};
+
+/// Inputs, output and settings of a pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineOptions {
+    pub preset: Preset,
+    /// Reference to map against: FASTA/FASTQ, possibly gzipped, or a prebuilt `.mmi` index
+    pub reference: PathBuf,
+    /// Reads to map (FASTA/FASTQ, possibly gzipped)
+    pub reads: PathBuf,
+    pub output: PathBuf,
+    /// Threads used to build the index and to map
+    pub threads: usize,
+    /// Whether to compute base-level alignments (CIGAR and cs), as minimap2 -c does
+    pub cigar: bool,
+}
+
+impl PipelineOptions {
+    /// Options mapping `reads` against `reference` with `preset` into `output`, with base-level
+    /// alignment on 4 threads
+    pub fn new<R, Q, O>(preset: Preset, reference: R, reads: Q, output: O) -> Self
+    where
+        R: AsRef<Path>,
+        Q: AsRef<Path>,
+        O: AsRef<Path>,
+    {
+        PipelineOptions {
+            preset,
+            reference: reference.as_ref().to_path_buf(),
+            reads: reads.as_ref().to_path_buf(),
+            output: output.as_ref().to_path_buf(),
+            threads: 4,
+            cigar: true,
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0, "threads must be positive");
+        self.threads = threads;
+        self
+    }
+
+    pub fn cigar(mut self, cigar: bool) -> Self {
+        self.cigar = cigar;
+        self
+    }
+
+    /// The aligner the pipelines map with
+    pub fn aligner(&self) -> Result<Aligner<Built>, &'static str> {
+        let mut builder = Aligner::builder()
+            .preset(self.preset.clone())
+            .with_index_threads(self.threads);
+        if self.cigar {
+            builder = builder.with_cigar();
+        }
+        builder.with_index(&self.reference, None)
+    }
+}
+
+/// Map the reads into a PAF file
+pub fn fastq_to_paf(options: &PipelineOptions) -> Result<(), &'static str> {
+    options
+        .aligner()?
+        .map_file_to_paf(&options.reads, &options.output, options.threads)
+}
+
+/// Map the reads into a BAM file, with a header listing the targets of the reference
+#[cfg(feature = "htslib")]
+pub fn fastq_to_bam(options: &PipelineOptions) -> Result<(), &'static str> {
+    options
+        .aligner()?
+        .map_file_to_bam(&options.reads, &options.output, options.threads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(output: &str) -> PipelineOptions {
+        PipelineOptions::new(
+            Preset::MapOnt,
+            "test_data/genome.fa",
+            "test_data/gDNA_reads.fq",
+            std::env::temp_dir().join(output),
+        )
+        .threads(2)
+    }
+
+    #[test]
+    fn test_fastq_to_paf() {
+        let options = options("minimap2_test_pipeline.paf");
+        fastq_to_paf(&options).unwrap();
+        let paf = std::fs::read_to_string(&options.output).unwrap();
+        std::fs::remove_file(&options.output).unwrap();
+        assert!(paf.lines().count() > 0);
+        assert!(paf.lines().all(|line| line.contains("\tcg:Z:")));
+
+        let options = options.cigar(false);
+        fastq_to_paf(&options).unwrap();
+        let paf = std::fs::read_to_string(&options.output).unwrap();
+        std::fs::remove_file(&options.output).unwrap();
+        assert!(!paf.contains("\tcg:Z:"));
+
+        let options = PipelineOptions {
+            reads: "test_data/missing.fq".into(),
+            ..options
+        };
+        assert!(fastq_to_paf(&options).is_err());
+    }
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn test_fastq_to_bam() {
+        use rust_htslib::bam::{self, Read};
+
+        let options = options("minimap2_test_pipeline.bam");
+        fastq_to_bam(&options).unwrap();
+        let mut reader = bam::Reader::from_path(&options.output).unwrap();
+        let mapped = reader
+            .records()
+            .map(|record| record.unwrap())
+            .filter(|record| !record.is_unmapped())
+            .count();
+        std::fs::remove_file(&options.output).unwrap();
+        assert!(mapped > 0);
+    }
+}
@@ -0,0 +1,220 @@
+//! Pool of aligners for services mapping against many references
+//!
+//! An [`AlignerPool`] builds or loads the aligner for a reference the first time it is asked
+//! for, shares it as an `Arc` between callers, and evicts the least recently used aligners
+//! once the estimated size of the loaded indexes exceeds a memory budget.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use crate::{Aligner, Built};
+
+type Loader<K> = Box<dyn Fn(&K) -> Result<Aligner<Built>, &'static str> + Send + Sync>;
+
+/// LRU cache of aligners keyed by reference id
+///
+/// ```
+/// use minimap2::pool::AlignerPool;
+/// use minimap2::Aligner;
+///
+/// let pool = AlignerPool::new(1 << 30, |path: &String| {
+///     Aligner::builder().map_ont().with_index(path, None)
+/// });
+///
+/// let aligner = pool.get(&"test_data/MT-human.fa".to_string()).unwrap();
+/// assert_eq!(aligner.n_seq(), 1);
+/// assert_eq!(pool.len(), 1);
+/// ```
+pub struct AlignerPool<K> {
+    loader: Loader<K>,
+    memory_budget: usize,
+    inner: Mutex<PoolInner<K>>,
+}
+
+struct PoolInner<K> {
+    entries: HashMap<K, Entry>,
+    memory_used: usize,
+    clock: u64,
+}
+
+struct Entry {
+    aligner: Arc<Aligner<Built>>,
+    size: usize,
+    last_used: u64,
+}
+
+impl<K: Eq + Hash + Clone> AlignerPool<K> {
+    /// Create a pool that builds aligners with `loader` and keeps the estimated size of their
+    /// indexes (see [`Aligner::index_size_estimate`]) within `memory_budget` bytes
+    ///
+    /// An aligner larger than the whole budget is still returned and kept until the next one
+    /// is loaded.
+    pub fn new<F>(memory_budget: usize, loader: F) -> Self
+    where
+        F: Fn(&K) -> Result<Aligner<Built>, &'static str> + Send + Sync + 'static,
+    {
+        AlignerPool {
+            loader: Box::new(loader),
+            memory_budget,
+            inner: Mutex::new(PoolInner {
+                entries: HashMap::new(),
+                memory_used: 0,
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Get the aligner for `key`, loading it if it is not in the pool
+    ///
+    /// Loading happens without holding the pool lock, so other references stay available
+    /// meanwhile. If two callers load the same reference at once, the first to finish is kept.
+    /// Evicted aligners stay valid for callers still holding them.
+    pub fn get(&self, key: &K) -> Result<Arc<Aligner<Built>>, &'static str> {
+        if let Some(aligner) = self.lock().touch(key) {
+            return Ok(aligner);
+        }
+
+        let aligner = Arc::new((self.loader)(key)?);
+        let size = aligner.index_size_estimate();
+
+        let mut inner = self.lock();
+        if let Some(aligner) = inner.touch(key) {
+            return Ok(aligner);
+        }
+
+        while !inner.entries.is_empty() && inner.memory_used + size > self.memory_budget {
+            inner.evict_lru();
+        }
+
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.memory_used += size;
+        inner.entries.insert(
+            key.clone(),
+            Entry {
+                aligner: Arc::clone(&aligner),
+                size,
+                last_used,
+            },
+        );
+
+        Ok(aligner)
+    }
+
+    /// Remove the aligner for `key` from the pool, returning whether it was loaded
+    pub fn remove(&self, key: &K) -> bool {
+        let mut inner = self.lock();
+        match inner.entries.remove(key) {
+            Some(entry) => {
+                inner.memory_used -= entry.size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the aligner for `key` is loaded
+    pub fn contains(&self, key: &K) -> bool {
+        self.lock().entries.contains_key(key)
+    }
+
+    /// Number of loaded aligners
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Estimated size in bytes of the loaded indexes
+    pub fn memory_used(&self) -> usize {
+        self.lock().memory_used
+    }
+
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolInner<K>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K: Eq + Hash + Clone> PoolInner<K> {
+    fn touch(&mut self, key: &K) -> Option<Arc<Aligner<Built>>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            Arc::clone(&entry.aligner)
+        })
+    }
+
+    fn evict_lru(&mut self) {
+        let lru = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+
+        if let Some(key) = lru {
+            let entry = self.entries.remove(&key).unwrap();
+            self.memory_used -= entry.size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_aligner_pool_lru() {
+        let loads = Arc::new(AtomicUsize::new(0));
+        let loads_ = Arc::clone(&loads);
+        let loader = move |path: &&str| {
+            loads_.fetch_add(1, Ordering::Relaxed);
+            Aligner::builder().map_ont().with_index(path, None)
+        };
+
+        let genome = "test_data/genome.fa";
+        let mt = "test_data/MT-human.fa";
+
+        // Room for both
+        let pool = AlignerPool::new(usize::MAX, loader.clone());
+        let a = pool.get(&genome).unwrap();
+        let b = pool.get(&genome).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        pool.get(&mt).unwrap();
+        assert_eq!(pool.len(), 2);
+        assert_eq!(loads.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            pool.memory_used(),
+            a.index_size_estimate() + pool.get(&mt).unwrap().index_size_estimate()
+        );
+
+        // Room for only one at a time
+        loads.store(0, Ordering::Relaxed);
+        let mt_size = pool.get(&mt).unwrap().index_size_estimate();
+        let budget = a.index_size_estimate().max(mt_size) + 1;
+        let pool = AlignerPool::new(budget, loader);
+        let genome_aligner = pool.get(&genome).unwrap();
+        pool.get(&mt).unwrap();
+        assert!(!pool.contains(&genome));
+        assert!(pool.contains(&mt));
+        assert_eq!(pool.len(), 1);
+
+        // Evicted aligners remain usable
+        assert_eq!(genome_aligner.n_seq(), 2);
+
+        pool.get(&genome).unwrap();
+        assert_eq!(loads.load(Ordering::Relaxed), 3);
+        assert!(pool.remove(&genome));
+        assert!(!pool.remove(&genome));
+        assert!(pool.is_empty());
+        assert_eq!(pool.memory_used(), 0);
+    }
+}
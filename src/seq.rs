@@ -0,0 +1,127 @@
+//! Nucleotide sequence utilities
+//!
+//! Reverse complement and minimap2's nucleotide encoding (A=0, C=1, G=2, T/U=3, anything
+//! else 4), as used by the index and by `mm_idx_getseq`.
+
+/// minimap2's `seq_nt4_table`: maps an ASCII base to its 2-bit code, or 4 for other characters
+pub const SEQ_NT4: [u8; 256] = {
+    let mut table = [4u8; 256];
+    table[b'A' as usize] = 0;
+    table[b'a' as usize] = 0;
+    table[b'C' as usize] = 1;
+    table[b'c' as usize] = 1;
+    table[b'G' as usize] = 2;
+    table[b'g' as usize] = 2;
+    table[b'T' as usize] = 3;
+    table[b't' as usize] = 3;
+    table[b'U' as usize] = 3;
+    table[b'u' as usize] = 3;
+    table
+};
+
+/// Complement of an IUPAC nucleotide code, keeping case. U complements to A; characters that
+/// are not nucleotide codes are returned unchanged.
+pub fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' | b'U' => b'A',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' | b'u' => b'a',
+        b'r' => b'y',
+        b'y' => b'r',
+        b'k' => b'm',
+        b'm' => b'k',
+        b'b' => b'v',
+        b'v' => b'b',
+        b'd' => b'h',
+        b'h' => b'd',
+        // S, W, N and non-nucleotides are their own complement
+        _ => base,
+    }
+}
+
+/// Reverse complement of a sequence, see [`complement`]
+///
+/// ```
+/// use minimap2::seq::revcomp;
+/// assert_eq!(revcomp(b"ACGTNacgtR"), b"YacgtNACGT");
+/// ```
+pub fn revcomp(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|&b| complement(b)).collect()
+}
+
+/// Reverse complement a sequence in place, see [`complement`]
+pub fn revcomp_in_place(seq: &mut [u8]) {
+    seq.reverse();
+    for base in seq.iter_mut() {
+        *base = complement(*base);
+    }
+}
+
+/// Encode a sequence to minimap2's 2-bit codes (4 for anything but ACGTU)
+///
+/// ```
+/// use minimap2::seq::{decode, encode};
+/// assert_eq!(encode(b"ACGTNu"), vec![0, 1, 2, 3, 4, 3]);
+/// assert_eq!(decode(&encode(b"acgtRu")), b"ACGTNT");
+/// ```
+pub fn encode(seq: &[u8]) -> Vec<u8> {
+    seq.iter().map(|&b| SEQ_NT4[b as usize]).collect()
+}
+
+/// Decode 2-bit codes to uppercase ACGT, with N for codes of 4 or more
+pub fn decode(codes: &[u8]) -> Vec<u8> {
+    let mut seq = codes.to_vec();
+    decode_in_place(&mut seq);
+    seq
+}
+
+/// Decode 2-bit codes to uppercase ACGT in place, with N for codes of 4 or more
+pub fn decode_in_place(codes: &mut [u8]) {
+    for base in codes.iter_mut() {
+        *base = b"ACGTN"[(*base).min(4) as usize];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seq_nt4_matches_minimap2() {
+        let table = unsafe { minimap2_sys::seq_nt4_table };
+        assert_eq!(SEQ_NT4, table);
+    }
+
+    #[test]
+    fn test_revcomp() {
+        let seq = b"ACGTURYKMBVDHSWNacgturykmbvdhswn-";
+        assert_eq!(
+            revcomp(&revcomp(seq)),
+            seq.iter()
+                .map(|&b| match b {
+                    b'U' => b'T',
+                    b'u' => b't',
+                    b => b,
+                })
+                .collect::<Vec<u8>>()
+        );
+
+        let mut in_place = seq.to_vec();
+        revcomp_in_place(&mut in_place);
+        assert_eq!(in_place, revcomp(seq));
+        assert_eq!(revcomp(b""), b"");
+    }
+}
@@ -0,0 +1,650 @@
+//! Destinations for mapping results
+//!
+//! The file drivers ([`Aligner::map_file_to_sink`](crate::Aligner::map_file_to_sink)) hand
+//! the mappings of each read, in input order, to an [`OutputSink`]. [`PafSink`] and
+//! [`TsvSink`] write text files, `htslib::BamSink` writes BAM with the htslib feature, and
+//! any other destination (a database, a message queue) only needs to implement the trait.
+//! Closures taking the read id and its mappings are sinks too.
+//!
+//! Reads that do not map, or whose mappings fail a filter, can be written back out with a
+//! [`FastqWriter`] for re-analysis, see
+//! [`Aligner::map_file_split`](crate::Aligner::map_file_split).
+
+use std::io::Write;
+
+use crate::Mapping;
+
+/// Receiver of the mappings of each read
+pub trait OutputSink {
+    /// Write the mappings of a read, empty if it did not map
+    fn write(&mut self, read_id: &[u8], mappings: &[Mapping]) -> Result<(), &'static str>;
+
+    /// Called once after the last read, e.g. to flush buffered output
+    fn finish(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Write out buffered output, called before a checkpoint is saved (see
+    /// [`checkpoint`](crate::checkpoint))
+    fn flush(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+}
+
+impl<F> OutputSink for F
+where
+    F: FnMut(&[u8], &[Mapping]) -> Result<(), &'static str>,
+{
+    fn write(&mut self, read_id: &[u8], mappings: &[Mapping]) -> Result<(), &'static str> {
+        self(read_id, mappings)
+    }
+}
+
+/// Writes mappings as PAF, one line per mapping; unmapped reads are skipped as in minimap2
+pub struct PafSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PafSink<W> {
+    pub fn new(writer: W) -> Self {
+        PafSink { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl PafSink<std::io::BufWriter<std::fs::File>> {
+    /// Create (or truncate) a PAF file
+    pub fn create<P: AsRef<std::path::Path>>(path: P) -> Result<Self, &'static str> {
+        let file = std::fs::File::create(path).map_err(|_| "Unable to create output file")?;
+        Ok(PafSink::new(std::io::BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> OutputSink for PafSink<W> {
+    fn write(&mut self, _read_id: &[u8], mappings: &[Mapping]) -> Result<(), &'static str> {
+        for mapping in mappings {
+            writeln!(self.writer, "{}", mapping.to_paf()).map_err(|_| "Unable to write PAF")?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), &'static str> {
+        self.writer.flush().map_err(|_| "Unable to write PAF")
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.finish()
+    }
+}
+
+/// Columns written by [`TsvSink`], in order
+pub const TSV_COLUMNS: [&str; 17] = [
+    "query_name",
+    "query_len",
+    "query_start",
+    "query_end",
+    "strand",
+    "target_name",
+    "target_len",
+    "target_start",
+    "target_end",
+    "match_len",
+    "block_len",
+    "mapq",
+    "is_primary",
+    "is_supplementary",
+    "nm",
+    "alignment_score",
+    "cigar",
+];
+
+/// Writes mappings as a tab-separated table with a header line, one row per mapping, for
+/// loading into data frames. Unmapped reads get a row with only the query name and length
+/// filled if `write_unmapped` is set; missing values are empty.
+pub struct TsvSink<W: Write> {
+    writer: W,
+    write_unmapped: bool,
+    header_written: bool,
+}
+
+impl<W: Write> TsvSink<W> {
+    pub fn new(writer: W, write_unmapped: bool) -> Self {
+        TsvSink {
+            writer,
+            write_unmapped,
+            header_written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        if !self.header_written {
+            self.header_written = true;
+            writeln!(self.writer, "{}", TSV_COLUMNS.join("\t"))?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, read_id: &[u8], mapping: &Mapping) -> std::io::Result<()> {
+        let aln = mapping.alignment.as_ref();
+        let opt = |x: Option<i32>| x.map(|x| x.to_string()).unwrap_or_default();
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            mapping
+                .query_name
+                .as_deref()
+                .map(|name| name.as_str())
+                .unwrap_or(&String::from_utf8_lossy(read_id)),
+            opt(mapping.query_len.map(|len| len.get())),
+            mapping.query_start,
+            mapping.query_end,
+            mapping.strand,
+            mapping
+                .target_name
+                .as_deref()
+                .map_or("", |name| name.as_str()),
+            mapping.target_len,
+            mapping.target_start,
+            mapping.target_end,
+            mapping.match_len,
+            mapping.block_len,
+            mapping.mapq,
+            mapping.is_primary,
+            mapping.is_supplementary,
+            opt(aln.map(|aln| aln.nm)),
+            opt(aln.and_then(|aln| aln.alignment_score)),
+            aln.and_then(|aln| aln.cigar_str.as_deref()).unwrap_or(""),
+        )
+    }
+}
+
+impl TsvSink<std::io::BufWriter<std::fs::File>> {
+    /// Create (or truncate) a TSV file
+    pub fn create<P: AsRef<std::path::Path>>(
+        path: P,
+        write_unmapped: bool,
+    ) -> Result<Self, &'static str> {
+        let file = std::fs::File::create(path).map_err(|_| "Unable to create output file")?;
+        Ok(TsvSink::new(std::io::BufWriter::new(file), write_unmapped))
+    }
+}
+
+impl<W: Write> OutputSink for TsvSink<W> {
+    fn write(&mut self, read_id: &[u8], mappings: &[Mapping]) -> Result<(), &'static str> {
+        const ERROR: &str = "Unable to write TSV";
+        self.write_header().map_err(|_| ERROR)?;
+        for mapping in mappings {
+            self.write_row(read_id, mapping).map_err(|_| ERROR)?;
+        }
+        if mappings.is_empty() && self.write_unmapped {
+            writeln!(
+                self.writer,
+                "{}{}",
+                String::from_utf8_lossy(read_id),
+                "\t".repeat(TSV_COLUMNS.len() - 1)
+            )
+            .map_err(|_| ERROR)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), &'static str> {
+        self.write_header()
+            .and_then(|_| self.writer.flush())
+            .map_err(|_| "Unable to write TSV")
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.writer.flush().map_err(|_| "Unable to write TSV")
+    }
+}
+
+/// Writes reads back out as FASTQ, e.g. those left unmapped or filtered out
+///
+/// The read id is written as given, so comments read with it (everything after the first
+/// space of the header line) are kept. Reads without qualities (from FASTA) are written as
+/// FASTA records.
+pub struct FastqWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> FastqWriter<W> {
+    pub fn new(writer: W) -> Self {
+        FastqWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write a read, `id` including any comment
+    pub fn write_read(
+        &mut self,
+        id: &[u8],
+        seq: &[u8],
+        qual: Option<&[u8]>,
+    ) -> Result<(), &'static str> {
+        let result = match qual {
+            Some(qual) => {
+                if qual.len() != seq.len() {
+                    return Err("Sequence and quality strings are different lengths");
+                }
+                [&b"@"[..], id, b"\n", seq, b"\n+\n", qual, b"\n"]
+                    .iter()
+                    .try_for_each(|part| self.writer.write_all(part))
+            }
+            None => [&b">"[..], id, b"\n", seq, b"\n"]
+                .iter()
+                .try_for_each(|part| self.writer.write_all(part)),
+        };
+        result.map_err(|_| "Unable to write FASTQ")
+    }
+
+    pub fn finish(&mut self) -> Result<(), &'static str> {
+        self.writer.flush().map_err(|_| "Unable to write FASTQ")
+    }
+}
+
+impl FastqWriter<std::io::BufWriter<std::fs::File>> {
+    /// Create (or truncate) a FASTQ file
+    pub fn create<P: AsRef<std::path::Path>>(path: P) -> Result<Self, &'static str> {
+        let file = std::fs::File::create(path).map_err(|_| "Unable to create output file")?;
+        Ok(FastqWriter::new(std::io::BufWriter::new(file)))
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub use self::parquet_sink::{parquet_schema, ParquetSink};
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use arrow_array::builder::{BooleanBuilder, Int32Builder, StringBuilder, UInt32Builder};
+    use arrow_array::{ArrayRef, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+
+    use super::OutputSink;
+    use crate::Mapping;
+
+    /// Rows buffered before they are written out as a row group
+    pub const DEFAULT_BATCH_ROWS: usize = 64 * 1024;
+
+    const ERROR: &str = "Unable to write Parquet";
+
+    /// Arrow schema of the files written by [`ParquetSink`]: the [`TSV_COLUMNS`](super::TSV_COLUMNS)
+    /// followed by cs and MD, with alignment columns null for mappings without an alignment
+    pub fn parquet_schema() -> SchemaRef {
+        let int = |name, nullable| Field::new(name, DataType::Int32, nullable);
+        let string = |name, nullable| Field::new(name, DataType::Utf8, nullable);
+        Arc::new(Schema::new(vec![
+            string("query_name", false),
+            int("query_len", true),
+            int("query_start", false),
+            int("query_end", false),
+            string("strand", false),
+            string("target_name", true),
+            int("target_len", false),
+            int("target_start", false),
+            int("target_end", false),
+            int("match_len", false),
+            int("block_len", false),
+            Field::new("mapq", DataType::UInt32, false),
+            Field::new("is_primary", DataType::Boolean, false),
+            Field::new("is_supplementary", DataType::Boolean, false),
+            int("nm", true),
+            int("alignment_score", true),
+            string("cigar", true),
+            string("cs", true),
+            string("md", true),
+        ]))
+    }
+
+    #[derive(Default)]
+    struct Columns {
+        query_name: StringBuilder,
+        query_len: Int32Builder,
+        query_start: Int32Builder,
+        query_end: Int32Builder,
+        strand: StringBuilder,
+        target_name: StringBuilder,
+        target_len: Int32Builder,
+        target_start: Int32Builder,
+        target_end: Int32Builder,
+        match_len: Int32Builder,
+        block_len: Int32Builder,
+        mapq: UInt32Builder,
+        is_primary: BooleanBuilder,
+        is_supplementary: BooleanBuilder,
+        nm: Int32Builder,
+        alignment_score: Int32Builder,
+        cigar: StringBuilder,
+        cs: StringBuilder,
+        md: StringBuilder,
+    }
+
+    impl Columns {
+        fn push(&mut self, read_id: &[u8], mapping: &Mapping) {
+            match mapping.query_name.as_deref() {
+                Some(name) => self.query_name.append_value(name),
+                None => self
+                    .query_name
+                    .append_value(String::from_utf8_lossy(read_id)),
+            }
+            self.query_len
+                .append_option(mapping.query_len.map(|len| len.get()));
+            self.query_start.append_value(mapping.query_start);
+            self.query_end.append_value(mapping.query_end);
+            self.strand.append_value(mapping.strand.to_string());
+            self.target_name
+                .append_option(mapping.target_name.as_deref());
+            self.target_len.append_value(mapping.target_len);
+            self.target_start.append_value(mapping.target_start);
+            self.target_end.append_value(mapping.target_end);
+            self.match_len.append_value(mapping.match_len);
+            self.block_len.append_value(mapping.block_len);
+            self.mapq.append_value(mapping.mapq);
+            self.is_primary.append_value(mapping.is_primary);
+            self.is_supplementary.append_value(mapping.is_supplementary);
+
+            let aln = mapping.alignment.as_ref();
+            self.nm.append_option(aln.map(|aln| aln.nm));
+            self.alignment_score
+                .append_option(aln.and_then(|aln| aln.alignment_score));
+            self.cigar
+                .append_option(aln.and_then(|aln| aln.cigar_str.as_deref()));
+            self.cs.append_option(aln.and_then(|aln| aln.cs.as_deref()));
+            self.md.append_option(aln.and_then(|aln| aln.md.as_deref()));
+        }
+
+        fn len(&self) -> usize {
+            self.query_start.len()
+        }
+
+        fn finish(&mut self, schema: SchemaRef) -> Result<RecordBatch, &'static str> {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(self.query_name.finish()),
+                Arc::new(self.query_len.finish()),
+                Arc::new(self.query_start.finish()),
+                Arc::new(self.query_end.finish()),
+                Arc::new(self.strand.finish()),
+                Arc::new(self.target_name.finish()),
+                Arc::new(self.target_len.finish()),
+                Arc::new(self.target_start.finish()),
+                Arc::new(self.target_end.finish()),
+                Arc::new(self.match_len.finish()),
+                Arc::new(self.block_len.finish()),
+                Arc::new(self.mapq.finish()),
+                Arc::new(self.is_primary.finish()),
+                Arc::new(self.is_supplementary.finish()),
+                Arc::new(self.nm.finish()),
+                Arc::new(self.alignment_score.finish()),
+                Arc::new(self.cigar.finish()),
+                Arc::new(self.cs.finish()),
+                Arc::new(self.md.finish()),
+            ];
+            RecordBatch::try_new(schema, columns).map_err(|_| ERROR)
+        }
+    }
+
+    /// Writes mappings as Parquet, one row per mapping with the schema of [`parquet_schema`],
+    /// for querying with DataFusion, DuckDB or polars. Unmapped reads are skipped.
+    ///
+    /// Rows are buffered and written as a row group every `batch_rows` mappings; the file is
+    /// only valid once [`finish`](OutputSink::finish) has written the footer.
+    ///
+    /// ```no_run
+    /// use minimap2::sink::ParquetSink;
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_cigar()
+    ///     .with_index("ReferenceFile.fasta", None)
+    ///     .expect("Unable to build index");
+    /// let mut sink = ParquetSink::create("reads.parquet").unwrap();
+    /// aligner
+    ///     .map_file_to_sink("reads.fastq", &mut sink, 4)
+    ///     .expect("Unable to map");
+    /// ```
+    pub struct ParquetSink<W: Write + Send> {
+        writer: Option<ArrowWriter<W>>,
+        inner: Option<W>,
+        schema: SchemaRef,
+        columns: Columns,
+        batch_rows: usize,
+    }
+
+    impl<W: Write + Send> ParquetSink<W> {
+        /// Write Snappy-compressed Parquet to `writer`
+        pub fn new(writer: W) -> Result<Self, &'static str> {
+            let props = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            Self::with_properties(writer, props)
+        }
+
+        /// Write Parquet with the given writer properties (compression, statistics, ...)
+        pub fn with_properties(writer: W, props: WriterProperties) -> Result<Self, &'static str> {
+            let schema = parquet_schema();
+            let writer = ArrowWriter::try_new(writer, schema.clone(), Some(props))
+                .map_err(|_| "Unable to create Parquet writer")?;
+            Ok(ParquetSink {
+                writer: Some(writer),
+                inner: None,
+                schema,
+                columns: Columns::default(),
+                batch_rows: DEFAULT_BATCH_ROWS,
+            })
+        }
+
+        /// Set the number of mappings buffered per row group
+        pub fn with_batch_rows(mut self, batch_rows: usize) -> Self {
+            assert!(batch_rows > 0, "batch_rows must be positive");
+            self.batch_rows = batch_rows;
+            self
+        }
+
+        /// The underlying writer, once [`finish`](OutputSink::finish) has been called
+        pub fn into_inner(self) -> Option<W> {
+            self.inner
+        }
+
+        fn flush_rows(&mut self) -> Result<(), &'static str> {
+            if self.columns.len() == 0 {
+                return Ok(());
+            }
+            let batch = self.columns.finish(self.schema.clone())?;
+            self.writer
+                .as_mut()
+                .ok_or("Parquet sink already finished")?
+                .write(&batch)
+                .map_err(|_| ERROR)
+        }
+    }
+
+    impl ParquetSink<std::io::BufWriter<std::fs::File>> {
+        /// Create (or truncate) a Parquet file
+        pub fn create<P: AsRef<std::path::Path>>(path: P) -> Result<Self, &'static str> {
+            let file = std::fs::File::create(path).map_err(|_| "Unable to create output file")?;
+            ParquetSink::new(std::io::BufWriter::new(file))
+        }
+    }
+
+    impl<W: Write + Send> OutputSink for ParquetSink<W> {
+        fn write(&mut self, read_id: &[u8], mappings: &[Mapping]) -> Result<(), &'static str> {
+            if self.writer.is_none() {
+                return Err("Parquet sink already finished");
+            }
+            for mapping in mappings {
+                self.columns.push(read_id, mapping);
+                if self.columns.len() >= self.batch_rows {
+                    self.flush_rows()?;
+                }
+            }
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), &'static str> {
+            self.flush_rows()?;
+            let writer = self.writer.take().ok_or("Parquet sink already finished")?;
+            let mut inner = writer.into_inner().map_err(|_| ERROR)?;
+            inner.flush().map_err(|_| ERROR)?;
+            self.inner = Some(inner);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Alignment, Strand};
+    use std::num::NonZeroI32;
+    use std::sync::Arc;
+
+    fn mapping() -> Mapping {
+        Mapping {
+            query_name: Some(Arc::new("read1".to_string())),
+            query_len: NonZeroI32::new(100),
+            query_start: 5,
+            query_end: 95,
+            strand: Strand::Reverse,
+            target_name: Some(Arc::new("chr1".to_string())),
+            target_len: 1000,
+            target_start: 200,
+            target_end: 290,
+            match_len: 88,
+            block_len: 90,
+            mapq: 60,
+            is_primary: true,
+            alignment: Some(Alignment {
+                nm: 2,
+                cigar: Some(vec![(90, 0)]),
+                cigar_str: Some("90M".to_string()),
+                md: None,
+                cs: None,
+                alignment_score: Some(170),
+                dp_skipped: Default::default(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_paf_sink() {
+        let mut sink = PafSink::new(Vec::new());
+        sink.write(b"read1", &[mapping()]).unwrap();
+        sink.write(b"read2", &[]).unwrap();
+        sink.finish().unwrap();
+        let paf = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(paf, format!("{}\n", mapping().to_paf()));
+    }
+
+    #[test]
+    fn test_fastq_writer() {
+        let mut writer = FastqWriter::new(Vec::new());
+        writer
+            .write_read(b"read1 runid=1", b"ACGT", Some(b"II#I"))
+            .unwrap();
+        writer.write_read(b"read2", b"GG", None).unwrap();
+        assert_eq!(
+            writer.write_read(b"read3", b"ACGT", Some(b"II")),
+            Err("Sequence and quality strings are different lengths")
+        );
+        writer.finish().unwrap();
+        assert_eq!(
+            writer.into_inner(),
+            b"@read1 runid=1\nACGT\n+\nII#I\n>read2\nGG\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_tsv_sink() {
+        let mut sink = TsvSink::new(Vec::new(), true);
+        sink.write(b"read1", &[mapping()]).unwrap();
+        sink.write(b"read2", &[]).unwrap();
+        sink.finish().unwrap();
+        let tsv = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines[0], TSV_COLUMNS.join("\t"));
+        assert_eq!(
+            lines[1],
+            "read1\t100\t5\t95\t-\tchr1\t1000\t200\t290\t88\t90\t60\ttrue\tfalse\t2\t170\t90M"
+        );
+        assert_eq!(
+            lines[2].split('\t').collect::<Vec<_>>().len(),
+            TSV_COLUMNS.len()
+        );
+        assert!(lines[2].starts_with("read2\t"));
+        assert_eq!(lines.len(), 3);
+
+        // The header is written even without reads
+        let mut sink = TsvSink::new(Vec::new(), false);
+        sink.finish().unwrap();
+        assert_eq!(
+            sink.into_inner(),
+            format!("{}\n", TSV_COLUMNS.join("\t")).into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_closure_sink() {
+        let mut n_mappings = 0;
+        let mut sink = |_: &[u8], mappings: &[Mapping]| -> Result<(), &'static str> {
+            n_mappings += mappings.len();
+            Ok(())
+        };
+        sink.write(b"read1", &[mapping(), mapping()]).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(n_mappings, 2);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_sink() {
+        use arrow_array::{Array, Int32Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let path = std::env::temp_dir().join("minimap2_test_parquet_sink.parquet");
+        let mut sink = ParquetSink::create(&path).unwrap().with_batch_rows(2);
+        let mut unaligned = mapping();
+        unaligned.alignment = None;
+        sink.write(b"read1", &[mapping(), mapping(), unaligned])
+            .unwrap();
+        sink.write(b"read2", &[]).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(
+            sink.write(b"read3", &[]),
+            Err("Parquet sink already finished")
+        );
+
+        let file = std::fs::File::open(&path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(reader.schema(), &parquet_schema());
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let batches: Vec<_> = reader.build().unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+
+        let last = batches.last().unwrap();
+        let column = |name| last.column(last.schema().index_of(name).unwrap()).clone();
+        let names = column("target_name");
+        let names = names.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(names.value(0), "chr1");
+        let nm = column("nm");
+        let nm = nm.as_any().downcast_ref::<Int32Array>().unwrap();
+        assert!(nm.is_null(nm.len() - 1));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,564 @@
+//! Minimizer sketches, computed exactly as minimap2 does for its index
+//!
+//! [`sketch`] wraps `mm_sketch` so that containment and screening tools can reuse minimap2's
+//! (w,k)-minimizers without building an index. [`Aligner::sketch`] uses the parameters of an
+//! aligner's index, and [`Aligner::screen`] estimates the containment of a query in each
+//! target from shared minimizers alone. [`Aligner::kmer_positions`] looks a single k-mer up in
+//! the index, e.g. for presence/absence marker genotyping. [`Aligner::with_minimizers`] goes
+//! the other way and builds an index from minimizers computed elsewhere.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::sync::Arc;
+
+use minimap2_sys::{
+    mm128_t, mm128_v, mm2rs_idx_from_minimizers, mm_idx_get, mm_idx_t, mm_sketch, MM_I_HPC,
+};
+
+use crate::{AcceptsParams, Aligner, BuilderState, Built, Strand};
+
+/// A minimizer of a sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Minimizer {
+    /// Hash of the k-mer, as stored in minimap2 indexes
+    pub hash: u64,
+    /// Position of the last base of the k-mer (0-based), as in minimap2
+    pub pos: u32,
+    /// Strand the k-mer was taken from: minimizers are canonical, so a k-mer and its reverse
+    /// complement have the same hash
+    pub strand: Strand,
+    /// Number of bases covered by the k-mer: k, or more with homopolymer compression
+    pub span: u32,
+}
+
+/// Compute the (w,k)-minimizers of a sequence, optionally homopolymer-compressed (`hpc`)
+///
+/// Requires 0 < k <= 28 and 0 < w < 256, as minimap2 does. Minimizers are returned in order
+/// of position; k-mers containing bases other than ACGTU are skipped.
+///
+/// ```
+/// use minimap2::sketch::sketch;
+/// let seq = b"GATCACAGGTCTATCACCCTATTAACCACTCACGGGAGCTCTCCATGCATTTGGTATTTTCGTCTGGGGGGTATGCACGC";
+/// let minimizers = sketch(seq, 15, 10, false).unwrap();
+/// assert!(!minimizers.is_empty());
+/// assert!(minimizers.iter().all(|m| m.span == 15 && m.pos >= 14));
+/// ```
+pub fn sketch(seq: &[u8], k: usize, w: usize, hpc: bool) -> Result<Vec<Minimizer>, &'static str> {
+    if k == 0 || k > 28 {
+        return Err("k must be between 1 and 28");
+    }
+    if w == 0 || w > 255 {
+        return Err("w must be between 1 and 255");
+    }
+    if seq.len() > i32::MAX as usize {
+        return Err("Sequence is too long");
+    }
+    if seq.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut p = mm128_v {
+        n: 0,
+        m: 0,
+        a: std::ptr::null_mut(),
+    };
+
+    unsafe {
+        // Without a kalloc pool, minimap2 allocates the output with malloc
+        mm_sketch(
+            std::ptr::null_mut(),
+            seq.as_ptr() as *const libc::c_char,
+            seq.len() as i32,
+            w as i32,
+            k as i32,
+            0,
+            hpc as i32,
+            &mut p,
+        );
+
+        let minimizers = if p.a.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(p.a, p.n)
+                .iter()
+                .map(|m| Minimizer {
+                    hash: m.x >> 8,
+                    pos: (m.y as u32) >> 1,
+                    strand: if m.y & 1 == 0 {
+                        Strand::Forward
+                    } else {
+                        Strand::Reverse
+                    },
+                    span: (m.x & 0xff) as u32,
+                })
+                .collect()
+        };
+        libc::free(p.a as *mut c_void);
+
+        Ok(minimizers)
+    }
+}
+
+/// Containment of a query in a target of the index, returned by [`Aligner::screen`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreenHit {
+    pub target_id: u32,
+    pub target_name: Arc<String>,
+    /// Number of distinct minimizers of the query found in the target
+    pub shared: usize,
+    /// Fraction of the distinct minimizers of the query found in the target
+    pub containment: f64,
+    /// Identity estimated from the containment as `containment^(1/k)`, as Mash screen does
+    pub identity: f64,
+}
+
+/// An occurrence of a k-mer in the index, returned by [`Aligner::kmer_positions`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KmerHit {
+    pub target_id: u32,
+    pub target_name: Arc<String>,
+    /// Position of the last base of the k-mer on the target (0-based), as in minimap2
+    pub pos: u32,
+    /// Forward if the target has the k-mer as given, Reverse if it has its reverse complement
+    pub strand: Strand,
+}
+
+impl<S> Aligner<S>
+where
+    S: BuilderState + AcceptsParams,
+{
+    /// Build the index from minimizers computed by the caller, e.g. by an accelerated or
+    /// experimental sketcher, instead of by minimap2. Mapping then seeds from these
+    /// minimizers and uses minimap2's chaining and alignment as usual.
+    ///
+    /// `minimizers[i]` are the minimizers of `seqs[i]`, in any order. Queries are still
+    /// sketched by minimap2 with the k, w and homopolymer compression of the index options,
+    /// so the hashes must be those of [`sketch`] for queries to find them; which k-mers are
+    /// kept is up to the caller. As `with_seqs_and_ids`, this also sets mapopt.mid_occ to 1000
+    /// and handles repeated IDs as set with `with_duplicate_names`.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// use minimap2::sketch::sketch;
+    /// let seq = b"GATCACAGGTCTATCACCCTATTAACCACTCACGGGAGCTCTCCATGCATTTGGTATTTTCGTCTGGGGGGTATGCACGCGATAGCATTGCGAGACGCTGGAGCCGGAGCACCCTATGTCGCAGTATCTGTCTTTGATTCCTGCCTCATCCTATTATTTATCGCACCTACGTTCAATATTACAGG".to_vec();
+    /// // Keep every other minimizer
+    /// let minimizers: Vec<_> = sketch(&seq, 15, 10, false).unwrap().into_iter().step_by(2).collect();
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_minimizers(&[seq.clone()], &[b"ref".to_vec()], &[minimizers])
+    ///     .unwrap();
+    /// let hits = aligner.map(&seq[20..180], false, false, None, None, None).unwrap();
+    /// assert_eq!(hits[0].target_name.as_ref().unwrap().as_str(), "ref");
+    /// ```
+    pub fn with_minimizers(
+        self,
+        seqs: &[Vec<u8>],
+        ids: &[Vec<u8>],
+        minimizers: &[Vec<Minimizer>],
+    ) -> Result<Aligner<Built>, &'static str> {
+        assert!(
+            self.idx.is_none(),
+            "Index already set. Can not set sequence as index."
+        );
+        if seqs.len() != ids.len() || seqs.len() != minimizers.len() {
+            return Err("Number of sequences, IDs and minimizer lists must be equal");
+        }
+        if seqs.is_empty() {
+            return Err("Must have at least one sequence");
+        }
+        self.check_opts()?;
+
+        let mut mini: Vec<mm128_t> = Vec::with_capacity(minimizers.iter().map(Vec::len).sum());
+        for (rid, (seq, minimizers)) in seqs.iter().zip(minimizers).enumerate() {
+            for m in minimizers {
+                if m.hash >> 56 != 0 || m.span == 0 || m.span > 255 {
+                    return Err("Minimizer hash or span out of range");
+                }
+                if m.pos as usize >= seq.len() || m.pos + 1 < m.span {
+                    return Err("Minimizer lies outside its sequence");
+                }
+                mini.push(mm128_t {
+                    x: m.hash << 8 | m.span as u64,
+                    y: (rid as u64) << 32
+                        | (m.pos as u64) << 1
+                        | (m.strand == Strand::Reverse) as u64,
+                });
+            }
+        }
+
+        let seqs: Vec<std::ffi::CString> = seqs
+            .iter()
+            .map(|s| std::ffi::CString::new(s.clone()).map_err(|_| "Invalid Sequence"))
+            .collect::<Result<_, _>>()?;
+        let mut seen = HashSet::new();
+        let ids: Vec<std::ffi::CString> = ids
+            .iter()
+            .map(|id| self.duplicate_names.rename(id, &mut seen))
+            .map(|id| std::ffi::CString::new(id?).map_err(|_| "Invalid ID"))
+            .collect::<Result<_, _>>()?;
+        let seq_ptrs: Vec<*const libc::c_char> = seqs.iter().map(|s| s.as_ptr()).collect();
+        let id_ptrs: Vec<*const libc::c_char> = ids.iter().map(|s| s.as_ptr()).collect();
+
+        let idx = unsafe {
+            mm2rs_idx_from_minimizers(
+                self.idxopt.w as i32,
+                self.idxopt.k as i32,
+                (self.idxopt.flag & 1) as i32,
+                self.idxopt.bucket_bits as i32,
+                seqs.len() as i32,
+                seq_ptrs.as_ptr() as *mut *const libc::c_char,
+                id_ptrs.as_ptr() as *mut *const libc::c_char,
+                mini.len(),
+                mini.as_ptr(),
+            )
+        };
+        if idx.is_null() {
+            return Err("Unable to build index from minimizers");
+        }
+
+        Ok(self.with_string_index(idx))
+    }
+}
+
+impl Aligner<Built> {
+    /// Estimate the containment of a query in each target of the index from minimizer hits
+    /// alone, without chaining or alignment, e.g. to pre-filter targets before mapping
+    ///
+    /// Returns the targets sharing at least one minimizer with the query, by decreasing
+    /// number of shared minimizers. Minimizers are counted once per target however often
+    /// they occur, so repeats weigh no more than unique sequence.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let read = aligner.fetch_subseq("chr2", 100..400, Strand::Reverse).unwrap();
+    /// let hits = aligner.screen(&read).unwrap();
+    /// assert_eq!(hits[0].target_name.as_str(), "chr2");
+    /// assert_eq!(hits[0].containment, 1.0);
+    /// ```
+    pub fn screen(&self, seq: &[u8]) -> Result<Vec<ScreenHit>, &'static str> {
+        let idx: &mm_idx_t = self.idx.as_ref().ok_or("No index")?;
+
+        let mut hashes: Vec<u64> = self.sketch(seq)?.iter().map(|m| m.hash).collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+
+        let mut shared: HashMap<u32, usize> = HashMap::new();
+        let mut rids: Vec<u32> = Vec::new();
+        for hash in &hashes {
+            let mut n: i32 = 0;
+            let hits = unsafe { mm_idx_get(idx, *hash, &mut n) };
+            if hits.is_null() || n <= 0 {
+                continue;
+            }
+
+            // Each hit is rid << 32 | last position << 1 | strand
+            let hits = unsafe { std::slice::from_raw_parts(hits, n as usize) };
+            rids.clear();
+            rids.extend(hits.iter().map(|hit| (hit >> 32) as u32));
+            rids.sort_unstable();
+            rids.dedup();
+            for rid in &rids {
+                *shared.entry(*rid).or_insert(0) += 1;
+            }
+        }
+
+        let names = &self.target_names().names;
+        let mut screen: Vec<ScreenHit> = shared
+            .into_iter()
+            .map(|(rid, shared)| {
+                let containment = shared as f64 / hashes.len() as f64;
+                ScreenHit {
+                    target_id: rid,
+                    target_name: Arc::clone(&names[rid as usize]),
+                    shared,
+                    containment,
+                    identity: containment.powf(1.0 / idx.k as f64),
+                }
+            })
+            .collect();
+        screen.sort_by(|a, b| b.shared.cmp(&a.shared).then(a.target_id.cmp(&b.target_id)));
+
+        Ok(screen)
+    }
+
+    /// Find where a k-mer occurs in the index, on either strand
+    ///
+    /// `kmer` must be exactly one k-mer of the index's k (before homopolymer compression, if
+    /// the index uses it) of ACGT bases. The index only holds the minimizers of the targets,
+    /// so a k-mer that is never a minimizer is not found even where the target has it: markers
+    /// should be chosen among the minimizers of the targets, e.g. with [`Aligner::sketch`].
+    /// Hits are sorted by target and position; high-occurrence k-mers are not filtered.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let aligner = Aligner::builder()
+    ///     .map_ont()
+    ///     .with_index("test_data/genome.fa", None)
+    ///     .unwrap();
+    /// let chr1 = aligner.fetch_subseq("chr1", 0..1720, Strand::Forward).unwrap();
+    /// let marker = aligner.sketch(&chr1).unwrap()[3];
+    /// let end = marker.pos as usize + 1;
+    /// let hits = aligner.kmer_positions(&chr1[end - 15..end]).unwrap();
+    /// assert!(hits.iter().any(|h| h.target_name.as_str() == "chr1" && h.pos == marker.pos));
+    /// ```
+    pub fn kmer_positions(&self, kmer: &[u8]) -> Result<Vec<KmerHit>, &'static str> {
+        let idx: &mm_idx_t = self.idx.as_ref().ok_or("No index")?;
+
+        let minimizers = sketch(kmer, idx.k as usize, 1, idx.flag as u32 & MM_I_HPC != 0)?;
+        let minimizer = match minimizers.as_slice() {
+            [m] if m.span as usize == kmer.len() => *m,
+            _ => return Err("Sequence is not a single k-mer of the index"),
+        };
+
+        let mut n: i32 = 0;
+        let hits = unsafe { mm_idx_get(idx, minimizer.hash, &mut n) };
+        if hits.is_null() || n <= 0 {
+            return Ok(Vec::new());
+        }
+
+        let names = &self.target_names().names;
+        let hits = unsafe { std::slice::from_raw_parts(hits, n as usize) };
+        let mut positions: Vec<KmerHit> = hits
+            .iter()
+            .map(|hit| {
+                let target_id = (hit >> 32) as u32;
+                // Same canonical orientation on the target as the k-mer given
+                let same = (hit & 1 == 1) == (minimizer.strand == Strand::Reverse);
+                KmerHit {
+                    target_id,
+                    target_name: Arc::clone(&names[target_id as usize]),
+                    pos: (*hit as u32) >> 1,
+                    strand: if same {
+                        Strand::Forward
+                    } else {
+                        Strand::Reverse
+                    },
+                }
+            })
+            .collect();
+        positions.sort_by_key(|h| (h.target_id, h.pos));
+
+        Ok(positions)
+    }
+
+    /// Whether a k-mer is in the index, see [`kmer_positions`](Aligner::kmer_positions)
+    pub fn contains_kmer(&self, kmer: &[u8]) -> Result<bool, &'static str> {
+        Ok(!self.kmer_positions(kmer)?.is_empty())
+    }
+
+    /// Compute the minimizers of a sequence with the k, w and homopolymer compression of this
+    /// aligner's index, see [`sketch`]
+    pub fn sketch(&self, seq: &[u8]) -> Result<Vec<Minimizer>, &'static str> {
+        let idx: &mm_idx_t = self.idx.as_ref().ok_or("No index")?;
+        sketch(
+            seq,
+            idx.k as usize,
+            idx.w as usize,
+            idx.flag as u32 & MM_I_HPC != 0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seq::revcomp;
+
+    const SEQ: &[u8] = b"GATCACAGGTCTATCACCCTATTAACCACTCACGGGAGCTCTCCATGCATTTGGTATTTTCGTCTGGGGGGTATGCACGCGATAGCATTGCGAGACGCTGGAGCCGGAGCACCCTATGTCGCAGTATCTGTCTTTGATTCCTGCCTCATCCTATTATTTATCGCACCTACGTTCAATATTACAGGCGAACATACTTACTAAAGTGTGTTAATTAATTAATGCTTGTAGGACATAATAATAACAATTGAATGTCTGCACAGCCACTTTCCACACAGACATCATAACAAAAAATTTCCACCAAACCCCCCCTCCCCCGCTTCTGGCCACAGCACTTAAACACATCTCTGCCAAACCCCAAAAACAAAGAACCCTAACACCAGCCTAACCAGATTTCAAATTTTATCTTTTGGCGGTATGCACTTTTAACAGTCACCCCCCAACTAACACATTATTTTCCCCTCCCACTCCCATACTACTAATCTCATCAATACAACCCCCGCCCATCCTACCCAGCACACACACACCGCTGCTAACCCCATACCCCGAACCAACCAAACCCCAAAGACACCCCCCACAGTTTATGTAGCTTACC";
+
+    #[test]
+    fn test_sketch() {
+        let minimizers = sketch(SEQ, 15, 10, false).unwrap();
+        // At least one minimizer per window of w k-mers
+        assert!(minimizers.len() >= (SEQ.len() - 14) / 10);
+        assert!(minimizers.windows(2).all(|m| m[0].pos < m[1].pos));
+
+        // Minimizers are canonical: the reverse complement has the same hashes, on the
+        // other strand
+        let rc = sketch(&revcomp(SEQ), 15, 10, false).unwrap();
+        let mut hashes: Vec<u64> = minimizers.iter().map(|m| m.hash).collect();
+        let mut rc_hashes: Vec<u64> = rc.iter().map(|m| m.hash).collect();
+        hashes.sort();
+        rc_hashes.sort();
+        assert_eq!(hashes, rc_hashes);
+        let first = minimizers[0];
+        let rc_first = rc
+            .iter()
+            .find(|m| m.pos as usize == SEQ.len() - 1 - (first.pos as usize + 1 - 15))
+            .unwrap();
+        assert_eq!(rc_first.hash, first.hash);
+        assert_ne!(rc_first.strand, first.strand);
+
+        // Homopolymer compression: runs count once and widen the span
+        let hpc = sketch(SEQ, 15, 10, true).unwrap();
+        assert!(hpc.iter().any(|m| m.span > 15));
+
+        assert_eq!(sketch(b"", 15, 10, false), Ok(Vec::new()));
+        assert_eq!(
+            sketch(b"NNNNNNNNNNNNNNNNNNNN", 15, 10, false),
+            Ok(Vec::new())
+        );
+        assert!(sketch(SEQ, 29, 10, false).is_err());
+        assert!(sketch(SEQ, 15, 256, false).is_err());
+    }
+
+    #[test]
+    fn test_aligner_sketch() {
+        let aligner = Aligner::builder()
+            .map_hifi()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        assert_eq!(
+            aligner.sketch(SEQ).unwrap(),
+            sketch(SEQ, 19, 19, false).unwrap()
+        );
+
+        let aligner = Aligner::builder()
+            .map_pb()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        assert_eq!(
+            aligner.sketch(SEQ).unwrap(),
+            sketch(SEQ, 19, 10, true).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_with_minimizers() {
+        let query = &SEQ[50..450];
+
+        // The minimizers of minimap2 itself give the same index as with_seqs_and_ids
+        let reference = Aligner::builder()
+            .map_ont()
+            .with_seqs_and_ids(&[SEQ.to_vec()], &[b"mt".to_vec()])
+            .unwrap();
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_minimizers(
+                &[SEQ.to_vec()],
+                &[b"mt".to_vec()],
+                &[sketch(SEQ, 15, 10, false).unwrap()],
+            )
+            .unwrap();
+        let expected = reference.map(query, true, false, None, None, None).unwrap();
+        let mappings = aligner.map(query, true, false, None, None, None).unwrap();
+        assert_eq!(mappings, expected);
+        assert_eq!(aligner.n_seq(), 1);
+
+        // A sparser sketch still maps
+        let sparse: Vec<Minimizer> = sketch(SEQ, 15, 10, false)
+            .unwrap()
+            .into_iter()
+            .step_by(3)
+            .collect();
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_minimizers(&[SEQ.to_vec()], &[b"mt".to_vec()], &[sparse.clone()])
+            .unwrap();
+        let mappings = aligner.map(query, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_start, 50);
+        assert_eq!(mappings[0].target_end, 450);
+
+        let mut outside = sparse[0];
+        outside.pos = SEQ.len() as u32;
+        assert_eq!(
+            Aligner::builder()
+                .map_ont()
+                .with_minimizers(&[SEQ.to_vec()], &[b"mt".to_vec()], &[vec![outside]])
+                .err(),
+            Some("Minimizer lies outside its sequence")
+        );
+        assert!(Aligner::builder()
+            .map_ont()
+            .with_minimizers(&[SEQ.to_vec()], &[b"mt".to_vec()], &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_screen() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+
+        let read = aligner
+            .fetch_subseq("chr1", 200..1200, Strand::Forward)
+            .unwrap();
+        let hits = aligner.screen(&read).unwrap();
+        assert_eq!(hits[0].target_id, 0);
+        assert_eq!(hits[0].containment, 1.0);
+        assert_eq!(hits[0].identity, 1.0);
+        assert_eq!(hits[0].shared, {
+            let mut hashes: Vec<u64> = aligner
+                .sketch(&read)
+                .unwrap()
+                .iter()
+                .map(|m| m.hash)
+                .collect();
+            hashes.sort_unstable();
+            hashes.dedup();
+            hashes.len()
+        });
+
+        // One substitution every 50 bases lowers containment and estimated identity
+        let mut mutated = read.clone();
+        for i in (25..mutated.len()).step_by(50) {
+            mutated[i] = if mutated[i] == b'A' { b'C' } else { b'A' };
+        }
+        let hits = aligner.screen(&mutated).unwrap();
+        assert_eq!(hits[0].target_id, 0);
+        assert!(hits[0].containment < 1.0 && hits[0].containment > 0.2);
+        assert!(hits[0].identity < 1.0 && hits[0].identity > 0.9);
+
+        assert_eq!(
+            aligner.screen(b"NNNNNNNNNNNNNNNNNNNNNNNNNNNNNN"),
+            Ok(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_kmer_positions() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", None)
+            .unwrap();
+        let chr2 = aligner
+            .fetch_subseq("chr2", 0..460, Strand::Forward)
+            .unwrap();
+
+        // Every minimizer of a target is found at its position, on both strands
+        for m in aligner.sketch(&chr2).unwrap() {
+            let end = m.pos as usize + 1;
+            let kmer = &chr2[end - 15..end];
+            let hits = aligner.kmer_positions(kmer).unwrap();
+            let hit = hits
+                .iter()
+                .find(|h| h.target_id == 1 && h.pos == m.pos)
+                .unwrap();
+            assert_eq!(hit.target_name.as_str(), "chr2");
+            assert_eq!(hit.strand, Strand::Forward);
+
+            let hits = aligner.kmer_positions(&revcomp(kmer)).unwrap();
+            let hit = hits
+                .iter()
+                .find(|h| h.target_id == 1 && h.pos == m.pos)
+                .unwrap();
+            assert_eq!(hit.strand, Strand::Reverse);
+        }
+
+        assert_eq!(aligner.contains_kmer(b"ACGTACGTACGTACG"), Ok(false));
+        assert_eq!(
+            aligner.kmer_positions(b"ACGTACGTACGTACGT"),
+            Err("Sequence is not a single k-mer of the index")
+        );
+        assert_eq!(
+            aligner.kmer_positions(b"ACGTACGNACGTACG"),
+            Err("Sequence is not a single k-mer of the index")
+        );
+    }
+}
@@ -0,0 +1,464 @@
+//! Splice junction scoring
+//!
+//! Scores the introns (`N` CIGAR operations) of a spliced [`Mapping`] by the dinucleotides at
+//! their donor and acceptor sites. The scoring table is configurable, as splice-site spectra
+//! vary between organisms.
+//!
+//! Splice scores in minimap2's `--spsc` format can be loaded with [`read_splice_scores`] and
+//! queried per site or per [`Junction`]. minimap2 2.28 has no `mm_idx_spsc_read`, so they are
+//! not passed on to the C library.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::seq::revcomp;
+use crate::{Aligner, Built, Mapping, Strand};
+
+/// Score of a donor/acceptor dinucleotide pair, given on the transcript strand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpliceMotif {
+    pub donor: [u8; 2],
+    pub acceptor: [u8; 2],
+    pub score: i32,
+}
+
+/// Scoring table for [`Aligner::score_junctions`]
+///
+/// The default scores GT-AG at 0, GC-AG and AT-AC at -4 and any other intron at -9, minimap2's
+/// default non-canonical splicing penalty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JunctionScoring {
+    pub motifs: Vec<SpliceMotif>,
+    /// Score of an intron matching none of the motifs
+    pub non_canonical: i32,
+}
+
+impl Default for JunctionScoring {
+    fn default() -> Self {
+        JunctionScoring {
+            motifs: vec![
+                SpliceMotif::new(b"GT", b"AG", 0),
+                SpliceMotif::new(b"GC", b"AG", -4),
+                SpliceMotif::new(b"AT", b"AC", -4),
+            ],
+            non_canonical: -9,
+        }
+    }
+}
+
+impl SpliceMotif {
+    pub fn new(donor: &[u8; 2], acceptor: &[u8; 2], score: i32) -> Self {
+        SpliceMotif {
+            donor: donor.map(|b| b.to_ascii_uppercase()),
+            acceptor: acceptor.map(|b| b.to_ascii_uppercase()),
+            score,
+        }
+    }
+}
+
+impl JunctionScoring {
+    /// Score an intron from the first and last two bases of its target sequence (forward
+    /// strand of the target). Returns the score and the transcript strand of the best
+    /// matching motif, or None for non-canonical introns. Ties go to the forward strand.
+    pub fn score(&self, start: [u8; 2], end: [u8; 2]) -> (i32, Option<Strand>) {
+        let start = start.map(|b| b.to_ascii_uppercase());
+        let end = end.map(|b| b.to_ascii_uppercase());
+
+        let mut best: Option<(i32, Strand)> = None;
+        for motif in &self.motifs {
+            // On the reverse strand GT-AG reads CT-AC on the target
+            let candidates = [
+                (motif.donor, motif.acceptor, Strand::Forward),
+                (rc2(motif.acceptor), rc2(motif.donor), Strand::Reverse),
+            ];
+
+            for (motif_start, motif_end, strand) in candidates {
+                if motif_start != start || motif_end != end {
+                    continue;
+                }
+                if let Some((score, _)) = best {
+                    if score >= motif.score {
+                        continue;
+                    }
+                }
+                best = Some((motif.score, strand));
+            }
+        }
+
+        match best {
+            Some((score, strand)) => (score, Some(strand)),
+            None => (self.non_canonical, None),
+        }
+    }
+}
+
+fn rc2(x: [u8; 2]) -> [u8; 2] {
+    let rc = revcomp(&x);
+    [rc[0], rc[1]]
+}
+
+/// An intron of a spliced alignment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Junction {
+    /// First base of the intron on the target
+    pub target_start: i32,
+    /// End of the intron on the target (exclusive)
+    pub target_end: i32,
+    /// First two bases of the intron on the forward strand of the target
+    pub start_motif: [u8; 2],
+    /// Last two bases of the intron on the forward strand of the target
+    pub end_motif: [u8; 2],
+    /// Transcript strand implied by the motif, None for non-canonical introns
+    pub strand: Option<Strand>,
+    pub score: i32,
+}
+
+/// Side of an intron a splice score applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpliceSite {
+    /// 5' end of the intron on the transcript strand (`D` in the file)
+    Donor,
+    /// 3' end of the intron on the transcript strand (`A` in the file)
+    Acceptor,
+}
+
+/// Splice scores loaded by [`read_splice_scores`]
+///
+/// Offsets are intron boundaries on the forward strand of the target: the first base of the
+/// intron or the base after its end. On the forward strand the donor is at the start of the
+/// intron, on the reverse strand at its end.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpliceScores {
+    scores: HashMap<String, HashMap<(u32, Strand, SpliceSite), i32>>,
+    len: usize,
+}
+
+impl SpliceScores {
+    /// Score of a splice site, None if the file has no score for it
+    pub fn score(&self, contig: &str, pos: u32, strand: Strand, site: SpliceSite) -> Option<i32> {
+        self.scores.get(contig)?.get(&(pos, strand, site)).copied()
+    }
+
+    /// Donor and acceptor scores of an intron on the strand of its motif, None for
+    /// non-canonical introns or when either site has no score
+    pub fn junction_score(&self, contig: &str, junction: &Junction) -> Option<(i32, i32)> {
+        let strand = junction.strand?;
+        let (start, end) = (junction.target_start as u32, junction.target_end as u32);
+        let (donor, acceptor) = match strand {
+            Strand::Forward => (start, end),
+            Strand::Reverse => (end, start),
+        };
+        Some((
+            self.score(contig, donor, strand, SpliceSite::Donor)?,
+            self.score(contig, acceptor, strand, SpliceSite::Acceptor)?,
+        ))
+    }
+
+    /// Number of scored sites
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Contigs with at least one scored site
+    pub fn contigs(&self) -> impl Iterator<Item = &str> {
+        self.scores.keys().map(|c| c.as_str())
+    }
+
+    /// All scored sites as (contig, offset, strand, site, score), in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32, Strand, SpliceSite, i32)> {
+        self.scores.iter().flat_map(|(contig, sites)| {
+            sites.iter().map(move |(&(pos, strand, site), &score)| {
+                (contig.as_str(), pos, strand, site, score)
+            })
+        })
+    }
+}
+
+/// Read splice scores in minimap2's `--spsc` format
+///
+/// Each line has five tab-separated fields: contig, 0-based offset, strand (`+`/`-`), site
+/// (`D` or `A`) and an integer score. Empty lines and lines starting with `#` are skipped; a
+/// site listed twice keeps its last score.
+///
+/// ```
+/// # use minimap2::*;
+/// use minimap2::splice::{read_splice_scores, SpliceSite};
+///
+/// let scores = read_splice_scores("chr1\t100\t+\tD\t3\nchr1\t200\t+\tA\t-2\n".as_bytes()).unwrap();
+/// assert_eq!(scores.score("chr1", 100, Strand::Forward, SpliceSite::Donor), Some(3));
+/// assert_eq!(scores.score("chr1", 100, Strand::Reverse, SpliceSite::Donor), None);
+/// ```
+pub fn read_splice_scores<R: BufRead>(reader: R) -> Result<SpliceScores, &'static str> {
+    let mut scores = SpliceScores::default();
+
+    for line in reader.lines() {
+        let line = line.map_err(|_| "Error reading splice score file")?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            return Err("Splice score line has fewer than 5 fields");
+        }
+        let pos = fields[1]
+            .parse()
+            .map_err(|_| "Invalid splice score offset")?;
+        let strand = match fields[2] {
+            "+" => Strand::Forward,
+            "-" => Strand::Reverse,
+            _ => return Err("Invalid splice score strand"),
+        };
+        let site = match fields[3] {
+            "D" => SpliceSite::Donor,
+            "A" => SpliceSite::Acceptor,
+            _ => return Err("Invalid splice site type"),
+        };
+        let score = fields[4].parse().map_err(|_| "Invalid splice score")?;
+
+        let sites = scores.scores.entry(fields[0].to_string()).or_default();
+        if sites.insert((pos, strand, site), score).is_none() {
+            scores.len += 1;
+        }
+    }
+
+    Ok(scores)
+}
+
+impl Aligner<Built> {
+    /// Score the splice junctions (`N` CIGAR operations) of a mapping
+    ///
+    /// The mapping needs a CIGAR (`with_cigar()`) and the index its sequences. Motifs are
+    /// read from the forward strand of the target and matched against both strands, so
+    /// reverse-strand transcripts (CT-AC on the target for GT-AG) are recognised whatever the
+    /// strand of the read.
+    pub fn score_junctions(
+        &self,
+        mapping: &Mapping,
+        scoring: &JunctionScoring,
+    ) -> Result<Vec<Junction>, &'static str> {
+        let cigar = mapping
+            .alignment
+            .as_ref()
+            .and_then(|aln| aln.cigar.as_ref())
+            .ok_or("Mapping has no CIGAR")?;
+        let target_name = mapping
+            .target_name
+            .as_ref()
+            .ok_or("Mapping has no target")?;
+        let rid = self
+            .target_id(target_name)
+            .ok_or("Target not found in index")?;
+
+        let mut junctions = Vec::new();
+        let mut pos = mapping.target_start;
+
+        for &(len, op) in cigar {
+            let len = len as i32;
+            match op {
+                // M, D, =, X consume the target
+                0 | 2 | 7 | 8 => pos += len,
+                3 => {
+                    let (start, end) = (pos, pos + len);
+                    let start_motif = self.motif(rid, start)?;
+                    let end_motif = self.motif(rid, end - 2)?;
+                    let (score, strand) = scoring.score(start_motif, end_motif);
+
+                    junctions.push(Junction {
+                        target_start: start,
+                        target_end: end,
+                        start_motif,
+                        end_motif,
+                        strand,
+                        score,
+                    });
+                    pos = end;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(junctions)
+    }
+
+    fn motif(&self, rid: u32, start: i32) -> Result<[u8; 2], &'static str> {
+        if start < 0 {
+            return Err("Range out of bounds");
+        }
+        let start = start as u32;
+        let seq = self.fetch_subseq_by_id(rid, start..start + 2, Strand::Forward)?;
+        Ok([seq[0], seq[1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Alignment, DpSkipped};
+    use std::sync::Arc;
+
+    // Exons separated by introns with the given first and last two bases
+    fn reference(introns: &[(&[u8; 2], &[u8; 2])]) -> Vec<u8> {
+        let exon = b"ACCTGATTCAGGCTAACGTTCGATCCAGTACGGATTACACGGTTCAAGCTTACGGATCCA";
+        let mut seq = exon.to_vec();
+        for (start, end) in introns {
+            seq.extend_from_slice(*start);
+            seq.resize(seq.len() + 96, b'T');
+            seq.extend_from_slice(*end);
+            seq.extend_from_slice(exon);
+        }
+        seq
+    }
+
+    fn spliced_mapping(n_introns: usize, strand: Strand) -> Mapping {
+        let mut cigar = vec![(60, 0)];
+        for _ in 0..n_introns {
+            cigar.extend([(100, 3), (60, 0)]);
+        }
+        Mapping {
+            target_name: Some(Arc::new("ref".to_string())),
+            target_start: 0,
+            target_end: 60 + 160 * n_introns as i32,
+            strand,
+            alignment: Some(Alignment {
+                nm: 0,
+                cigar: Some(cigar),
+                cigar_str: None,
+                md: None,
+                cs: None,
+                alignment_score: None,
+                dp_skipped: DpSkipped::None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_score_junctions() {
+        let introns: [(&[u8; 2], &[u8; 2]); 5] = [
+            (b"GT", b"AG"),
+            (b"CT", b"AC"),
+            (b"GC", b"AG"),
+            (b"CT", b"GC"),
+            (b"GA", b"TC"),
+        ];
+        let aligner = Aligner::builder()
+            .splice()
+            .with_seqs_and_ids(&[reference(&introns)], &[b"ref".to_vec()])
+            .unwrap();
+
+        // The read strand does not change the transcript strand of the motifs
+        for read_strand in [Strand::Forward, Strand::Reverse] {
+            let junctions = aligner
+                .score_junctions(
+                    &spliced_mapping(introns.len(), read_strand),
+                    &JunctionScoring::default(),
+                )
+                .unwrap();
+
+            let summary: Vec<_> = junctions.iter().map(|j| (j.strand, j.score)).collect();
+            assert_eq!(
+                summary,
+                vec![
+                    (Some(Strand::Forward), 0),
+                    (Some(Strand::Reverse), 0),
+                    (Some(Strand::Forward), -4),
+                    (Some(Strand::Reverse), -4),
+                    (None, -9),
+                ]
+            );
+
+            assert_eq!(
+                (junctions[1].target_start, junctions[1].target_end),
+                (220, 320)
+            );
+            assert_eq!(junctions[1].start_motif, *b"CT");
+            assert_eq!(junctions[1].end_motif, *b"AC");
+        }
+
+        // Custom table: GC-AG as good as GT-AG, no AT-AC
+        let scoring = JunctionScoring {
+            motifs: vec![
+                SpliceMotif::new(b"GT", b"AG", 2),
+                SpliceMotif::new(b"gc", b"ag", 2),
+            ],
+            non_canonical: -20,
+        };
+        let junctions = aligner
+            .score_junctions(&spliced_mapping(introns.len(), Strand::Forward), &scoring)
+            .unwrap();
+        let scores: Vec<_> = junctions.iter().map(|j| j.score).collect();
+        assert_eq!(scores, vec![2, 2, 2, 2, -20]);
+    }
+
+    #[test]
+    fn test_read_splice_scores() {
+        let file = "# comment\n\
+            ref\t60\t+\tD\t5\n\
+            ref\t160\t+\tA\t-1\n\
+            ref\t380\t-\tD\t2\n\
+            ref\t280\t-\tA\t4\n\
+            \n\
+            ref\t60\t+\tD\t6\n\
+            other\t10\t-\tA\t0\n";
+        let scores = read_splice_scores(file.as_bytes()).unwrap();
+
+        assert_eq!(scores.len(), 5);
+        let mut contigs: Vec<_> = scores.contigs().collect();
+        contigs.sort();
+        assert_eq!(contigs, vec!["other", "ref"]);
+        assert_eq!(scores.iter().count(), 5);
+
+        // Last score wins
+        assert_eq!(
+            scores.score("ref", 60, Strand::Forward, SpliceSite::Donor),
+            Some(6)
+        );
+        assert_eq!(
+            scores.score("ref", 60, Strand::Forward, SpliceSite::Acceptor),
+            None
+        );
+        assert_eq!(
+            scores.score("missing", 60, Strand::Forward, SpliceSite::Donor),
+            None
+        );
+
+        let junction = |target_start, target_end, strand| Junction {
+            target_start,
+            target_end,
+            start_motif: *b"GT",
+            end_motif: *b"AG",
+            strand,
+            score: 0,
+        };
+        assert_eq!(
+            scores.junction_score("ref", &junction(60, 160, Some(Strand::Forward))),
+            Some((6, -1))
+        );
+        assert_eq!(
+            scores.junction_score("ref", &junction(280, 380, Some(Strand::Reverse))),
+            Some((2, 4))
+        );
+        assert_eq!(scores.junction_score("ref", &junction(60, 160, None)), None);
+
+        assert!(read_splice_scores("ref\t60\t+\tD\n".as_bytes()).is_err());
+        assert!(read_splice_scores("ref\t60\t.\tD\t1\n".as_bytes()).is_err());
+        assert!(read_splice_scores("ref\t60\t+\tX\t1\n".as_bytes()).is_err());
+        assert!(read_splice_scores("".as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_junction_scoring_ties() {
+        // AT-AT reads AT-AT on both strands, the forward strand wins the tie
+        let scoring = JunctionScoring {
+            motifs: vec![SpliceMotif::new(b"AT", b"AT", 1)],
+            non_canonical: 0,
+        };
+        assert_eq!(scoring.score(*b"at", *b"AT"), (1, Some(Strand::Forward)));
+        assert_eq!(scoring.score(*b"GT", *b"AG"), (0, None));
+    }
+}
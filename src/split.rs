@@ -0,0 +1,592 @@
+//! Mapping against every part of a multi-part index
+//!
+//! minimap2 splits an index into parts when the reference exceeds the index batch size
+//! (`-I`, `idxopt.batch_size`). Hits found in one part know nothing of the others, so a read
+//! matching a repeat split across parts would be reported as a unique primary in each.
+//! The minimap2 command line reconciles them with `--split-prefix`: the hits of all parts are
+//! pooled, then sorted, primary and secondary hits re-selected and MAPQ recomputed as if
+//! they came from one index. [`SplitAligner`] does the same in memory.
+//!
+//! Parts can also be read one at a time with [`Aligner::index_parts`], e.g. to map a batch
+//! of reads against each part in turn without holding the whole index in memory.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use libc::c_void;
+use minimap2_sys::*;
+
+use crate::{
+    check_hits, free_hits, with_buf, AcceptsParams, Aligner, BuilderState, Built, Mapping,
+};
+
+/// Aligner over all parts of an index, built with
+/// [`with_index_parts`](Aligner::with_index_parts)
+#[derive(Clone)]
+pub struct SplitAligner {
+    parts: Vec<Aligner<Built>>,
+    // Id of the first target of each part, in the order of the index
+    offsets: Vec<u32>,
+}
+
+/// Reader over the parts of an index, built with [`index_parts`](Aligner::index_parts)
+///
+/// Each part is read when the iterator is advanced, so only the parts in use need be held in
+/// memory. The index reader stays open until the last part has been read or the iterator is
+/// dropped.
+pub struct IndexPartsIter {
+    reader: *mut mm_idx_reader_t,
+    // Settings shared by the aligners of all parts, without an index
+    template: Aligner<Built>,
+}
+
+// The reader is only used through &mut self
+unsafe impl Send for IndexPartsIter {}
+
+impl IndexPartsIter {
+    /// Whether all parts have been read
+    pub fn is_eof(&self) -> bool {
+        self.reader.is_null() || unsafe { mm_idx_reader_eof(self.reader) } != 0
+    }
+
+    fn close(&mut self) {
+        if !self.reader.is_null() {
+            unsafe { mm_idx_reader_close(self.reader) };
+            self.reader = std::ptr::null_mut();
+        }
+    }
+}
+
+impl Iterator for IndexPartsIter {
+    type Item = Aligner<Built>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.is_null() {
+            return None;
+        }
+
+        let idx = unsafe { mm_idx_reader_read(self.reader, self.template.threads as libc::c_int) };
+        if idx.is_null() {
+            self.close();
+            return None;
+        }
+
+        let mut part = self.template.clone();
+        unsafe {
+            mm_mapopt_update(&mut part.mapopt, idx);
+            mm_idx_index_name(idx);
+        }
+        part.idx = Some(Arc::new(idx.into()));
+        Some(part)
+    }
+}
+
+impl std::iter::FusedIterator for IndexPartsIter {}
+
+impl Drop for IndexPartsIter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Check that a prebuilt `.mmi` index holds everything its headers declare
+///
+/// minimap2 does not check its reads of an index file, so a truncated file (e.g. from an
+/// interrupted copy) would leave the index partly uninitialised and its hits pointing
+/// anywhere. The layout of each part, as written by `mm_idx_dump`, is walked without reading
+/// the minimizers themselves. Files that are not indexes are left to minimap2.
+fn check_mmi(path: &Path) -> Result<(), &'static str> {
+    const TRUNCATED: &str = "Index file is truncated";
+
+    let file = File::open(path).map_err(|_| "Unable to open index")?;
+    let file_len = file.metadata().map_err(|_| "Unable to open index")?.len();
+    let mut reader = BufReader::new(file);
+
+    let read_u32 = |reader: &mut BufReader<File>| -> Result<u32, &'static str> {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).map_err(|_| TRUNCATED)?;
+        Ok(u32::from_le_bytes(buf))
+    };
+    let skip = |reader: &mut BufReader<File>, n: u64| -> Result<(), &'static str> {
+        reader.seek_relative(n as i64).map_err(|_| TRUNCATED)
+    };
+
+    let magic = MM_IDX_MAGIC.to_bytes();
+    let mut pos = 0;
+    // Parts are written one after the other
+    while pos < file_len {
+        let mut part_magic = [0u8; 4];
+        if reader.read_exact(&mut part_magic).is_err() || part_magic != magic {
+            return Ok(());
+        }
+
+        // w, k, b, n_seq, flag
+        let mut header = [0u32; 5];
+        for x in header.iter_mut() {
+            *x = read_u32(&mut reader)?;
+        }
+        let [_, _, b, n_seq, flag] = header;
+        pos += 4 + 20;
+
+        let mut sum_len = 0u64;
+        for _ in 0..n_seq {
+            let mut name_len = [0u8];
+            reader.read_exact(&mut name_len).map_err(|_| TRUNCATED)?;
+            skip(&mut reader, name_len[0] as u64)?;
+            sum_len += read_u32(&mut reader)? as u64;
+            pos += 1 + name_len[0] as u64 + 4;
+        }
+
+        if b >= 32 {
+            return Err("Index file is corrupted");
+        }
+        for _ in 0..1u64 << b {
+            let n_minimizers = read_u32(&mut reader)? as u64;
+            skip(&mut reader, n_minimizers * 8)?;
+            let hash_size = read_u32(&mut reader)? as u64;
+            skip(&mut reader, hash_size * 16)?;
+            pos += 4 + n_minimizers * 8 + 4 + hash_size * 16;
+            if pos > file_len {
+                return Err(TRUNCATED);
+            }
+        }
+
+        if flag & MM_I_NO_SEQ == 0 {
+            let n_words = sum_len.div_ceil(8);
+            skip(&mut reader, n_words * 4)?;
+            pos += n_words * 4;
+        }
+        if pos > file_len {
+            return Err(TRUNCATED);
+        }
+    }
+    Ok(())
+}
+
+impl<S> Aligner<S>
+where
+    S: BuilderState + AcceptsParams,
+{
+    /// Open an index (or a FASTA file to index) to read its parts one at a time
+    ///
+    /// minimap2 splits indexes of references larger than `idxopt.batch_size` into parts.
+    /// Each part is returned as an aligner with the settings of this builder. If `output` is
+    /// given, the parts are written to it as they are read.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let mut builder = Aligner::builder().map_ont();
+    /// // chr1 and chr2 end up in separate parts
+    /// builder.idxopt.batch_size = 1000;
+    /// builder.idxopt.mini_batch_size = 1000;
+    /// let mut parts = builder.index_parts("test_data/genome.fa", None).unwrap();
+    /// let first = parts.next().unwrap();
+    /// assert_eq!(first.n_seq(), 1);
+    /// let second = parts.next().unwrap();
+    /// assert_eq!(second.seqs().next().unwrap().name, "chr2");
+    /// assert!(parts.next().is_none());
+    /// assert!(parts.is_eof());
+    /// ```
+    pub fn index_parts<P>(
+        self,
+        path: P,
+        output: Option<&str>,
+    ) -> Result<IndexPartsIter, &'static str>
+    where
+        P: AsRef<Path>,
+    {
+        self.check_opts()?;
+
+        let path_str = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|_| "Invalid Path for Index")?;
+
+        // Confirm file exists
+        if !path.as_ref().exists() {
+            return Err("Index File does not exist");
+        }
+
+        // Confirm file is not empty
+        if path
+            .as_ref()
+            .metadata()
+            .map_err(|_| "Invalid Path for Index")?
+            .len()
+            == 0
+        {
+            return Err("Index File is empty");
+        }
+
+        check_mmi(path.as_ref())?;
+
+        let output =
+            CString::new(output.unwrap_or_default()).map_err(|_| "Invalid Output for Index")?;
+
+        let reader =
+            unsafe { mm_idx_reader_open(path_str.as_ptr(), &self.idxopt, output.as_ptr()) };
+        if reader.is_null() {
+            return Err("Unable to open index");
+        }
+
+        Ok(IndexPartsIter {
+            reader,
+            template: self.into_built(),
+        })
+    }
+
+    /// Read every part of an index, for references larger than `idxopt.batch_size`
+    ///
+    /// `with_index` only reads the first part. All parts are held in memory; the hits of a
+    /// query against each are merged as minimap2 does with `--split-prefix`, see
+    /// [`SplitAligner::map`]. If `output` is given, all parts are written to it.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let mut builder = Aligner::builder().map_ont();
+    /// builder.idxopt.batch_size = 1000;
+    /// builder.idxopt.mini_batch_size = 1000;
+    /// let aligner = builder.with_index_parts("test_data/genome.fa", None).unwrap();
+    /// assert_eq!(aligner.parts().len(), 2);
+    /// assert_eq!(aligner.n_seq(), 2);
+    /// ```
+    pub fn with_index_parts<P>(
+        self,
+        path: P,
+        output: Option<&str>,
+    ) -> Result<SplitAligner, &'static str>
+    where
+        P: AsRef<Path>,
+    {
+        let parts: Vec<Aligner<Built>> = self.index_parts(path, output)?.collect();
+        if parts.is_empty() {
+            return Err("Index is empty");
+        }
+
+        let offsets = parts
+            .iter()
+            .scan(0, |n_seq, part| {
+                let offset = *n_seq;
+                *n_seq += part.n_seq();
+                Some(offset)
+            })
+            .collect();
+
+        Ok(SplitAligner { parts, offsets })
+    }
+}
+
+impl SplitAligner {
+    /// Aligners for each part of the index, in order
+    pub fn parts(&self) -> &[Aligner<Built>] {
+        &self.parts
+    }
+
+    /// Number of targets across all parts
+    pub fn n_seq(&self) -> u32 {
+        self.offsets.last().unwrap() + self.parts.last().unwrap().n_seq()
+    }
+
+    /// Map a query against all parts, as [`Aligner::map`] does against one
+    ///
+    /// The hits of all parts are pooled and reconciled as minimap2 does for `--split-prefix`:
+    /// they are sorted by score, secondary hits are re-assigned to their primary and filtered
+    /// with `pri_ratio` and `best_n`, and MAPQ is recomputed over the pooled hits, so a repeat
+    /// spanning parts gets a low MAPQ and a single primary. As in minimap2, MAPQ can differ
+    /// slightly from a single-part index, since each part filters its hits first.
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// let mut builder = Aligner::builder().map_ont();
+    /// builder.idxopt.batch_size = 1000;
+    /// builder.idxopt.mini_batch_size = 1000;
+    /// let aligner = builder.with_index_parts("test_data/genome.fa", None).unwrap();
+    /// let read = aligner.parts()[1].fetch_subseq("chr2", 0..400, Strand::Forward).unwrap();
+    /// let mappings = aligner.map(&read, false, false, None, None, None).unwrap();
+    /// assert_eq!(mappings[0].target_name.as_deref().unwrap(), "chr2");
+    /// ```
+    pub fn map(
+        &self,
+        seq: &[u8],
+        cs: bool,
+        md: bool,
+        max_frag_len: Option<usize>,
+        extra_flags: Option<&[u64]>,
+        query_name: Option<&[u8]>,
+    ) -> Result<Vec<Mapping>, &'static str> {
+        if seq.is_empty() {
+            return Err("Sequence is empty");
+        }
+
+        let first = &self.parts[0];
+        first.check_query_len(seq.len())?;
+        let oriented = first.orient_query(seq);
+        let seq = &oriented[..];
+        let map_opt = first.call_mapopt(max_frag_len, extra_flags, query_name);
+
+        let query_name = match query_name {
+            None => None,
+            Some(qname) => {
+                let qname = qname.strip_suffix(b"\0").unwrap_or(qname);
+                Some(CString::new(qname).map_err(|_| "Invalid query name")?)
+            }
+        };
+        let query_name_arc = query_name
+            .as_ref()
+            .map(|x| Arc::new(x.to_string_lossy().into_owned()));
+        let qname = query_name.as_ref().map_or(std::ptr::null(), |x| x.as_ptr());
+
+        let mappings = with_buf(|buf| unsafe {
+            let km = mm_tbuf_get_km(buf.get_buf());
+
+            // Pool the hits of all parts, with target ids made unique across parts
+            let mut regs: Vec<mm_reg1_t> = Vec::new();
+            let mut rep_len = 0;
+            for (part, offset) in self.parts.iter().zip(&self.offsets) {
+                let mut n_regs = 0;
+                let part_regs = mm_map(
+                    &**part.idx.as_ref().unwrap().as_ref() as *const mm_idx_t,
+                    seq.len() as i32,
+                    seq.as_ptr() as *const libc::c_char,
+                    &mut n_regs,
+                    buf.get_buf(),
+                    &map_opt,
+                    qname,
+                );
+                rep_len = rep_len.max((*buf.get_buf()).rep_len);
+                let part_idx: &mm_idx_t = part.idx.as_ref().unwrap();
+                if let Err(e) = check_hits(part_idx, part_regs, n_regs) {
+                    free_hits(part_regs, n_regs);
+                    regs.iter().for_each(|reg| libc::free(reg.p as *mut c_void));
+                    return Err(e);
+                }
+                if !part_regs.is_null() {
+                    for reg in std::slice::from_raw_parts(part_regs, n_regs.max(0) as usize) {
+                        let mut reg = *reg;
+                        reg.rid += *offset as i32;
+                        regs.push(reg);
+                    }
+                }
+                libc::free(part_regs as *mut c_void);
+            }
+
+            // Same steps as merge_hits() in minimap2's map.c
+            let mut n_regs = regs.len() as i32;
+            mm_hit_sort(km, &mut n_regs, regs.as_mut_ptr(), map_opt.alt_drop);
+            mm_set_parent(
+                km,
+                map_opt.mask_level,
+                map_opt.mask_len,
+                n_regs,
+                regs.as_mut_ptr(),
+                map_opt.a * 2 + map_opt.b,
+                (map_opt.flag & MM_F_HARD_MLEVEL as i64 != 0) as i32,
+                map_opt.alt_drop,
+            );
+            if map_opt.flag & MM_F_ALL_CHAINS as i64 == 0 {
+                let k = first.idx.as_ref().unwrap().k;
+                mm_select_sub(
+                    km,
+                    map_opt.pri_ratio,
+                    k * 2,
+                    map_opt.best_n,
+                    0,
+                    (map_opt.max_gap as f32 * 0.8) as i32,
+                    &mut n_regs,
+                    regs.as_mut_ptr(),
+                );
+                mm_set_sam_pri(n_regs, regs.as_mut_ptr());
+            }
+            mm_set_mapq(
+                km,
+                n_regs,
+                regs.as_mut_ptr(),
+                map_opt.min_chain_score,
+                map_opt.a,
+                rep_len,
+                (map_opt.flag & MM_F_SR as i64 != 0) as i32,
+            );
+            regs.truncate(n_regs as usize);
+            n_regs = first.filter_secondary(regs.as_mut_ptr(), n_regs, map_opt.flag);
+
+            let (n_kept, _) = first.limit_hits(
+                regs.as_mut_ptr(),
+                n_regs,
+                query_name_arc.as_ref().map(|name| name.as_str()),
+            );
+            // Target ids are still those of the whole index here
+            first.sort_hits(regs.as_mut_ptr(), n_kept);
+            let mut mappings = Vec::with_capacity(n_kept);
+            for reg in &mut regs[..n_kept] {
+                let part = self.offsets.partition_point(|&o| o <= reg.rid as u32) - 1;
+                reg.rid -= self.offsets[part] as i32;
+                mappings.push(self.parts[part].reg_to_mapping(reg, seq, cs, md, &query_name_arc));
+            }
+            Ok(mappings)
+        })?;
+
+        Ok(mappings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random_seq, Strand};
+    use std::io::Write;
+
+    #[test]
+    fn test_index_parts() {
+        let builder = || {
+            let mut builder = Aligner::builder().map_ont();
+            builder.idxopt.batch_size = 1000;
+            builder.idxopt.mini_batch_size = 1000;
+            builder
+        };
+
+        // Writing the index while reading it lazily
+        let mmi = std::env::temp_dir().join("minimap2_test_index_parts.mmi");
+        let mmi_str = mmi.to_str().unwrap();
+        let names: Vec<Vec<String>> = builder()
+            .index_parts("test_data/genome.fa", Some(mmi_str))
+            .unwrap()
+            .map(|part| part.seqs().map(|s| s.name).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["chr1"], vec!["chr2"]]);
+
+        // Reading it back, stopping after the first part
+        let mut parts = builder().index_parts(&mmi, None).unwrap();
+        let first = parts.next().unwrap();
+        drop(parts);
+        assert_eq!(first.n_seq(), 1);
+        let read = first.fetch_subseq("chr1", 0..500, Strand::Forward).unwrap();
+        let mappings = first.map(&read, false, false, None, None, None).unwrap();
+        assert_eq!(mappings[0].target_start, 0);
+
+        // with_index reads the first part only
+        let aligner = builder().with_index(&mmi, None).unwrap();
+        assert_eq!(aligner.n_seq(), 1);
+        assert!(aligner.idx_reader.is_none());
+
+        let split = builder().with_index_parts(&mmi, None).unwrap();
+        assert_eq!(split.n_seq(), 2);
+        std::fs::remove_file(&mmi).unwrap();
+
+        assert!(builder()
+            .index_parts("test_data/does_not_exist.fa", None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_truncated_index() {
+        let mmi = std::env::temp_dir().join("minimap2_test_truncated_index.mmi");
+        let mmi_str = mmi.to_str().unwrap();
+        Aligner::builder()
+            .map_ont()
+            .with_index("test_data/genome.fa", Some(mmi_str))
+            .unwrap();
+        let bytes = std::fs::read(&mmi).unwrap();
+        assert!(check_mmi(&mmi).is_ok());
+        assert!(check_mmi(Path::new("test_data/genome.fa")).is_ok());
+
+        // Cut in the sequences, the minimizers and the names
+        let truncated = std::env::temp_dir().join("minimap2_test_truncated_index_cut.mmi");
+        for len in [bytes.len() - 1, bytes.len() / 2, 30] {
+            std::fs::write(&truncated, &bytes[..len]).unwrap();
+            assert_eq!(
+                Aligner::builder()
+                    .map_ont()
+                    .with_index(&truncated, None)
+                    .err(),
+                Some("Index file is truncated"),
+                "index cut at {len} bytes"
+            );
+        }
+
+        std::fs::remove_file(&truncated).unwrap();
+        std::fs::remove_file(&mmi).unwrap();
+    }
+
+    #[test]
+    fn test_split_merge() {
+        // ctg3 carries a copy of the middle of ctg1, so reads from there are repeats
+        let ctg1 = random_seq(6000, 11);
+        let ctg2 = random_seq(3000, 12);
+        let mut ctg3 = random_seq(1000, 13);
+        ctg3.extend_from_slice(&ctg1[2000..4000]);
+        ctg3.extend(random_seq(1000, 14));
+
+        let path = std::env::temp_dir().join("minimap2_test_split_merge.fa");
+        let mut fasta = std::fs::File::create(&path).unwrap();
+        for (name, seq) in [("ctg1", &ctg1), ("ctg2", &ctg2), ("ctg3", &ctg3)] {
+            writeln!(fasta, ">{}", name).unwrap();
+            fasta.write_all(seq).unwrap();
+            writeln!(fasta).unwrap();
+        }
+        drop(fasta);
+
+        let single = Aligner::builder()
+            .map_ont()
+            .with_cigar()
+            .with_index(&path, None)
+            .unwrap();
+        let mut builder = Aligner::builder().map_ont().with_cigar();
+        builder.idxopt.batch_size = 1;
+        builder.idxopt.mini_batch_size = 1;
+        let split = builder.clone().with_index_parts(&path, None).unwrap();
+        let no_secondary = builder
+            .with_secondary(false)
+            .with_index_parts(&path, None)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(split.parts().len(), 3);
+        assert_eq!(split.n_seq(), 3);
+
+        // The repeat is unique within each part, but not across them
+        let repeat = &ctg1[2500..3500];
+        for part in [&split.parts()[0], &split.parts()[2]] {
+            let mappings = part.map(repeat, false, false, None, None, None).unwrap();
+            assert_eq!(mappings.len(), 1);
+            assert!(mappings[0].mapq > 0);
+        }
+        let merged = split.map(repeat, false, false, None, None, None).unwrap();
+        let expected = single.map(repeat, false, false, None, None, None).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged.iter().filter(|m| m.is_primary).count(), 1);
+        // minimap2 gives MAPQ 1 rather than 0 when the alignment scores of the other parts
+        // are not known
+        assert!(merged[0].mapq <= 1);
+        assert!(expected[0].mapq <= 1);
+        let mut targets: Vec<_> = merged
+            .iter()
+            .map(|m| (m.target_name.as_deref().unwrap().as_str(), m.target_start))
+            .collect();
+        targets.sort();
+        assert_eq!(targets, vec![("ctg1", 2500), ("ctg3", 1500)]);
+        let primary = no_secondary
+            .map(repeat, false, false, None, None, None)
+            .unwrap();
+        assert_eq!(primary.len(), 1);
+        assert!(primary[0].is_primary);
+
+        // Unique hits are unchanged, with names and coordinates from their own part
+        let unique = &ctg2[500..1500];
+        let merged = split.map(unique, false, false, None, None, None).unwrap();
+        let expected = single.map(unique, false, false, None, None, None).unwrap();
+        assert_eq!(merged, expected);
+        assert_eq!(merged[0].target_name.as_deref().unwrap(), "ctg2");
+        assert_eq!(merged[0].mapq, 60);
+
+        let reverse = split.parts()[2]
+            .fetch_subseq("ctg3", 100..900, Strand::Reverse)
+            .unwrap();
+        let merged = split.map(&reverse, false, false, None, None, None).unwrap();
+        assert_eq!(merged[0].target_name.as_deref().unwrap(), "ctg3");
+        assert_eq!(merged[0].strand, Strand::Reverse);
+
+        assert!(split.map(b"", false, false, None, None, None).is_err());
+    }
+}
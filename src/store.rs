@@ -0,0 +1,237 @@
+//! Stores of mappings queryable by reference interval
+//!
+//! A [`MappingSet`] holds the mappings of a run in memory and answers which of them overlap a
+//! target interval, as a BAM index does for a sorted BAM. It collects mappings from the file
+//! drivers as an [`OutputSink`], and can be saved to a file and loaded back later, e.g. to
+//! look at a region of a finished run in an interactive session without mapping it again.
+//!
+//! Saved sets are [`wire`] payloads, so a file can also be read with [`wire::decode`]. The
+//! interval index is not saved: it is built again on the first query after loading, in time
+//! linear in the number of mappings after sorting them.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::sink::OutputSink;
+use crate::{wire, Mapping};
+
+/// Mappings with an index by target interval
+///
+/// ```
+/// use minimap2::store::MappingSet;
+/// use minimap2::Mapping;
+/// use std::sync::Arc;
+///
+/// let hit = |read: &str, start, end| Mapping {
+///     query_name: Some(Arc::new(read.to_string())),
+///     target_name: Some(Arc::new("chr1".to_string())),
+///     target_len: 10_000,
+///     target_start: start,
+///     target_end: end,
+///     ..Default::default()
+/// };
+/// let set: MappingSet = [hit("a", 100, 900), hit("b", 800, 2000), hit("c", 5000, 6000)]
+///     .into_iter()
+///     .collect();
+///
+/// let path = std::env::temp_dir().join("minimap2_doc_mapping_set.mm2w");
+/// set.save(&path).unwrap();
+/// let set = MappingSet::load(&path).unwrap();
+/// let reads: Vec<&str> = set
+///     .overlapping("chr1", 850..1000)
+///     .map(|m| m.query_name.as_deref().unwrap().as_str())
+///     .collect();
+/// assert_eq!(reads, ["a", "b"]);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct MappingSet {
+    mappings: Vec<Mapping>,
+    // Built on the first query, dropped when mappings are added
+    index: OnceLock<HashMap<String, TargetIndex>>,
+}
+
+/// The mappings of one target, by start
+#[derive(Debug, Default)]
+struct TargetIndex {
+    // Positions in `mappings`, ordered by start
+    order: Vec<usize>,
+    // Largest end of the mappings up to each position of `order`
+    max_end: Vec<i32>,
+}
+
+impl MappingSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of mappings
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// All mappings, in the order they were added
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// Mappings to `target` that overlap `range` (0-based, end-exclusive), by start on the
+    /// target
+    pub fn overlapping<'a>(
+        &'a self,
+        target: &str,
+        range: Range<i32>,
+    ) -> impl Iterator<Item = &'a Mapping> + 'a {
+        let (order, max_end): (&[usize], &[i32]) = match self.index().get(target) {
+            Some(index) => (&index.order, &index.max_end),
+            None => (&[], &[]),
+        };
+        // Mappings before the first whose running end passes the start all end before it
+        let first = max_end.partition_point(|&end| end <= range.start);
+        order[first..]
+            .iter()
+            .map(|&i| &self.mappings[i])
+            .take_while(move |m| m.target_start < range.end)
+            .filter(move |m| m.target_end > range.start)
+    }
+
+    /// Save the mappings to `path` as a [`wire`] payload
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), &'static str> {
+        std::fs::write(path, wire::encode(&self.mappings)).map_err(|_| "Unable to write file")
+    }
+
+    /// Save the mappings to `path` as a [`wire`] payload compressed by zstd at `level`
+    #[cfg(feature = "zstd")]
+    pub fn save_compressed<P: AsRef<Path>>(&self, path: P, level: i32) -> Result<(), &'static str> {
+        let payload = wire::encode_compressed(&self.mappings, level)?;
+        std::fs::write(path, payload).map_err(|_| "Unable to write file")
+    }
+
+    /// Load mappings saved by [`save`](MappingSet::save) or
+    /// [`save_compressed`](MappingSet::save_compressed)
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, &'static str> {
+        let payload = std::fs::read(path).map_err(|_| "Unable to read file")?;
+        Ok(wire::decode(&payload)?.into_iter().collect())
+    }
+
+    fn index(&self) -> &HashMap<String, TargetIndex> {
+        self.index.get_or_init(|| {
+            let mut index: HashMap<String, TargetIndex> = HashMap::new();
+            for (i, mapping) in self.mappings.iter().enumerate() {
+                if let Some(target) = &mapping.target_name {
+                    index.entry(target.to_string()).or_default().order.push(i);
+                }
+            }
+            for target in index.values_mut() {
+                target
+                    .order
+                    .sort_by_key(|&i| (self.mappings[i].target_start, i));
+                let mut max_end = i32::MIN;
+                target.max_end = target
+                    .order
+                    .iter()
+                    .map(|&i| {
+                        max_end = max_end.max(self.mappings[i].target_end);
+                        max_end
+                    })
+                    .collect();
+            }
+            index
+        })
+    }
+}
+
+impl Extend<Mapping> for MappingSet {
+    fn extend<I: IntoIterator<Item = Mapping>>(&mut self, mappings: I) {
+        self.mappings.extend(mappings);
+        self.index = OnceLock::new();
+    }
+}
+
+impl FromIterator<Mapping> for MappingSet {
+    fn from_iter<I: IntoIterator<Item = Mapping>>(mappings: I) -> Self {
+        MappingSet {
+            mappings: mappings.into_iter().collect(),
+            index: OnceLock::new(),
+        }
+    }
+}
+
+/// Collects the mappings of a file driver run
+impl OutputSink for MappingSet {
+    fn write(&mut self, _read_id: &[u8], mappings: &[Mapping]) -> Result<(), &'static str> {
+        self.extend(mappings.iter().cloned());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Aligner;
+
+    #[test]
+    fn test_overlapping() {
+        let hit = |target: &str, start, end| Mapping {
+            target_name: Some(std::sync::Arc::new(target.to_string())),
+            target_start: start,
+            target_end: end,
+            ..Default::default()
+        };
+        let mut set: MappingSet = [hit("a", 0, 10_000), hit("a", 100, 200), hit("b", 150, 160)]
+            .into_iter()
+            .collect();
+        let starts = |set: &MappingSet, target, range| -> Vec<i32> {
+            set.overlapping(target, range)
+                .map(|m| m.target_start)
+                .collect()
+        };
+
+        // A long mapping starting early still overlaps
+        assert_eq!(starts(&set, "a", 5000..6000), [0]);
+        assert_eq!(starts(&set, "a", 150..151), [0, 100]);
+        // Ends are exclusive
+        assert_eq!(starts(&set, "a", 200..300), [0]);
+        assert_eq!(starts(&set, "b", 100..150), [] as [i32; 0]);
+        assert_eq!(starts(&set, "c", 0..100), [] as [i32; 0]);
+
+        // Adding mappings updates the index
+        set.extend([hit("a", 50, 120), Mapping::default()]);
+        assert_eq!(set.len(), 5);
+        assert_eq!(starts(&set, "a", 110..115), [0, 50, 100]);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let aligner = Aligner::builder()
+            .map_ont()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap();
+        let mut set = MappingSet::new();
+        aligner
+            .map_file_to_sink("test_data/MT-human.fa", &mut set, 1)
+            .unwrap();
+        assert!(!set.is_empty());
+
+        let path = std::env::temp_dir().join("minimap2_test_mapping_set.mm2w");
+        set.save(&path).unwrap();
+        let loaded = MappingSet::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.mappings(), set.mappings());
+        assert!(loaded
+            .overlapping("MT_human", 1000..1001)
+            .eq(set.overlapping("MT_human", 1000..1001)));
+        assert_eq!(loaded.overlapping("MT_human", 1000..1001).count(), 1);
+
+        assert_eq!(
+            MappingSet::load("test_data/MT-human.fa").err(),
+            Some("Not a mapping payload")
+        );
+    }
+}
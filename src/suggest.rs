@@ -0,0 +1,192 @@
+//! Preset suggestion from a sample of reads
+//!
+//! [`ReadStats`] summarises the lengths and qualities of a sample of reads and
+//! [`ReadStats::suggest_preset`] picks the preset matching them, for frontends whose users do
+//! not know which sequencing technology produced their reads. The choice is a heuristic;
+//! users who know their data should set the preset themselves.
+
+#[cfg(feature = "map-file")]
+use std::path::Path;
+
+use crate::Preset;
+
+/// Reads with a median length up to this are mapped as short reads
+pub const SHORT_READ_MAX_LEN: usize = 700;
+
+/// Mean Phred quality from which long reads are taken as PacBio HiFi
+pub const HIFI_MIN_QUALITY: f64 = 30.0;
+
+/// Mean Phred quality from which long reads are taken as high-accuracy ONT (Q20+)
+pub const LRHQ_MIN_QUALITY: f64 = 20.0;
+
+/// Length and quality summary of a sample of reads
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReadStats {
+    pub n_reads: usize,
+    pub total_len: usize,
+    pub median_len: usize,
+    pub max_len: usize,
+    /// Read length such that half of the bases are in reads at least this long
+    pub n50: usize,
+    /// Mean Phred score of all bases, None if no read had qualities (FASTA)
+    pub mean_quality: Option<f64>,
+}
+
+impl ReadStats {
+    /// Summarise reads given as sequences and optional Phred+33 quality strings
+    pub fn from_reads<I, S, Q>(reads: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Option<Q>)>,
+        S: AsRef<[u8]>,
+        Q: AsRef<[u8]>,
+    {
+        let mut lens = Vec::new();
+        let mut quality_sum = 0u64;
+        let mut n_quality = 0u64;
+
+        for (seq, qual) in reads {
+            lens.push(seq.as_ref().len());
+            if let Some(qual) = qual {
+                let qual = qual.as_ref();
+                quality_sum += qual
+                    .iter()
+                    .map(|q| q.saturating_sub(33) as u64)
+                    .sum::<u64>();
+                n_quality += qual.len() as u64;
+            }
+        }
+
+        if lens.is_empty() {
+            return ReadStats::default();
+        }
+
+        lens.sort_unstable();
+        let total_len: usize = lens.iter().sum();
+        let mut n50 = 0;
+        let mut cumulative = 0;
+        for &len in lens.iter().rev() {
+            cumulative += len;
+            if cumulative * 2 >= total_len {
+                n50 = len;
+                break;
+            }
+        }
+
+        ReadStats {
+            n_reads: lens.len(),
+            total_len,
+            median_len: lens[lens.len() / 2],
+            max_len: *lens.last().unwrap(),
+            n50,
+            mean_quality: if n_quality > 0 {
+                Some(quality_sum as f64 / n_quality as f64)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Summarise the first `sample` reads of a FASTA/FASTQ file (may be gzipped)
+    ///
+    /// ```
+    /// # use minimap2::*;
+    /// use minimap2::suggest::ReadStats;
+    ///
+    /// let stats = ReadStats::from_file("test_data/cDNA_reads.fq", 1000).unwrap();
+    /// let aligner = Aligner::builder().preset(stats.suggest_preset());
+    /// ```
+    #[cfg(feature = "map-file")]
+    pub fn from_file<P: AsRef<Path>>(path: P, sample: usize) -> Result<Self, &'static str> {
+        let mut reader =
+            needletail::parse_fastx_file(path).map_err(|_| "Unable to open read file")?;
+
+        let mut reads = Vec::new();
+        while reads.len() < sample {
+            match reader.next() {
+                Some(Ok(record)) => {
+                    reads.push((record.seq().to_vec(), record.qual().map(|q| q.to_vec())))
+                }
+                Some(Err(_)) => {
+                    return Err("Error reading record in FASTA/X files. Please confirm integrity.")
+                }
+                None => break,
+            }
+        }
+
+        if reads.is_empty() {
+            return Err("File is empty");
+        }
+        Ok(ReadStats::from_reads(reads))
+    }
+
+    /// The preset matching the reads
+    ///
+    /// - median length up to [`SHORT_READ_MAX_LEN`]: `sr`
+    /// - mean quality from [`HIFI_MIN_QUALITY`]: `map-hifi`
+    /// - mean quality from [`LRHQ_MIN_QUALITY`]: `lr:hq`
+    /// - otherwise, including reads without qualities: `map-ont`
+    ///
+    /// Spliced (RNA) and assembly inputs can not be told apart from genomic reads by length
+    /// and quality alone, so `splice` and `asm*` are never suggested.
+    pub fn suggest_preset(&self) -> Preset {
+        if self.median_len <= SHORT_READ_MAX_LEN {
+            return Preset::Sr;
+        }
+        match self.mean_quality {
+            Some(q) if q >= HIFI_MIN_QUALITY => Preset::MapHifi,
+            Some(q) if q >= LRHQ_MIN_QUALITY => Preset::LrHq,
+            _ => Preset::MapOnt,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reads(lens: &[usize], quality: Option<u8>) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        lens.iter()
+            .map(|&len| (vec![b'A'; len], quality.map(|q| vec![q + 33; len])))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_stats() {
+        let stats = ReadStats::from_reads(reads(&[100, 400, 200, 300], Some(20)));
+        assert_eq!(stats.n_reads, 4);
+        assert_eq!(stats.total_len, 1000);
+        assert_eq!(stats.median_len, 300);
+        assert_eq!(stats.max_len, 400);
+        assert_eq!(stats.n50, 300);
+        assert_eq!(stats.mean_quality, Some(20.0));
+
+        let stats = ReadStats::from_reads(reads(&[100], None));
+        assert_eq!(stats.mean_quality, None);
+        assert_eq!(
+            ReadStats::from_reads(reads(&[], None)),
+            ReadStats::default()
+        );
+    }
+
+    #[test]
+    fn test_suggest_preset() {
+        let suggest =
+            |lens: &[usize], quality| ReadStats::from_reads(reads(lens, quality)).suggest_preset();
+        assert_eq!(suggest(&[150, 150, 151], Some(35)), Preset::Sr);
+        assert_eq!(suggest(&[15000, 18000, 20000], Some(35)), Preset::MapHifi);
+        assert_eq!(suggest(&[5000, 30000, 60000], Some(22)), Preset::LrHq);
+        assert_eq!(suggest(&[5000, 30000, 60000], Some(12)), Preset::MapOnt);
+        assert_eq!(suggest(&[5000, 30000, 60000], None), Preset::MapOnt);
+    }
+
+    #[cfg(feature = "map-file")]
+    #[test]
+    fn test_read_stats_from_file() {
+        let stats = ReadStats::from_file("test_data/gDNA_reads.fq", 2).unwrap();
+        assert_eq!(stats.n_reads, 2);
+        assert!(stats.mean_quality.is_some());
+
+        assert!(ReadStats::from_file("test_data/empty.fa", 10).is_err());
+        assert!(ReadStats::from_file("test_data/missing.fa", 10).is_err());
+    }
+}
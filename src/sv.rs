@@ -0,0 +1,313 @@
+//! Discordant, split and clipped read extraction for structural variant calling
+//!
+//! SV pipelines run samblaster right after mapping to pull out the reads that carry
+//! structural variant evidence. [`classify_pair`] does the same on the mappings of a read
+//! pair ([`PairedMappings`]) and [`classify_read`] on those of a single read: a pair is
+//! concordant or discordant, and each read is split (primary plus supplementary alignments)
+//! or clipped (unmapped, or with a long unaligned end). [`SvRouter`] sends each class to its
+//! own [`OutputSink`].
+
+use crate::paf::{Mate, PairedMappings};
+use crate::sink::OutputSink;
+use crate::{Mapping, Strand};
+
+/// Thresholds for [`classify_pair`] and [`classify_read`], defaults as samblaster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvParams {
+    /// Largest template length of a concordant pair
+    pub max_insert: i32,
+    /// Minimum unaligned bases at an end of the primary alignment for a read to be clipped
+    pub min_clip: i32,
+    /// Maximum number of alignments (primary and supplementary) of a split read; reads split
+    /// in more pieces are more likely repeats than SV evidence
+    pub max_split_count: usize,
+}
+
+impl Default for SvParams {
+    fn default() -> Self {
+        SvParams {
+            max_insert: 1000,
+            min_clip: 20,
+            max_split_count: 2,
+        }
+    }
+}
+
+/// How the mates of a pair map relative to each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairClass {
+    /// Both mates on the same target, facing each other, within `max_insert`
+    Concordant,
+    /// Both mates mapped otherwise: different targets, wrong orientation or too far apart
+    Discordant,
+    /// At least one mate unmapped
+    Unpaired,
+}
+
+/// SV evidence of a single read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReadEvidence {
+    /// A primary and up to `max_split_count - 1` supplementary alignments
+    pub split: bool,
+    /// Unmapped, or with at least `min_clip` unaligned bases at one end
+    pub clipped: bool,
+}
+
+/// Classification of a read pair by [`classify_pair`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairEvidence {
+    pub class: PairClass,
+    pub first: ReadEvidence,
+    pub second: ReadEvidence,
+}
+
+impl PairEvidence {
+    pub fn mate(&self, mate: Mate) -> ReadEvidence {
+        match mate {
+            Mate::First => self.first,
+            Mate::Second => self.second,
+        }
+    }
+}
+
+fn primary(mappings: &[Mapping]) -> Option<&Mapping> {
+    mappings
+        .iter()
+        .find(|m| m.is_primary && !m.is_supplementary)
+}
+
+/// Whether a read is split or clipped, from all its mappings
+pub fn classify_read(mappings: &[Mapping], params: &SvParams) -> ReadEvidence {
+    let Some(primary) = primary(mappings) else {
+        return ReadEvidence {
+            split: false,
+            clipped: true,
+        };
+    };
+
+    let pieces = mappings
+        .iter()
+        .filter(|m| m.is_primary || m.is_supplementary)
+        .count();
+    let split = pieces > 1 && pieces <= params.max_split_count;
+
+    let query_len = primary.query_len.map_or(primary.query_end, |len| len.get());
+    let clip = primary.query_start.max(query_len - primary.query_end);
+
+    ReadEvidence {
+        split,
+        clipped: clip >= params.min_clip,
+    }
+}
+
+/// Whether the primary alignments of the mates are concordant, and the SV evidence of each mate
+///
+/// Mates are expected forward-reverse, as for Illumina paired-end reads: the forward mate
+/// starts no later than the reverse one.
+pub fn classify_pair(pair: &PairedMappings, params: &SvParams) -> PairEvidence {
+    let class = match (primary(&pair.first), primary(&pair.second)) {
+        (Some(a), Some(b)) => {
+            let insert = a.target_end.max(b.target_end) - a.target_start.min(b.target_start);
+            let facing = match (a.strand, b.strand) {
+                (Strand::Forward, Strand::Reverse) => a.target_start <= b.target_start,
+                (Strand::Reverse, Strand::Forward) => b.target_start <= a.target_start,
+                _ => false,
+            };
+            if a.target_name == b.target_name && facing && insert <= params.max_insert {
+                PairClass::Concordant
+            } else {
+                PairClass::Discordant
+            }
+        }
+        _ => PairClass::Unpaired,
+    };
+
+    PairEvidence {
+        class,
+        first: classify_read(&pair.first, params),
+        second: classify_read(&pair.second, params),
+    }
+}
+
+/// Routes read pairs to sinks by their SV evidence, as samblaster's -d, -s and -u outputs
+///
+/// Discordant pairs are written with the mappings of both mates under the pair name; split
+/// and clipped mates with their own mappings under the pair name with a `/1` or `/2` suffix.
+/// A pair or mate can go to several sinks, and sinks left as None are skipped.
+#[derive(Default)]
+pub struct SvRouter<'a> {
+    pub params: SvParams,
+    pub discordant: Option<&'a mut dyn OutputSink>,
+    pub split: Option<&'a mut dyn OutputSink>,
+    pub clipped: Option<&'a mut dyn OutputSink>,
+}
+
+impl SvRouter<'_> {
+    /// Classify a pair and write it to the matching sinks
+    pub fn route(&mut self, pair: &PairedMappings) -> Result<PairEvidence, &'static str> {
+        let evidence = classify_pair(pair, &self.params);
+        let name = pair.name.as_deref().map_or("*", |name| name.as_str());
+
+        if evidence.class == PairClass::Discordant {
+            if let Some(sink) = self.discordant.as_mut() {
+                let mappings: Vec<Mapping> = pair.iter().map(|(_, m)| m.clone()).collect();
+                sink.write(name.as_bytes(), &mappings)?;
+            }
+        }
+
+        for mate in [Mate::First, Mate::Second] {
+            let read = evidence.mate(mate);
+            let id = format!("{}{}", name, mate.suffix());
+            if read.split {
+                if let Some(sink) = self.split.as_mut() {
+                    sink.write(id.as_bytes(), pair.mate(mate))?;
+                }
+            }
+            if read.clipped {
+                if let Some(sink) = self.clipped.as_mut() {
+                    sink.write(id.as_bytes(), pair.mate(mate))?;
+                }
+            }
+        }
+
+        Ok(evidence)
+    }
+
+    /// Call [`OutputSink::finish`] on every sink
+    pub fn finish(&mut self) -> Result<(), &'static str> {
+        for sink in [&mut self.discordant, &mut self.split, &mut self.clipped]
+            .into_iter()
+            .flatten()
+        {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroI32;
+    use std::sync::Arc;
+
+    fn mapping(
+        target: &str,
+        query: (i32, i32),
+        target_range: (i32, i32),
+        strand: Strand,
+        is_primary: bool,
+    ) -> Mapping {
+        Mapping {
+            query_len: NonZeroI32::new(150),
+            query_start: query.0,
+            query_end: query.1,
+            target_name: Some(Arc::new(target.to_string())),
+            target_start: target_range.0,
+            target_end: target_range.1,
+            strand,
+            mapq: 60,
+            is_primary,
+            is_supplementary: !is_primary,
+            ..Default::default()
+        }
+    }
+
+    fn pair(first: Vec<Mapping>, second: Vec<Mapping>) -> PairedMappings {
+        PairedMappings {
+            name: Some(Arc::new("pair".to_string())),
+            first,
+            second,
+        }
+    }
+
+    #[test]
+    fn test_classify_pair() {
+        let params = SvParams::default();
+        let fwd = mapping("chr1", (0, 150), (100, 250), Strand::Forward, true);
+        let rev = mapping("chr1", (0, 150), (400, 550), Strand::Reverse, true);
+
+        let evidence = classify_pair(&pair(vec![fwd.clone()], vec![rev.clone()]), &params);
+        assert_eq!(evidence.class, PairClass::Concordant);
+        assert_eq!(evidence.first, ReadEvidence::default());
+
+        // Facing away, too far apart, other target, same strand
+        let mut outward = rev.clone();
+        outward.target_start = 0;
+        outward.target_end = 150;
+        let mut far = rev.clone();
+        far.target_start = 5000;
+        far.target_end = 5150;
+        let mut other = rev.clone();
+        other.target_name = Some(Arc::new("chr2".to_string()));
+        let mut same_strand = rev.clone();
+        same_strand.strand = Strand::Forward;
+        for second in [outward, far, other, same_strand] {
+            let evidence = classify_pair(&pair(vec![fwd.clone()], vec![second]), &params);
+            assert_eq!(evidence.class, PairClass::Discordant);
+        }
+
+        let evidence = classify_pair(&pair(vec![fwd.clone()], vec![]), &params);
+        assert_eq!(evidence.class, PairClass::Unpaired);
+        assert!(evidence.second.clipped);
+    }
+
+    #[test]
+    fn test_classify_read() {
+        let params = SvParams::default();
+        let primary = mapping("chr1", (0, 100), (100, 200), Strand::Forward, true);
+        let supplementary = mapping("chr2", (100, 150), (0, 50), Strand::Forward, false);
+
+        let evidence = classify_read(&[primary.clone(), supplementary.clone()], &params);
+        assert_eq!(
+            evidence,
+            ReadEvidence {
+                split: true,
+                clipped: true
+            }
+        );
+
+        // Too many pieces
+        let third = mapping("chr3", (120, 150), (0, 30), Strand::Forward, false);
+        assert!(!classify_read(&[primary.clone(), supplementary, third], &params).split);
+
+        // A short clip
+        let mut short_clip = primary;
+        short_clip.query_end = 140;
+        assert_eq!(
+            classify_read(&[short_clip], &params),
+            ReadEvidence::default()
+        );
+    }
+
+    #[test]
+    fn test_sv_router() {
+        let fwd = mapping("chr1", (0, 150), (100, 250), Strand::Forward, true);
+        let rev = mapping("chr2", (0, 100), (400, 500), Strand::Reverse, true);
+        let supplementary = mapping("chr1", (100, 150), (900, 950), Strand::Forward, false);
+        let discordant_pair = pair(vec![fwd], vec![rev, supplementary]);
+
+        let mut discordant = Vec::new();
+        let mut split = Vec::new();
+        let mut discordant_sink = |id: &[u8], m: &[Mapping]| -> Result<(), &'static str> {
+            discordant.push((id.to_vec(), m.len()));
+            Ok(())
+        };
+        let mut split_sink = |id: &[u8], m: &[Mapping]| -> Result<(), &'static str> {
+            split.push((id.to_vec(), m.len()));
+            Ok(())
+        };
+        let mut router = SvRouter {
+            discordant: Some(&mut discordant_sink),
+            split: Some(&mut split_sink),
+            ..Default::default()
+        };
+        let evidence = router.route(&discordant_pair).unwrap();
+        router.finish().unwrap();
+        drop(router);
+
+        assert_eq!(evidence.class, PairClass::Discordant);
+        assert_eq!(discordant, vec![(b"pair".to_vec(), 3)]);
+        assert_eq!(split, vec![(b"pair/2".to_vec(), 2)]);
+    }
+}
@@ -0,0 +1,593 @@
+//! Compact binary encoding of mappings for sending between processes
+//!
+//! [`encode`] packs a batch of [`Mapping`]s into a self-describing payload and [`decode`]
+//! unpacks it, for worker/aggregator setups where text PAF would be parsed back on the other
+//! side. Names are stored once per payload, so decoded mappings of the same read or target
+//! share one `Arc<String>`. Integers are varints, and with the `zstd` feature the body can be
+//! compressed ([`encode_compressed`]); [`decode`] handles both.
+//!
+//! A payload starts with [`MAGIC`], the format version and a flags byte. Decoders reject
+//! versions newer than [`FORMAT_VERSION`], which is bumped whenever the layout changes.
+//! [`write_frame`] and [`read_frame`] add a length prefix for streams such as pipes and
+//! sockets.
+//!
+//! ```
+//! use minimap2::wire::{decode, encode};
+//! use minimap2::Mapping;
+//! use std::sync::Arc;
+//!
+//! let mappings = vec![Mapping {
+//!     query_name: Some(Arc::new("read1".to_string())),
+//!     target_name: Some(Arc::new("chr1".to_string())),
+//!     target_len: 1000,
+//!     target_start: 100,
+//!     target_end: 200,
+//!     mapq: 60,
+//!     ..Default::default()
+//! }];
+//! let payload = encode(&mappings);
+//! assert_eq!(decode(&payload).unwrap(), mappings);
+//! ```
+
+use std::io::{Read, Write};
+use std::num::NonZeroI32;
+use std::sync::Arc;
+
+use crate::{Alignment, AlignmentType, DpSkipped, Mapping, Strand};
+
+/// First bytes of every payload
+pub const MAGIC: &[u8; 4] = b"MM2W";
+
+/// Version of the payload layout written by [`encode`]
+pub const FORMAT_VERSION: u8 = 2;
+
+// Payload flags
+const COMPRESSED: u8 = 1;
+
+// Mapping flags; from version 2, bits 5 and 6 hold AlignmentType
+const REVERSE: u8 = 1;
+const PRIMARY: u8 = 1 << 1;
+const SUPPLEMENTARY: u8 = 1 << 2;
+const HAS_ALIGNMENT: u8 = 1 << 3;
+const HAS_TARGET_SEQ: u8 = 1 << 4;
+
+// Alignment flags; bits 5 and 6 hold DpSkipped
+const HAS_CIGAR: u8 = 1;
+const HAS_CIGAR_STR: u8 = 1 << 1;
+const HAS_MD: u8 = 1 << 2;
+const HAS_CS: u8 = 1 << 3;
+const HAS_SCORE: u8 = 1 << 4;
+
+/// Encode mappings into an uncompressed payload
+pub fn encode(mappings: &[Mapping]) -> Vec<u8> {
+    let mut payload = header(0);
+    encode_body(mappings, &mut payload);
+    payload
+}
+
+/// Encode mappings into a payload with the body compressed by zstd at `level` (1-22, 0 for
+/// the zstd default)
+#[cfg(feature = "zstd")]
+pub fn encode_compressed(mappings: &[Mapping], level: i32) -> Result<Vec<u8>, &'static str> {
+    let mut body = Vec::new();
+    encode_body(mappings, &mut body);
+    let mut payload = header(COMPRESSED);
+    let compressed = zstd::bulk::compress(&body, level).map_err(|_| "Unable to compress")?;
+    write_varint(&mut payload, body.len() as u64);
+    payload.extend_from_slice(&compressed);
+    Ok(payload)
+}
+
+/// Decode a payload written by [`encode`] or [`encode_compressed`]
+///
+/// Compressed payloads need the `zstd` feature.
+pub fn decode(payload: &[u8]) -> Result<Vec<Mapping>, &'static str> {
+    if payload.len() < MAGIC.len() + 2 || &payload[..MAGIC.len()] != MAGIC {
+        return Err("Not a mapping payload");
+    }
+    let version = payload[MAGIC.len()];
+    if version == 0 || version > FORMAT_VERSION {
+        return Err("Unsupported payload version");
+    }
+    let flags = payload[MAGIC.len() + 1];
+    let body = &payload[MAGIC.len() + 2..];
+
+    if flags & COMPRESSED == 0 {
+        decode_body(body, version)
+    } else {
+        decode_body(&decompress(body)?, version)
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(body: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut reader = Reader { buf: body, pos: 0 };
+    let len = reader.varint()?;
+    // Streamed rather than into a buffer of the stated size, which may be corrupt
+    let decoder = zstd::stream::read::Decoder::new(&body[reader.pos..])
+        .map_err(|_| "Unable to decompress payload")?;
+    let mut decompressed = Vec::new();
+    decoder
+        .take(len.saturating_add(1))
+        .read_to_end(&mut decompressed)
+        .map_err(|_| "Unable to decompress payload")?;
+    if decompressed.len() as u64 != len {
+        return Err("Unable to decompress payload");
+    }
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_body: &[u8]) -> Result<Vec<u8>, &'static str> {
+    Err("Compressed payloads need the zstd feature")
+}
+
+/// Write `payload` to `writer` preceded by its length (u64, little endian)
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> Result<(), &'static str> {
+    writer
+        .write_all(&(payload.len() as u64).to_le_bytes())
+        .and_then(|_| writer.write_all(payload))
+        .map_err(|_| "Unable to write frame")
+}
+
+/// Read a frame written by [`write_frame`], or None at the end of the stream
+///
+/// Memory grows with the bytes actually read, so a corrupt length fails as a truncated frame
+/// instead of allocating its size up front.
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, &'static str> {
+    let mut len = [0u8; 8];
+    // Distinguish a clean end of stream from one inside the length
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err("Truncated frame"),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(_) => return Err("Unable to read frame"),
+        }
+    }
+    let len = u64::from_le_bytes(len);
+    let mut payload = Vec::new();
+    reader
+        .take(len)
+        .read_to_end(&mut payload)
+        .map_err(|_| "Unable to read frame")?;
+    if payload.len() as u64 != len {
+        return Err("Truncated frame");
+    }
+    Ok(Some(payload))
+}
+
+fn header(flags: u8) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(FORMAT_VERSION);
+    header.push(flags);
+    header
+}
+
+/// Names table, then the mappings
+fn encode_body(mappings: &[Mapping], out: &mut Vec<u8>) {
+    let mut names: Vec<&str> = Vec::new();
+    let mut ids = std::collections::HashMap::new();
+    let mut name_id = |name: &Option<Arc<String>>| -> u64 {
+        match name {
+            None => 0,
+            Some(name) => {
+                let n = names.len();
+                let id = *ids.entry(name.as_str()).or_insert_with(|| {
+                    names.push(name.as_str());
+                    n
+                });
+                id as u64 + 1
+            }
+        }
+    };
+    let name_ids: Vec<(u64, u64)> = mappings
+        .iter()
+        .map(|m| (name_id(&m.query_name), name_id(&m.target_name)))
+        .collect();
+
+    write_varint(out, names.len() as u64);
+    for name in &names {
+        write_bytes(out, name.as_bytes());
+    }
+
+    write_varint(out, mappings.len() as u64);
+    for (m, (query, target)) in mappings.iter().zip(name_ids) {
+        let mut flags = match m.alignment_type {
+            AlignmentType::Primary => 0,
+            AlignmentType::Secondary => 1,
+            AlignmentType::Supplementary => 2,
+            AlignmentType::Inversion => 3,
+        } << 5;
+        if m.strand == Strand::Reverse {
+            flags |= REVERSE;
+        }
+        if m.is_primary {
+            flags |= PRIMARY;
+        }
+        if m.is_supplementary {
+            flags |= SUPPLEMENTARY;
+        }
+        if m.alignment.is_some() {
+            flags |= HAS_ALIGNMENT;
+        }
+        if m.target_seq.is_some() {
+            flags |= HAS_TARGET_SEQ;
+        }
+        out.push(flags);
+
+        write_varint(out, query);
+        write_varint(out, m.query_len.map_or(0, |len| zigzag(len.get())));
+        for value in [m.query_start, m.query_end] {
+            write_varint(out, zigzag(value));
+        }
+        write_varint(out, target);
+        for value in [
+            m.target_len,
+            m.target_start,
+            m.target_end,
+            m.match_len,
+            m.block_len,
+        ] {
+            write_varint(out, zigzag(value));
+        }
+        write_varint(out, m.mapq as u64);
+
+        if let Some(aln) = &m.alignment {
+            encode_alignment(aln, out);
+        }
+        if let Some(seq) = &m.target_seq {
+            write_bytes(out, seq);
+        }
+    }
+}
+
+fn encode_alignment(aln: &Alignment, out: &mut Vec<u8>) {
+    let mut flags = match aln.dp_skipped {
+        DpSkipped::None => 0,
+        DpSkipped::Start => 1,
+        DpSkipped::End => 2,
+        DpSkipped::Both => 3,
+    } << 5;
+    for (present, flag) in [
+        (aln.cigar.is_some(), HAS_CIGAR),
+        (aln.cigar_str.is_some(), HAS_CIGAR_STR),
+        (aln.md.is_some(), HAS_MD),
+        (aln.cs.is_some(), HAS_CS),
+        (aln.alignment_score.is_some(), HAS_SCORE),
+    ] {
+        if present {
+            flags |= flag;
+        }
+    }
+    out.push(flags);
+
+    write_varint(out, zigzag(aln.nm));
+    if let Some(cigar) = &aln.cigar {
+        write_varint(out, cigar.len() as u64);
+        for &(len, op) in cigar {
+            write_varint(out, ((len as u64) << 4) | op as u64);
+        }
+    }
+    for s in [&aln.cigar_str, &aln.md, &aln.cs].into_iter().flatten() {
+        write_bytes(out, s.as_bytes());
+    }
+    if let Some(score) = aln.alignment_score {
+        write_varint(out, zigzag(score));
+    }
+}
+
+fn decode_body(body: &[u8], version: u8) -> Result<Vec<Mapping>, &'static str> {
+    let mut r = Reader { buf: body, pos: 0 };
+
+    let n_names = r.len()?;
+    let mut names = Vec::with_capacity(n_names);
+    for _ in 0..n_names {
+        names.push(Arc::new(r.string()?));
+    }
+    let name = |id: u64| -> Result<Option<Arc<String>>, &'static str> {
+        match id {
+            0 => Ok(None),
+            id => names
+                .get(id as usize - 1)
+                .map(|name| Some(Arc::clone(name)))
+                .ok_or("Invalid name index in payload"),
+        }
+    };
+
+    let n = r.len()?;
+    let mut mappings = Vec::with_capacity(n);
+    for _ in 0..n {
+        let flags = r.byte()?;
+        let query_name = name(r.varint()?)?;
+        let query_len = NonZeroI32::new(r.int()?);
+        let query_start = r.int()?;
+        let query_end = r.int()?;
+        let target_name = name(r.varint()?)?;
+        let target_len = r.int()?;
+        let target_start = r.int()?;
+        let target_end = r.int()?;
+        let match_len = r.int()?;
+        let block_len = r.int()?;
+        let mapq = u32::try_from(r.varint()?).map_err(|_| "Invalid MAPQ in payload")?;
+        let alignment = if flags & HAS_ALIGNMENT != 0 {
+            Some(decode_alignment(&mut r)?)
+        } else {
+            None
+        };
+        let target_seq = if flags & HAS_TARGET_SEQ != 0 {
+            Some(r.bytes()?.to_vec())
+        } else {
+            None
+        };
+        let (is_primary, is_supplementary) = (flags & PRIMARY != 0, flags & SUPPLEMENTARY != 0);
+        let alignment_type = match (version, (flags >> 5) & 3) {
+            (1, _) => AlignmentType::from_flags(is_primary, is_supplementary),
+            (_, 0) => AlignmentType::Primary,
+            (_, 1) => AlignmentType::Secondary,
+            (_, 2) => AlignmentType::Supplementary,
+            _ => AlignmentType::Inversion,
+        };
+
+        mappings.push(Mapping {
+            query_name,
+            query_len,
+            query_start,
+            query_end,
+            strand: if flags & REVERSE != 0 {
+                Strand::Reverse
+            } else {
+                Strand::Forward
+            },
+            target_name,
+            target_len,
+            target_start,
+            target_end,
+            match_len,
+            block_len,
+            mapq,
+            is_primary,
+            is_supplementary,
+            alignment_type,
+            alignment,
+            target_seq,
+        });
+    }
+
+    if r.pos != body.len() {
+        return Err("Trailing bytes in payload");
+    }
+    Ok(mappings)
+}
+
+fn decode_alignment(r: &mut Reader) -> Result<Alignment, &'static str> {
+    let flags = r.byte()?;
+    let nm = r.int()?;
+    let cigar = if flags & HAS_CIGAR != 0 {
+        let n = r.len()?;
+        let mut cigar = Vec::with_capacity(n);
+        for _ in 0..n {
+            let op = r.varint()?;
+            let len = u32::try_from(op >> 4).map_err(|_| "Invalid CIGAR in payload")?;
+            cigar.push((len, (op & 0xf) as u8));
+        }
+        Some(cigar)
+    } else {
+        None
+    };
+    let mut string = |flag: u8| -> Result<Option<String>, &'static str> {
+        if flags & flag != 0 {
+            r.string().map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let cigar_str = string(HAS_CIGAR_STR)?;
+    let md = string(HAS_MD)?;
+    let cs = string(HAS_CS)?;
+    let alignment_score = if flags & HAS_SCORE != 0 {
+        Some(r.int()?)
+    } else {
+        None
+    };
+    let dp_skipped = match (flags >> 5) & 3 {
+        0 => DpSkipped::None,
+        1 => DpSkipped::Start,
+        2 => DpSkipped::End,
+        _ => DpSkipped::Both,
+    };
+
+    Ok(Alignment {
+        nm,
+        cigar,
+        cigar_str,
+        md,
+        cs,
+        alignment_score,
+        dp_skipped,
+    })
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        let byte = *self.buf.get(self.pos).ok_or("Truncated payload")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid varint in payload")
+    }
+
+    fn int(&mut self) -> Result<i32, &'static str> {
+        let value = u32::try_from(self.varint()?).map_err(|_| "Invalid integer in payload")?;
+        Ok(unzigzag(value))
+    }
+
+    /// A count, bounded by the bytes left so a corrupt payload cannot trigger a huge
+    /// allocation
+    fn len(&mut self) -> Result<usize, &'static str> {
+        let len = self.varint()?;
+        if len > (self.buf.len() - self.pos) as u64 {
+            return Err("Truncated payload");
+        }
+        Ok(len as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], &'static str> {
+        let len = self.len()?;
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, &'static str> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| "Invalid UTF-8 in payload")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mappings() -> Vec<Mapping> {
+        let file = std::fs::File::open("test_data/gDNA_vs_genome.paf").unwrap();
+        crate::paf::read_paf(std::io::BufReader::new(file))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut mappings = mappings();
+        assert!(!mappings.is_empty());
+        // Fields PAF does not carry
+        mappings[0].target_seq = Some(b"ACGT".to_vec());
+        mappings[0].query_start = -1;
+        mappings[0].query_len = None;
+        if let Some(aln) = mappings[0].alignment.as_mut() {
+            aln.dp_skipped = DpSkipped::Both;
+            aln.alignment_score = Some(i32::MIN);
+        }
+        mappings[0].alignment_type = AlignmentType::Inversion;
+        mappings.push(Mapping::default());
+
+        let payload = encode(&mappings);
+        let decoded = decode(&payload).unwrap();
+        assert_eq!(decoded, mappings);
+        // Names are shared between the mappings of a payload
+        let read = decoded[0].query_name.as_ref().unwrap();
+        let same_read = decoded
+            .iter()
+            .skip(1)
+            .find(|m| m.query_name.as_ref() == Some(read))
+            .and_then(|m| m.query_name.as_ref());
+        if let Some(same_read) = same_read {
+            assert!(Arc::ptr_eq(read, same_read));
+        }
+
+        assert_eq!(decode(&encode(&[])).unwrap(), vec![]);
+        // Version 1 payloads have no alignment type
+        let mut v1 = encode(&[Mapping::default()]);
+        v1[MAGIC.len()] = 1;
+        let decoded = decode(&v1).unwrap();
+        assert_eq!(decoded[0].alignment_type, AlignmentType::Secondary);
+        // Smaller than the PAF it came from
+        let paf: usize = mappings.iter().map(|m| m.to_paf().len() + 1).sum();
+        assert!(payload.len() < paf);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let payload = encode(&mappings());
+        assert_eq!(decode(b"PAF"), Err("Not a mapping payload"));
+        let mut newer = payload.clone();
+        newer[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert_eq!(decode(&newer), Err("Unsupported payload version"));
+        assert_eq!(
+            decode(&payload[..payload.len() - 1]),
+            Err("Truncated payload")
+        );
+        let mut trailing = payload.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), Err("Trailing bytes in payload"));
+    }
+
+    #[test]
+    fn test_frames() {
+        let mappings = mappings();
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &encode(&mappings[..1])).unwrap();
+        write_frame(&mut stream, &encode(&mappings[1..])).unwrap();
+
+        let mut reader = stream.as_slice();
+        let mut decoded = Vec::new();
+        while let Some(frame) = read_frame(&mut reader).unwrap() {
+            decoded.extend(decode(&frame).unwrap());
+        }
+        assert_eq!(decoded, mappings);
+
+        let mut truncated = &stream[..stream.len() - 1];
+        read_frame(&mut truncated).unwrap();
+        assert_eq!(read_frame(&mut truncated), Err("Truncated frame"));
+
+        // A corrupt length does not allocate what it claims
+        let mut corrupt = u64::MAX.to_le_bytes().to_vec();
+        corrupt.extend_from_slice(b"MM2W");
+        assert_eq!(read_frame(&mut &corrupt[..]), Err("Truncated frame"));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compressed() {
+        let mappings = mappings();
+        let payload = encode_compressed(&mappings, 0).unwrap();
+        assert!(payload.len() < encode(&mappings).len());
+        assert_eq!(decode(&payload).unwrap(), mappings);
+
+        // The decompressed length must be the one stated
+        let body = &payload[MAGIC.len() + 2..];
+        let mut reader = Reader { buf: body, pos: 0 };
+        reader.varint().unwrap();
+        let mut corrupt = header(COMPRESSED);
+        write_varint(&mut corrupt, u64::MAX);
+        corrupt.extend_from_slice(&body[reader.pos..]);
+        assert_eq!(decode(&corrupt), Err("Unable to decompress payload"));
+    }
+}
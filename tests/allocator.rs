@@ -0,0 +1,108 @@
+//! The crate must not assume that Rust and minimap2 share an allocator, so that it works under
+//! any global allocator (mimalloc, jemalloc...). This global allocator offsets every Rust
+//! allocation behind a tagged header: memory freed by the wrong side either fails the tag
+//! check here or is rejected by the C allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+
+use minimap2::*;
+
+mod common;
+
+struct GuardAlloc;
+
+const MAGIC: u64 = 0x6d6d_3272_735f_6f6b;
+
+fn header_len(layout: &Layout) -> usize {
+    layout.align().max(16)
+}
+
+unsafe impl GlobalAlloc for GuardAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let header = header_len(&layout);
+        let outer = Layout::from_size_align_unchecked(layout.size() + header, layout.align());
+        let ptr = System.alloc(outer);
+        if ptr.is_null() {
+            return ptr;
+        }
+        let ptr = ptr.add(header);
+        (ptr as *mut u64).sub(1).write(MAGIC);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = header_len(&layout);
+        let tag = (ptr as *mut u64).sub(1);
+        if tag.read() != MAGIC {
+            // Not allocated by Rust, panicking would allocate
+            std::process::abort();
+        }
+        tag.write(0);
+        let outer = Layout::from_size_align_unchecked(layout.size() + header, layout.align());
+        System.dealloc(ptr.sub(header), outer);
+    }
+}
+
+#[global_allocator]
+static ALLOC: GuardAlloc = GuardAlloc;
+
+#[test]
+fn test_mapping_under_foreign_allocator() {
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_cigar()
+        .with_target_seq()
+        .with_index("test_data/MT-human.fa", None)
+        .unwrap();
+
+    let reference = common::mt_seq();
+    let mut query = reference[2000..3000].to_vec();
+    query.drain(400..410);
+
+    let mappings = aligner
+        .map(&query, true, true, None, None, Some(b"read"))
+        .unwrap();
+    assert_eq!(mappings[0].target_start, 2000);
+
+    let batch = aligner
+        .map_batch([(&b"read"[..], &query[..])], true, true)
+        .unwrap();
+    assert_eq!(batch.len(), mappings.len());
+
+    let sub = aligner
+        .fetch_subseq("MT_human", 0..10, Strand::Reverse)
+        .unwrap();
+    assert_eq!(sub.len(), 10);
+
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_seqs_and_ids(&[reference], &[b"MT".to_vec()])
+        .unwrap();
+    assert_eq!(aligner.n_seq(), 1);
+}
+
+#[cfg(feature = "htslib")]
+#[test]
+fn test_htslib_under_foreign_allocator() {
+    use rust_htslib::bam::{Header, HeaderView};
+
+    let aligner = Aligner::builder()
+        .with_cigar()
+        .with_index("test_data/genome.fa", None)
+        .unwrap();
+    let mut header = Header::new();
+    aligner.populate_header(&mut header);
+    let header_view = HeaderView::from_header(&header);
+
+    let records = aligner
+        .map_to_sam(
+            b"TACGCCACACGGGCTACACTCTCGCCTTCTCGTCTCAACTACGAGATGGACTGTCGGCCTAGAGGATCTAACACGAGAAGTACTTGCCGGCAAGCCCTAA",
+            None,
+            Some(b"read1"),
+            &header_view,
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(records.len(), 1);
+}
@@ -0,0 +1,7 @@
+//! Fixtures shared by the integration tests
+
+/// The sequence of test_data/MT-human.fa
+pub fn mt_seq() -> Vec<u8> {
+    let fasta = std::fs::read_to_string("test_data/MT-human.fa").unwrap();
+    fasta.lines().skip(1).flat_map(|l| l.bytes()).collect()
+}
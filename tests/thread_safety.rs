@@ -0,0 +1,247 @@
+//! Stress tests of the thread-safety contract in the crate documentation: concurrent mapping
+//! calls on a shared aligner must give the same results as mapping on one thread, whatever
+//! the interleaving, and aligners may be cloned and dropped on any thread while others map.
+//!
+//! These are hammer tests rather than a model checker: each test repeats its calls from many
+//! threads to make races likely, and relies on the allocator and on result comparisons to
+//! catch them. Run them under `--release` for more interleavings per second.
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use minimap2::splice::{JunctionScoring, SpliceScores};
+use minimap2::*;
+
+mod common;
+
+const THREADS: usize = 16;
+const ROUNDS: usize = 20;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_send_sync() {
+    assert_send_sync::<Aligner<Unset>>();
+    assert_send_sync::<Aligner<PresetSet>>();
+    assert_send_sync::<Aligner<Built>>();
+    assert_send_sync::<Mapping>();
+    assert_send_sync::<JunctionScoring>();
+    assert_send_sync::<SpliceScores>();
+}
+
+/// Slices of the MT genome on both strands
+fn mt_queries() -> Vec<Vec<u8>> {
+    let mt = common::mt_seq();
+    (0..32)
+        .map(|i| {
+            let query = mt[i * 500..i * 500 + 400].to_vec();
+            if i % 2 == 0 {
+                query
+            } else {
+                seq::revcomp(&query)
+            }
+        })
+        .collect()
+}
+
+fn fastq_reads(path: &str) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let fastq = std::fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = fastq.lines().collect();
+    lines
+        .chunks(4)
+        .map(|read| {
+            (
+                read[0][1..].as_bytes().to_vec(),
+                read[1].as_bytes().to_vec(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_concurrent_map() {
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_cigar()
+        .with_index("test_data/MT-human.fa", None)
+        .unwrap();
+    let queries = mt_queries();
+    let expected: Vec<Vec<Mapping>> = queries
+        .iter()
+        .map(|query| {
+            aligner
+                .map(query, true, true, None, None, Some(b"q"))
+                .unwrap()
+        })
+        .collect();
+
+    // Every thread maps every query, starting at a different one
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (aligner, queries, expected) = (&aligner, &queries, &expected);
+            s.spawn(move || {
+                for round in 0..ROUNDS {
+                    for i in 0..queries.len() {
+                        let i = (i + t + round) % queries.len();
+                        let (mappings, summary) = aligner
+                            .map_with_summary(&queries[i], true, true, None, None, Some(b"q"))
+                            .unwrap();
+                        assert_eq!(mappings, expected[i]);
+                        assert_eq!(summary.n_hits(), mappings.len());
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_clones_dropped_while_mapping() {
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_index("test_data/MT-human.fa", None)
+        .unwrap();
+    let queries = Arc::new(mt_queries());
+    let expected: Vec<Vec<Mapping>> = queries
+        .iter()
+        .map(|query| aligner.map(query, false, false, None, None, None).unwrap())
+        .collect();
+    let expected = Arc::new(expected);
+    let start = Arc::new(Barrier::new(THREADS + 1));
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let aligner = aligner.clone();
+            let (queries, expected, start) = (queries.clone(), expected.clone(), start.clone());
+            thread::spawn(move || {
+                start.wait();
+                // Threads stop, and drop their clone, after different numbers of queries
+                for i in 0..(t + 1) * queries.len() / 4 {
+                    let i = i % queries.len();
+                    let mappings = aligner
+                        .map(&queries[i], false, false, None, None, None)
+                        .unwrap();
+                    assert_eq!(mappings, expected[i]);
+                }
+                drop(aligner);
+            })
+        })
+        .collect();
+
+    // The original goes first, the index lives on in the clones
+    drop(aligner);
+    start.wait();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_concurrent_options() {
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_cigar()
+        .with_index("test_data/MT-human.fa", None)
+        .unwrap();
+    // A clone with other settings maps alongside the original, sharing its index
+    let mut strict = aligner.clone();
+    strict.mapopt.min_dp_max = 100_000;
+    let queries = mt_queries();
+
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (aligner, strict, queries) = (&aligner, &strict, &queries);
+            s.spawn(move || {
+                for round in 0..ROUNDS {
+                    let query = &queries[(t + round) % queries.len()];
+                    let mapped = aligner.map(query, false, false, None, None, None).unwrap();
+                    let filtered = strict.map(query, false, false, None, None, None).unwrap();
+                    assert!(!mapped.is_empty());
+                    assert!(filtered.is_empty());
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_concurrent_splice() {
+    let aligner = Aligner::builder()
+        .splice()
+        .with_cigar()
+        .with_index("test_data/genome.fa", None)
+        .unwrap();
+    let reads = fastq_reads("test_data/cDNA_reads.fq");
+    let scoring = JunctionScoring::default();
+    let score = |aligner: &Aligner<Built>, name: &[u8], seq: &[u8]| {
+        aligner
+            .map(seq, true, false, None, None, Some(name))
+            .unwrap()
+            .iter()
+            .map(|mapping| {
+                let junctions = aligner.score_junctions(mapping, &scoring).unwrap();
+                (mapping.clone(), junctions)
+            })
+            .collect::<Vec<_>>()
+    };
+    let expected: Vec<_> = reads
+        .iter()
+        .map(|(name, seq)| score(&aligner, name, seq))
+        .collect();
+    assert!(expected
+        .iter()
+        .flatten()
+        .any(|(_, junctions)| !junctions.is_empty()));
+
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (aligner, reads, expected, score) = (&aligner, &reads, &expected, &score);
+            s.spawn(move || {
+                for round in 0..ROUNDS {
+                    let i = (t + round) % reads.len();
+                    let (name, seq) = &reads[i];
+                    assert_eq!(score(aligner, name, seq), expected[i]);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_nested_map_from_hook() {
+    let inner = Arc::new(
+        Aligner::builder()
+            .map_ont()
+            .with_index("test_data/MT-human.fa", None)
+            .unwrap(),
+    );
+    let queries = Arc::new(mt_queries());
+    let hook_inner = inner.clone();
+    let hook_queries = queries.clone();
+    // The hook maps with another aligner from inside each mapping call
+    let aligner = Aligner::builder()
+        .map_ont()
+        .with_mapq_recalibration(move |mapping| {
+            let query = &hook_queries[mapping.target_start as usize % hook_queries.len()];
+            let nested = hook_inner
+                .map(query, false, false, None, None, None)
+                .unwrap();
+            assert!(!nested.is_empty());
+            mapping.mapq
+        })
+        .with_index("test_data/MT-human.fa", None)
+        .unwrap();
+
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (aligner, queries) = (&aligner, &queries);
+            s.spawn(move || {
+                for round in 0..ROUNDS {
+                    let query = &queries[(t + round) % queries.len()];
+                    let mappings = aligner.map(query, false, false, None, None, None).unwrap();
+                    assert!(!mappings.is_empty());
+                }
+            });
+        }
+    });
+}
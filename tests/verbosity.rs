@@ -0,0 +1,18 @@
+//! `mm_verbose` is a process-wide global that minimap2 reads without synchronization, so it is
+//! tested in its own binary rather than next to unit tests that map reads on other threads.
+
+use minimap2::*;
+
+#[test]
+fn test_verbosity() {
+    let original = verbosity();
+
+    // Safety: this binary runs no other test, so nothing maps or builds an index meanwhile
+    let _aligner = unsafe { Aligner::builder().map_ont().with_verbosity(0) };
+    assert_eq!(verbosity(), 0);
+
+    unsafe { set_verbosity(3) };
+    assert_eq!(verbosity(), 3);
+
+    unsafe { set_verbosity(original) };
+}